@@ -1,22 +1,30 @@
 use core::slice;
 use collections::vec::Vec;
 use collections::vec;
-use crate::{Color, Hue, X_SCALE, Y_SCALE};
+use crate::{Color, Hue, ScreenInfo, X_SCALE, Y_SCALE};
 
 /// The number of colors in the default VGA palette.
 /// All bitmaps used are assumed to have this number of colors in their color tables
 const COLOR_TABLE_SIZE: usize = 254;
+/// The compression method of a bitmap whose pixel array is stored as it is
+const BI_RGB: u32 = 0;
+/// The compression method of an 8bpp bitmap whose pixel array is run length encoded
+const BI_RLE8: u32 = 1;
 
 /// A bitmap file with a BITMAPV5HEADER.
 /// The bitmap is assumed to be 8bpp (bits per pixel) and it's palette is assumed
 /// to correspond to the default VGA palette
 ///
+/// The pixel array can be stored as it is or run length encoded (RLE8), which
+/// shrinks bitmaps with large areas of the same color, like most of the game's art.
+/// Either way, it's decoded into a byte per pixel when the bitmap is created
+///
 /// For information on the bitmap file format: <https://en.wikipedia.org/wiki/BMP_file_format>
 ///
 /// I used arrays of u8s instead of the corresponding u32 or u16 in all the related
 /// bitmap structures because integer values in the bitmap structure are stored in
 /// little-endian format
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Bitmap {
     /// The start of the file used for identification
     file_header: &'static BitmapFileHeader,
@@ -27,8 +35,9 @@ pub struct Bitmap {
     /// This structure assumes it always corresponds with the default VGA palette
     /// so there is no need to change the VGA palette to draw the bitmap
     color_table: &'static [u8],
-    /// The actual bit array which gets drawn on the screen
-    pub image_data: &'static [u8],
+    /// The actual bit array which gets drawn on the screen,
+    /// a byte per pixel from the bottom row up
+    pub image_data: Vec<'static, u8>,
     /// Defines which color in the bitmap image data should be considered transparent
     pub transparency: Transparency
 }
@@ -66,7 +75,7 @@ struct BitmapDIBHeader {
     bits_per_pixel: [u8; 2],
     /// Specifies the compression used in the bitmap
     ///
-    /// This bitmap representation assumes this field to never be compressed, that is always set to 0 (BI_RGB)
+    /// This bitmap representation only supports no compression (BI_RGB) and RLE8 (BI_RLE8)
     compression_method: [u8; 4],
     /// Size of the image in bytes. May be set to 0 if no compression is used
     size_image: [u8; 4],
@@ -135,10 +144,27 @@ impl Bitmap {
             let file_header = &(*(raw_bytes.as_ptr() as *const BitmapFileHeader));
             let dib_header = &(*(raw_bytes.as_ptr().offset(FILE_HEADER_SIZE) as *const BitmapDIBHeader));
             let color_table = slice::from_raw_parts(raw_bytes.as_ptr().offset(FILE_HEADER_SIZE + DIB_HEADER_SIZE), COLOR_TABLE_SIZE);
-            let image_data_offset = u32::from_le_bytes(file_header.image_data_offset) as isize;
+            let image_data_offset = u32::from_le_bytes(file_header.image_data_offset) as usize;
             let image_width = u32::from_le_bytes(dib_header.image_width) as usize;
             let image_height = u32::from_le_bytes(dib_header.image_height) as usize;
-            let image_data = slice::from_raw_parts(raw_bytes.as_ptr().offset(image_data_offset), image_width * image_height);
+            let pixel_array = raw_bytes.get(image_data_offset..)
+                .ok_or("The bitmap's pixel array is past the end of the file")?;
+            let no_of_pixels = image_width * image_height;
+            let mut image_data = vec!(item_type => u8, capacity => no_of_pixels);
+            match u32::from_le_bytes(dib_header.compression_method) {
+                BI_RGB => {
+                    let pixels = pixel_array.get(..no_of_pixels).ok_or("The bitmap's pixel array is cut short")?;
+                    image_data.extend_from_slice(pixels);
+                }
+                BI_RLE8 => {
+                    // Pixels the encoding skips over are black
+                    for _ in 0..no_of_pixels {
+                        image_data.push(0);
+                    }
+                    decode_rle8(pixel_array, image_width, image_height, |idx, color| image_data[idx] = color)?;
+                }
+                _ => return Err("Only uncompressed and RLE8 bitmaps are supported")
+            }
             Ok(Bitmap {
                 file_header,
                 dib_header,
@@ -187,16 +213,22 @@ impl Bitmap {
     /// Converts the bitmap's image_data into the actual scaled
     /// image data that will be drawn on the screen
     pub fn convert_to_scaled_bitmap(self) -> ScaledBitmap {
+        self.convert_to_scaled_bitmap_by(X_SCALE, Y_SCALE)
+    }
+
+    /// Converts the bitmap's image_data into image data scaled
+    /// `x_scale` times horizontally and `y_scale` times vertically
+    pub fn convert_to_scaled_bitmap_by(self, x_scale: usize, y_scale: usize) -> ScaledBitmap {
         let mut scaled_image = vec!(
             item_type => Color,
-            capacity => self.width() * X_SCALE * self.height() * Y_SCALE
+            capacity => self.width() * x_scale * self.height() * y_scale
         );
         for y in 0..self.height() {
             let i = y + 1;
-            for _yp in y * Y_SCALE..i * Y_SCALE {
+            for _yp in y * y_scale..i * y_scale {
                 for x in 0..self.width() {
                     let j = x + 1;
-                    for _xp in x * X_SCALE..j * X_SCALE {
+                    for _xp in x * x_scale..j * x_scale {
                         let pixel_array_y = self.height() - y - 1;
                         let raw_color = self.image_data[pixel_array_y*self.width()+x];
                         scaled_image.push(Color::from_bitmap_data(raw_color));
@@ -204,11 +236,117 @@ impl Bitmap {
                 }
             }
         }
-        ScaledBitmap {
-            image_data: scaled_image,
-            width: self.scaled_width(),
-            height: self.scaled_height(),
-            transparency: self.transparency
+        ScaledBitmap::new(scaled_image, self.width() * x_scale, self.height() * y_scale, self.transparency)
+    }
+
+    /// Converts the bitmap's image_data into image data scaled `fx` times
+    /// horizontally and `fy` times vertically with nearest neighbor scaling
+    ///
+    /// Unlike `convert_to_scaled_bitmap_by`, the factors don't have to be whole
+    /// numbers, so the bitmap can be shrunk too. The factors are relative to the
+    /// bitmap's own pixels, so `X_SCALE` and `Y_SCALE` give the usual size
+    pub fn scaled(&self, fx: f32, fy: f32) -> Result<ScaledBitmap, &'static str> {
+        if !(fx > 0.0 && fy > 0.0) {
+            return Err("A bitmap can only be scaled by positive factors");
+        }
+        let width = scaled_len(self.width(), fx);
+        let height = scaled_len(self.height(), fy);
+        let mut scaled_image = vec!(item_type => Color, capacity => width * height);
+        // Both image datas are stored from the bottom row up, so the rows line up
+        for y in 0..height {
+            let src_y = nearest_src_coord(y, height, self.height());
+            for x in 0..width {
+                let src_x = nearest_src_coord(x, width, self.width());
+                scaled_image.push(Color::from_bitmap_data(self.image_data[src_y * self.width() + src_x]));
+            }
+        }
+        Ok(ScaledBitmap::new(scaled_image, width, height, self.transparency))
+    }
+}
+
+/// The length of `len` pixels scaled by `factor`, rounded to the nearest pixel,
+/// but never less than a pixel
+fn scaled_len(len: usize, factor: f32) -> usize {
+    ((len as f32 * factor + 0.5) as usize).max(1)
+}
+
+/// The coordinate of the pixel in a `src_len` pixels long source that is
+/// nearest to `pos` in the source scaled to `len` pixels
+fn nearest_src_coord(pos: usize, len: usize, src_len: usize) -> usize {
+    pos * src_len / len
+}
+
+/// A way of turning or mirroring a bitmap
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transform {
+    /// Turned a quarter of the way round, clockwise
+    Rotate90,
+    /// Turned upside down
+    Rotate180,
+    /// Turned a quarter of the way round, counterclockwise
+    Rotate270,
+    /// Mirrored left to right
+    FlipHorizontal,
+    /// Mirrored top to bottom
+    FlipVertical
+}
+
+impl Transform {
+    /// Whether the width and the height of the bitmap trade places
+    fn swaps_dimensions(&self) -> bool {
+        matches!(self, Transform::Rotate90 | Transform::Rotate270)
+    }
+
+    /// The (x, y) of the pixel in a `width` by `height` bitmap that ends up
+    /// at (`x`, `y`) once the bitmap has been transformed, counted from the top left
+    fn src_pixel(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Transform::Rotate90 => (y, height - 1 - x),
+            Transform::Rotate180 => (width - 1 - x, height - 1 - y),
+            Transform::Rotate270 => (width - 1 - y, x),
+            Transform::FlipHorizontal => (width - 1 - x, y),
+            Transform::FlipVertical => (x, height - 1 - y)
+        }
+    }
+}
+
+/// A bitmap with variants drawn for different screen resolutions
+///
+/// Scaling art drawn for the 320x200 screen up with nearest neighbor
+/// makes it blocky, so when a variant drawn at twice the resolution exists
+/// and the screen is big enough for it, that variant is used instead
+#[derive(Clone, Copy)]
+pub struct BitmapAsset {
+    /// The raw bytes of the variant drawn for a 320x200 screen
+    base: &'static [u8],
+    /// The raw bytes of the variant drawn at twice the resolution of `base`
+    double: Option<&'static [u8]>
+}
+
+impl BitmapAsset {
+    /// Creates an asset with only the variant drawn for a 320x200 screen
+    pub const fn new(base: &'static [u8]) -> Self {
+        Self { base, double: None }
+    }
+
+    /// Adds a variant drawn at twice the resolution of the base variant
+    pub const fn with_double(self, double: &'static [u8]) -> Self {
+        Self { double: Some(double), ..self }
+    }
+
+    /// Picks the variant that best fits `screen` and scales it the
+    /// rest of the way to the screen's size
+    pub fn load(&self, screen: ScreenInfo, transparency: Transparency) -> Result<ScaledBitmap, &'static str> {
+        let (x_scale, y_scale) = (screen.x_scale(), screen.y_scale());
+        match self.double {
+            Some(double) if x_scale >= 2 && y_scale >= 2 && x_scale % 2 == 0 && y_scale % 2 == 0 => {
+                let bitmap = Bitmap::from(double, transparency)?;
+                Ok(bitmap.convert_to_scaled_bitmap_by(x_scale / 2, y_scale / 2))
+            }
+            _ => {
+                let bitmap = Bitmap::from(self.base, transparency)?;
+                Ok(bitmap.convert_to_scaled_bitmap_by(x_scale, y_scale))
+            }
         }
     }
 }
@@ -218,16 +356,301 @@ pub struct ScaledBitmap {
     pub image_data: Vec<'static, Color>,
     width: usize,
     height: usize,
-    pub transparency: Transparency
+    pub transparency: Transparency,
+    /// The runs of pixels in each row that get drawn, worked out once when the
+    /// bitmap is created so drawing it doesn't have to test every pixel
+    opaque_spans: Vec<'static, OpaqueSpan>
+}
+
+/// A run of pixels in a row of a `ScaledBitmap` which are all drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpaqueSpan {
+    /// The column of the span's first pixel
+    pub x: usize,
+    /// The row of the span, counted from the top of the bitmap
+    pub y: usize,
+    /// The number of pixels in the span
+    pub len: usize,
+    /// The index of the span's first pixel in the bitmap's image data
+    pub start: usize
 }
 
 impl ScaledBitmap {
+    /// Creates a bitmap out of `image_data`, which is stored from the bottom row up
+    fn new(image_data: Vec<'static, Color>, width: usize, height: usize, transparency: Transparency) -> Self {
+        // Most bitmaps have a run or two per row
+        let mut opaque_spans = vec!(item_type => OpaqueSpan, capacity => height.max(1));
+        for y in 0..height {
+            let row_start = (height - y - 1) * width;
+            let is_opaque = |x: usize| {
+                transparency == Transparency::None || image_data[row_start + x] != Color::BLACK
+            };
+            for_each_opaque_run(width, is_opaque, |x, len| {
+                opaque_spans.push(OpaqueSpan { x, y, len, start: row_start + x });
+            });
+        }
+        Self { image_data, width, height, transparency, opaque_spans }
+    }
+
     pub fn height(&self) -> usize {
         self.height
     }
     pub fn width(&self) -> usize {
         self.width
     }
+
+    /// The runs of pixels that get drawn, from the top row down
+    pub fn opaque_spans(&self) -> core::slice::Iter<'_, OpaqueSpan> {
+        self.opaque_spans.iter()
+    }
+
+    /// Creates a copy of the bitmap stretched `x_factor` times horizontally
+    /// and `y_factor` times vertically
+    pub fn stretched(&self, x_factor: usize, y_factor: usize) -> ScaledBitmap {
+        let width = self.width * x_factor;
+        let height = self.height * y_factor;
+        let mut image_data = vec!(item_type => Color, capacity => width * height);
+        for y in 0..height {
+            for x in 0..width {
+                image_data.push(self.image_data[(y / y_factor) * self.width + x / x_factor]);
+            }
+        }
+        ScaledBitmap::new(image_data, width, height, self.transparency)
+    }
+
+    /// Creates a copy of the bitmap turned or mirrored by `transform`
+    pub fn transformed(&self, transform: Transform) -> ScaledBitmap {
+        let (width, height) = if transform.swaps_dimensions() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        let mut image_data = vec!(item_type => Color, capacity => self.image_data.len());
+        // The image data is stored from the bottom row up
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let (src_x, src_y) = transform.src_pixel(x, y, self.width, self.height);
+                image_data.push(self.image_data[(self.height - src_y - 1) * self.width + src_x]);
+            }
+        }
+        ScaledBitmap::new(image_data, width, height, self.transparency)
+    }
+
+    /// Creates a copy of the `width` by `height` part of the bitmap
+    /// with its top left corner at (`x`, `y`)
+    ///
+    /// # Panics
+    ///
+    /// If the part doesn't fit in the bitmap
+    pub fn cropped(&self, x: usize, y: usize, width: usize, height: usize) -> ScaledBitmap {
+        assert!(x + width <= self.width && y + height <= self.height, "The part is outside the bitmap");
+        let mut image_data = vec!(item_type => Color, capacity => width * height);
+        // The image data is stored from the bottom row up
+        for row in (y..y + height).rev() {
+            let row_start = (self.height - row - 1) * self.width + x;
+            for i in row_start..row_start + width {
+                image_data.push(self.image_data[i]);
+            }
+        }
+        ScaledBitmap::new(image_data, width, height, self.transparency)
+    }
+
+    /// Creates a copy of the bitmap with every pixel that would be drawn
+    /// replaced with `color`
+    ///
+    /// Pixels that are transparent remain transparent
+    pub fn silhouette(&self, color: Color) -> ScaledBitmap {
+        let mut image_data = vec!(item_type => Color, capacity => self.image_data.len());
+        for pixel in self.image_data.iter() {
+            if self.transparency == Transparency::Black && *pixel == Color::BLACK {
+                image_data.push(*pixel);
+            } else {
+                image_data.push(color);
+            }
+        }
+        ScaledBitmap::new(image_data, self.width, self.height, self.transparency)
+    }
+}
+
+/// How the edges and the center of a `NinePatch` fill the space between its corners
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PatchFill {
+    /// Repeat the pixels as they are
+    Tile,
+    /// Stretch the pixels with nearest neighbor scaling
+    Stretch
+}
+
+/// A bitmap that can be drawn at any size by keeping its corners as they are
+/// and filling the space between them with its edges and center
+///
+/// The bitmap is divided into 9 parts by the widths of its left and right
+/// borders and the heights of its top and bottom borders
+///
+/// ```text
+///  left      right
+/// +----+----+----+
+/// |    |    |    | top
+/// +----+----+----+
+/// |    |    |    |
+/// +----+----+----+
+/// |    |    |    | bottom
+/// +----+----+----+
+/// ```
+///
+/// The corners are drawn once, the top and bottom edges are filled horizontally,
+/// the left and right edges vertically and the center in both directions
+#[derive(Clone)]
+pub struct NinePatch {
+    bitmap: ScaledBitmap,
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+    pub fill: PatchFill
+}
+
+impl NinePatch {
+    /// Creates a nine-patch out of `bitmap` with borders of the given sizes,
+    /// in the bitmap's scaled pixels
+    pub fn new(bitmap: ScaledBitmap, left: usize, top: usize, right: usize, bottom: usize, fill: PatchFill) -> Result<Self, &'static str> {
+        if left + right >= bitmap.width() || top + bottom >= bitmap.height() {
+            return Err("The borders of a nine-patch must leave space for its center");
+        }
+        Ok(Self { bitmap, left, top, right, bottom, fill })
+    }
+
+    /// The smallest width the nine-patch can be drawn with, without cutting its corners
+    pub fn min_width(&self) -> usize {
+        self.left + self.right
+    }
+
+    /// The smallest height the nine-patch can be drawn with, without cutting its corners
+    pub fn min_height(&self) -> usize {
+        self.top + self.bottom
+    }
+
+    /// Returns the color of the pixel at (`x`, `y`) when the nine-patch is drawn
+    /// `width` pixels wide and `height` pixels high, or None if it's transparent
+    pub fn pixel(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Color> {
+        let src_x = patch_src_coord(x, width, self.bitmap.width(), self.left, self.right, self.fill);
+        let src_y = patch_src_coord(y, height, self.bitmap.height(), self.top, self.bottom, self.fill);
+        // The image data is stored from the bottom row up
+        let pixel_array_y = self.bitmap.height() - src_y - 1;
+        let color = self.bitmap.image_data[pixel_array_y * self.bitmap.width() + src_x];
+        if self.bitmap.transparency == Transparency::Black && color == Color::BLACK {
+            None
+        } else {
+            Some(color)
+        }
+    }
+}
+
+/// Maps the coordinate `pos` along an axis of a nine-patch drawn `len` pixels long
+/// to a coordinate in the source bitmap, which is `src_len` pixels long with borders
+/// `start` and `end` pixels long
+fn patch_src_coord(pos: usize, len: usize, src_len: usize, start: usize, end: usize, fill: PatchFill) -> usize {
+    // Anything shorter than the borders would cut the corners, so
+    // the ends are drawn as they are and the middle is left out
+    let len = len.max(start + end);
+    if pos < start {
+        pos
+    } else if pos >= len - end {
+        src_len - (len - pos)
+    } else {
+        let src_middle_len = src_len - start - end;
+        let offset = pos - start;
+        match fill {
+            PatchFill::Tile => start + offset % src_middle_len,
+            PatchFill::Stretch => start + offset * src_middle_len / (len - start - end)
+        }
+    }
+}
+
+/// Calls `on_run` with the start and length of every run of pixels
+/// in a row `row_len` pixels long for which `is_opaque` is true
+fn for_each_opaque_run<O, R>(row_len: usize, is_opaque: O, mut on_run: R)
+    where O: Fn(usize) -> bool, R: FnMut(usize, usize)
+{
+    let mut run_start = None;
+    for x in 0..row_len {
+        match (run_start, is_opaque(x)) {
+            (None, true) => run_start = Some(x),
+            (Some(start), false) => {
+                on_run(start, x - start);
+                run_start = None;
+            }
+            _ => ()
+        }
+    }
+    if let Some(start) = run_start {
+        on_run(start, row_len - start);
+    }
+}
+
+/// Decodes the RLE8 pixel array `data` of a `width` by `height` bitmap,
+/// calling `put` with the index of every pixel it sets, counted from the
+/// bottom row up like an uncompressed pixel array, and its color
+///
+/// The data is a series of 2 byte pairs. A pair that starts with a count
+/// repeats its second byte that many times. One that starts with 0 is an escape:
+/// 0 ends the row, 1 ends the bitmap, 2 skips the number of pixels to the right
+/// and rows up in the next 2 bytes, and anything else is the number of pixels
+/// that follow as they are, padded to an even number of bytes
+///
+/// Format reference: <https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-compression>
+fn decode_rle8<P>(data: &[u8], width: usize, height: usize, mut put: P) -> Result<(), &'static str>
+    where P: FnMut(usize, u8)
+{
+    const CUT_SHORT: &str = "The bitmap's RLE8 data is cut short";
+    let byte = |i: usize| data.get(i).copied().ok_or(CUT_SHORT);
+    // Where the next pixel goes
+    let (mut x, mut row) = (0, 0);
+    // A run mustn't go past the end of its row
+    let run_start = |x: usize, row: usize, len: usize| {
+        if x + len <= width && row < height {
+            Ok(row * width + x)
+        } else {
+            Err("The bitmap's RLE8 data goes past its edges")
+        }
+    };
+    let mut i = 0;
+    // Data that ends without the end of bitmap escape is taken to end there
+    while i < data.len() {
+        let (count, value) = (byte(i)?, byte(i + 1)?);
+        i += 2;
+        match (count, value) {
+            (0, 0) => {
+                x = 0;
+                row += 1;
+            }
+            (0, 1) => break,
+            (0, 2) => {
+                x += byte(i)? as usize;
+                row += byte(i + 1)? as usize;
+                i += 2;
+            }
+            (0, len) => {
+                let len = len as usize;
+                let pixels = data.get(i..i + len).ok_or(CUT_SHORT)?;
+                let start = run_start(x, row, len)?;
+                for (offset, color) in pixels.iter().enumerate() {
+                    put(start + offset, *color);
+                }
+                x += len;
+                i += len + len % 2;
+            }
+            (count, color) => {
+                let count = count as usize;
+                let start = run_start(x, row, count)?;
+                for idx in start..start + count {
+                    put(idx, color);
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
 }
 
 fn is_valid_bitmap(raw_bytes: &[u8]) -> bool {
@@ -242,4 +665,221 @@ pub enum Transparency {
     Black,
     /// Draw everything, don't exclude any color
     None
-}
\ No newline at end of file
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec as StdVec;
+    use std::sync::Once;
+    use collections::allocator;
+    use machine::memory::{Addr, MemChunk};
+
+    /// The size of the heap the bitmaps in the tests are kept on
+    const TEST_HEAP_SIZE: usize = 0x10000;
+
+    /// Gives the allocator memory to hand out, since the bitmaps
+    /// are kept on the heap the bootloader sets up
+    fn init_heap() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            // u64s, so the heap starts where the allocator's list nodes are aligned
+            let mem = std::vec![0u64; TEST_HEAP_SIZE / 8].leak();
+            allocator::init(MemChunk { start_addr: Addr::from_ptr(mem.as_ptr()), size: TEST_HEAP_SIZE as u64 });
+        });
+    }
+
+    /// Turns `rows`, given from the top row down, into colors
+    pub(crate) fn colors(rows: &[&[u8]]) -> StdVec<StdVec<Color>> {
+        rows.iter().map(|row| row.iter().map(|&color| Color::new(color.into())).collect()).collect()
+    }
+
+    /// Creates a bitmap out of `rows`, given from the top row down
+    pub(crate) fn bitmap_of(rows: &[&[u8]]) -> ScaledBitmap {
+        init_heap();
+        let (width, height) = (rows[0].len(), rows.len());
+        let mut image_data = vec!(item_type => Color, capacity => width * height);
+        // The image data is stored from the bottom row up
+        for row in colors(rows).iter().rev() {
+            for &color in row {
+                image_data.push(color);
+            }
+        }
+        ScaledBitmap::new(image_data, width, height, Transparency::None)
+    }
+
+    /// The colors of the bitmap's pixels from the top row down
+    pub(crate) fn rows_of(bitmap: &ScaledBitmap) -> StdVec<StdVec<Color>> {
+        (0..bitmap.height()).rev()
+            .map(|row| bitmap.image_data.iter().skip(row * bitmap.width()).take(bitmap.width()).copied().collect())
+            .collect()
+    }
+
+    /// Collects the runs `for_each_opaque_run` finds in `row`, where 0 is transparent,
+    /// and returns them with the number of runs found
+    fn opaque_runs<const N: usize>(row: [u8; N]) -> ([(usize, usize); N], usize) {
+        let mut runs = [(0, 0); N];
+        let mut no_of_runs = 0;
+        for_each_opaque_run(N, |x| row[x] != 0, |start, len| {
+            runs[no_of_runs] = (start, len);
+            no_of_runs += 1;
+        });
+        (runs, no_of_runs)
+    }
+
+    #[test]
+    fn test_opaque_runs() {
+        let (runs, no_of_runs) = opaque_runs([0, 1, 1, 0, 0, 1, 0, 1]);
+        assert_eq!(no_of_runs, 3);
+        assert_eq!(runs[..no_of_runs], [(1, 2), (5, 1), (7, 1)]);
+
+        // Runs touching both ends of the row
+        let (runs, no_of_runs) = opaque_runs([1, 1, 0, 1]);
+        assert_eq!(runs[..no_of_runs], [(0, 2), (3, 1)]);
+
+        let (runs, no_of_runs) = opaque_runs([1, 1, 1, 1]);
+        assert_eq!(runs[..no_of_runs], [(0, 4)]);
+
+        let (_, no_of_runs) = opaque_runs([0, 0, 0]);
+        assert_eq!(no_of_runs, 0);
+    }
+
+    /// Decodes the RLE8 `data` of a bitmap `N` pixels in all, starting out black
+    fn rle8_pixels<const N: usize>(data: &[u8], width: usize) -> Result<[u8; N], &'static str> {
+        let mut pixels = [0; N];
+        decode_rle8(data, width, N / width, |idx, color| pixels[idx] = color)?;
+        Ok(pixels)
+    }
+
+    #[test]
+    fn test_decode_rle8() {
+        // A run, then 3 pixels as they are with a byte of padding, then the next row
+        let data = [2, 7, 0, 3, 1, 2, 3, 0, 0, 0, 4, 9, 0, 1];
+        assert_eq!(rle8_pixels::<10>(&data, 5), Ok([7, 7, 1, 2, 3, 9, 9, 9, 9, 0]));
+        // Skipping 1 pixel right and 1 row up leaves the pixels in between black
+        let data = [1, 5, 0, 2, 1, 1, 1, 6, 0, 1];
+        assert_eq!(rle8_pixels::<6>(&data, 3), Ok([5, 0, 0, 0, 0, 6]));
+        // Without the end of bitmap escape
+        assert_eq!(rle8_pixels::<2>(&[2, 4], 2), Ok([4, 4]));
+    }
+
+    #[test]
+    fn test_decode_rle8_errors() {
+        // A run longer than the row
+        assert!(rle8_pixels::<4>(&[3, 1], 2).is_err());
+        // Rows past the top
+        assert!(rle8_pixels::<4>(&[0, 0, 0, 0, 1, 1], 2).is_err());
+        // Pixels that are cut off
+        assert!(rle8_pixels::<4>(&[0, 3, 1, 2], 4).is_err());
+        assert!(rle8_pixels::<4>(&[2], 4).is_err());
+    }
+
+    #[test]
+    fn test_scaled_len() {
+        assert_eq!(scaled_len(12, 2.0), 24);
+        assert_eq!(scaled_len(12, 0.5), 6);
+        assert_eq!(scaled_len(12, 1.3), 16);
+        // Never shrunk to nothing
+        assert_eq!(scaled_len(12, 0.01), 1);
+    }
+
+    #[test]
+    fn test_nearest_src_coord() {
+        let enlarged: [usize; 6] = core::array::from_fn(|pos| nearest_src_coord(pos, 6, 4));
+        assert_eq!(enlarged, [0, 0, 1, 2, 2, 3]);
+        let shrunk: [usize; 3] = core::array::from_fn(|pos| nearest_src_coord(pos, 3, 6));
+        assert_eq!(shrunk, [0, 2, 4]);
+    }
+
+    #[test]
+    fn test_transform_src_pixel() {
+        // A 3 by 2 bitmap, whose corners go to each other's places
+        let corners = |transform: Transform| {
+            let (width, height) = if transform.swaps_dimensions() { (2, 3) } else { (3, 2) };
+            [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)]
+                .map(|(x, y)| transform.src_pixel(x, y, 3, 2))
+        };
+        assert_eq!(corners(Transform::Rotate90), [(0, 1), (0, 0), (2, 1), (2, 0)]);
+        assert_eq!(corners(Transform::Rotate180), [(2, 1), (0, 1), (2, 0), (0, 0)]);
+        assert_eq!(corners(Transform::Rotate270), [(2, 0), (2, 1), (0, 0), (0, 1)]);
+        assert_eq!(corners(Transform::FlipHorizontal), [(2, 0), (0, 0), (2, 1), (0, 1)]);
+        assert_eq!(corners(Transform::FlipVertical), [(0, 1), (2, 1), (0, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn test_cropped() {
+        let bitmap = bitmap_of(&[
+            &[1, 2, 3, 4],
+            &[5, 6, 7, 8],
+            &[9, 10, 11, 12]
+        ]);
+        let part = bitmap.cropped(1, 1, 2, 2);
+        assert_eq!((part.width(), part.height()), (2, 2));
+        assert_eq!(rows_of(&part), colors(&[&[6, 7], &[10, 11]]));
+        // The top row, and the whole bitmap
+        assert_eq!(rows_of(&bitmap.cropped(0, 0, 4, 1)), colors(&[&[1, 2, 3, 4]]));
+        assert_eq!(rows_of(&bitmap.cropped(0, 0, 4, 3)), rows_of(&bitmap));
+    }
+
+    #[test]
+    #[should_panic(expected = "The part is outside the bitmap")]
+    fn test_cropped_outside_the_bitmap() {
+        bitmap_of(&[&[1, 2], &[3, 4]]).cropped(1, 0, 2, 1);
+    }
+
+    #[test]
+    fn test_transformed() {
+        let bitmap = bitmap_of(&[
+            &[1, 2, 3],
+            &[4, 5, 6]
+        ]);
+        let cases: [(&str, Transform, &[&[u8]]); 5] = [
+            ("rotated 90", Transform::Rotate90, &[&[4, 1], &[5, 2], &[6, 3]]),
+            ("rotated 180", Transform::Rotate180, &[&[6, 5, 4], &[3, 2, 1]]),
+            ("rotated 270", Transform::Rotate270, &[&[3, 6], &[2, 5], &[1, 4]]),
+            ("flipped horizontally", Transform::FlipHorizontal, &[&[3, 2, 1], &[6, 5, 4]]),
+            ("flipped vertically", Transform::FlipVertical, &[&[4, 5, 6], &[1, 2, 3]])
+        ];
+        for (name, transform, expected) in cases {
+            let transformed = bitmap.transformed(transform);
+            assert_eq!((transformed.width(), transformed.height()), (expected[0].len(), expected.len()), "{}", name);
+            assert_eq!(rows_of(&transformed), colors(expected), "{}", name);
+        }
+        // Turning it all the way round gives the bitmap back
+        let turned = bitmap.transformed(Transform::Rotate90).transformed(Transform::Rotate270);
+        assert_eq!(rows_of(&turned), rows_of(&bitmap));
+    }
+
+    #[test]
+    fn test_patch_src_coord_corners() {
+        // A 12 pixel source with 4 pixel borders drawn 30 pixels long
+        for pos in 0..4 {
+            assert_eq!(patch_src_coord(pos, 30, 12, 4, 4, PatchFill::Tile), pos);
+            assert_eq!(patch_src_coord(pos, 30, 12, 4, 4, PatchFill::Stretch), pos);
+        }
+        for pos in 26..30 {
+            assert_eq!(patch_src_coord(pos, 30, 12, 4, 4, PatchFill::Tile), pos - 18);
+            assert_eq!(patch_src_coord(pos, 30, 12, 4, 4, PatchFill::Stretch), pos - 18);
+        }
+    }
+
+    #[test]
+    fn test_patch_src_coord_tile() {
+        let coords: [usize; 10] = core::array::from_fn(|i| patch_src_coord(i + 4, 18, 12, 4, 4, PatchFill::Tile));
+        assert_eq!(coords, [4, 5, 6, 7, 4, 5, 6, 7, 4, 5]);
+    }
+
+    #[test]
+    fn test_patch_src_coord_stretch() {
+        // The 4 middle pixels stretched to 8
+        let coords: [usize; 8] = core::array::from_fn(|i| patch_src_coord(i + 4, 16, 12, 4, 4, PatchFill::Stretch));
+        assert_eq!(coords, [4, 4, 5, 5, 6, 6, 7, 7]);
+    }
+
+    #[test]
+    fn test_patch_src_coord_too_short() {
+        // Only the borders are drawn when there's no space for the middle
+        let coords: [usize; 8] = core::array::from_fn(|pos| patch_src_coord(pos, 5, 12, 4, 4, PatchFill::Tile));
+        assert_eq!(coords, [0, 1, 2, 3, 8, 9, 10, 11]);
+    }
+}
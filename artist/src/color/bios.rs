@@ -1,5 +1,12 @@
 use core::cmp::PartialEq;
+use machine::port::{Port, PortReadWrite};
 use crate::Hue;
+use super::palette::Palette;
+
+/// The port the index of the first DAC color to be written is written to
+const DAC_WRITE_INDEX_PORT: u16 = 0x3c8;
+/// The port the red, green and blue of every color are written to, one after the other
+const DAC_DATA_PORT: u16 = 0x3c9;
 
 #[derive(Copy, Clone, PartialEq, Debug, Eq)]
 #[repr(transparent)]
@@ -28,6 +35,27 @@ impl Color {
     pub fn new(color: u8) -> Self {
         Self(color)
     }
+
+    /// The color at `brightness`, which is the same color, since
+    /// the palette is faded instead of the pixels in BIOS mode
+    pub(crate) fn dimmed(&self, _brightness: u8) -> Color {
+        *self
+    }
+}
+
+/// Sets the colors the card's DAC shows the pixels' indices as
+///
+/// The DAC only takes 6 bits of each color
+pub(crate) fn load_palette(palette: &Palette) {
+    let mut index_port: Port<u8> = Port::new(DAC_WRITE_INDEX_PORT);
+    let mut data_port: Port<u8> = Port::new(DAC_DATA_PORT);
+    // The index moves on to the next color by itself after every 3 writes
+    index_port.write(0);
+    for rgb in palette.colors() {
+        for intensity in rgb {
+            data_port.write(intensity >> 2);
+        }
+    }
 }
 
 impl Hue for Color {
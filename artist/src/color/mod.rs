@@ -1,5 +1,11 @@
 mod bios;
 mod uefi;
+mod palette;
+
+pub use palette::{Palette, FULL_BRIGHTNESS};
+pub(crate) use palette::fade_step;
+#[cfg(feature = "bios")]
+pub(crate) use bios::load_palette;
 
 #[cfg(feature = "bios")]
 pub use bios::Color;
@@ -0,0 +1,95 @@
+//! The colors the screen shows, and how bright it shows them
+//!
+//! In the BIOS's VGA mode, a pixel is an index into the 256 colors the card's
+//! DAC (digital to analog converter) holds, so changing the DAC's colors changes
+//! every pixel on the screen at once, without drawing anything. That's how the
+//! screen is faded in BIOS mode. UEFI modes have no palette, since every pixel
+//! has its own colors, so the screen is faded by ramping the colors of every
+//! pixel down as it's copied to the screen instead.
+//!
+//! # References
+//!
+//! * FreeVGA's DAC registers <http://www.osdever.net/FreeVGA/vga/colorreg.htm>
+
+use super::uefi::VGA_INDEX_TO_RGB_ARRAY;
+
+/// The brightness colors are shown at when nothing is faded
+pub const FULL_BRIGHTNESS: u8 = 255;
+
+/// 256 colors, each of the form [red, green, blue]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: [[u8; 3]; 256]
+}
+
+impl Palette {
+    /// The default VGA palette, which the bitmaps' colors are indices into
+    pub const fn vga() -> Self {
+        Self { colors: VGA_INDEX_TO_RGB_ARRAY }
+    }
+
+    /// The [red, green, blue] color at `idx`
+    pub fn color(&self, idx: u8) -> [u8; 3] {
+        self.colors[idx as usize]
+    }
+
+    pub fn set_color(&mut self, idx: u8, rgb: [u8; 3]) {
+        self.colors[idx as usize] = rgb;
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]; 256] {
+        &self.colors
+    }
+
+    /// The palette with every color at `brightness`, out of `FULL_BRIGHTNESS`
+    pub fn faded(&self, brightness: u8) -> Self {
+        let mut faded = *self;
+        for color in faded.colors.iter_mut() {
+            *color = color.map(|intensity| ramp(intensity, brightness));
+        }
+        faded
+    }
+}
+
+/// The `intensity` of a color at `brightness`, out of `FULL_BRIGHTNESS`
+pub(crate) const fn ramp(intensity: u8, brightness: u8) -> u8 {
+    (intensity as u16 * brightness as u16 / FULL_BRIGHTNESS as u16) as u8
+}
+
+/// The brightness `frame` frames into a fade of `frames` frames
+/// from brightness `from` to `to`
+pub(crate) fn fade_step(from: u8, to: u8, frame: usize, frames: usize) -> u8 {
+    if frame >= frames {
+        return to;
+    }
+    let (from, to) = (from as isize, to as isize);
+    (from + (to - from) * frame as isize / frames as isize) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faded_palette() {
+        let palette = Palette::vga();
+        assert_eq!(palette.color(15), [255, 255, 255]);
+        assert_eq!(palette.faded(FULL_BRIGHTNESS), palette);
+        assert_eq!(palette.faded(0).colors().iter().find(|color| **color != [0, 0, 0]), None);
+        assert_eq!(palette.faded(128).color(15), [128, 128, 128]);
+        assert_eq!(palette.faded(128).color(12), [128, 43, 43]);
+        let mut palette = palette;
+        palette.set_color(0, [10, 20, 30]);
+        assert_eq!(palette.color(0), [10, 20, 30]);
+    }
+
+    #[test]
+    fn test_fade_steps() {
+        let steps: [u8; 5] = core::array::from_fn(|frame| fade_step(FULL_BRIGHTNESS, 0, frame, 4));
+        assert_eq!(steps, [255, 192, 128, 64, 0]);
+        let steps: [u8; 4] = core::array::from_fn(|frame| fade_step(0, FULL_BRIGHTNESS, frame + 1, 3));
+        assert_eq!(steps, [85, 170, 255, 255]);
+        // Fading in no frames goes straight to the end
+        assert_eq!(fade_step(0, FULL_BRIGHTNESS, 0, 0), FULL_BRIGHTNESS);
+    }
+}
@@ -1,13 +1,12 @@
-use crate::Hue;
+use machine::framebuffer::PixelFormat;
+use crate::{Hue, SCREEN_BUFFER};
+use super::palette::ramp;
 
+/// A pixel's value, with the colors laid out the way the screen's
+/// framebuffer has them, so it can be copied to the screen as it is
 #[derive(Copy, Clone, PartialEq, Debug, Eq)]
-#[repr(C)]
-pub struct Color {
-    pub blue: u8,
-    pub green: u8,
-    pub red: u8,
-    pub reserved: u8
-}
+#[repr(transparent)]
+pub struct Color(u32);
 
 impl Color {
     pub const BLACK: u32       = Self::vga_index_to_color_u32(0);
@@ -28,24 +27,31 @@ impl Color {
     pub const WHITE: u32       = Self::vga_index_to_color_u32(15);
     pub const PURPLE: u32      = Self::vga_index_to_color_u32(0x6a);
 
-    /// Creates a new instance of the color
+    /// Creates a new instance of the color from a u32 of the form
+    /// blue, green, red, reserved, like the constants
     pub fn new(color: u32) -> Self {
-        Self {
-            blue: (color >> 24 & 0xff) as u8,
-            green: (color >> 16 & 0xff) as u8,
-            red: (color >> 8 & 0xff) as u8,
-            reserved: 0
-        }
+        let [blue, green, red, _] = color.to_be_bytes();
+        Self::from_rgb_array([red, green, blue])
     }
 
     /// Converts an array of form [red, green, blue] to a Color
-    const fn from_rgb_array(rgb: [u8; 3]) -> Color {
-        Color {
-            blue: rgb[2],
-            green: rgb[1],
-            red: rgb[0],
-            reserved: 0
-        }
+    /// in the screen's pixel format
+    fn from_rgb_array(rgb: [u8; 3]) -> Color {
+        Self::from_rgb_array_in(rgb, screen_pixel_format())
+    }
+
+    fn from_rgb_array_in([red, green, blue]: [u8; 3], pixel_format: PixelFormat) -> Color {
+        Color(pixel_format.encode(red, green, blue))
+    }
+
+    /// The [red, green, blue] intensities of the color
+    fn rgb_array(&self) -> [u8; 3] {
+        screen_pixel_format().decode(self.0)
+    }
+
+    /// The color at `brightness`, out of `FULL_BRIGHTNESS`
+    pub(crate) fn dimmed(&self, brightness: u8) -> Color {
+        Self::from_rgb_array(self.rgb_array().map(|intensity| ramp(intensity, brightness)))
     }
 
     /// Converts a VGA index in 320x200 VGA mode to a u32
@@ -56,6 +62,14 @@ impl Color {
     }
 }
 
+/// The pixel format of the screen's framebuffer
+///
+/// Before the framebuffer is known, colors are laid out in the format
+/// most cards use
+fn screen_pixel_format() -> PixelFormat {
+    SCREEN_BUFFER.get().map_or(PixelFormat::Bgr, |framebuffer| framebuffer.pixel_format())
+}
+
 impl Hue for Color {
     /// Converts a byte in the color indexed bitmap pixel array to
     /// a color
@@ -66,13 +80,14 @@ impl Hue for Color {
         Self::from_rgb_array(VGA_INDEX_TO_RGB_ARRAY[raw_color as usize])
     }
 
-    /// Returns a color into its numerical representation
+    /// Returns a color into its numerical representation, which is
+    /// the pixel's value in the screen's framebuffer
     fn to_num(&self) -> u32 {
-        u32::from_le_bytes([self.blue, self.green, self.red, 0])
+        self.0
     }
 }
 
-const VGA_INDEX_TO_RGB_ARRAY: [[u8; 3]; 256] = [
+pub(crate) const VGA_INDEX_TO_RGB_ARRAY: [[u8; 3]; 256] = [
     [0, 0, 0, ],
     [0, 0, 168, ],
     [0, 168, 0, ],
@@ -333,7 +348,25 @@ const VGA_INDEX_TO_RGB_ARRAY: [[u8; 3]; 256] = [
 
 impl PartialEq<u32> for Color {
     fn eq(&self, rhs: &u32) -> bool {
-        ((self.blue as u32) << 24 | (self.green as u32) << 16 | (self.red as u32) << 8)
-            == *rhs
+        let [red, green, blue] = self.rgb_array();
+        u32::from_be_bytes([blue, green, red, 0]) == *rhs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_in_pixel_formats() {
+        let yellow = VGA_INDEX_TO_RGB_ARRAY[14];
+        assert_eq!(Color::from_rgb_array_in(yellow, PixelFormat::Bgr).to_num(), 0x00ffff57);
+        assert_eq!(Color::from_rgb_array_in(yellow, PixelFormat::Rgb).to_num(), 0x0057ffff);
+        let rgb565 = PixelFormat::Bitmask { red: 0xf800, green: 0x07e0, blue: 0x001f };
+        assert_eq!(Color::from_rgb_array_in([255, 0, 0], rgb565).to_num(), 0xf800);
+        // Without a framebuffer, the colors are in the format most cards use
+        assert_eq!(Color::new(Color::YELLOW).to_num(), 0x00ffff57);
+        assert_eq!(Color::from_bitmap_data(14), Color::YELLOW);
+        assert!(Color::new(Color::WHITE) != Color::BLACK);
     }
 }
@@ -0,0 +1,135 @@
+//! A software mouse cursor
+//!
+//! The cursor is not part of the double buffer. It is composited directly
+//! on the screen after everything else has been drawn, so moving it doesn't
+//! require redrawing the scene. Before it is drawn, the pixels beneath it are
+//! saved, so they can be put back when it moves or is hidden.
+//!
+//! Nothing here depends on the graphics hardware, so it works the same way
+//! in BIOS's VGA mode and with UEFI's GOP framebuffer.
+
+use physics::Point;
+use num::Integer;
+use crate::{Screen, Color, SCREEN_WIDTH, SCREEN_HEIGHT, X_SCALE, Y_SCALE};
+use crate::dirty_rects::Rect;
+
+/// The width of the cursor shape, before scaling
+const CURSOR_WIDTH: usize = 8;
+/// The height of the cursor shape, before scaling
+const CURSOR_HEIGHT: usize = 12;
+
+const SCALED_CURSOR_WIDTH: usize = CURSOR_WIDTH * X_SCALE;
+const SCALED_CURSOR_HEIGHT: usize = CURSOR_HEIGHT * Y_SCALE;
+
+/// An arrow pointing to the top left corner, which is the cursor's hotspot
+///
+/// 'X' is the outline, '.' is the fill and ' ' is transparent
+const CURSOR_SHAPE: [&[u8; CURSOR_WIDTH]; CURSOR_HEIGHT] = [
+    b"X       ",
+    b"XX      ",
+    b"X.X     ",
+    b"X..X    ",
+    b"X...X   ",
+    b"X....X  ",
+    b"X.....X ",
+    b"X......X",
+    b"X...XXXX",
+    b"X.X.X   ",
+    b"XX X.X  ",
+    b"X   XX  "
+];
+
+/// The state of the mouse cursor layer
+pub(crate) struct Cursor {
+    /// The position of the cursor's hotspot on the screen
+    pos: Point,
+    /// Tells whether or not the cursor should be drawn
+    visible: bool,
+    /// The position the cursor was drawn at, if it is on the screen now
+    drawn_at: Option<Point>,
+    /// The pixels that were on the screen beneath the cursor before it was drawn
+    save_under: [[Color; SCALED_CURSOR_WIDTH]; SCALED_CURSOR_HEIGHT]
+}
+
+impl Cursor {
+    /// Creates a new hidden cursor in the middle of the screen
+    pub(crate) fn new() -> Self {
+        Self {
+            pos: Point((SCREEN_WIDTH / 2).as_i16(), (SCREEN_HEIGHT / 2).as_i16()),
+            visible: false,
+            drawn_at: None,
+            save_under: [[Color::new(Color::BLACK); SCALED_CURSOR_WIDTH]; SCALED_CURSOR_HEIGHT]
+        }
+    }
+
+    pub(crate) fn pos(&self) -> Point {
+        self.pos
+    }
+
+    pub(crate) fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub(crate) fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// Sets the cursor position, keeping the hotspot on the screen
+    pub(crate) fn set_pos(&mut self, pos: Point) {
+        let x = pos.x().clamp(0, SCREEN_WIDTH.as_i16() - 1);
+        let y = pos.y().clamp(0, SCREEN_HEIGHT.as_i16() - 1);
+        self.pos = Point(x, y);
+    }
+
+    /// Puts the pixels that were beneath the cursor back on the screen
+    pub(crate) fn erase(&mut self, screen: &mut Screen) {
+        if let Some(pos) = self.drawn_at.take() {
+            for (y, x) in Self::pixels_on_screen(pos) {
+                screen.set_pixel(pos.x().as_usize() + x, pos.y().as_usize() + y, self.save_under[y][x]);
+            }
+        }
+    }
+
+    /// Saves the pixels beneath the cursor's position and draws the cursor over them
+    ///
+    /// The cursor must have been erased from the screen first
+    pub(crate) fn draw(&mut self, screen: &mut Screen) {
+        if !self.visible {
+            return;
+        }
+        let pos = self.pos;
+        let outline = Color::new(Color::BLACK);
+        let fill = Color::new(Color::WHITE);
+        for (y, x) in Self::pixels_on_screen(pos) {
+            let (screen_x, screen_y) = (pos.x().as_usize() + x, pos.y().as_usize() + y);
+            self.save_under[y][x] = screen.pixel(screen_x, screen_y);
+            match CURSOR_SHAPE[y / Y_SCALE][x / X_SCALE] {
+                b'X' => screen.set_pixel(screen_x, screen_y, outline),
+                b'.' => screen.set_pixel(screen_x, screen_y, fill),
+                _ => ()
+            }
+        }
+        self.drawn_at = Some(pos);
+    }
+
+    /// The part of the screen the cursor is drawn over, if it is on the screen now
+    pub(crate) fn drawn_rect(&self) -> Option<Rect> {
+        self.drawn_at.and_then(|pos| Rect::clipped(pos, SCALED_CURSOR_WIDTH, SCALED_CURSOR_HEIGHT))
+    }
+
+    /// Forgets the pixels beneath the cursor without putting them back
+    ///
+    /// For when the whole screen has been overwritten, which also
+    /// overwrites the cursor
+    pub(crate) fn invalidate(&mut self) {
+        self.drawn_at = None;
+    }
+
+    /// An iterator over the (y, x) offsets of the cursor's pixels at `pos`
+    /// that fall within the screen
+    fn pixels_on_screen(pos: Point) -> impl Iterator<Item = (usize, usize)> {
+        let height = SCALED_CURSOR_HEIGHT.min(SCREEN_HEIGHT - pos.y().as_usize());
+        let width = SCALED_CURSOR_WIDTH.min(SCREEN_WIDTH - pos.x().as_usize());
+        (0..height).flat_map(move |y| (0..width).map(move |x| (y, x)))
+    }
+}
@@ -0,0 +1,199 @@
+//! The parts of the double buffer that have changed since it was last
+//! put on the screen
+//!
+//! Copying the whole double buffer to the screen every frame is slow at
+//! 640x480, while a frame usually only changes a few small parts of it,
+//! like where the ball and the paddle were and are now. The drawing
+//! functions mark the rectangles they draw in, and only those are copied.
+
+use physics::Point;
+use num::Integer;
+use crate::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+/// The most rectangles kept apart before they're all merged into one
+const MAX_DIRTY_RECTS: usize = 16;
+
+/// A rectangle that is entirely on the screen and isn't empty
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rect {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize
+}
+
+impl Rect {
+    const FULL_SCREEN: Rect = Rect { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT };
+
+    /// The part of the `width` by `height` rectangle at `pos` that is on the screen,
+    /// or None if none of it is
+    pub(crate) fn clipped(pos: Point, width: usize, height: usize) -> Option<Self> {
+        let (x, y) = (pos.x().as_isize(), pos.y().as_isize());
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = (x + width.as_isize()).min(SCREEN_WIDTH.as_isize());
+        let bottom = (y + height.as_isize()).min(SCREEN_HEIGHT.as_isize());
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Rect {
+            x: left.as_usize(),
+            y: top.as_usize(),
+            width: (right - left).as_usize(),
+            height: (bottom - top).as_usize()
+        })
+    }
+
+    fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    /// Tells whether or not the rectangles share any pixels
+    pub(crate) fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.right() && other.x < self.right()
+            && self.y < other.bottom() && other.y < self.bottom()
+    }
+
+    /// Tells whether or not the rectangles share any pixels or are right next to each other,
+    /// so putting them together doesn't take in much that hasn't changed
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right() && other.x <= self.right()
+            && self.y <= other.bottom() && other.y <= self.bottom()
+    }
+
+    /// The smallest rectangle that contains both rectangles
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y
+        }
+    }
+}
+
+/// The rectangles of the double buffer that have to be copied to the screen
+///
+/// Rectangles that touch are merged, so no pixel is copied twice
+pub(crate) struct DirtyRects {
+    rects: [Rect; MAX_DIRTY_RECTS],
+    len: usize
+}
+
+impl DirtyRects {
+    /// Starts out with the whole screen dirty, since nothing
+    /// has been put on it yet
+    pub(crate) const fn new() -> Self {
+        Self {
+            rects: [Rect::FULL_SCREEN; MAX_DIRTY_RECTS],
+            len: 1
+        }
+    }
+
+    /// Starts out with nothing dirty
+    pub(crate) const fn empty() -> Self {
+        Self {
+            rects: [Rect::FULL_SCREEN; MAX_DIRTY_RECTS],
+            len: 0
+        }
+    }
+
+    /// Marks the part of the `width` by `height` rectangle at `pos`
+    /// that is on the screen as dirty
+    pub(crate) fn add(&mut self, pos: Point, width: usize, height: usize) {
+        let mut rect = match Rect::clipped(pos, width, height) {
+            Some(rect) => rect,
+            None => return
+        };
+        // A merged rectangle can touch others that the new one didn't,
+        // so merging goes on until none do
+        while let Some(i) = self.rects().iter().position(|other| other.touches(&rect)) {
+            rect = rect.union(&self.rects[i]);
+            self.len -= 1;
+            self.rects[i] = self.rects[self.len];
+        }
+        if self.len == MAX_DIRTY_RECTS {
+            // Too many to keep apart, so everything is copied in one go
+            rect = self.rects().iter().fold(rect, |all, other| all.union(other));
+            self.len = 0;
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    pub(crate) fn rects(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    pub(crate) fn is_full_screen(&self) -> bool {
+        self.rects() == [Rect::FULL_SCREEN]
+    }
+
+    /// Forgets every rectangle, after they've been copied to the screen
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipped() {
+        assert_eq!(Rect::clipped(Point(-5, 10), 10, 20), Some(Rect { x: 0, y: 10, width: 5, height: 20 }));
+        let (screen_width, screen_height) = (SCREEN_WIDTH.as_i16(), SCREEN_HEIGHT.as_i16());
+        assert_eq!(
+            Rect::clipped(Point(screen_width - 2, screen_height - 3), 10, 10),
+            Some(Rect { x: SCREEN_WIDTH - 2, y: SCREEN_HEIGHT - 3, width: 2, height: 3 })
+        );
+        assert_eq!(Rect::clipped(Point(-10, 0), 10, 10), None);
+        assert_eq!(Rect::clipped(Point(screen_width, 0), 10, 10), None);
+        assert_eq!(Rect::clipped(Point(0, 0), 0, 10), None);
+    }
+
+    #[test]
+    fn test_merging() {
+        let mut dirty = DirtyRects::new();
+        assert!(dirty.is_full_screen());
+        dirty.clear();
+        assert!(dirty.rects().is_empty());
+        dirty.add(Point(10, 10), 10, 10);
+        dirty.add(Point(100, 100), 10, 10);
+        assert_eq!(dirty.rects().len(), 2);
+        // Overlaps the first one
+        dirty.add(Point(15, 15), 10, 10);
+        // Right next to the second one
+        dirty.add(Point(110, 100), 5, 10);
+        assert_eq!(dirty.rects(), [
+            Rect { x: 10, y: 10, width: 15, height: 15 },
+            Rect { x: 100, y: 100, width: 15, height: 10 }
+        ]);
+        // Joins both, so they become one
+        dirty.add(Point(20, 20), 85, 85);
+        assert_eq!(dirty.rects(), [Rect { x: 10, y: 10, width: 105, height: 100 }]);
+        // Off the screen
+        dirty.add(Point(-20, -20), 10, 10);
+        assert_eq!(dirty.rects().len(), 1);
+    }
+
+    #[test]
+    fn test_too_many_rects() {
+        let mut dirty = DirtyRects::new();
+        dirty.clear();
+        for i in 0..MAX_DIRTY_RECTS as i16 {
+            dirty.add(Point(i * 4, 0), 2, 2);
+        }
+        assert_eq!(dirty.rects().len(), MAX_DIRTY_RECTS);
+        dirty.add(Point(0, 10), 2, 2);
+        assert_eq!(dirty.rects(), [Rect { x: 0, y: 0, width: MAX_DIRTY_RECTS * 4 - 2, height: 12 }]);
+        dirty.add(Point(0, 0), SCREEN_WIDTH, SCREEN_HEIGHT);
+        assert!(dirty.is_full_screen());
+    }
+}
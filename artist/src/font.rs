@@ -3,6 +3,10 @@
 //! The arrays of bytes are descriptions of how to print a letter on screen
 //! Each byte in an array gives the pixel configurations for printing a row of the
 //! character on an 8x8 buffer
+//!
+//! `FONT` is what text is written in. Text written with `Artist::write_str_at`
+//! can be in any `BitmapFont` instead, like the taller `LARGE` font, the narrower
+//! `PROPORTIONAL` font or a font read from a PSF1 file
 
 use core::ops::Index;
 
@@ -936,3 +940,207 @@ pub const FONT: Font = Font([
     CURLY_BRACE_CLOSE,
     TILDE
 ]);
+
+/// The number of glyphs in `FONT`, one for each printable ascii character
+const GLYPHS: usize = 95;
+/// The width of every glyph in a bitmap font, which is a byte per row
+const GLYPH_WIDTH: usize = 8;
+/// The columns left between the characters of a proportional font
+const PROPORTIONAL_SPACING: u8 = 1;
+/// The columns a space takes in a proportional font, since it has no pixels to measure
+const PROPORTIONAL_SPACE_WIDTH: u8 = 3;
+
+/// A typeface that text can be written in with `Artist::write_str_at`
+///
+/// Every glyph is 8 pixels wide and `height` pixels high, with a byte for each
+/// row and the leftmost pixel in the highest bit, like the glyphs in `FONT`
+#[derive(Debug, PartialEq)]
+pub struct BitmapFont {
+    /// The rows of every glyph, one glyph after the other
+    glyphs: &'static [u8],
+    /// The ascii code of the character whose glyph comes first
+    first_char: u8,
+    height: usize,
+    /// The first column and the number of columns of each glyph that are drawn,
+    /// for a proportional font. All of a monospaced font's columns are drawn
+    columns: Option<&'static [(u8, u8); GLYPHS]>
+}
+
+/// The 8x8 font, the same as `FONT`
+pub static SMALL: BitmapFont = BitmapFont {
+    glyphs: &SMALL_GLYPHS,
+    first_char: b' ',
+    height: 8,
+    columns: None
+};
+
+/// An 8x16 font, for titles
+pub static LARGE: BitmapFont = BitmapFont {
+    glyphs: &LARGE_GLYPHS,
+    first_char: b' ',
+    height: 16,
+    columns: None
+};
+
+/// The 8x8 font with every character only as wide as its pixels,
+/// so narrow characters like 'I' and '1' take less space
+pub static PROPORTIONAL: BitmapFont = BitmapFont {
+    glyphs: &SMALL_GLYPHS,
+    first_char: b' ',
+    height: 8,
+    columns: Some(&PROPORTIONAL_COLUMNS)
+};
+
+static SMALL_GLYPHS: [u8; GLYPHS * 8] = small_glyphs();
+static LARGE_GLYPHS: [u8; GLYPHS * 16] = large_glyphs();
+static PROPORTIONAL_COLUMNS: [(u8, u8); GLYPHS] = proportional_columns();
+
+/// The glyphs of `FONT` one after the other
+const fn small_glyphs() -> [u8; GLYPHS * 8] {
+    let mut glyphs = [0; GLYPHS * 8];
+    let mut i = 0;
+    while i < glyphs.len() {
+        glyphs[i] = FONT.0[i / 8][i % 8];
+        i += 1;
+    }
+    glyphs
+}
+
+/// The glyphs of `FONT` with every row doubled
+const fn large_glyphs() -> [u8; GLYPHS * 16] {
+    let mut glyphs = [0; GLYPHS * 16];
+    let mut i = 0;
+    while i < glyphs.len() {
+        glyphs[i] = FONT.0[i / 16][i % 16 / 2];
+        i += 1;
+    }
+    glyphs
+}
+
+/// The columns between the leftmost and the rightmost pixels of each glyph of `FONT`
+const fn proportional_columns() -> [(u8, u8); GLYPHS] {
+    let mut columns = [(0, PROPORTIONAL_SPACE_WIDTH); GLYPHS];
+    let mut i = 0;
+    while i < GLYPHS {
+        // Every column with a pixel in any row
+        let mut used = 0u8;
+        let mut row = 0;
+        while row < 8 {
+            used |= FONT.0[i][row];
+            row += 1;
+        }
+        if used != 0 {
+            let first = used.leading_zeros() as u8;
+            columns[i] = (first, GLYPH_WIDTH as u8 - first - used.trailing_zeros() as u8);
+        }
+        i += 1;
+    }
+    columns
+}
+
+impl BitmapFont {
+    /// Reads a font in the PC Screen Font version 1 format, which is the format of
+    /// the Linux console's fonts, so any of them can be used
+    ///
+    /// For information on the format: <https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html>
+    pub fn from_psf1(raw_bytes: &'static [u8]) -> Result<Self, &'static str> {
+        const MAGIC: [u8; 2] = [0x36, 0x04];
+        const HEADER_SIZE: usize = 4;
+        /// Set in the mode if the font has 512 glyphs instead of 256
+        const MODE_512: u8 = 0x01;
+        if raw_bytes.len() < HEADER_SIZE || raw_bytes[..2] != MAGIC {
+            return Err("The font is not a PSF1 font");
+        }
+        let no_of_glyphs = if raw_bytes[2] & MODE_512 != 0 { 512 } else { 256 };
+        let height = raw_bytes[3] as usize;
+        let glyphs = raw_bytes.get(HEADER_SIZE..HEADER_SIZE + no_of_glyphs * height)
+            .ok_or("The font file is too short for its glyphs")?;
+        Ok(Self { glyphs, first_char: 0, height, columns: None })
+    }
+
+    /// The height of every glyph
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The rows of the glyph of `c`, or None if the font has no glyph for it
+    pub fn glyph(&self, c: u8) -> Option<&'static [u8]> {
+        let start = (c.checked_sub(self.first_char)? as usize) * self.height;
+        self.glyphs.get(start..start + self.height)
+    }
+
+    /// The first column of `c`'s glyph that's drawn and the number of columns
+    /// from there to the start of the next character
+    pub fn columns(&self, c: u8) -> (usize, usize) {
+        match self.columns {
+            Some(columns) => {
+                let (first, len) = c.checked_sub(self.first_char)
+                    .and_then(|i| columns.get(i as usize))
+                    .copied()
+                    .unwrap_or((0, GLYPH_WIDTH as u8));
+                (first as usize, (len + PROPORTIONAL_SPACING) as usize)
+            }
+            None => (0, GLYPH_WIDTH)
+        }
+    }
+
+    /// The number of columns between the start of `c` and the start of the next character
+    pub fn advance(&self, c: u8) -> usize {
+        self.columns(c).1
+    }
+
+    /// The width of the longest line of `s` in the font's pixels
+    pub fn text_width(&self, s: &str) -> usize {
+        s.split('\n').map(|line| line.bytes().map(|c| self.advance(c)).sum()).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fonts_have_the_same_characters() {
+        for c in b' '..=b'~' {
+            let glyph = &FONT[c];
+            assert_eq!(SMALL.glyph(c), Some(&glyph[..]));
+            let large = LARGE.glyph(c).unwrap();
+            for (y, row) in large.iter().enumerate() {
+                assert_eq!(*row, glyph[y / 2]);
+            }
+        }
+        assert_eq!(SMALL.glyph(b'\n'), None);
+        assert_eq!(LARGE.glyph(127), None);
+    }
+
+    #[test]
+    fn test_proportional_columns() {
+        // ░░░██░░░ takes 2 columns and the space after it
+        assert_eq!(PROPORTIONAL.columns(b'!'), (3, 3));
+        assert_eq!(PROPORTIONAL.columns(b' '), (0, 4));
+        assert_eq!(PROPORTIONAL.columns(b'#'), (1, 8));
+        assert_eq!(SMALL.columns(b'!'), (0, 8));
+        assert_eq!(PROPORTIONAL.text_width("!!\n!"), 6);
+        assert_eq!(SMALL.text_width("!!\n!"), 16);
+    }
+
+    #[test]
+    fn test_psf1() {
+        static PSF: [u8; 4 + 256 * 2] = {
+            let mut bytes = [0; 4 + 256 * 2];
+            bytes[0] = 0x36;
+            bytes[1] = 0x04;
+            bytes[3] = 2;
+            // The glyph of 'A'
+            bytes[4 + 65 * 2] = 0x18;
+            bytes[4 + 65 * 2 + 1] = 0x24;
+            bytes
+        };
+        let font = BitmapFont::from_psf1(&PSF).unwrap();
+        assert_eq!(font.height(), 2);
+        assert_eq!(font.glyph(b'A'), Some(&[0x18, 0x24][..]));
+        assert_eq!(font.advance(b'A'), 8);
+        assert!(BitmapFont::from_psf1(&PSF[..100]).is_err());
+        assert!(BitmapFont::from_psf1(&PSF[1..]).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! The glyphs of the font, expanded to the screen's scale in the colors and
+//! style text is drawn in
+//!
+//! Working out the color of every scaled pixel of a character, especially
+//! for outlined text, made text heavy screens slow. A glyph is expanded the
+//! first time it's drawn and copied from the cache after that. Changing the
+//! colors or style text is drawn in empties the cache
+
+use crate::{Color, ColorCode, TextStyle, FONT_WIDTH, FONT_HEIGHT, X_SCALE, Y_SCALE};
+use crate::{font, glyph_pixel_color};
+
+pub(crate) const SCALED_GLYPH_WIDTH: usize = FONT_WIDTH * X_SCALE;
+pub(crate) const SCALED_GLYPH_HEIGHT: usize = FONT_HEIGHT * Y_SCALE;
+
+/// The number of printable ascii characters, which are the ones in the font
+const GLYPHS: usize = (b'~' - b' ' + 1) as usize;
+
+/// A glyph at the screen's scale
+#[derive(Clone, Copy)]
+pub(crate) struct ScaledGlyph {
+    /// The color of each pixel, or None where the pixel is transparent
+    pub(crate) pixels: [[Option<Color>; SCALED_GLYPH_WIDTH]; SCALED_GLYPH_HEIGHT]
+}
+
+const NO_GLYPH: Option<ScaledGlyph> = None;
+
+pub(crate) struct GlyphCache {
+    /// The colors and style the cached glyphs were expanded in
+    style: Option<(ColorCode, TextStyle)>,
+    glyphs: [Option<ScaledGlyph>; GLYPHS]
+}
+
+impl GlyphCache {
+    pub(crate) const fn new() -> Self {
+        Self { style: None, glyphs: [NO_GLYPH; GLYPHS] }
+    }
+
+    /// The glyph of the printable ascii character `c` drawn in `color_code` and `text_style`
+    pub(crate) fn get(&mut self, c: u8, color_code: ColorCode, text_style: TextStyle) -> &ScaledGlyph {
+        if self.style != Some((color_code, text_style)) {
+            self.glyphs = [NO_GLYPH; GLYPHS];
+            self.style = Some((color_code, text_style));
+        }
+        self.glyphs[(c - b' ') as usize].get_or_insert_with(|| expand(&font::FONT[c], color_code, text_style))
+    }
+}
+
+/// Works out the color of every pixel of `glyph` at the screen's scale
+fn expand(glyph: &[u8; 8], color_code: ColorCode, text_style: TextStyle) -> ScaledGlyph {
+    let mut scaled = ScaledGlyph { pixels: [[None; SCALED_GLYPH_WIDTH]; SCALED_GLYPH_HEIGHT] };
+    for y in 0..FONT_HEIGHT {
+        for x in 0..FONT_WIDTH {
+            let color = glyph_pixel_color(glyph, x, y, color_code, text_style);
+            for row in &mut scaled.pixels[y * Y_SCALE..(y + 1) * Y_SCALE] {
+                row[x * X_SCALE..(x + 1) * X_SCALE].fill(color);
+            }
+        }
+    }
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyphs_are_expanded_in_the_current_style() {
+        let fg = Color::new(Color::YELLOW);
+        let bg = Color::new(Color::BLACK);
+        let outline = Color::new(Color::RED);
+        let color_code = ColorCode(fg, bg);
+        let mut cache = GlyphCache::new();
+
+        let glyph = cache.get(b'A', color_code, TextStyle::Plain);
+        for y in 0..SCALED_GLYPH_HEIGHT {
+            for x in 0..SCALED_GLYPH_WIDTH {
+                let expected = glyph_pixel_color(&font::FONT[b'A'], x / X_SCALE, y / Y_SCALE, color_code, TextStyle::Plain);
+                assert_eq!(glyph.pixels[y][x], expected);
+            }
+        }
+        // Plain glyphs have no transparent pixels
+        assert!(glyph.pixels.iter().flatten().all(|pixel| pixel.is_some()));
+
+        // Changing the style expands the glyph again instead of using the plain one
+        let glyph = cache.get(b'A', color_code, TextStyle::Outline(outline));
+        assert!(glyph.pixels.iter().flatten().any(|pixel| pixel.is_none()));
+        assert!(glyph.pixels.iter().flatten().any(|pixel| *pixel == Some(outline)));
+    }
+}
@@ -10,16 +10,40 @@ use lazy_static::lazy_static;
 use sync::mutex::Mutex;
 use sync::once::Once;
 use physics::Point;
-use machine::memory::Addr;
+use machine::framebuffer::Framebuffer;
+use machine::{vsync, time};
 use num::Integer;
+use collections::allocator::{self, Allocator};
 
 pub mod font;
 pub mod bitmap;
+pub mod sprite;
 
 mod color;
-pub use color::{Color, Hue};
+pub use color::{Color, Hue, Palette, FULL_BRIGHTNESS};
 
-use bitmap::{ScaledBitmap, Transparency};
+mod cursor;
+use cursor::Cursor;
+
+mod glyph_cache;
+use glyph_cache::{GlyphCache, SCALED_GLYPH_WIDTH, SCALED_GLYPH_HEIGHT};
+
+mod dirty_rects;
+use dirty_rects::{DirtyRects, Rect};
+
+mod viewport;
+use viewport::{Viewport, ClipRect};
+
+mod shapes;
+pub use shapes::MAX_POLYGON_VERTICES;
+
+mod perf_hud;
+pub use perf_hud::{PerfStats, PERF_HUD_LINE_LEN, PERF_HUD_LINES, PERF_HUD_WIDTH, PERF_HUD_HEIGHT};
+use perf_hud::FrameTimer;
+
+use bitmap::{ScaledBitmap, NinePatch, OpaqueSpan};
+use sprite::{SpriteSheet, Animation};
+use font::BitmapFont;
 
 #[cfg(feature = "bios")]
 pub const SCREEN_WIDTH: usize = 320;
@@ -35,27 +59,73 @@ pub const X_SCALE: usize = SCREEN_WIDTH / 320;
 /// Factor by which bitmaps should be scaled vertically to fit the screen
 pub const Y_SCALE: usize = SCREEN_HEIGHT / 200;
 
+/// The dimensions of the screen the game is drawn on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenInfo {
+    pub width: usize,
+    pub height: usize
+}
+
+impl ScreenInfo {
+    /// Factor by which art drawn for a 320x200 screen has to be scaled
+    /// horizontally to fit this screen
+    pub fn x_scale(&self) -> usize {
+        self.width / 320
+    }
+
+    /// Factor by which art drawn for a 320x200 screen has to be scaled
+    /// vertically to fit this screen
+    pub fn y_scale(&self) -> usize {
+        self.height / 200
+    }
+}
+
+/// Returns the dimensions of the screen
+pub fn screen_info() -> ScreenInfo {
+    ScreenInfo { width: SCREEN_WIDTH, height: SCREEN_HEIGHT }
+}
+
 /// Height of the letters and numbers in the font module
 pub const FONT_HEIGHT: usize = 8;
 /// Width of the letters and numbers in the font module
 pub const FONT_WIDTH: usize = 8;
 
 pub const DOUBLE_BUFFER_SIZE: usize = SCREEN_HEIGHT * SCREEN_WIDTH;
-pub static SCREEN_BUFFER_ADDRESS: Once<Addr> = Once::new();
+/// The number of off-screen targets that can exist at once
+pub const MAX_OFFSCREEN_TARGETS: usize = 4;
+/// The memory the screen is displayed from
+pub static SCREEN_BUFFER: Once<Framebuffer> = Once::new();
 
 lazy_static! {
     pub static ref ARTIST: Mutex<Artist> = Mutex::new(Artist {
         x_pos: 0,
         y_pos: 0,
         color_code: ColorCode(Color::new(Color::YELLOW), Color::new(Color::BLACK)),
+        text_style: TextStyle::Plain,
         vga_buffer: {
-            let screen_buffer_addr = SCREEN_BUFFER_ADDRESS.get()
+            let screen_buffer = SCREEN_BUFFER.get()
                 .expect("The screen buffer is not initialized");
-            unsafe { &mut *(screen_buffer_addr.as_mut_ptr() as *mut VGABuffer) }
+            Screen::new(screen_buffer)
         },
         double_buffer: VGABuffer {
             pixels: [[Color::new(Color::BLACK); SCREEN_WIDTH]; SCREEN_HEIGHT]
-        }
+        },
+        offscreen_targets: {
+            const NO_TARGET: Option<&'static mut VGABuffer> = None;
+            [NO_TARGET; MAX_OFFSCREEN_TARGETS]
+        },
+        target: Target::DoubleBuffer,
+        cursor: Cursor::new(),
+        glyph_cache: GlyphCache::new(),
+        dirty_rects: DirtyRects::new(),
+        viewport: Viewport::new(),
+        present_mode: PresentMode::Immediate,
+        third_buffer: None,
+        pending_rects: DirtyRects::empty(),
+        palette: Palette::vga(),
+        brightness: FULL_BRIGHTNESS,
+        frame_timer: FrameTimer::new(),
+        perf_hud_visible: false
     });
 }
 
@@ -84,6 +154,16 @@ pub fn get_artist() -> &'static Mutex<Artist> {
     &ARTIST
 }
 
+/// Blacks the screen out
+///
+/// The artist isn't locked, since whoever is shutting the computer down
+/// may be holding it, so this is only meant for a shutdown hook
+pub fn clear_screen() {
+    if let Some(screen_buffer) = SCREEN_BUFFER.get() {
+        screen_buffer.clear();
+    }
+}
+
 /// A foreground/background color code for printing characters
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ColorCode(Color, Color);
@@ -105,6 +185,63 @@ struct VGABuffer {
     pixels: [[Color; SCREEN_WIDTH]; SCREEN_HEIGHT]
 }
 
+impl VGABuffer {
+    /// Copies the `width` by `height` rectangle of pixels at `src` to `dst`,
+    /// leaving out the parts of the rectangles that are off the screen
+    fn copy_rect(&mut self, src: Point, dst: Point, width: usize, height: usize) {
+        let (src_x, src_y) = (src.x().as_isize(), src.y().as_isize());
+        let (dst_x, dst_y) = (dst.x().as_isize(), dst.y().as_isize());
+        let screen_width = SCREEN_WIDTH.as_isize();
+        let screen_height = SCREEN_HEIGHT.as_isize();
+        // The columns of the rectangle that are on the screen at both positions
+        let first_col = 0.max(-src_x).max(-dst_x);
+        let end_col = width.as_isize().min(screen_width - src_x).min(screen_width - dst_x);
+        if first_col >= end_col {
+            return;
+        }
+        let row_len = (end_col - first_col).as_usize();
+        let pixels_ptr = self.pixels.as_mut_ptr().cast::<Color>();
+        let copy_row = |row: isize| {
+            let (from_y, to_y) = (src_y + row, dst_y + row);
+            if from_y < 0 || from_y >= screen_height || to_y < 0 || to_y >= screen_height {
+                return;
+            }
+            unsafe {
+                let from = pixels_ptr.offset(from_y * screen_width + src_x + first_col);
+                let to = pixels_ptr.offset(to_y * screen_width + dst_x + first_col);
+                // Like memmove, so the rows can overlap
+                core::ptr::copy(from, to, row_len);
+            }
+        };
+        // Rows are copied starting from the side the rectangle is moving to,
+        // so no row is overwritten before it has been copied
+        if dst_y > src_y {
+            (0..height.as_isize()).rev().for_each(copy_row);
+        } else {
+            (0..height.as_isize()).for_each(copy_row);
+        }
+    }
+
+    /// Copies the `width` by `height` rectangle of pixels at `pos` in `src`
+    /// to the same place in this buffer, leaving out the part that is off the screen
+    fn copy_rect_from(&mut self, src: &VGABuffer, pos: Point, width: usize, height: usize) {
+        let (x, y) = (pos.x().as_isize(), pos.y().as_isize());
+        let first_col = 0.max(-x);
+        let end_col = width.as_isize().min(SCREEN_WIDTH.as_isize() - x);
+        if first_col >= end_col {
+            return;
+        }
+        let first_row = 0.max(-y);
+        let end_row = height.as_isize().min(SCREEN_HEIGHT.as_isize() - y);
+        let col = (x + first_col).as_usize();
+        let row_len = (end_col - first_col).as_usize();
+        for row in first_row..end_row {
+            let row = (y + row).as_usize();
+            self.pixels[row][col..col + row_len].copy_from_slice(&src.pixels[row][col..col + row_len]);
+        }
+    }
+}
+
 impl Index<usize> for VGABuffer {
     type Output = [Color; SCREEN_WIDTH];
     fn index(&self, idx: usize) -> &[Color; SCREEN_WIDTH] {
@@ -118,37 +255,191 @@ impl IndexMut<usize> for VGABuffer {
     }
 }
 
+/// The screen's memory, seen as `SCREEN_WIDTH` by `SCREEN_HEIGHT` pixels
+///
+/// The graphics mode is picked at boot, so the framebuffer can be bigger
+/// than the screen. Every pixel is then drawn as a square of framebuffer
+/// pixels, as big as fits, and the scaled up screen is put in the middle
+/// of the framebuffer, with black all around
+struct Screen {
+    /// The screen's top left pixel in the framebuffer
+    pixels: *mut Color,
+    /// The number of pixels from the start of a row to the start of the next
+    stride: usize,
+    /// The number of framebuffer pixels across and down each pixel is drawn as
+    scale: usize,
+    /// How bright the pixels copied to the screen are made, out of `FULL_BRIGHTNESS`
+    brightness: u8
+}
+
+impl Screen {
+    /// # Panics
+    ///
+    /// If the screen doesn't fit in `framebuffer`
+    fn new(framebuffer: &Framebuffer) -> Screen {
+        let scale = (framebuffer.width() / SCREEN_WIDTH).min(framebuffer.height() / SCREEN_HEIGHT).max(1);
+        let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+        let x = framebuffer.width().saturating_sub(width) / 2;
+        let y = framebuffer.height().saturating_sub(height) / 2;
+        if !framebuffer.fits(x + width, y + height, core::mem::size_of::<Color>()) {
+            panic!("The framebuffer is too small for the screen");
+        }
+        if framebuffer.width() != width || framebuffer.height() != height {
+            framebuffer.clear();
+        }
+        Screen {
+            pixels: framebuffer.pixel_addr(x, y).unwrap().as_mut_ptr() as *mut Color,
+            stride: framebuffer.stride(),
+            scale,
+            brightness: FULL_BRIGHTNESS
+        }
+    }
+
+    /// The framebuffer pixel at the top left of the pixel at (`x`, `y`)
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut Color {
+        assert!(x < SCREEN_WIDTH && y < SCREEN_HEIGHT, "The pixel is off the screen");
+        // The framebuffer was checked to hold every pixel when the screen was created
+        unsafe { self.pixels.add((y * self.stride + x) * self.scale) }
+    }
+
+    /// The color of the pixel at (`x`, `y`)
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        unsafe { *self.pixel_ptr(x, y) }
+    }
+
+    /// Sets the pixel at (`x`, `y`) to `color`, whatever the brightness is
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let pixel = self.pixel_ptr(x, y);
+        for row in 0..self.scale {
+            unsafe { core::slice::from_raw_parts_mut(pixel.add(row * self.stride), self.scale).fill(color) };
+        }
+    }
+
+    /// Copies `src` to row `y` of the screen, starting at column `x`
+    fn copy_row(&mut self, x: usize, y: usize, src: &[Color]) {
+        assert!(x + src.len() <= SCREEN_WIDTH, "The row is off the screen");
+        if src.is_empty() {
+            return;
+        }
+        let (scale, brightness) = (self.scale, self.brightness);
+        let first = self.pixel_ptr(x, y);
+        let len = src.len() * scale;
+        let dst = unsafe { core::slice::from_raw_parts_mut(first, len) };
+        if scale == 1 {
+            copy_pixels(dst, src, brightness);
+            return;
+        }
+        for (dst, src) in dst.chunks_exact_mut(scale).zip(src) {
+            dst.fill(if brightness == FULL_BRIGHTNESS { *src } else { src.dimmed(brightness) });
+        }
+        // The rest of the framebuffer rows the pixels are drawn in are the same
+        for row in 1..scale {
+            unsafe { core::ptr::copy_nonoverlapping(first, first.add(row * self.stride), len) };
+        }
+    }
+
+    /// Copies the pixels in `rect` in `buffer` to the same place on the screen
+    fn copy_rect_from(&mut self, buffer: &VGABuffer, rect: Rect) {
+        for y in rect.y..rect.y + rect.height {
+            self.copy_row(rect.x, y, &buffer.pixels[y][rect.x..rect.x + rect.width]);
+        }
+    }
+
+    /// Copies all of `buffer` to the screen
+    fn copy_from(&mut self, buffer: &VGABuffer) {
+        if self.stride == SCREEN_WIDTH && self.scale == 1 && self.brightness == FULL_BRIGHTNESS {
+            // Rust was too slow for this, like in `draw_background_in_double_buffer`
+            unsafe {
+                use core::arch::asm;
+                asm!("
+                    # Move 4 bytes at a time from esi to edi, ecx times
+                    rep movsd",
+                    in("esi") buffer.pixels.as_slice().as_ptr(),
+                    in("edi") self.pixels,
+                    in("ecx") DOUBLE_BUFFER_SIZE
+                );
+            }
+        } else {
+            for (y, row) in buffer.pixels.iter().enumerate() {
+                self.copy_row(0, y, row);
+            }
+        }
+    }
+}
+
 /// Draws to the VGA buffer
 pub struct Artist {
     x_pos: usize,
     y_pos: usize,
     color_code: ColorCode,
-    vga_buffer: &'static mut VGABuffer,
+    text_style: TextStyle,
+    vga_buffer: Screen,
     double_buffer: VGABuffer,
+    /// Heap buffers the size of the screen that can be drawn in
+    /// instead of the double buffer
+    offscreen_targets: [Option<&'static mut VGABuffer>; MAX_OFFSCREEN_TARGETS],
+    /// The buffer the drawing functions draw in
+    target: Target,
+    /// The mouse cursor, which is drawn on the screen over everything else
+    cursor: Cursor,
+    /// The scaled glyphs of the characters that have been written
+    glyph_cache: GlyphCache,
+    /// The parts of the double buffer that have changed since
+    /// it was last put on the screen
+    dirty_rects: DirtyRects,
+    /// The camera and the clip rectangle the drawing functions draw through
+    viewport: Viewport,
+    present_mode: PresentMode,
+    /// The frame waiting to be put on the screen when triple buffering
+    third_buffer: Option<&'static mut VGABuffer>,
+    /// The parts of the third buffer that have changed since it was last put on the screen
+    pending_rects: DirtyRects,
+    /// The colors shown at full brightness, which are put in the DAC in BIOS mode
+    palette: Palette,
+    /// How bright the screen is, out of `FULL_BRIGHTNESS`
+    brightness: u8,
+    /// Measures how often the double buffer is put on the screen
+    frame_timer: FrameTimer,
+    /// Whether `draw_perf_hud_in_double_buffer` draws the performance HUD
+    perf_hud_visible: bool,
     //move_bitmap_in_double_buffer_request_queue: Queue<'static, MoveBitmapInDoubleBufferRequest>
 }
 
 impl Artist {
 
     /// Writes a byte to the VGA buffer
+    ///
+    /// A frame still waiting in the third buffer is put on the screen before
+    /// writing to the VGA buffer, so it can't be presented over the text later
     pub fn write_byte(&mut self, c: u8, write_target: WriteTarget) {
+        if let WriteTarget::VGABuffer = write_target {
+            self.show_pending_frame();
+        }
         if c == b'\n' {
             self.newline();
         } else if is_printable_ascii(c) {
-            let buffer = match write_target {
-                WriteTarget::VGABuffer => &mut self.vga_buffer,
-                WriteTarget::DoubleBuffer => &mut self.double_buffer
-            };
-            for (y, byte) in font::FONT[c].iter().enumerate() {
-                let i = y + 1;
-                for yp in y * Y_SCALE..i*Y_SCALE {
-                    for x in 0..FONT_WIDTH {
-                        let j = x + 1;
-                        for xp in x * X_SCALE..j * X_SCALE {
-                            if byte & (1 << (FONT_WIDTH - x - 1)) == 0 {
-                                buffer[self.y_pos + yp][self.x_pos + xp] = self.color_code.background();
-                            } else {
-                                buffer[self.y_pos + yp][self.x_pos + xp] = self.color_code.foreground();
+            if let WriteTarget::DoubleBuffer = write_target {
+                self.mark_dirty(Point(self.x_pos.as_i16(), self.y_pos.as_i16()), SCALED_GLYPH_WIDTH, SCALED_GLYPH_HEIGHT);
+            }
+            let glyph = self.glyph_cache.get(c, self.color_code, self.text_style);
+            match write_target {
+                WriteTarget::VGABuffer => {
+                    for (y, glyph_row) in glyph.pixels.iter().enumerate() {
+                        for (x, color) in glyph_row.iter().enumerate() {
+                            if let Some(color) = color {
+                                self.vga_buffer.set_pixel(self.x_pos + x, self.y_pos + y, *color);
+                            }
+                        }
+                    }
+                }
+                WriteTarget::DoubleBuffer => {
+                    let buffer = target_buffer(self.target, &mut self.double_buffer, &mut self.offscreen_targets);
+                    for (y, glyph_row) in glyph.pixels.iter().enumerate() {
+                        let row = &mut buffer[self.y_pos + y][self.x_pos..self.x_pos + SCALED_GLYPH_WIDTH];
+                        for (pixel, color) in row.iter_mut().zip(glyph_row) {
+                            // Transparent pixels leave whatever is already there
+                            if let Some(color) = color {
+                                *pixel = *color;
                             }
                         }
                     }
@@ -177,6 +468,37 @@ impl Artist {
         self.write_string(s, WriteTarget::DoubleBuffer);
     }
 
+    /// Writes `s` in the double buffer with `format`, leaving the writing position
+    /// and the colors and style of other text as they are
+    ///
+    /// `pos` is the top of the text, and is where its lines start, are centered
+    /// or end depending on `format.align`. Every '\n' starts a new line below.
+    /// The parts of the text that are outside the clip rectangle are left out
+    pub fn write_str_at(&mut self, s: &str, pos: Point, format: TextFormat) {
+        let pos = self.viewport.screen_pos(pos);
+        let clip = self.viewport.clip;
+        let font = format.font;
+        let scale = format.scale.max(1);
+        let line_height = font.height() * Y_SCALE * scale;
+        for (i, line) in s.split('\n').enumerate() {
+            let width = text_width(line, font, scale);
+            let line_pos = Point(
+                aligned_x(pos.x(), width, format.align),
+                pos.y().saturating_add((i * line_height).as_i16())
+            );
+            self.mark_clipped_dirty(line_pos, width, line_height);
+            let buffer = self.target_buffer();
+            let mut x = line_pos.x().as_isize();
+            for c in line.bytes() {
+                let c = if is_printable_ascii(c) { c } else { b'?' };
+                if let Some(glyph) = font.glyph(c).or_else(|| font.glyph(b'?')) {
+                    draw_glyph(buffer, clip, glyph, font.columns(c), Point(x.as_i16(), line_pos.y()), &format);
+                }
+                x += (font.advance(c) * X_SCALE * scale).as_isize();
+            }
+        }
+    }
+
     fn printint<T: Integer>(&mut self, n: T) {
         fn inner_printint<T: Integer>(w: &mut Artist, n: T) {
             if n.as_u8() < 10 {
@@ -193,18 +515,55 @@ impl Artist {
         self.newline();
     }
 
+    /// Sets the style that text is drawn in from now on
+    pub fn set_text_style(&mut self, text_style: TextStyle) {
+        self.text_style = text_style;
+    }
+
+    pub fn text_style(&self) -> TextStyle {
+        self.text_style
+    }
+
+    /// Sets the colors of the characters of text and of the box
+    /// plain text is drawn on, from now on
+    pub fn set_text_colors(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode(foreground, background);
+    }
+
+    /// The foreground and background colors text is drawn in
+    pub fn text_colors(&self) -> (Color, Color) {
+        (self.color_code.foreground(), self.color_code.background())
+    }
+
     pub fn reset_writing_pos(&mut self) {
         self.x_pos = 0;
         self.y_pos = 0;
     }
 
+    /// Sets the position the next character will be written at
+    pub fn set_writing_pos(&mut self, pos: Point) {
+        self.x_pos = pos.x().as_usize();
+        self.y_pos = pos.y().as_usize();
+    }
+
     /// Prints a newline in the VGA buffer
     pub fn newline(&mut self) {
         self.y_pos += FONT_HEIGHT * Y_SCALE;
         self.x_pos = 0;
     }
     
+    /// Fills the clip rectangle with `color`, which is the whole screen unless
+    /// it has been set with `set_clip_rect`
     pub fn draw_background_in_double_buffer(&mut self, color: &Color) {
+        let clip = self.viewport.clip;
+        if clip != ClipRect::SCREEN {
+            self.mark_clipped_dirty(clip.top_left(), clip.width(), clip.height());
+            let buffer = self.target_buffer();
+            for y in clip.top..clip.bottom {
+                fill_span(buffer, clip, y, clip.left, clip.right, *color);
+            }
+            return;
+        }
         // Rust was too slow for this.
         // Had to use assembly
         use core::arch::asm;
@@ -216,12 +575,13 @@ impl Artist {
         // A color is 4 bytes because of the UEFI setup and 4 bytes
         // are moved at a time, so this has to be the full buffer
         let no_of_movements = DOUBLE_BUFFER_SIZE;
+        self.mark_dirty(Point(0, 0), SCREEN_WIDTH, SCREEN_HEIGHT);
         unsafe {
             asm!("
                 # Move the value in eax into edi, ecx times
                 rep stosd",
                 in("eax") color.to_num(),
-                in("edi") self.double_buffer.pixels.as_slice().as_ptr(),
+                in("edi") self.target_buffer().pixels.as_slice().as_ptr(),
                 in("ecx") no_of_movements 
             );
         }
@@ -233,50 +593,667 @@ impl Artist {
     }
 
     pub fn draw_scaled_bitmap_in_double_buffer(&mut self, pos: Point, bitmap: &ScaledBitmap) {
-        for y in 0..bitmap.height() {
-            for x in 0..bitmap.width() {
-                if pos_is_within_screen_bounds(pos, x, y) {
-                    let pixel_array_y = bitmap.height() - y - 1;
-                    let color = bitmap.image_data[pixel_array_y*bitmap.width()+x];
-                    if bitmap.transparency == Transparency::Black && color == Color::BLACK {
-                        continue;
-                    }
-                    self.double_buffer[pos.y().as_usize() + y][pos.x().as_usize() + x] = color;
+        let pos = self.viewport.screen_pos(pos);
+        let clip = self.viewport.clip;
+        self.mark_clipped_dirty(pos, bitmap.width(), bitmap.height());
+        for span in bitmap.opaque_spans() {
+            if let Some((row, col, skipped, len)) = clip_opaque_span(clip, pos, span) {
+                unsafe {
+                    let src = bitmap.image_data.as_ptr().add(span.start + skipped);
+                    let dst = self.target_buffer()[row].as_mut_ptr().add(col);
+                    copy_colors(src, dst, len);
                 }
             }
         }
     }
 
     pub fn erase_scaled_bitmap_from_double_buffer(&mut self, bitmap: &ScaledBitmap, pos: Point, background: &Color) {
-        for y in 0..bitmap.height() {
-            for x in 0..bitmap.width() {
-                if pos_is_within_screen_bounds(pos, x, y) {
-                    let pixel_array_y = bitmap.height() - y - 1;
-                    let color = bitmap.image_data[pixel_array_y*bitmap.width()+x];
-                    if bitmap.transparency == Transparency::Black && color == Color::BLACK {
-                        continue;
-                    }
-                    self.double_buffer[pos.y().as_usize() + y][pos.x().as_usize() + x] = *background;
+        let pos = self.viewport.screen_pos(pos);
+        let clip = self.viewport.clip;
+        self.mark_clipped_dirty(pos, bitmap.width(), bitmap.height());
+        for span in bitmap.opaque_spans() {
+            if let Some((row, col, _, len)) = clip_opaque_span(clip, pos, span) {
+                unsafe {
+                    let dst = self.target_buffer()[row].as_mut_ptr().add(col);
+                    fill_colors(dst, *background, len);
                 }
             }
         }
     }
 
+    /// Draws `patch` stretched or tiled to `width` by `height` pixels, with its top left at `pos`
+    pub fn draw_nine_patch_in_double_buffer(&mut self, patch: &NinePatch, pos: Point, width: usize, height: usize) {
+        let pos = self.viewport.screen_pos(pos);
+        let area = ClipRect::new(pos, width, height).intersection(&self.viewport.clip);
+        self.mark_clipped_dirty(pos, width, height);
+        let (x, y) = (pos.x().as_isize(), pos.y().as_isize());
+        let buffer = self.target_buffer();
+        for row in area.top..area.bottom {
+            for col in area.left..area.right {
+                if let Some(color) = patch.pixel((col - x).as_usize(), (row - y).as_usize(), width, height) {
+                    buffer[row.as_usize()][col.as_usize()] = color;
+                }
+            }
+        }
+    }
+
+    /// Copies the `width` by `height` rectangle of pixels at `src` in the double buffer to `dst`
+    ///
+    /// The rectangles may overlap, so a region can be scrolled in place.
+    /// The parts of the rectangles that are off the screen are left out,
+    /// as is the part of `dst` that is outside the clip rectangle
+    pub fn copy_rect_in_double_buffer(&mut self, src: Point, dst: Point, width: usize, height: usize) {
+        let (src, dst) = (self.viewport.screen_pos(src), self.viewport.screen_pos(dst));
+        let area = ClipRect::new(dst, width, height).intersection(&self.viewport.clip);
+        if area.is_empty() {
+            return;
+        }
+        // The source moves along with the part of the destination that's left
+        let src = Point(
+            src.x().saturating_add((area.left - dst.x().as_isize()).as_i16()),
+            src.y().saturating_add((area.top - dst.y().as_isize()).as_i16())
+        );
+        self.mark_dirty(area.top_left(), area.width(), area.height());
+        self.target_buffer().copy_rect(src, area.top_left(), area.width(), area.height());
+    }
+
+    /// Fills the `width` by `height` rectangle at `pos` in the double buffer with `color`
+    ///
+    /// The part of the rectangle that is outside the clip rectangle is left out
+    pub fn fill_rect_in_double_buffer(&mut self, pos: Point, width: usize, height: usize, color: &Color) {
+        let pos = self.viewport.screen_pos(pos);
+        let area = ClipRect::new(pos, width, height).intersection(&self.viewport.clip);
+        self.mark_clipped_dirty(pos, width, height);
+        let buffer = self.target_buffer();
+        for y in area.top..area.bottom {
+            fill_span(buffer, area, y, area.left, area.right, *color);
+        }
+    }
+
+    /// Draws the outline of the `width` by `height` rectangle at `pos`, 1 pixel thick
+    pub fn draw_rect_in_double_buffer(&mut self, pos: Point, width: usize, height: usize, color: &Color) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let right = Point(pos.x() + width.as_i16() - 1, pos.y());
+        let bottom = Point(pos.x(), pos.y() + height.as_i16() - 1);
+        self.fill_rect_in_double_buffer(pos, width, 1, color);
+        self.fill_rect_in_double_buffer(bottom, width, 1, color);
+        self.fill_rect_in_double_buffer(pos, 1, height, color);
+        self.fill_rect_in_double_buffer(right, 1, height, color);
+    }
+
+    /// Draws a line 1 pixel thick from `from` to `to`, both ends included
+    ///
+    /// The part of the line that is outside the clip rectangle is left out
+    pub fn draw_line_in_double_buffer(&mut self, from: Point, to: Point, color: &Color) {
+        let top_left = Point(from.x().min(to.x()), from.y().min(to.y()));
+        let width = (from.x() - to.x()).unsigned_abs().as_usize() + 1;
+        let height = (from.y() - to.y()).unsigned_abs().as_usize() + 1;
+        self.mark_clipped_dirty(self.viewport.screen_pos(top_left), width, height);
+        let ((dx, dy), clip) = (self.viewport.offset(), self.viewport.clip);
+        let buffer = self.target_buffer();
+        shapes::line(from, to, |x, y| set_pixel(buffer, clip, x - dx, y - dy, *color));
+    }
+
+    /// Draws the outline of the circle of `radius` around `center`, 1 pixel thick
+    ///
+    /// The part of the circle that is outside the clip rectangle is left out
+    pub fn draw_circle_in_double_buffer(&mut self, center: Point, radius: usize, color: &Color) {
+        self.mark_circle_dirty(center, radius);
+        let ((dx, dy), clip) = (self.viewport.offset(), self.viewport.clip);
+        let buffer = self.target_buffer();
+        shapes::circle(center, radius, |x, y| set_pixel(buffer, clip, x - dx, y - dy, *color));
+    }
+
+    /// Fills the circle of `radius` around `center`, outline included
+    ///
+    /// The part of the circle that is outside the clip rectangle is left out
+    pub fn fill_circle_in_double_buffer(&mut self, center: Point, radius: usize, color: &Color) {
+        self.mark_circle_dirty(center, radius);
+        let ((dx, dy), clip) = (self.viewport.offset(), self.viewport.clip);
+        let buffer = self.target_buffer();
+        shapes::disc(center, radius, |y, start, end| fill_span(buffer, clip, y - dy, start - dx, end - dx, *color));
+    }
+
+    /// Fills the polygon with `vertices`, which can have up to `MAX_POLYGON_VERTICES`
+    ///
+    /// Like with rectangles, the pixels on the polygon's right and bottom edges
+    /// aren't filled. The part of the polygon that is outside the clip rectangle is left out
+    pub fn fill_polygon_in_double_buffer(&mut self, vertices: &[Point], color: &Color) -> Result<(), &'static str> {
+        if let (Some(left), Some(right), Some(top), Some(bottom)) = (
+            vertices.iter().map(|vertex| vertex.x()).min(),
+            vertices.iter().map(|vertex| vertex.x()).max(),
+            vertices.iter().map(|vertex| vertex.y()).min(),
+            vertices.iter().map(|vertex| vertex.y()).max()
+        ) {
+            let top_left = self.viewport.screen_pos(Point(left, top));
+            self.mark_clipped_dirty(top_left, (right - left).as_usize(), (bottom - top).as_usize());
+        }
+        let ((dx, dy), clip) = (self.viewport.offset(), self.viewport.clip);
+        let buffer = self.target_buffer();
+        shapes::polygon(vertices, |y, start, end| fill_span(buffer, clip, y - dy, start - dx, end - dx, *color))
+    }
+
+    /// Draws the frame of `sheet` that `animation` is at, with its top left at `pos`
+    pub fn draw_animation_frame(&mut self, sheet: &SpriteSheet, animation: &Animation, pos: Point) {
+        self.draw_scaled_bitmap_in_double_buffer(pos, sheet.frame(animation.frame()));
+    }
+
+    /// Creates an off-screen target, a buffer the size of the screen on the heap,
+    /// which starts out black
+    ///
+    /// Drawing in it is as fast as drawing in the double buffer, so art that rarely
+    /// changes can be drawn in it once and composed onto the double buffer
+    /// with `compose_target` every frame
+    pub fn create_target(&mut self) -> Result<Target, &'static str> {
+        let slot = self.offscreen_targets.iter().position(|target| target.is_none())
+            .ok_or("Too many off-screen targets")?;
+        let buffer = alloc_buffer().ok_or("No enough space on the heap for the off-screen target")?;
+        for row in buffer.pixels.iter_mut() {
+            row.fill(Color::new(Color::BLACK));
+        }
+        self.offscreen_targets[slot] = Some(buffer);
+        Ok(Target::Offscreen(OffscreenTarget(slot)))
+    }
+
+    /// Frees the buffer of an off-screen target created with `create_target`
+    ///
+    /// Drawing goes back to the double buffer if the target was being drawn in
+    pub fn destroy_target(&mut self, target: Target) {
+        if let Target::Offscreen(OffscreenTarget(slot)) = target {
+            if let Some(buffer) = self.offscreen_targets[slot].take() {
+                free_buffer(buffer);
+            }
+            if self.target == target {
+                self.target = Target::DoubleBuffer;
+            }
+        }
+    }
+
+    /// Sets the buffer that the drawing functions draw in from now on
+    ///
+    /// The functions named after the double buffer draw in `target` instead,
+    /// and text written with `write_string_in_double_buffer` goes there too
+    pub fn set_target(&mut self, target: Target) {
+        if let Target::Offscreen(OffscreenTarget(slot)) = target {
+            assert!(self.offscreen_targets[slot].is_some(), "The off-screen target has been destroyed");
+        }
+        self.target = target;
+    }
+
+    /// The buffer the drawing functions draw in
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Copies the `width` by `height` rectangle at `pos` in `target` to the
+    /// same place in the double buffer
+    ///
+    /// `pos` is on the screen, since the target was drawn through the camera already.
+    /// The part of the rectangle that is outside the clip rectangle is left out
+    pub fn compose_target(&mut self, target: Target, pos: Point, width: usize, height: usize) {
+        let area = ClipRect::new(pos, width, height).intersection(&self.viewport.clip);
+        let src = match target {
+            Target::DoubleBuffer => return,
+            Target::Offscreen(OffscreenTarget(slot)) => self.offscreen_targets[slot].as_deref()
+                .expect("The off-screen target has been destroyed")
+        };
+        if !area.is_empty() {
+            self.double_buffer.copy_rect_from(src, area.top_left(), area.width(), area.height());
+            self.dirty_rects.add(area.top_left(), area.width(), area.height());
+        }
+    }
+
+    /// Moves the camera so `pos` in the world is at the top left of the screen
+    ///
+    /// Everything drawn from then on is moved by it, so a level bigger than
+    /// the screen is scrolled by moving the camera. Text written with
+    /// `write_string_in_double_buffer` and `print!` isn't moved, so it can be used for a HUD
+    pub fn set_camera(&mut self, pos: Point) {
+        self.viewport.camera = pos;
+    }
+
+    /// The point of the world at the top left of the screen
+    pub fn camera(&self) -> Point {
+        self.viewport.camera
+    }
+
+    /// Keeps the drawing functions from drawing outside the `width` by `height`
+    /// rectangle at `pos` on the screen
+    ///
+    /// The rectangle isn't moved by the camera
+    pub fn set_clip_rect(&mut self, pos: Point, width: usize, height: usize) {
+        self.viewport.clip = ClipRect::new(pos, width, height);
+    }
+
+    /// Lets the drawing functions draw on the whole screen again
+    pub fn reset_clip_rect(&mut self) {
+        self.viewport.clip = ClipRect::SCREEN;
+    }
+
+    fn target_buffer(&mut self) -> &mut VGABuffer {
+        target_buffer(self.target, &mut self.double_buffer, &mut self.offscreen_targets)
+    }
+
+    /// Marks the `width` by `height` rectangle at `pos` to be copied to the screen,
+    /// if it's the double buffer that's being drawn in
+    fn mark_dirty(&mut self, pos: Point, width: usize, height: usize) {
+        if self.target == Target::DoubleBuffer {
+            self.dirty_rects.add(pos, width, height);
+        }
+    }
+
+    /// Marks the part of the `width` by `height` rectangle at `pos`
+    /// that is inside the clip rectangle like `mark_dirty`
+    fn mark_clipped_dirty(&mut self, pos: Point, width: usize, height: usize) {
+        let area = ClipRect::new(pos, width, height).intersection(&self.viewport.clip);
+        if !area.is_empty() {
+            self.mark_dirty(area.top_left(), area.width(), area.height());
+        }
+    }
+
+    fn mark_circle_dirty(&mut self, center: Point, radius: usize) {
+        let center = self.viewport.screen_pos(center);
+        let top_left = Point(center.x().saturating_sub(radius.as_i16()), center.y().saturating_sub(radius.as_i16()));
+        self.mark_clipped_dirty(top_left, radius * 2 + 1, radius * 2 + 1);
+    }
+
+    /// Copies the parts of the double buffer that have been drawn in
+    /// since the last time to the screen, in the way `set_present_mode` set
     pub fn draw_on_screen_from_double_buffer(&mut self) {
-        
-        unsafe {
-            use core::arch::asm;
-            asm!("
-                # Move 4 bytes at a time from esi to edi, ecx times
-                rep movsd",
-                in("esi") self.double_buffer.pixels.as_slice().as_ptr(),
-                in("edi") self.vga_buffer.pixels.as_slice().as_ptr(),
-                in("ecx") DOUBLE_BUFFER_SIZE
-            );
+        self.frame_timer.frame_presented(time::uptime_us());
+        match self.present_mode {
+            PresentMode::Immediate => (),
+            PresentMode::Vsync => {
+                if !self.dirty_rects.rects().is_empty() {
+                    vsync::wait_for_vertical_retrace();
+                }
+            }
+            PresentMode::TripleBuffered => {
+                if let Some(third_buffer) = self.third_buffer.as_deref_mut() {
+                    for rect in self.dirty_rects.rects() {
+                        let pos = Point(rect.x.as_i16(), rect.y.as_i16());
+                        third_buffer.copy_rect_from(&self.double_buffer, pos, rect.width, rect.height);
+                        self.pending_rects.add(pos, rect.width, rect.height);
+                    }
+                    self.dirty_rects.clear();
+                    self.flip_pending_frame();
+                    return;
+                }
+            }
+        }
+        copy_to_screen(&mut self.vga_buffer, &mut self.cursor, &self.double_buffer, &self.dirty_rects);
+        self.dirty_rects.clear();
+    }
+
+    /// Puts the frame waiting in the third buffer on the screen,
+    /// if the screen is in the vertical retrace
+    ///
+    /// Returns true if it did. Has to be called over and over while
+    /// triple buffering, since nothing tells the artist when the retrace comes
+    pub fn flip_pending_frame(&mut self) -> bool {
+        let third_buffer = match self.third_buffer.as_deref() {
+            Some(third_buffer) => third_buffer,
+            None => return false
+        };
+        if self.pending_rects.rects().is_empty() || !vsync::in_vertical_retrace() {
+            return false;
         }
+        copy_to_screen(&mut self.vga_buffer, &mut self.cursor, third_buffer, &self.pending_rects);
+        self.pending_rects.clear();
+        true
+    }
+
+    /// Tells whether or not a frame is waiting in the third buffer for the retrace
+    pub fn has_pending_frame(&self) -> bool {
+        !self.pending_rects.rects().is_empty()
+    }
+
+    /// Sets how `draw_on_screen_from_double_buffer` puts frames on the screen
+    ///
+    /// Whatever was drawn before is put on the screen first. Triple buffering
+    /// takes a buffer the size of the screen on the heap, and returns an error
+    /// if there isn't enough space for it
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), &'static str> {
+        if mode == self.present_mode {
+            return Ok(());
+        }
+        self.draw_on_screen_from_double_buffer();
+        if let Some(third_buffer) = self.third_buffer.take() {
+            copy_to_screen(&mut self.vga_buffer, &mut self.cursor, third_buffer, &self.pending_rects);
+            self.pending_rects.clear();
+            free_buffer(third_buffer);
+        }
+        self.present_mode = PresentMode::Immediate;
+        if mode == PresentMode::TripleBuffered {
+            let third_buffer = alloc_buffer().ok_or("No enough space on the heap for the third buffer")?;
+            // Only the parts that change are copied into it, so it has to
+            // start out as what's on the screen
+            third_buffer.pixels.copy_from_slice(&self.double_buffer.pixels);
+            self.third_buffer = Some(third_buffer);
+        }
+        self.present_mode = mode;
+        Ok(())
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    /// Sets the colors the pixels' indices are shown as
+    ///
+    /// UEFI modes have no palette, so it returns an error in them
+    pub fn set_palette(&mut self, palette: Palette) -> Result<(), &'static str> {
+        if cfg!(not(feature = "bios")) {
+            return Err("There's no palette to change in UEFI mode");
+        }
+        self.palette = palette;
+        self.set_brightness(self.brightness);
+        Ok(())
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Shows the screen at `brightness`, out of `FULL_BRIGHTNESS`
+    ///
+    /// Everything drawn afterwards is shown at `brightness` too, so a scene can
+    /// be drawn while the screen is black and then faded in. In BIOS mode,
+    /// the DAC's palette is faded. In UEFI mode, the whole screen is copied again
+    /// with its colors ramped down, though the mouse cursor isn't
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        #[cfg(feature = "bios")]
+        color::load_palette(&self.palette.faded(brightness));
+        #[cfg(not(feature = "bios"))]
+        {
+            self.show_pending_frame();
+            self.vga_buffer.brightness = brightness;
+            copy_to_screen(&mut self.vga_buffer, &mut self.cursor, &self.double_buffer, &DirtyRects::new());
+        }
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Fades the screen out to black over `frames` refreshes of the screen,
+    /// after putting everything drawn so far on it
+    pub fn fade_to_black(&mut self, frames: usize) {
+        self.fade_to(0, frames);
+    }
+
+    /// Fades the screen in from black over `frames` refreshes of the screen,
+    /// after putting everything drawn so far on it
+    pub fn fade_from_black(&mut self, frames: usize) {
+        self.fade_to(FULL_BRIGHTNESS, frames);
+    }
+
+    /// Changes the brightness to `brightness` a step every refresh of the screen,
+    /// over `frames` refreshes
+    ///
+    /// Doesn't return until the fade is done
+    fn fade_to(&mut self, brightness: u8, frames: usize) {
+        self.draw_on_screen_from_double_buffer();
+        self.show_pending_frame();
+        if frames == 0 {
+            self.set_brightness(brightness);
+            return;
+        }
+        let start = self.brightness;
+        for frame in 1..=frames {
+            if vsync::source() == vsync::RetraceSource::None {
+                time::sleep_us(vsync::refresh_period_us());
+            } else {
+                vsync::wait_for_vertical_retrace();
+            }
+            self.set_brightness(color::fade_step(start, brightness, frame, frames));
+        }
+    }
+
+    /// Puts the frame waiting in the third buffer on the screen without
+    /// waiting for the retrace, so the screen shows all of the double buffer
+    fn show_pending_frame(&mut self) {
+        if let Some(third_buffer) = self.third_buffer.as_deref() {
+            copy_to_screen(&mut self.vga_buffer, &mut self.cursor, third_buffer, &self.pending_rects);
+            self.pending_rects.clear();
+        }
+    }
+
+    /// Sets whether or not `draw_perf_hud_in_double_buffer` draws the performance HUD
+    pub fn set_perf_hud_visible(&mut self, visible: bool) {
+        self.perf_hud_visible = visible;
+    }
+
+    pub fn perf_hud_is_visible(&self) -> bool {
+        self.perf_hud_visible
+    }
+
+    /// The frame rate, frame time and heap usage, with the `queued_events`
+    /// the caller counted
+    pub fn perf_stats(&self, queued_events: usize) -> PerfStats {
+        PerfStats {
+            fps: self.frame_timer.fps(),
+            frame_time_us: self.frame_timer.frame_time_us(),
+            heap: allocator::heap_stats(),
+            queued_events
+        }
+    }
+
+    /// Draws the performance HUD on `background` in the double buffer, with
+    /// its top left corner at `pos`, if it has been made visible
+    ///
+    /// `queued_events` is the number of events waiting to be handled.
+    /// The HUD is `PERF_HUD_WIDTH` by `PERF_HUD_HEIGHT` pixels
+    pub fn draw_perf_hud_in_double_buffer(&mut self, pos: Point, queued_events: usize, background: &Color) {
+        if !self.perf_hud_visible {
+            return;
+        }
+        self.fill_rect_in_double_buffer(pos, PERF_HUD_WIDTH, PERF_HUD_HEIGHT, background);
+        let (x_pos, y_pos) = (self.x_pos, self.y_pos);
+        for (i, line) in self.perf_stats(queued_events).lines().iter().enumerate() {
+            self.set_writing_pos(pos + Point(0, (i * FONT_HEIGHT * Y_SCALE).as_i16()));
+            // The lines are only made of ASCII
+            self.write_string_in_double_buffer(core::str::from_utf8(line).unwrap());
+        }
+        (self.x_pos, self.y_pos) = (x_pos, y_pos);
+    }
+
+    /// Makes the mouse cursor visible on the screen
+    pub fn show_cursor(&mut self) {
+        self.cursor.set_visible(true);
+        self.cursor.erase(&mut self.vga_buffer);
+        self.cursor.draw(&mut self.vga_buffer);
+    }
+
+    /// Removes the mouse cursor from the screen
+    pub fn hide_cursor(&mut self) {
+        self.cursor.erase(&mut self.vga_buffer);
+        self.cursor.set_visible(false);
+    }
+
+    /// Tells whether or not the mouse cursor is visible
+    pub fn cursor_is_visible(&self) -> bool {
+        self.cursor.is_visible()
+    }
+
+    /// The position of the mouse cursor's hotspot on the screen
+    pub fn cursor_pos(&self) -> Point {
+        self.cursor.pos()
+    }
+
+    /// Moves the mouse cursor to `pos`, restoring the pixels it was covering.
+    /// The position is clamped to the screen
+    pub fn move_cursor_to(&mut self, pos: Point) {
+        self.cursor.erase(&mut self.vga_buffer);
+        self.cursor.set_pos(pos);
+        self.cursor.draw(&mut self.vga_buffer);
+    }
+
+    /// Moves the mouse cursor by `dx` and `dy` pixels, as reported by relative
+    /// mouse movement events
+    pub fn move_cursor_by(&mut self, dx: i16, dy: i16) {
+        let pos = self.cursor.pos();
+        self.move_cursor_to(Point(pos.x().saturating_add(dx), pos.y().saturating_add(dy)));
+    }
+}
+
+/// The buffer `target` refers to
+///
+/// Takes the buffers instead of the artist, so the artist's other
+/// fields can still be used while the buffer is borrowed
+fn target_buffer<'a>(
+    target: Target,
+    double_buffer: &'a mut VGABuffer,
+    offscreen_targets: &'a mut [Option<&'static mut VGABuffer>; MAX_OFFSCREEN_TARGETS]
+) -> &'a mut VGABuffer {
+    match target {
+        Target::DoubleBuffer => double_buffer,
+        Target::Offscreen(OffscreenTarget(slot)) => offscreen_targets[slot].as_deref_mut()
+            .expect("The off-screen target has been destroyed")
     }
 }
 
+/// Copies the parts of `src` in `rects` to the same places on `screen`,
+/// keeping the mouse cursor over them
+fn copy_to_screen(screen: &mut Screen, cursor: &mut Cursor, src: &VGABuffer, rects: &DirtyRects) {
+    if rects.is_full_screen() {
+        screen.copy_from(src);
+        // The copy overwrote the cursor, so it has to be drawn again
+        cursor.invalidate();
+        cursor.draw(screen);
+        return;
+    }
+    let cursor_covered = cursor.drawn_rect()
+        .map_or(false, |cursor| rects.rects().iter().any(|rect| rect.overlaps(&cursor)));
+    // Erased first, so the pixels saved beneath it are put back before
+    // they're copied over, instead of being left stale
+    if cursor_covered {
+        cursor.erase(screen);
+    }
+    for rect in rects.rects() {
+        screen.copy_rect_from(src, *rect);
+    }
+    if cursor_covered {
+        cursor.draw(screen);
+    }
+}
+
+/// Copies `src` to `dst`, with the colors at `brightness`
+fn copy_pixels(dst: &mut [Color], src: &[Color], brightness: u8) {
+    if brightness == FULL_BRIGHTNESS {
+        dst.copy_from_slice(src);
+    } else {
+        for (dst, src) in dst.iter_mut().zip(src) {
+            *dst = src.dimmed(brightness);
+        }
+    }
+}
+
+/// Allocates a buffer the size of the screen on the heap, or returns None if there's no space
+fn alloc_buffer() -> Option<&'static mut VGABuffer> {
+    let buffer_ptr = unsafe { allocator::get_allocator().alloc(core::mem::size_of::<VGABuffer>(), 1) }.ok()?;
+    Some(unsafe { &mut *buffer_ptr.cast::<VGABuffer>() })
+}
+
+/// Frees a buffer allocated with `alloc_buffer`
+fn free_buffer(buffer: &'static mut VGABuffer) {
+    let size = core::mem::size_of::<VGABuffer>();
+    unsafe { allocator::get_allocator().dealloc((buffer as *mut VGABuffer).cast::<u8>(), size).unwrap() };
+}
+
+/// Sets the pixel at (`x`, `y`) in `buffer` to `color`, if it's in `clip`
+fn set_pixel(buffer: &mut VGABuffer, clip: ClipRect, x: isize, y: isize, color: Color) {
+    if clip.contains(x, y) {
+        buffer[y.as_usize()][x.as_usize()] = color;
+    }
+}
+
+/// Sets the pixels in row `y` of `buffer` from column `start` up to but not
+/// including column `end` to `color`, leaving out the ones outside `clip`
+fn fill_span(buffer: &mut VGABuffer, clip: ClipRect, y: isize, start: isize, end: isize, color: Color) {
+    if let Some((start, end)) = clip.clip_span(y, start, end) {
+        buffer[y.as_usize()][start.as_usize()..end.as_usize()].fill(color);
+    }
+}
+
+/// The color of the pixel at (`x`, `y`) in a character's glyph,
+/// or None if the pixel is transparent
+fn glyph_pixel_color(glyph: &[u8], x: usize, y: usize, color_code: ColorCode, text_style: TextStyle) -> Option<Color> {
+    if glyph_bit_is_set(glyph, x as isize, y as isize) {
+        return Some(color_code.foreground());
+    }
+    let (x, y) = (x as isize, y as isize);
+    match text_style {
+        TextStyle::Plain => Some(color_code.background()),
+        TextStyle::Shadow(shadow_color) => {
+            if glyph_bit_is_set(glyph, x - 1, y - 1) {
+                Some(shadow_color)
+            } else {
+                None
+            }
+        }
+        TextStyle::Outline(outline_color) => {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    if glyph_bit_is_set(glyph, x + dx, y + dy) {
+                        return Some(outline_color);
+                    }
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Draws the `columns` of `glyph` given by `BitmapFont::columns` in the part
+/// of `buffer` in `clip`, with the first of them at `pos`, in `format`
+///
+/// Not cached, so text written in its own colors doesn't empty the cache
+fn draw_glyph(buffer: &mut VGABuffer, clip: ClipRect, glyph: &[u8], columns: (usize, usize), pos: Point, format: &TextFormat) {
+    let color_code = ColorCode(format.foreground, format.background);
+    let scale = format.scale.max(1);
+    let (pixel_width, pixel_height) = ((X_SCALE * scale).as_isize(), (Y_SCALE * scale).as_isize());
+    let (first_column, no_of_columns) = columns;
+    for glyph_y in 0..glyph.len() {
+        for (i, glyph_x) in (first_column..first_column + no_of_columns).enumerate() {
+            if let Some(color) = glyph_pixel_color(glyph, glyph_x, glyph_y, color_code, format.style) {
+                let start = pos.x().as_isize() + i.as_isize() * pixel_width;
+                let top = pos.y().as_isize() + glyph_y.as_isize() * pixel_height;
+                for row in top..top + pixel_height {
+                    fill_span(buffer, clip, row, start, start + pixel_width, color);
+                }
+            }
+        }
+    }
+}
+
+/// The x coordinate a line `width` pixels wide starts at when it's
+/// aligned with `align` to `x`
+fn aligned_x(x: i16, width: usize, align: Align) -> i16 {
+    match align {
+        Align::Left => x,
+        Align::Center => x.saturating_sub((width / 2).as_i16()),
+        Align::Right => x.saturating_sub(width.as_i16())
+    }
+}
+
+/// The width of the longest line of `s` written in `font`, `scale` times the usual size
+pub fn text_width(s: &str, font: &BitmapFont, scale: usize) -> usize {
+    font.text_width(s) * X_SCALE * scale.max(1)
+}
+
+/// Checks if the pixel at (`x`, `y`) in a glyph is part of the character.
+/// Pixels outside the glyph are never set
+fn glyph_bit_is_set(glyph: &[u8], x: isize, y: isize) -> bool {
+    if x < 0 || y < 0 || x >= FONT_WIDTH as isize || y >= glyph.len() as isize {
+        return false;
+    }
+    glyph[y as usize] & (1 << (FONT_WIDTH - x as usize - 1)) != 0
+}
+
 pub fn is_printable_ascii(c: u8) -> bool {
     match c {
         b' '..=b'~' => true,
@@ -284,6 +1261,70 @@ pub fn is_printable_ascii(c: u8) -> bool {
     }
 }
 
+/// Returns the row, column, number of pixels left out at the start and number of pixels
+/// of the part of `span` that is in `clip` when its bitmap is drawn at `pos`,
+/// or None if none of it is
+#[inline]
+fn clip_opaque_span(clip: ClipRect, pos: Point, span: &OpaqueSpan) -> Option<(usize, usize, usize, usize)> {
+    let start = pos.x().as_isize() + span.x.as_isize();
+    let row = pos.y().as_isize() + span.y.as_isize();
+    let (first, end) = clip.clip_span(row, start, start + span.len.as_isize())?;
+    Some((row.as_usize(), first.as_usize(), (first - start).as_usize(), (end - first).as_usize()))
+}
+
+/// Copies `len` colors from `src` to `dst`
+///
+/// Rust was too slow for drawing the bitmaps every frame, so
+/// the pixels are moved with string instructions
+#[inline]
+unsafe fn copy_colors(src: *const Color, dst: *mut Color, len: usize) {
+    use core::arch::asm;
+    // A color is 4 bytes in the UEFI setup, so movsd moves a color at a time
+    #[cfg(not(feature = "bios"))]
+    asm!("
+        # Move 4 bytes at a time from rsi to rdi, rcx times
+        rep movsd",
+        inout("rsi") src => _,
+        inout("rdi") dst => _,
+        inout("rcx") len => _,
+        options(nostack, preserves_flags)
+    );
+    // A color is 1 byte in BIOS's VGA 320x200 mode
+    #[cfg(feature = "bios")]
+    asm!("
+        # Move 1 byte at a time from rsi to rdi, rcx times
+        rep movsb",
+        inout("rsi") src => _,
+        inout("rdi") dst => _,
+        inout("rcx") len => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Sets `len` colors starting from `dst` to `color`
+#[inline]
+unsafe fn fill_colors(dst: *mut Color, color: Color, len: usize) {
+    use core::arch::asm;
+    #[cfg(not(feature = "bios"))]
+    asm!("
+        # Move the value in eax into rdi, rcx times
+        rep stosd",
+        in("eax") color.to_num(),
+        inout("rdi") dst => _,
+        inout("rcx") len => _,
+        options(nostack, preserves_flags)
+    );
+    #[cfg(feature = "bios")]
+    asm!("
+        # Move the value in al into rdi, rcx times
+        rep stosb",
+        in("eax") color.to_num(),
+        inout("rdi") dst => _,
+        inout("rcx") len => _,
+        options(nostack, preserves_flags)
+    );
+}
+
 #[inline]
 pub fn pos_is_within_screen_bounds(pos: Point, dx: usize, dy: usize) -> bool {
     pos.y() >= 0 && pos.x() >= 0 
@@ -293,11 +1334,92 @@ pub fn pos_is_within_screen_bounds(pos: Point, dx: usize, dy: usize) -> bool {
 
 impl fmt::Write for Artist {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Keeping the text from being written into the cursor's saved pixels
+        self.cursor.erase(&mut self.vga_buffer);
         self.write_string(s, WriteTarget::VGABuffer);
+        self.cursor.draw(&mut self.vga_buffer);
         Ok(())
     }
 }
 
+/// How the artist draws the characters of text
+///
+/// The shadow and outline styles keep text readable over bright bitmaps.
+/// They leave the pixels around the characters untouched instead of filling
+/// them with the background color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextStyle {
+    /// Characters are drawn on a box of the background color
+    Plain,
+    /// Characters get a shadow of the given color, one font pixel down and to the right
+    Shadow(Color),
+    /// Characters are surrounded by a one font pixel border of the given color
+    Outline(Color)
+}
+
+/// Where the lines of text written with `Artist::write_str_at` are
+/// placed against the position they're written at
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Align {
+    /// The lines start at the position
+    #[default]
+    Left,
+    /// The lines are centered on the position
+    Center,
+    /// The lines end at the position
+    Right
+}
+
+/// The font, colors, style, size and alignment text is written in with `Artist::write_str_at`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextFormat {
+    pub font: &'static BitmapFont,
+    pub foreground: Color,
+    pub background: Color,
+    pub style: TextStyle,
+    /// How many times bigger than other text the characters are drawn
+    pub scale: usize,
+    pub align: Align
+}
+
+impl TextFormat {
+    /// Plain, left aligned text in the small font in `foreground` on `background`,
+    /// the size of other text
+    pub fn new(foreground: Color, background: Color) -> Self {
+        Self { font: &font::SMALL, foreground, background, style: TextStyle::Plain, scale: 1, align: Align::Left }
+    }
+}
+
+/// How the artist puts the double buffer on the screen
+///
+/// Changing the screen while the monitor is showing it tears the picture,
+/// so it can be put off until the vertical retrace
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    /// Copied right away, which can tear
+    #[default]
+    Immediate,
+    /// Copied once the next retrace starts, waiting for it
+    Vsync,
+    /// Copied to a third buffer right away, which `flip_pending_frame` puts
+    /// on the screen in the retrace, so drawing never waits. A frame that isn't
+    /// put on the screen before the next one is drawn is dropped
+    TripleBuffered
+}
+
+/// A buffer the artist's drawing functions can draw in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// The buffer that is copied to the screen
+    DoubleBuffer,
+    /// A buffer created with `Artist::create_target`
+    Offscreen(OffscreenTarget)
+}
+
+/// Identifies an off-screen target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OffscreenTarget(usize);
+
 /// Tells the artist where to write text to
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum WriteTarget {
@@ -346,5 +1468,167 @@ mod tests {
         let is_within_bounds = pos_is_within_screen_bounds(pos, 0, 0);
         assert!(is_within_bounds);
     }
+
+    #[test]
+    fn test_copy_rect() {
+        extern crate std;
+        use std::boxed::Box;
+        use std::vec;
+        let black = Color::new(Color::BLACK);
+        let red = Color::new(Color::RED);
+        // The buffer is too big to be created on the test thread's stack
+        let pixels = vec![black; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice();
+        let mut buffer = unsafe { Box::from_raw(Box::into_raw(pixels).cast::<VGABuffer>()) };
+        // A 3x3 red square at (10, 10)
+        for y in 10..13 {
+            for x in 10..13 {
+                buffer[y][x] = red;
+            }
+        }
+
+        // Scrolling it down by 2 rows, so the rectangles overlap
+        buffer.copy_rect(Point(10, 10), Point(10, 12), 3, 3);
+        for y in 12..15 {
+            for x in 10..13 {
+                assert_eq!(buffer[y][x], red);
+            }
+        }
+        // The rows that weren't overwritten are left as they were
+        assert_eq!(buffer[10][10], red);
+        assert_eq!(buffer[15][10], black);
+
+        // Parts that would land off the screen are left out
+        let bottom = (SCREEN_HEIGHT - 1).as_i16();
+        buffer.copy_rect(Point(10, 12), Point(-1, bottom), 3, 3);
+        assert_eq!(buffer[SCREEN_HEIGHT - 1][0], red);
+        assert_eq!(buffer[SCREEN_HEIGHT - 1][1], red);
+        assert_eq!(buffer[SCREEN_HEIGHT - 1][2], black);
+    }
+
+    #[test]
+    fn test_copy_rect_from() {
+        extern crate std;
+        use std::boxed::Box;
+        use std::vec;
+        let black = Color::new(Color::BLACK);
+        let red = Color::new(Color::RED);
+        let new_buffer = || {
+            let pixels = vec![black; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice();
+            unsafe { Box::from_raw(Box::into_raw(pixels).cast::<VGABuffer>()) }
+        };
+        let mut src = new_buffer();
+        let mut dst = new_buffer();
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                src[y][x] = red;
+            }
+        }
+
+        dst.copy_rect_from(&src, Point(10, 20), 3, 2);
+        assert_eq!(dst[20][10], red);
+        assert_eq!(dst[21][12], red);
+        assert_eq!(dst[22][10], black);
+        assert_eq!(dst[20][13], black);
+        assert_eq!(dst[19][10], black);
+
+        // Parts that are off the screen are left out
+        dst.copy_rect_from(&src, Point(-2, -1), 4, 3);
+        assert_eq!(dst[0][0], red);
+        assert_eq!(dst[1][1], red);
+        assert_eq!(dst[2][0], black);
+        assert_eq!(dst[0][2], black);
+    }
+
+    #[test]
+    fn test_screen_is_scaled_up_in_bigger_framebuffers() {
+        extern crate std;
+        use std::boxed::Box;
+        use std::vec;
+        use machine::memory::Addr;
+        use machine::framebuffer::PixelFormat;
+        let black = Color::new(Color::BLACK);
+        let red = Color::new(Color::RED);
+        let green = Color::new(Color::GREEN);
+        // Room for the screen at twice its size, 4 pixels from the left and 1 from the top
+        let (width, height, stride) = (SCREEN_WIDTH * 2 + 8, SCREEN_HEIGHT * 2 + 2, SCREEN_WIDTH * 2 + 16);
+        let mut pixels = vec![green; stride * height];
+        let framebuffer = Framebuffer::new(
+            Addr::new(pixels.as_mut_ptr() as u64),
+            pixels.len() * core::mem::size_of::<Color>(),
+            width,
+            height,
+            stride,
+            core::mem::size_of::<Color>(),
+            PixelFormat::Bgr
+        );
+        let mut screen = Screen::new(&framebuffer);
+        let mut src = {
+            let pixels = vec![black; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice();
+            unsafe { Box::from_raw(Box::into_raw(pixels).cast::<VGABuffer>()) }
+        };
+        src[0][0] = red;
+        src[SCREEN_HEIGHT - 1][SCREEN_WIDTH - 1] = red;
+
+        screen.copy_from(&src);
+        let at = |pixels: &[Color], x: usize, y: usize| pixels[y * stride + x];
+        for (x, y) in [(4, 1), (5, 1), (4, 2), (5, 2), (width - 5, height - 2), (width - 6, height - 3)] {
+            assert_eq!(at(&pixels, x, y), red, "({}, {})", x, y);
+        }
+        for (x, y) in [(6, 1), (4, 3), (width - 7, height - 2)] {
+            assert_eq!(at(&pixels, x, y), black, "({}, {})", x, y);
+        }
+        // The framebuffer around the screen is blacked out
+        assert_ne!(at(&pixels, 3, 1), green);
+        assert_ne!(at(&pixels, 4, 0), green);
+
+        screen.set_pixel(1, 0, green);
+        assert_eq!(screen.pixel(1, 0), green);
+        assert_eq!(screen.pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1), red);
+        for (x, y) in [(6, 1), (7, 1), (6, 2), (7, 2)] {
+            assert_eq!(at(&pixels, x, y), green, "({}, {})", x, y);
+        }
+        assert_eq!(at(&pixels, 8, 1), black);
+
+        // Only the rectangle is copied
+        src[0][1] = red;
+        screen.copy_rect_from(&src, Rect { x: 1, y: 0, width: 1, height: 1 });
+        assert_eq!(at(&pixels, 7, 2), red);
+        assert_eq!(at(&pixels, 8, 2), black);
+    }
+
+    #[test]
+    fn test_text_layout() {
+        let char_width = FONT_WIDTH * X_SCALE;
+        assert_eq!(text_width("SCORE", &font::SMALL, 1), 5 * char_width);
+        assert_eq!(text_width("You win\nPress y to play again", &font::LARGE, 2), 21 * char_width * 2);
+        assert_eq!(text_width("", &font::SMALL, 1), 0);
+        assert_eq!(text_width("1", &font::PROPORTIONAL, 1), font::PROPORTIONAL.advance(b'1') * X_SCALE);
+        assert_eq!(aligned_x(100, 40, Align::Left), 100);
+        assert_eq!(aligned_x(100, 40, Align::Center), 80);
+        assert_eq!(aligned_x(100, 40, Align::Right), 60);
+        // Can start off the screen
+        assert_eq!(aligned_x(10, 40, Align::Right), -30);
+    }
+
+    #[test]
+    fn test_glyph_pixel_color() {
+        let fg = Color::new(Color::YELLOW);
+        let bg = Color::new(Color::BLACK);
+        let effect = Color::new(Color::RED);
+        let color_code = ColorCode(fg, bg);
+        // A single set pixel at (1, 1)
+        let glyph = [0x00, 0b01000000, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+        assert_eq!(glyph_pixel_color(&glyph, 1, 1, color_code, TextStyle::Plain), Some(fg));
+        assert_eq!(glyph_pixel_color(&glyph, 2, 2, color_code, TextStyle::Plain), Some(bg));
+
+        assert_eq!(glyph_pixel_color(&glyph, 2, 2, color_code, TextStyle::Shadow(effect)), Some(effect));
+        assert_eq!(glyph_pixel_color(&glyph, 0, 0, color_code, TextStyle::Shadow(effect)), None);
+
+        assert_eq!(glyph_pixel_color(&glyph, 0, 0, color_code, TextStyle::Outline(effect)), Some(effect));
+        assert_eq!(glyph_pixel_color(&glyph, 2, 1, color_code, TextStyle::Outline(effect)), Some(effect));
+        assert_eq!(glyph_pixel_color(&glyph, 3, 1, color_code, TextStyle::Outline(effect)), None);
+        assert_eq!(glyph_pixel_color(&glyph, 1, 1, color_code, TextStyle::Outline(effect)), Some(fg));
+    }
 }
 
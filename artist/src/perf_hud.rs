@@ -0,0 +1,163 @@
+//! A heads up display of how well the game is keeping up, for finding out
+//! what slowed it down on real machines, where there's no profiler
+//!
+//! The artist measures the frame rate and frame time itself, every time the
+//! double buffer is put on the screen. The heap usage is read from the allocator,
+//! and the number of events waiting to be handled is passed in by the game,
+//! since the artist doesn't know about events
+
+use collections::allocator::HeapStats;
+use num::fmt;
+use crate::{FONT_WIDTH, FONT_HEIGHT, X_SCALE, Y_SCALE};
+
+/// The number of characters in each line of the HUD
+pub const PERF_HUD_LINE_LEN: usize = 18;
+/// The number of lines in the HUD
+pub const PERF_HUD_LINES: usize = 4;
+/// The width of the HUD on the screen
+pub const PERF_HUD_WIDTH: usize = PERF_HUD_LINE_LEN * FONT_WIDTH * X_SCALE;
+/// The height of the HUD on the screen
+pub const PERF_HUD_HEIGHT: usize = PERF_HUD_LINES * FONT_HEIGHT * Y_SCALE;
+
+const US_PER_SEC: u64 = 1_000_000;
+
+/// What the HUD shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PerfStats {
+    /// The number of frames put on the screen in the last second
+    pub fps: usize,
+    /// The microseconds between the last two frames
+    pub frame_time_us: u64,
+    pub heap: HeapStats,
+    /// The number of events waiting to be handled
+    pub queued_events: usize
+}
+
+impl PerfStats {
+    /// The lines of text the stats are shown in on the HUD, with
+    /// the names on the left and the values on the right
+    pub fn lines(&self) -> [[u8; PERF_HUD_LINE_LEN]; PERF_HUD_LINES] {
+        let mut fps = [0; fmt::U64_MAX_LEN];
+        let mut frame_time = [0; fmt::I64_MAX_LEN + 1];
+        let mut heap_used = [0; fmt::U64_MAX_LEN];
+        let mut heap_size = [0; fmt::U64_MAX_LEN];
+        let mut queued_events = [0; fmt::U64_MAX_LEN];
+        // In tenths of a millisecond
+        let frame_time_tenths = (self.frame_time_us / 100).min(i64::MAX as u64) as i64;
+        [
+            line("FPS", &[fmt::write_u64(&mut fps, self.fps as u64)]),
+            line("FRAME", &[fmt::write_fixed(&mut frame_time, frame_time_tenths, 1), "MS"]),
+            line("HEAP", &[
+                fmt::write_u64(&mut heap_used, (self.heap.used / 1024) as u64),
+                "/",
+                fmt::write_u64(&mut heap_size, (self.heap.size / 1024) as u64),
+                "K"
+            ]),
+            line("EVENTS", &[fmt::write_u64(&mut queued_events, self.queued_events as u64)])
+        ]
+    }
+}
+
+/// A line of the HUD with `name` on the left and the `value` parts on the right
+///
+/// The value is kept over the name if they don't both fit
+fn line(name: &str, value: &[&str]) -> [u8; PERF_HUD_LINE_LEN] {
+    let mut line = [b' '; PERF_HUD_LINE_LEN];
+    let name_len = name.len().min(PERF_HUD_LINE_LEN);
+    line[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+    let mut end = PERF_HUD_LINE_LEN;
+    for byte in value.iter().rev().flat_map(|part| part.bytes().rev()).take(PERF_HUD_LINE_LEN) {
+        end -= 1;
+        line[end] = byte;
+    }
+    line
+}
+
+/// Measures the frame rate and how long frames take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FrameTimer {
+    /// When the last frame was put on the screen, in microseconds since boot
+    last_frame_us: Option<u64>,
+    /// The time between the last two frames
+    frame_time_us: u64,
+    /// When the frames started being counted for the current second
+    second_start_us: u64,
+    /// The number of frames since `second_start_us`
+    frames_in_second: usize,
+    /// The frame rate worked out at the end of the last second
+    fps: usize
+}
+
+impl FrameTimer {
+    pub(crate) const fn new() -> Self {
+        Self { last_frame_us: None, frame_time_us: 0, second_start_us: 0, frames_in_second: 0, fps: 0 }
+    }
+
+    /// Counts a frame put on the screen `now_us` microseconds after boot
+    pub(crate) fn frame_presented(&mut self, now_us: u64) {
+        let last_frame_us = match self.last_frame_us.replace(now_us) {
+            Some(last_frame_us) => last_frame_us,
+            None => {
+                self.second_start_us = now_us;
+                return;
+            }
+        };
+        self.frame_time_us = now_us.saturating_sub(last_frame_us);
+        self.frames_in_second += 1;
+        let elapsed_us = now_us.saturating_sub(self.second_start_us);
+        if elapsed_us >= US_PER_SEC {
+            // Rounded, since a second's frames rarely end right on the second
+            let frames = self.frames_in_second as u64;
+            self.fps = ((frames * US_PER_SEC + elapsed_us / 2) / elapsed_us) as usize;
+            self.frames_in_second = 0;
+            self.second_start_us = now_us;
+        }
+    }
+
+    pub(crate) fn fps(&self) -> usize {
+        self.fps
+    }
+
+    pub(crate) fn frame_time_us(&self) -> u64 {
+        self.frame_time_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_timer() {
+        let mut timer = FrameTimer::new();
+        timer.frame_presented(5_000);
+        assert_eq!((timer.fps(), timer.frame_time_us()), (0, 0));
+        // 60 frames a second, with the last one a little late
+        for frame in 1..60 {
+            timer.frame_presented(5_000 + frame * 16_667);
+        }
+        assert_eq!((timer.fps(), timer.frame_time_us()), (0, 16_667));
+        timer.frame_presented(5_000 + 1_000_200);
+        assert_eq!(timer.fps(), 60);
+        // A slow frame shows up in the frame time right away
+        timer.frame_presented(5_000 + 1_100_200);
+        assert_eq!((timer.fps(), timer.frame_time_us()), (60, 100_000));
+    }
+
+    #[test]
+    fn test_perf_stats_lines() {
+        let stats = PerfStats {
+            fps: 60,
+            frame_time_us: 16_667,
+            heap: HeapStats { used: 812 * 1024, size: 12 * 1024 * 1024 },
+            queued_events: 3
+        };
+        let lines = stats.lines();
+        assert_eq!(&lines[0], b"FPS             60");
+        assert_eq!(&lines[1], b"FRAME       16.6MS");
+        assert_eq!(&lines[2], b"HEAP    812/12288K");
+        assert_eq!(&lines[3], b"EVENTS           3");
+        // The value is kept when it doesn't fit with the name
+        assert_eq!(&line("EVENTS", &["1234567890123456"]), b"EV1234567890123456");
+    }
+}
@@ -0,0 +1,188 @@
+//! Working out the pixels of lines, circles and polygons
+//!
+//! The functions here hand the pixels to a closure instead of drawing them,
+//! so the artist can draw them in whichever buffer is being drawn in.
+//! The pixels aren't clipped, so some of them can be off the screen
+
+use physics::Point;
+use num::Integer;
+
+/// The most vertices a polygon can have to be filled
+pub const MAX_POLYGON_VERTICES: usize = 32;
+
+/// Calls `plot` with every pixel on the line from `from` to `to`, both ends included
+///
+/// Uses Bresenham's line algorithm, so only integers are needed
+pub(crate) fn line(from: Point, to: Point, mut plot: impl FnMut(isize, isize)) {
+    let (mut x, mut y) = (from.x().as_isize(), from.y().as_isize());
+    let (end_x, end_y) = (to.x().as_isize(), to.y().as_isize());
+    let dx = (end_x - x).abs();
+    let dy = -(end_y - y).abs();
+    let step_x = if x < end_x { 1 } else { -1 };
+    let step_y = if y < end_y { 1 } else { -1 };
+    // How far off the line the next pixel would be, times 2 * dx * dy
+    let mut error = dx + dy;
+    loop {
+        plot(x, y);
+        if x == end_x && y == end_y {
+            break;
+        }
+        let double_error = 2 * error;
+        if double_error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if double_error <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+/// Calls `plot` with every pixel on the circle of `radius` around `center`
+///
+/// Uses the midpoint circle algorithm, working out one eighth of the circle
+/// and mirroring it, so a few pixels are plotted twice
+pub(crate) fn circle(center: Point, radius: usize, mut plot: impl FnMut(isize, isize)) {
+    let (cx, cy) = (center.x().as_isize(), center.y().as_isize());
+    let (mut x, mut y) = (radius.as_isize(), 0);
+    let mut error = 1 - x;
+    while x >= y {
+        for (px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+            plot(cx + px, cy + py);
+        }
+        y += 1;
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
+        }
+    }
+}
+
+/// Calls `span` with the row, first column and the column just past the last one
+/// of every row of pixels in the disc of `radius` around `center`
+pub(crate) fn disc(center: Point, radius: usize, mut span: impl FnMut(isize, isize, isize)) {
+    let (cx, cy) = (center.x().as_isize(), center.y().as_isize());
+    let radius = radius.as_isize();
+    // The same as the pixels the midpoint circle algorithm picks,
+    // so a filled circle covers its outline
+    let limit = radius * radius + radius;
+    let mut half_width = radius;
+    for dy in 0..=radius {
+        while half_width * half_width + dy * dy > limit {
+            half_width -= 1;
+        }
+        span(cy + dy, cx - half_width, cx + half_width + 1);
+        if dy != 0 {
+            span(cy - dy, cx - half_width, cx + half_width + 1);
+        }
+    }
+}
+
+/// Calls `span` with the row, first column and the column just past the last one
+/// of every row of pixels inside the polygon with `vertices`
+///
+/// A pixel is inside if a line from it crosses the polygon's edges an odd number
+/// of times, so the polygon can cross itself. Like rectangles, the right and bottom
+/// edges are left out, so polygons that share an edge don't both cover it
+pub(crate) fn polygon(vertices: &[Point], mut span: impl FnMut(isize, isize, isize)) -> Result<(), &'static str> {
+    if vertices.len() > MAX_POLYGON_VERTICES {
+        return Err("The polygon has too many vertices to be filled");
+    }
+    let top = vertices.iter().map(|vertex| vertex.y().as_isize()).min().unwrap_or(0);
+    let bottom = vertices.iter().map(|vertex| vertex.y().as_isize()).max().unwrap_or(0);
+    let edges = vertices.iter().zip(vertices.iter().cycle().skip(1));
+    for y in top..bottom {
+        // Where the row crosses the edges, which is at most once per edge
+        let mut crossings = [0; MAX_POLYGON_VERTICES];
+        let mut no_of_crossings = 0;
+        for (start, end) in edges.clone() {
+            let (x0, y0) = (start.x().as_isize(), start.y().as_isize());
+            let (x1, y1) = (end.x().as_isize(), end.y().as_isize());
+            // Counting the top end but not the bottom one makes a vertex where
+            // 2 edges meet count once, and horizontal edges never count
+            if y0.min(y1) <= y && y < y0.max(y1) {
+                crossings[no_of_crossings] = x0 + (y - y0) * (x1 - x0) / (y1 - y0);
+                no_of_crossings += 1;
+            }
+        }
+        let crossings = &mut crossings[..no_of_crossings];
+        crossings.sort_unstable();
+        for pair in crossings.chunks_exact(2) {
+            if pair[0] < pair[1] {
+                span(y, pair[0], pair[1]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    fn line_pixels(from: Point, to: Point) -> Vec<(isize, isize)> {
+        let mut pixels = Vec::new();
+        line(from, to, |x, y| pixels.push((x, y)));
+        pixels
+    }
+
+    fn spans(fill: impl FnOnce(&mut dyn FnMut(isize, isize, isize))) -> Vec<(isize, isize, isize)> {
+        let mut spans = Vec::new();
+        fill(&mut |y, start, end| spans.push((y, start, end)));
+        spans.sort();
+        spans
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line_pixels(Point(2, 3), Point(2, 3)), [(2, 3)]);
+        assert_eq!(line_pixels(Point(0, 0), Point(3, 0)), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(line_pixels(Point(1, 1), Point(-1, -1)), [(1, 1), (0, 0), (-1, -1)]);
+        assert_eq!(line_pixels(Point(0, 0), Point(4, 2)), [(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]);
+        assert_eq!(line_pixels(Point(0, 4), Point(1, 0)), [(0, 4), (0, 3), (1, 2), (1, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn test_circle() {
+        let mut pixels = Vec::new();
+        circle(Point(10, 10), 2, |x, y| pixels.push((x, y)));
+        pixels.sort();
+        pixels.dedup();
+        assert_eq!(pixels, [
+            (8, 9), (8, 10), (8, 11), (9, 8), (9, 12), (10, 8), (10, 12),
+            (11, 8), (11, 12), (12, 9), (12, 10), (12, 11)
+        ]);
+        let mut pixels = Vec::new();
+        circle(Point(0, 0), 0, |x, y| pixels.push((x, y)));
+        pixels.dedup();
+        assert_eq!(pixels, [(0, 0)]);
+    }
+
+    #[test]
+    fn test_disc() {
+        assert_eq!(spans(|span| disc(Point(10, 10), 2, span)), [
+            (8, 9, 12), (9, 8, 13), (10, 8, 13), (11, 8, 13), (12, 9, 12)
+        ]);
+        assert_eq!(spans(|span| disc(Point(0, 0), 0, span)), [(0, 0, 1)]);
+    }
+
+    #[test]
+    fn test_polygon() {
+        let square = [Point(0, 0), Point(3, 0), Point(3, 2), Point(0, 2)];
+        assert_eq!(spans(|span| polygon(&square, span).unwrap()), [(0, 0, 3), (1, 0, 3)]);
+        let triangle = [Point(0, 0), Point(4, 4), Point(0, 4)];
+        assert_eq!(spans(|span| polygon(&triangle, span).unwrap()), [(1, 0, 1), (2, 0, 2), (3, 0, 3)]);
+        // A bow tie, which crosses itself in the middle
+        let bow_tie = [Point(0, 0), Point(4, 4), Point(4, 0), Point(0, 4)];
+        assert_eq!(spans(|span| polygon(&bow_tie, span).unwrap()), [
+            (1, 0, 1), (1, 3, 4), (2, 0, 2), (2, 2, 4), (3, 0, 1), (3, 3, 4)
+        ]);
+        assert!(polygon(&[], |_, _, _| panic!("An empty polygon has no pixels")).is_ok());
+        assert!(polygon(&[Point(0, 0); MAX_POLYGON_VERTICES + 1], |_, _, _| ()).is_err());
+    }
+}
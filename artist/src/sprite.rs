@@ -0,0 +1,200 @@
+//! Bitmaps made up of several frames, and animations that go through them
+//!
+//! All the frames of an animation are drawn in a single bitmap, the sprite
+//! sheet, in a grid of frames of the same size. An `Animation` keeps track of
+//! which frame is shown, moving on every few timer ticks.
+
+use collections::vec::Vec;
+use collections::vec;
+use crate::bitmap::ScaledBitmap;
+
+/// A bitmap sliced into frames of the same size
+pub struct SpriteSheet {
+    /// The frames from left to right, then from top to bottom
+    frames: Vec<'static, ScaledBitmap>
+}
+
+impl SpriteSheet {
+    /// Slices `bitmap` into a grid of `columns` by `rows` frames
+    ///
+    /// Since it only counts frames, it works the same no matter how
+    /// much the bitmap has been scaled
+    pub fn new(bitmap: &ScaledBitmap, columns: usize, rows: usize) -> Result<Self, &'static str> {
+        if columns == 0 || rows == 0 {
+            return Err("A sprite sheet must have at least one frame");
+        }
+        if bitmap.width() % columns != 0 || bitmap.height() % rows != 0 {
+            return Err("The sprite sheet can't be sliced into frames of the same size");
+        }
+        let (frame_width, frame_height) = (bitmap.width() / columns, bitmap.height() / rows);
+        let mut frames = vec!(item_type => ScaledBitmap, capacity => columns * rows);
+        for row in 0..rows {
+            for col in 0..columns {
+                frames.push(bitmap.cropped(col * frame_width, row * frame_height, frame_width, frame_height));
+            }
+        }
+        Ok(Self { frames })
+    }
+
+    /// The frame at `idx`
+    ///
+    /// # Panics
+    ///
+    /// If there are only `idx` frames or less
+    pub fn frame(&self, idx: usize) -> &ScaledBitmap {
+        &self.frames[idx]
+    }
+
+    pub fn no_of_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn frame_width(&self) -> usize {
+        self.frames[0].width()
+    }
+
+    pub fn frame_height(&self) -> usize {
+        self.frames[0].height()
+    }
+}
+
+/// Goes through a number of frames, showing each for a number of timer ticks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Animation {
+    no_of_frames: usize,
+    ticks_per_frame: usize,
+    /// Whether the animation starts over after the last frame,
+    /// instead of stopping at it
+    looping: bool,
+    /// The number of ticks since the animation started
+    ticks: usize
+}
+
+impl Animation {
+    /// Creates an animation at its first frame
+    ///
+    /// Every frame is shown for at least a tick
+    pub fn new(no_of_frames: usize, ticks_per_frame: usize, looping: bool) -> Self {
+        Self {
+            no_of_frames: no_of_frames.max(1),
+            ticks_per_frame: ticks_per_frame.max(1),
+            looping,
+            ticks: 0
+        }
+    }
+
+    /// Creates an animation that goes through all the frames of `sheet`
+    pub fn for_sheet(sheet: &SpriteSheet, ticks_per_frame: usize, looping: bool) -> Self {
+        Self::new(sheet.no_of_frames(), ticks_per_frame, looping)
+    }
+
+    /// Moves the animation on by a timer tick
+    ///
+    /// Returns true if it moved on to another frame
+    pub fn tick(&mut self) -> bool {
+        let frame = self.frame();
+        let total_ticks = self.no_of_frames * self.ticks_per_frame;
+        if self.looping {
+            self.ticks = (self.ticks + 1) % total_ticks;
+        } else {
+            self.ticks = (self.ticks + 1).min(total_ticks);
+        }
+        self.frame() != frame
+    }
+
+    /// The index of the frame to be shown
+    pub fn frame(&self) -> usize {
+        (self.ticks / self.ticks_per_frame).min(self.no_of_frames - 1)
+    }
+
+    /// Tells whether an animation that doesn't loop has shown its last frame
+    /// for all its ticks
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.ticks == self.no_of_frames * self.ticks_per_frame
+    }
+
+    /// Goes back to the first frame
+    pub fn restart(&mut self) {
+        self.ticks = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Animation, SpriteSheet};
+    use crate::bitmap::tests::{bitmap_of, colors, rows_of};
+
+    #[test]
+    fn test_sprite_sheet_frames() {
+        let bitmap = bitmap_of(&[
+            &[1, 2, 3, 4, 5, 6],
+            &[7, 8, 9, 10, 11, 12],
+            &[13, 14, 15, 16, 17, 18],
+            &[19, 20, 21, 22, 23, 24]
+        ]);
+        let sheet = SpriteSheet::new(&bitmap, 3, 2).unwrap();
+        assert_eq!(sheet.no_of_frames(), 6);
+        assert_eq!((sheet.frame_width(), sheet.frame_height()), (2, 2));
+        // From left to right, then from top to bottom
+        let expected: [&[&[u8]]; 6] = [
+            &[&[1, 2], &[7, 8]],
+            &[&[3, 4], &[9, 10]],
+            &[&[5, 6], &[11, 12]],
+            &[&[13, 14], &[19, 20]],
+            &[&[15, 16], &[21, 22]],
+            &[&[17, 18], &[23, 24]]
+        ];
+        for (idx, frame) in expected.iter().enumerate() {
+            assert_eq!(rows_of(sheet.frame(idx)), colors(frame), "frame {}", idx);
+        }
+    }
+
+    #[test]
+    fn test_sprite_sheet_errors() {
+        let bitmap = bitmap_of(&[&[1, 2, 3, 4], &[5, 6, 7, 8]]);
+        let cases = [
+            // name, columns, rows
+            ("no columns", 0, 1),
+            ("no rows", 2, 0),
+            ("uneven columns", 3, 1),
+            ("uneven rows", 2, 3)
+        ];
+        for (name, columns, rows) in cases {
+            assert!(SpriteSheet::new(&bitmap, columns, rows).is_err(), "{}", name);
+        }
+        // A single frame is the whole bitmap
+        let sheet = SpriteSheet::new(&bitmap, 1, 1).unwrap();
+        assert_eq!(rows_of(sheet.frame(0)), rows_of(&bitmap));
+    }
+
+    #[test]
+    fn test_animation() {
+        let mut animation = Animation::new(3, 2, false);
+        let mut frames = [0; 7];
+        for frame in frames.iter_mut() {
+            *frame = animation.frame();
+            animation.tick();
+        }
+        assert_eq!(frames, [0, 0, 1, 1, 2, 2, 2]);
+        assert!(animation.is_finished());
+        // Stays at the last frame
+        assert!(!animation.tick());
+        assert_eq!(animation.frame(), 2);
+        animation.restart();
+        assert_eq!(animation.frame(), 0);
+        assert!(!animation.is_finished());
+    }
+
+    #[test]
+    fn test_looping_animation() {
+        let mut animation = Animation::new(2, 1, true);
+        let changes: [bool; 4] = core::array::from_fn(|_| animation.tick());
+        assert_eq!(changes, [true; 4]);
+        assert_eq!(animation.frame(), 0);
+        assert!(!animation.is_finished());
+        // Frames last at least a tick
+        let mut animation = Animation::new(2, 0, true);
+        assert!(animation.tick());
+        assert_eq!(animation.frame(), 1);
+    }
+}
@@ -0,0 +1,142 @@
+//! The camera and the clip rectangle, which decide where things are drawn
+//!
+//! The positions given to the drawing functions are in the game's world,
+//! which can be bigger than the screen. The camera is the point of the world
+//! that is at the top left of the screen, so a scrolling level is drawn by
+//! moving the camera instead of every sprite. The clip rectangle is the part
+//! of the screen that can be drawn in, so a scrolling playfield can be kept
+//! from drawing over the things around it.
+
+use physics::Point;
+use num::Integer;
+use crate::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+/// A rectangle of the screen, which can be empty
+///
+/// Like the rectangles drawn, the right and bottom edges are left out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ClipRect {
+    pub(crate) left: isize,
+    pub(crate) top: isize,
+    pub(crate) right: isize,
+    pub(crate) bottom: isize
+}
+
+impl ClipRect {
+    pub(crate) const SCREEN: ClipRect = ClipRect {
+        left: 0,
+        top: 0,
+        right: SCREEN_WIDTH as isize,
+        bottom: SCREEN_HEIGHT as isize
+    };
+
+    /// The part of the `width` by `height` rectangle at `pos` that is on the screen
+    pub(crate) fn new(pos: Point, width: usize, height: usize) -> Self {
+        let (x, y) = (pos.x().as_isize(), pos.y().as_isize());
+        let rect = ClipRect { left: x, top: y, right: x + width.as_isize(), bottom: y + height.as_isize() };
+        rect.intersection(&Self::SCREEN)
+    }
+
+    /// The part of the screen that is in both rectangles
+    pub(crate) fn intersection(&self, other: &ClipRect) -> Self {
+        ClipRect {
+            left: self.left.max(other.left),
+            top: self.top.max(other.top),
+            right: self.right.min(other.right),
+            bottom: self.bottom.min(other.bottom)
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.left >= self.right || self.top >= self.bottom
+    }
+
+    pub(crate) fn top_left(&self) -> Point {
+        Point(self.left.as_i16(), self.top.as_i16())
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        (self.right - self.left).max(0).as_usize()
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        (self.bottom - self.top).max(0).as_usize()
+    }
+
+    pub(crate) fn contains(&self, x: isize, y: isize) -> bool {
+        (self.left..self.right).contains(&x) && (self.top..self.bottom).contains(&y)
+    }
+
+    /// The part of row `y` from column `start` up to but not including column `end`
+    /// that is in the rectangle, or None if none of it is
+    pub(crate) fn clip_span(&self, y: isize, start: isize, end: isize) -> Option<(isize, isize)> {
+        let (start, end) = (start.max(self.left), end.min(self.right));
+        if (self.top..self.bottom).contains(&y) && start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+}
+
+/// Where the world is drawn on the screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Viewport {
+    /// The point of the world at the top left of the screen
+    pub(crate) camera: Point,
+    /// The part of the screen that can be drawn in
+    pub(crate) clip: ClipRect
+}
+
+impl Viewport {
+    /// A camera at the origin, so the world and the screen line up,
+    /// and the whole screen to draw in
+    pub(crate) const fn new() -> Self {
+        Self { camera: Point(0, 0), clip: ClipRect::SCREEN }
+    }
+
+    /// Where `pos` in the world is on the screen
+    pub(crate) fn screen_pos(&self, pos: Point) -> Point {
+        Point(pos.x().saturating_sub(self.camera.x()), pos.y().saturating_sub(self.camera.y()))
+    }
+
+    /// How far to the left and up the world is moved to be put on the screen
+    pub(crate) fn offset(&self) -> (isize, isize) {
+        (self.camera.x().as_isize(), self.camera.y().as_isize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip_rect() {
+        let rect = ClipRect::new(Point(-5, 10), 20, 10);
+        assert_eq!(rect, ClipRect { left: 0, top: 10, right: 15, bottom: 20 });
+        assert_eq!((rect.top_left(), rect.width(), rect.height()), (Point(0, 10), 15, 10));
+        assert!(rect.contains(0, 10) && rect.contains(14, 19));
+        assert!(!rect.contains(15, 10) && !rect.contains(0, 20));
+        assert_eq!(rect.clip_span(12, -3, 8), Some((0, 8)));
+        assert_eq!(rect.clip_span(12, 10, 30), Some((10, 15)));
+        assert_eq!(rect.clip_span(20, 0, 5), None);
+        assert_eq!(rect.clip_span(12, 15, 20), None);
+        // Rectangles that don't meet have nothing in common
+        let other = ClipRect::new(Point(30, 0), 10, 10);
+        let none = rect.intersection(&other);
+        assert!(none.is_empty());
+        assert_eq!((none.width(), none.height()), (0, 0));
+        assert_eq!(none.clip_span(5, 0, 100), None);
+        assert!(ClipRect::new(Point(SCREEN_WIDTH.as_i16(), 0), 10, 10).is_empty());
+    }
+
+    #[test]
+    fn test_camera() {
+        let mut viewport = Viewport::new();
+        assert_eq!(viewport.screen_pos(Point(3, 4)), Point(3, 4));
+        viewport.camera = Point(100, -20);
+        assert_eq!(viewport.screen_pos(Point(130, 0)), Point(30, 20));
+        assert_eq!(viewport.offset(), (100, -20));
+        assert_eq!(viewport.screen_pos(Point(i16::MIN, 0)), Point(i16::MIN, 20));
+    }
+}
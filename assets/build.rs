@@ -0,0 +1,125 @@
+//! Packs every file in the `data` directory into the asset archive
+//!
+//! The archive's layout is described in the crate's documentation.
+//! Besides the archive, a Rust file with its size and number of files
+//! is written, so the crate can declare the archive's static with them
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[allow(dead_code)]
+#[path = "src/format.rs"]
+mod format;
+use format::{Compression, MAGIC, HEADER_SIZE, ENTRY_SIZE, DATA_ALIGN};
+
+/// A file to be put in the archive
+struct File {
+    name: String,
+    /// The bytes as they're stored in the archive
+    stored: Vec<u8>,
+    /// The length of the file once decompressed
+    len: usize,
+    compression: Compression
+}
+
+fn main() {
+    let data_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("data");
+    // Cargo looks at everything in a directory, so adding a file rebuilds the archive
+    println!("cargo:rerun-if-changed={}", data_dir.display());
+    let mut files: Vec<File> = fs::read_dir(&data_dir)
+        .expect("Couldn't read the asset directory")
+        .map(|entry| entry.expect("Couldn't read the asset directory").path())
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let name = path.file_name().unwrap().to_str()
+                .unwrap_or_else(|| panic!("The name of {} isn't UTF-8", path.display()))
+                .to_string();
+            let bytes = fs::read(&path).unwrap_or_else(|_| panic!("Couldn't read {}", path.display()));
+            compressed(name, bytes)
+        })
+        .collect();
+    // So the archive is the same on every build
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let archive = archive(&files);
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("assets.bmba"), &archive).expect("Couldn't write the asset archive");
+    let info = format!("const ARCHIVE_SIZE: usize = {};\nconst NO_OF_ASSETS: usize = {};\n", archive.len(), files.len());
+    fs::write(Path::new(&out_dir).join("archive_info.rs"), info).expect("Couldn't write the asset archive's info");
+}
+
+/// The file compressed with PackBits, if that shrinks it by at least a sixteenth
+///
+/// A compressed file takes up heap space once it's decompressed, so files that
+/// hardly shrink, like music, are better left to be read in place
+fn compressed(name: String, bytes: Vec<u8>) -> File {
+    let packed = pack_bits(&bytes);
+    let mut unpacked = vec![0; bytes.len()];
+    assert!(
+        format::unpack_bits(&packed, &mut unpacked).is_ok() && unpacked == bytes,
+        "{} doesn't decompress to what it was", name
+    );
+    let len = bytes.len();
+    if packed.len() + len / 16 <= len {
+        File { name, stored: packed, len, compression: Compression::PackBits }
+    } else {
+        File { name, stored: bytes, len, compression: Compression::None }
+    }
+}
+
+/// Encodes `src` with PackBits, with runs of 3 bytes or more repeated
+/// and everything else as it is
+fn pack_bits(src: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let mut i = 0;
+    while i < src.len() {
+        let run = src[i..].iter().take(128).take_while(|byte| **byte == src[i]).count();
+        if run >= 3 {
+            packed.push((257 - run) as u8);
+            packed.push(src[i]);
+            i += run;
+        } else {
+            let start = i;
+            let run_starts_at = |i: usize| i + 2 < src.len() && src[i] == src[i + 1] && src[i] == src[i + 2];
+            while i < src.len() && i - start < 128 && !run_starts_at(i) {
+                i += 1;
+            }
+            packed.push((i - start - 1) as u8);
+            packed.extend_from_slice(&src[start..i]);
+        }
+    }
+    packed
+}
+
+/// Lays out the header, the entries, the names and the files' bytes
+fn archive(files: &[File]) -> Vec<u8> {
+    let align = |offset: usize| (offset + DATA_ALIGN - 1) / DATA_ALIGN * DATA_ALIGN;
+    let names_start = HEADER_SIZE + ENTRY_SIZE * files.len();
+    let names_len: usize = files.iter().map(|file| file.name.len()).sum();
+    let mut data_offset = align(names_start + names_len);
+    let mut name_offset = names_start;
+
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&MAGIC);
+    archive.extend_from_slice(&u32_bytes(files.len()));
+    for file in files {
+        for field in [name_offset, file.name.len(), data_offset, file.stored.len(), file.len, file.compression as usize] {
+            archive.extend_from_slice(&u32_bytes(field));
+        }
+        name_offset += file.name.len();
+        data_offset = align(data_offset + file.stored.len());
+    }
+    for file in files {
+        archive.extend_from_slice(file.name.as_bytes());
+    }
+    for file in files {
+        archive.resize(align(archive.len()), 0);
+        archive.extend_from_slice(&file.stored);
+    }
+    archive
+}
+
+fn u32_bytes(n: usize) -> [u8; 4] {
+    u32::try_from(n).expect("The asset archive is too big").to_le_bytes()
+}
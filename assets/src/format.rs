@@ -0,0 +1,68 @@
+//! The layout of the asset archive, which the build script writes and the crate reads
+
+/// The bytes the archive starts with
+pub(crate) const MAGIC: [u8; 4] = *b"BMBA";
+/// The magic bytes and the number of files
+pub(crate) const HEADER_SIZE: usize = 8;
+/// The offset and length of the name, the offset and length of the stored bytes,
+/// the length once decompressed and the compression
+pub(crate) const ENTRY_SIZE: usize = 24;
+/// Every file's bytes start at a multiple of this in the archive
+pub(crate) const DATA_ALIGN: usize = 8;
+
+/// How a file's bytes are stored in the archive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Compression {
+    /// As they are
+    None = 0,
+    /// Run length encoded with PackBits
+    PackBits = 1
+}
+
+impl Compression {
+    pub(crate) fn from_u32(n: u32) -> Option<Self> {
+        match n {
+            0 => Some(Compression::None),
+            1 => Some(Compression::PackBits),
+            _ => None
+        }
+    }
+}
+
+/// Decodes the PackBits encoded `src` into `dst`, which must be exactly
+/// as long as the decoded bytes
+///
+/// Each run starts with a control byte. 0 to 127 are followed by that many bytes
+/// plus 1 as they are, 129 to 255 by a byte that is repeated 257 minus that many
+/// times, and 128 is skipped
+pub(crate) fn unpack_bits(src: &[u8], dst: &mut [u8]) -> Result<(), &'static str> {
+    const CUT_SHORT: &str = "The compressed bytes are cut short";
+    const TOO_LONG: &str = "The compressed bytes decode to more than was expected";
+    let (mut i, mut out) = (0, 0);
+    while i < src.len() {
+        let control = src[i];
+        i += 1;
+        match control {
+            0..=127 => {
+                let len = control as usize + 1;
+                let bytes = src.get(i..i + len).ok_or(CUT_SHORT)?;
+                dst.get_mut(out..out + len).ok_or(TOO_LONG)?.copy_from_slice(bytes);
+                i += len;
+                out += len;
+            }
+            128 => (),
+            _ => {
+                let len = 257 - control as usize;
+                let byte = *src.get(i).ok_or(CUT_SHORT)?;
+                dst.get_mut(out..out + len).ok_or(TOO_LONG)?.fill(byte);
+                i += 1;
+                out += len;
+            }
+        }
+    }
+    if out != dst.len() {
+        return Err("The compressed bytes decode to less than was expected");
+    }
+    Ok(())
+}
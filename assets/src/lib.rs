@@ -0,0 +1,248 @@
+//! The game's files, bundled into an archive when it's built
+//!
+//! The build script packs every file in the `data` directory into one archive,
+//! which is included in the binary, so a new level or sound only has to be put
+//! in the directory to be found by its name. Files that shrink with run length
+//! encoding are stored compressed, and are decompressed onto the heap the first
+//! time they're asked for.
+//!
+//! # The archive format
+//!
+//! All numbers are little endian u32s. The archive starts with the magic
+//! bytes `BMBA` and the number of files. After that comes an entry for each
+//! file, sorted by name: the offset and length of its name, the offset and
+//! length of its bytes as they're stored, its length once decompressed and its
+//! `Compression`. The names and then the files' bytes follow. Every file's bytes
+//! start at a multiple of 8, so the samples in WAV files can be read in place.
+
+#![cfg_attr(not(test), no_std)]
+
+use collections::allocator::{self, Allocator};
+use sync::once::Once;
+
+mod format;
+pub use format::Compression;
+use format::{MAGIC, HEADER_SIZE, ENTRY_SIZE, DATA_ALIGN, unpack_bits};
+
+// The size of the archive and the number of files in it
+include!(concat!(env!("OUT_DIR"), "/archive_info.rs"));
+
+/// The bytes of the archive, aligned like the files in it
+#[repr(C, align(8))]
+struct AlignedArchive<const N: usize>([u8; N]);
+
+// The archive is several MiB, far more than fits in the low memory the
+// BIOS boot stages load the rest of the app into, so it goes in the section
+// the second stage loads above 1MiB
+#[cfg_attr(feature = "bios", link_section = ".sound")]
+static ARCHIVE: AlignedArchive<ARCHIVE_SIZE> = AlignedArchive(*include_bytes!(concat!(env!("OUT_DIR"), "/assets.bmba")));
+static PARSED_ARCHIVE: Once<Archive<'static>> = Once::new();
+/// The compressed files that have been decompressed, by their indices in the archive
+static DECOMPRESSED: [Once<Option<&'static [u8]>>; NO_OF_ASSETS] = [Once::INIT; NO_OF_ASSETS];
+
+/// The files bundled into the game
+pub struct Assets;
+
+impl Assets {
+    /// The bytes of the file called `name`, or None if there's no such file
+    /// or it couldn't be decompressed
+    ///
+    /// The bytes of a compressed file are decompressed onto the heap the first
+    /// time, and are never freed
+    pub fn get(name: &str) -> Option<&'static [u8]> {
+        let (idx, entry) = archive().find(name)?;
+        match entry.compression {
+            Compression::None => Some(entry.stored),
+            Compression::PackBits => *DECOMPRESSED[idx].call_once(|| decompress(entry))
+        }
+    }
+
+    /// The names of all the files, sorted
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        archive().entries().map(|entry| entry.name)
+    }
+}
+
+fn archive() -> &'static Archive<'static> {
+    PARSED_ARCHIVE.call_once(|| Archive::parse(&ARCHIVE.0).expect("The asset archive is corrupt"))
+}
+
+/// Decompresses `entry` into a buffer on the heap, or returns None if there's
+/// no space for it or its bytes are corrupt
+fn decompress(entry: Entry<'static>) -> Option<&'static [u8]> {
+    if entry.len == 0 {
+        return Some(&[]);
+    }
+    let allocator = allocator::get_allocator();
+    let buffer_ptr = unsafe { allocator.alloc(1, entry.len) }.ok()?;
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_ptr, entry.len) };
+    if entry.decompress_into(buffer).is_err() {
+        unsafe { allocator.dealloc(buffer_ptr, entry.len) }.ok()?;
+        return None;
+    }
+    Some(buffer)
+}
+
+/// An archive of named files in the format the build script writes
+#[derive(Clone, Copy, Debug)]
+pub struct Archive<'a> {
+    bytes: &'a [u8],
+    no_of_entries: usize
+}
+
+/// A file in an archive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub name: &'a str,
+    /// The bytes as they're stored in the archive
+    pub stored: &'a [u8],
+    /// The number of bytes once decompressed
+    pub len: usize,
+    pub compression: Compression
+}
+
+impl<'a> Archive<'a> {
+    /// Reads the archive in `bytes`, checking that every entry in it can be read
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        if bytes.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+            return Err("The archive doesn't start with the magic bytes");
+        }
+        let no_of_entries = read_u32(bytes, MAGIC.len()).ok_or("The archive's header is cut short")?;
+        let archive = Self { bytes, no_of_entries };
+        for idx in 0..no_of_entries {
+            archive.read_entry(idx)?;
+        }
+        Ok(archive)
+    }
+
+    pub fn len(&self) -> usize {
+        self.no_of_entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.no_of_entries == 0
+    }
+
+    /// The entry at `idx`, or None if there are only `idx` entries or less
+    pub fn entry(&self, idx: usize) -> Option<Entry<'a>> {
+        self.read_entry(idx).ok()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'a>> + '_ {
+        (0..self.no_of_entries).filter_map(|idx| self.entry(idx))
+    }
+
+    /// The entry of the file called `name` and its index
+    pub fn find(&self, name: &str) -> Option<(usize, Entry<'a>)> {
+        self.entries().enumerate().find(|(_, entry)| entry.name == name)
+    }
+
+    fn read_entry(&self, idx: usize) -> Result<Entry<'a>, &'static str> {
+        const CUT_SHORT: &str = "The archive is cut short";
+        let entry_start = idx.checked_mul(ENTRY_SIZE).and_then(|offset| offset.checked_add(HEADER_SIZE)).ok_or(CUT_SHORT)?;
+        let field = |n: usize| read_u32(self.bytes, entry_start + n * 4).ok_or(CUT_SHORT);
+        let (name_offset, name_len) = (field(0)?, field(1)?);
+        let (data_offset, stored_len) = (field(2)?, field(3)?);
+        let (len, compression) = (field(4)?, field(5)?);
+        let name = self.bytes.get(name_offset..name_offset + name_len).ok_or(CUT_SHORT)?;
+        let name = core::str::from_utf8(name).map_err(|_| "A name in the archive isn't UTF-8")?;
+        let stored = self.bytes.get(data_offset..data_offset + stored_len).ok_or(CUT_SHORT)?;
+        if data_offset % DATA_ALIGN != 0 {
+            return Err("A file in the archive isn't aligned");
+        }
+        let compression = Compression::from_u32(compression as u32)
+            .ok_or("A file in the archive is compressed in an unknown way")?;
+        if compression == Compression::None && stored_len != len {
+            return Err("An uncompressed file in the archive has the wrong length");
+        }
+        Ok(Entry { name, stored, len, compression })
+    }
+}
+
+impl<'a> Entry<'a> {
+    /// Decompresses the file into `dst`, which must be exactly `len` bytes long
+    pub fn decompress_into(&self, dst: &mut [u8]) -> Result<(), &'static str> {
+        if dst.len() != self.len {
+            return Err("The buffer isn't as long as the file");
+        }
+        match self.compression {
+            Compression::None => {
+                dst.copy_from_slice(self.stored);
+                Ok(())
+            }
+            Compression::PackBits => unpack_bits(self.stored, dst)
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<usize> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedded_archive() -> Archive<'static> {
+        Archive::parse(&ARCHIVE.0).unwrap()
+    }
+
+    #[test]
+    fn test_unpack_bits() {
+        let mut dst = [0; 8];
+        // 2 bytes as they are, 4 repeated, a skipped control byte and 2 more as they are
+        assert_eq!(unpack_bits(&[1, 7, 8, 253, 9, 128, 1, 1, 2], &mut dst), Ok(()));
+        assert_eq!(dst, [7, 8, 9, 9, 9, 9, 1, 2]);
+        assert!(unpack_bits(&[253, 9], &mut dst).is_err());
+        assert!(unpack_bits(&[129, 9], &mut dst).is_err());
+        assert!(unpack_bits(&[7, 1, 2], &mut dst).is_err());
+    }
+
+    #[test]
+    fn test_embedded_archive() {
+        let archive = embedded_archive();
+        assert_eq!(archive.len(), NO_OF_ASSETS);
+        let names: std::vec::Vec<&str> = archive.entries().map(|entry| entry.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+        assert!(names.contains(&"ball.bmp") && names.contains(&"drum.wav"));
+        assert_eq!(archive.find("missing.bmp"), None);
+        for entry in archive.entries() {
+            assert_eq!(entry.stored.as_ptr() as usize % DATA_ALIGN, 0);
+        }
+    }
+
+    #[test]
+    fn test_files_decompress_to_the_originals() {
+        let archive = embedded_archive();
+        for entry in archive.entries() {
+            let original = std::fs::read(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("data").join(entry.name)).unwrap();
+            let mut decompressed = std::vec![0; entry.len];
+            entry.decompress_into(&mut decompressed).unwrap();
+            assert_eq!(decompressed, original, "{} changed in the archive", entry.name);
+        }
+        // The bitmaps shrink enough to be compressed
+        assert!(archive.entries().any(|entry| entry.compression == Compression::PackBits));
+    }
+
+    #[test]
+    fn test_corrupt_archive() {
+        assert!(Archive::parse(b"BMB").is_err());
+        assert!(Archive::parse(b"ABCD\0\0\0\0").is_err());
+        assert!(Archive::parse(b"BMBA\0\0\0\0").unwrap().is_empty());
+        // An entry whose name is past the end
+        let mut bytes = std::vec::Vec::from(*b"BMBA\x01\0\0\0");
+        for field in [100u32, 4, 32, 0, 0, 0] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        assert!(Archive::parse(&bytes).is_err());
+        bytes[8] = 32;
+        bytes.extend_from_slice(b"name");
+        assert_eq!(Archive::parse(&bytes).unwrap().entry(0).unwrap().name, "name");
+        // An unknown compression
+        bytes[28] = 9;
+        assert!(Archive::parse(&bytes).is_err());
+    }
+}
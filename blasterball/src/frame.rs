@@ -0,0 +1,93 @@
+//! Updating the game at a fixed rate, however fast the machine is
+//!
+//! The game used to move everything by a whole step on every timer tick, so
+//! how fast the ball moved depended on how quickly each tick was handled.
+//! Now the game is updated `UPDATES_PER_SEC` times a second of real time,
+//! measured with the uptime. Every timer event runs the updates that have
+//! come due since the last one and then draws a single frame, with the ball
+//! drawn between its last two positions by how close the next update is.
+//!
+//! Speeds are still given in pixels per timer tick, so an update moves an
+//! object by a fraction of its speed and the leftover part of a pixel is
+//! carried over to the next update
+
+use machine::time::{self, TIMER_TICKS_PER_SEC};
+use physics::{Object, Point};
+use num::Integer;
+
+/// The number of times the game is updated in a second
+pub(crate) const UPDATES_PER_SEC: usize = 60;
+/// The most time caught up on in one frame, so the game doesn't
+/// jump ahead after something held the timer events up
+const MAX_CATCH_UP_MS: u64 = 100;
+/// `FrameScheduler::alpha` is a fraction of this
+pub(crate) const ALPHA_ONE: i32 = 256;
+
+/// Keeps track of when the game's updates are due
+pub(crate) struct FrameScheduler {
+    /// The uptime at the last frame
+    last_ms: u64,
+    /// The time since the last update in thousandths of an update,
+    /// which is milliseconds multiplied by `UPDATES_PER_SEC`
+    pending: u64
+}
+
+impl FrameScheduler {
+    pub(crate) fn new() -> Self {
+        Self { last_ms: time::uptime_ms(), pending: 0 }
+    }
+
+    /// Starts counting from now, leaving out the time the game was stopped
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// The number of updates that have come due since the last frame
+    pub(crate) fn updates_due(&mut self) -> usize {
+        let now = time::uptime_ms();
+        let elapsed = now.saturating_sub(self.last_ms).min(MAX_CATCH_UP_MS);
+        self.last_ms = now;
+        self.pending += elapsed * UPDATES_PER_SEC as u64;
+        let updates = self.pending / 1000;
+        self.pending %= 1000;
+        updates as usize
+    }
+
+    /// How far the time is between the last update and the next,
+    /// as a fraction of `ALPHA_ONE`
+    pub(crate) fn alpha(&self) -> i32 {
+        (self.pending * ALPHA_ONE as u64 / 1000) as i32
+    }
+}
+
+/// Moves an object by one update's worth of its speed at a time
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Motion {
+    /// The parts of a pixel the object hasn't been moved by yet,
+    /// in `UPDATES_PER_SEC`ths of a pixel
+    carry: (i32, i32)
+}
+
+impl Motion {
+    /// Moves `object` on by an update
+    ///
+    /// Returns the position the object was at before moving
+    pub(crate) fn step(&mut self, object: &mut Object, x_scale: usize, y_scale: usize) -> Point {
+        let per_update = UPDATES_PER_SEC as i32;
+        let dx = self.carry.0 + object.velocity.horizontal_component() as i32 * (x_scale * TIMER_TICKS_PER_SEC) as i32;
+        let dy = self.carry.1 + object.velocity.vertical_component() as i32 * (y_scale * TIMER_TICKS_PER_SEC) as i32;
+        self.carry = (dx.rem_euclid(per_update), dy.rem_euclid(per_update));
+        let old_pos = object.pos;
+        object.pos += Point(dx.div_euclid(per_update).as_i16(), dy.div_euclid(per_update).as_i16());
+        old_pos
+    }
+}
+
+/// The point `alpha` of the way from `from` to `to`, where `ALPHA_ONE` is all the way
+pub(crate) fn interpolate(from: Point, to: Point, alpha: i32) -> Point {
+    let diff = to - from;
+    from + Point(
+        (diff.x() as i32 * alpha / ALPHA_ONE).as_i16(),
+        (diff.y() as i32 * alpha / ALPHA_ONE).as_i16()
+    )
+}
@@ -4,37 +4,154 @@
 #![allow(unaligned_references)]
 
 use core::fmt::Write;
-use core::ops::Deref;
 use machine::keyboard::{KeyCode, KeyDirection};
-use sound::{WavFile, Sound, Sample, ActionOnEnd};
-use machine::cmos;
+use machine::gamepad::{self, GamepadState};
+use sound::{Sound, SoundHandle, ActionOnEnd};
+use machine::entropy;
+use machine::power;
+use machine::stats;
+use machine::settings;
+use machine::time::TIMER_TICKS_PER_SEC;
 use machine;
 use event_hook;
-use event_hook::{EventKind, Event, box_fn};
-use physics::{Point, Object, Velocity};
-use num::{Integer, Float};
+use event_hook::{EventKind, Event, HandlerOwner, HookOptions, HandlerResult, Priority, box_fn};
+use physics::{Point, Object, Velocity, Rectangle};
+use num::{Integer, Float, Rng};
 use sync::mutex::MutexGuard;
 use collections::vec::Vec;
 use collections::vec;
-use artist::{println, SCREEN_HEIGHT, SCREEN_WIDTH, Artist, Color, X_SCALE, Y_SCALE};
-use artist::bitmap::{Bitmap, ScaledBitmap, Transparency};
+use collections::arena::Arena;
+use collections::allocator::get_allocator;
+use artist::{ScreenInfo, SCREEN_HEIGHT, SCREEN_WIDTH, FONT_HEIGHT, FONT_WIDTH, Artist, Target, Color, X_SCALE, Y_SCALE};
+use artist::{TextFormat, Align, PresentMode, PERF_HUD_WIDTH, PERF_HUD_HEIGHT};
+use artist::font;
+use artist::bitmap::{BitmapAsset, ScaledBitmap, Transparency, NinePatch, PatchFill};
+use artist::sprite::{SpriteSheet, Animation};
+use assets::Assets;
 use artist;
+use frame::{FrameScheduler, Motion};
 
+mod frame;
 
-sound::sound!(MUSIC, RAW_MUSIC => "./assets/canon-in-d-major.wav", size => 7287938);
-sound::sound!(DRUM, RAW_DRUM => "./assets/drum.wav", size => 734028);
+/// The music played on the menus
+const MENU_MUSIC_PATH: &str = "canon-in-d-major.wav";
+/// The music played while the game is running
+const LEVEL_MUSIC_PATH: &str = "drum.wav";
+
+const BALL_BMP: BitmapFile = BitmapFile::new("ball.bmp").with_double("ball_2x.bmp");
+const PADDLE_BMP: BitmapFile = BitmapFile::new("paddle.bmp").with_double("paddle_2x.bmp");
+const BLUE_BLOCK_BMP: BitmapFile = BitmapFile::new("blue_block.bmp").with_double("blue_block_2x.bmp");
+const CYAN_BLOCK_BMP: BitmapFile = BitmapFile::new("cyan_block.bmp").with_double("cyan_block_2x.bmp");
+const GREEN_BLOCK_BMP: BitmapFile = BitmapFile::new("green_block.bmp").with_double("green_block_2x.bmp");
+const PINK_BLOCK_BMP: BitmapFile = BitmapFile::new("pink_block.bmp").with_double("pink_block_2x.bmp");
+const YELLOW_BLOCK_BMP: BitmapFile = BitmapFile::new("yellow_block.bmp").with_double("yellow_block_2x.bmp");
+/// The frames of a block breaking apart, side by side
+const BLOCK_BREAK_BMP: BitmapFile = BitmapFile::new("block_break.bmp");
+const BLOCK_BREAK_FRAMES: usize = 4;
+/// The frame that dialogs are drawn in
+const PANEL_BMP: BitmapFile = BitmapFile::new("panel.bmp");
+/// The size of the panel's borders, in the panel bitmap's pixels
+const PANEL_BORDER: usize = 4;
+/// The bitmaps the walls around the playfield are tiled with
+const PLAYFIELD_WALLS: WallBitmaps = WallBitmaps {
+    side: BitmapFile::new("wall_side.bmp"),
+    top: BitmapFile::new("wall_top.bmp")
+};
+
+/// The number of timer ticks a key has to be held before it starts repeating
+const KEY_REPEAT_DELAY_TICKS: usize = 4;
+/// The number of timer ticks between repeats of a held key,
+/// so a held arrow key moves the paddle on every tick
+const KEY_REPEAT_INTERVAL_TICKS: usize = 1;
+/// The number of seconds between descents of the block wall in pressure mode
+const PRESSURE_DESCENT_INTERVAL_SECS: usize = 15;
+/// The horizontal distance between the screen edges and the block wall
+const BLOCK_START_POS_X: usize = 15;
+/// The distance between the top of the screen and the block wall
+const BLOCK_START_POS_Y: usize = 10;
+
+/// The speed the ball is launched at
+const BALL_SPEED: usize = 5;
+/// The fastest the ball moves with the slow ball accessibility option
+const SLOW_BALL_SPEED: usize = 3;
+
+/// The number of times the boss has to be hit to be defeated
+const BOSS_HIT_POINTS: usize = 8;
+/// The number of updates the boss is drawn flashing after a hit
+const BOSS_FLASH_UPDATES: usize = frame::UPDATES_PER_SEC / 6;
+
+/// The points a block is worth before the combo multiplier
+const BLOCK_SCORE: usize = 10;
+/// The points a hit on the boss is worth before the combo multiplier
+const BOSS_HIT_SCORE: usize = 50;
+/// The highest the combo multiplier goes
+const MAX_COMBO_MULTIPLIER: usize = 8;
+/// The number of timer ticks a floating text stays up, fading as it goes
+const FLOATING_TEXT_TICKS: usize = TIMER_TICKS_PER_SEC;
+/// The number of timer ticks each frame of a breaking block is shown for
+const BLOCK_BREAK_TICKS_PER_FRAME: usize = 2;
+/// The most characters a floating text can have
+const FLOATING_TEXT_LEN: usize = 16;
+/// The number of frames a game fades in from black over
+const FADE_IN_FRAMES: usize = 30;
+/// The pixels the paddle moves in an update with the gamepad's stick pushed all the way
+const GAMEPAD_PADDLE_SPEED: usize = 4 * X_SCALE;
+/// The number of bytes set aside for the lists that only last an update
+const FRAME_ARENA_SIZE: usize = 1024;
+
+/// The text on the debug overlay, with space for the CPU load percentage
+const DEBUG_OVERLAY_TEMPLATE: &[u8; 8] = b"CPU    %";
+/// The height of each channel's bar in the VU meter under the performance HUD
+const VU_METER_BAR_HEIGHT: usize = 2;
+/// The width of the debug overlay, which is as wide as the artist's performance HUD
+const DEBUG_OVERLAY_WIDTH: usize = PERF_HUD_WIDTH;
+
+/// The owner of all the event handlers hooked by a running game
+const GAME_HOOK_OWNER: HandlerOwner = "game";
+
+/// The id of the custom event sent when the ball goes off the screen, with the score
+const BALL_LOST: u32 = 0;
+/// The id of the custom event sent when the ball destroys a block,
+/// with the number of blocks left
+const BLOCK_DESTROYED: u32 = 1;
+
+/// Every bitmap the game draws, which `load_assets` checks can be read
+const BITMAP_ASSETS: [BitmapFile; 11] = [
+    BALL_BMP, PADDLE_BMP, BLUE_BLOCK_BMP, CYAN_BLOCK_BMP, GREEN_BLOCK_BMP,
+    PINK_BLOCK_BMP, YELLOW_BLOCK_BMP, BLOCK_BREAK_BMP, PANEL_BMP, PLAYFIELD_WALLS.side, PLAYFIELD_WALLS.top
+];
+
+/// Checks that the music can be played and every bitmap can be read,
+/// so the game doesn't fail on its assets once it has started
+///
+/// Must be called before `game_entry_point`
+pub fn load_assets() -> Result<(), &'static str> {
+    sound::set_asset_lookup(Assets::get);
+    sound::load_streamed(MENU_MUSIC_PATH)?;
+    sound::load_streamed(LEVEL_MUSIC_PATH)?;
+    let screen = artist::screen_info();
+    for asset in BITMAP_ASSETS {
+        asset.load(screen, Transparency::None)?;
+    }
+    Ok(())
+}
 
 pub fn game_entry_point() -> ! {
-    println!("Loading...");
-    sound::play_sound(MUSIC.deref(), ActionOnEnd::Replay);
+    // The paddle moves as smoothly on every machine, whatever its typematic rate
+    event_hook::enable_key_repeat(KEY_REPEAT_DELAY_TICKS, KEY_REPEAT_INTERVAL_TICKS);
+    // The game draws in its handlers, which shouldn't keep interrupts disabled,
+    // so the events are handled in the loops below instead of in the interrupt handlers
+    event_hook::enable_deferred_events();
+    let menu_music = sound::load_streamed(MENU_MUSIC_PATH).expect("Failed to load the menu music");
+    let mut menu_music_handle = sound::play_sound(&menu_music, ActionOnEnd::Replay).ok();
     
     loop {
-        let mut game = Game::init();
+        let mut game = Game::init(menu_music_handle);
         // The artist is locked by the game at this point
         // Do not use any print macro here until the game hase been dropped
         game.main_loop();
         core::mem::drop(game);
-        sound::play_sound(MUSIC.deref(), ActionOnEnd::Replay);
+        menu_music_handle = sound::play_sound(&menu_music, ActionOnEnd::Replay).ok();
         let mut restart = false;
         let restart_exit_hook = event_hook::hook_event(EventKind::Keyboard, box_fn!(|event| {
             if let Event::Keyboard(keycode, direction, _modifiers) = event {
@@ -49,99 +166,661 @@ pub fn game_entry_point() -> ! {
             }
         }));
         loop {
+            event_hook::poll();
             if restart {
                 event_hook::unhook_event(restart_exit_hook, EventKind::Keyboard);
                 break;
             }
+            stats::idle();
+        }
+    }
+}
+
+/// An action that has to be confirmed by the player before it's performed
+#[derive(Clone, Copy)]
+enum Confirmation {
+    /// Restarting the computer, requested with Ctrl+Alt+Del
+    Restart,
+    /// Shutting down the computer, requested with the power button
+    Quit
+}
+
+impl Confirmation {
+    fn question(&self) -> &'static str {
+        match self {
+            Confirmation::Restart => "Restart the computer?\n",
+            Confirmation::Quit => "Really quit and shut down the computer?\n"
+        }
+    }
+
+    fn prompt(&self) -> &'static str {
+        match self {
+            Confirmation::Restart => "Press y to restart or n to go back\n",
+            Confirmation::Quit => "Press y to shut down or n to go back\n"
+        }
+    }
+}
+
+/// Options that make the game easier to see and play,
+/// kept in the settings so they survive reboots
+#[derive(Clone, Copy)]
+struct Accessibility {
+    /// The paddle is twice as wide
+    wide_paddle: bool,
+    /// The ball never moves faster than `SLOW_BALL_SPEED`
+    slow_ball: bool,
+    /// Everything is drawn in bright, flat colors on a black background
+    high_contrast: bool
+}
+
+impl Accessibility {
+    const WIDE_PADDLE: u8 = 1 << 0;
+    const SLOW_BALL: u8 = 1 << 1;
+    const HIGH_CONTRAST: u8 = 1 << 2;
+
+    /// Reads the options from the settings, or returns the defaults
+    /// if they have never been saved
+    fn load() -> Self {
+        let flags = settings::load().unwrap_or(0);
+        Self {
+            wide_paddle: flags & Self::WIDE_PADDLE != 0,
+            slow_ball: flags & Self::SLOW_BALL != 0,
+            high_contrast: flags & Self::HIGH_CONTRAST != 0
+        }
+    }
+
+    fn save(&self) {
+        let mut flags = 0;
+        if self.wide_paddle {
+            flags |= Self::WIDE_PADDLE;
+        }
+        if self.slow_ball {
+            flags |= Self::SLOW_BALL;
+        }
+        if self.high_contrast {
+            flags |= Self::HIGH_CONTRAST;
+        }
+        // The options still apply to this session if they can't be saved
+        let _ = settings::save(flags);
+    }
+
+    fn ball_speed(&self) -> usize {
+        if self.slow_ball {
+            BALL_SPEED.min(SLOW_BALL_SPEED)
+        } else {
+            BALL_SPEED
+        }
+    }
+
+    fn background(&self) -> Color {
+        if self.high_contrast {
+            Color::new(Color::BLACK)
+        } else {
+            Color::new(Color::PURPLE)
+        }
+    }
+
+    /// The lines of the accessibility dialog
+    fn dialog_lines(&self) -> [&'static str; 5] {
+        [
+            "Accessibility",
+            if self.wide_paddle { "1 Wide paddle: on" } else { "1 Wide paddle: off" },
+            if self.slow_ball { "2 Slow ball: on" } else { "2 Slow ball: off" },
+            if self.high_contrast { "3 High contrast: on" } else { "3 High contrast: off" },
+            "Press a to go back"
+        ]
+    }
+}
+
+/// The color the block bitmap at `i` in `Game::block_bmps` is drawn with in high contrast
+fn high_contrast_block_color(i: usize) -> Color {
+    match i {
+        0 => Color::new(Color::LIGHT_CYAN),
+        1 => Color::new(Color::PINK),
+        2 => Color::new(Color::LIGHT_GREEN),
+        3 => Color::new(Color::LIGHT_RED),
+        _ => Color::new(Color::YELLOW)
+    }
+}
+
+/// Loads the bitmaps of the blocks, in the order their colors appear in the wall
+fn load_block_bmps(screen: ScreenInfo, accessibility: Accessibility) -> [ScaledBitmap; 5] {
+    let mut i = 0;
+    [BLUE_BLOCK_BMP, PINK_BLOCK_BMP, GREEN_BLOCK_BMP, CYAN_BLOCK_BMP, YELLOW_BLOCK_BMP].map(|asset| {
+        let bmp = asset.load(screen, Transparency::None)
+            .expect("Failed to read the bitmap from the given source");
+        let color = high_contrast_block_color(i);
+        i += 1;
+        if accessibility.high_contrast { bmp.silhouette(color) } else { bmp }
+    })
+}
+
+fn load_paddle_bmp(screen: ScreenInfo, accessibility: Accessibility) -> ScaledBitmap {
+    let mut bmp = PADDLE_BMP.load(screen, Transparency::Black)
+        .expect("Failed to read the bitmap from the given source");
+    if accessibility.wide_paddle {
+        bmp = bmp.stretched(2, 1);
+    }
+    if accessibility.high_contrast {
+        bmp = bmp.silhouette(Color::new(Color::WHITE));
+    }
+    bmp
+}
+
+fn load_ball_bmp(screen: ScreenInfo, accessibility: Accessibility) -> ScaledBitmap {
+    let bmp = BALL_BMP.load(screen, Transparency::Black)
+        .expect("Failed to read the bitmap from the given source");
+    if accessibility.high_contrast {
+        bmp.silhouette(Color::new(Color::WHITE))
+    } else {
+        bmp
+    }
+}
+
+/// A bitmap in the asset archive, by the names of its variants
+#[derive(Clone, Copy)]
+struct BitmapFile {
+    /// The variant drawn for a 320x200 screen
+    base: &'static str,
+    /// The variant drawn at twice the resolution of `base`
+    double: Option<&'static str>
+}
+
+impl BitmapFile {
+    const fn new(base: &'static str) -> Self {
+        Self { base, double: None }
+    }
+
+    const fn with_double(self, double: &'static str) -> Self {
+        Self { double: Some(double), ..self }
+    }
+
+    /// Looks the variants up in the asset archive and loads the one that best fits `screen`
+    fn load(&self, screen: ScreenInfo, transparency: Transparency) -> Result<ScaledBitmap, &'static str> {
+        let mut asset = BitmapAsset::new(Assets::get(self.base).ok_or("A bitmap is missing from the assets")?);
+        if let Some(double) = self.double {
+            asset = asset.with_double(Assets::get(double).ok_or("A bitmap is missing from the assets")?);
+        }
+        asset.load(screen, transparency)
+    }
+}
+
+/// The bitmaps a playfield's walls are tiled with
+#[derive(Clone, Copy)]
+struct WallBitmaps {
+    /// Tiled down the left and right walls
+    side: BitmapFile,
+    /// Tiled along the top wall, between the side walls
+    top: BitmapFile
+}
+
+/// The walls on the left, right and top of the playfield
+///
+/// The ball bounces off the walls and the paddle stops at them, so their
+/// rectangles are worked out from the size of the bitmaps they're tiled with
+struct Playfield {
+    side_tile: ScaledBitmap,
+    top_tile: ScaledBitmap
+}
+
+impl Playfield {
+    fn load(walls: WallBitmaps, screen: ScreenInfo, accessibility: Accessibility) -> Self {
+        let load_tile = |asset: BitmapFile| {
+            let bmp = asset.load(screen, Transparency::None)
+                .expect("Failed to read the bitmap from the given source");
+            if accessibility.high_contrast { bmp.silhouette(Color::new(Color::LIGHT_GRAY)) } else { bmp }
+        };
+        Self {
+            side_tile: load_tile(walls.side),
+            top_tile: load_tile(walls.top)
+        }
+    }
+
+    fn left_wall(&self) -> Rectangle {
+        Rectangle { top_left: Point(0, 0), width: self.side_tile.width(), height: SCREEN_HEIGHT }
+    }
+
+    fn right_wall(&self) -> Rectangle {
+        Rectangle {
+            top_left: Point((SCREEN_WIDTH - self.side_tile.width()).as_i16(), 0),
+            width: self.side_tile.width(),
+            height: SCREEN_HEIGHT
         }
     }
+
+    fn top_wall(&self) -> Rectangle {
+        Rectangle {
+            top_left: Point(self.side_tile.width().as_i16(), 0),
+            width: SCREEN_WIDTH - 2 * self.side_tile.width(),
+            height: self.top_tile.height()
+        }
+    }
+
+    fn draw_in_double_buffer(&self, artist: &mut Artist) {
+        // The right wall is drawn last, so it covers the top wall's last tile
+        // when the top wall isn't a whole number of tiles long
+        draw_tiled_in_double_buffer(artist, &self.side_tile, self.left_wall());
+        draw_tiled_in_double_buffer(artist, &self.top_tile, self.top_wall());
+        draw_tiled_in_double_buffer(artist, &self.side_tile, self.right_wall());
+    }
+}
+
+/// Covers `area` with copies of `tile`, starting from its top left corner
+///
+/// The last row and column of tiles may stick out of `area`
+fn draw_tiled_in_double_buffer(artist: &mut Artist, tile: &ScaledBitmap, area: Rectangle) {
+    for y in (area.top_left.y()..area.bottom()).step_by(tile.height()) {
+        for x in (area.top_left.x()..area.right()).step_by(tile.width()) {
+            artist.draw_scaled_bitmap_in_double_buffer(Point(x, y), tile);
+        }
+    }
+}
+
+/// Creates the paddle at its starting position, in the middle of the bottom of the screen
+fn new_paddle(paddle_bmp: ScaledBitmap) -> Character {
+    Character::new(Object {
+            pos: Point(
+                (SCREEN_WIDTH / 2 - paddle_bmp.width() / 2).as_i16(),
+                (SCREEN_HEIGHT - 20 - paddle_bmp.height()).as_i16()
+            ),
+            velocity: Velocity { direction: 0, speed: 0 }
+        }, paddle_bmp
+    )
+}
+
+/// Creates the ball at its starting position, resting on the paddle
+fn new_ball(ball_bmp: ScaledBitmap, paddle_char: &Character) -> Character {
+    Character::new(Object {
+            pos: Point(
+                (SCREEN_WIDTH / 2 - ball_bmp.width() / 2).as_i16(),
+                paddle_char.object.pos.y() - ball_bmp.height().as_i16()
+            ),
+            velocity: Velocity { direction: 0, speed: 0 }
+        }, ball_bmp
+    )
 }
 
 struct Game {
+    /// The walls around the playfield
+    playfield: Playfield,
     ball_char: Character,
     paddle_char: Character,
     has_started: bool,
     paused: bool,
     shutdown_attempted: bool,
+    /// Ctrl+Alt+Del or the power button was pressed and the player
+    /// hasn't confirmed or cancelled yet
+    pending_confirmation: Option<Confirmation>,
     paused_msg_has_been_drawn: bool,
+    /// In pressure mode, the block wall moves down a row every
+    /// `PRESSURE_DESCENT_INTERVAL_SECS` seconds and a new row appears at the top
+    pressure_mode: bool,
+    /// The number of timer ticks since the block wall last moved down
+    ticks_since_descent: usize,
+    accessibility: Accessibility,
+    /// Whether the accessibility options are being shown, instead of the start screen
+    accessibility_open: bool,
     background: Color,
+    /// The bitmaps of the blocks, in the order their colors appear in the wall
+    block_bmps: [ScaledBitmap; 5],
+    /// The index in `block_bmps` of the first block in the next new row
+    next_block_bmp_idx: usize,
     blocks: Vec<'static, Character>,
+    /// The frames of a block breaking apart
+    block_break_sheet: SpriteSheet,
+    /// The blocks that have been hit and are still breaking apart
+    block_breaks: Vec<'static, BlockBreak>,
+    /// Holds the lists that only last an update, like the blocks the ball is
+    /// touching, so they don't fragment the heap
+    frame_arena: Arena<'static>,
+    /// An off-screen target the block wall is kept drawn in, so it's composed
+    /// onto the double buffer in one copy instead of block by block every frame.
+    /// None if the target couldn't be created
+    wall_target: Option<Target>,
+    /// The boss that appears after all the blocks have been destroyed
+    boss: Option<Boss>,
+    boss_defeated: bool,
+    /// Seeded differently on every boot, so no two games start the same way
+    rng: Rng,
+    /// The frame that dialogs are drawn in
+    panel: NinePatch,
+    /// The music played while the game is running
+    music: Sound,
+    /// The handle of the music that's playing, whether it's the menu's or the game's
+    music_handle: Option<SoundHandle>,
+    score: usize,
+    /// The number of blocks broken and boss hits since the ball last touched
+    /// the paddle, which every hit's points are multiplied by, up to `MAX_COMBO_MULTIPLIER`
+    combo: usize,
+    /// The combo counter, which rises from where the last hit was and fades out
+    combo_text: Option<FloatingText>,
+    /// Decides how many updates are run on each timer event
+    scheduler: FrameScheduler,
+    ball_motion: Motion,
+    /// Where the ball was before the last update, which it's drawn
+    /// between its current position and
+    ball_prev_pos: Point,
+    /// Where the ball is drawn in the double buffer
+    ball_drawn_pos: Point,
+    /// The gamepad's state as of its last event, since the paddle
+    /// keeps moving while the stick is held
+    gamepad: GamepadState,
     artist: MutexGuard<'static, Artist>
 }
 
+/// A block breaking apart where it was hit
+#[derive(Clone)]
+struct BlockBreak {
+    pos: Point,
+    animation: Animation
+}
+
+/// A short piece of text drawn over the game that rises and fades out
+struct FloatingText {
+    text: [u8; FLOATING_TEXT_LEN],
+    len: usize,
+    pos: Point,
+    ticks_left: usize
+}
+
+impl FloatingText {
+    /// Creates a text out of `parts` at `pos`, moved left if it would go
+    /// past the right edge of the screen
+    ///
+    /// Whatever doesn't fit in `FLOATING_TEXT_LEN` characters is left out
+    fn new(parts: &[&str], pos: Point) -> Self {
+        let mut text = [b' '; FLOATING_TEXT_LEN];
+        let mut len = 0;
+        for byte in parts.iter().flat_map(|part| part.bytes()).take(FLOATING_TEXT_LEN) {
+            text[len] = byte;
+            len += 1;
+        }
+        let max_x = (SCREEN_WIDTH - len * FONT_WIDTH * X_SCALE).as_i16();
+        Self {
+            text,
+            len,
+            pos: Point(pos.x().min(max_x).max(0), pos.y().max(0)),
+            ticks_left: FLOATING_TEXT_TICKS
+        }
+    }
+
+    fn text(&self) -> &str {
+        core::str::from_utf8(&self.text[..self.len]).unwrap()
+    }
+
+    fn width(&self) -> usize {
+        self.len * FONT_WIDTH * X_SCALE
+    }
+
+    fn height(&self) -> usize {
+        FONT_HEIGHT * Y_SCALE
+    }
+
+    /// Moves the text up for another tick
+    ///
+    /// Returns false once the text has faded out
+    fn update(&mut self) -> bool {
+        self.ticks_left = self.ticks_left.saturating_sub(1);
+        self.pos = Point(self.pos.x(), (self.pos.y() - Y_SCALE.as_i16()).max(0));
+        self.ticks_left > 0
+    }
+
+    /// The color the text is drawn in, which gets darker as the text fades out
+    fn color(&self) -> Color {
+        match self.ticks_left * 3 / FLOATING_TEXT_TICKS {
+            0 => Color::new(Color::DARK_GRAY),
+            1 => Color::new(Color::LIGHT_GRAY),
+            _ => Color::new(Color::WHITE)
+        }
+    }
+}
+
+/// A moving target that takes several hits to destroy
+struct Boss {
+    character: Character,
+    /// What's drawn instead of the character's bitmap right after a hit
+    flash_repr: ScaledBitmap,
+    hit_points: usize,
+    /// The number of updates left to draw the boss flashing
+    flash_updates_left: usize,
+    motion: Motion
+}
+
+impl Boss {
+    /// Creates a boss out of a stretched block at the top of the screen
+    fn new(block_bmp: &ScaledBitmap) -> Self {
+        let repr = block_bmp.stretched(3, 2);
+        let flash_repr = repr.silhouette(Color::new(Color::WHITE));
+        let character = Character::new(Object {
+            pos: Point(
+                (SCREEN_WIDTH / 2 - repr.width() / 2).as_i16(),
+                (BLOCK_START_POS_Y + repr.height()).as_i16()
+            ),
+            velocity: Velocity { direction: 0, speed: 2 }
+        }, repr);
+        Self {
+            character,
+            flash_repr,
+            hit_points: BOSS_HIT_POINTS,
+            flash_updates_left: 0,
+            motion: Motion::default()
+        }
+    }
+
+    /// Moves the boss from side to side by an update, turning around at the walls
+    ///
+    /// Returns the position the boss was at before moving
+    fn update_pos(&mut self) -> Point {
+        let old_pos = self.motion.step(&mut self.character.object, X_SCALE, Y_SCALE);
+        let x = self.character.object.pos.x();
+        let max_x = (SCREEN_WIDTH - BLOCK_START_POS_X - self.character.repr.width()).as_i16();
+        if x <= BLOCK_START_POS_X.as_i16() || x >= max_x {
+            self.character.object.pos = Point(
+                x.max(BLOCK_START_POS_X.as_i16()).min(max_x),
+                self.character.object.pos.y()
+            );
+            self.character.object.velocity.reflect_about_y_axis();
+        }
+        if self.flash_updates_left > 0 {
+            self.flash_updates_left -= 1;
+        }
+        old_pos
+    }
+
+    /// Takes a hit point from the boss and makes it flash
+    ///
+    /// Every hit makes the boss a little faster
+    fn hit(&mut self) {
+        self.hit_points -= 1;
+        self.flash_updates_left = BOSS_FLASH_UPDATES;
+        self.character.object.velocity.speed += 1;
+    }
+
+    fn is_defeated(&self) -> bool {
+        self.hit_points == 0
+    }
+
+    /// The bitmap the boss should be drawn with at the moment
+    fn current_repr(&self) -> &ScaledBitmap {
+        if self.flash_updates_left > 0 {
+            &self.flash_repr
+        } else {
+            &self.character.repr
+        }
+    }
+}
+
 impl Game {
-    fn init() -> Self {
-        let ball_bmp_bytes = include_bytes!("./assets/ball.bmp");
-        let ball_bmp = Bitmap::from(ball_bmp_bytes, Transparency::Black)
+    /// Sets a new game up while the menu music with `menu_music_handle` plays
+    fn init(menu_music_handle: Option<SoundHandle>) -> Self {
+        let screen = artist::screen_info();
+        let accessibility = Accessibility::load();
+        let block_bmps = load_block_bmps(screen, accessibility);
+        let (blocks, next_block_bmp_idx) = Self::generate_blocks(&block_bmps);
+        let block_break_bmp = BLOCK_BREAK_BMP.load(screen, Transparency::Black)
             .expect("Failed to read the bitmap from the given source");
-        let paddle_bmp_bytes = include_bytes!("./assets/paddle.bmp");
-        let paddle_bmp = Bitmap::from(paddle_bmp_bytes, Transparency::Black)
+        let block_break_sheet = SpriteSheet::new(&block_break_bmp, BLOCK_BREAK_FRAMES, 1).unwrap();
+        let panel_bmp = PANEL_BMP.load(screen, Transparency::Black)
             .expect("Failed to read the bitmap from the given source");
-        let paddle_char = Character::new(Object {
-                pos: Point(
-                    (SCREEN_WIDTH / 2 - paddle_bmp.scaled_width() / 2).as_i16(),
-                    (SCREEN_HEIGHT - 20 - paddle_bmp.scaled_height()).as_i16()
-                ),
-                velocity: Velocity { direction: 0, speed: 0 }
-            }, paddle_bmp.convert_to_scaled_bitmap()
-        );
-        let ball_char = Character::new(Object {
-                pos: Point(
-                    (SCREEN_WIDTH / 2 - ball_bmp.width() / 2).as_i16(),
-                    paddle_char.object.pos.y() - ball_bmp.scaled_height().as_i16()
-                ),
-                velocity: Velocity { direction: 0, speed: 0 }
-            }, ball_bmp.convert_to_scaled_bitmap()
-        );
-        Self {
+        let panel = NinePatch::new(
+            panel_bmp,
+            PANEL_BORDER * screen.x_scale(),
+            PANEL_BORDER * screen.y_scale(),
+            PANEL_BORDER * screen.x_scale(),
+            PANEL_BORDER * screen.y_scale(),
+            PatchFill::Tile
+        ).unwrap();
+        // The music is cached after the first game, so only the first game reads the file
+        let music = sound::load_streamed(LEVEL_MUSIC_PATH).expect("Failed to load the level music");
+        let paddle_char = new_paddle(load_paddle_bmp(screen, accessibility));
+        let ball_char = new_ball(load_ball_bmp(screen, accessibility), &paddle_char);
+        let playfield = Playfield::load(PLAYFIELD_WALLS, screen, accessibility);
+        let mut artist = artist::get_artist().lock();
+        let wall_target = artist.create_target().ok();
+        // Frames are put on the screen in the retrace by the main loop, so the
+        // handlers drawing them don't wait. Without the heap space, they tear instead
+        artist.set_present_mode(PresentMode::TripleBuffered).ok();
+        let ball_pos = ball_char.object.pos;
+        let mut game = Self {
+            playfield,
             ball_char,
             paddle_char,
             has_started: false,
             paused: false,
             shutdown_attempted: false,
+            pending_confirmation: None,
             paused_msg_has_been_drawn: false,
-            background: Color::new(Color::PURPLE),
-            blocks: Self::generate_blocks(),
-            artist: artist::get_artist().lock()
-        }
+            pressure_mode: false,
+            ticks_since_descent: 0,
+            accessibility,
+            accessibility_open: false,
+            background: accessibility.background(),
+            next_block_bmp_idx,
+            blocks,
+            block_break_sheet,
+            block_breaks: vec!(item_type => BlockBreak, capacity => 4),
+            frame_arena: Arena::with_capacity(FRAME_ARENA_SIZE, get_allocator()),
+            wall_target,
+            block_bmps,
+            boss: None,
+            boss_defeated: false,
+            rng: Rng::new(entropy::get_u64()),
+            panel,
+            music,
+            music_handle: menu_music_handle,
+            score: 0,
+            combo: 0,
+            combo_text: None,
+            scheduler: FrameScheduler::new(),
+            ball_motion: Motion::default(),
+            ball_prev_pos: ball_pos,
+            ball_drawn_pos: ball_pos,
+            gamepad: GamepadState::default(),
+            artist
+        };
+        game.redraw_wall_target();
+        game
     }
 
     fn main_loop(&mut self) {
         let mut ended = false;
-        let game_hook = event_hook::hook_event(EventKind::Keyboard, box_fn!(|event| {
+        // While a confirmation is pending, it gets the keys before the game and keeps them
+        let confirmation_hook_options = HookOptions {
+            owner: Some(GAME_HOOK_OWNER),
+            priority: Priority::High,
+            ..HookOptions::default()
+        };
+        event_hook::hook_event_with_options(EventKind::Keyboard, confirmation_hook_options, box_fn!(|event| {
+            if self.pending_confirmation.is_none() {
+                return HandlerResult::Continue;
+            }
+            if let Event::Keyboard(keycode, direction, _modifiers) = event {
+                if direction == KeyDirection::Down {
+                    match keycode {
+                        KeyCode::Y => match self.pending_confirmation.take().unwrap() {
+                            Confirmation::Restart => power::reboot(),
+                            Confirmation::Quit => {
+                                let _ = unsafe { power::shutdown() };
+                                // Still running, so the shutdown failed
+                                self.shutdown_attempted = true;
+                                self.pause();
+                            }
+                        }
+                        KeyCode::N | KeyCode::Escape => {
+                            self.pending_confirmation = None;
+                            self.paused_msg_has_been_drawn = false;
+                            self.scheduler.reset();
+                            self.draw_game_in_double_buffer();
+                            self.artist.draw_on_screen_from_double_buffer();
+                        }
+                        _ => ()
+                    };
+                }
+            }
+            HandlerResult::Consume
+        }));
+        event_hook::hook_event_with_owner(EventKind::Keyboard, GAME_HOOK_OWNER, box_fn!(|event| {
             if let Event::Keyboard(keycode, direction, _modifiers) = event {
                 if direction == KeyDirection::Down {
                     match keycode {
                         KeyCode::ArrowRight => {
                             if self.has_started && direction == KeyDirection::Down {
-                                if !paddle_collided_with_right_wall(&self.paddle_char) {
+                                if !paddle_collided_with_right_wall(&self.paddle_char, &self.playfield) {
                                     self.move_paddle_in_double_buffer(PaddleDirection::Right);
                                 }
                             }
                         }
                         KeyCode::ArrowLeft => {
                             if self.has_started && direction == KeyDirection::Down {
-                                if !paddle_collided_with_left_wall(&self.paddle_char) {
+                                if !paddle_collided_with_left_wall(&self.paddle_char, &self.playfield) {
                                     self.move_paddle_in_double_buffer(PaddleDirection::Left);
                                 }
                             }
                         }
-                        KeyCode::Enter => {
+                        KeyCode::F3 => {
+                            if direction == KeyDirection::Down {
+                                // The artist keeps whether the overlay is shown,
+                                // so it stays up from one game to the next
+                                let visible = !self.artist.perf_hud_is_visible();
+                                self.artist.set_perf_hud_visible(visible);
+                                if !visible {
+                                    self.erase_debug_overlay_from_double_buffer();
+                                }
+                            }
+                        }
+                        KeyCode::P => {
+                            if !self.has_started && !self.accessibility_open {
+                                self.pressure_mode = !self.pressure_mode;
+                            }
+                        }
+                        KeyCode::A => {
                             if !self.has_started {
-                                self.ball_char.object.velocity.direction = self.generate_direction();
-                                self.ball_char.object.velocity.speed = 5;
-                                self.has_started = true;
-                                sound::play_sound(DRUM.deref(), ActionOnEnd::Replay);
-                            } else if self.paused {
-                                self.paused = false;
-                                self.paused_msg_has_been_drawn = false;
-                                sound::play_sound(DRUM.deref(), ActionOnEnd::Replay);
+                                self.accessibility_open = !self.accessibility_open;
+                                if self.accessibility_open {
+                                    self.draw_dialog(&self.accessibility.dialog_lines());
+                                } else {
+                                    self.artist.draw_background_in_double_buffer(&self.background);
+                                    self.draw_game_in_double_buffer();
+                                    self.artist.draw_on_screen_from_double_buffer();
+                                }
                             }
                         }
+                        KeyCode::One | KeyCode::Two | KeyCode::Three => {
+                            if self.accessibility_open {
+                                match keycode {
+                                    KeyCode::One => self.accessibility.wide_paddle = !self.accessibility.wide_paddle,
+                                    KeyCode::Two => self.accessibility.slow_ball = !self.accessibility.slow_ball,
+                                    _ => self.accessibility.high_contrast = !self.accessibility.high_contrast
+                                }
+                                self.accessibility.save();
+                                self.apply_accessibility();
+                                self.draw_dialog(&self.accessibility.dialog_lines());
+                            }
+                        }
+                        KeyCode::Enter => self.start_or_resume(),
                         KeyCode::Escape => {
-                            if self.has_started {
-                                self.paused = true;
-                                sound::play_sound(MUSIC.deref(), ActionOnEnd::Replay);
+                            if self.has_started && !self.paused {
+                                self.pause();
                             }
                         }
                         _ => ()
@@ -149,14 +828,71 @@ impl Game {
                 }
             }
         }));
+        // The mouse moves the paddle across by as much as it's moved, in the game's pixels
+        event_hook::hook_event_with_owner(EventKind::Mouse, GAME_HOOK_OWNER, box_fn!(|event| {
+            if let Event::Mouse(dx, _dy, _buttons) = event {
+                if self.has_started && !self.paused && self.pending_confirmation.is_none() && dx != 0 {
+                    self.move_paddle_by_in_double_buffer(dx.saturating_mul(X_SCALE.as_i16()));
+                }
+            }
+        }));
+        // The gamepad's A button works like enter
+        event_hook::hook_event_with_owner(EventKind::Gamepad, GAME_HOOK_OWNER, box_fn!(|event| {
+            if let Event::Gamepad(state) = event {
+                if state.buttons.a && !self.gamepad.buttons.a && self.pending_confirmation.is_none() {
+                    self.start_or_resume();
+                }
+                self.gamepad = state;
+            }
+        }));
+        event_hook::hook_event_with_owner(EventKind::SystemReset, GAME_HOOK_OWNER, box_fn!(|_| {
+            self.pending_confirmation = Some(Confirmation::Restart);
+            self.paused_msg_has_been_drawn = false;
+        }));
+        event_hook::hook_event_with_owner(EventKind::PowerButton, GAME_HOOK_OWNER, box_fn!(|_| {
+            self.pending_confirmation = Some(Confirmation::Quit);
+            self.paused_msg_has_been_drawn = false;
+        }));
+        event_hook::hook_event_with_owner(EventKind::Custom(BALL_LOST), GAME_HOOK_OWNER, box_fn!(|_| {
+            self.artist.write_str("Game over\n").unwrap();
+            self.artist.write_str("Press y to play again\n").unwrap();
+            ended = true;
+        }));
+        // The boss comes out once the last block is gone
+        event_hook::hook_event_with_owner(EventKind::Custom(BLOCK_DESTROYED), GAME_HOOK_OWNER, box_fn!(|event| {
+            if let Event::Custom(_, blocks_left) = event {
+                if blocks_left == 0 && !self.boss_defeated && self.boss.is_none() {
+                    self.boss = Some(Boss::new(&self.block_bmps[4]));
+                }
+            }
+        }));
+        // Drawn while the screen is black, so the game fades in all at once
+        self.artist.set_brightness(0);
         self.artist.draw_background_in_double_buffer(&self.background);
         self.draw_game_in_double_buffer();
-        self.artist.draw_on_screen_from_double_buffer();
+        self.artist.fade_from_black(FADE_IN_FRAMES);
         self.artist.reset_writing_pos();
         
-        let main_loop_hook = event_hook::hook_event(EventKind::Timer, box_fn!(|_| {
+        event_hook::hook_event_with_owner(EventKind::Timer, GAME_HOOK_OWNER, box_fn!(|_| {
+            if let Some(confirmation) = self.pending_confirmation {
+                if !self.paused_msg_has_been_drawn {
+                    self.draw_game_in_double_buffer();
+                    self.draw_dialog(&[confirmation.question(), confirmation.prompt()]);
+                    self.paused_msg_has_been_drawn = true;
+                }
+                return;
+            }
+            if self.accessibility_open {
+                return;
+            }
             if !self.has_started && !self.paused {
                 self.artist.write_str("Press enter to start\n").unwrap();
+                if self.pressure_mode {
+                    self.artist.write_str("Pressure mode: on (p to change)\n").unwrap();
+                } else {
+                    self.artist.write_str("Pressure mode: off (p to change)\n").unwrap();
+                }
+                self.artist.write_str("Accessibility (a to change)\n").unwrap();
                 self.artist.reset_writing_pos();
                 return;
             }
@@ -167,121 +903,439 @@ impl Game {
                 } else {
                     if !self.paused_msg_has_been_drawn {
                         self.draw_game_in_double_buffer();
-                        self.artist.draw_on_screen_from_double_buffer();
-                        self.artist.write_str("Paused\n").unwrap();
-                        self.artist.write_str("Press enter to continue\n").unwrap();
-                        self.artist.reset_writing_pos();
+                        self.draw_dialog(&["Paused", "Press enter to continue"]);
                         self.paused_msg_has_been_drawn = true
                     }
                 }
                 return;
             }
-            if self.blocks.len() == 0 {
+            if self.pressure_mode && self.boss.is_none() {
+                self.ticks_since_descent += 1;
+                if self.ticks_since_descent == PRESSURE_DESCENT_INTERVAL_SECS * TIMER_TICKS_PER_SEC {
+                    self.ticks_since_descent = 0;
+                    self.lower_block_wall();
+                    if self.block_wall_reached_paddle() {
+                        self.draw_game_in_double_buffer();
+                        self.artist.draw_on_screen_from_double_buffer();
+                        self.artist.write_str("Game over\n").unwrap();
+                        self.artist.write_str("Press y to play again\n").unwrap();
+                        self.artist.reset_writing_pos();
+                        ended = true;
+                        return;
+                    }
+                }
+            }
+            if self.blocks.len() == 0 && self.boss_defeated {
                 self.artist.write_str("You win\n").unwrap();
                 self.artist.write_str("Press y to play again\n").unwrap();
                 self.artist.reset_writing_pos();
                 ended = true;
                 return;
             }
-            if ball_collided_with_left_wall(&self.ball_char) {
-                // Need to consider the scenario where the direction is 180/0 degrees
-                self.ball_char.object.velocity.reflect_about_y_axis();
-            } else if ball_collided_with_right_wall(&self.ball_char) {
-                // Need to consider the scenario where the direction is 180/0 degrees
-                self.ball_char.object.velocity.reflect_about_y_axis();
-            } else if ball_collided_with_ceiling(&self.ball_char) {
-                // Need to consider the scenario where the direction is 270/90 degrees
-                self.ball_char.object.velocity.reflect_about_x_axis();
-            } else if self.ball_char.collided_with(&self.paddle_char).0 {
-                // Need to consider the scenario where the direction is 270/90 degrees
-                self.ball_char.object.velocity.reflect_about_x_axis();
-            } else if ball_is_off_screen(&self.ball_char) {
-                self.artist.write_str("Game over\n").unwrap();
-                self.artist.write_str("Press y to play again\n").unwrap();
-                ended = true;
-                return;
-            }
-            for i in 0..self.blocks.len() {
-                let block_char = &self.blocks[i];
-                if self.ball_char.collided_with(block_char).0 {
-                    self.artist.erase_scaled_bitmap_from_double_buffer(&block_char.repr, block_char.object.pos, &self.background);
-                    self.ball_char.object.velocity.reflect_about_x_axis();
-                    self.blocks.remove(i);
-                    break;
+            for _ in 0..self.scheduler.updates_due() {
+                if !self.update() {
+                    event_hook::send_event(Event::Custom(BALL_LOST, self.score));
+                    return;
                 }
             }
-            let old_pos = self.ball_char.object.update_pos(1, X_SCALE, Y_SCALE);
-            let (ball_passed_through_paddle, point_at_paddle_level_opt) = ball_passed_through_paddle(old_pos, self.ball_char.object.pos, self.ball_char.object.velocity.direction, &self.paddle_char);
-            if ball_passed_through_paddle {
-                self.ball_char.object.pos = point_at_paddle_level_opt.unwrap();
-            }
-            self.artist.move_scaled_bitmap_in_double_buffer(&self.ball_char.repr, old_pos, self.ball_char.object.pos, &self.background);
+            self.move_ball_in_double_buffer(self.scheduler.alpha());
+            self.update_combo_text_in_double_buffer();
+            self.update_block_breaks_in_double_buffer();
             self.draw_game_in_double_buffer();
+            if self.artist.perf_hud_is_visible() {
+                self.draw_debug_overlay_in_double_buffer();
+            }
             self.artist.draw_on_screen_from_double_buffer();
         }));
 
         loop {
+            event_hook::poll();
             if ended { break; }
+            self.artist.flip_pending_frame();
+            // Nothing interrupts the processor when the retrace comes,
+            // so it has to keep checking while a frame is waiting
+            if !self.artist.has_pending_frame() {
+                stats::idle();
+            }
         }
-        event_hook::unhook_event(game_hook, EventKind::Keyboard);
-        event_hook::unhook_event(main_loop_hook, EventKind::Timer);
+        // Removing every handler the game hooked, so none of them outlives the game
+        event_hook::unhook_all(GAME_HOOK_OWNER);
+    }
+
+    /// Moves the ball and the boss on by one update and handles what they hit
+    ///
+    /// Returns false if the ball has gone off the screen
+    fn update(&mut self) -> bool {
+        if self.gamepad.x != 0 {
+            let dx = self.gamepad.x as i16 * GAMEPAD_PADDLE_SPEED.as_i16() / gamepad::AXIS_MAX as i16;
+            self.move_paddle_by_in_double_buffer(dx);
+        }
+        if ball_collided_with_left_wall(&self.ball_char, &self.playfield) {
+            // Need to consider the scenario where the direction is 180/0 degrees
+            self.ball_char.object.velocity.reflect_about_y_axis();
+        } else if ball_collided_with_right_wall(&self.ball_char, &self.playfield) {
+            // Need to consider the scenario where the direction is 180/0 degrees
+            self.ball_char.object.velocity.reflect_about_y_axis();
+        } else if ball_collided_with_ceiling(&self.ball_char, &self.playfield) {
+            // Need to consider the scenario where the direction is 270/90 degrees
+            self.ball_char.object.velocity.reflect_about_x_axis();
+        } else if self.ball_char.collided_with(&self.paddle_char).0 {
+            // Need to consider the scenario where the direction is 270/90 degrees
+            self.ball_char.object.velocity.reflect_about_x_axis();
+            self.combo = 0;
+        } else if ball_is_off_screen(&self.ball_char) {
+            return false;
+        }
+        if let Some(i) = self.hit_block() {
+            let block_char = &self.blocks[i];
+            let block_pos = block_char.object.pos;
+            self.artist.erase_scaled_bitmap_from_double_buffer(&block_char.repr, block_pos, &self.background);
+            self.ball_char.object.velocity.reflect_about_x_axis();
+            self.blocks.remove(i);
+            self.redraw_wall_target();
+            self.block_breaks.push(BlockBreak {
+                pos: block_pos,
+                animation: Animation::for_sheet(&self.block_break_sheet, BLOCK_BREAK_TICKS_PER_FRAME, false)
+            });
+            self.score_hit(BLOCK_SCORE, block_pos);
+            event_hook::send_event(Event::Custom(BLOCK_DESTROYED, self.blocks.len()));
+        }
+        let old_pos = self.ball_motion.step(&mut self.ball_char.object, X_SCALE, Y_SCALE);
+        self.ball_prev_pos = old_pos;
+        let (ball_passed_through_paddle, point_at_paddle_level_opt) = ball_passed_through_paddle(old_pos, self.ball_char.object.pos, self.ball_char.object.velocity.direction, &self.paddle_char);
+        if ball_passed_through_paddle {
+            self.ball_char.object.pos = point_at_paddle_level_opt.unwrap();
+        }
+        if self.boss.is_some() {
+            self.update_boss(old_pos);
+        }
+        true
+    }
+
+    /// The index of the block the ball hit, if it hit one
+    ///
+    /// The ball can be touching two blocks at once, where they meet, in which
+    /// case it hit the one nearest to where it was before the last update
+    fn hit_block(&mut self) -> Option<usize> {
+        let (ball_char, blocks, prev_pos) = (&self.ball_char, &self.blocks, self.ball_prev_pos);
+        self.frame_arena.scope(|arena| {
+            let mut touching: Vec<usize> = Vec::with_capacity(2, arena);
+            for (i, block_char) in blocks.iter().enumerate() {
+                if ball_char.collided_with(block_char).0 {
+                    touching.push(i);
+                }
+            }
+            touching.iter().copied().min_by_key(|&i| {
+                let pos = blocks[i].object.pos;
+                let (dx, dy) = (pos.x() as i32 - prev_pos.x() as i32, pos.y() as i32 - prev_pos.y() as i32);
+                dx * dx + dy * dy
+            })
+        })
+    }
+
+    /// Moves the ball in the double buffer to `alpha` of the way from where it
+    /// was before the last update to where it is now
+    fn move_ball_in_double_buffer(&mut self, alpha: i32) {
+        let old_pos = self.ball_drawn_pos;
+        self.ball_drawn_pos = frame::interpolate(self.ball_prev_pos, self.ball_char.object.pos, alpha);
+        self.artist.move_scaled_bitmap_in_double_buffer(&self.ball_char.repr, old_pos, self.ball_drawn_pos, &self.background);
     }
 
     fn move_paddle_in_double_buffer(&mut self, direction: PaddleDirection) {
-        let diff = match direction {
-            PaddleDirection::Left => Point(-5 * X_SCALE.as_i16(), 0),
-            PaddleDirection::Right => Point(5 * X_SCALE.as_i16(), 0)
+        let dx = match direction {
+            PaddleDirection::Left => -5 * X_SCALE.as_i16(),
+            PaddleDirection::Right => 5 * X_SCALE.as_i16()
         };
+        self.move_paddle_by_in_double_buffer(dx);
+    }
+
+    /// Moves the paddle `dx` pixels across in the double buffer
+    fn move_paddle_by_in_double_buffer(&mut self, dx: i16) {
         let old_pos = self.paddle_char.object.pos;
-        self.paddle_char.object.pos += diff;
+        // Stopping at the walls instead of moving into them
+        let min_x = self.playfield.left_wall().right();
+        let max_x = self.playfield.right_wall().top_left.x() - self.paddle_char.repr.width().as_i16();
+        let x = old_pos.x().saturating_add(dx).max(min_x).min(max_x);
+        self.paddle_char.object.pos = Point(x, old_pos.y());
         self.artist.move_scaled_bitmap_in_double_buffer(&self.paddle_char.repr, old_pos, self.paddle_char.object.pos, &self.background);
     }
 
-    fn generate_blocks() -> Vec<'static, Character> {
-        let blue_block_bmp_bytes = include_bytes!("./assets/blue_block.bmp");
-        let blue_block_bmp = Bitmap::from(blue_block_bmp_bytes, Transparency::None)
-            .expect("Failed to read the bitmap from the given source");
-        let cyan_block_bmp_bytes = include_bytes!("./assets/cyan_block.bmp");
-        let cyan_block_bmp = Bitmap::from(cyan_block_bmp_bytes, Transparency::None)
-            .expect("Failed to read the bitmap from the given source");
-        let green_block_bmp_bytes = include_bytes!("./assets/green_block.bmp");
-        let green_block_bmp = Bitmap::from(green_block_bmp_bytes, Transparency::None)
-            .expect("Failed to read the bitmap from the given source");
-        let pink_block_bmp_bytes = include_bytes!("./assets/pink_block.bmp");
-        let pink_block_bmp = Bitmap::from(pink_block_bmp_bytes, Transparency::None)
-            .expect("Failed to read the bitmap from the given source");
-        let yellow_block_bmp_bytes = include_bytes!("./assets/yellow_block.bmp");
-        let yellow_block_bmp = Bitmap::from(yellow_block_bmp_bytes, Transparency::None)
-            .expect("Failed to read the bitmap from the given source");
-        let block_bmps = [blue_block_bmp, pink_block_bmp, green_block_bmp, cyan_block_bmp, yellow_block_bmp];
+    /// Reloads the bitmaps and resets the paddle, the ball and the block wall
+    /// with the current accessibility options, then redraws the double buffer
+    ///
+    /// Only meant to be used before the game has started
+    fn apply_accessibility(&mut self) {
+        let screen = artist::screen_info();
+        self.background = self.accessibility.background();
+        self.block_bmps = load_block_bmps(screen, self.accessibility);
+        let (blocks, next_block_bmp_idx) = Self::generate_blocks(&self.block_bmps);
+        self.blocks = blocks;
+        self.next_block_bmp_idx = next_block_bmp_idx;
+        self.paddle_char = new_paddle(load_paddle_bmp(screen, self.accessibility));
+        self.ball_char = new_ball(load_ball_bmp(screen, self.accessibility), &self.paddle_char);
+        self.ball_prev_pos = self.ball_char.object.pos;
+        self.ball_drawn_pos = self.ball_char.object.pos;
+        self.playfield = Playfield::load(PLAYFIELD_WALLS, screen, self.accessibility);
+        self.redraw_wall_target();
+        self.artist.draw_background_in_double_buffer(&self.background);
+        self.draw_game_in_double_buffer();
+    }
+
+    /// Redraws the block wall in the wall target, which has to be done
+    /// whenever a block is added, moved or removed
+    fn redraw_wall_target(&mut self) {
+        let wall_target = match self.wall_target {
+            Some(wall_target) => wall_target,
+            None => return
+        };
+        self.artist.set_target(wall_target);
+        self.artist.draw_background_in_double_buffer(&self.background);
+        for i in 0..self.blocks.len() {
+            self.artist.draw_scaled_bitmap_in_double_buffer(self.blocks[i].object.pos, &self.blocks[i].repr);
+        }
+        self.artist.set_target(Target::DoubleBuffer);
+    }
+
+    /// The y coordinate of the bottom of the lowest row of blocks
+    fn wall_bottom(&self) -> usize {
+        self.blocks.iter()
+            .map(|block| block.object.pos.y().as_usize() + block.repr.height())
+            .max()
+            .unwrap_or(BLOCK_START_POS_Y)
+    }
+
+    /// Creates the initial block wall
+    ///
+    /// Returns the blocks and the index in `block_bmps` of the
+    /// color that comes after the last block's
+    fn generate_blocks(block_bmps: &[ScaledBitmap; 5]) -> (Vec<'static, Character>, usize) {
         let mut blocks = vec!(item_type => Character, capacity => 10);
-        let block_start_pos_x: usize = 15;
-        let block_end_pos_x: usize = SCREEN_WIDTH - block_start_pos_x - block_bmps[0].scaled_width();
-        let block_start_pos_y: usize = 10;
+        let block_end_pos_x: usize = SCREEN_WIDTH - BLOCK_START_POS_X - block_bmps[0].width();
         let block_end_pos_y: usize = SCREEN_HEIGHT / 4;
         let mut i = 0;
-        for y in (block_start_pos_y..=block_end_pos_y).step_by(block_bmps[0].scaled_height()) {
-            for x in (block_start_pos_x..=block_end_pos_x).step_by(block_bmps[0].scaled_width()) {
+        for y in (BLOCK_START_POS_Y..=block_end_pos_y).step_by(block_bmps[0].height()) {
+            for x in (BLOCK_START_POS_X..=block_end_pos_x).step_by(block_bmps[0].width()) {
                 let block = Character::new(Object {
                     pos: Point(x.as_i16(), y.as_i16()),
                     velocity: Velocity { direction: 0, speed: 0 }
-                }, block_bmps[i].convert_to_scaled_bitmap());
+                }, block_bmps[i].clone());
                 blocks.push(block);
                 i = (i + 1) % block_bmps.len();
             }
         }
-        blocks
+        (blocks, i)
+    }
+
+    /// Moves the block wall down by a row and adds a new row at the top
+    ///
+    /// The wall is scrolled in the double buffer instead of being redrawn,
+    /// so the gaps left by blocks that have been hit move down with it
+    fn lower_block_wall(&mut self) {
+        let row_height = self.block_bmps[0].height();
+        let block_width = self.block_bmps[0].width();
+        let wall_bottom = self.wall_bottom();
+        let wall_top_left = Point(BLOCK_START_POS_X.as_i16(), BLOCK_START_POS_Y.as_i16());
+        let wall_width = SCREEN_WIDTH - 2 * BLOCK_START_POS_X;
+        // The ball would be dragged down with the wall if it was in it
+        self.artist.erase_scaled_bitmap_from_double_buffer(&self.ball_char.repr, self.ball_drawn_pos, &self.background);
+        self.artist.copy_rect_in_double_buffer(
+            wall_top_left,
+            wall_top_left + Point(0, row_height.as_i16()),
+            wall_width,
+            wall_bottom - BLOCK_START_POS_Y
+        );
+        self.artist.fill_rect_in_double_buffer(wall_top_left, wall_width, row_height, &self.background);
+        for block in self.blocks.iter_mut() {
+            block.object.pos += Point(0, row_height.as_i16());
+        }
+        let block_end_pos_x = SCREEN_WIDTH - BLOCK_START_POS_X - block_width;
+        let mut i = self.next_block_bmp_idx;
+        for (col, x) in (BLOCK_START_POS_X..=block_end_pos_x).step_by(block_width).enumerate() {
+            let block = Character::new(Object {
+                pos: Point(x.as_i16(), BLOCK_START_POS_Y.as_i16()),
+                velocity: Velocity { direction: 0, speed: 0 }
+            }, self.block_bmps[i].clone());
+            // The blocks are kept in the order they appear on the
+            // screen, so the new row goes at the front
+            self.blocks.insert(col, block);
+            self.artist.draw_scaled_bitmap_in_double_buffer(self.blocks[col].object.pos, &self.blocks[col].repr);
+            i = (i + 1) % self.block_bmps.len();
+        }
+        self.next_block_bmp_idx = i;
+        self.redraw_wall_target();
+    }
+
+    /// Moves the boss and checks if the ball, which just moved from
+    /// `ball_old_pos`, hit it
+    ///
+    /// Both the ball and the boss can move more than a pixel in an update, so their
+    /// swept rectangles are checked to make sure the ball can't pass through
+    fn update_boss(&mut self, ball_old_pos: Point) {
+        let boss = self.boss.as_mut().unwrap();
+        let boss_old_pos = boss.update_pos();
+        self.artist.erase_scaled_bitmap_from_double_buffer(&boss.character.repr, boss_old_pos, &self.background);
+        let ball_sweep = Rectangle::swept(
+            ball_old_pos,
+            self.ball_char.object.pos,
+            self.ball_char.repr.width(),
+            self.ball_char.repr.height()
+        );
+        let boss_sweep = Rectangle::swept(
+            boss_old_pos,
+            boss.character.object.pos,
+            boss.character.repr.width(),
+            boss.character.repr.height()
+        );
+        if !ball_sweep.intersects(&boss_sweep) {
+            return;
+        }
+        self.ball_char.object.pos = ball_old_pos;
+        self.ball_char.object.velocity.reflect_about_x_axis();
+        boss.hit();
+        let boss_pos = boss.character.object.pos;
+        if boss.is_defeated() {
+            self.boss = None;
+            self.boss_defeated = true;
+            self.stop_music();
+        } else {
+            // Restarting the beat so the hit lands on it
+            self.play_music(self.music);
+        }
+        self.score_hit(BOSS_HIT_SCORE, boss_pos);
+    }
+
+    /// Counts a hit at `pos` toward the combo and adds `points`, multiplied
+    /// by the combo, to the score
+    ///
+    /// From the second hit of a combo on, the combo counter is shown at `pos`
+    fn score_hit(&mut self, points: usize, pos: Point) {
+        self.combo += 1;
+        self.score += points * self.combo.min(MAX_COMBO_MULTIPLIER);
+        if self.combo < 2 {
+            return;
+        }
+        self.erase_combo_text_from_double_buffer();
+        let mut digits = [0; 10];
+        let combo = num::fmt::write_u32(&mut digits, self.combo as u32);
+        self.combo_text = Some(FloatingText::new(&["COMBO x", combo], pos));
+    }
+
+    /// Erases the combo counter from where it was and moves it up, or removes
+    /// it once it has faded out
+    ///
+    /// Must be called before the game is drawn, so whatever the
+    /// counter was drawn over is drawn again
+    fn update_combo_text_in_double_buffer(&mut self) {
+        self.erase_combo_text_from_double_buffer();
+        if let Some(ref mut combo_text) = self.combo_text {
+            if !combo_text.update() {
+                self.combo_text = None;
+            }
+        }
+    }
+
+    /// Erases the breaking blocks and moves their animations on,
+    /// removing the ones that have finished
+    ///
+    /// Must be called before the game is drawn, so whatever
+    /// they were drawn over is drawn again
+    fn update_block_breaks_in_double_buffer(&mut self) {
+        let mut i = 0;
+        while i < self.block_breaks.len() {
+            let block_break = &mut self.block_breaks[i];
+            let frame = self.block_break_sheet.frame(block_break.animation.frame());
+            self.artist.erase_scaled_bitmap_from_double_buffer(frame, block_break.pos, &self.background);
+            block_break.animation.tick();
+            if block_break.animation.is_finished() {
+                self.block_breaks.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn erase_combo_text_from_double_buffer(&mut self) {
+        if let Some(ref combo_text) = self.combo_text {
+            self.artist.fill_rect_in_double_buffer(combo_text.pos, combo_text.width(), combo_text.height(), &self.background);
+        }
+    }
+
+    /// Draws the score in the top left corner and the combo counter,
+    /// over everything else in the double buffer
+    fn draw_hud_in_double_buffer(&mut self) {
+        let mut digits = [0; 20];
+        let score = num::fmt::write_u64(&mut digits, self.score as u64);
+        self.artist.set_writing_pos(Point(0, 0));
+        self.artist.write_string_in_double_buffer("SCORE ");
+        self.artist.write_string_in_double_buffer(score);
+        self.artist.reset_writing_pos();
+        if let Some(ref combo_text) = self.combo_text {
+            let format = TextFormat {
+                style: self.artist.text_style(),
+                ..TextFormat::new(combo_text.color(), self.background)
+            };
+            self.artist.write_str_at(combo_text.text(), combo_text.pos, format);
+        }
+    }
+
+    /// Pauses the game along with the music, which carries on from
+    /// where it was on `resume`
+    ///
+    /// No sound effect can be played until the game is resumed
+    fn pause(&mut self) {
+        self.paused = true;
+        self.paused_msg_has_been_drawn = false;
+        sound::pause_sound();
+    }
+
+    /// Starts the game from the start screen, or carries on with it if it's paused
+    fn start_or_resume(&mut self) {
+        if !self.has_started && !self.accessibility_open {
+            self.ball_char.object.velocity.direction = self.generate_direction();
+            self.ball_char.object.velocity.speed = self.accessibility.ball_speed();
+            self.has_started = true;
+            self.scheduler.reset();
+            self.music_handle = match self.music_handle {
+                Some(menu_music_handle) => sound::crossfade(menu_music_handle, &self.music, 1000).ok(),
+                None => sound::play_sound(&self.music, ActionOnEnd::Replay).ok()
+            };
+        } else if self.paused {
+            self.resume();
+        }
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.scheduler.reset();
+        self.paused_msg_has_been_drawn = false;
+        sound::resume_sound();
+    }
+
+    /// Replaces the music that's playing with `music`, from its beginning
+    fn play_music(&mut self, music: Sound) {
+        self.stop_music();
+        self.music_handle = sound::play_sound(&music, ActionOnEnd::Replay).ok();
+    }
+
+    fn stop_music(&mut self) {
+        if let Some(music_handle) = self.music_handle.take() {
+            let _ = sound::stop_sound(music_handle);
+        }
+    }
+
+    /// Checks if the block wall has come down to the paddle
+    fn block_wall_reached_paddle(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            block.object.pos.y() + block.repr.height().as_i16() > self.paddle_char.object.pos.y()
+        })
     }
 
     /// Returns an angle in degrees that can be used for an initial angle
     /// for the ball movement in the game
-    fn generate_direction(&self) -> usize {
-        // The current time is random enough for this purpose
-        let time = cmos::get_current_time();
-        // Adding 180 because the initial direction can't be anything
-        // lesser than 180.
+    fn generate_direction(&mut self) -> usize {
+        // The initial direction can't be anything lesser than 180.
         // Anything lesser than 180 will result in the ball moving downwards
-        let direction = (time.sum_of_fields() % 180) + 180;
+        let direction = self.rng.gen_range(180..360);
         // A direction of 180 will result in weird movements to the left only
         if direction == 180 {
             direction + 10
@@ -293,12 +1347,116 @@ impl Game {
         }
     }
 
+    /// Draws `lines` of text in a panel in the middle of the screen
+    ///
+    /// The panel is only drawn on the screen. It's removed from the double buffer
+    /// right after, so it disappears when the game is drawn again
+    fn draw_dialog(&mut self, lines: &[&str]) {
+        let padding_x = self.panel.min_width() / 2;
+        let padding_y = self.panel.min_height() / 2;
+        let (foreground, background) = self.artist.text_colors();
+        let format = TextFormat {
+            style: self.artist.text_style(),
+            align: Align::Center,
+            ..TextFormat::new(foreground, background)
+        };
+        // The first line is the title, which is written in the large font
+        let title_format = TextFormat { font: &font::LARGE, ..format };
+        let line_format = |i: usize| if i == 0 { title_format } else { format };
+        let line_height = |i: usize| line_format(i).font.height() * Y_SCALE;
+        let longest_line = lines.iter().enumerate()
+            .map(|(i, line)| artist::text_width(line.trim_end(), line_format(i).font, 1))
+            .max()
+            .unwrap_or(0);
+        let width = (longest_line + 2 * padding_x).min(SCREEN_WIDTH);
+        let height = (0..lines.len()).map(line_height).sum::<usize>() + 2 * padding_y;
+        let pos = Point(((SCREEN_WIDTH - width) / 2).as_i16(), ((SCREEN_HEIGHT - height) / 2).as_i16());
+        self.artist.draw_nine_patch_in_double_buffer(&self.panel, pos, width, height);
+        let mut line_y = pos.y() + padding_y.as_i16();
+        for (i, line) in lines.iter().enumerate() {
+            self.artist.write_str_at(line.trim_end(), Point((SCREEN_WIDTH / 2).as_i16(), line_y), line_format(i));
+            line_y += line_height(i).as_i16();
+        }
+        self.artist.draw_on_screen_from_double_buffer();
+        self.artist.fill_rect_in_double_buffer(pos, width, height, &self.background);
+        self.draw_game_in_double_buffer();
+    }
+
+    /// Draws the CPU load, the artist's performance HUD and a VU meter
+    /// of the sound levels over the top right corner of the double buffer
+    ///
+    /// Toggled with F3, for finding out what slows the game down on real machines
+    fn draw_debug_overlay_in_double_buffer(&mut self) {
+        let mut text = *DEBUG_OVERLAY_TEMPLATE;
+        // Right aligned in the 3 spaces before the '%'
+        let mut digits = [0; 3];
+        let load = num::fmt::write_u32(&mut digits, stats::cpu_load().min(100) as u32);
+        text[7 - load.len()..7].copy_from_slice(load.as_bytes());
+        self.erase_debug_overlay_from_double_buffer();
+        self.artist.set_writing_pos(debug_overlay_pos());
+        self.artist.write_string_in_double_buffer(core::str::from_utf8(&text).unwrap());
+        self.artist.reset_writing_pos();
+        // The events handled in this handler have been taken off the queue,
+        // so these are the ones that came in while the frame was being drawn
+        let queued_events = event_hook::deferred_stats().pending;
+        let hud_pos = debug_overlay_pos() + Point(0, (FONT_HEIGHT * Y_SCALE).as_i16());
+        self.artist.draw_perf_hud_in_double_buffer(hud_pos, queued_events, &self.background);
+        // A bar for each channel, as long as the overlay at full volume
+        let bar_height = VU_METER_BAR_HEIGHT * Y_SCALE;
+        let (left, right) = sound::levels();
+        for (i, level) in [left, right].into_iter().enumerate() {
+            let bar_pos = hud_pos + Point(0, (PERF_HUD_HEIGHT + i * bar_height).as_i16());
+            let bar_width = DEBUG_OVERLAY_WIDTH * level as usize / (i16::MAX as usize + 1);
+            self.artist.fill_rect_in_double_buffer(bar_pos, bar_width, bar_height, &Color::new(Color::LIGHT_GREEN));
+        }
+    }
+
+    fn erase_debug_overlay_from_double_buffer(&mut self) {
+        let height = (FONT_HEIGHT + 2 * VU_METER_BAR_HEIGHT) * Y_SCALE + PERF_HUD_HEIGHT;
+        self.artist.fill_rect_in_double_buffer(debug_overlay_pos(), DEBUG_OVERLAY_WIDTH, height, &self.background);
+    }
+
     fn draw_game_in_double_buffer(&mut self) {
+        // Redrawn every time, since erasing the ball next to a wall erases part of the wall
+        self.playfield.draw_in_double_buffer(&mut self.artist);
         self.artist.draw_scaled_bitmap_in_double_buffer(self.paddle_char.object.pos, &self.paddle_char.repr);
-        for i in 0..self.blocks.len() {
-            self.artist.draw_scaled_bitmap_in_double_buffer(self.blocks[i].object.pos, &self.blocks[i].repr);
+        match self.wall_target {
+            Some(wall_target) => {
+                let wall_top_left = Point(BLOCK_START_POS_X.as_i16(), BLOCK_START_POS_Y.as_i16());
+                let wall_width = SCREEN_WIDTH - 2 * BLOCK_START_POS_X;
+                let wall_height = self.wall_bottom() - BLOCK_START_POS_Y;
+                self.artist.compose_target(wall_target, wall_top_left, wall_width, wall_height);
+            }
+            None => for i in 0..self.blocks.len() {
+                self.artist.draw_scaled_bitmap_in_double_buffer(self.blocks[i].object.pos, &self.blocks[i].repr);
+            }
+        }
+        for i in 0..self.block_breaks.len() {
+            let block_break = &self.block_breaks[i];
+            self.artist.draw_animation_frame(&self.block_break_sheet, &block_break.animation, block_break.pos);
+        }
+        if let Some(ref boss) = self.boss {
+            self.artist.draw_scaled_bitmap_in_double_buffer(boss.character.object.pos, boss.current_repr());
+        }
+        self.artist.draw_scaled_bitmap_in_double_buffer(self.ball_drawn_pos, &self.ball_char.repr);
+        // The walls are drawn over the score, so it's drawn again every time
+        if self.has_started {
+            self.draw_hud_in_double_buffer();
         }
-        self.artist.draw_scaled_bitmap_in_double_buffer(self.ball_char.object.pos, &self.ball_char.repr);
+    }
+}
+
+impl Drop for Game {
+    fn drop(&mut self) {
+        self.stop_music();
+        // So the menu music can play if the game ended while paused
+        sound::resume_sound();
+        // A new wall target is created for every game
+        if let Some(wall_target) = self.wall_target.take() {
+            self.artist.destroy_target(wall_target);
+        }
+        // The menus write straight to the screen, so the last frame can't be left waiting
+        self.artist.set_present_mode(PresentMode::Immediate).ok();
     }
 }
 
@@ -308,28 +1466,33 @@ enum PaddleDirection {
     Right
 }
 
-fn ball_collided_with_left_wall(ball_char: &Character) -> bool {
-    ball_char.object.pos.x() <= 0
+/// Where the debug overlay is drawn, so it ends at the right edge of the screen
+fn debug_overlay_pos() -> Point {
+    Point((SCREEN_WIDTH - DEBUG_OVERLAY_WIDTH).as_i16(), 0)
+}
+
+fn ball_collided_with_left_wall(ball_char: &Character, playfield: &Playfield) -> bool {
+    ball_char.object.pos.x() <= playfield.left_wall().right()
 }
 
-fn ball_collided_with_right_wall(ball_char: &Character) -> bool {
-    ball_char.object.pos.x() >= SCREEN_WIDTH as i16 - ball_char.repr.width().as_i16()
+fn ball_collided_with_right_wall(ball_char: &Character, playfield: &Playfield) -> bool {
+    ball_char.object.pos.x() + ball_char.repr.width().as_i16() >= playfield.right_wall().top_left.x()
 }
 
-fn ball_collided_with_ceiling(ball_char: &Character) -> bool {
-    ball_char.object.pos.y() <= 0 + 5
+fn ball_collided_with_ceiling(ball_char: &Character, playfield: &Playfield) -> bool {
+    ball_char.object.pos.y() <= playfield.top_wall().bottom()
 }
 
 fn ball_is_off_screen(ball_char: &Character) -> bool {
     ball_char.object.pos.y() >= SCREEN_HEIGHT.as_i16()
 }
 
-fn paddle_collided_with_right_wall(paddle_char: &Character) -> bool {
-    paddle_char.object.pos.x() + paddle_char.repr.width().as_i16() >= SCREEN_WIDTH.as_i16() - 8
+fn paddle_collided_with_right_wall(paddle_char: &Character, playfield: &Playfield) -> bool {
+    paddle_char.object.pos.x() + paddle_char.repr.width().as_i16() >= playfield.right_wall().top_left.x()
 }
 
-fn paddle_collided_with_left_wall(paddle_char: &Character) -> bool {
-    paddle_char.object.pos.x() <= 0 + 5
+fn paddle_collided_with_left_wall(paddle_char: &Character, playfield: &Playfield) -> bool {
+    paddle_char.object.pos.x() <= playfield.left_wall().right()
 }
 
 fn ball_passed_through_paddle(old_pos: Point, new_pos: Point, direction: usize, paddle_char: &Character) -> (bool, Option<Point>) {
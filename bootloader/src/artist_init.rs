@@ -1,6 +1,11 @@
-use machine::memory::Addr;
-use artist::SCREEN_BUFFER_ADDRESS;
+use machine::framebuffer::Framebuffer;
+use machine::{power, serial_println};
+use artist::SCREEN_BUFFER;
 
-pub fn init(graphics_buffer_address: Addr) {
-    SCREEN_BUFFER_ADDRESS.call_once(|| graphics_buffer_address);
-}
\ No newline at end of file
+pub fn init(screen_buffer: Framebuffer) {
+    SCREEN_BUFFER.call_once(|| screen_buffer);
+    // Registered before any other hook, so the screen is cleared after everything else has stopped
+    if let Err(msg) = power::on_shutdown(artist::clear_screen) {
+        serial_println!("The screen won't be cleared before shutting down: {}", msg);
+    }
+}
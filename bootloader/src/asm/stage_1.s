@@ -20,14 +20,6 @@ boot:
     mov bx, offset loading_msg
     call print_string16
 
-enable_a20:
-    in al, 0x92       # Enable A20-Line with port 92
-    test al, 2
-    jnz enter_protected_mode
-    or al, 2
-    and al, 0xfe
-    out 0x92, al
-
 enter_protected_mode:
     cli
     push ds
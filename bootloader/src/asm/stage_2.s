@@ -3,6 +3,9 @@
 .global mmap_entry_count
 
 stage_2:
+    # The sounds are loaded above 1MiB, which is aliased to low memory
+    # if the A20 line is disabled
+    call enable_a20
 
 load_sounds:
     mov word ptr [dap_buffer_segment], 0
@@ -27,6 +30,9 @@ load_sounds:
     mov word ptr [dap_lba_start], dx
     mov edi, offset __sound_start  # Initial address where the buffered sector should be stored
 
+    cmp ebx, 0                          # Nothing to load if the section is empty
+    je set_target_op_mode
+
 load_sound_loop:
     mov si, offset dap
     mov ah, 0x42
@@ -59,6 +65,7 @@ set_target_op_mode:
 build_mem_map:
     lea di, es:[_mmap]
     call map_memory
+    jc map_memory_err
 
 switch_to_graphics_mode:
     mov ah, 0
@@ -103,6 +110,8 @@ no_text:
     jz skip_entry
     inc bp
     add di, 24
+    cmp bp, 100                         # MAX_MEM_MAP_SIZE in machine/src/memory.rs, the most the map holds
+    jae e820f
 skip_entry:
     test ebx, ebx
     jne e820lp
@@ -123,7 +132,167 @@ map_memory_fail:
     stc
     ret
 
+# Enables the A20 line
+#
+# Tries the BIOS, the keyboard controller and the fast A20 gate in that order,
+# verifying after each method, because none of them works on every board.
+# Halts with an error message if none of them works
+#
+# References
+# ----------
+# https://wiki.osdev.org/A20_Line
+enable_a20:
+    call check_a20
+    cmp ax, 1
+    je enable_a20_done
+
+enable_a20_bios:
+    mov ax, 0x2401
+    int 0x15
+    call check_a20_with_retries
+    cmp ax, 1
+    je enable_a20_done
+
+enable_a20_kbc:
+    cli
+    call a20_kbc_wait_input
+    mov al, 0xad                        # Disable the keyboard
+    out 0x64, al
+    call a20_kbc_wait_input
+    mov al, 0xd0                        # Read the controller output port
+    out 0x64, al
+    call a20_kbc_wait_output
+    in al, 0x60
+    push ax
+    call a20_kbc_wait_input
+    mov al, 0xd1                        # Write the controller output port
+    out 0x64, al
+    call a20_kbc_wait_input
+    pop ax
+    or al, 2                            # Bit 1 of the output port is the A20 gate
+    out 0x60, al
+    call a20_kbc_wait_input
+    mov al, 0xae                        # Enable the keyboard
+    out 0x64, al
+    call a20_kbc_wait_input
+    sti
+    call check_a20_with_retries
+    cmp ax, 1
+    je enable_a20_done
+
+enable_a20_fast:
+    in al, 0x92
+    test al, 2
+    jnz enable_a20_fast_check
+    or al, 2
+    and al, 0xfe                        # Bit 0 causes a fast reset
+    out 0x92, al
+enable_a20_fast_check:
+    call check_a20_with_retries
+    cmp ax, 1
+    je enable_a20_done
+
+    mov bx, offset a20_err_msg
+    call print_string16
+    jmp halt
+
+enable_a20_done:
+    ret
+
+# Waits for the keyboard controller's input buffer to be empty
+# Gives up after a while so boards without a keyboard controller don't hang
+a20_kbc_wait_input:
+    mov cx, 0xffff
+a20_kbc_wait_input_loop:
+    in al, 0x64
+    test al, 2
+    jz a20_kbc_wait_input_done
+    loop a20_kbc_wait_input_loop
+a20_kbc_wait_input_done:
+    ret
+
+# Waits for the keyboard controller's output buffer to be full
+a20_kbc_wait_output:
+    mov cx, 0xffff
+a20_kbc_wait_output_loop:
+    in al, 0x64
+    test al, 1
+    jnz a20_kbc_wait_output_done
+    loop a20_kbc_wait_output_loop
+a20_kbc_wait_output_done:
+    ret
+
+# Checks if the A20 line is enabled a number of times
+# to give the hardware time to respond
+#
+# Result
+# ------
+# ax is 1 if the A20 line is enabled, 0 otherwise
+check_a20_with_retries:
+    push cx
+    mov cx, 0x1000
+check_a20_with_retries_loop:
+    push cx
+    call check_a20
+    pop cx
+    cmp ax, 1
+    je check_a20_with_retries_done
+    loop check_a20_with_retries_loop
+check_a20_with_retries_done:
+    pop cx
+    ret
+
+# Checks if the A20 line is enabled
+#
+# The byte at 0x0000:0x0500 and the byte at 0xffff:0x0510 are the same byte
+# if addresses wrap around at 1MiB, which happens when the A20 line is disabled
+#
+# Result
+# ------
+# ax is 1 if the A20 line is enabled, 0 otherwise
+check_a20:
+    pushf
+    push ds
+    push es
+    push di
+    push si
+    cli
+    xor ax, ax
+    mov es, ax
+    not ax
+    mov ds, ax
+    mov di, 0x0500
+    mov si, 0x0510
+    mov al, es:[di]                     # Saving the original values
+    push ax
+    mov al, ds:[si]
+    push ax
+    mov byte ptr es:[di], 0x00
+    mov byte ptr ds:[si], 0xff
+    cmp byte ptr es:[di], 0xff          # If the write to 0xffff:0x0510 changed 0x0000:0x0500, they alias
+    pop ax                              # Restoring the original values
+    mov ds:[si], al
+    pop ax
+    mov es:[di], al
+    mov ax, 0
+    je check_a20_done
+    mov ax, 1
+check_a20_done:
+    pop si
+    pop di
+    pop es
+    pop ds
+    popf
+    ret
+
+map_memory_err:
+    mov bx, offset map_memory_err_msg
+    call print_string16
+    jmp halt
+
 
 load_app_fail_err_msg:              .asciz "Failed to load app"
 load_sound_err_msg:                 .asciz "Failed to load the sounds"
+map_memory_err_msg:                 .asciz "Failed to get the memory map"
+a20_err_msg:                        .asciz "Failed to enable the A20 line"
 mmap_entry_count:                   .word 0
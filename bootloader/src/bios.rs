@@ -5,11 +5,13 @@ global_asm!(include_str!("asm/stage_1.s"));
 global_asm!(include_str!("asm/stage_2.s"));
 global_asm!(include_str!("asm/stage_3.s"));
 
-use crate::setup_memory_and_run_game;
+use crate::{setup_memory_and_run_game, BootInfo};
 use crate::{APP_STACK_SIZE, APP_HEAP_SIZE};
 
 
 use machine::memory::{Addr, MemRegion, MemRegionType, AddrRange, MemAllocator, MemMap, E820MemMapDescriptor};
+use machine::crashlog::{self, CRASH_LOG_ADDR, CRASH_LOG_SIZE};
+use machine::framebuffer::{Framebuffer, PixelFormat};
 
 const VGA_BUFFER_ADDR: Addr = Addr::new(0xa0000);
 
@@ -51,7 +53,7 @@ pub extern "C" fn main() -> ! {
             out(reg) sound_end
         );
     }
-    let mmap_entry_count = mmap_entry_count & 0xff;         // Only lower byte needed
+    let mmap_entry_count = mmap_entry_count & 0xffff;       // Only lower word needed
     if mmap_entry_count == 0 {
         panic!("No memory regions found");
     }
@@ -66,7 +68,7 @@ pub extern "C" fn main() -> ! {
 
     let app_start_addr = Addr::new(app_start);
     let app_end_addr = Addr::new(app_end);
-    let app_region_range = AddrRange::new(app_start_addr.as_u64(), app_end_addr.as_u64() + 1);
+    let app_region_range = AddrRange::new(app_start_addr.as_u64(), app_end_addr.as_u64());
     mem_allocator.mark_alloc_region(MemRegion {
         range: app_region_range,
         region_type: MemRegionType::App
@@ -74,7 +76,7 @@ pub extern "C" fn main() -> ! {
 
     let sound_start_addr = Addr::new(sound_start);
     let sound_end_addr = Addr::new(sound_end);
-    let sound_region_range = AddrRange::new(sound_start_addr.as_u64(), sound_end_addr.as_u64() + 1);
+    let sound_region_range = AddrRange::new(sound_start_addr.as_u64(), sound_end_addr.as_u64());
     mem_allocator.mark_alloc_region(MemRegion {
         range: sound_region_range,
         region_type: MemRegionType::App
@@ -82,13 +84,21 @@ pub extern "C" fn main() -> ! {
 
     let page_table_start_addr = Addr::new(page_table_start);
     let page_table_end_addr = Addr::new(page_table_end);
-    let page_table_region_range = AddrRange::new(page_table_start_addr.as_u64(), page_table_end_addr.as_u64() + 1);
+    let page_table_region_range = AddrRange::new(page_table_start_addr.as_u64(), page_table_end_addr.as_u64());
     mem_allocator.mark_alloc_region(MemRegion {
         range: page_table_region_range,
         region_type: MemRegionType::PageTable
     });
 
-    crate::artist_init::init(VGA_BUFFER_ADDR);
+    // The crash log is only kept if its region is free on this machine
+    let crash_log_range = AddrRange::new(CRASH_LOG_ADDR, CRASH_LOG_ADDR + CRASH_LOG_SIZE);
+    if mem_allocator.range_is_usable(crash_log_range) {
+        mem_allocator.mark_alloc_region(MemRegion {
+            range: crash_log_range,
+            region_type: MemRegionType::InUse
+        });
+        unsafe { crashlog::enable(); }
+    }
 
     let stack_mem = mem_allocator.alloc_mem(MemRegionType::AppStack, APP_STACK_SIZE)
         .expect("Couldn't allocate memory for the stack");
@@ -96,13 +106,25 @@ pub extern "C" fn main() -> ! {
         .expect("Couldn't allocate memory for the heap");
     
 
-    setup_memory_and_run_game(stack_mem, heap_mem);
+    setup_memory_and_run_game(BootInfo {
+        stack_mem,
+        heap_mem,
+        // Mode 13h, 320x200 with a byte per pixel
+        screen_buffer: Framebuffer::new(VGA_BUFFER_ADDR, 320 * 200, 320, 200, 320, 1, PixelFormat::Indexed)
+    });
 }
 
 // Allowing dead code because this function is unused during testing
 #[allow(dead_code)]
 #[cfg_attr(not(test), panic_handler)]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
+    machine::cmos::record_crash();
+    machine::serial_println!("{}", _info);
+    #[cfg(feature = "port_audit")]
+    machine::trace::dump();
+    if let Some(mut crash_log) = crashlog::writer() {
+        let _ = write!(crash_log, "{}", _info);
+    }
     // A function that allows for printing independently of the artist
     use artist::{is_printable_ascii, font, Color};
     impl PanicWriter {
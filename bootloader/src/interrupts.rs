@@ -1,10 +1,17 @@
-use machine::interrupts::{InterruptDescriptorTable, InterruptStackFrame, IRQ};
+use machine::interrupts::{InterruptDescriptorTable, InterruptStackFrame, IRQ, NMIStatus};
+use machine::apic;
+use machine::gamepad;
+use machine::mce;
+use machine::power;
+use machine::stats;
+use machine::time;
 use machine::pic8259::{Pics, PIC_1_OFFSET};
 use machine::instructions::interrupts::{enable as enable_interrupts, disable as disable_interrupts};
-use machine::keyboard::Keyboard;
+use machine::keyboard::ps2::{self, Ps2Keyboard, Ps2Event};
+use machine::mouse::Mouse;
 use lazy_static::lazy_static;
 use sync::mutex::Mutex;
-use event_hook::Event;
+use event_hook::{Event, EventKind};
 use event_hook;
 use crate::gdt::DOUBLE_FAULT_IST_INDEX;
 
@@ -16,9 +23,13 @@ lazy_static! {
         idt.page_fault.set_handler(page_fault_handler);
         idt.general_protection_fault.set_handler(general_protection_fault_handler);
         idt.brkpoint.set_handler(brkpoint_interrupt_handler);
+        idt.non_maskable_interrupt.set_handler(non_maskable_interrupt_handler);
+        idt.machine_check.set_handler(machine_check_handler);
         idt[IRQ::Timer].set_handler(timer_interrupt_handler);
         idt[IRQ::Keyboard].set_handler(keyboard_interrupt_handler);
         idt[IRQ::Sound].set_handler(sound_interrupt_handler);
+        idt[IRQ::Acpi].set_handler(acpi_interrupt_handler);
+        idt[IRQ::Mouse].set_handler(mouse_interrupt_handler);
         idt
     };
 }
@@ -26,17 +37,68 @@ lazy_static! {
 pub static PICS: Mutex<Pics> = Mutex::new(Pics::new());
 
 lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
+    static ref KEYBOARD: Mutex<Ps2Keyboard> = Mutex::new(Ps2Keyboard::new());
 }
 
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+
 pub fn init(){
     disable_interrupts();
     IDT.load();
     PICS.lock().init();
-    event_hook::init();
+    KEYBOARD.lock().set_scancode_set(ps2::scancode_set());
+    // Without ACPI, the power button just keeps working the way the firmware set it up
+    if power::enable_power_button().is_ok() {
+        let mut pics = PICS.lock();
+        let (primary_mask, mut secondary_mask) = pics.read_masks();
+        // The SCI is on the secondary PIC
+        secondary_mask &= !(1 << (IRQ::Acpi.as_u8() - 8));
+        pics.write_masks(primary_mask, secondary_mask);
+    }
     enable_interrupts();
 }
 
+/// Hands interrupt delivery over from the PICs to the APICs, for the
+/// interrupts the PICs were letting through
+pub fn switch_to_apic() -> Result<(), &'static str> {
+    let (primary_mask, secondary_mask) = PICS.lock().read_masks();
+    let masks = (secondary_mask as u16) << 8 | primary_mask as u16;
+    // The timer is left out, since the local APIC's timer takes over from the PIT
+    let mut isa_irqs = [IRQ::Keyboard; 3];
+    let mut len = 0;
+    for irq in [IRQ::Keyboard, IRQ::Acpi, IRQ::Mouse] {
+        if masks & (1 << irq.as_u8()) == 0 {
+            isa_irqs[len] = irq;
+            len += 1;
+        }
+    }
+    apic::init(&isa_irqs[..len])
+}
+
+/// Lets `irq` through to its handler, for devices set up after the interrupts
+pub fn enable_irq(irq: IRQ) -> Result<(), &'static str> {
+    if apic::is_enabled() {
+        return apic::route_isa_interrupt(irq);
+    }
+    let mut pics = PICS.lock();
+    let (mut primary_mask, mut secondary_mask) = pics.read_masks();
+    match irq.as_u8() {
+        line @ 0..=7 => primary_mask &= !(1 << line),
+        line => secondary_mask &= !(1 << (line - 8))
+    }
+    pics.write_masks(primary_mask, secondary_mask);
+    Ok(())
+}
+
+/// Tells the interrupt controller in use that the handler of `irq` is done
+fn end_of_interrupt(irq: IRQ) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        PICS.lock().end_of_interrupt(irq.as_u8() + PIC_1_OFFSET);
+    }
+}
+
 extern "x86-interrupt" fn brkpoint_interrupt_handler(_sf: InterruptStackFrame) {
     panic!("In the breakpoint");
 }
@@ -49,25 +111,97 @@ extern "x86-interrupt" fn double_fault_handler(sf: InterruptStackFrame, err_code
     panic!("Double Fault\nErr Code: {}\n{:?}", err_code, sf);
 }
 
+/// Reports hardware errors signalled through the NMI line
+///
+/// Chipsets signal memory parity errors and I/O channel checks with NMIs and
+/// some platforms also deliver corrected machine check errors this way.
+/// NMIs with no hardware error behind them are ignored
+extern "x86-interrupt" fn non_maskable_interrupt_handler(sf: InterruptStackFrame) {
+    let nmi_status = NMIStatus::read();
+    let mce_report = mce::report().filter(|report| report.has_errors());
+    if nmi_status.is_hardware_error() || mce_report.is_some() {
+        match mce_report {
+            Some(report) => panic!("Non Maskable Interrupt\n{:?}\n{}\n{:?}", nmi_status, report, sf),
+            None => panic!("Non Maskable Interrupt\n{:?}\n{:?}", nmi_status, sf)
+        }
+    }
+}
+
+extern "x86-interrupt" fn machine_check_handler(sf: InterruptStackFrame) -> ! {
+    match mce::report() {
+        Some(report) => {
+            // Clearing the machine check in progress flag so a machine check
+            // during the panic doesn't shut the processor down before the
+            // report is displayed
+            report.clear_in_progress();
+            panic!("Machine Check\n{}\n{:?}", report, sf);
+        }
+        None => panic!("Machine Check\nMCA not supported\n{:?}", sf)
+    }
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(_sf: InterruptStackFrame) {
+    time::timer_tick();
+    stats::timer_tick();
     event_hook::send_event(Event::Timer);
-    PICS.lock().end_of_interrupt(IRQ::Timer.as_u8() + PIC_1_OFFSET)
+    // The game port has no interrupt of its own
+    if let Some(state) = gamepad::poll() {
+        event_hook::send_event(Event::Gamepad(state));
+    }
+    end_of_interrupt(IRQ::Timer)
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_sf: InterruptStackFrame) {
+    stats::interrupt_entered();
     use machine::port::{Port, PortReadWrite};
     let port: Port<u8> = Port::new(0x60);
     let scancode: u8 = port.read();
     let mut keyboard = KEYBOARD.lock();
-    if let Ok(Some(event)) = keyboard.process_byte(scancode) {
-        event_hook::send_event(Event::Keyboard(event.keycode, event.direction, event.key_modifiers));
+    match keyboard.process_byte(scancode) {
+        Ok(Some(Ps2Event::Key(event))) => if event.is_ctrl_alt_del() {
+            // The game gets a chance to confirm the reset first.
+            // If nothing is listening, the computer is just restarted
+            if event_hook::has_handlers(EventKind::SystemReset) == Some(false) {
+                power::reboot();
+            }
+            event_hook::send_event(Event::SystemReset);
+        } else {
+            event_hook::send_event(Event::Keyboard(event.keycode, event.direction, event.key_modifiers));
+        }
+        Ok(Some(Ps2Event::Reattached)) => event_hook::send_event(Event::KeyboardReattached),
+        _ => ()
     }
-    PICS.lock().end_of_interrupt(IRQ::Keyboard.as_u8() + PIC_1_OFFSET)
+    end_of_interrupt(IRQ::Keyboard)
 }
 
 extern "x86-interrupt" fn sound_interrupt_handler(_sf: InterruptStackFrame) {
+    stats::interrupt_entered();
     event_hook::send_event(Event::Sound);
-    PICS.lock().end_of_interrupt(IRQ::Sound.as_u8() + PIC_1_OFFSET)
+    end_of_interrupt(IRQ::Sound)
+}
+
+extern "x86-interrupt" fn acpi_interrupt_handler(_sf: InterruptStackFrame) {
+    stats::interrupt_entered();
+    if power::handle_sci() {
+        // The game gets a chance to confirm first.
+        // If nothing is listening, the computer is just shut down
+        if event_hook::has_handlers(EventKind::PowerButton) == Some(false) {
+            let _ = unsafe { power::shutdown() };
+        }
+        event_hook::send_event(Event::PowerButton);
+    }
+    end_of_interrupt(IRQ::Acpi)
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_sf: InterruptStackFrame) {
+    stats::interrupt_entered();
+    use machine::port::{Port, PortReadWrite};
+    let port: Port<u8> = Port::new(0x60);
+    let byte: u8 = port.read();
+    if let Some(packet) = MOUSE.lock().process_byte(byte) {
+        event_hook::send_event(Event::Mouse(packet.dx, packet.dy, packet.buttons));
+    }
+    end_of_interrupt(IRQ::Mouse)
 }
 
 extern "x86-interrupt" fn general_protection_fault_handler(sf: InterruptStackFrame, err_code: u64) {
@@ -4,10 +4,12 @@
 #![allow(unaligned_references)]
 
 
-#[cfg(feature="bios")]
+// The BIOS target is the only one without an OS.
+// Its dependencies get their bios features from the bootloader's manifest
+#[cfg(target_os = "none")]
 mod bios;
 
-#[cfg(not(feature="bios"))]
+#[cfg(not(target_os = "none"))]
 mod uefi;
 
 mod interrupts;
@@ -16,8 +18,19 @@ mod gdt;
 
 mod artist_init;
 
+mod progress;
+
 use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
 use machine::memory::MemChunk;
+use machine::framebuffer::Framebuffer;
+use machine::keyboard::{KeyCode, KeyDirection};
+use machine::{cmos, crashlog, driver, gamepad, mouse, serial, serial_println, time, vsync};
+use machine::interrupts::IRQ;
+use machine::driver::{DriverDescriptor, InitFn, InitStage};
+use machine::cmos::BootRecord;
+use event_hook::{EventKind, Event};
+use artist::println;
 use collections::allocator;
 use sound;
 use blasterball;
@@ -31,39 +44,233 @@ macro_rules! Mem {
 
 const APP_STACK_SIZE: u64 = Mem!(10, Mib);
 
-const APP_HEAP_SIZE: u64 = Mem!(10, Mib);
+/// Sound effects are decoded onto the heap, along with the off-screen targets.
+/// The music is streamed from its file, so it takes none
+const APP_HEAP_SIZE: u64 = Mem!(12, Mib);
+
+/// What the BIOS and UEFI entry points found out about the machine,
+/// which is all the game needs from them
+#[derive(Clone, Copy)]
+struct BootInfo {
+    stack_mem: MemChunk,
+    heap_mem: MemChunk,
+    /// The memory the screen is drawn from
+    screen_buffer: Framebuffer
+}
 
-fn setup_memory_and_run_game(stack_mem: MemChunk, heap_mem: MemChunk) -> ! {
+fn setup_memory_and_run_game(boot_info: BootInfo) -> ! {
     
     // Changing the stack pointer
-    // Need to save heap_mem so it can be used later
+    // Need to save boot_info so it can be used later
     unsafe {
         asm!("
             mov rdi, {}
             mov rsp, {}",
-            in(reg) &heap_mem as *const _ as u64,
-            in(reg) stack_mem.range().end_addr.as_u64() - 1,
+            in(reg) &boot_info as *const _ as u64,
+            in(reg) boot_info.stack_mem.range().end_addr.as_u64() - 1,
         );
     }
     
-    let heap_mem_addr: u64;
+    let boot_info_addr: u64;
     unsafe { 
         asm!("
             mov {}, rdi
             ",
-            out(reg) heap_mem_addr,
+            out(reg) boot_info_addr,
         );
     }
-    let heap_mem = unsafe { *(heap_mem_addr as *const MemChunk) };
-    // It's important that the GDT is initialized before the interrupts
+    let boot_info = unsafe { *(boot_info_addr as *const BootInfo) };
+    unsafe { HEAP_MEM = Some(boot_info.heap_mem); }
+    artist_init::init(boot_info.screen_buffer);
+    for descriptor in DRIVERS {
+        if let Err(msg) = driver::register(descriptor) {
+            progress::halt_with_error(descriptor.name, msg);
+        }
+    }
+    init_drivers(InitStage::Core);
+    unsafe { BOOT_RECORD = Some(check_previous_session()); }
+    select_interrupt_controller();
+    init_drivers(InitStage::Devices);
+    progress::required_stage("Assets", blasterball::load_assets);
+
+    blasterball::game_entry_point();
+}
+
+/// The drivers set up while booting, in the order they're set up in
+/// when they don't depend on each other
+const DRIVERS: [DriverDescriptor; 9] = [
     // The interrupts make use of the GDT
-    gdt::init();
-    // The allocator must be initialized before the interrupts
-    // because the interrupts use the event hooker which in turn uses
-    // the allocator
+    DriverDescriptor { name: "GDT", stage: InitStage::Core, depends_on: &[], init: InitFn::Infallible(gdt::init), fallback: None, required: true },
+    DriverDescriptor { name: "Allocator", stage: InitStage::Core, depends_on: &[], init: InitFn::Fallible(init_allocator), fallback: None, required: true },
+    // The event hooker keeps its hooks on the heap
+    DriverDescriptor { name: "Event hook", stage: InitStage::Core, depends_on: &["Allocator"], init: InitFn::Infallible(event_hook::init), fallback: None, required: true },
+    // The interrupt handlers send events through the event hooker
+    DriverDescriptor { name: "Interrupts", stage: InitStage::Core, depends_on: &["GDT", "Event hook"], init: InitFn::Infallible(interrupts::init), fallback: None, required: true },
+    // Time is kept in timer ticks if the time stamp counter can't be measured
+    DriverDescriptor { name: "Timer", stage: InitStage::Core, depends_on: &[], init: InitFn::Fallible(time::init), fallback: None, required: false },
+    // The game can still beep through the PC speaker without the sound device
+    DriverDescriptor { name: "Sound", stage: InitStage::Devices, depends_on: &["Interrupts"], init: InitFn::Fallible(init_sound), fallback: Some(InitFn::Fallible(offer_software_sound)), required: false },
+    // The paddle can still be moved with the keyboard without a mouse
+    DriverDescriptor { name: "Mouse", stage: InitStage::Devices, depends_on: &["Interrupts"], init: InitFn::Fallible(init_mouse), fallback: None, required: false },
+    DriverDescriptor { name: "Gamepad", stage: InitStage::Devices, depends_on: &[], init: InitFn::Fallible(gamepad::init), fallback: None, required: false },
+    // Displays that don't show the retrace are taken to refresh at 60Hz
+    DriverDescriptor { name: "Vsync", stage: InitStage::Devices, depends_on: &[], init: InitFn::Fallible(vsync::init), fallback: Some(InitFn::Infallible(vsync::init_timed)), required: false }
+];
+
+/// The memory the allocator hands out, found by the entry points
+static mut HEAP_MEM: Option<MemChunk> = None;
+/// The boot record the previous session left, read once the core drivers are set up
+static mut BOOT_RECORD: Option<BootRecord> = None;
+
+/// Sets up the drivers of `stage`, showing each one on the screen,
+/// and stops booting if one that's required fails
+fn init_drivers(stage: InitStage) {
+    if let Err(err) = driver::init_stage(stage, |name, init| progress::stage(name, || init.call())) {
+        progress::halt_with_error(err.driver(), err.msg());
+    }
+}
+
+fn init_allocator() -> Result<(), &'static str> {
+    let heap_mem = unsafe { HEAP_MEM }.ok_or("No memory was found for the heap")?;
     allocator::init(heap_mem);
-    interrupts::init();
-    sound::init().unwrap();
+    Ok(())
+}
 
-    blasterball::game_entry_point();
+fn init_mouse() -> Result<(), &'static str> {
+    mouse::init()?;
+    interrupts::enable_irq(IRQ::Mouse)
+}
+
+/// Records the boot in the CMOS and, if the previous session crashed,
+/// offers to show the crash log and asks whether debug logging over
+/// the serial port should be enabled
+///
+/// Returns the boot record left by the previous session
+fn check_previous_session() -> BootRecord {
+    let boot_record = cmos::record_boot();
+    if boot_record.serial_debug_enabled() {
+        serial::enable_logging();
+    }
+    serial_println!("Boot {}", boot_record.boot_count);
+    // The crash log survives a warm reset even when the CMOS record
+    // of the crash is lost, so it's checked on its own
+    if let Some(message) = crashlog::previous_crash() {
+        serial_println!("Previous crash: {}", message);
+        println!("A crash log from the previous session was found.");
+        println!("Show it? (y/n)");
+        if ask_yes_no() {
+            println!("{}", message);
+            println!("Press any key to continue.");
+            wait_for_key();
+        }
+        crashlog::clear();
+    }
+    if !boot_record.previous_session_crashed() {
+        return boot_record;
+    }
+    println!("The previous session crashed.");
+    println!("Enable serial debug? (y/n)");
+    let enable_serial_debug = ask_yes_no();
+    cmos::set_serial_debug(enable_serial_debug);
+    if enable_serial_debug {
+        serial::enable_logging();
+    }
+    boot_record
+}
+
+/// Hands interrupt delivery over to the APICs if the boot option for it is set,
+/// for machines whose PCI interrupts never reach the PICs
+///
+/// While serial debug is enabled, the option is asked for the first time,
+/// and again after a crash, in case the APICs or the PICs were the cause.
+/// The answer is kept across boots. The devices are set up after this,
+/// so they're routed to whichever is in use
+fn select_interrupt_controller() {
+    let boot_record = unsafe { BOOT_RECORD };
+    let mut use_apic = boot_record.map_or(false, |record| record.apic_enabled());
+    let ask = boot_record.map_or(true, |record| !record.apic_chosen() || record.previous_session_crashed());
+    if serial::logging_enabled() && ask {
+        println!("Deliver interrupts with the APIC instead of the PIC? (y/n)");
+        use_apic = ask_yes_no();
+        cmos::set_apic(use_apic);
+    }
+    if use_apic {
+        // The PICs are still in use if it fails
+        let _ = progress::stage("APIC", interrupts::switch_to_apic);
+    }
+}
+
+/// Sets up the sound device, or the software mixer if the boot option for it is set
+///
+/// The software mixer is for testing under QEMU without the HDA device, so the
+/// option is only offered, and only applies, while serial debug is enabled
+fn init_sound() -> Result<(), &'static str> {
+    let software_sound = unsafe { BOOT_RECORD }.map_or(false, |record| record.software_sound_enabled());
+    if software_sound && serial::logging_enabled() {
+        serial_println!("Mixing sound in memory");
+        return sound::init_software();
+    }
+    sound::init()
+}
+
+/// Asks whether sound should be mixed in memory from now on, since the
+/// sound device couldn't be set up, and sets the software mixer up if so
+fn offer_software_sound() -> Result<(), &'static str> {
+    if !serial::logging_enabled() {
+        return Err("Sound can only be mixed in memory while serial debug is enabled");
+    }
+    println!("Sound can't be played. Mix sound in memory from now on? (y/n)");
+    if !ask_yes_no() {
+        return Err("Sound isn't mixed in memory");
+    }
+    cmos::set_software_sound(true);
+    progress::stage("Software sound", sound::init_software)
+}
+
+/// Set by `ask_yes_no`'s keyboard handler once Y or N has been pressed
+static ANSWERED: AtomicBool = AtomicBool::new(false);
+/// Whether the key `ask_yes_no` was answered with was Y
+static ANSWER: AtomicBool = AtomicBool::new(false);
+/// Set by `wait_for_key`'s keyboard handler once a key has been pressed
+static KEY_PRESSED: AtomicBool = AtomicBool::new(false);
+
+/// Waits for the Y or N key to be pressed and returns true if it was Y
+///
+/// The answer is set by the keyboard interrupt handler while the loop waits
+/// for it, so it's kept in atomics, which the loop can't keep in a register.
+/// The handler is hooked without the heap, so the question can be asked
+/// however far booting got
+fn ask_yes_no() -> bool {
+    ANSWERED.store(false, Ordering::SeqCst);
+    let answer_hook = event_hook::hook_early_event(EventKind::Keyboard, |event| {
+        if let Event::Keyboard(keycode, direction, _modifiers) = event {
+            if direction == KeyDirection::Down && matches!(keycode, KeyCode::Y | KeyCode::N) {
+                ANSWER.store(keycode == KeyCode::Y, Ordering::SeqCst);
+                ANSWERED.store(true, Ordering::SeqCst);
+            }
+        }
+    }).expect("Failed to hook the keyboard handler for the answer");
+    while !ANSWERED.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+    event_hook::unhook_early_event(answer_hook, EventKind::Keyboard).unwrap();
+    ANSWER.load(Ordering::SeqCst)
+}
+
+/// Waits for any key to be pressed
+///
+/// Like `ask_yes_no`'s answer, whether a key was pressed is kept in an atomic
+fn wait_for_key() {
+    KEY_PRESSED.store(false, Ordering::SeqCst);
+    let key_hook = event_hook::hook_early_event(EventKind::Keyboard, |event| {
+        if let Event::Keyboard(_keycode, direction, _modifiers) = event {
+            if direction == KeyDirection::Down {
+                KEY_PRESSED.store(true, Ordering::SeqCst);
+            }
+        }
+    }).expect("Failed to hook the keyboard handler for the key press");
+    while !KEY_PRESSED.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+    event_hook::unhook_early_event(key_hook, EventKind::Keyboard).unwrap();
 }
@@ -0,0 +1,47 @@
+//! The stages of setting the machine up, shown on the screen as they're done
+//!
+//! Each stage is printed when it starts and marked once it's over, so a
+//! machine that stops while booting shows how far it got. A stage the game
+//! can't run without stops the boot with its error left on the screen,
+//! instead of a panic
+
+use artist::{print, println};
+use machine::serial_println;
+use machine::instructions::interrupts;
+
+/// Runs `f` as the stage `name`, marking it as done or failed
+///
+/// A failed stage's error is printed under it
+pub fn stage<T>(name: &str, f: impl FnOnce() -> Result<T, &'static str>) -> Result<T, &'static str> {
+    print!("{}... ", name);
+    let result = f();
+    match result {
+        Ok(_) => println!("ok"),
+        Err(msg) => {
+            println!("failed");
+            println!("  {}", msg);
+            serial_println!("Boot stage {} failed: {}", name, msg);
+        }
+    }
+    result
+}
+
+/// Runs `f` as the stage `name`, which the game can't run without,
+/// and stops booting if it fails
+pub fn required_stage<T>(name: &str, f: impl FnOnce() -> Result<T, &'static str>) -> T {
+    match stage(name, f) {
+        Ok(val) => val,
+        Err(msg) => halt_with_error(name, msg)
+    }
+}
+
+/// Leaves the reason the boot can't go on on the screen and does nothing else
+pub fn halt_with_error(stage: &str, msg: &str) -> ! {
+    println!();
+    println!("The game can't start because {} failed:", stage);
+    println!("{}", msg);
+    println!("Turn the computer off or restart it to try again.");
+    loop {
+        interrupts::enable_and_hlt();
+    }
+}
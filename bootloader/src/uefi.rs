@@ -1,9 +1,13 @@
 use machine::FRAMEBUFFER;
+use machine::framebuffer::Framebuffer;
 use machine::memory::{Addr, EFIMemRegionType, MemChunk};
 use machine::uefi;
+use machine::crashlog::{self, CRASH_LOG_ADDR, CRASH_LOG_SIZE};
 use machine::uefi::EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID;
+use machine::uefi::gop;
+use artist::{SCREEN_WIDTH, SCREEN_HEIGHT};
 use crate::{APP_STACK_SIZE, APP_HEAP_SIZE};
-use crate::{setup_memory_and_run_game};
+use crate::{setup_memory_and_run_game, BootInfo};
 
 
 machine::efi_entry_point!(main);
@@ -18,13 +22,20 @@ fn main(image_handle: machine::uefi::EFIHandle) -> ! {
     init_framebuffer(framebuffer);
 
     let (stack_mem, heap_mem) = alloc_game_mem().unwrap();
+    reserve_crash_log();
     let boot_services = systable.boot_services();
-    boot_services.exit_boot_services(image_handle).unwrap();
-    setup_memory_and_run_game(stack_mem, heap_mem);
+    if let Err(msg) = boot_services.exit_boot_services(image_handle) {
+        panic!("Failed to exit boot services: {}", msg);
+    }
+    setup_memory_and_run_game(BootInfo {
+        stack_mem,
+        heap_mem,
+        screen_buffer: framebuffer
+    });
 }
 
-/// Initializes the graphics mode to a 640x480 mode
-fn init_graphics() -> Result<Addr, &'static str> {
+/// Sets the graphics mode to the best one the screen fits in
+fn init_graphics() -> Result<Framebuffer, &'static str> {
     let systable = uefi::get_systable();
     if systable.is_none() {
         return Err("System table is not initialized");
@@ -34,23 +45,30 @@ fn init_graphics() -> Result<Addr, &'static str> {
     // To change the graphics mode
     // The GOP (Graphics Output Protocol) needs to be located
     let gop = boot_services.locate_protocol(&EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID)?;
-    let max_mode = gop.mode().max_mode();
-    let mut mode_no = 0;
-    loop {
-        if mode_no == max_mode {
-            return Err("Couldn't find a mode with the necessary requirements");
-        }
-        let mode_info = gop.query_mode(mode_no)?;
-        if mode_info.vertical_resolution() == 480 && mode_info.horizontal_resolution() == 640 {
-            gop.set_mode(mode_no)?;
-            let framebuffer = Addr::new(gop.mode().frame_buffer_base());
-            crate::artist_init::init(framebuffer);
-            return Ok(framebuffer)
-        }
-        mode_no += 1;
-    }
+    let mode = gop::pick_mode(
+        gop.modes(),
+        gop.mode().current_mode(),
+        gop::preferred_resolution(),
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT
+    ).ok_or("Couldn't find a mode with the necessary requirements")?;
+    gop.set_mode(mode.number)?;
+    // Rows can be padded, so they may be further apart than the width,
+    // and the colors in a pixel can be in any order
+    Ok(Framebuffer::new(
+        Addr::new(gop.mode().frame_buffer_base()),
+        gop.mode().frame_buffer_size(),
+        mode.width,
+        mode.height,
+        mode.stride,
+        4,
+        mode.pixel_format
+    ))
 }
 
+/// The highest address the heap can be allocated at
+const MAX_HEAP_ADDR: u64 = 0xffff_ffff;
+
 fn alloc_game_mem() -> Result<(MemChunk, MemChunk), &'static str> {
     let systable = uefi::get_systable();
     if systable.is_none() {
@@ -59,11 +77,28 @@ fn alloc_game_mem() -> Result<(MemChunk, MemChunk), &'static str> {
     let systable = systable.unwrap();
     let boot_services = systable.boot_services();
     let stack_mem = boot_services.alloc_mem(EFIMemRegionType::LoaderData, APP_STACK_SIZE as usize)?;
-    let heap_mem = boot_services.alloc_mem(EFIMemRegionType::LoaderData, APP_HEAP_SIZE as usize)?;
+    // Sound controllers without 64 bit addressing fetch their buffers from the heap,
+    // so it has to be below 4GiB. The BIOS boot path's heap is taken from the
+    // first usable region that fits, which is in low memory
+    let heap_pages = (APP_HEAP_SIZE / 4096) as usize;
+    let heap_mem = boot_services.alloc_pages_below(MAX_HEAP_ADDR, heap_pages, EFIMemRegionType::LoaderData)?;
     Ok((stack_mem, heap_mem))
 }
 
-fn init_framebuffer(fb: Addr) {
+/// Allocates the crash log region, so the firmware doesn't hand it out,
+/// and enables the crash log if that succeeded
+///
+/// The crash log is left disabled if the firmware already uses the region
+fn reserve_crash_log() {
+    let systable = uefi::get_systable().unwrap();
+    let boot_services = systable.boot_services();
+    let pages = (CRASH_LOG_SIZE / 4096) as usize;
+    if boot_services.alloc_pages_at(CRASH_LOG_ADDR, pages, EFIMemRegionType::LoaderData).is_ok() {
+        unsafe { crashlog::enable(); }
+    }
+}
+
+fn init_framebuffer(fb: Framebuffer) {
     FRAMEBUFFER.call_once(|| fb);
 }
 
@@ -71,6 +106,13 @@ fn init_framebuffer(fb: Addr) {
 #[allow(dead_code)]
 #[cfg_attr(not(test), panic_handler)]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+    machine::cmos::record_crash();
+    machine::serial_println!("{}", info);
+    #[cfg(feature = "port_audit")]
+    machine::trace::dump();
+    if let Some(mut crash_log) = crashlog::writer() {
+        let _ = write!(crash_log, "{}", info);
+    }
     if FRAMEBUFFER.get().is_some() {
         // The printer can't be used until the
         // FRAMEBUFFER has been initialized
@@ -83,7 +125,7 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
 use core::sync::atomic::{AtomicUsize, Ordering};
 use core::fmt;
 use::core::fmt::Write;
-use artist::{FONT_WIDTH, FONT_HEIGHT, X_SCALE, Y_SCALE, SCREEN_WIDTH, Color};
+use artist::{FONT_WIDTH, FONT_HEIGHT, X_SCALE, Y_SCALE, Color};
 static X_POS: AtomicUsize = AtomicUsize::new(0);
 static Y_POS: AtomicUsize = AtomicUsize::new(0);
 use artist::font;
@@ -103,11 +145,10 @@ impl fmt::Write for Printer {
 // Quick and dirty printing
 impl Printer {
     pub fn print_char(&mut self, c: u8) {
-        let framebuffer = FRAMEBUFFER.get();
-        if framebuffer.is_none() {
-            return;
-        }
-        let vga = framebuffer.unwrap().as_mut_ptr() as *mut Color;
+        let framebuffer = match FRAMEBUFFER.get() {
+            Some(framebuffer) => framebuffer,
+            None => return
+        };
         let curr_x = X_POS.load(Ordering::Relaxed);
         let curr_y = Y_POS.load(Ordering::Relaxed);
         if c == b'\n' {
@@ -121,11 +162,16 @@ impl Printer {
                     for x in 0..FONT_WIDTH {
                         let j = x + 1;
                         for xp in x * X_SCALE..j * X_SCALE {
+                            let pixel = match framebuffer.pixel_addr(curr_x + xp, curr_y + yp) {
+                                Some(addr) => addr.as_mut_ptr() as *mut Color,
+                                // Off the screen
+                                None => continue
+                            };
                             unsafe {
                                 if byte & (1 << (FONT_WIDTH - x - 1)) == 0 {
-                                    *vga.offset(((curr_y + yp)*SCREEN_WIDTH+xp+curr_x) as isize) = Color::new(Color::BLUE);
+                                    *pixel = Color::new(Color::BLUE);
                                 } else {
-                                    *vga.offset(((curr_y + yp)*SCREEN_WIDTH+xp+curr_x) as isize) = Color::new(Color::BLACK);
+                                    *pixel = Color::new(Color::BLACK);
                                 }
                             }
                         }
@@ -133,7 +179,7 @@ impl Printer {
                 }
             }
             X_POS.store(curr_x + FONT_WIDTH * X_SCALE, Ordering::Relaxed);
-            if X_POS.load(Ordering::Relaxed) >= SCREEN_WIDTH {
+            if X_POS.load(Ordering::Relaxed) >= framebuffer.width() {
                 X_POS.store(0, Ordering::Relaxed);
                 Y_POS.store(curr_y + FONT_HEIGHT * Y_SCALE, Ordering::Relaxed);
             }
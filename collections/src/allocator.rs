@@ -24,7 +24,8 @@ lazy_static! {
             head: ListNode {
                 size: 0,
                 next: None
-            }
+            },
+            size: 0
         }
     );
 }
@@ -37,11 +38,25 @@ pub fn get_allocator() -> &'static Mutex<LinkedListAllocator> {
 /// Creates a new LinkedListAllocator, assuming that all memory
 /// in heap_mem's range is free
 pub fn init(heap_mem: MemChunk) {
+    let mut allocator = ALLOCATOR.lock();
+    allocator.size += heap_mem.size();
     unsafe {
-        ALLOCATOR.lock().add_free_region(heap_mem);
+        allocator.add_free_region(heap_mem);
     }
 }
 
+/// How much of the heap the allocator is managing is in use
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// How much of a heap is in use, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    pub used: usize,
+    pub size: usize
+}
+
 /// Representation of a free region of memory
 #[derive(PartialEq, Eq, Debug)]
 struct ListNode {
@@ -72,10 +87,20 @@ impl ListNode {
 /// stress references were giving me
 #[derive(Debug)]
 pub struct LinkedListAllocator {
-    head: ListNode
+    head: ListNode,
+    /// The size of all the memory the allocator was given to manage
+    size: u64
 }
 
 impl LinkedListAllocator {
+    /// How much of the memory the allocator manages is in use
+    ///
+    /// Walks the free list, so it takes longer the more fragmented the heap is
+    pub fn stats(&self) -> HeapStats {
+        let free: u64 = unsafe { self.iter() }.map(|region| region.size()).sum();
+        HeapStats { used: (self.size - free) as usize, size: self.size as usize }
+    }
+
     /// Searches the free list to find free memory of size `size`
     unsafe fn find_free_region(&mut self, size: usize) -> Option<*mut u8> {
         let size = size as u64;
@@ -85,8 +110,8 @@ impl LinkedListAllocator {
             if (*curr_node_ptr).size == size {
                 mem::swap(&mut (*(node_ptr_opt.unwrap())).next, &mut (*curr_node_ptr).next);
                 return Some((*curr_node_ptr).start_addr().as_mut_ptr());
-            } else if (*curr_node_ptr).size > size {
-                // Bigger
+            } else if (*curr_node_ptr).size >= size + mem::size_of::<ListNode>() as u64 {
+                // Bigger, with room for the node of what's left over
                 let mut new_node_ptr = ((*curr_node_ptr).start_addr() + size).as_u64() as *mut ListNode;
                 (*new_node_ptr).size = (*curr_node_ptr).size - size;
                 (*new_node_ptr).next = (*curr_node_ptr).next;
@@ -182,9 +207,21 @@ impl Iterator for FreeRegionIter {
     }
 }
 
+/// `size` rounded up to a multiple of a `ListNode`'s alignment,
+/// and to at least the size of one
+///
+/// Every block handed out and freed is rounded up, so a freed block
+/// has room for its `ListNode` and the free regions left over always
+/// start where a `ListNode` is aligned
+fn node_size(size: usize) -> usize {
+    let align = mem::align_of::<ListNode>();
+    let size = size.max(mem::size_of::<ListNode>());
+    (size + align - 1) / align * align
+}
+
 unsafe impl Allocator for Mutex<LinkedListAllocator> {
     unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
-        if let Some(mem_ptr) = self.lock().find_free_region(size_of_type * size_to_alloc) {
+        if let Some(mem_ptr) = self.lock().find_free_region(node_size(size_of_type * size_to_alloc)) {
             Ok(mem_ptr)
         } else {
             Err(Error::AllocationError)
@@ -194,7 +231,7 @@ unsafe impl Allocator for Mutex<LinkedListAllocator> {
     unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize)  -> Result<(), Error> {
         self.lock().add_free_region(MemChunk {
             start_addr: Addr::from_ptr(ptr),
-            size: size_to_dealloc as u64
+            size: node_size(size_to_dealloc) as u64
         });
         Ok(())
     }
@@ -216,7 +253,7 @@ mod tests {
         let v: Vec<u8> = Vec::with_capacity(vec_size, &allocator);
         unsafe {
             let new_heap_size = (*allocator.lock().head.next.unwrap()).size;
-            assert_eq!(new_heap_size as usize, FOUR_KIB - vec_size);
+            assert_eq!(new_heap_size as usize, FOUR_KIB - node_size(vec_size));
         }
     }
 
@@ -259,7 +296,7 @@ mod tests {
         let allocd_ptr = unsafe { allocator.alloc(4, 4).unwrap() };
         let mut iter = unsafe { allocator.lock().iter() };
         if let Some(MemChunk { size, .. }) = iter.next() {
-            assert_eq!(size as usize, FOUR_KIB - 4 * 4);
+            assert_eq!(size as usize, FOUR_KIB - node_size(4 * 4));
         }
         assert_eq!(None, iter.next());
 
@@ -279,7 +316,7 @@ mod tests {
         let v: Vec<Struct> = Vec::with_capacity(5, &allocator);
         let mut iter = unsafe { allocator.lock().iter() };
         if let Some(MemChunk { size, .. }) = iter.next() {
-            assert_eq!(size as usize, FOUR_KIB - 5 * mem::size_of::<Struct>());
+            assert_eq!(size as usize, FOUR_KIB - node_size(5 * mem::size_of::<Struct>()));
         }
         assert_eq!(None, iter.next());
         mem::drop(v);
@@ -290,14 +327,43 @@ mod tests {
         assert_eq!(None, iter.next());
     }
 
+    #[test]
+    fn test_heap_stats() {
+        let allocator = Mutex::new(get_4kib_allocator());
+        assert_eq!(allocator.lock().stats(), HeapStats { used: 0, size: FOUR_KIB });
+        let allocd_ptr = unsafe { allocator.alloc(4, 8).unwrap() };
+        let v: Vec<u8> = Vec::with_capacity(100, &allocator);
+        // The 100 bytes are rounded up to whole list nodes
+        assert_eq!(allocator.lock().stats().used, 4 * 8 + 104);
+        mem::drop(v);
+        unsafe { allocator.dealloc(allocd_ptr, 4 * 8).unwrap() };
+        assert_eq!(allocator.lock().stats(), HeapStats { used: 0, size: FOUR_KIB });
+    }
+
+    #[test]
+    fn test_blocks_are_rounded_up_to_list_nodes() {
+        assert_eq!((node_size(0), node_size(20), node_size(24), node_size(30)), (24, 24, 24, 32));
+        let allocator = Mutex::new(get_4kib_allocator());
+        let odd_ptrs: StdVec<*mut u8> = (1..8).map(|size| unsafe { allocator.alloc(1, size).unwrap() }).collect();
+        for ptr in &odd_ptrs {
+            assert_eq!(*ptr as usize % mem::align_of::<ListNode>(), 0);
+        }
+        for (size, ptr) in (1..8).zip(odd_ptrs) {
+            unsafe { allocator.dealloc(ptr, size).unwrap() };
+        }
+        assert_eq!(allocator.lock().stats().used, 0);
+    }
+
     fn get_4kib_allocator() -> LinkedListAllocator {
-        let mem: ManuallyDrop<StdVec<u8>> = ManuallyDrop::new(StdVec::with_capacity(FOUR_KIB));
+        // u64s, so the heap starts where a list node is aligned
+        let mem: ManuallyDrop<StdVec<u64>> = ManuallyDrop::new(StdVec::with_capacity(FOUR_KIB / 8));
         let mem_ptr = mem.as_ptr() as *mut u8;
         let mut allocator = LinkedListAllocator {
             head: ListNode {
                 size: 0,
                 next: None
-            }
+            },
+            size: FOUR_KIB as u64
         };
         unsafe {
             allocator.add_free_region(MemChunk {
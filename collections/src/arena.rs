@@ -0,0 +1,182 @@
+//! A bump allocator for short lived allocations that are all freed at once
+
+use core::cell::Cell;
+use core::ops::Drop;
+use crate::allocator::{Allocator, Error};
+
+/// Hands out memory from one chunk taken from another allocator, freeing
+/// all of it at once when it's reset
+///
+/// Meant for allocations that only last a frame, like dirty rect and collision
+/// candidate lists, which would otherwise fragment the heap. Collections can
+/// be created with an arena as their allocator. Memory given back with `dealloc`
+/// is only reused if it was the last allocation, so growing collections waste
+/// their old memory until the arena is reset
+pub struct Arena<'a> {
+    start_ptr: *mut u8,
+    /// The number of bytes in the chunk
+    capacity: usize,
+    /// The offset of the first free byte in the chunk
+    used: Cell<usize>,
+    /// The allocator the chunk was taken from
+    allocator: &'a dyn Allocator
+}
+
+impl<'a> Arena<'a> {
+    /// Every allocation starts on this boundary, which is enough for any type
+    /// the collections hold
+    const ALIGN: usize = 16;
+
+    /// Creates an arena that can hand out up to `capacity` bytes
+    ///
+    /// # Panics
+    ///
+    /// If there is no enough space on the heap
+    pub fn with_capacity(capacity: usize, allocator: &dyn Allocator) -> Arena {
+        // Room to align the start of the chunk
+        match unsafe { allocator.alloc(1, capacity + Self::ALIGN) } {
+            Ok(ptr) => Arena {
+                start_ptr: ptr,
+                capacity,
+                used: Cell::new(align_offset(ptr, Self::ALIGN)),
+                allocator
+            },
+            Err(_) => panic!("No enough space on the heap")
+        }
+    }
+
+    /// Frees everything that has been allocated in the arena
+    ///
+    /// Nothing allocated in the arena can still be around, since
+    /// collections in it borrow the arena
+    pub fn reset(&mut self) {
+        self.used.set(align_offset(self.start_ptr, Self::ALIGN));
+    }
+
+    /// Runs `f` with the arena and frees everything `f` allocated in it afterwards
+    pub fn scope<R>(&mut self, f: impl FnOnce(&Arena) -> R) -> R {
+        let used = self.used.get();
+        let result = f(self);
+        self.used.set(used);
+        result
+    }
+
+    /// The number of bytes that can still be allocated, ignoring alignment
+    pub fn remaining(&self) -> usize {
+        self.capacity + align_offset(self.start_ptr, Self::ALIGN) - self.used.get()
+    }
+}
+
+unsafe impl<'a> Allocator for Arena<'a> {
+    unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+        let size = size_of_type.checked_mul(size_to_alloc).ok_or(Error::AllocationError)?;
+        let start = self.used.get() + align_offset(self.start_ptr.add(self.used.get()), Self::ALIGN);
+        let end = start.checked_add(size).ok_or(Error::AllocationError)?;
+        if end > self.capacity + align_offset(self.start_ptr, Self::ALIGN) {
+            return Err(Error::AllocationError);
+        }
+        self.used.set(end);
+        Ok(self.start_ptr.add(start))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize) -> Result<(), Error> {
+        // Only the last allocation can be taken back without leaving a hole
+        if ptr.add(size_to_dealloc) == self.start_ptr.add(self.used.get()) {
+            self.used.set(self.used.get() - size_to_dealloc);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Arena<'a> {
+    fn drop(&mut self) {
+        if unsafe { self.allocator.dealloc(self.start_ptr, self.capacity + Self::ALIGN).is_err() } {
+            panic!("Couldn't free the arena's memory");
+        }
+    }
+}
+
+/// The number of bytes from `ptr` to the next `align` boundary
+fn align_offset(ptr: *mut u8, align: usize) -> usize {
+    let addr = ptr as usize;
+    (align - addr % align) % align
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Vec;
+    use std::vec::Vec as StdVec;
+    use core::mem::ManuallyDrop;
+
+    #[test]
+    fn test_alloc_aligned() {
+        let arena = Arena::with_capacity(256, &StdAllocator);
+        let a = unsafe { arena.alloc(1, 3).unwrap() };
+        let b = unsafe { arena.alloc(8, 2).unwrap() };
+        assert_eq!(a as usize % Arena::ALIGN, 0);
+        assert_eq!(b as usize % Arena::ALIGN, 0);
+        assert!(b as usize >= a as usize + 3);
+    }
+
+    #[test]
+    fn test_alloc_too_big() {
+        let arena = Arena::with_capacity(64, &StdAllocator);
+        assert!(unsafe { arena.alloc(1, 65) }.is_err());
+        assert!(unsafe { arena.alloc(1, 64) }.is_ok());
+        assert!(unsafe { arena.alloc(1, 1) }.is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut arena = Arena::with_capacity(64, &StdAllocator);
+        unsafe {
+            arena.alloc(4, 4).unwrap();
+            arena.alloc(4, 4).unwrap();
+        }
+        assert!(arena.remaining() < 64);
+        arena.reset();
+        assert_eq!(arena.remaining(), 64);
+    }
+
+    #[test]
+    fn test_scope() {
+        let mut arena = Arena::with_capacity(128, &StdAllocator);
+        let first = unsafe { arena.alloc(4, 4).unwrap() };
+        let remaining = arena.remaining();
+        let sum = arena.scope(|arena| {
+            let mut v: Vec<u32> = Vec::with_capacity(2, arena);
+            for i in 0..5 {
+                v.push(i);
+            }
+            v.iter().sum::<u32>()
+        });
+        assert_eq!(sum, 10);
+        assert_eq!(arena.remaining(), remaining);
+        // Allocations from before the scope are left alone
+        assert!(unsafe { arena.alloc(1, 1).unwrap() } as usize > first as usize);
+    }
+
+    #[test]
+    fn test_dealloc_last() {
+        let arena = Arena::with_capacity(64, &StdAllocator);
+        let remaining = arena.remaining();
+        let ptr = unsafe { arena.alloc(1, 16).unwrap() };
+        unsafe { arena.dealloc(ptr, 16).unwrap() };
+        assert_eq!(arena.remaining(), remaining);
+    }
+
+    struct StdAllocator;
+
+    unsafe impl Allocator for StdAllocator {
+        unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+            let mut v: ManuallyDrop<StdVec<u8>> = ManuallyDrop::new(StdVec::with_capacity(size_of_type * size_to_alloc));
+            Ok(v.as_mut_ptr())
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize) -> Result<(), Error> {
+            drop(StdVec::from_raw_parts(ptr, size_to_dealloc, size_to_dealloc));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Bulk copying of plain values
+//!
+//! Copying item by item in a loop was too slow for the large buffers of
+//! samples and pixels, so whole ranges of memory are moved at once with
+//! the string instructions, which recent processors run a cache line at a time
+
+use core::arch::asm;
+use core::mem;
+
+/// Copies the items in `src` into `dst`, which must be the same length
+///
+/// # Panics
+///
+/// If the slices' lengths are different
+pub fn copy_slice<T: Copy>(src: &[T], dst: &mut [T]) {
+    assert_eq!(src.len(), dst.len(), "The source and destination slices have different lengths");
+    // The borrows make sure the slices don't overlap
+    unsafe { copy_bytes(src.as_ptr().cast(), dst.as_mut_ptr().cast(), mem::size_of_val(src)) };
+}
+
+/// Copies `len` bytes from `src` to `dst`
+///
+/// # Safety
+///
+/// Both ranges must be valid for `len` bytes and must not overlap
+#[inline]
+pub unsafe fn copy_bytes(src: *const u8, dst: *mut u8, len: usize) {
+    asm!("
+        # Move 1 byte at a time from rsi to rdi, rcx times
+        rep movsb",
+        inout("rsi") src => _,
+        inout("rdi") dst => _,
+        inout("rcx") len => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_slice() {
+        let src: [u32; 5] = [1, 2, 3, 0xdead_beef, 5];
+        let mut dst = [0u32; 5];
+        copy_slice(&src, &mut dst);
+        assert_eq!(dst, src);
+
+        let mut empty: [u32; 0] = [];
+        copy_slice(&[], &mut empty);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_copy_slice_of_different_lengths() {
+        copy_slice(&[1u8, 2], &mut [0u8; 3]);
+    }
+}
@@ -0,0 +1,212 @@
+//! An allocator that fails when it's told to, for testing how code copes
+//! with running out of memory
+
+use core::cell::Cell;
+use crate::allocator::{Allocator, Error};
+
+/// Passes allocations on to another allocator, except the ones it's been told to fail
+///
+/// Allocations are counted from when the allocator is created, so a test can run
+/// the code once to count how many allocations it makes, then run it again
+/// failing each of them in turn. Deallocations are always passed on
+///
+/// # Example
+///
+/// ```
+/// use collections::allocator::{Allocator, Error};
+/// use collections::fault::FaultInjectionAllocator;
+/// use collections::queue::Queue;
+/// use std::vec::Vec as StdVec;
+/// use core::mem::ManuallyDrop;
+///
+/// struct StdAllocator;
+/// unsafe impl Allocator for StdAllocator {
+///     unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+///         let mut v: ManuallyDrop<StdVec<u8>> = ManuallyDrop::new(StdVec::with_capacity(size_of_type * size_to_alloc));
+///         Ok(v.as_mut_ptr())
+///     }
+///     unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize) -> Result<(), Error> {
+///         drop(StdVec::from_raw_parts(ptr, size_to_dealloc, size_to_dealloc));
+///         Ok(())
+///     }
+/// }
+///
+/// let allocator = FaultInjectionAllocator::new(&StdAllocator);
+/// let mut queue: Queue<u8> = Queue::with_capacity(1, &allocator);
+/// queue.enqueue(1);
+/// // The queue has to grow to take another item
+/// allocator.fail_nth(0);
+/// assert_eq!(queue.try_enqueue(2), Err(2));
+/// assert_eq!(queue.try_enqueue(2), Ok(()));
+/// assert_eq!(allocator.failures(), 1);
+/// ```
+pub struct FaultInjectionAllocator<'a> {
+    inner: &'a dyn Allocator,
+    /// The number of allocations that have been asked for, including the failed ones
+    allocations: Cell<usize>,
+    /// The number of allocations that have been failed
+    failures: Cell<usize>,
+    /// The allocation to fail, counted like `allocations`
+    fail_at: Cell<Option<usize>>,
+    /// Whether the allocations after the one at `fail_at` fail too
+    keep_failing: Cell<bool>
+}
+
+impl<'a> FaultInjectionAllocator<'a> {
+    /// Creates an allocator that passes everything on to `inner`
+    /// until it's told to fail
+    pub fn new(inner: &'a dyn Allocator) -> Self {
+        Self {
+            inner,
+            allocations: Cell::new(0),
+            failures: Cell::new(0),
+            fail_at: Cell::new(None),
+            keep_failing: Cell::new(false)
+        }
+    }
+
+    /// Creates an allocator that fails its `n`th allocation, counting from 0
+    pub fn failing_nth(inner: &'a dyn Allocator, n: usize) -> Self {
+        let allocator = Self::new(inner);
+        allocator.fail_nth(n);
+        allocator
+    }
+
+    /// Fails the `n`th allocation from now, counting from 0, and only that one
+    pub fn fail_nth(&self, n: usize) {
+        self.fail_at.set(Some(self.allocations.get() + n));
+        self.keep_failing.set(false);
+    }
+
+    /// Fails the `n`th allocation from now, counting from 0, and every one after it
+    pub fn fail_from_nth(&self, n: usize) {
+        self.fail_at.set(Some(self.allocations.get() + n));
+        self.keep_failing.set(true);
+    }
+
+    /// Stops failing allocations
+    pub fn stop_failing(&self) {
+        self.fail_at.set(None);
+    }
+
+    /// The number of allocations that have been asked for, including the failed ones
+    pub fn allocations(&self) -> usize {
+        self.allocations.get()
+    }
+
+    /// The number of allocations that have been failed
+    pub fn failures(&self) -> usize {
+        self.failures.get()
+    }
+
+    fn should_fail(&self, allocation: usize) -> bool {
+        match self.fail_at.get() {
+            Some(fail_at) if self.keep_failing.get() => allocation >= fail_at,
+            Some(fail_at) => allocation == fail_at,
+            None => false
+        }
+    }
+}
+
+unsafe impl<'a> Allocator for FaultInjectionAllocator<'a> {
+    unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+        let allocation = self.allocations.get();
+        self.allocations.set(allocation + 1);
+        if self.should_fail(allocation) {
+            self.failures.set(self.failures.get() + 1);
+            return Err(Error::AllocationError);
+        }
+        self.inner.alloc(size_of_type, size_to_alloc)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize) -> Result<(), Error> {
+        self.inner.dealloc(ptr, size_to_dealloc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::Queue;
+    use crate::boxed::Box;
+    use crate::vec::Vec;
+    use std::vec::Vec as StdVec;
+    use core::mem::ManuallyDrop;
+
+    #[test]
+    fn test_fails_only_the_nth_allocation() {
+        let allocator = FaultInjectionAllocator::failing_nth(&StdAllocator, 1);
+        let results: StdVec<bool> = (0..4)
+            .map(|_| unsafe { allocator.alloc(1, 1) }.is_ok())
+            .collect();
+        assert_eq!(results, [true, false, true, true]);
+        assert_eq!(allocator.allocations(), 4);
+        assert_eq!(allocator.failures(), 1);
+    }
+
+    #[test]
+    fn test_fail_from_nth() {
+        let allocator = FaultInjectionAllocator::new(&StdAllocator);
+        assert!(unsafe { allocator.alloc(1, 1) }.is_ok());
+        allocator.fail_from_nth(1);
+        assert!(unsafe { allocator.alloc(1, 1) }.is_ok());
+        assert!(unsafe { allocator.alloc(1, 1) }.is_err());
+        assert!(unsafe { allocator.alloc(1, 1) }.is_err());
+        allocator.stop_failing();
+        assert!(unsafe { allocator.alloc(1, 1) }.is_ok());
+        assert_eq!(allocator.failures(), 2);
+    }
+
+    #[test]
+    fn test_queue_keeps_its_items_when_growing_fails() {
+        let allocator = FaultInjectionAllocator::new(&StdAllocator);
+        let mut queue: Queue<u32> = Queue::with_capacity(2, &allocator);
+        queue.enqueue(1);
+        queue.enqueue(2);
+        // Every growth from now on fails
+        allocator.fail_from_nth(0);
+        for i in 3..10 {
+            assert_eq!(queue.try_enqueue(i), Err(i));
+        }
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.try_enqueue(3), Ok(()));
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_box_panics_instead_of_using_a_failed_allocation() {
+        let allocator = FaultInjectionAllocator::failing_nth(&StdAllocator, 0);
+        let _b: Box<usize> = Box::new(1, &allocator);
+    }
+
+    #[test]
+    fn test_vec_is_left_intact_when_growing_fails() {
+        let allocator = FaultInjectionAllocator::new(&StdAllocator);
+        let mut v: Vec<u32> = Vec::with_capacity(1, &allocator);
+        v.push(1);
+        allocator.fail_nth(0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| v.push(2)));
+        assert!(result.is_err());
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0], 1);
+        v.push(2);
+        assert_eq!(v.len(), 2);
+    }
+
+    struct StdAllocator;
+
+    unsafe impl Allocator for StdAllocator {
+        unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+            let mut v: ManuallyDrop<StdVec<u8>> = ManuallyDrop::new(StdVec::with_capacity(size_of_type * size_to_alloc));
+            Ok(v.as_mut_ptr())
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize) -> Result<(), Error> {
+            drop(StdVec::from_raw_parts(ptr, size_to_dealloc, size_to_dealloc));
+            Ok(())
+        }
+    }
+}
@@ -7,4 +7,7 @@ pub mod vec;
 pub mod allocator;
 pub mod boxed;
 pub mod queue;
+pub mod arena;
+pub mod fault;
+pub mod copy;
 pub use allocator::Allocator;
\ No newline at end of file
@@ -43,6 +43,8 @@ impl<'a, T: Clone> Queue<'a, T> {
     
     /// Places an item at the back of the queue
     ///
+    /// Items are never dropped or overwritten. When the queue is full, it grows.
+    ///
     /// # Analysis
     /// Takes O(1) amortized time
     ///
@@ -54,28 +56,54 @@ impl<'a, T: Clone> Queue<'a, T> {
     /// This function panics in the event where more memory is needed for the queue
     /// but the allocator fails to provide it
     pub fn enqueue(&mut self, item: T) {
-        if self.len >= self.capacity {
-            let new_size = self.capacity * 2;
-            let old_size = self.capacity;
-            let old_start_ptr = self.start_ptr as *mut u8;
-            let alloc_result = unsafe { self.allocator.alloc(mem::size_of::<T>(), new_size) };
-            let len = self.len;
-            if alloc_result.is_err() {
-                panic!("No enough space on the heap.");
-            }
-            let new_start_ptr = alloc_result.unwrap() as *mut T;
-            for i in 0..self.len as isize {
-                unsafe {
-                    new_start_ptr.offset(i).write(self.dequeue().unwrap());
-                }
+        if self.is_full() && self.grow().is_err() {
+            panic!("No enough space on the heap.");
+        }
+        self.enqueue_without_growing(item);
+    }
+
+    /// Does the same as enqueue, but gives the item back instead of panicking
+    /// if the queue is full and the allocator fails to provide more memory
+    ///
+    /// For callers that would rather count or log an item that couldn't be queued
+    /// than bring the whole system down
+    pub fn try_enqueue(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() && self.grow().is_err() {
+            return Err(item);
+        }
+        self.enqueue_without_growing(item);
+        Ok(())
+    }
+
+    /// Copies the items into a newly allocated location with 2x the capacity
+    ///
+    /// The queue is left untouched if the allocation fails
+    fn grow(&mut self) -> Result<(), ()> {
+        let new_size = (self.capacity * 2).max(1);
+        let old_size = self.capacity;
+        let old_start_ptr = self.start_ptr as *mut u8;
+        let alloc_result = unsafe { self.allocator.alloc(mem::size_of::<T>(), new_size) };
+        let len = self.len;
+        if alloc_result.is_err() {
+            return Err(());
+        }
+        let new_start_ptr = alloc_result.unwrap() as *mut T;
+        for i in 0..self.len as isize {
+            unsafe {
+                new_start_ptr.offset(i).write(self.dequeue().unwrap());
             }
-            unsafe { self.allocator.dealloc(old_start_ptr, old_size * mem::size_of::<T>()).unwrap() };
-            self.len = len;
-            self.capacity = new_size;
-            self.start_ptr = new_start_ptr as *mut T;
-            self.front_ptr = new_start_ptr as *mut T;
-            unsafe { self.back_ptr = self.start_ptr.offset(self.len as isize) };
         }
+        unsafe { self.allocator.dealloc(old_start_ptr, old_size * mem::size_of::<T>()).unwrap() };
+        self.len = len;
+        self.capacity = new_size;
+        self.start_ptr = new_start_ptr as *mut T;
+        self.front_ptr = new_start_ptr as *mut T;
+        unsafe { self.back_ptr = self.start_ptr.offset(self.len as isize) };
+        Ok(())
+    }
+
+    /// Places an item at the back of the queue, which must not be full
+    fn enqueue_without_growing(&mut self, item: T) {
         unsafe {
             self.back_ptr.write(item);
             // Items in the queue's chunk of memory can only be in indexes 0..=capacity - 1
@@ -117,11 +145,35 @@ impl<'a, T: Clone> Queue<'a, T> {
         }
     }
 
+    /// Returns a reference to the item at the front of the queue, if there is any,
+    /// without removing it
+    ///
+    /// # Analysis
+    ///
+    /// Takes O(1) time
+    pub fn peek(&self) -> Option<&T> {
+        if self.len == 0 {
+            None
+        } else {
+            unsafe { Some(&*self.front_ptr) }
+        }
+    }
+
     /// Returns the number of items in the queue
     pub fn len(&self) -> usize {
         self.len
     }
 
+    /// Checks if there are no items in the queue
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks if the queue has no space left, so the next enqueue will have to grow it
+    pub fn is_full(&self) -> bool {
+        self.len >= self.capacity
+    }
+
     /// Returns the capacity of the queue
     pub fn capacity(&self) -> usize {
         self.capacity
@@ -291,6 +343,46 @@ mod tests {
         assert_eq!(queue.dequeue(), Some(56));
     }
 
+    #[test]
+    fn test_peek() {
+        let mut queue: Queue<u8> = Queue::with_capacity(2, &AlwaysSuccessfulAllocator);
+        assert_eq!(queue.peek(), None);
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert_eq!(queue.peek(), Some(&1));
+        assert_eq!(queue.len(), 2);
+        queue.dequeue();
+        queue.enqueue(3);
+        // The front has wrapped around by now
+        queue.dequeue();
+        assert_eq!(queue.peek(), Some(&3));
+    }
+
+    #[test]
+    fn test_is_full_is_empty() {
+        let mut queue: Queue<u8> = Queue::with_capacity(2, &AlwaysSuccessfulAllocator);
+        assert!(queue.is_empty());
+        assert!(!queue.is_full());
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert!(!queue.is_empty());
+        assert!(queue.is_full());
+        queue.enqueue(3);
+        assert!(!queue.is_full());
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn test_try_enqueue_alloc_fail() {
+        let cond_failure_allocator = ConditionalFailureAllocator { should_fail: false };
+        let mut queue: ManuallyDrop<Queue<u32>> = ManuallyDrop::new(Queue::with_capacity(1, &cond_failure_allocator));
+        assert_eq!(queue.try_enqueue(3), Ok(()));
+        unsafe { mutate_cond_fail_alloc!(cond_failure_allocator, should_fail => true) };
+        assert_eq!(queue.try_enqueue(4), Err(4));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.dequeue(), Some(3));
+    }
+
     struct AlwaysSuccessfulAllocator;
 
     use std::vec::Vec as StdVec;
@@ -1,11 +1,12 @@
 //! A contiguous growable array with heap-allocated contents
 
 use core::ops::{Drop, Index, IndexMut};
-use core::cmp::PartialEq;
+use core::cmp::{PartialEq, Ordering};
 use core::iter::Iterator;
 use core::mem;
 use core::fmt;
 use crate::allocator::Allocator;
+use crate::copy;
 
 pub struct Vec<'a, T: Clone> {
     len: usize,
@@ -50,28 +51,63 @@ impl<'a, T: Clone> Vec<'a, T> {
     /// If there is no enough space on the heap
     pub fn push(&mut self, item: T) {
         if self.len >= self.capacity {
-            let new_size = self.capacity * 2;
-            let old_size = self.capacity;
-            let old_start_ptr = self.start_ptr as *mut u8;
-            let alloc_result = unsafe { self.allocator.alloc(mem::size_of::<T>(), new_size) };
-            if alloc_result.is_err() {
-                panic!("No enough space on the heap.");
-            }
-            let new_start_ptr = alloc_result.unwrap() as *mut T;
-            for i in 0..self.len {
-                unsafe {
-                    let val = self.start_ptr.offset(i as isize).read();
-                    new_start_ptr.offset(i as isize).write(val);
-                }
-            }
-            unsafe { self.allocator.dealloc(old_start_ptr, old_size * mem::size_of::<T>()).unwrap() };
-            self.capacity = new_size;
-            self.start_ptr = new_start_ptr as *mut T;   
+            self.grow((self.capacity * 2).max(1));
         }
         unsafe { self.start_ptr.offset(self.len as isize).write(item) };
         self.len += 1;
     }
 
+    /// Appends all the items in `items` to the end of the vector, copying
+    /// them all at once instead of one at a time
+    ///
+    /// # Panics
+    ///
+    /// If there is no enough space on the heap
+    pub fn extend_from_slice(&mut self, items: &[T])
+        where T: Copy
+    {
+        let new_len = self.len + items.len();
+        if new_len > self.capacity {
+            self.grow(new_len.max(self.capacity * 2));
+        }
+        let dst = unsafe { core::slice::from_raw_parts_mut(self.start_ptr.add(self.len), items.len()) };
+        copy::copy_slice(items, dst);
+        self.len = new_len;
+    }
+
+    /// Overwrites all the items in the vector with the items in `items`,
+    /// copying them all at once
+    ///
+    /// # Panics
+    ///
+    /// If `items` isn't as long as the vector
+    pub fn copy_from_slice(&mut self, items: &[T])
+        where T: Copy
+    {
+        let dst = unsafe { core::slice::from_raw_parts_mut(self.start_ptr, self.len) };
+        copy::copy_slice(items, dst);
+    }
+
+    /// Moves the items into a new allocation that can hold `new_capacity` items
+    ///
+    /// # Panics
+    ///
+    /// If there is no enough space on the heap, in which case the vector is left as it was
+    fn grow(&mut self, new_capacity: usize) {
+        let old_size = self.capacity;
+        let old_start_ptr = self.start_ptr as *mut u8;
+        let alloc_result = unsafe { self.allocator.alloc(mem::size_of::<T>(), new_capacity) };
+        if alloc_result.is_err() {
+            panic!("No enough space on the heap.");
+        }
+        let new_start_ptr = alloc_result.unwrap() as *mut T;
+        // The items are moved, so they can be copied whatever their type
+        unsafe { copy::copy_bytes(old_start_ptr, new_start_ptr as *mut u8, self.len * mem::size_of::<T>()) };
+        unsafe { self.allocator.dealloc(old_start_ptr, old_size * mem::size_of::<T>()).unwrap() };
+        self.capacity = new_capacity;
+        self.start_ptr = new_start_ptr;
+    }
+
     /// Removes an item from the end of the vector and returns it
     ///
     /// # Analysis
@@ -124,6 +160,84 @@ impl<'a, T: Clone> Vec<'a, T> {
         value
     }
 
+    /// Inserts an item at index idx, shifting all items after it downwards
+    ///
+    /// # Analysis
+    ///
+    /// Running time is O(n) because all items after index `idx` must be shifted
+    ///
+    /// # Panics
+    ///
+    /// When idx is greater than the length of the vector
+    pub fn insert(&mut self, idx: usize, item: T) {
+        if idx > self.len {
+            panic!("Invalid index");
+        }
+        self.push(item);
+        // Moving the new item from the end into its position
+        let items = unsafe { core::slice::from_raw_parts_mut(self.start_ptr, self.len) };
+        items[idx..].rotate_right(1);
+    }
+
+    /// Searches a sorted vector with a comparator function that tells whether
+    /// an item is less than, equal to or greater than the target
+    ///
+    /// Returns Ok with the index of a matching item if there is one,
+    /// or Err with the index where a matching item could be inserted
+    /// to keep the vector sorted
+    ///
+    /// # Analysis
+    ///
+    /// Running time is O(log n)
+    pub fn binary_search_by<F>(&self, mut f: F) -> Result<usize, usize>
+        where F: FnMut(&T) -> Ordering
+    {
+        let mut low = 0;
+        let mut high = self.len;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match f(&self[mid]) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(mid)
+            }
+        }
+        Err(low)
+    }
+
+    /// Inserts an item into a sorted vector, keeping it sorted according to
+    /// the comparator function
+    ///
+    /// The item is inserted after any items that compare equal to it,
+    /// so items that compare equal stay in the order they were inserted
+    ///
+    /// # Analysis
+    ///
+    /// Running time is O(n). Finding the position takes O(log n) time,
+    /// but the items after it still have to be shifted
+    pub fn insert_sorted_by<F>(&mut self, item: T, mut compare: F)
+        where F: FnMut(&T, &T) -> Ordering
+    {
+        let idx = match self.binary_search_by(|other| match compare(other, &item) {
+            Ordering::Equal => Ordering::Less,
+            ordering => ordering
+        }) {
+            Ok(idx) | Err(idx) => idx
+        };
+        self.insert(idx, item);
+    }
+
+    /// Inserts an item into a vector sorted in ascending order, keeping it sorted
+    ///
+    /// # Analysis
+    ///
+    /// Running time is O(n)
+    pub fn insert_sorted(&mut self, item: T)
+        where T: Ord
+    {
+        self.insert_sorted_by(item, |a, b| a.cmp(b));
+    }
+
     /// Returns the number of items in the vector
     pub fn len(&self) -> usize {
         self.len
@@ -292,6 +406,26 @@ mod tests {
         assert_eq!(v[1], 4);
     }
 
+    #[test]
+    fn test_extend_from_slice() {
+        let mut v = Vec::with_capacity(2, &AlwaysSuccessfulAllocator);
+        v.push(1u16);
+        v.extend_from_slice(&[2, 3, 4, 5]);
+        assert_eq!(v.len(), 5);
+        assert!(v.capacity() >= 5);
+        assert!(v.iter().copied().eq(1..=5));
+        v.extend_from_slice(&[]);
+        assert_eq!(v.len(), 5);
+    }
+
+    #[test]
+    fn test_copy_from_slice() {
+        let mut v = Vec::with_capacity(3, &AlwaysSuccessfulAllocator);
+        v.extend_from_slice(&[0u32; 3]);
+        v.copy_from_slice(&[7, 8, 9]);
+        assert!(v.iter().copied().eq([7, 8, 9]));
+    }
+
     #[test]
     fn test_pop() {
         let mut v = Vec::with_capacity(3, &AlwaysSuccessfulAllocator);
@@ -439,6 +573,51 @@ mod tests {
         assert_eq!(v.capacity(), other_v.capacity());
     }
 
+    #[test]
+    fn test_insert() {
+        let mut v = crate::vec![1, 3; &AlwaysSuccessfulAllocator];
+        v.insert(1, 2);
+        v.insert(0, 0);
+        v.insert(4, 4);
+        assert_eq!(v, crate::vec![0, 1, 2, 3, 4; &AlwaysSuccessfulAllocator]);
+        assert_eq!(v.len(), 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_invalid_index() {
+        let mut v = crate::vec![1; &AlwaysSuccessfulAllocator];
+        v.insert(2, 2);
+    }
+
+    #[test]
+    fn test_binary_search_by() {
+        let v = crate::vec![1, 3, 5, 7; &AlwaysSuccessfulAllocator];
+        assert_eq!(v.binary_search_by(|x| x.cmp(&5)), Ok(2));
+        assert_eq!(v.binary_search_by(|x| x.cmp(&0)), Err(0));
+        assert_eq!(v.binary_search_by(|x| x.cmp(&4)), Err(2));
+        assert_eq!(v.binary_search_by(|x| x.cmp(&8)), Err(4));
+
+        let v: Vec<u8> = Vec::with_capacity(1, &AlwaysSuccessfulAllocator);
+        assert_eq!(v.binary_search_by(|x| x.cmp(&1)), Err(0));
+    }
+
+    #[test]
+    fn test_insert_sorted() {
+        let mut v = Vec::with_capacity(1, &AlwaysSuccessfulAllocator);
+        for n in [5, 1, 4, 2, 3, 0] {
+            v.insert_sorted(n);
+        }
+        assert_eq!(v, crate::vec![0, 1, 2, 3, 4, 5; &AlwaysSuccessfulAllocator]);
+
+        // Items that compare equal keep their insertion order
+        let mut v = Vec::with_capacity(1, &AlwaysSuccessfulAllocator);
+        for item in [(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd')] {
+            v.insert_sorted_by(item, |a, b| a.0.cmp(&b.0));
+        }
+        assert_eq!(v, crate::vec![(1, 'b'), (1, 'd'), (2, 'a'), (2, 'c'); &AlwaysSuccessfulAllocator]);
+    }
+
     struct AlwaysSuccessfulAllocator;
 
     use std::vec::Vec as StdVec;
@@ -8,13 +8,13 @@ use core::ptr::NonNull;
 use core::fmt;
 use collections::allocator::Allocator;
 use collections::boxed::Box;
-use crate::Event;
+use crate::{Event, HandlerResult};
 
 /// The polymorphic representation of a base function which can stand in
 /// for any event handler function
 #[repr(C)]
 struct BaseFn {
-    call_boxed: fn(*const BoxedFn, Event) -> (),
+    call_boxed: fn(*const BoxedFn, Event) -> HandlerResult,
     drop: fn(*const BoxedFn) -> (),
     clone: fn(*const BoxedFn) -> BoxedFn
 }
@@ -27,9 +27,12 @@ pub struct BoxedFn<'a>(NonNull<BaseFn>, &'a dyn Allocator);
 impl<'a> BoxedFn<'a> {
     /// Creates a new BoxedFn from the given function and returns the
     /// polymorphic BaseFn
-    pub fn new<F>(func: F, allocator: &'a dyn Allocator) -> Self where F: FnMut(Event) {
+    ///
+    /// The function can return a `HandlerResult`, or nothing to let the
+    /// event go on to the next handler
+    pub fn new<F, R>(func: F, allocator: &'a dyn Allocator) -> Self where F: FnMut(Event) -> R, R: Into<HandlerResult> {
         let concrete_repr = Repr {
-            base: BaseFn { call_boxed: call_boxed::<F>, drop: drop::<F>, clone: clone::<F> },
+            base: BaseFn { call_boxed: call_boxed::<F, R>, drop: drop::<F>, clone: clone::<F, R> },
             func
         };
         let concrete_repr_ptr: *mut Repr<F> = Box::<Repr<F>>::into_raw(Box::new(concrete_repr, allocator));
@@ -39,15 +42,15 @@ impl<'a> BoxedFn<'a> {
 }
 
 /// Calls the concrete function wrapped by the BoxedFunction
-fn call_boxed<F>(boxed_fn_ptr: *const BoxedFn, event: Event) where F: FnMut(Event) {
+fn call_boxed<F, R>(boxed_fn_ptr: *const BoxedFn, event: Event) -> HandlerResult where F: FnMut(Event) -> R, R: Into<HandlerResult> {
     unsafe {
         let concrete_repr_ptr = (*boxed_fn_ptr).0.as_ptr() as *mut BaseFn as *mut Repr<F>;
-        ((*concrete_repr_ptr).func)(event) 
+        ((*concrete_repr_ptr).func)(event).into()
     }
 }
 
 /// Drops the boxed function
-fn drop<F>(boxed_fn_ptr: *const BoxedFn) where F: FnMut(Event) {
+fn drop<F>(boxed_fn_ptr: *const BoxedFn) {
     unsafe {
         let base_fn_ptr = (*boxed_fn_ptr).0.as_ptr();
         let concrete_ptr: *mut Repr<F> = base_fn_ptr as *mut Repr<F>;
@@ -64,7 +67,7 @@ fn drop<F>(boxed_fn_ptr: *const BoxedFn) where F: FnMut(Event) {
 /// Cloning a BoxedFn is highly unsafe. The function may contains mutable
 /// references to the outer scope. Cloning the BoxedFn will result in cloning
 /// mutable references, defeating Rust's safety guarantees.
-fn clone<F>(boxed_fn_ptr: *const BoxedFn) -> BoxedFn where F: FnMut(Event) {
+fn clone<F, R>(boxed_fn_ptr: *const BoxedFn) -> BoxedFn where F: FnMut(Event) -> R, R: Into<HandlerResult> {
     unsafe {
         let base_fn_ptr = (*boxed_fn_ptr).0.as_ptr();
         let concrete_ptr: *mut Repr<F> = base_fn_ptr.cast::<Repr<F>>();
@@ -89,7 +92,7 @@ impl<'a> Clone for BoxedFn<'a> {
 }
 
 #[repr(C)]
-struct Repr<F: FnMut(Event)> {
+struct Repr<F> {
     base: BaseFn,
     func: F
 }
@@ -108,7 +111,7 @@ impl<'a> FnMut<(Event,)> for BoxedFn<'a> {
 }
 
 impl<'a> FnOnce<(Event,)> for BoxedFn<'a> {
-    type Output = ();
+    type Output = HandlerResult;
     extern "rust-call" fn call_once(self, args: (Event,)) -> Self::Output {
         self.call(args)
     }
@@ -176,7 +179,7 @@ pub mod tests {
             box_fn!(|_| no_of_fns_called += 1, allocator);
             allocator
         ];
-        v.iter().for_each(|f| f(Event::Timer));
+        v.iter().for_each(|f| { f(Event::Timer); });
         assert_eq!(no_of_fns_called, 3);
     }
 
@@ -205,6 +208,15 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_handler_result() {
+        let allocator = &AlwaysSuccessfulAllocator;
+        let f = box_fn!(|_| (), allocator);
+        assert_eq!(f(Event::Timer), HandlerResult::Continue);
+        let g = box_fn!(|_| HandlerResult::Consume, allocator);
+        assert_eq!(g(Event::Timer), HandlerResult::Consume);
+    }
+
     #[test]
     fn test_clone() {
         let allocator = &AlwaysSuccessfulAllocator;
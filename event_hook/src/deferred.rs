@@ -0,0 +1,107 @@
+//! Events kept to be handled outside the interrupt handlers that sent them
+//!
+//! Normally, the functions hooked to an event are invoked by the interrupt
+//! handler that sent it, so long running ones, like the ones that draw,
+//! keep interrupts disabled the whole time. When events are deferred, the
+//! interrupt handlers only put the events in a `DeferredEvents` queue,
+//! and the main loop takes them out and sends them to the functions
+//! with interrupts enabled.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::mutex::Mutex;
+use machine::serial_println;
+use crate::Event;
+use crate::fixed::MissedEvents;
+
+/// A queue of up to `N` events waiting to be handled
+///
+/// The queue is locked while it's pushed to and popped from, so it has to be
+/// done with interrupts disabled. Otherwise, an interrupt handler pushing an
+/// event can find the queue locked by the code it interrupted, and drop the event
+pub struct DeferredEvents<const N: usize> {
+    events: Mutex<MissedEvents<N>>,
+    /// The most events that have been waiting at once
+    most_pending: AtomicUsize,
+    /// The number of events that couldn't be kept
+    dropped: AtomicUsize
+}
+
+/// How the deferred events queue has been keeping up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeferredStats {
+    /// The number of events waiting to be handled
+    pub pending: usize,
+    /// The most events that have been waiting at once
+    pub most_pending: usize,
+    /// The number of events that were lost because the queue was full,
+    /// or locked by the code that was interrupted
+    pub dropped: usize
+}
+
+impl<const N: usize> DeferredEvents<N> {
+    pub const fn new() -> Self {
+        Self {
+            events: Mutex::new(MissedEvents::new()),
+            most_pending: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0)
+        }
+    }
+
+    /// Keeps `event` to be handled later
+    ///
+    /// Returns false if the event was dropped
+    pub fn push(&self, event: Event) -> bool {
+        let pending = self.events.try_lock().and_then(|mut events| {
+            if events.push(event) { Some(events.len()) } else { None }
+        });
+        match pending {
+            Some(pending) => {
+                self.most_pending.fetch_max(pending, Ordering::Relaxed);
+                true
+            }
+            None => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                serial_println!("Dropped a deferred {:?} event ({} deferred events dropped so far)", event, dropped);
+                false
+            }
+        }
+    }
+
+    /// Takes out the event that has been waiting the longest
+    ///
+    /// Returns None if there is none, or if the queue is locked
+    /// by the code that was interrupted
+    pub fn pop(&self) -> Option<Event> {
+        self.events.try_lock().and_then(|mut events| events.pop())
+    }
+
+    pub fn stats(&self) -> DeferredStats {
+        DeferredStats {
+            pending: self.events.try_lock().map_or(0, |events| events.len()),
+            most_pending: self.most_pending.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Event;
+    use super::{DeferredEvents, DeferredStats};
+
+    #[test]
+    fn test_deferred_events() {
+        let deferred: DeferredEvents<2> = DeferredEvents::new();
+        assert!(deferred.push(Event::Timer));
+        assert!(deferred.push(Event::Sound));
+        // Full
+        assert!(!deferred.push(Event::PowerButton));
+        assert_eq!(deferred.stats(), DeferredStats { pending: 2, most_pending: 2, dropped: 1 });
+        assert!(matches!(deferred.pop(), Some(Event::Timer)));
+        assert!(deferred.push(Event::SystemReset));
+        assert!(matches!(deferred.pop(), Some(Event::Sound)));
+        assert!(matches!(deferred.pop(), Some(Event::SystemReset)));
+        assert!(deferred.pop().is_none());
+        assert_eq!(deferred.stats(), DeferredStats { pending: 0, most_pending: 2, dropped: 1 });
+    }
+}
@@ -0,0 +1,360 @@
+//! An event hooker that works without an allocator
+//!
+//! The `EventHooker` stores its handlers in vectors and boxes the closures
+//! hooked to it, so it can only be used after the heap has been set up.
+//! The `FixedEventHooker` stores plain functions in arrays of `N` handlers
+//! per event kind instead, so it can be created in a static and used right
+//! from the start of the boot process, or anywhere allocating isn't allowed.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::mutex::Mutex;
+use machine::serial_println;
+use crate::{Event, EventKind, HandlerId, HandlerOwner, Error, DropStats, builtin_index};
+
+/// A function that can be hooked to a `FixedEventHooker`
+pub type FixedHandlerFn = fn(Event);
+
+/// Manages up to `N` handlers for every kind of event without allocating
+///
+/// # Synchronization
+///
+/// The handlers are copied out of the lock before they're invoked, so handlers
+/// can hook and unhook functions themselves. An event that is sent while
+/// a function is being hooked or unhooked is kept until the hooking or unhooking
+/// is done, and then sent. Up to `N` such events are kept; any more are dropped.
+///
+/// # Example
+///
+/// ```
+/// use event_hook::{FixedEventHooker, Event, EventKind};
+///
+/// static EARLY_HOOKER: FixedEventHooker<4> = FixedEventHooker::new();
+///
+/// fn on_timer(_event: Event) {}
+///
+/// let id = EARLY_HOOKER.hook_event(EventKind::Timer, on_timer).unwrap();
+/// EARLY_HOOKER.send_event(Event::Timer);
+/// EARLY_HOOKER.unhook_event(id, EventKind::Timer).unwrap();
+/// ```
+pub struct FixedEventHooker<const N: usize> {
+    /// The functions to be called when events take place
+    handlers: Mutex<[[Option<FixedHandler>; N]; 9]>,
+    /// The next id to be used as a handler id
+    next_id: AtomicUsize,
+    /// Events that were sent while the handlers were locked
+    missed_events: Mutex<MissedEvents<N>>,
+    /// The number of events that were sent while the handlers were locked
+    /// and couldn't be kept
+    dropped_events: AtomicUsize
+}
+
+impl<const N: usize> FixedEventHooker<N> {
+    /// Creates a new FixedEventHooker with no handlers
+    pub const fn new() -> Self {
+        Self {
+            handlers: Mutex::new([[None; N]; 9]),
+            next_id: AtomicUsize::new(0),
+            missed_events: Mutex::new(MissedEvents::new()),
+            dropped_events: AtomicUsize::new(0)
+        }
+    }
+
+    /// Registers a function `func` to be invoked when an event of kind `event_kind` is sent
+    ///
+    /// Returns the id of the handler which can be used to unhook the function
+    ///
+    /// # Errors
+    ///
+    /// * `Error::NoSpace` if `N` functions have already been hooked to the event kind
+    /// * `Error::Busy` if it's called from an interrupt handler while functions are
+    ///   being hooked or unhooked
+    /// * `Error::CustomEventKind` if `event_kind` is a custom event kind
+    pub fn hook_event(&self, event_kind: EventKind, func: FixedHandlerFn) -> Result<HandlerId, Error> {
+        self.hook_event_with_optional_owner(event_kind, None, func)
+    }
+
+    /// Registers a function `func` that belongs to `owner` to be invoked when an
+    /// event of kind `event_kind` is sent
+    ///
+    /// All the functions with the same owner can be removed at once with `unhook_all`
+    pub fn hook_event_with_owner(&self, event_kind: EventKind, owner: HandlerOwner, func: FixedHandlerFn) -> Result<HandlerId, Error> {
+        self.hook_event_with_optional_owner(event_kind, Some(owner), func)
+    }
+
+    fn hook_event_with_optional_owner(&self, event_kind: EventKind, owner: Option<HandlerOwner>, func: FixedHandlerFn) -> Result<HandlerId, Error> {
+        let result = {
+            let idx = builtin_index(event_kind).ok_or(Error::CustomEventKind)?;
+            let mut handlers = self.handlers.try_lock().ok_or(Error::Busy)?;
+            let free_slot = handlers[idx].iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or(Error::NoSpace)?;
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            *free_slot = Some(FixedHandler { id, owner, func });
+            Ok(id)
+        };
+        self.send_missed_events();
+        result
+    }
+
+    /// Removes the function with id `id` hooked to `event_kind`.
+    /// If there is no such function, nothing is removed
+    ///
+    /// # Errors
+    ///
+    /// * `Error::Busy` if it's called from an interrupt handler while functions are
+    ///   being hooked or unhooked
+    pub fn unhook_event(&self, id: HandlerId, event_kind: EventKind) -> Result<(), Error> {
+        // Nothing can be hooked to custom event kinds
+        let idx = match builtin_index(event_kind) {
+            Some(idx) => idx,
+            None => return Ok(())
+        };
+        {
+            let mut handlers = self.handlers.try_lock().ok_or(Error::Busy)?;
+            for slot in handlers[idx].iter_mut() {
+                if slot.map(|handler| handler.id) == Some(id) {
+                    *slot = None;
+                }
+            }
+        }
+        self.send_missed_events();
+        Ok(())
+    }
+
+    /// Removes all the functions that were hooked with `owner` as their owner,
+    /// for all events
+    ///
+    /// # Errors
+    ///
+    /// * `Error::Busy` if it's called from an interrupt handler while functions are
+    ///   being hooked or unhooked
+    pub fn unhook_all(&self, owner: HandlerOwner) -> Result<(), Error> {
+        {
+            let mut handlers = self.handlers.try_lock().ok_or(Error::Busy)?;
+            for slot in handlers.iter_mut().flat_map(|event_handlers| event_handlers.iter_mut()) {
+                if slot.and_then(|handler| handler.owner) == Some(owner) {
+                    *slot = None;
+                }
+            }
+        }
+        self.send_missed_events();
+        Ok(())
+    }
+
+    /// Invokes all the functions hooked to the event's kind
+    pub fn send_event(&self, event: Event) {
+        let idx = match builtin_index(EventKind::from_event(event)) {
+            Some(idx) => idx,
+            None => return
+        };
+        let event_handlers = match self.handlers.try_lock() {
+            Some(handlers) => handlers[idx],
+            None => {
+                let kept = self.missed_events.try_lock()
+                    .map_or(false, |mut missed_events| missed_events.push(event));
+                if !kept {
+                    let dropped = self.dropped_events.fetch_add(1, Ordering::Relaxed) + 1;
+                    serial_println!("Dropped a {:?} event ({} events dropped so far)", event, dropped);
+                }
+                return;
+            }
+        };
+        for handler in event_handlers.iter().flatten() {
+            (handler.func)(event);
+        }
+    }
+
+    /// Checks if any function is hooked to events of kind `event_kind`
+    ///
+    /// Returns None if the handlers are locked, because they are being modified,
+    /// so it can't be determined at the moment
+    pub fn has_handlers(&self, event_kind: EventKind) -> Option<bool> {
+        match builtin_index(event_kind) {
+            Some(idx) => self.handlers.try_lock().map(|handlers| handlers[idx].iter().any(|slot| slot.is_some())),
+            None => Some(false)
+        }
+    }
+
+    /// The number of events that have been lost because they were sent
+    /// while the handlers were locked and `N` events were already being kept
+    ///
+    /// Hooks and unhooks are never dropped, since they fail with `Error::Busy` instead
+    pub fn dropped(&self) -> DropStats {
+        DropStats { events: self.dropped_events.load(Ordering::Relaxed), ..DropStats::default() }
+    }
+
+    fn send_missed_events(&self) {
+        loop {
+            let missed_event = self.missed_events.try_lock().and_then(|mut missed_events| missed_events.pop());
+            match missed_event {
+                Some(event) => self.send_event(event),
+                None => break
+            }
+        }
+    }
+}
+
+/// A function hooked to a `FixedEventHooker`
+#[derive(Clone, Copy, Debug)]
+struct FixedHandler {
+    id: HandlerId,
+    owner: Option<HandlerOwner>,
+    func: FixedHandlerFn
+}
+
+/// A queue of up to `N` events
+pub(crate) struct MissedEvents<const N: usize> {
+    events: [Option<Event>; N],
+    /// The index of the oldest event
+    head: usize,
+    len: usize
+}
+
+impl<const N: usize> MissedEvents<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: [None; N],
+            head: 0,
+            len: 0
+        }
+    }
+
+    /// Adds an event to the back of the queue, unless the queue is full
+    ///
+    /// Returns false if the queue was full
+    pub(crate) fn push(&mut self, event: Event) -> bool {
+        if self.len < N {
+            self.events[(self.head + self.len) % N] = Some(event);
+            self.len += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        event
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use crate::{Event, EventKind, Error, DropStats};
+    use super::FixedEventHooker;
+
+    #[test]
+    fn test_hook_send_unhook() {
+        static HOOKER: FixedEventHooker<2> = FixedEventHooker::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn count(_: Event) {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+        }
+        let id = HOOKER.hook_event(EventKind::Timer, count).unwrap();
+        assert_eq!(HOOKER.has_handlers(EventKind::Timer), Some(true));
+        assert_eq!(HOOKER.has_handlers(EventKind::Sound), Some(false));
+        HOOKER.send_event(Event::Timer);
+        HOOKER.send_event(Event::Sound);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        HOOKER.unhook_event(id, EventKind::Timer).unwrap();
+        HOOKER.send_event(Event::Timer);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(HOOKER.has_handlers(EventKind::Timer), Some(false));
+    }
+
+    #[test]
+    fn test_no_space() {
+        let hooker: FixedEventHooker<2> = FixedEventHooker::new();
+        hooker.hook_event(EventKind::Timer, |_| ()).unwrap();
+        let id = hooker.hook_event(EventKind::Timer, |_| ()).unwrap();
+        assert_eq!(hooker.hook_event(EventKind::Timer, |_| ()), Err(Error::NoSpace));
+        // Other event kinds have their own space
+        assert!(hooker.hook_event(EventKind::Keyboard, |_| ()).is_ok());
+        // Custom event kinds have none
+        assert_eq!(hooker.hook_event(EventKind::Custom(0), |_| ()), Err(Error::CustomEventKind));
+        // Unhooking makes space
+        hooker.unhook_event(id, EventKind::Timer).unwrap();
+        assert!(hooker.hook_event(EventKind::Timer, |_| ()).is_ok());
+    }
+
+    #[test]
+    fn test_unhook_all_only_removes_the_owners_handlers() {
+        static HOOKER: FixedEventHooker<4> = FixedEventHooker::new();
+        static X: AtomicUsize = AtomicUsize::new(0);
+        HOOKER.hook_event_with_owner(EventKind::Timer, "game", |_| { X.fetch_add(1, Ordering::SeqCst); }).unwrap();
+        HOOKER.hook_event_with_owner(EventKind::Sound, "game", |_| { X.fetch_add(1, Ordering::SeqCst); }).unwrap();
+        HOOKER.hook_event_with_owner(EventKind::Timer, "menu", |_| { X.fetch_add(10, Ordering::SeqCst); }).unwrap();
+        HOOKER.hook_event(EventKind::Timer, |_| { X.fetch_add(100, Ordering::SeqCst); }).unwrap();
+        HOOKER.unhook_all("game").unwrap();
+        HOOKER.send_event(Event::Timer);
+        HOOKER.send_event(Event::Sound);
+        assert_eq!(X.load(Ordering::SeqCst), 110);
+    }
+
+    #[test]
+    fn test_using_event_hooks_inside_event_hooks() {
+        static HOOKER: FixedEventHooker<2> = FixedEventHooker::new();
+        static X: AtomicUsize = AtomicUsize::new(0);
+        static FIRST_ID: AtomicUsize = AtomicUsize::new(0);
+        fn first(_: Event) {
+            X.fetch_add(1, Ordering::SeqCst);
+            HOOKER.unhook_event(FIRST_ID.load(Ordering::SeqCst), EventKind::Timer).unwrap();
+            HOOKER.hook_event(EventKind::Timer, second).unwrap();
+        }
+        fn second(_: Event) {
+            X.fetch_add(10, Ordering::SeqCst);
+        }
+        FIRST_ID.store(HOOKER.hook_event(EventKind::Timer, first).unwrap(), Ordering::SeqCst);
+        // The handlers are copied before they're invoked,
+        // so the newly hooked one only runs on the next event
+        HOOKER.send_event(Event::Timer);
+        assert_eq!(X.load(Ordering::SeqCst), 1);
+        HOOKER.send_event(Event::Timer);
+        assert_eq!(X.load(Ordering::SeqCst), 11);
+    }
+
+    #[test]
+    fn test_events_sent_while_locked_are_not_lost() {
+        static HOOKER: FixedEventHooker<2> = FixedEventHooker::new();
+        static X: AtomicUsize = AtomicUsize::new(0);
+        HOOKER.hook_event(EventKind::Timer, |_| { X.fetch_add(1, Ordering::SeqCst); }).unwrap();
+        {
+            // Pretending to be an interrupt in the middle of a hook
+            let _handlers = HOOKER.handlers.lock();
+            HOOKER.send_event(Event::Timer);
+            HOOKER.send_event(Event::Timer);
+            assert_eq!(HOOKER.hook_event(EventKind::Sound, |_| ()), Err(Error::Busy));
+            assert_eq!(X.load(Ordering::SeqCst), 0);
+        }
+        HOOKER.hook_event(EventKind::Sound, |_| ()).unwrap();
+        assert_eq!(X.load(Ordering::SeqCst), 2);
+        assert_eq!(HOOKER.dropped().total(), 0);
+    }
+
+    #[test]
+    fn test_events_past_capacity_are_counted() {
+        static HOOKER: FixedEventHooker<2> = FixedEventHooker::new();
+        static X: AtomicUsize = AtomicUsize::new(0);
+        HOOKER.hook_event(EventKind::Sound, |_| { X.fetch_add(1, Ordering::SeqCst); }).unwrap();
+        {
+            let _handlers = HOOKER.handlers.lock();
+            for _ in 0..5 {
+                HOOKER.send_event(Event::Sound);
+            }
+        }
+        HOOKER.unhook_all("nobody").unwrap();
+        // Only the first 2 were kept
+        assert_eq!(X.load(Ordering::SeqCst), 2);
+        assert_eq!(HOOKER.dropped(), DropStats { events: 3, hooks: 0, unhooks: 0 });
+    }
+}
@@ -4,61 +4,386 @@
 #![feature(unboxed_closures, fn_traits)]
 #![allow(dead_code)]
 
-use core::ops::{Index, IndexMut};
 use core::clone::Clone;
+use core::sync::atomic::{AtomicBool, AtomicUsize, AtomicU64, Ordering};
 use machine::keyboard::{KeyCode, KeyDirection, KeyModifiers};
+use machine::mouse::MouseButtons;
+use machine::gamepad::GamepadState;
 use collections::vec::Vec;
 use collections::queue::Queue;
 use collections::queue;
 use collections::allocator::{get_allocator, Allocator};
 use sync::mutex::Mutex;
+use sync::once::Once;
+use machine::serial_println;
+use machine::time;
+use machine::instructions::interrupts::without_interrupts;
 
 pub mod boxed_fn;
 pub use boxed_fn::BoxedFn;
+pub mod fixed;
+pub use fixed::FixedEventHooker;
+use fixed::FixedHandlerFn;
+pub mod repeat;
+pub use repeat::KeyRepeater;
+pub mod deferred;
+pub use deferred::{DeferredEvents, DeferredStats};
+pub mod timeout;
+pub use timeout::TimeoutId;
+use timeout::Timeouts;
 
 
-static mut EVENT_HOOKER: Option<EventHooker<'static>> = None;
+static EVENT_HOOKER: Once<EventHooker<'static>> = Once::new();
+
+/// The most functions that can be hooked to each kind of event with `hook_early_event`
+const EARLY_HANDLERS: usize = 4;
+
+/// Holds the functions hooked with `hook_early_event`, which needs no heap
+static EARLY_EVENT_HOOKER: FixedEventHooker<EARLY_HANDLERS> = FixedEventHooker::new();
+
+/// Holds the sound handlers apart from the rest, so a sound interrupt can
+/// run them while `poll` has the other handlers locked, drawing a frame
+static SOUND_EVENT_HOOKER: Once<EventHooker<'static>> = Once::new();
+
+/// Repeats held keys on timer ticks when key repeat is enabled
+static KEY_REPEATER: Mutex<Option<KeyRepeater>> = Mutex::new(None);
+
+/// The most events that can wait for `poll` at once
+const DEFERRED_EVENTS_CAPACITY: usize = 64;
+
+/// Events sent while events are deferred, waiting for `poll`
+static DEFERRED_EVENTS: DeferredEvents<DEFERRED_EVENTS_CAPACITY> = DeferredEvents::new();
+
+/// Set while events are deferred
+static DEFERRING_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// The functions set with `set_timeout` and `set_interval`
+static TIMEOUTS: Mutex<Option<Timeouts<'static>>> = Mutex::new(None);
+
+/// Ticks that haven't been counted off the timeouts yet, because
+/// the timeouts were being set or cleared when they came
+static UNCOUNTED_TICKS: AtomicU64 = AtomicU64::new(0);
 
 pub fn init() {
-    unsafe {
-        if EVENT_HOOKER.is_none() {
-            EVENT_HOOKER = Some(EventHooker::new(get_allocator()));
-        }
+    let mut initialized = false;
+    EVENT_HOOKER.call_once(|| {
+        initialized = true;
+        EventHooker::new(get_allocator())
+    });
+    if initialized {
+        SOUND_EVENT_HOOKER.call_once(|| EventHooker::new(get_allocator()));
+        *TIMEOUTS.lock() = Some(Timeouts::new(get_allocator()));
+        hook_event(EventKind::Timer, BoxedFn::new(|_| run_timeouts(), get_allocator()));
+    }
+}
+
+/// The global event hooker
+///
+/// # Panics
+///
+/// If `init` hasn't been called yet
+fn event_hooker() -> &'static EventHooker<'static> {
+    EVENT_HOOKER.get().expect("The event hooker hasn't been initialized")
+}
+
+/// The global event hooker that holds the handlers of `event_kind`
+///
+/// # Panics
+///
+/// If `init` hasn't been called yet
+fn event_hooker_for(event_kind: EventKind) -> &'static EventHooker<'static> {
+    match event_kind {
+        EventKind::Sound => SOUND_EVENT_HOOKER.get().expect("The event hooker hasn't been initialized"),
+        _ => event_hooker()
     }
 }
 
 pub fn hook_event(event: EventKind, f: BoxedFn<'static>) -> HandlerId {
-    unsafe { EVENT_HOOKER.as_mut().unwrap().hook_event(event, f) }
+    event_hooker_for(event).hook_event(event, f)
+}
+
+pub fn hook_event_with_owner(event: EventKind, owner: HandlerOwner, f: BoxedFn<'static>) -> HandlerId {
+    event_hooker_for(event).hook_event_with_owner(event, owner, f)
+}
+
+pub fn hook_event_with_options(event: EventKind, options: HookOptions, f: BoxedFn<'static>) -> HandlerId {
+    event_hooker_for(event).hook_event_with_options(event, options, f)
 }
 
 pub fn unhook_event(event_id: HandlerId, event_kind: EventKind) {
-    unsafe { EVENT_HOOKER.as_mut().unwrap().unhook_event(event_id, event_kind); }
+    event_hooker_for(event_kind).unhook_event(event_id, event_kind);
+}
+
+/// Hooks the function `func` to `event` without allocating, so it can be
+/// done before `init` has been called, or where allocating isn't allowed
+///
+/// The functions hooked this way are invoked before the ones hooked with `hook_event`
+pub fn hook_early_event(event: EventKind, func: FixedHandlerFn) -> Result<HandlerId, Error> {
+    EARLY_EVENT_HOOKER.hook_event(event, func)
+}
+
+pub fn unhook_early_event(event_id: HandlerId, event_kind: EventKind) -> Result<(), Error> {
+    EARLY_EVENT_HOOKER.unhook_event(event_id, event_kind)
 }
 
+pub fn unhook_all(owner: HandlerOwner) {
+    event_hooker().unhook_all(owner);
+    event_hooker_for(EventKind::Sound).unhook_all(owner);
+}
+
+/// Sends `event` to the handlers hooked to its kind
+///
+/// While events are deferred, the event is only kept until `poll` is called,
+/// except for sound events, since the sound handlers have to acknowledge
+/// the sound device's interrupt before the interrupt handler returns.
+/// The sound handlers are kept apart from the rest, so they run even
+/// while `poll` is invoking the other handlers
+///
+/// With key repeat enabled, the keyboard's own repeats are dropped and
+/// a timer event is followed by a repeat of the held key when one is due
+///
+/// Events sent before `init` only reach the functions hooked with `hook_early_event`
 pub fn send_event(event: Event) {
-    unsafe { EVENT_HOOKER.as_mut().unwrap().send_event(event); }
+    if DEFERRING_EVENTS.load(Ordering::SeqCst) && !matches!(event, Event::Sound) {
+        // An interrupt's event would be dropped if it found the queue locked
+        // by the code it interrupted
+        without_interrupts(|| DEFERRED_EVENTS.push(event));
+        return;
+    }
+    dispatch(event);
+}
+
+/// Sends the events that were deferred to the handlers hooked to their kinds,
+/// in the order they were sent
+///
+/// Meant to be called by the main loop while events are deferred, so the
+/// handlers run with interrupts enabled. Returns the number of events sent
+pub fn poll() -> usize {
+    let mut sent = 0;
+    while let Some(event) = without_interrupts(|| DEFERRED_EVENTS.pop()) {
+        dispatch(event);
+        sent += 1;
+    }
+    sent
+}
+
+/// Makes `send_event` keep events for `poll` instead of sending them right away
+pub fn enable_deferred_events() {
+    DEFERRING_EVENTS.store(true, Ordering::SeqCst);
+}
+
+/// Goes back to sending events right away, first sending the ones that are waiting
+pub fn disable_deferred_events() {
+    DEFERRING_EVENTS.store(false, Ordering::SeqCst);
+    poll();
+}
+
+/// How many events are waiting for `poll` and how many have been lost
+/// because too many were waiting
+pub fn deferred_stats() -> DeferredStats {
+    DEFERRED_EVENTS.stats()
+}
+
+/// Sends `event` to the handlers right away, repeating keys if key repeat is enabled
+fn dispatch(event: Event) {
+    EARLY_EVENT_HOOKER.send_event(event);
+    if EVENT_HOOKER.get().is_none() {
+        return;
+    }
+    let event_hooker = event_hooker_for(EventKind::from_event(event));
+    match event {
+        Event::Keyboard(keycode, direction, modifiers) => {
+            let repeat_of_keyboard = with_key_repeater(|repeater| !repeater.key_event(keycode, direction, modifiers));
+            if repeat_of_keyboard != Some(true) {
+                event_hooker.send_event(event);
+            }
+        }
+        Event::KeyboardReattached => {
+            with_key_repeater(|repeater| repeater.release());
+            event_hooker.send_event(event);
+        }
+        Event::Timer => {
+            event_hooker.send_event(event);
+            if let Some(Some(repeat)) = with_key_repeater(|repeater| repeater.tick()) {
+                event_hooker.send_event(repeat);
+            }
+        }
+        _ => event_hooker.send_event(event)
+    }
+}
+
+/// Calls `f` with the key repeater, returning None if key repeat is disabled
+///
+/// The repeater isn't locked while the handlers are invoked, so handlers
+/// can enable and disable key repeat themselves
+fn with_key_repeater<R>(f: impl FnOnce(&mut KeyRepeater) -> R) -> Option<R> {
+    KEY_REPEATER.try_lock().and_then(|mut repeater| repeater.as_mut().map(f))
+}
+
+/// Makes held keys repeat `delay_ticks` timer ticks after they're pressed,
+/// and every `interval_ticks` ticks after that, instead of at the keyboard's typematic rate
+pub fn enable_key_repeat(delay_ticks: usize, interval_ticks: usize) {
+    *KEY_REPEATER.lock() = Some(KeyRepeater::new(delay_ticks, interval_ticks));
+}
+
+/// Goes back to the keyboard's own key repeat
+pub fn disable_key_repeat() {
+    *KEY_REPEATER.lock() = None;
+}
+
+pub fn has_handlers(event_kind: EventKind) -> Option<bool> {
+    event_hooker_for(event_kind).has_handlers(event_kind)
+}
+
+/// The number of events, hooks and unhooks that have been lost so far
+pub fn dropped() -> DropStats {
+    let (dropped, sound_dropped) = (event_hooker().dropped(), event_hooker_for(EventKind::Sound).dropped());
+    DropStats {
+        events: dropped.events + sound_dropped.events,
+        hooks: dropped.hooks + sound_dropped.hooks,
+        unhooks: dropped.unhooks + sound_dropped.unhooks
+    }
+}
+
+/// Invokes `f` once, at least `ms` milliseconds from now
+///
+/// Returns an id that can be used to clear the timeout before `f` is invoked
+pub fn set_timeout(ms: u64, f: BoxedFn<'static>) -> TimeoutId {
+    set_timeout_or_interval(ms, false, f)
+}
+
+/// Invokes `f` every `ms` milliseconds until the interval is cleared
+pub fn set_interval(ms: u64, f: BoxedFn<'static>) -> TimeoutId {
+    set_timeout_or_interval(ms, true, f)
+}
+
+/// Stops the function set with `set_timeout` or `set_interval` that has the id `id`
+/// from being invoked again. It can be called from the function itself
+pub fn clear_timeout(id: TimeoutId) {
+    TIMEOUTS.lock().as_mut().expect("The event hooker hasn't been initialized").clear(id);
+}
+
+fn set_timeout_or_interval(ms: u64, repeat: bool, f: BoxedFn<'static>) -> TimeoutId {
+    let ticks = time::ms_to_ticks(ms);
+    TIMEOUTS.lock().as_mut().expect("The event hooker hasn't been initialized").set(ticks, repeat, f)
+}
+
+/// Counts the tick off the timeouts and invokes the functions that are due
+///
+/// The timeouts are only locked while a function is taken out or put back,
+/// so the functions can set and clear timeouts themselves
+fn run_timeouts() {
+    UNCOUNTED_TICKS.fetch_add(1, Ordering::SeqCst);
+    match TIMEOUTS.try_lock() {
+        Some(mut timeouts) => match timeouts.as_mut() {
+            Some(timeouts) => timeouts.tick(UNCOUNTED_TICKS.swap(0, Ordering::SeqCst)),
+            None => return
+        },
+        // Counted on a later tick
+        None => return
+    }
+    loop {
+        let due = TIMEOUTS.try_lock().and_then(|mut timeouts| timeouts.as_mut()?.take_due());
+        let timeout = match due {
+            Some(timeout) => timeout,
+            None => break
+        };
+        timeout.invoke();
+        TIMEOUTS.lock().as_mut().unwrap().finish(timeout);
+    }
+}
+
+/// Counts of events, hooks and unhooks that were lost because they came in
+/// while the handlers were locked and couldn't be kept for later
+///
+/// A dropped keyboard event is a lost keypress and a dropped unhook is a
+/// handler that keeps running, so these are logged as they happen too
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropStats {
+    pub events: usize,
+    pub hooks: usize,
+    pub unhooks: usize
+}
+
+impl DropStats {
+    pub fn total(&self) -> usize {
+        self.events + self.hooks + self.unhooks
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum Event {
     Timer,
     Keyboard(KeyCode, KeyDirection, KeyModifiers),
-    Sound
+    Sound,
+    /// The Ctrl+Alt+Del chord was pressed
+    SystemReset,
+    /// The power button was pressed
+    PowerButton,
+    /// Headphones were plugged into or unplugged from the headphone jack
+    JackChange(JackState),
+    /// A keyboard was plugged back in. Sent to the keyboard handlers,
+    /// since any key that was held down has been let go of
+    KeyboardReattached,
+    /// The sound controller reported that a stream went wrong
+    SoundError(SoundErrorKind),
+    /// The mouse moved by (dx, dy) or had its buttons pressed or let go of.
+    /// dy is positive downwards, like the screen's y
+    Mouse(i16, i16, MouseButtons),
+    /// The gamepad's stick was moved or its buttons were pressed or let go of
+    Gamepad(GamepadState),
+    /// An event the game defined itself, sent to the functions hooked to
+    /// `EventKind::Custom` with the same id, with a value that goes with it
+    Custom(u32, usize)
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Whether or not something is plugged into a jack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JackState {
+    Plugged,
+    Unplugged
+}
+
+/// What went wrong with a sound stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundErrorKind {
+    /// The controller ran out of samples to play, so there was a gap in the sound
+    OutputUnderrun,
+    /// The controller couldn't write recorded samples to memory fast enough,
+    /// so some were lost
+    InputOverrun,
+    /// The controller couldn't fetch a buffer descriptor, which stops the stream
+    DescriptorError
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EventKind {
     Timer,
     Keyboard,
-    Sound
+    Sound,
+    SystemReset,
+    PowerButton,
+    JackChange,
+    SoundError,
+    Mouse,
+    Gamepad,
+    /// A kind of event the game defined itself, identified by its id
+    Custom(u32)
 }
 
 impl EventKind {
     fn from_event(event: Event) -> Self {
         match event {
             Event::Timer => EventKind::Timer,
-            Event::Keyboard(_, _, _) => EventKind::Keyboard,
-            Event::Sound => EventKind::Sound
+            Event::Keyboard(_, _, _) | Event::KeyboardReattached => EventKind::Keyboard,
+            Event::Sound => EventKind::Sound,
+            Event::SystemReset => EventKind::SystemReset,
+            Event::PowerButton => EventKind::PowerButton,
+            Event::JackChange(_) => EventKind::JackChange,
+            Event::SoundError(_) => EventKind::SoundError,
+            Event::Mouse(_, _, _) => EventKind::Mouse,
+            Event::Gamepad(_) => EventKind::Gamepad,
+            Event::Custom(id, _) => EventKind::Custom(id)
         }
     }
 }
@@ -69,6 +394,18 @@ const TIMER_INDEX: usize = 0;
 const KEYBOARD_INDEX: usize = 1;
 /// Index into the EventHooker's handlers field for sound handlers
 const SOUND_INDEX: usize = 2;
+/// Index into the EventHooker's handlers field for system reset handlers
+const SYSTEM_RESET_INDEX: usize = 3;
+/// Index into the EventHooker's handlers field for power button handlers
+const POWER_BUTTON_INDEX: usize = 4;
+/// Index into the EventHooker's handlers field for jack change handlers
+const JACK_CHANGE_INDEX: usize = 5;
+/// Index into the EventHooker's handlers field for sound error handlers
+const SOUND_ERROR_INDEX: usize = 6;
+/// Index into the EventHooker's handlers field for mouse handlers
+const MOUSE_INDEX: usize = 7;
+/// Index into the EventHooker's handlers field for gamepad handlers
+const GAMEPAD_INDEX: usize = 8;
 
 /// Acts as mediator between the interrupt service routines and the game code
 ///
@@ -96,7 +433,9 @@ const SOUND_INDEX: usize = 2;
 /// a handler is either in or out of the vector; no partially removed state
 ///
 /// From the info above, only the vector needs a mutex, not the whole EventHooker
-/// instance.
+/// instance. The queues are behind their own mutexes and the counters are atomic,
+/// so the EventHooker is only ever used through shared references, and it
+/// can be kept in a static without any `static mut`.
 /// The `hook_event` function writes to the handlers vector and the `send_event`
 /// function reads the vector. These 2 actions cannot occur at the same time.
 /// It can be resolved by adding 3 new queues: `missed_events`, `missed_hooks` and `missed_unhooks`.
@@ -151,35 +490,57 @@ const SOUND_INDEX: usize = 2;
 /// the handlers lock is released. The same goes for the `hook_event`'s execution.
 pub struct EventHooker<'a> {
     /// The functions to be called when events take place
-    handlers: Mutex<[Vec<'a, Handler<'a>>; 3]>,
+    handlers: Mutex<Handlers<'a>>,
     /// The next id to be used as a handler idx
-    next_idx: HandlerId,
+    next_idx: AtomicUsize,
     /// Hooks that were requested while the corresponding handlers
     /// vector was locked
-    missed_hooks: Queue<'a, HookArgs<'a>>,
+    missed_hooks: Mutex<Queue<'a, HookArgs<'a>>>,
     /// Unhook that were requested while the corresponding handlers
     /// where locked
-    missed_unhooks: Queue<'a, UnhookArgs>,
+    missed_unhooks: Mutex<Queue<'a, UnhookArgs>>,
     /// Events that were sent while the corresponding handlers
     /// where locked
-    missed_events: Queue<'a, Event>
+    missed_events: Mutex<Queue<'a, Event>>,
+    /// The number of events that couldn't be put on `missed_events`
+    /// because it was full and couldn't grow, or was locked itself
+    dropped_events: AtomicUsize,
+    /// The same for `missed_hooks`
+    dropped_hooks: AtomicUsize,
+    /// The same for `missed_unhooks`
+    dropped_unhooks: AtomicUsize
 }
 
 unsafe impl<'a> Send for EventHooker<'a> {}
+// Everything that's modified through a shared reference is behind a mutex or atomic
+unsafe impl<'a> Sync for EventHooker<'a> {}
 
 impl<'a> EventHooker<'a> {
     /// Creates a new empty EventHooker
     pub fn new(allocator: &'a dyn Allocator) -> Self {
         EventHooker {
-            handlers: Mutex::new([
-                Vec::with_capacity(1, allocator),
-                Vec::with_capacity(1, allocator),
-                Vec::with_capacity(1, allocator)
-            ]),
-            missed_events: queue!(item_type => Event, capacity => 3, allocator),
-            missed_hooks: queue!(item_type => HookArgs, capacity => 3, allocator),
-            missed_unhooks: queue!(item_type => UnhookArgs, capacity => 3, allocator),
-            next_idx: 0
+            handlers: Mutex::new(Handlers {
+                builtin: [
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator),
+                    Vec::with_capacity(1, allocator)
+                ],
+                custom: Vec::with_capacity(1, allocator),
+                allocator
+            }),
+            missed_events: Mutex::new(queue!(item_type => Event, capacity => 3, allocator)),
+            missed_hooks: Mutex::new(queue!(item_type => HookArgs, capacity => 3, allocator)),
+            missed_unhooks: Mutex::new(queue!(item_type => UnhookArgs, capacity => 3, allocator)),
+            next_idx: AtomicUsize::new(0),
+            dropped_events: AtomicUsize::new(0),
+            dropped_hooks: AtomicUsize::new(0),
+            dropped_unhooks: AtomicUsize::new(0)
         }
     }
 
@@ -189,6 +550,9 @@ impl<'a> EventHooker<'a> {
     ///
     /// Takes O(1) time since it's just appending to a vector
     ///
+    /// Custom event kinds don't have to be declared anywhere. A custom kind
+    /// gets its own vector the first time a function is hooked to it
+    ///
     /// # Example
     ///
     /// ```
@@ -212,7 +576,7 @@ impl<'a> EventHooker<'a> {
     ///     }
     /// }
     ///
-    /// let mut event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+    /// let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
     /// let idx = event_hooker.hook_event(EventKind::Timer, BoxedFn::new(|_| (), &AlwaysSuccessfulAllocator));
     /// assert_eq!(idx, 0);
     /// ```
@@ -227,27 +591,86 @@ impl<'a> EventHooker<'a> {
     /// takes any reference that doesn't live long enough or performs any unsafe
     /// operations. Anything that `func` performs is completely opaque, with no way
     /// to verify its safety
-    pub fn hook_event(&mut self, event_kind: EventKind, func: BoxedFn<'a>) -> usize {
-        let next_idx = self.next_idx;
+    pub fn hook_event(&self, event_kind: EventKind, func: BoxedFn<'a>) -> usize {
+        self.hook_event_with_options(event_kind, HookOptions::default(), func)
+    }
+
+    /// Registers a function `f` that belongs to `owner` to be invoked when event is sent.
+    ///
+    /// It works just like `hook_event`, but all the functions with the same owner
+    /// can be removed at once with `unhook_all`, no matter the event they are hooked to.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use collections::allocator::{Allocator, Error};
+    /// use std::vec::Vec as StdVec;
+    /// use core::mem::ManuallyDrop;
+    /// use core::mem;
+    /// use event_hook::{EventHooker, Event, EventKind};
+    /// use event_hook::boxed_fn::BoxedFn;
+    ///
+    /// pub struct AlwaysSuccessfulAllocator;
+    /// unsafe impl Allocator for AlwaysSuccessfulAllocator {
+    ///     unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+    ///         let mut v: ManuallyDrop<StdVec<u8>> = ManuallyDrop::new(StdVec::with_capacity(size_of_type * size_to_alloc));
+    ///         Ok(v.as_mut_ptr() as *mut u8)
+    ///     }
+    ///     unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize)  -> Result<(), Error> {
+    ///         let v: StdVec<u8> = StdVec::from_raw_parts(ptr, size_to_dealloc, size_to_dealloc);
+    ///         mem::drop(v);
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+    /// let mut x = 1;
+    /// event_hooker.hook_event_with_owner(EventKind::Timer, "game", BoxedFn::new(|_| x += 1, &AlwaysSuccessfulAllocator));
+    /// event_hooker.hook_event_with_owner(EventKind::Sound, "game", BoxedFn::new(|_| x += 1, &AlwaysSuccessfulAllocator));
+    /// event_hooker.unhook_all("game");
+    /// event_hooker.send_event(Event::Timer);
+    /// event_hooker.send_event(Event::Sound);
+    /// assert_eq!(x, 1);
+    /// ```
+    pub fn hook_event_with_owner(&self, event_kind: EventKind, owner: HandlerOwner, func: BoxedFn<'a>) -> usize {
+        self.hook_event_with_options(event_kind, HookOptions { owner: Some(owner), ..HookOptions::default() }, func)
+    }
+
+    /// Registers a function `f` to be invoked when event is sent, hooked the way `options` says
+    ///
+    /// It works just like `hook_event`, but the function can be given an owner like
+    /// with `hook_event_with_owner`, a priority that puts it before the functions
+    /// hooked with lower ones, and can be unhooked after it's first invoked.
+    ///
+    /// Takes O(n) time, where n is the number of functions hooked to the event,
+    /// since the function is inserted among them by its priority
+    pub fn hook_event_with_options(&self, event_kind: EventKind, options: HookOptions, func: BoxedFn<'a>) -> usize {
+        let next_idx = self.next_idx.fetch_add(1, Ordering::SeqCst);
+        if next_idx == usize::MAX - 1 {
+            panic!("next_idx has reached max");
+        }
         if let Some(ref mut event_handlers) = self.handlers.try_lock() {
-            Self::hook(event_handlers, HookArgs { event_kind, handler_id: next_idx, func });
-            while let Some(missed_unhook) = self.missed_unhooks.dequeue() {
+            Self::hook(event_handlers, HookArgs { event_kind, handler_id: next_idx, options, func });
+            while let Some(missed_unhook) = Self::dequeue(&self.missed_unhooks) {
                 Self::unhook(event_handlers, missed_unhook);
             }
-            while let Some(missed_event) = self.missed_events.dequeue() {
+            while let Some(missed_event) = Self::dequeue(&self.missed_events) {
                 Self::event(event_handlers, missed_event);
             }
         } else {
-            self.missed_hooks.enqueue(HookArgs { event_kind, handler_id: next_idx, func });
-        }
-        self.next_idx += 1;
-        if self.next_idx == usize::MAX {
-            panic!("next_idx has reached max");
+            if !Self::enqueue(&self.missed_hooks, HookArgs { event_kind, handler_id: next_idx, options, func }) {
+                let dropped = self.dropped_hooks.fetch_add(1, Ordering::Relaxed) + 1;
+                serial_println!("Dropped a hook for {:?} events ({} hooks dropped so far)", event_kind, dropped);
+            }
         }
         next_idx
     }
 
-    /// Invokes all functions hooked to event
+    /// Invokes all functions hooked to event, the ones with higher priorities first,
+    /// and unhooks the ones that were only to be invoked once
+    ///
+    /// A function that returns `HandlerResult::Consume` keeps the event
+    /// from the functions after it
     ///
     /// Takes O(nm) time where n is the number of functions in `event`'s vector and m is
     /// the running time of the longest running function, since it is invoking
@@ -276,23 +699,31 @@ impl<'a> EventHooker<'a> {
     ///     }
     /// }
     ///
-    /// let mut event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+    /// let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
     /// let mut x = 1;
     /// event_hooker.hook_event(EventKind::Timer, BoxedFn::new(|_| x += 1, &AlwaysSuccessfulAllocator));
     /// event_hooker.send_event(Event::Timer);
     /// assert_eq!(x, 2);
     /// ```
-    pub fn send_event(&mut self, event: Event) {
+    pub fn send_event(&self, event: Event) {
         if let Some(ref mut event_handlers) = self.handlers.try_lock() {
             Self::event(event_handlers, event);
-            while let Some(missed_hook) = self.missed_hooks.dequeue() {
+            // Events the handlers sent themselves, like a sound device
+            // reporting a jack change from its sound interrupt handler
+            while let Some(missed_event) = Self::dequeue(&self.missed_events) {
+                Self::event(event_handlers, missed_event);
+            }
+            while let Some(missed_hook) = Self::dequeue(&self.missed_hooks) {
                 Self::hook(event_handlers, missed_hook);
             }
-            while let Some(missed_unhook) = self.missed_unhooks.dequeue() {
+            while let Some(missed_unhook) = Self::dequeue(&self.missed_unhooks) {
                 Self::unhook(event_handlers, missed_unhook);
             }
         } else {
-            self.missed_events.enqueue(event);
+            if !Self::enqueue(&self.missed_events, event) {
+                let dropped = self.dropped_events.fetch_add(1, Ordering::Relaxed) + 1;
+                serial_println!("Dropped a {:?} event ({} events dropped so far)", event, dropped);
+            }
         }
     }
 
@@ -326,7 +757,7 @@ impl<'a> EventHooker<'a> {
     ///     }
     /// }
     ///
-    /// let mut event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+    /// let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
     /// let mut x = 1;
     /// let idx = event_hooker.hook_event(EventKind::Timer, BoxedFn::new(|_| x += 1, &AlwaysSuccessfulAllocator));
     /// event_hooker.unhook_event(idx, EventKind::Timer);
@@ -337,24 +768,77 @@ impl<'a> EventHooker<'a> {
     /// event_hooker.send_event(Event::Timer);
     /// assert_eq!(x, 1);
     /// ```
-    pub fn unhook_event(&mut self, idx: HandlerId, event_kind: EventKind) {
+    pub fn unhook_event(&self, idx: HandlerId, event_kind: EventKind) {
+        self.request_unhook(UnhookArgs::Handler { event_kind, handler_id: idx });
+    }
+
+    /// Removes all the functions that were hooked with `owner` as their owner,
+    /// for all events
+    ///
+    /// Takes O(n) time, where n is the total number of hooked functions
+    pub fn unhook_all(&self, owner: HandlerOwner) {
+        self.request_unhook(UnhookArgs::Owner(owner));
+    }
+
+    fn request_unhook(&self, args: UnhookArgs) {
         if let Some(ref mut event_handlers) = self.handlers.try_lock() {
-            Self::unhook(event_handlers, UnhookArgs { event_kind, handler_id: idx });
-            while let Some(missed_hook) = self.missed_hooks.dequeue() {
+            Self::unhook(event_handlers, args);
+            while let Some(missed_hook) = Self::dequeue(&self.missed_hooks) {
                 Self::hook(event_handlers, missed_hook);
             }
-            while let Some(missed_event) = self.missed_events.dequeue() {
+            while let Some(missed_event) = Self::dequeue(&self.missed_events) {
                 Self::event(event_handlers, missed_event);
             }
         } else {
-            self.missed_unhooks.enqueue(UnhookArgs { event_kind, handler_id: idx });
+            if !Self::enqueue(&self.missed_unhooks, args) {
+                let dropped = self.dropped_unhooks.fetch_add(1, Ordering::Relaxed) + 1;
+                serial_println!("Dropped an unhook ({} unhooks dropped so far)", dropped);
+            }
+        }
+    }
+
+    /// Checks if any function is hooked to events of kind `event_kind`
+    ///
+    /// Returns None if the handlers are locked, because they are being modified
+    /// or invoked, so it can't be determined at the moment
+    pub fn has_handlers(&self, event_kind: EventKind) -> Option<bool> {
+        self.handlers.try_lock().map(|handlers| handlers.get(event_kind).map_or(false, |handlers| handlers.len() > 0))
+    }
+
+    /// The number of events, hooks and unhooks that have been lost
+    /// because they couldn't be queued while the handlers were locked
+    pub fn dropped(&self) -> DropStats {
+        DropStats {
+            events: self.dropped_events.load(Ordering::Relaxed),
+            hooks: self.dropped_hooks.load(Ordering::Relaxed),
+            unhooks: self.dropped_unhooks.load(Ordering::Relaxed)
         }
     }
 
-    fn handler_exists(&mut self, event_kind: EventKind, idx: HandlerId) -> Option<bool> {
+    /// Puts `item` on the missed queue `queue`
+    ///
+    /// Returns false if the queue was full and couldn't grow, or was being
+    /// used by the code that was interrupted
+    fn enqueue<T: Clone>(queue: &Mutex<Queue<'a, T>>, item: T) -> bool {
+        queue.try_lock().map_or(false, |mut queue| queue.try_enqueue(item).is_ok())
+    }
+
+    /// Takes the oldest item off the missed queue `queue`
+    ///
+    /// The queue is only locked while the item is taken off, so the item
+    /// can be handled while more items are put on the queue
+    fn dequeue<T: Clone>(queue: &Mutex<Queue<'a, T>>) -> Option<T> {
+        queue.try_lock().and_then(|mut queue| queue.dequeue())
+    }
+
+    fn handler_exists(&self, event_kind: EventKind, idx: HandlerId) -> Option<bool> {
         if let Some(handlers) = self.handlers.try_lock() {
-            for i in 0..handlers[event_kind].len() {
-                if handlers[event_kind][i].idx == idx {
+            let event_handlers = match handlers.get(event_kind) {
+                Some(event_handlers) => event_handlers,
+                None => return Some(false)
+            };
+            for i in 0..event_handlers.len() {
+                if event_handlers[i].idx == idx {
                     return Some(true);
                 }
             }
@@ -365,23 +849,61 @@ impl<'a> EventHooker<'a> {
     }
 
     fn event(handlers: &mut Handlers<'a>, event: Event) {
-        let event_kind = EventKind::from_event(event);
-        for i in 0..handlers[event_kind].len() {
-            let handler = &handlers[event_kind][i];
-            (handler.func)(event);
+        let event_handlers = match handlers.get_mut(EventKind::from_event(event)) {
+            Some(event_handlers) => event_handlers,
+            // A custom event nothing was ever hooked to
+            None => return
+        };
+        let mut i = 0;
+        while i < event_handlers.len() {
+            let handler = &event_handlers[i];
+            let result = (handler.func)(event);
+            if handler.once {
+                event_handlers.remove(i);
+            } else {
+                i += 1;
+            }
+            if result == HandlerResult::Consume {
+                break;
+            }
         }
     }
 
     fn hook(handlers: &mut Handlers<'a>, args: HookArgs<'a>) {
-        handlers[args.event_kind].push(Handler { idx: args.handler_id, func: args.func });
+        let HookOptions { owner, priority, once } = args.options;
+        let event_handlers = handlers.get_or_insert(args.event_kind);
+        // After the handlers with the same priority, so they're invoked in the order they were hooked
+        let pos = event_handlers.iter()
+            .position(|handler| handler.priority < priority)
+            .unwrap_or(event_handlers.len());
+        event_handlers.insert(pos, Handler { idx: args.handler_id, owner, priority, once, func: args.func });
     }
 
     fn unhook(handlers: &mut Handlers<'a>, args: UnhookArgs) {
-        for i in 0..handlers[args.event_kind].len() {
-            let handler = &mut handlers[args.event_kind][i];
-            if handler.idx == args.handler_id {
-                handlers[args.event_kind].remove(i);
-                break;
+        match args {
+            UnhookArgs::Handler { event_kind, handler_id } => {
+                let event_handlers = match handlers.get_mut(event_kind) {
+                    Some(event_handlers) => event_handlers,
+                    None => return
+                };
+                for i in 0..event_handlers.len() {
+                    if event_handlers[i].idx == handler_id {
+                        event_handlers.remove(i);
+                        break;
+                    }
+                }
+            }
+            UnhookArgs::Owner(owner) => {
+                for event_handlers in handlers.iter_mut() {
+                    let mut i = 0;
+                    while i < event_handlers.len() {
+                        if event_handlers[i].owner == Some(owner) {
+                            event_handlers.remove(i);
+                        } else {
+                            i += 1;
+                        }
+                    }
+                }
             }
         }
     }
@@ -391,48 +913,143 @@ impl<'a> EventHooker<'a> {
 struct HookArgs<'a> {
     event_kind: EventKind,
     handler_id: HandlerId,
+    options: HookOptions,
     func: BoxedFn<'a>
 }
 
 #[derive(Clone)]
-struct UnhookArgs {
-    event_kind: EventKind,
-    handler_id: HandlerId 
+enum UnhookArgs {
+    /// Remove the handler with id `handler_id` hooked to `event_kind`
+    Handler {
+        event_kind: EventKind,
+        handler_id: HandlerId
+    },
+    /// Remove all the handlers that belong to the owner
+    Owner(HandlerOwner)
 }
 
 
-type Handlers<'a> = [Vec<'a, Handler<'a>>; 3];
+/// The functions hooked to every kind of event
+struct Handlers<'a> {
+    /// The functions hooked to the kinds of events the machine sends,
+    /// at the kinds' `*_INDEX`es
+    builtin: [Vec<'a, Handler<'a>>; 9],
+    /// The functions hooked to custom event kinds, with the kinds' ids
+    custom: Vec<'a, (u32, Vec<'a, Handler<'a>>)>,
+    /// Allocates the vectors of custom event kinds as they're first hooked to
+    allocator: &'a dyn Allocator
+}
 
-impl<'a> Index<EventKind> for Handlers<'a> {
-    type Output = Vec<'a, Handler<'a>>;
-    fn index(&self, event: EventKind) -> &Self::Output {
-        match event {
-            EventKind::Timer => &self[TIMER_INDEX],
-            EventKind::Keyboard => &self[KEYBOARD_INDEX],
-            EventKind::Sound => &self[SOUND_INDEX]
+impl<'a> Handlers<'a> {
+    /// The functions hooked to `event_kind`, or None for a custom event kind
+    /// nothing has been hooked to yet
+    fn get(&self, event_kind: EventKind) -> Option<&Vec<'a, Handler<'a>>> {
+        match builtin_index(event_kind) {
+            Some(idx) => Some(&self.builtin[idx]),
+            None => self.custom.iter()
+                .find(|(id, _)| EventKind::Custom(*id) == event_kind)
+                .map(|(_, handlers)| handlers)
         }
     }
-}
 
-impl<'a> IndexMut<EventKind> for Handlers<'a> {
-    fn index_mut(&mut self, event_kind: EventKind) -> &mut Self::Output {
-        match event_kind {
-            EventKind::Timer => &mut self[TIMER_INDEX],
-            EventKind::Keyboard => &mut self[KEYBOARD_INDEX],
-            EventKind::Sound => &mut self[SOUND_INDEX]
+    fn get_mut(&mut self, event_kind: EventKind) -> Option<&mut Vec<'a, Handler<'a>>> {
+        match builtin_index(event_kind) {
+            Some(idx) => Some(&mut self.builtin[idx]),
+            None => self.custom.iter_mut()
+                .find(|(id, _)| EventKind::Custom(*id) == event_kind)
+                .map(|(_, handlers)| handlers)
+        }
+    }
+
+    /// The functions hooked to `event_kind`, giving a custom event kind
+    /// its vector if it doesn't have one yet
+    fn get_or_insert(&mut self, event_kind: EventKind) -> &mut Vec<'a, Handler<'a>> {
+        if let EventKind::Custom(id) = event_kind {
+            if self.get(event_kind).is_none() {
+                self.custom.push((id, Vec::with_capacity(1, self.allocator)));
+            }
         }
+        self.get_mut(event_kind).unwrap()
+    }
+
+    /// The vectors of functions of all the event kinds
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Vec<'a, Handler<'a>>> {
+        self.builtin.iter_mut().chain(self.custom.iter_mut().map(|(_, handlers)| handlers))
+    }
+}
+
+/// The index of a kind of event the machine sends in the handler arrays,
+/// or None for a custom event kind
+fn builtin_index(event_kind: EventKind) -> Option<usize> {
+    match event_kind {
+        EventKind::Timer => Some(TIMER_INDEX),
+        EventKind::Keyboard => Some(KEYBOARD_INDEX),
+        EventKind::Sound => Some(SOUND_INDEX),
+        EventKind::SystemReset => Some(SYSTEM_RESET_INDEX),
+        EventKind::PowerButton => Some(POWER_BUTTON_INDEX),
+        EventKind::JackChange => Some(JACK_CHANGE_INDEX),
+        EventKind::SoundError => Some(SOUND_ERROR_INDEX),
+        EventKind::Mouse => Some(MOUSE_INDEX),
+        EventKind::Gamepad => Some(GAMEPAD_INDEX),
+        EventKind::Custom(_) => None
     }
 }
 
 
 pub type HandlerId = usize;
 
+/// A tag that identifies the part of the code that hooked a handler, like "game" or "menu"
+pub type HandlerOwner = &'static str;
+
+/// What a handler lets happen to the event it was invoked with
+///
+/// Handlers that return nothing let the event go on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HandlerResult {
+    /// The event goes on to the next handler
+    #[default]
+    Continue,
+    /// The event was dealt with, so the handlers after this one don't get it
+    Consume
+}
+
+impl From<()> for HandlerResult {
+    fn from(_: ()) -> Self {
+        HandlerResult::Continue
+    }
+}
+
+/// When a handler is invoked compared to the other handlers of the same event
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High
+}
+
+/// How a handler is hooked
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HookOptions {
+    /// The part of the code the handler belongs to, for `unhook_all`
+    pub owner: Option<HandlerOwner>,
+    /// Handlers with higher priorities are invoked first
+    pub priority: Priority,
+    /// Unhooks the handler after the first time it's invoked
+    pub once: bool
+}
+
 /// A unique function in an vector associated with a particular event
 #[derive(Clone, Debug)]
 pub struct Handler<'a> {
     /// A unique number in the vector associated with the handler.
     /// Used to identify the handler when removing handlers
     idx: HandlerId,
+    /// The part of the code the handler belongs to, if it was hooked with one
+    owner: Option<HandlerOwner>,
+    priority: Priority,
+    /// Whether the handler is unhooked after it's invoked
+    once: bool,
     /// A function that is executed whenever the associated event is sent
     func: BoxedFn<'a>,
 }
@@ -440,40 +1057,44 @@ pub struct Handler<'a> {
 #[derive(Debug, PartialEq)]
 pub enum Error {
     /// Returned when unhook_event is called with a non existent idx
-    IdxNotFound
+    IdxNotFound,
+    /// Returned when a `FixedEventHooker` has no room for another handler
+    NoSpace,
+    /// Returned when a `FixedEventHooker`'s handlers are being modified
+    /// by the code that was interrupted
+    Busy,
+    /// Returned when a function is hooked to a custom event kind on a
+    /// `FixedEventHooker`, which only has room for the machine's events
+    CustomEventKind
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Event, EventKind, EventHooker, HandlerId, BoxedFn};
+    use crate::{Event, EventKind, EventHooker, HandlerId, BoxedFn, DropStats, HookOptions, Priority, HandlerResult};
     use collections::allocator::{Allocator, Error};
+    use collections::fault::FaultInjectionAllocator;
     use std::vec::Vec as StdVec;
     use core::mem::ManuallyDrop;
     use core::mem;
     use crate::box_fn;
+    use sync::once::Once;
 
-    static mut EVENT_HOOKER: Option<EventHooker> = None;
+    static EVENT_HOOKER: Once<EventHooker<'static>> = Once::new();
 
     fn init() {
-        unsafe { EVENT_HOOKER = Some(EventHooker::new(&AlwaysSuccessfulAllocator)); }
+        EVENT_HOOKER.call_once(|| EventHooker::new(&AlwaysSuccessfulAllocator));
     }
 
     fn send_event(event: Event) {
-        unsafe {
-            EVENT_HOOKER.as_mut().unwrap().send_event(event)
-        }
+        EVENT_HOOKER.get().unwrap().send_event(event)
     }
 
     fn hook_event(event_kind: EventKind, func: BoxedFn<'static>) -> HandlerId {
-        unsafe {
-            EVENT_HOOKER.as_mut().unwrap().hook_event(event_kind, func)
-        }
+        EVENT_HOOKER.get().unwrap().hook_event(event_kind, func)
     }
 
     fn unhook_event(handler_id: HandlerId, event_kind: EventKind) {
-        unsafe {
-            EVENT_HOOKER.as_mut().unwrap().unhook_event(handler_id, event_kind);
-        }
+        EVENT_HOOKER.get().unwrap().unhook_event(handler_id, event_kind);
     }
 
     #[test]
@@ -497,18 +1118,147 @@ mod tests {
         send_event(Event::Timer);
         assert_eq!(x, 2);
 
-        let hook1_id_in_handlers = unsafe { EVENT_HOOKER.as_mut().unwrap() }
+        let hook1_id_in_handlers = EVENT_HOOKER.get().unwrap()
             .handler_exists(EventKind::Timer, hook1_id).unwrap();
         assert!(hook1_id_in_handlers);
 
         send_event(Event::Timer);
         assert_eq!(x, 2 + 3);
 
-        let hook1_id_in_handlers = unsafe { EVENT_HOOKER.as_mut().unwrap() }
+        let hook1_id_in_handlers = EVENT_HOOKER.get().unwrap()
             .handler_exists(EventKind::Timer, hook1_id).unwrap();
         assert!(!hook1_id_in_handlers);
     }
 
+    #[test]
+    fn test_unhook_all_only_removes_the_owners_handlers() {
+        let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+        let mut x = 0;
+        let game_hook_id = event_hooker.hook_event_with_owner(EventKind::Timer, "game", box_fn!(|_| {
+            x += 1;
+        }, &AlwaysSuccessfulAllocator));
+        event_hooker.hook_event_with_owner(EventKind::Timer, "menu", box_fn!(|_| {
+            x += 10;
+        }, &AlwaysSuccessfulAllocator));
+        event_hooker.hook_event(EventKind::Timer, box_fn!(|_| {
+            x += 100;
+        }, &AlwaysSuccessfulAllocator));
+        event_hooker.unhook_all("game");
+        assert!(!event_hooker.handler_exists(EventKind::Timer, game_hook_id).unwrap());
+
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(x, 110);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+        let order = core::cell::RefCell::new(StdVec::new());
+        let hook = |event_hooker: &EventHooker, priority, n| {
+            let options = HookOptions { priority, ..HookOptions::default() };
+            let order = &order;
+            event_hooker.hook_event_with_options(EventKind::Timer, options, box_fn!(move |_| order.borrow_mut().push(n), &AlwaysSuccessfulAllocator));
+        };
+        hook(&event_hooker, Priority::Normal, 1);
+        hook(&event_hooker, Priority::Low, 2);
+        hook(&event_hooker, Priority::High, 3);
+        hook(&event_hooker, Priority::Normal, 4);
+        hook(&event_hooker, Priority::High, 5);
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(*order.borrow(), [3, 5, 1, 4, 2]);
+    }
+
+    #[test]
+    fn test_once() {
+        let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+        let mut x = 0;
+        let once = HookOptions { once: true, ..HookOptions::default() };
+        let once_id = event_hooker.hook_event_with_options(EventKind::Timer, once, box_fn!(|_| x += 1, &AlwaysSuccessfulAllocator));
+        event_hooker.hook_event(EventKind::Timer, box_fn!(|_| x += 10, &AlwaysSuccessfulAllocator));
+        event_hooker.send_event(Event::Sound);
+        assert!(event_hooker.handler_exists(EventKind::Timer, once_id).unwrap());
+        event_hooker.send_event(Event::Timer);
+        assert!(!event_hooker.handler_exists(EventKind::Timer, once_id).unwrap());
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(x, 21);
+    }
+
+    #[test]
+    fn test_consumed_events() {
+        let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+        let consume = core::cell::Cell::new(false);
+        let mut x = 0;
+        let high = HookOptions { priority: Priority::High, ..HookOptions::default() };
+        event_hooker.hook_event(EventKind::Timer, box_fn!(|_| x += 1, &AlwaysSuccessfulAllocator));
+        event_hooker.hook_event_with_options(EventKind::Timer, high, box_fn!(|_| {
+            if consume.get() { HandlerResult::Consume } else { HandlerResult::Continue }
+        }, &AlwaysSuccessfulAllocator));
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(x, 1);
+        consume.set(true);
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(x, 1);
+    }
+
+    #[test]
+    fn test_custom_events() {
+        let event_hooker = EventHooker::new(&AlwaysSuccessfulAllocator);
+        let mut x = 0;
+        // Nothing is hooked to them yet, so they go nowhere
+        event_hooker.send_event(Event::Custom(1, 5));
+        assert_eq!(event_hooker.has_handlers(EventKind::Custom(1)), Some(false));
+        let idx = event_hooker.hook_event(EventKind::Custom(1), box_fn!(|event| {
+            if let Event::Custom(_, value) = event {
+                x += value;
+            }
+        }, &AlwaysSuccessfulAllocator));
+        event_hooker.hook_event_with_owner(EventKind::Custom(2), "game", box_fn!(|_| x += 100, &AlwaysSuccessfulAllocator));
+        event_hooker.send_event(Event::Custom(1, 5));
+        assert_eq!(x, 5);
+        event_hooker.send_event(Event::Custom(2, 0));
+        assert_eq!(x, 105);
+        event_hooker.unhook_event(idx, EventKind::Custom(1));
+        event_hooker.unhook_all("game");
+        event_hooker.send_event(Event::Custom(1, 5));
+        event_hooker.send_event(Event::Custom(2, 0));
+        assert_eq!(x, 105);
+        assert_eq!(event_hooker.has_handlers(EventKind::Custom(2)), Some(false));
+    }
+
+    #[test]
+    fn test_events_that_cant_be_queued_are_counted() {
+        let allocator = FaultInjectionAllocator::new(&AlwaysSuccessfulAllocator);
+        let event_hooker = EventHooker::new(&allocator);
+        let mut sounds = 0;
+        event_hooker.hook_event(EventKind::Sound, box_fn!(|_| sounds += 1, &AlwaysSuccessfulAllocator));
+        event_hooker.hook_event(EventKind::Timer, box_fn!(|_| {
+            for _ in 0..4 {
+                event_hooker.send_event(Event::Sound);
+            }
+        }, &AlwaysSuccessfulAllocator));
+        // The missed events queue can hold 3 events without growing
+        allocator.fail_from_nth(0);
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(sounds, 3);
+        assert_eq!(event_hooker.dropped(), DropStats { events: 1, hooks: 0, unhooks: 0 });
+        assert_eq!(allocator.failures(), 1);
+    }
+
+    #[test]
+    fn test_hooks_that_cant_be_queued_are_counted() {
+        let allocator = FaultInjectionAllocator::new(&AlwaysSuccessfulAllocator);
+        let event_hooker = EventHooker::new(&allocator);
+        event_hooker.hook_event(EventKind::Timer, box_fn!(|_| {
+            for _ in 0..4 {
+                event_hooker.hook_event(EventKind::Sound, box_fn!(|_| (), &AlwaysSuccessfulAllocator));
+            }
+        }, &AlwaysSuccessfulAllocator));
+        // Only the queue growing for the fourth hook fails
+        allocator.fail_nth(0);
+        event_hooker.send_event(Event::Timer);
+        assert_eq!(event_hooker.dropped(), DropStats { events: 0, hooks: 1, unhooks: 0 });
+    }
+
     struct AlwaysSuccessfulAllocator;
     unsafe impl Allocator for AlwaysSuccessfulAllocator {
         unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
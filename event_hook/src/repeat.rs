@@ -0,0 +1,122 @@
+//! Key repeat synthesized from timer ticks
+//!
+//! When a key is held down, the keyboard itself sends the key's make code again
+//! and again at the typematic rate, which is set by the firmware and differs
+//! from machine to machine. The `KeyRepeater` drops those repeats and sends its
+//! own instead, on timer ticks, for as long as the key hasn't been released.
+
+use machine::keyboard::{KeyCode, KeyDirection, KeyModifiers};
+use crate::Event;
+
+/// Tracks the key that is held down and repeats it at a fixed rate
+#[derive(Clone, Copy, Debug)]
+pub struct KeyRepeater {
+    /// The number of timer ticks between a key being pressed and its first repeat
+    delay_ticks: usize,
+    /// The number of timer ticks between repeats
+    interval_ticks: usize,
+    /// The key that is held down, with the modifiers it was pressed with
+    held: Option<(KeyCode, KeyModifiers)>,
+    /// The number of timer ticks until the next repeat of the held key
+    ticks_left: usize
+}
+
+impl KeyRepeater {
+    /// Creates a repeater that starts repeating a key `delay_ticks` timer ticks
+    /// after it was pressed and repeats it every `interval_ticks` ticks after that
+    ///
+    /// An interval of 0 is treated as 1, a repeat on every tick
+    pub const fn new(delay_ticks: usize, interval_ticks: usize) -> Self {
+        Self {
+            delay_ticks,
+            interval_ticks: if interval_ticks == 0 { 1 } else { interval_ticks },
+            held: None,
+            ticks_left: 0
+        }
+    }
+
+    /// Tracks the key presses and releases the keyboard sends
+    ///
+    /// Returns false if the event is the keyboard's own repeat of
+    /// the held key, which should be dropped
+    pub fn key_event(&mut self, keycode: KeyCode, direction: KeyDirection, modifiers: KeyModifiers) -> bool {
+        match direction {
+            KeyDirection::Down => {
+                if let Some((held_keycode, _)) = self.held {
+                    if held_keycode == keycode {
+                        return false;
+                    }
+                }
+                // Like the keyboard, only the key pressed last is repeated
+                self.held = Some((keycode, modifiers));
+                self.ticks_left = self.delay_ticks.max(1);
+            }
+            KeyDirection::Up => {
+                if let Some((held_keycode, _)) = self.held {
+                    if held_keycode == keycode {
+                        self.held = None;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Stops repeating the held key, for when the keyboard can't be holding it anymore
+    pub fn release(&mut self) {
+        self.held = None;
+    }
+
+    /// Counts a timer tick
+    ///
+    /// Returns the repeat of the held key if one is due
+    pub fn tick(&mut self) -> Option<Event> {
+        let (keycode, modifiers) = self.held?;
+        self.ticks_left -= 1;
+        if self.ticks_left > 0 {
+            return None;
+        }
+        self.ticks_left = self.interval_ticks;
+        Some(Event::Keyboard(keycode, KeyDirection::Down, modifiers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use machine::keyboard::{KeyCode, KeyDirection, KeyModifiers};
+    use crate::Event;
+    use super::KeyRepeater;
+
+    fn repeats(repeater: &mut KeyRepeater, ticks: usize) -> usize {
+        (0..ticks).filter(|_| repeater.tick().is_some()).count()
+    }
+
+    #[test]
+    fn test_held_key_is_repeated_after_the_delay() {
+        let mut repeater = KeyRepeater::new(3, 2);
+        assert!(repeater.key_event(KeyCode::ArrowLeft, KeyDirection::Down, KeyModifiers::new()));
+        assert_eq!(repeats(&mut repeater, 2), 0);
+        match repeater.tick() {
+            Some(Event::Keyboard(KeyCode::ArrowLeft, KeyDirection::Down, _)) => (),
+            other => panic!("Expected a repeat of the left arrow, got {:?}", other)
+        }
+        // Every other tick after the first repeat
+        assert_eq!(repeats(&mut repeater, 6), 3);
+        assert!(repeater.key_event(KeyCode::ArrowLeft, KeyDirection::Up, KeyModifiers::new()));
+        assert_eq!(repeats(&mut repeater, 10), 0);
+    }
+
+    #[test]
+    fn test_keyboard_repeats_are_dropped() {
+        let mut repeater = KeyRepeater::new(1, 1);
+        assert!(repeater.key_event(KeyCode::ArrowRight, KeyDirection::Down, KeyModifiers::new()));
+        assert!(!repeater.key_event(KeyCode::ArrowRight, KeyDirection::Down, KeyModifiers::new()));
+        // A new key replaces the held one
+        assert!(repeater.key_event(KeyCode::ArrowLeft, KeyDirection::Down, KeyModifiers::new()));
+        assert!(repeater.key_event(KeyCode::ArrowRight, KeyDirection::Up, KeyModifiers::new()));
+        match repeater.tick() {
+            Some(Event::Keyboard(KeyCode::ArrowLeft, KeyDirection::Down, _)) => (),
+            other => panic!("Expected a repeat of the left arrow, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Functions invoked once after a while, or over and over at an interval
+//!
+//! The waits are counted in timer ticks, so they're rounded up to whole
+//! ticks of about 55ms. The functions are invoked from the timer event's
+//! handler with `Event::Timer`
+
+use collections::vec::Vec;
+use collections::allocator::Allocator;
+use crate::{BoxedFn, Event};
+
+pub type TimeoutId = usize;
+
+/// A function waiting to be invoked
+#[derive(Clone)]
+pub(crate) struct Timeout<'a> {
+    id: TimeoutId,
+    /// The number of ticks until the function is invoked
+    ticks_left: u64,
+    /// The number of ticks between invocations, for a function that's
+    /// invoked over and over
+    interval_ticks: Option<u64>,
+    func: BoxedFn<'a>
+}
+
+impl<'a> Timeout<'a> {
+    pub(crate) fn invoke(&self) {
+        (self.func)(Event::Timer);
+    }
+}
+
+/// The functions waiting to be invoked after some ticks
+///
+/// A function that's due is taken out with `take_due` and put back with
+/// `finish` once it's been invoked, so the timeouts don't have to be locked
+/// while it runs and it can set and clear timeouts itself
+pub(crate) struct Timeouts<'a> {
+    timeouts: Vec<'a, Timeout<'a>>,
+    next_id: TimeoutId,
+    /// The timeout that has been taken out to be invoked
+    running: Option<TimeoutId>,
+    /// Whether the running timeout was cleared while it was being invoked
+    running_cleared: bool
+}
+
+unsafe impl<'a> Send for Timeouts<'a> {}
+
+impl<'a> Timeouts<'a> {
+    pub(crate) fn new(allocator: &'a dyn Allocator) -> Self {
+        Self {
+            timeouts: Vec::with_capacity(1, allocator),
+            next_id: 0,
+            running: None,
+            running_cleared: false
+        }
+    }
+
+    /// Adds `func` to be invoked after `ticks` ticks, and every `ticks` ticks
+    /// after that if `repeat` is set
+    ///
+    /// A wait of 0 ticks is taken as 1, so the function is invoked on the next tick
+    pub(crate) fn set(&mut self, ticks: u64, repeat: bool, func: BoxedFn<'a>) -> TimeoutId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let ticks = ticks.max(1);
+        self.timeouts.push(Timeout {
+            id,
+            ticks_left: ticks,
+            interval_ticks: if repeat { Some(ticks) } else { None },
+            func
+        });
+        id
+    }
+
+    /// Removes the timeout with id `id`, so its function isn't invoked anymore
+    pub(crate) fn clear(&mut self, id: TimeoutId) {
+        if self.running == Some(id) {
+            self.running_cleared = true;
+            return;
+        }
+        if let Some(i) = self.timeouts.iter().position(|timeout| timeout.id == id) {
+            self.timeouts.remove(i);
+        }
+    }
+
+    /// Counts `ticks` ticks off every timeout
+    pub(crate) fn tick(&mut self, ticks: u64) {
+        for timeout in self.timeouts.iter_mut() {
+            timeout.ticks_left = timeout.ticks_left.saturating_sub(ticks);
+        }
+    }
+
+    /// Takes out a timeout whose function is due to be invoked
+    ///
+    /// It has to be given back to `finish` after its function has been invoked
+    pub(crate) fn take_due(&mut self) -> Option<Timeout<'a>> {
+        let i = self.timeouts.iter().position(|timeout| timeout.ticks_left == 0)?;
+        let timeout = self.timeouts.remove(i);
+        self.running = Some(timeout.id);
+        self.running_cleared = false;
+        Some(timeout)
+    }
+
+    /// Puts a timeout whose function has been invoked back for its next
+    /// interval, or drops it if it was only to be invoked once or it was cleared
+    pub(crate) fn finish(&mut self, mut timeout: Timeout<'a>) {
+        self.running = None;
+        if let (Some(interval_ticks), false) = (timeout.interval_ticks, self.running_cleared) {
+            timeout.ticks_left = interval_ticks;
+            self.timeouts.push(timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timeouts;
+    use crate::box_fn;
+    use collections::allocator::{Allocator, Error};
+    use std::vec::Vec as StdVec;
+    use core::mem::ManuallyDrop;
+    use core::mem;
+
+    fn run_due(timeouts: &mut Timeouts) {
+        while let Some(timeout) = timeouts.take_due() {
+            timeout.invoke();
+            timeouts.finish(timeout);
+        }
+    }
+
+    #[test]
+    fn test_timeouts_and_intervals() {
+        let mut timeouts = Timeouts::new(&AlwaysSuccessfulAllocator);
+        let (mut once, mut repeated) = (0, 0);
+        timeouts.set(2, false, box_fn!(|_| once += 1, &AlwaysSuccessfulAllocator));
+        let interval = timeouts.set(3, true, box_fn!(|_| repeated += 1, &AlwaysSuccessfulAllocator));
+        for _ in 0..7 {
+            timeouts.tick(1);
+            run_due(&mut timeouts);
+        }
+        assert_eq!((once, repeated), (1, 2));
+        timeouts.clear(interval);
+        timeouts.tick(3);
+        run_due(&mut timeouts);
+        assert_eq!((once, repeated), (1, 2));
+    }
+
+    #[test]
+    fn test_clearing_an_interval_while_it_runs() {
+        let mut timeouts = Timeouts::new(&AlwaysSuccessfulAllocator);
+        let mut calls = 0;
+        let id = timeouts.set(0, true, box_fn!(|_| calls += 1, &AlwaysSuccessfulAllocator));
+        timeouts.tick(1);
+        let timeout = timeouts.take_due().unwrap();
+        timeout.invoke();
+        timeouts.clear(id);
+        timeouts.finish(timeout);
+        timeouts.tick(1);
+        run_due(&mut timeouts);
+        assert_eq!(calls, 1);
+    }
+
+    struct AlwaysSuccessfulAllocator;
+    unsafe impl Allocator for AlwaysSuccessfulAllocator {
+        unsafe fn alloc(&self, size_of_type: usize, size_to_alloc: usize) -> Result<*mut u8, Error> {
+            let mut v: ManuallyDrop<StdVec<u8>> = ManuallyDrop::new(StdVec::with_capacity(size_of_type * size_to_alloc));
+            Ok(v.as_mut_ptr() as *mut u8)
+        }
+        unsafe fn dealloc(&self, ptr: *mut u8, size_to_dealloc: usize)  -> Result<(), Error> {
+            let v: StdVec<u8> = StdVec::from_raw_parts(ptr, size_to_dealloc, size_to_dealloc);
+            mem::drop(v);
+            Ok(())
+        }
+    }
+}
@@ -201,6 +201,10 @@ impl RSDT {
         self.find_table::<MADT>(MADT_SIGNATURE)
     }
 
+    pub unsafe fn find_mcfg(&self) -> Option<&MCFG> {
+        self.find_table::<MCFG>(MCFG_SIGNATURE)
+    }
+
     unsafe fn find_table<T>(&self, table_sig: ACPITableSig) -> Option<&T> {
         for sdt_addr_array in self.entries_bytes().array_windows::<4>() {
             let sdt_addr = u32::from_le_bytes(*sdt_addr_array);
@@ -251,13 +255,34 @@ pub struct FADT {
     firmware_ctrl: u32,
     /// The address of the DSDT
     dsdt_address: u32,
-    unneeded_fields1: [u8; 20],
+    reserved: u8,
+    preferred_pm_profile: u8,
+    /// The ISA interrupt the System Control Interrupt is wired to
+    sci_int: u16,
+    /// The I/O port for switching the hardware between legacy and ACPI mode
+    smi_cmd: u32,
+    /// The value to write to `smi_cmd` to switch to ACPI mode
+    acpi_enable: u8,
+    /// The value to write to `smi_cmd` to switch back to legacy mode
+    acpi_disable: u8,
+    unneeded_fields1: [u8; 2],
+    pm1a_event_block: u32,
+    pm1b_event_block: u32,
     pm1a_ctrl_block: u32,
     pm1b_ctrl_block: u32,
-    unneeded_fields2: [u8; 17],
+    unneeded_fields2: [u8; 8],
+    gpe0_block: u32,
+    gpe1_block: u32,
+    pm1_event_length: u8,
     pm1_ctrl_length: u8,
+    unneeded_fields3: [u8; 2],
+    gpe0_block_length: u8,
+    gpe1_block_length: u8,
+    unneeded_fields4: [u8; 18],
+    /// The fixed feature flags
+    flags: u32,
     /// We don't need the other fields
-    others: [u8; 152]
+    others: [u8; 128]
 }
 
 impl FADT {
@@ -273,6 +298,65 @@ impl FADT {
     pub fn pm1b_ctrl_block(&self) -> u32 {
         self.pm1b_ctrl_block
     }
+
+    /// The port of the PM1a event register block, which holds
+    /// the status and enable registers of the fixed events
+    pub fn pm1a_event_block(&self) -> u32 {
+        self.pm1a_event_block
+    }
+
+    /// The port of the PM1b event register block, or 0 if there isn't one
+    pub fn pm1b_event_block(&self) -> u32 {
+        self.pm1b_event_block
+    }
+
+    /// The length in bytes of each PM1 event register block.
+    /// The status register is the first half and the enable register is the second half
+    pub fn pm1_event_length(&self) -> u8 {
+        self.pm1_event_length
+    }
+
+    /// The port of the general purpose event 0 register block, or 0 if there isn't one
+    pub fn gpe0_block(&self) -> u32 {
+        self.gpe0_block
+    }
+
+    pub fn gpe0_block_length(&self) -> u8 {
+        self.gpe0_block_length
+    }
+
+    /// The port of the general purpose event 1 register block, or 0 if there isn't one
+    pub fn gpe1_block(&self) -> u32 {
+        self.gpe1_block
+    }
+
+    pub fn gpe1_block_length(&self) -> u8 {
+        self.gpe1_block_length
+    }
+
+    /// The ISA interrupt the System Control Interrupt is wired to
+    pub fn sci_int(&self) -> u16 {
+        self.sci_int
+    }
+
+    /// The port for switching to ACPI mode, or 0 if the hardware
+    /// is always in ACPI mode
+    pub fn smi_cmd(&self) -> u32 {
+        self.smi_cmd
+    }
+
+    /// The value to write to the `smi_cmd` port to switch to ACPI mode
+    pub fn acpi_enable(&self) -> u8 {
+        self.acpi_enable
+    }
+
+    /// Checks if the power button is a fixed feature button, whose presses
+    /// are reported with the PWRBTN_STS bit, rather than a control method
+    /// button, which can only be handled by executing AML
+    pub fn has_fixed_power_button(&self) -> bool {
+        // The PWR_BUTTON flag is set for control method power buttons
+        self.flags & (1 << 4) == 0
+    }
 }
 
 impl SDTTable for FADT {
@@ -509,6 +593,77 @@ impl InterruptController {
     pub fn type_(&self) -> u8 {
         self.type_
     }
+
+    /// The whole entry, starting with its type and length
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, self.length as usize) }
+    }
+}
+
+const MCFG_SIGNATURE: &[u8; 4] = b"MCFG";
+
+/// The PCI Express Memory Mapped Configuration table (MCFG)
+///
+/// Tells where the configuration spaces of the PCI Express devices are mapped
+/// in memory, one entry for each range of buses in a segment group
+///
+/// # References
+///
+/// * The OSDev wiki <https://wiki.osdev.org/PCI_Express>
+#[repr(C)]
+pub struct MCFG {
+    header: SDTHeader,
+    reserved: [u8; 8]
+    // After this is a list of `MCFGEntry`s
+}
+
+impl MCFG {
+    pub fn entries(&self) -> impl Iterator<Item = MCFGEntry> + '_ {
+        let start_ptr = unsafe { (self as *const Self as *const u8).add(mem::size_of::<Self>()) };
+        let no_of_entries = (self.header.length as usize).saturating_sub(mem::size_of::<Self>()) / mem::size_of::<MCFGEntry>();
+        // The entries start 4 bytes past an 8 byte boundary, so their addresses aren't aligned
+        (0..no_of_entries).map(move |i| unsafe {
+            start_ptr.add(i * mem::size_of::<MCFGEntry>()).cast::<MCFGEntry>().read_unaligned()
+        })
+    }
+}
+
+impl SDTTable for MCFG {
+    unsafe fn is_valid(&self) -> bool {
+        is_valid(self, self.header.length)
+    }
+}
+
+/// The location of the configuration spaces of the buses `start_bus..=end_bus`
+/// in the PCI segment group `segment`
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MCFGEntry {
+    /// The physical address of the configuration space of the first function
+    /// of the first device on bus 0, whether or not the range starts at bus 0
+    base_addr: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32
+}
+
+impl MCFGEntry {
+    pub fn base_addr(&self) -> u64 {
+        self.base_addr
+    }
+
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
+
+    pub fn start_bus(&self) -> u8 {
+        self.start_bus
+    }
+
+    pub fn end_bus(&self) -> u8 {
+        self.end_bus
+    }
 }
 
 type ACPITableSig = &'static [u8; 4];
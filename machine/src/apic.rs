@@ -0,0 +1,478 @@
+//! The local APIC and the I/O APICs, which can deliver interrupts in place of the 8259 PICs
+//!
+//! On some chipsets, PCI interrupts are only wired to the I/O APIC, so the
+//! HDA controller's interrupt never reaches the PICs, whatever its interrupt
+//! line says. `init` masks the PICs, enables the local APIC and points I/O
+//! APIC redirection entries at the vectors the PICs used, so the IDT stays
+//! the same. The local APIC timer is measured against the PIT and takes over
+//! from channel 0 at the same rate, so ticks last as long as they did.
+//!
+//! Which I/O APIC input a PCI interrupt pin is wired to can only be found out
+//! for sure by executing AML, which this project can't do, so a PCI device's
+//! MSI is used when it has one, and the interrupt line the firmware left is
+//! trusted otherwise
+//!
+//! # References
+//!
+//! * The Intel SDM, volume 3, chapter 10 (Advanced Programmable Interrupt Controller)
+//! * The 82093AA I/O APIC datasheet
+//! * The ACPI spec, version 6.2, section 5.2.12
+//! * <https://wiki.osdev.org/APIC>
+//! * <https://wiki.osdev.org/IOAPIC>
+
+use core::arch::x86_64::__cpuid;
+use crate::acpi::{detect_rsdp, SDTTable, RSDP, MADT};
+use crate::interrupts::{swap_handler, InterruptStackFrame, IRQ};
+use crate::instructions::interrupts::without_interrupts;
+use crate::mmio::Register;
+use crate::pci::PCIDevice;
+use crate::pic8259::{Pics, PIC_1_OFFSET};
+use crate::registers::Msr;
+use crate::{serial_println, time};
+
+/// The MSR with the local APIC's base address and its global enable bit
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+/// The bit in CPUID leaf 1's EDX that says the processor has a local APIC
+const CPUID_APIC_BIT: u32 = 1 << 9;
+
+const LOCAL_APIC_ID: usize = 0x20;
+const LOCAL_APIC_TASK_PRIORITY: usize = 0x80;
+const LOCAL_APIC_EOI: usize = 0xb0;
+const LOCAL_APIC_SPURIOUS: usize = 0xf0;
+const LOCAL_APIC_LVT_TIMER: usize = 0x320;
+const LOCAL_APIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LOCAL_APIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LOCAL_APIC_TIMER_DIVIDE: usize = 0x3e0;
+/// Set in the spurious interrupt vector register to enable the local APIC
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+/// The vector of the interrupts the local APIC raises when an interrupt
+/// went away before it could be delivered, which need no EOI
+const SPURIOUS_VECTOR: u8 = 0xff;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Makes the local APIC timer count at the bus frequency divided by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+const IO_APIC_REGISTER_SELECT: usize = 0x00;
+const IO_APIC_WINDOW: usize = 0x10;
+const IO_APIC_VERSION: u32 = 0x01;
+/// The first of the pairs of registers that make up the redirection entries
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+/// MSI messages are writes to this address, with the destination local APIC's ID in bits 12 to 19
+const MSI_ADDR_BASE: u64 = 0xfee0_0000;
+
+const MADT_IO_APIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_ADDR_OVERRIDE: u8 = 5;
+
+/// The most I/O APICs that are used
+const MAX_IO_APICS: usize = 4;
+/// The number of ISA interrupts, which the MADT's source overrides are for
+const ISA_IRQS: usize = 16;
+
+/// How ISA devices signal their interrupts
+const ISA_MODE: InputMode = InputMode { active_low: false, level_triggered: false };
+/// How PCI devices signal their interrupts without MSI, and the SCI, which are
+/// shared lines held low until the interrupt is acknowledged
+const SHARED_MODE: InputMode = InputMode { active_low: true, level_triggered: true };
+
+/// The APICs, set once they have taken over from the PICs
+static mut APIC: Option<Apic> = None;
+
+/// Switches interrupt delivery over from the PICs to the APICs
+///
+/// The ISA interrupts in `isa_irqs` are routed through the I/O APICs and the
+/// local APIC timer ticks in place of PIT channel 0. Must be called with the
+/// IDT loaded. Returns an error, with the PICs still in use, if there's no
+/// local APIC, no valid MADT or I/O APIC, or if the timer couldn't be measured
+pub fn init(isa_irqs: &[IRQ]) -> Result<(), &'static str> {
+    if is_enabled() {
+        return Ok(());
+    }
+    if unsafe { __cpuid(1).edx } & CPUID_APIC_BIT == 0 {
+        return Err("The processor has no local APIC");
+    }
+    let madt = unsafe { find_madt() }.ok_or("Couldn't find a valid MADT")?;
+    let info = MadtInfo::parse(madt.local_interrupt_controller_addr(), madt.interrupt_controllers().map(|entry| entry.bytes()));
+    if info.io_apics.iter().all(Option::is_none) {
+        return Err("The MADT lists no I/O APIC");
+    }
+    without_interrupts(|| {
+        let local = LocalApic { addr: info.local_apic_addr };
+        local.enable()?;
+        let timer_count_per_ms = local.timer_count_per_ms()
+            .ok_or("The local APIC timer couldn't be measured against the PIT")?;
+        // Masked before the I/O APICs are set up, so no interrupt is delivered twice
+        Pics::new().write_masks(u8::MAX, u8::MAX);
+        for io_apic in info.io_apics.iter().flatten() {
+            io_apic.regs().mask_all();
+        }
+        let apic = Apic { local, info };
+        for &irq in isa_irqs {
+            let (gsi, mode) = info.isa_input(irq.as_u8(), default_isa_mode(irq));
+            if let Err(msg) = apic.route(gsi, mode, vector(irq)) {
+                serial_println!("IRQ {} can't be routed: {}", irq.as_u8(), msg);
+            }
+        }
+        let count_per_tick = time::count_per_tick(timer_count_per_ms).min(u32::MAX as u64) as u32;
+        local.start_timer(vector(IRQ::Timer), count_per_tick);
+        unsafe { APIC = Some(apic) };
+        Ok(())
+    })
+}
+
+/// Tells whether the APICs have taken over from the PICs
+pub fn is_enabled() -> bool {
+    unsafe { APIC.is_some() }
+}
+
+/// Tells the local APIC the interrupt being handled is done with
+///
+/// Must be called at the end of every interrupt handler, except the spurious one,
+/// once the APICs are in use
+pub fn end_of_interrupt() {
+    if let Some(apic) = unsafe { APIC.as_ref() } {
+        apic.local.reg(LOCAL_APIC_EOI).write(0);
+    }
+}
+
+/// Delivers `device`'s interrupts on `irq`'s vector
+///
+/// The device's MSI is used if it has one. Otherwise its interrupt line is
+/// taken as the interrupt it's wired to, which is only right if the
+/// firmware set it up that way
+pub fn route_pci_interrupt(device: &mut PCIDevice, irq: IRQ) -> Result<(), &'static str> {
+    let apic = unsafe { APIC.as_ref() }.ok_or("The APICs aren't in use")?;
+    let dest = apic.local.id();
+    if device.enable_msi(MSI_ADDR_BASE | (dest as u64) << 12, vector(irq) as u16).is_ok() {
+        return Ok(());
+    }
+    match device.interrupt_line() {
+        0xff => Err("The device has neither MSI nor an interrupt line"),
+        line => {
+            let (gsi, mode) = apic.info.isa_input(line, SHARED_MODE);
+            apic.route(gsi, mode, vector(irq))
+        }
+    }
+}
+
+/// Delivers the ISA interrupt `irq`, for devices set up after the APICs took over
+pub fn route_isa_interrupt(irq: IRQ) -> Result<(), &'static str> {
+    let apic = unsafe { APIC.as_ref() }.ok_or("The APICs aren't in use")?;
+    let (gsi, mode) = apic.info.isa_input(irq.as_u8(), default_isa_mode(irq));
+    apic.route(gsi, mode, vector(irq))
+}
+
+/// The vector `irq` is delivered on, which is the one the PICs delivered it on
+fn vector(irq: IRQ) -> u8 {
+    PIC_1_OFFSET + irq.as_u8()
+}
+
+/// How `irq` is signalled when the MADT doesn't say otherwise
+fn default_isa_mode(irq: IRQ) -> InputMode {
+    match irq {
+        // The ACPI spec says the SCI is a shareable, level triggered, active low interrupt
+        IRQ::Acpi => SHARED_MODE,
+        _ => ISA_MODE
+    }
+}
+
+/// Finds the MADT and checks that it and the tables leading to it are valid
+unsafe fn find_madt() -> Option<&'static MADT> {
+    let rsdp = detect_rsdp()?;
+    if rsdp == RSDP::None || !rsdp.is_valid() {
+        return None;
+    }
+    let rsdt = &*rsdp.rsdt_ptr();
+    if !rsdt.is_valid() {
+        return None;
+    }
+    let madt = rsdt.find_madt()?;
+    if !madt.is_valid() {
+        return None;
+    }
+    Some(madt)
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_sf: InterruptStackFrame) {}
+
+struct Apic {
+    local: LocalApic,
+    info: MadtInfo
+}
+
+impl Apic {
+    /// Points the redirection entry of global system interrupt `gsi`
+    /// at `vector` on this processor
+    fn route(&self, gsi: u32, mode: InputMode, vector: u8) -> Result<(), &'static str> {
+        let io_apic = self.info.io_apic_for(gsi).ok_or("No I/O APIC takes the interrupt")?;
+        let regs = io_apic.regs();
+        let input = gsi - io_apic.gsi_base;
+        if input >= regs.redirection_entries() {
+            return Err("No I/O APIC takes the interrupt");
+        }
+        regs.set_redirection_entry(input, redirection_entry(vector, mode, self.local.id()));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LocalApic {
+    addr: u64
+}
+
+impl LocalApic {
+    fn reg(&self, offset: usize) -> Register<u32> {
+        unsafe { Register::new((self.addr as usize + offset) as *mut u32) }
+    }
+
+    fn id(&self) -> u8 {
+        (self.reg(LOCAL_APIC_ID).read() >> 24) as u8
+    }
+
+    /// Enables the local APIC, accepting interrupts of every priority
+    fn enable(&self) -> Result<(), &'static str> {
+        // Spurious interrupts can come as soon as the local APIC is enabled,
+        // and the handler is never put back
+        let _ = swap_handler(SPURIOUS_VECTOR, spurious_interrupt_handler)?;
+        let base = Msr::new(IA32_APIC_BASE_MSR);
+        unsafe { base.write(base.read() | APIC_GLOBAL_ENABLE) };
+        self.reg(LOCAL_APIC_SPURIOUS).write(SPURIOUS_VECTOR as u32 | APIC_SOFTWARE_ENABLE);
+        self.reg(LOCAL_APIC_TASK_PRIORITY).write(0);
+        Ok(())
+    }
+
+    /// Measures how many times the timer counts in a millisecond, or
+    /// returns None if it couldn't be measured
+    fn timer_count_per_ms(&self) -> Option<u64> {
+        self.reg(LOCAL_APIC_TIMER_DIVIDE).write(TIMER_DIVIDE_BY_16);
+        self.reg(LOCAL_APIC_LVT_TIMER).write(LVT_MASKED);
+        self.reg(LOCAL_APIC_TIMER_INITIAL_COUNT).write(u32::MAX);
+        let current_count = self.reg(LOCAL_APIC_TIMER_CURRENT_COUNT);
+        // The timer counts down
+        let count_per_ms = time::count_per_ms(|| (u32::MAX - current_count.read()) as u64);
+        // Stops the timer
+        self.reg(LOCAL_APIC_TIMER_INITIAL_COUNT).write(0);
+        count_per_ms.filter(|&count| count > 0)
+    }
+
+    /// Makes the timer raise `vector` every `count` counts
+    fn start_timer(&self, vector: u8, count: u32) {
+        self.reg(LOCAL_APIC_TIMER_DIVIDE).write(TIMER_DIVIDE_BY_16);
+        self.reg(LOCAL_APIC_LVT_TIMER).write(vector as u32 | LVT_TIMER_PERIODIC);
+        self.reg(LOCAL_APIC_TIMER_INITIAL_COUNT).write(count);
+    }
+}
+
+/// The registers of an I/O APIC, which are reached through a select register and a window
+struct IoApicRegs {
+    addr: u64
+}
+
+impl IoApicRegs {
+    fn read(&self, reg: u32) -> u32 {
+        self.select(reg);
+        self.window().read()
+    }
+
+    fn write(&self, reg: u32, val: u32) {
+        self.select(reg);
+        self.window().write(val);
+    }
+
+    fn select(&self, reg: u32) {
+        unsafe { Register::new((self.addr as usize + IO_APIC_REGISTER_SELECT) as *mut u32) }.write(reg);
+    }
+
+    fn window(&self) -> Register<u32> {
+        unsafe { Register::new((self.addr as usize + IO_APIC_WINDOW) as *mut u32) }
+    }
+
+    fn redirection_entries(&self) -> u32 {
+        ((self.read(IO_APIC_VERSION) >> 16) & 0xff) + 1
+    }
+
+    fn set_redirection_entry(&self, input: u32, entry: u64) {
+        let reg = IO_APIC_REDIRECTION_TABLE + input * 2;
+        // Masked while it's half written, so a half written entry is never used
+        self.write(reg, REDIRECTION_MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+
+    fn mask_all(&self) {
+        for input in 0..self.redirection_entries() {
+            self.set_redirection_entry(input, REDIRECTION_MASKED);
+        }
+    }
+}
+
+/// The redirection entry that delivers `vector` to the local APIC with ID `dest`
+fn redirection_entry(vector: u8, mode: InputMode, dest: u8) -> u64 {
+    let mut entry = vector as u64 | (dest as u64) << 56;
+    if mode.active_low {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if mode.level_triggered {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+    entry
+}
+
+/// How an interrupt is signalled on its input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct InputMode {
+    active_low: bool,
+    level_triggered: bool
+}
+
+impl InputMode {
+    /// Reads the polarity and trigger mode flags of a MADT entry, where
+    /// the ones that conform to the bus are `default`'s
+    fn from_flags(flags: u16, default: InputMode) -> Self {
+        let active_low = match flags & 0b11 {
+            0b01 => false,
+            0b11 => true,
+            _ => default.active_low
+        };
+        let level_triggered = match (flags >> 2) & 0b11 {
+            0b01 => false,
+            0b11 => true,
+            _ => default.level_triggered
+        };
+        Self { active_low, level_triggered }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IoApic {
+    addr: u32,
+    /// The global system interrupt of the I/O APIC's first input
+    gsi_base: u32
+}
+
+impl IoApic {
+    fn regs(&self) -> IoApicRegs {
+        IoApicRegs { addr: self.addr as u64 }
+    }
+}
+
+/// The global system interrupt an ISA interrupt is connected to, where it isn't the same number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SourceOverride {
+    gsi: u32,
+    flags: u16
+}
+
+/// What the MADT says about the interrupt controllers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MadtInfo {
+    local_apic_addr: u64,
+    io_apics: [Option<IoApic>; MAX_IO_APICS],
+    overrides: [Option<SourceOverride>; ISA_IRQS]
+}
+
+impl MadtInfo {
+    /// Reads the MADT's entries, each of which starts with its type and length
+    fn parse<'a>(local_apic_addr: u32, entries: impl Iterator<Item = &'a [u8]>) -> Self {
+        let mut info = Self {
+            local_apic_addr: local_apic_addr as u64,
+            io_apics: [None; MAX_IO_APICS],
+            overrides: [None; ISA_IRQS]
+        };
+        for entry in entries {
+            match (entry[0], entry.len()) {
+                (MADT_IO_APIC, 12..) => {
+                    let io_apic = IoApic { addr: read_u32(entry, 4), gsi_base: read_u32(entry, 8) };
+                    if let Some(slot) = info.io_apics.iter_mut().find(|slot| slot.is_none()) {
+                        *slot = Some(io_apic);
+                    }
+                }
+                // Only the overrides of the ISA bus, which is bus 0, are used
+                (MADT_SOURCE_OVERRIDE, 10..) if entry[2] == 0 && (entry[3] as usize) < ISA_IRQS => {
+                    let flags = u16::from_le_bytes([entry[8], entry[9]]);
+                    info.overrides[entry[3] as usize] = Some(SourceOverride { gsi: read_u32(entry, 4), flags });
+                }
+                (MADT_LOCAL_APIC_ADDR_OVERRIDE, 12..) => {
+                    info.local_apic_addr = read_u32(entry, 4) as u64 | (read_u32(entry, 8) as u64) << 32;
+                }
+                _ => ()
+            }
+        }
+        info
+    }
+
+    /// The global system interrupt ISA interrupt `irq` comes in on and how
+    /// it's signalled, which is `default` if the MADT doesn't say
+    fn isa_input(&self, irq: u8, default: InputMode) -> (u32, InputMode) {
+        match self.overrides.get(irq as usize).copied().flatten() {
+            Some(source_override) => (source_override.gsi, InputMode::from_flags(source_override.flags, default)),
+            None => (irq as u32, default)
+        }
+    }
+
+    /// The I/O APIC whose inputs start closest below `gsi`
+    fn io_apic_for(&self, gsi: u32) -> Option<IoApic> {
+        self.io_apics.iter()
+            .flatten()
+            .filter(|io_apic| io_apic.gsi_base <= gsi)
+            .max_by_key(|io_apic| io_apic.gsi_base)
+            .copied()
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_madt_parsing() {
+        let io_apic: [u8; 12] = [1, 12, 0, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0];
+        // The PIT on IRQ 0 comes in on GSI 2, as it does on most PCs
+        let timer_override: [u8; 10] = [2, 10, 0, 0, 2, 0, 0, 0, 0, 0];
+        // The SCI, level triggered and active high
+        let sci_override: [u8; 10] = [2, 10, 0, 9, 9, 0, 0, 0, 0b1101, 0];
+        let not_isa_override: [u8; 10] = [2, 10, 1, 5, 20, 0, 0, 0, 0, 0];
+        let local_apic: [u8; 8] = [0, 8, 0, 0, 1, 0, 0, 0];
+        let entries: [&[u8]; 5] = [&io_apic, &timer_override, &sci_override, &not_isa_override, &local_apic];
+        let info = MadtInfo::parse(0xfee0_0000, entries.into_iter());
+
+        assert_eq!(info.local_apic_addr, 0xfee0_0000);
+        assert_eq!(info.io_apics[0], Some(IoApic { addr: 0xfec0_0000, gsi_base: 0 }));
+        assert_eq!(info.io_apics[1], None);
+        assert_eq!(info.isa_input(0, ISA_MODE), (2, ISA_MODE));
+        assert_eq!(info.isa_input(1, ISA_MODE), (1, ISA_MODE));
+        assert_eq!(info.isa_input(9, SHARED_MODE), (9, InputMode { active_low: false, level_triggered: true }));
+        assert_eq!(info.isa_input(5, ISA_MODE), (5, ISA_MODE));
+
+        let addr_override: [u8; 12] = [5, 12, 0, 0, 0x00, 0x00, 0xe0, 0xfe, 1, 0, 0, 0];
+        let info = MadtInfo::parse(0xfee0_0000, [&addr_override[..]].into_iter());
+        assert_eq!(info.local_apic_addr, 0x1_fee0_0000);
+    }
+
+    #[test]
+    fn test_io_apic_for_gsi() {
+        let mut info = MadtInfo::parse(0xfee0_0000, core::iter::empty());
+        assert_eq!(info.io_apic_for(0), None);
+        info.io_apics[0] = Some(IoApic { addr: 0xfec0_1000, gsi_base: 24 });
+        info.io_apics[1] = Some(IoApic { addr: 0xfec0_0000, gsi_base: 0 });
+        assert_eq!(info.io_apic_for(9).unwrap().gsi_base, 0);
+        assert_eq!(info.io_apic_for(24).unwrap().gsi_base, 24);
+        assert_eq!(info.io_apic_for(30).unwrap().gsi_base, 24);
+    }
+
+    #[test]
+    fn test_redirection_entry() {
+        assert_eq!(redirection_entry(0x21, ISA_MODE, 0), 0x21);
+        assert_eq!(redirection_entry(0x2b, SHARED_MODE, 3), 0x0300_0000_0000_a02b);
+    }
+}
@@ -32,6 +32,24 @@ pub fn get_current_time() -> RTCTime {
 ///
 /// Reference: https://wiki.osdev.org/CMOS#Accessing_CMOS_Registers
 fn read_register(register_no: u8) -> usize {
+    select_register(register_no);
+    // Reading the value of the selected register
+    let port: Port<u8> = Port::new(0x71);
+    let val = port.read();
+    val as usize
+}
+
+/// Writes `value` into a CMOS register
+///
+/// Reference: https://wiki.osdev.org/CMOS#Accessing_CMOS_Registers
+fn write_register(register_no: u8, value: u8) {
+    select_register(register_no);
+    // Writing the value into the selected register
+    let mut port: Port<u8> = Port::new(0x71);
+    port.write(value);
+}
+
+fn select_register(register_no: u8) {
     // A CMOS register is selected by writing the register number to port 0x70
     // The most significant bit of whichever register_no is written to port 0x70
     // controls the Non Maskable Interrupts (NMI)
@@ -42,10 +60,6 @@ fn read_register(register_no: u8) -> usize {
     // wait
     let mut wait_port: Port<u8> = Port::new(WAIT_PORT_NO);
     wait_port.write(0);
-    // Reading the value of the selected register
-    let port: Port<u8> = Port::new(0x71);
-    let val = port.read();
-    val as usize
 }
 
 /// The time that is retrieved from the CMOS
@@ -68,4 +82,334 @@ impl RTCTime {
         self.year + self.month + self.day_of_month + self.weekday + self.hours
         + self.minutes + self.seconds
     }
-}
\ No newline at end of file
+}
+
+/// Holds a signature that tells if the boot record registers have been initialized.
+///
+/// Only the registers below 0x40 have a standard meaning. The ones above are
+/// left to the chipset and the firmware, so nothing guarantees that they're free.
+/// SeaBIOS, the firmware QEMU and Bochs run, uses nothing past 0x5f. A real
+/// machine's firmware may keep its own settings anywhere from 0x40 up, so the
+/// registers are only used if they're blank or already hold the signature,
+/// as checked by `Registers::are_free`
+const BOOT_RECORD_SIGNATURE_REG: u8 = 0x70;
+/// Holds the boot record flags
+const BOOT_RECORD_FLAGS_REG: u8 = 0x71;
+/// Holds the number of times the game has been booted
+const BOOT_RECORD_COUNT_REG: u8 = 0x72;
+const BOOT_RECORD_SIGNATURE: u8 = 0xb1;
+const BOOT_RECORD_REGS: [u8; 3] = [BOOT_RECORD_SIGNATURE_REG, BOOT_RECORD_FLAGS_REG, BOOT_RECORD_COUNT_REG];
+
+/// Information about the previous sessions kept in the CMOS,
+/// which survives reboots
+///
+/// Every boot is recorded with `record_boot`. If the session ends with
+/// a call to `record_clean_shutdown`, the next boot will see a clean shutdown.
+/// If it ends with a call to `record_crash`, the next boot will see a crash.
+/// A session that is ended by cutting the power is neither, since the game
+/// has no way of telling
+#[derive(Debug, Clone, Copy)]
+pub struct BootRecord {
+    /// The number of boots, including the current one.
+    /// Wraps around after 255
+    pub boot_count: u8,
+    flags: BootRecordFlags
+}
+
+impl BootRecord {
+    /// Checks if the previous session ended in a crash
+    pub fn previous_session_crashed(&self) -> bool {
+        self.flags.contains(BootRecordFlags::CRASHED)
+    }
+
+    /// Checks if the previous session was ended with a clean shutdown
+    pub fn previous_shutdown_was_clean(&self) -> bool {
+        self.flags.contains(BootRecordFlags::CLEAN_SHUTDOWN)
+    }
+
+    /// Checks if debug logging over the serial port has been enabled
+    pub fn serial_debug_enabled(&self) -> bool {
+        self.flags.contains(BootRecordFlags::SERIAL_DEBUG)
+    }
+
+    /// Checks if sound should be mixed in memory instead of played
+    /// through the sound device
+    pub fn software_sound_enabled(&self) -> bool {
+        self.flags.contains(BootRecordFlags::SOFTWARE_SOUND)
+    }
+
+    /// Checks if interrupts should be delivered by the APICs instead of the PICs
+    pub fn apic_enabled(&self) -> bool {
+        self.flags.contains(BootRecordFlags::APIC)
+    }
+
+    /// Checks if the option for the APICs has ever been set with `set_apic`
+    pub fn apic_chosen(&self) -> bool {
+        self.flags.contains(BootRecordFlags::APIC_CHOSEN)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(transparent)]
+struct BootRecordFlags(u8);
+
+impl BootRecordFlags {
+    const CLEAN_SHUTDOWN: u8 = 1 << 0;
+    const CRASHED: u8 = 1 << 1;
+    const SERIAL_DEBUG: u8 = 1 << 2;
+    const SOFTWARE_SOUND: u8 = 1 << 3;
+    const APIC: u8 = 1 << 4;
+    const APIC_CHOSEN: u8 = 1 << 5;
+
+    fn read(regs: &mut impl Registers) -> BootRecordFlags {
+        if regs.read(BOOT_RECORD_SIGNATURE_REG) != BOOT_RECORD_SIGNATURE {
+            return BootRecordFlags(0);
+        }
+        BootRecordFlags(regs.read(BOOT_RECORD_FLAGS_REG))
+    }
+
+    fn contains(&self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+
+    fn set(&mut self, flag: u8) {
+        self.0 |= flag;
+    }
+
+    fn unset(&mut self, flag: u8) {
+        self.0 &= !flag;
+    }
+
+    fn set_to(&mut self, flag: u8, enabled: bool) {
+        if enabled {
+            self.set(flag);
+        } else {
+            self.unset(flag);
+        }
+    }
+}
+
+/// The registers the boot record and the settings are kept in
+trait Registers {
+    fn read(&mut self, register_no: u8) -> u8;
+    fn write(&mut self, register_no: u8, value: u8);
+
+    /// Tells whether or not `regs` can be used for a record whose
+    /// `signature` is kept in `signature_reg`
+    ///
+    /// They can if the signature is already there, or if every one of them is
+    /// blank, reading 0 or 0xff, which is what registers nothing uses read as
+    fn are_free(&mut self, signature_reg: u8, signature: u8, regs: &[u8]) -> bool {
+        self.read(signature_reg) == signature
+            || regs.iter().all(|reg| matches!(self.read(*reg), 0x00 | 0xff))
+    }
+}
+
+/// The machine's CMOS registers
+struct Cmos;
+
+impl Registers for Cmos {
+    fn read(&mut self, register_no: u8) -> u8 {
+        read_register(register_no) as u8
+    }
+
+    fn write(&mut self, register_no: u8, value: u8) {
+        write_register(register_no, value);
+    }
+}
+
+/// Increments the boot counter and marks the current session as running
+///
+/// Returns the record as it was left by the previous session, with
+/// the boot count of the current session. If the registers aren't free,
+/// nothing is recorded, and every boot is the first with nothing set
+pub fn record_boot() -> BootRecord {
+    record_boot_in(&mut Cmos)
+}
+
+fn record_boot_in(regs: &mut impl Registers) -> BootRecord {
+    if !regs.are_free(BOOT_RECORD_SIGNATURE_REG, BOOT_RECORD_SIGNATURE, &BOOT_RECORD_REGS) {
+        return BootRecord { boot_count: 1, flags: BootRecordFlags(0) };
+    }
+    let initialized = regs.read(BOOT_RECORD_SIGNATURE_REG) == BOOT_RECORD_SIGNATURE;
+    let boot_count = if initialized {
+        regs.read(BOOT_RECORD_COUNT_REG).wrapping_add(1)
+    } else {
+        1
+    };
+    let previous_flags = BootRecordFlags::read(regs);
+    let mut flags = previous_flags;
+    flags.unset(BootRecordFlags::CLEAN_SHUTDOWN);
+    flags.unset(BootRecordFlags::CRASHED);
+    regs.write(BOOT_RECORD_FLAGS_REG, flags.0);
+    regs.write(BOOT_RECORD_COUNT_REG, boot_count);
+    // Written last, so the registers are only claimed once the rest of the record is there
+    regs.write(BOOT_RECORD_SIGNATURE_REG, BOOT_RECORD_SIGNATURE);
+    BootRecord { boot_count, flags: previous_flags }
+}
+
+/// Changes the flags of the boot record with `change`
+///
+/// Does nothing if `record_boot` hasn't claimed the registers
+fn update_flags(regs: &mut impl Registers, change: impl FnOnce(&mut BootRecordFlags)) {
+    if regs.read(BOOT_RECORD_SIGNATURE_REG) != BOOT_RECORD_SIGNATURE {
+        return;
+    }
+    let mut flags = BootRecordFlags::read(regs);
+    change(&mut flags);
+    regs.write(BOOT_RECORD_FLAGS_REG, flags.0);
+}
+
+/// Marks the current session as ended with a clean shutdown
+pub fn record_clean_shutdown() {
+    update_flags(&mut Cmos, |flags| flags.set(BootRecordFlags::CLEAN_SHUTDOWN));
+}
+
+/// Marks the current session as crashed
+///
+/// Meant to be called from the panic handler, so the next boot
+/// knows something went wrong
+pub fn record_crash() {
+    update_flags(&mut Cmos, |flags| flags.set(BootRecordFlags::CRASHED));
+}
+
+/// Sets the option for debug logging over the serial port
+///
+/// The option is kept across boots until it is changed
+pub fn set_serial_debug(enabled: bool) {
+    update_flags(&mut Cmos, |flags| flags.set_to(BootRecordFlags::SERIAL_DEBUG, enabled));
+}
+
+/// Sets the option for mixing sound in memory instead of playing it
+/// through the sound device
+///
+/// The option is kept across boots until it is changed
+pub fn set_software_sound(enabled: bool) {
+    update_flags(&mut Cmos, |flags| flags.set_to(BootRecordFlags::SOFTWARE_SOUND, enabled));
+}
+
+/// Sets the option for delivering interrupts with the APICs instead of the PICs
+///
+/// The option is kept across boots until it is changed
+pub fn set_apic(enabled: bool) {
+    set_apic_in(&mut Cmos, enabled);
+}
+
+fn set_apic_in(regs: &mut impl Registers, enabled: bool) {
+    update_flags(regs, |flags| {
+        flags.set_to(BootRecordFlags::APIC, enabled);
+        flags.set(BootRecordFlags::APIC_CHOSEN);
+    });
+}
+
+/// Holds a signature that tells if the settings register has been written.
+/// Like the boot record's, the registers are only used if they're free
+const SETTINGS_SIGNATURE_REG: u8 = 0x73;
+/// Holds the game's settings byte
+const SETTINGS_REG: u8 = 0x74;
+const SETTINGS_SIGNATURE: u8 = 0x5e;
+
+/// Returns the settings byte written with `write_settings`,
+/// or None if it has never been written
+pub fn read_settings() -> Option<u8> {
+    if read_register(SETTINGS_SIGNATURE_REG) as u8 != SETTINGS_SIGNATURE {
+        return None;
+    }
+    Some(read_register(SETTINGS_REG) as u8)
+}
+
+/// Keeps the settings byte in the CMOS, so it survives reboots
+///
+/// Does nothing if the settings registers aren't free
+pub fn write_settings(settings: u8) {
+    write_settings_in(&mut Cmos, settings);
+}
+
+fn write_settings_in(regs: &mut impl Registers, settings: u8) {
+    if !regs.are_free(SETTINGS_SIGNATURE_REG, SETTINGS_SIGNATURE, &[SETTINGS_SIGNATURE_REG, SETTINGS_REG]) {
+        return;
+    }
+    regs.write(SETTINGS_REG, settings);
+    regs.write(SETTINGS_SIGNATURE_REG, SETTINGS_SIGNATURE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bank of CMOS registers in memory
+    struct FakeCmos([u8; 128]);
+
+    impl Registers for FakeCmos {
+        fn read(&mut self, register_no: u8) -> u8 {
+            self.0[register_no as usize]
+        }
+
+        fn write(&mut self, register_no: u8, value: u8) {
+            self.0[register_no as usize] = value;
+        }
+    }
+
+    #[test]
+    fn test_boot_record_is_kept_across_boots() {
+        for blank in [0x00, 0xff] {
+            let mut cmos = FakeCmos([blank; 128]);
+            let first = record_boot_in(&mut cmos);
+            assert_eq!(first.boot_count, 1);
+            assert!(!first.previous_session_crashed());
+            assert_eq!(cmos.0[BOOT_RECORD_SIGNATURE_REG as usize], BOOT_RECORD_SIGNATURE);
+            update_flags(&mut cmos, |flags| flags.set_to(BootRecordFlags::SERIAL_DEBUG, true));
+            update_flags(&mut cmos, |flags| flags.set(BootRecordFlags::CRASHED));
+            let second = record_boot_in(&mut cmos);
+            assert_eq!(second.boot_count, 2);
+            assert!(second.previous_session_crashed());
+            assert!(second.serial_debug_enabled());
+            update_flags(&mut cmos, |flags| flags.set(BootRecordFlags::CLEAN_SHUTDOWN));
+            let third = record_boot_in(&mut cmos);
+            assert_eq!(third.boot_count, 3);
+            assert!(third.previous_shutdown_was_clean());
+            assert!(!third.previous_session_crashed());
+            // Options stay set until they're changed
+            assert!(third.serial_debug_enabled());
+        }
+    }
+
+    #[test]
+    fn test_apic_choice_is_kept() {
+        let mut cmos = FakeCmos([0; 128]);
+        let first = record_boot_in(&mut cmos);
+        assert!(!first.apic_chosen());
+        set_apic_in(&mut cmos, false);
+        let second = record_boot_in(&mut cmos);
+        assert!(second.apic_chosen());
+        assert!(!second.apic_enabled());
+        set_apic_in(&mut cmos, true);
+        let third = record_boot_in(&mut cmos);
+        assert!(third.apic_chosen());
+        assert!(third.apic_enabled());
+    }
+
+    #[test]
+    fn test_registers_in_use_are_left_alone() {
+        let mut cmos = FakeCmos([0; 128]);
+        cmos.0[BOOT_RECORD_COUNT_REG as usize] = 0x35;
+        cmos.0[SETTINGS_REG as usize] = 0x12;
+        let before = cmos.0;
+        for _ in 0..2 {
+            let record = record_boot_in(&mut cmos);
+            assert_eq!(record.boot_count, 1);
+            update_flags(&mut cmos, |flags| flags.set(BootRecordFlags::APIC));
+            write_settings_in(&mut cmos, 0xaa);
+        }
+        assert_eq!(cmos.0, before);
+    }
+
+    #[test]
+    fn test_settings_are_kept() {
+        let mut cmos = FakeCmos([0; 128]);
+        write_settings_in(&mut cmos, 0x03);
+        write_settings_in(&mut cmos, 0x05);
+        assert_eq!(cmos.0[SETTINGS_SIGNATURE_REG as usize], SETTINGS_SIGNATURE);
+        assert_eq!(cmos.0[SETTINGS_REG as usize], 0x05);
+    }
+}
@@ -0,0 +1,184 @@
+//! A crash log kept in memory that survives a warm reset
+//!
+//! The panic handler writes the panic message into a small region of physical
+//! memory that nothing else uses. RAM isn't cleared on a warm reset, so when the
+//! machine is reset after a crash, the next boot can find the message and show it,
+//! even though it vanished from the screen.
+//!
+//! The region starts with a header holding a magic number, the length of the message
+//! and a checksum of it, so leftover garbage from a cold boot isn't mistaken for a log.
+//! The bootloader must reserve the region and call `enable` before anything is written.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The physical address of the crash log region
+///
+/// It's in conventional memory below the EBDA, which is usable on every
+/// machine and too small for the bootloader to place the stack or heap in
+pub const CRASH_LOG_ADDR: u64 = 0x9_0000;
+/// The size of the crash log region, in bytes
+pub const CRASH_LOG_SIZE: u64 = 0x1000;
+
+/// Identifies a crash log, the ascii "BMBCRASH"
+const CRASH_LOG_MAGIC: u64 = u64::from_le_bytes(*b"BMBCRASH");
+/// The magic number, the length of the message and its checksum
+const HEADER_SIZE: usize = 16;
+
+/// Whether the region has been reserved for the crash log
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Allows the crash log region to be read and written
+///
+/// # Safety
+///
+/// The region at `CRASH_LOG_ADDR` must be reserved, so nothing else uses it
+pub unsafe fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Returns the message of the crash from before the last warm reset,
+/// or None if there is no valid crash log
+pub fn previous_crash() -> Option<&'static str> {
+    region().and_then(|region| CrashLog { bytes: region }.message())
+}
+
+/// Removes the crash log, so it isn't found on the next boot
+pub fn clear() {
+    if let Some(region) = region() {
+        CrashLog { bytes: region }.clear();
+    }
+}
+
+/// Replaces the crash log with an empty one and returns a writer for its message,
+/// or None if the crash log hasn't been enabled
+///
+/// The log is sealed after every write, so whatever was written before
+/// a fault in the middle of writing is still found on the next boot.
+/// A message that doesn't fit is cut short
+pub fn writer() -> Option<CrashLogWriter> {
+    let mut log = CrashLog { bytes: region()? };
+    log.clear();
+    Some(CrashLogWriter { log })
+}
+
+/// Writes a panic message into the crash log
+pub struct CrashLogWriter {
+    log: CrashLog<'static>
+}
+
+impl fmt::Write for CrashLogWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.log.append(s.as_bytes());
+        Ok(())
+    }
+}
+
+fn region() -> Option<&'static mut [u8]> {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(CRASH_LOG_ADDR as *mut u8, CRASH_LOG_SIZE as usize) })
+}
+
+/// A crash log in a region of bytes
+struct CrashLog<'a> {
+    bytes: &'a mut [u8]
+}
+
+impl<'a> CrashLog<'a> {
+    fn magic(&self) -> u64 {
+        u64::from_le_bytes(self.bytes[0..8].try_into().unwrap())
+    }
+
+    fn len(&self) -> usize {
+        u32::from_le_bytes(self.bytes[8..12].try_into().unwrap()) as usize
+    }
+
+    fn checksum(&self) -> u32 {
+        u32::from_le_bytes(self.bytes[12..16].try_into().unwrap())
+    }
+
+    fn max_len(&self) -> usize {
+        self.bytes.len() - HEADER_SIZE
+    }
+
+    fn clear(&mut self) {
+        self.bytes[0..HEADER_SIZE].fill(0);
+    }
+
+    /// Adds `s` to the end of the message and seals the log
+    fn append(&mut self, s: &[u8]) {
+        let len = self.len();
+        let added = s.len().min(self.max_len() - len);
+        let start = HEADER_SIZE + len;
+        self.bytes[start..start + added].copy_from_slice(&s[..added]);
+        let len = len + added;
+        let checksum = fnv1a(&self.bytes[HEADER_SIZE..HEADER_SIZE + len]);
+        self.bytes[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        self.bytes[12..16].copy_from_slice(&checksum.to_le_bytes());
+        self.bytes[0..8].copy_from_slice(&CRASH_LOG_MAGIC.to_le_bytes());
+    }
+
+    /// The message in the log, or None if the region doesn't hold a valid log
+    fn message(self) -> Option<&'a str> {
+        let len = self.len();
+        if self.magic() != CRASH_LOG_MAGIC || len > self.max_len() {
+            return None;
+        }
+        let checksum = self.checksum();
+        let message = &self.bytes[HEADER_SIZE..HEADER_SIZE + len];
+        if fnv1a(message) != checksum {
+            return None;
+        }
+        // A message cut short can end in the middle of a character
+        match core::str::from_utf8(message) {
+            Ok(message) => Some(message),
+            Err(err) => core::str::from_utf8(&message[..err.valid_up_to()]).ok()
+        }
+    }
+}
+
+/// The 32 bit FNV-1a hash of `bytes`
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_log_round_trip() {
+        let mut region = [0u8; 64];
+        CrashLog { bytes: &mut region }.append(b"panicked at ");
+        CrashLog { bytes: &mut region }.append(b"src/lib.rs:10:5");
+        assert_eq!(CrashLog { bytes: &mut region }.message(), Some("panicked at src/lib.rs:10:5"));
+    }
+
+    #[test]
+    fn test_crash_log_garbage_is_not_a_log() {
+        let mut region = [0xaau8; 64];
+        assert_eq!(CrashLog { bytes: &mut region }.message(), None);
+
+        // A valid log with a corrupted message
+        let mut region = [0u8; 64];
+        CrashLog { bytes: &mut region }.append(b"panicked");
+        region[HEADER_SIZE] = b'P';
+        assert_eq!(CrashLog { bytes: &mut region }.message(), None);
+
+        let mut region = [0u8; 64];
+        CrashLog { bytes: &mut region }.append(b"panicked");
+        CrashLog { bytes: &mut region }.clear();
+        assert_eq!(CrashLog { bytes: &mut region }.message(), None);
+    }
+
+    #[test]
+    fn test_crash_log_cuts_long_messages() {
+        let mut region = [0u8; HEADER_SIZE + 8];
+        CrashLog { bytes: &mut region }.append(b"0123456789");
+        assert_eq!(CrashLog { bytes: &mut region }.message(), Some("01234567"));
+        CrashLog { bytes: &mut region }.append(b"more");
+        assert_eq!(CrashLog { bytes: &mut region }.message(), Some("01234567"));
+    }
+}
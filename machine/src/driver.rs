@@ -0,0 +1,326 @@
+//! Registration of the drivers that are set up at boot, and the order they're set up in
+//!
+//! A driver is registered with a descriptor that names the drivers it depends on
+//! and the stage of booting it's set up in. `init_stage` sets up the drivers of
+//! a stage, each one after the drivers it depends on, and otherwise in the order
+//! they were registered, so a new driver only has to be registered
+
+use sync::mutex::Mutex;
+use crate::serial_println;
+
+/// The number of drivers that can be registered
+pub const MAX_DRIVERS: usize = 16;
+
+static DRIVERS: Mutex<DriverRegistry<MAX_DRIVERS>> = Mutex::new(DriverRegistry::new());
+
+/// The stages of booting, in the order they're set up in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InitStage {
+    /// What everything else needs, like the descriptor tables, the heap and interrupts
+    Core,
+    /// The devices, set up once the machine can take input and print errors
+    Devices
+}
+
+/// Sets a driver up
+#[derive(Clone, Copy)]
+pub enum InitFn {
+    /// Sets up a driver that's always set up once it's called
+    Infallible(fn()),
+    Fallible(fn() -> Result<(), &'static str>)
+}
+
+impl InitFn {
+    pub fn call(self) -> Result<(), &'static str> {
+        match self {
+            InitFn::Infallible(init) => {
+                init();
+                Ok(())
+            }
+            InitFn::Fallible(init) => init()
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DriverDescriptor {
+    pub name: &'static str,
+    pub stage: InitStage,
+    /// The names of the drivers that have to be set up before this one
+    pub depends_on: &'static [&'static str],
+    pub init: InitFn,
+    /// Sets up something that can stand in for the driver when `init` fails
+    pub fallback: Option<InitFn>,
+    /// Whether booting can't go on without the driver
+    pub required: bool
+}
+
+/// Why the drivers of a stage couldn't all be set up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitError {
+    /// A required driver failed, or was skipped because a driver it depends on failed
+    Failed { driver: &'static str, msg: &'static str },
+    /// A driver depends on a driver that hasn't been registered
+    MissingDependency { driver: &'static str, dependency: &'static str },
+    /// A driver depends on a driver that's set up in a later stage
+    DependencyInLaterStage { driver: &'static str, dependency: &'static str },
+    /// A driver's dependencies can't be set up before it, because they depend on
+    /// it in turn or are in an earlier stage that hasn't been set up
+    Unresolvable { driver: &'static str }
+}
+
+impl InitError {
+    /// The driver the error is about
+    pub fn driver(&self) -> &'static str {
+        match *self {
+            InitError::Failed { driver, .. } => driver,
+            InitError::MissingDependency { driver, .. } => driver,
+            InitError::DependencyInLaterStage { driver, .. } => driver,
+            InitError::Unresolvable { driver } => driver
+        }
+    }
+
+    pub fn msg(&self) -> &'static str {
+        match *self {
+            InitError::Failed { msg, .. } => msg,
+            InitError::MissingDependency { .. } => "It depends on a driver that hasn't been registered",
+            InitError::DependencyInLaterStage { .. } => "It depends on a driver that's set up after it",
+            InitError::Unresolvable { .. } => "Its dependencies can't be set up before it"
+        }
+    }
+}
+
+/// Registers `driver` to be set up when its stage is
+pub fn register(driver: DriverDescriptor) -> Result<(), &'static str> {
+    DRIVERS.lock().register(driver)
+}
+
+/// Sets up the drivers registered for `stage` that haven't been set up yet
+///
+/// Each driver's init function is passed to `run` along with the driver's name,
+/// so the caller can show the progress. A driver that fails is replaced with its
+/// fallback if it has one, and the drivers that depend on a failed driver are
+/// skipped. Only the failure of a required driver stops the stage
+pub fn init_stage<F>(stage: InitStage, run: F) -> Result<(), InitError>
+    where F: FnMut(&'static str, InitFn) -> Result<(), &'static str>
+{
+    init_stage_in(&DRIVERS, stage, run)
+}
+
+fn init_stage_in<const N: usize, F>(drivers: &Mutex<DriverRegistry<N>>, stage: InitStage, mut run: F) -> Result<(), InitError>
+    where F: FnMut(&'static str, InitFn) -> Result<(), &'static str>
+{
+    drivers.lock().check(stage)?;
+    // The registry isn't kept locked while a driver is set up,
+    // so a driver can register the drivers it finds
+    loop {
+        let next = drivers.lock().next(stage)?;
+        let (idx, driver, dependency_failed) = match next {
+            Some(next) => next,
+            None => return Ok(())
+        };
+        let result = if dependency_failed {
+            Err("A driver it depends on failed")
+        } else {
+            run(driver.name, driver.init).or_else(|msg| match driver.fallback {
+                Some(fallback) => fallback.call().map_err(|_| msg),
+                None => Err(msg)
+            })
+        };
+        drivers.lock().finish(idx, result.is_ok());
+        if let Err(msg) = result {
+            serial_println!("Driver {} wasn't set up: {}", driver.name, msg);
+            if driver.required {
+                return Err(InitError::Failed { driver: driver.name, msg });
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DriverState {
+    Pending,
+    Ready,
+    Failed
+}
+
+/// The registered drivers and how far each one has been set up
+struct DriverRegistry<const N: usize> {
+    drivers: [Option<(DriverDescriptor, DriverState)>; N],
+    len: usize
+}
+
+impl<const N: usize> DriverRegistry<N> {
+    const fn new() -> Self {
+        Self { drivers: [None; N], len: 0 }
+    }
+
+    fn register(&mut self, driver: DriverDescriptor) -> Result<(), &'static str> {
+        if self.find(driver.name).is_some() {
+            return Err("A driver with the same name has already been registered");
+        }
+        if self.len == N {
+            return Err("Too many drivers have been registered");
+        }
+        self.drivers[self.len] = Some((driver, DriverState::Pending));
+        self.len += 1;
+        Ok(())
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &(DriverDescriptor, DriverState)> {
+        self.drivers[..self.len].iter().flatten()
+    }
+
+    fn find(&self, name: &str) -> Option<&(DriverDescriptor, DriverState)> {
+        self.iter().find(|(driver, _)| driver.name == name)
+    }
+
+    /// Checks that the dependencies of the drivers in `stage` exist and
+    /// aren't set up after them
+    fn check(&self, stage: InitStage) -> Result<(), InitError> {
+        for (driver, _) in self.iter().filter(|(driver, _)| driver.stage == stage) {
+            for &dependency in driver.depends_on {
+                match self.find(dependency) {
+                    None => return Err(InitError::MissingDependency { driver: driver.name, dependency }),
+                    Some((dep, _)) if dep.stage > stage =>
+                        return Err(InitError::DependencyInLaterStage { driver: driver.name, dependency }),
+                    _ => ()
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The first driver of `stage` that hasn't been set up and whose dependencies
+    /// have all been dealt with, along with whether any of them failed
+    ///
+    /// Returns None once every driver of `stage` has been dealt with
+    fn next(&self, stage: InitStage) -> Result<Option<(usize, DriverDescriptor, bool)>, InitError> {
+        let mut pending = self.iter().enumerate()
+            .filter(|(_, (driver, state))| driver.stage == stage && *state == DriverState::Pending)
+            .peekable();
+        let first_pending = match pending.peek() {
+            Some((_, (driver, _))) => driver.name,
+            None => return Ok(None)
+        };
+        for (idx, (driver, _)) in pending {
+            let mut states = driver.depends_on.iter()
+                .map(|dependency| self.find(dependency).map_or(DriverState::Failed, |(_, state)| *state));
+            if states.clone().all(|state| state != DriverState::Pending) {
+                let dependency_failed = states.any(|state| state == DriverState::Failed);
+                return Ok(Some((idx, *driver, dependency_failed)));
+            }
+        }
+        Err(InitError::Unresolvable { driver: first_pending })
+    }
+
+    fn finish(&mut self, idx: usize, ok: bool) {
+        if let Some((_, ref mut state)) = self.drivers[idx] {
+            *state = if ok { DriverState::Ready } else { DriverState::Failed };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const OK: InitFn = InitFn::Infallible(|| ());
+    const FAIL: InitFn = InitFn::Fallible(|| Err("No device"));
+
+    fn driver(name: &'static str, stage: InitStage, depends_on: &'static [&'static str], init: InitFn) -> DriverDescriptor {
+        DriverDescriptor { name, stage, depends_on, init, fallback: None, required: false }
+    }
+
+    /// Sets up `stage`, returning the names of the drivers whose init functions ran
+    fn run_stage<const N: usize>(drivers: &Mutex<DriverRegistry<N>>, stage: InitStage) -> (Result<(), InitError>, Vec<&'static str>) {
+        let mut ran = Vec::new();
+        let result = init_stage_in(drivers, stage, |name, init| {
+            ran.push(name);
+            init.call()
+        });
+        (result, ran)
+    }
+
+    #[test]
+    fn test_drivers_are_set_up_after_their_dependencies() {
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        drivers.lock().register(driver("Interrupts", InitStage::Core, &["GDT", "Allocator"], OK)).unwrap();
+        drivers.lock().register(driver("Sound", InitStage::Devices, &["Interrupts"], OK)).unwrap();
+        drivers.lock().register(driver("Allocator", InitStage::Core, &[], OK)).unwrap();
+        drivers.lock().register(driver("GDT", InitStage::Core, &[], OK)).unwrap();
+        let (result, ran) = run_stage(&drivers, InitStage::Core);
+        assert_eq!(result, Ok(()));
+        assert_eq!(ran, ["Allocator", "GDT", "Interrupts"]);
+        let (result, ran) = run_stage(&drivers, InitStage::Devices);
+        assert_eq!(result, Ok(()));
+        assert_eq!(ran, ["Sound"]);
+    }
+
+    #[test]
+    fn test_drivers_that_depend_on_a_failed_driver_are_skipped() {
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        drivers.lock().register(driver("PCI", InitStage::Devices, &[], FAIL)).unwrap();
+        drivers.lock().register(driver("Sound", InitStage::Devices, &["PCI"], OK)).unwrap();
+        drivers.lock().register(driver("Mouse", InitStage::Devices, &[], OK)).unwrap();
+        let (result, ran) = run_stage(&drivers, InitStage::Devices);
+        assert_eq!(result, Ok(()));
+        assert_eq!(ran, ["PCI", "Mouse"]);
+    }
+
+    #[test]
+    fn test_a_failed_driver_is_replaced_by_its_fallback() {
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        let mut sound = driver("Sound", InitStage::Devices, &[], FAIL);
+        sound.fallback = Some(OK);
+        sound.required = true;
+        drivers.lock().register(sound).unwrap();
+        drivers.lock().register(driver("Music", InitStage::Devices, &["Sound"], OK)).unwrap();
+        let (result, ran) = run_stage(&drivers, InitStage::Devices);
+        assert_eq!(result, Ok(()));
+        assert_eq!(ran, ["Sound", "Music"]);
+    }
+
+    #[test]
+    fn test_a_failed_required_driver_stops_the_stage() {
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        let mut allocator = driver("Allocator", InitStage::Core, &[], FAIL);
+        allocator.required = true;
+        drivers.lock().register(allocator).unwrap();
+        drivers.lock().register(driver("GDT", InitStage::Core, &[], OK)).unwrap();
+        let (result, ran) = run_stage(&drivers, InitStage::Core);
+        assert_eq!(result, Err(InitError::Failed { driver: "Allocator", msg: "No device" }));
+        assert_eq!(ran, ["Allocator"]);
+    }
+
+    #[test]
+    fn test_dependencies_that_cant_be_met_are_reported() {
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        drivers.lock().register(driver("Interrupts", InitStage::Core, &["Sound"], OK)).unwrap();
+        drivers.lock().register(driver("Sound", InitStage::Devices, &[], OK)).unwrap();
+        assert_eq!(run_stage(&drivers, InitStage::Core).0,
+            Err(InitError::DependencyInLaterStage { driver: "Interrupts", dependency: "Sound" }));
+
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        drivers.lock().register(driver("Sound", InitStage::Devices, &["PCI"], OK)).unwrap();
+        assert_eq!(run_stage(&drivers, InitStage::Devices).0,
+            Err(InitError::MissingDependency { driver: "Sound", dependency: "PCI" }));
+
+        let drivers: Mutex<DriverRegistry<8>> = Mutex::new(DriverRegistry::new());
+        drivers.lock().register(driver("A", InitStage::Core, &["B"], OK)).unwrap();
+        drivers.lock().register(driver("B", InitStage::Core, &["A"], OK)).unwrap();
+        let (result, ran) = run_stage(&drivers, InitStage::Core);
+        assert_eq!(result, Err(InitError::Unresolvable { driver: "A" }));
+        assert!(ran.is_empty());
+    }
+
+    #[test]
+    fn test_registering_the_same_driver_twice_or_too_many_drivers_fails() {
+        let mut drivers: DriverRegistry<2> = DriverRegistry::new();
+        assert!(drivers.register(driver("GDT", InitStage::Core, &[], OK)).is_ok());
+        assert!(drivers.register(driver("GDT", InitStage::Core, &[], OK)).is_err());
+        assert!(drivers.register(driver("Allocator", InitStage::Core, &[], OK)).is_ok());
+        assert!(drivers.register(driver("Interrupts", InitStage::Core, &[], OK)).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Abstractions for getting unpredictable numbers from the machine
+//!
+//! The RDRAND and RDSEED instructions read from the processor's hardware
+//! random number generator. Processors without them, like the ones emulated
+//! by older versions of QEMU, fall back to mixing the time stamp counter with
+//! the CMOS time, which is nowhere near as good but still differs per boot.
+//!
+//! # References
+//!
+//! * Intel 64 and IA-32 Architectures Software Developer's Manual, Volume 1, Section 7.3.17
+//! * Intel Digital Random Number Generator (DRNG) Software Implementation Guide
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use num::{Integer, BitState};
+use crate::cmos;
+use crate::instructions::rdtsc;
+
+/// The number of times RDRAND is retried before giving up, as recommended
+/// by the DRNG Software Implementation Guide.
+/// RDSEED fails far more often, so it's retried for longer
+const RDRAND_RETRIES: usize = 10;
+const RDSEED_RETRIES: usize = 100;
+
+/// Returns a 64 bit number that is different every time it's called
+/// and on every boot
+///
+/// Meant for seeding random number generators, not for cryptography
+pub fn get_u64() -> u64 {
+    if rdrand_supported() {
+        if let Some(n) = rdrand() {
+            return n;
+        }
+    }
+    if rdseed_supported() {
+        if let Some(n) = rdseed() {
+            return n;
+        }
+    }
+    tsc_and_cmos()
+}
+
+/// Checks if the processor has the RDRAND instruction
+pub fn rdrand_supported() -> bool {
+    // CPUID.01H:ECX bit 30 is the RDRAND flag
+    let ecx = unsafe { __cpuid(1).ecx };
+    ecx.get_bit(30) == BitState::Set
+}
+
+/// Checks if the processor has the RDSEED instruction
+pub fn rdseed_supported() -> bool {
+    // The structured extended feature flags leaf isn't there
+    // on processors whose highest basic leaf is lesser than 7
+    let highest_leaf = unsafe { __cpuid(0).eax };
+    if highest_leaf < 7 {
+        return false;
+    }
+    // CPUID.(EAX=07H, ECX=0H):EBX bit 18 is the RDSEED flag
+    let ebx = unsafe { __cpuid_count(7, 0).ebx };
+    ebx.get_bit(18) == BitState::Set
+}
+
+/// Reads a number from the hardware random number generator
+///
+/// Returns None if the generator didn't have a number ready after all the retries
+fn rdrand() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let n: u64;
+        let success: u8;
+        unsafe {
+            // The carry flag is set when the number is valid
+            asm!("rdrand {}", "setc {}", out(reg) n, out(reg_byte) success, options(nomem, nostack));
+        }
+        if success == 1 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// Reads a number from the entropy source behind the hardware random number generator
+///
+/// Returns None if the source didn't have a number ready after all the retries
+fn rdseed() -> Option<u64> {
+    for _ in 0..RDSEED_RETRIES {
+        let n: u64;
+        let success: u8;
+        unsafe {
+            // The carry flag is set when the number is valid
+            asm!("rdseed {}", "setc {}", out(reg) n, out(reg_byte) success, options(nomem, nostack));
+        }
+        if success == 1 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// Mixes the time stamp counter and the CMOS time into a number
+///
+/// The number of cycles it takes to boot varies a little, so the low bits
+/// of the time stamp counter are somewhat unpredictable. The CMOS time
+/// makes sure the number is different even if they aren't
+fn tsc_and_cmos() -> u64 {
+    let time = cmos::get_current_time();
+    let time = (time.year as u64) << 40
+        | (time.month as u64) << 32
+        | (time.day_of_month as u64) << 24
+        | (time.hours as u64) << 16
+        | (time.minutes as u64) << 8
+        | time.seconds as u64;
+    mix(rdtsc() ^ mix(time))
+}
+
+/// Spreads the differences between similar numbers over all the bits
+///
+/// This is the finalizer of the SplitMix64 generator
+fn mix(n: u64) -> u64 {
+    let n = (n ^ (n >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let n = (n ^ (n >> 27)).wrapping_mul(0x94d049bb133111eb);
+    n ^ (n >> 31)
+}
@@ -0,0 +1,221 @@
+//! The memory the screen is displayed from
+//!
+//! The rows of a framebuffer aren't always as long as the screen is wide.
+//! Some UEFI graphics modes pad each row to an alignment the card likes,
+//! so the address of a pixel has to be worked out with the number of pixels
+//! in a scan line, the stride, and not the width. Cards don't agree on the
+//! order of the colors in a pixel either, so the framebuffer also has the
+//! pixel format colors have to be put in
+
+use crate::memory::Addr;
+
+/// How the colors of a pixel are laid out in its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Blue, green and red in bytes 0, 1 and 2, which most cards use
+    Bgr,
+    /// Red, green and blue in bytes 0, 1 and 2
+    Rgb,
+    /// The bits of each color are the ones set in its mask
+    Bitmask { red: u32, green: u32, blue: u32 },
+    /// A byte that is an index into the card's palette, like in the BIOS's VGA mode
+    Indexed
+}
+
+impl PixelFormat {
+    /// The value of a pixel with the `red`, `green` and `blue` intensities,
+    /// stored in little endian
+    ///
+    /// An indexed pixel has no colors of its own, so it's always 0
+    pub const fn encode(&self, red: u8, green: u8, blue: u8) -> u32 {
+        match *self {
+            PixelFormat::Bgr => u32::from_le_bytes([blue, green, red, 0]),
+            PixelFormat::Rgb => u32::from_le_bytes([red, green, blue, 0]),
+            PixelFormat::Bitmask { red: red_mask, green: green_mask, blue: blue_mask } => {
+                scale_into_mask(red, red_mask) | scale_into_mask(green, green_mask) | scale_into_mask(blue, blue_mask)
+            }
+            PixelFormat::Indexed => 0
+        }
+    }
+
+    /// The red, green and blue intensities of the pixel `value`, which is the
+    /// opposite of `encode`, leaving out the precision a mask doesn't have
+    pub const fn decode(&self, value: u32) -> [u8; 3] {
+        match *self {
+            PixelFormat::Bgr => {
+                let [blue, green, red, _] = value.to_le_bytes();
+                [red, green, blue]
+            }
+            PixelFormat::Rgb => {
+                let [red, green, blue, _] = value.to_le_bytes();
+                [red, green, blue]
+            }
+            PixelFormat::Bitmask { red, green, blue } => {
+                [scale_from_mask(value, red), scale_from_mask(value, green), scale_from_mask(value, blue)]
+            }
+            PixelFormat::Indexed => [0, 0, 0]
+        }
+    }
+}
+
+/// Scales the 8 bit `intensity` to the bits set in `mask`,
+/// which must be next to each other
+const fn scale_into_mask(intensity: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    ((intensity as u64 * max / 255) as u32) << shift
+}
+
+/// The 8 bit intensity of the bits of `value` set in `mask`
+const fn scale_from_mask(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    (((value & mask) >> shift) as u64 * 255 / max) as u8
+}
+
+/// The location, size and layout of the screen's memory
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    addr: Addr,
+    /// The number of bytes that are mapped, starting at `addr`
+    size: usize,
+    /// The number of visible pixels in a row
+    width: usize,
+    /// The number of rows
+    height: usize,
+    /// The number of pixels from the start of a row to the start of the next
+    stride: usize,
+    bytes_per_pixel: usize,
+    pixel_format: PixelFormat
+}
+
+impl Framebuffer {
+    pub const fn new(
+        addr: Addr,
+        size: usize,
+        width: usize,
+        height: usize,
+        stride: usize,
+        bytes_per_pixel: usize,
+        pixel_format: PixelFormat
+    ) -> Self {
+        Self { addr, size, width, height, stride, bytes_per_pixel, pixel_format }
+    }
+
+    pub fn addr(&self) -> Addr {
+        self.addr
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// The offset in bytes of the pixel at (`x`, `y`) from the start of the framebuffer
+    ///
+    /// Returns None if the pixel is off the screen or past the end of the framebuffer
+    pub fn pixel_offset(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = y.checked_mul(self.stride)?.checked_add(x)?.checked_mul(self.bytes_per_pixel)?;
+        if offset.checked_add(self.bytes_per_pixel)? > self.size {
+            return None;
+        }
+        Some(offset)
+    }
+
+    /// The address of the pixel at (`x`, `y`), or None if it isn't within the framebuffer
+    pub fn pixel_addr(&self, x: usize, y: usize) -> Option<Addr> {
+        self.pixel_offset(x, y).map(|offset| self.addr + offset)
+    }
+
+    /// The address of the start of row `y`, or None if the whole row isn't within the framebuffer
+    pub fn row_addr(&self, y: usize) -> Option<Addr> {
+        self.pixel_offset(self.width.checked_sub(1)?, y)?;
+        self.pixel_addr(0, y)
+    }
+
+    /// Sets every byte of the framebuffer to 0, which is black in every mode
+    pub fn clear(&self) {
+        unsafe { core::ptr::write_bytes(self.addr.as_mut_ptr(), 0, self.size) };
+    }
+
+    /// Tells whether or not a `width` by `height` screen of pixels with
+    /// `bytes_per_pixel` bytes each can be drawn in the framebuffer
+    pub fn fits(&self, width: usize, height: usize, bytes_per_pixel: usize) -> bool {
+        width <= self.width && height <= self.height && bytes_per_pixel == self.bytes_per_pixel
+            && height > 0 && self.row_addr(height - 1).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_offset_uses_stride() {
+        let fb = Framebuffer::new(Addr::new(0x1000), 4 * 648 * 480, 640, 480, 648, 4, PixelFormat::Bgr);
+        assert_eq!(fb.pixel_offset(0, 0), Some(0));
+        assert_eq!(fb.pixel_offset(3, 1), Some(4 * (648 + 3)));
+        assert_eq!(fb.pixel_addr(0, 2), Some(Addr::new(0x1000 + 4 * 648 * 2)));
+    }
+
+    #[test]
+    fn test_pixel_offset_out_of_bounds() {
+        let fb = Framebuffer::new(Addr::new(0x1000), 4 * 648 * 480, 640, 480, 648, 4, PixelFormat::Bgr);
+        assert_eq!(fb.pixel_offset(640, 0), None);
+        assert_eq!(fb.pixel_offset(0, 480), None);
+        // The firmware said the framebuffer is smaller than the mode needs
+        let fb = Framebuffer::new(Addr::new(0x1000), 4 * 640 * 479, 640, 480, 640, 4, PixelFormat::Bgr);
+        assert_eq!(fb.row_addr(478), Some(Addr::new(0x1000 + 4 * 640 * 478)));
+        assert_eq!(fb.row_addr(479), None);
+        assert!(!fb.fits(640, 480, 4));
+        assert!(fb.fits(640, 479, 4));
+        assert!(!fb.fits(640, 479, 1));
+    }
+
+    #[test]
+    fn test_pixel_formats() {
+        assert_eq!(PixelFormat::Bgr.encode(0x11, 0x22, 0x33), 0x00112233);
+        assert_eq!(PixelFormat::Rgb.encode(0x11, 0x22, 0x33), 0x00332211);
+        assert_eq!(PixelFormat::Bgr.decode(0x00112233), [0x11, 0x22, 0x33]);
+        assert_eq!(PixelFormat::Rgb.decode(0x00332211), [0x11, 0x22, 0x33]);
+        // 5 bits of red and blue and 6 of green
+        let rgb565 = PixelFormat::Bitmask { red: 0xf800, green: 0x07e0, blue: 0x001f };
+        assert_eq!(rgb565.encode(255, 255, 255), 0xffff);
+        assert_eq!(rgb565.encode(255, 0, 0), 0xf800);
+        assert_eq!(rgb565.encode(0, 128, 0), 31 << 5);
+        assert_eq!(rgb565.decode(0xf800), [255, 0, 0]);
+        assert_eq!(rgb565.decode(rgb565.encode(0, 0, 255)), [0, 0, 255]);
+        // The same as Bgr, described with masks
+        let bgr = PixelFormat::Bitmask { red: 0xff0000, green: 0xff00, blue: 0xff };
+        assert_eq!(bgr.encode(0x11, 0x22, 0x33), PixelFormat::Bgr.encode(0x11, 0x22, 0x33));
+        assert_eq!(PixelFormat::Bitmask { red: 0, green: 0, blue: 0xff }.encode(255, 255, 255), 0xff);
+    }
+}
@@ -0,0 +1,220 @@
+//! A gamepad or joystick plugged into the legacy game port
+//!
+//! The game port is a single I/O port. Its top 4 bits are the buttons, which
+//! read as 0 while pressed. Its bottom 4 bits are the axes: writing to the port
+//! sets them, and each one goes back to 0 after a time that depends on how far
+//! its stick is pushed. There are no interrupts, so the gamepad is polled on
+//! timer ticks.
+//!
+//! Only the first gamepad's 2 axes and 2 buttons are read. Without anything
+//! plugged in, the port floats and reads 0xff, so the axes never go back to 0.
+//! A gamepad whose axes time out a few polls in a row is taken to have been
+//! unplugged and isn't polled anymore, since every timed out read spins
+//! in the timer interrupt handler
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/Game_Port>
+//! * Linux's drivers/input/joystick/analog.c
+
+use crate::port::{Port, PortReadWrite};
+use crate::instructions::interrupts::without_interrupts;
+use sync::mutex::Mutex;
+
+/// The port the game port is read and written through
+const GAME_PORT: u16 = 0x201;
+const X_AXIS_BIT: u8 = 1 << 0;
+const Y_AXIS_BIT: u8 = 1 << 1;
+const BUTTON_A_BIT: u8 = 1 << 4;
+const BUTTON_B_BIT: u8 = 1 << 5;
+/// The number of times the port is read waiting for the axes before giving up.
+/// Reading the port takes about a microsecond and a fully pushed stick
+/// takes a little over a millisecond
+const AXIS_TIMEOUT: usize = 10_000;
+/// The number of polls in a row the axes can time out in
+/// before the gamepad is taken to have been unplugged
+const MAX_MISSED_READS: u8 = 3;
+/// How far from the middle an axis has to be pushed to count, out of `AXIS_MAX`,
+/// so a stick resting slightly off center isn't taken as pushed
+const DEAD_ZONE: i32 = 24;
+/// The value of an axis pushed all the way
+pub const AXIS_MAX: i8 = 127;
+
+/// The gamepad plugged into the game port, if there is one
+static GAMEPAD: Mutex<Option<Gamepad>> = Mutex::new(None);
+
+/// The buttons held down on the gamepad
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GamepadButtons {
+    pub a: bool,
+    pub b: bool
+}
+
+/// Where the gamepad's stick is and which buttons are held down
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GamepadState {
+    /// From -`AXIS_MAX` all the way left to `AXIS_MAX` all the way right
+    pub x: i8,
+    /// From -`AXIS_MAX` all the way up to `AXIS_MAX` all the way down
+    pub y: i8,
+    pub buttons: GamepadButtons
+}
+
+/// Looks for a gamepad on the game port, taking wherever its stick is
+/// as the middle
+pub fn init() -> Result<(), &'static str> {
+    let (x, y) = without_interrupts(read_axes).ok_or("No gamepad is plugged into the game port")?;
+    *GAMEPAD.lock() = Some(Gamepad {
+        x: Axis::new(x),
+        y: Axis::new(y),
+        last_state: GamepadState::default(),
+        missed_reads: 0
+    });
+    Ok(())
+}
+
+/// Reads the gamepad, returning its state if it changed since the last poll
+///
+/// Called by the timer interrupt handler. Returns None without a gamepad
+pub fn poll() -> Option<GamepadState> {
+    let mut gamepad_lock = GAMEPAD.try_lock()?;
+    let gamepad = gamepad_lock.as_mut()?;
+    let (x, y) = match read_axes() {
+        Some(axes) => axes,
+        None => {
+            if gamepad.miss_read() {
+                *gamepad_lock = None;
+            }
+            return None;
+        }
+    };
+    gamepad.missed_reads = 0;
+    let state = GamepadState {
+        x: gamepad.x.value(x),
+        y: gamepad.y.value(y),
+        buttons: buttons(Port::<u8>::new(GAME_PORT).read())
+    };
+    if state == gamepad.last_state {
+        return None;
+    }
+    gamepad.last_state = state;
+    Some(state)
+}
+
+struct Gamepad {
+    x: Axis,
+    y: Axis,
+    last_state: GamepadState,
+    /// The number of polls in a row the axes have timed out in
+    missed_reads: u8
+}
+
+impl Gamepad {
+    /// Counts a poll the axes timed out in, returning true
+    /// once the gamepad is taken to have been unplugged
+    fn miss_read(&mut self) -> bool {
+        self.missed_reads += 1;
+        self.missed_reads >= MAX_MISSED_READS
+    }
+}
+
+/// Turns the times an axis takes to go back to 0 into how far it's pushed
+///
+/// Sticks differ in their ranges, so the furthest the axis has been
+/// pushed either way is taken as all the way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Axis {
+    center: u32,
+    min: u32,
+    max: u32
+}
+
+impl Axis {
+    /// An axis that's at `center` when the stick is let go of.
+    /// Until it's seen otherwise, the range is taken to go from 0 to twice the center
+    fn new(center: u32) -> Self {
+        Self { center, min: 0, max: center * 2 }
+    }
+
+    /// How far the stick is pushed when the axis took `count` reads to go back to 0
+    fn value(&mut self, count: u32) -> i8 {
+        self.min = self.min.min(count);
+        self.max = self.max.max(count);
+        let value = if count >= self.center {
+            ((count - self.center) as u64 * AXIS_MAX as u64 / (self.max - self.center).max(1) as u64) as i32
+        } else {
+            -(((self.center - count) as u64 * AXIS_MAX as u64 / (self.center - self.min).max(1) as u64) as i32)
+        };
+        if value.abs() < DEAD_ZONE { 0 } else { value as i8 }
+    }
+}
+
+/// The buttons held down according to `byte`, read from the game port
+fn buttons(byte: u8) -> GamepadButtons {
+    GamepadButtons {
+        a: byte & BUTTON_A_BIT == 0,
+        b: byte & BUTTON_B_BIT == 0
+    }
+}
+
+/// The number of reads the x and y axes take to go back to 0,
+/// or None if they never do
+fn read_axes() -> Option<(u32, u32)> {
+    let mut port: Port<u8> = Port::new(GAME_PORT);
+    // Any value starts the timing
+    port.write(0xff);
+    let (mut x, mut y) = (None, None);
+    for count in 0..AXIS_TIMEOUT as u32 {
+        let byte = port.read();
+        if x.is_none() && byte & X_AXIS_BIT == 0 {
+            x = Some(count);
+        }
+        if y.is_none() && byte & Y_AXIS_BIT == 0 {
+            y = Some(count);
+        }
+        if let (Some(x), Some(y)) = (x, y) {
+            return Some((x, y));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axis() {
+        let mut axis = Axis::new(100);
+        assert_eq!(axis.value(100), 0);
+        // Slightly off center is still the middle
+        assert_eq!(axis.value(110), 0);
+        assert_eq!(axis.value(200), AXIS_MAX);
+        assert_eq!(axis.value(150), 63);
+        assert_eq!(axis.value(0), -AXIS_MAX);
+        // A stick that goes further makes the rest of the range smaller
+        assert_eq!(axis.value(300), AXIS_MAX);
+        assert_eq!(axis.value(200), 63);
+    }
+
+    #[test]
+    fn test_gamepad_is_unplugged_after_missed_reads() {
+        let mut gamepad = Gamepad {
+            x: Axis::new(100),
+            y: Axis::new(100),
+            last_state: GamepadState::default(),
+            missed_reads: 0
+        };
+        for _ in 1..MAX_MISSED_READS {
+            assert!(!gamepad.miss_read());
+        }
+        assert!(gamepad.miss_read());
+    }
+
+    #[test]
+    fn test_buttons() {
+        assert_eq!(buttons(0xff), GamepadButtons { a: false, b: false });
+        assert_eq!(buttons(0xef), GamepadButtons { a: true, b: false });
+        assert_eq!(buttons(0xcf), GamepadButtons { a: true, b: true });
+    }
+}
@@ -45,4 +45,78 @@ pub mod interrupts {
         result
     }
 
+    /// Enables interrupts and halts the processor until the next one arrives
+    ///
+    /// The sti instruction only takes effect after the instruction that follows it,
+    /// so no interrupt can be handled between the two and leave the processor
+    /// halted with nothing left to wake it up.
+    ///
+    /// Memory isn't left out of the asm options because the interrupt
+    /// handlers that run during the halt can change it
+    #[inline]
+    pub fn enable_and_hlt() {
+        unsafe {
+            asm!("sti", "hlt", options(nostack));
+        }
+    }
+
+}
+
+/// Reads the time stamp counter, which counts the processor's cycles since it was reset
+#[inline]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack));
+    }
+    (high as u64) << 32 | low as u64
+}
+
+/// Memory ordering instructions
+///
+/// The compiler and the processor are free to reorder memory accesses that
+/// don't depend on each other, which is a problem when a buffer is handed over
+/// to a device with DMA: the device can be told about the buffer before the
+/// writes to the buffer have been performed.
+///
+/// # References
+///
+/// * Intel 64 and IA-32 Architectures Software Developer's Manual, Volume 3, Section 8.2
+pub mod barrier {
+    use super::*;
+    use core::sync::atomic::{self, Ordering};
+
+    /// Prevents the compiler from moving memory accesses across this point.
+    /// No instruction is emitted, so it doesn't affect the processor
+    #[inline]
+    pub fn compiler_fence() {
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+
+    /// Makes sure all loads and stores before this point are globally visible
+    /// before any load or store after it
+    #[inline]
+    pub fn mfence() {
+        unsafe {
+            asm!("mfence", options(nostack, preserves_flags));
+        }
+    }
+
+    /// Makes sure all loads before this point complete before any load after it
+    #[inline]
+    pub fn lfence() {
+        unsafe {
+            asm!("lfence", options(nostack, preserves_flags));
+        }
+    }
+
+    /// Makes sure all stores before this point are globally visible
+    /// before any store after it
+    #[inline]
+    pub fn sfence() {
+        unsafe {
+            asm!("sfence", options(nostack, preserves_flags));
+        }
+    }
 }
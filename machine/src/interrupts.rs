@@ -8,11 +8,14 @@ use core::fmt;
 use core::ops::{Index, IndexMut};
 use crate::memory::Addr;
 use crate::DescriptorTablePointer;
-use num::Integer;
+use crate::port::{Port, PortReadWrite};
+use crate::instructions::interrupts;
+use num::{Integer, BitState};
 
 /// The number of none exception entries in the IDT
 const NO_OF_INTERRUPTS: usize = 224;
 
+#[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum CPUException {
     DivideByZero                = 0x0,
@@ -84,6 +87,45 @@ impl<F> IDTEntry<F> {
         self.options.set_ist_stack_index(index)
     }
 
+    pub fn is_present(&self) -> bool {
+        self.options.0.get_bit(15) == BitState::Set
+    }
+
+    /// The address of the handler, if the entry has one
+    pub fn handler_addr(&self) -> Option<u64> {
+        if !self.is_present() {
+            return None;
+        }
+        Some(self.handler_ptr_low as u64
+            | (self.handler_ptr_middle as u64) << 16
+            | (self.handler_ptr_high as u64) << 32)
+    }
+
+    /// Checks that the CPU can use the entry, if it's present
+    ///
+    /// A present entry needs a canonical, non null handler address and a
+    /// selector of a code segment in the GDT, since a bad entry only shows
+    /// up once its interrupt comes, as a double fault
+    pub fn check(&self) -> Result<(), &'static str> {
+        let handler_addr = match self.handler_addr() {
+            Some(handler_addr) => handler_addr,
+            None => return Ok(())
+        };
+        if handler_addr == 0 {
+            return Err("The entry's handler address is null");
+        }
+        if Addr::new_trunc(handler_addr).as_u64() != handler_addr {
+            return Err("The entry's handler address isn't canonical");
+        }
+        // The index of the selector is in bits 3 and up, and bit 2 picks the LDT
+        if self.gdt_selector >> 3 == 0 {
+            return Err("The entry's selector is null");
+        }
+        if self.gdt_selector.get_bit(2) == BitState::Set {
+            return Err("The entry's selector is in the LDT");
+        }
+        Ok(())
+    }
 }
 
 impl<F> fmt::Debug for IDTEntry<F> {
@@ -161,7 +203,7 @@ pub struct InterruptDescriptorTable {
     reserved1: IDTEntry<Handler>,
     pub x87_floating_point_exception: IDTEntry<Handler>,
     pub alignment_check: IDTEntry<HandlerWithErrCode>,
-    pub machine_check: IDTEntry<HandlerOfNoReturn>,
+    pub machine_check: IDTEntry<HandlerOfNoReturnWithoutErrCode>,
     pub simd_floating_point_exception: IDTEntry<Handler>,
     pub virtualization_exception: IDTEntry<Handler>,
     reserved2: [IDTEntry<Handler>; 8],
@@ -274,6 +316,79 @@ macro_rules! impl_set_handler {
 impl_set_handler!(Handler);
 impl_set_handler!(HandlerWithErrCode);
 impl_set_handler!(HandlerOfNoReturn);
+impl_set_handler!(HandlerOfNoReturnWithoutErrCode);
+
+/// An IDT entry taken out of the loaded IDT by `swap_handler`,
+/// to be put back with `restore_handler`
+#[derive(Debug)]
+#[must_use = "The entry has to be restored with `restore_handler` to put the old handler back"]
+pub struct SavedEntry {
+    vector: u8,
+    entry: IDTEntry<Handler>
+}
+
+/// Replaces the handler of `vector` in the loaded IDT with `handler`,
+/// for hooking vectors like the breakpoint and debug exceptions for a while
+///
+/// Returns the entry that was there. Only vectors whose handlers take no
+/// error code and that can be masked can be swapped: a non-maskable
+/// interrupt, a double fault or a machine check could come in the middle
+/// of the swap, so those vectors are refused
+pub fn swap_handler(vector: u8, handler: Handler) -> Result<SavedEntry, &'static str> {
+    check_swappable(vector)?;
+    let mut entry: IDTEntry<Handler> = IDTEntry::empty();
+    entry.set_handler(handler);
+    let old_entry = write_loaded_entry(vector, entry)?;
+    Ok(SavedEntry { vector, entry: old_entry })
+}
+
+/// Puts the entry `swap_handler` replaced back in the loaded IDT
+pub fn restore_handler(saved: SavedEntry) -> Result<(), &'static str> {
+    write_loaded_entry(saved.vector, saved.entry).map(|_| ())
+}
+
+/// Checks that `vector`'s entry can be changed while the IDT is in use
+fn check_swappable(vector: u8) -> Result<(), &'static str> {
+    use CPUException::*;
+    const NOT_MASKABLE: [CPUException; 3] = [NonMaskableInterrupt, DoubleFault, MachineCheck];
+    const WITH_ERR_CODE: [CPUException; 9] = [
+        InvalidTss, SegmentNotPresent, StackSegmentFault, GeneralProtectionFault,
+        PageFault, AlignmentCheck, ControlProtection, VMMCommunication, Security
+    ];
+    if NOT_MASKABLE.iter().any(|&exception| exception as u8 == vector) {
+        return Err("The vector can't be masked while its entry is swapped");
+    }
+    if WITH_ERR_CODE.iter().any(|&exception| exception as u8 == vector) {
+        return Err("The vector's handler takes an error code");
+    }
+    Ok(())
+}
+
+/// Checks `entry` and writes it over `vector`'s entry in the loaded IDT
+/// with interrupts disabled, returning the entry that was there
+fn write_loaded_entry(vector: u8, entry: IDTEntry<Handler>) -> Result<IDTEntry<Handler>, &'static str> {
+    entry.check()?;
+    let mut idt_ptr = DescriptorTablePointer { limit: 0, base: Addr::new(0) };
+    unsafe {
+        asm!("sidt [{}]", in(reg) &mut idt_ptr, options(nostack, preserves_flags));
+    }
+    let entry_size = mem::size_of::<IDTEntry<Handler>>();
+    // The pointer is packed, so its fields are copied out before being used
+    let (base, limit) = ({ idt_ptr.base }.as_u64() as usize, { idt_ptr.limit } as usize);
+    if base == 0 {
+        return Err("No IDT has been loaded");
+    }
+    if (vector as usize + 1) * entry_size > limit + 1 {
+        return Err("The vector is past the end of the loaded IDT");
+    }
+    let entry_ptr = (base + vector as usize * entry_size) as *mut IDTEntry<Handler>;
+    // Nothing can come in while the entry is half written
+    Ok(interrupts::without_interrupts(|| unsafe {
+        let old_entry = entry_ptr.read_volatile();
+        entry_ptr.write_volatile(entry);
+        old_entry
+    }))
+}
 
 pub type Handler = extern "x86-interrupt" fn(InterruptStackFrame);
 
@@ -281,6 +396,9 @@ pub type HandlerWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, u64
 
 pub type HandlerOfNoReturn = extern "x86-interrupt" fn(InterruptStackFrame, u64) -> !;
 
+/// The machine check exception doesn't push an error code
+pub type HandlerOfNoReturnWithoutErrCode = extern "x86-interrupt" fn(InterruptStackFrame) -> !;
+
 
 /// The values pushed on the stack by the CPU during an interrupt or exception
 ///
@@ -313,6 +431,50 @@ impl fmt::Debug for InterruptStackFrame {
     }
 }
 
+/// The reason for a non-maskable interrupt, as reported by
+/// the System Control Port B
+///
+/// # References
+///
+/// * <https://wiki.osdev.org/Non_Maskable_Interrupt>
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct NMIStatus(u8);
+
+impl NMIStatus {
+    const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+
+    /// Reads the NMI status bits from the System Control Port B
+    pub fn read() -> NMIStatus {
+        let port: Port<u8> = Port::new(Self::SYSTEM_CONTROL_PORT_B);
+        NMIStatus(port.read())
+    }
+
+    /// A memory parity or ECC error was detected on the system bus
+    pub fn memory_parity_error(&self) -> bool {
+        self.0.get_bit(7) == BitState::Set
+    }
+
+    /// A device signalled a fatal error on the I/O channel
+    pub fn io_channel_check(&self) -> bool {
+        self.0.get_bit(6) == BitState::Set
+    }
+
+    /// Checks if the NMI was caused by a hardware error on the board
+    pub fn is_hardware_error(&self) -> bool {
+        self.memory_parity_error() || self.io_channel_check()
+    }
+}
+
+impl fmt::Debug for NMIStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NMIStatus")
+            .field("memory_parity_error", &self.memory_parity_error())
+            .field("io_channel_check", &self.io_channel_check())
+            .finish()
+    }
+}
+
 /// An index into the IDT specifically for regular interrupts and not exceptions
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -324,7 +486,13 @@ pub enum IRQ {
     /// According to the info gotten from <https://os.phil-opp.com/hardware-interrupts/>,
     /// interrupt line 11 is generally available, so it is used for sound in this
     /// project
-    Sound = 11
+    Sound = 11,
+    /// The System Control Interrupt, which ACPI uses to report events like
+    /// power button presses. The FADT says which interrupt it is wired to,
+    /// but it's interrupt line 9 on practically every PC
+    Acpi = 9,
+    /// This is a hardcoded value for the PS/2 controller's second port
+    Mouse = 12
 }
 
 impl IRQ {
@@ -350,4 +518,41 @@ mod segment {
     #[repr(transparent)]
     pub struct SegmentSelector(pub u16);
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_check() {
+        let mut entry: IDTEntry<Handler> = IDTEntry::empty();
+        // Entries that aren't present are never used
+        assert!(entry.check().is_ok());
+        assert_eq!(entry.handler_addr(), None);
+
+        entry.set_handler_addr(Addr::new(0x1234_5678));
+        assert_eq!(entry.handler_addr(), Some(0x1234_5678));
+        entry.gdt_selector = 0x8;
+        assert!(entry.check().is_ok());
+
+        entry.gdt_selector = 0;
+        assert!(entry.check().is_err());
+        entry.gdt_selector = 0x8 | 0b100;
+        assert!(entry.check().is_err());
+
+        entry.set_handler_addr(Addr::new(0));
+        entry.gdt_selector = 0x8;
+        assert!(entry.check().is_err());
+    }
+
+    #[test]
+    fn test_only_maskable_vectors_without_err_codes_can_be_swapped() {
+        assert!(check_swappable(CPUException::Breakpoint as u8).is_ok());
+        assert!(check_swappable(CPUException::Debug as u8).is_ok());
+        assert!(check_swappable(32 + IRQ::Timer.as_u8()).is_ok());
+        assert!(check_swappable(CPUException::NonMaskableInterrupt as u8).is_err());
+        assert!(check_swappable(CPUException::DoubleFault as u8).is_err());
+        assert!(check_swappable(CPUException::PageFault as u8).is_err());
+    }
+}
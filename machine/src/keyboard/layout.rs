@@ -0,0 +1,318 @@
+//! The characters the keys type in different keyboard layouts
+//!
+//! KeyCodes are named after the keys of a US keyboard, but they stand for
+//! where a key is, not what's printed on it. The layout says what a key at
+//! each place types. Only the printable characters are given, so keys like
+//! Enter and Backspace have to be handled by their KeyCodes
+//!
+//! # References
+//!
+//! * The kbd layouts in Windows' Keyboard Layout Creator for US, French and German
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::keyboard::{KeyCode, KeyEvent, KeyModifiers};
+
+/// The layout keys are turned into characters with
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Qwerty as u8);
+
+/// Sets the layout `KeyEvent::to_char` uses
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::SeqCst);
+}
+
+/// The layout `KeyEvent::to_char` uses, which is QWERTY until it's set
+pub fn layout() -> Layout {
+    Layout::from_u8(LAYOUT.load(Ordering::SeqCst)).unwrap_or(Layout::Qwerty)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    /// The US layout
+    Qwerty = 0,
+    /// The French layout
+    Azerty = 1,
+    /// The German layout
+    Qwertz = 2
+}
+
+/// What a key types by itself, with shift and with AltGr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Levels {
+    normal: char,
+    shifted: Option<char>,
+    alt_gr: Option<char>
+}
+
+const fn key(normal: char, shifted: char) -> Levels {
+    Levels { normal, shifted: Some(shifted), alt_gr: None }
+}
+
+const fn key_with_alt_gr(normal: char, shifted: char, alt_gr: char) -> Levels {
+    Levels { normal, shifted: Some(shifted), alt_gr: Some(alt_gr) }
+}
+
+const fn letter(normal: char) -> Levels {
+    key(normal, normal.to_ascii_uppercase())
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Qwerty, Layout::Azerty, Layout::Qwertz];
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| *layout as u8 == value)
+    }
+
+    /// The layout's name, for showing in menus
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Qwerty => "QWERTY (US)",
+            Layout::Azerty => "AZERTY (French)",
+            Layout::Qwertz => "QWERTZ (German)"
+        }
+    }
+
+    /// The character `keycode` types with `modifiers` held down, or None if it
+    /// doesn't type one. Nothing is typed while ctrl or the left alt is held,
+    /// since those are shortcuts
+    pub fn to_char(&self, keycode: KeyCode, modifiers: KeyModifiers) -> Option<char> {
+        if modifiers.ctrl() || modifiers.alt {
+            return None;
+        }
+        let levels = keypad_levels(keycode).or_else(|| self.levels(keycode))?;
+        if modifiers.alt_gr {
+            return levels.alt_gr;
+        }
+        // Caps lock only works like shift for letters
+        let is_letter = levels.shifted == Some(levels.normal.to_ascii_uppercase())
+            && levels.normal.is_alphabetic();
+        if modifiers.shift() || (modifiers.caps_lock && is_letter) {
+            levels.shifted
+        } else {
+            Some(levels.normal)
+        }
+    }
+
+    fn levels(&self, keycode: KeyCode) -> Option<Levels> {
+        match self {
+            Layout::Qwerty => qwerty_levels(keycode),
+            Layout::Azerty => azerty_levels(keycode).or_else(|| qwerty_levels(keycode)),
+            Layout::Qwertz => qwertz_levels(keycode).or_else(|| qwerty_levels(keycode))
+        }
+    }
+}
+
+impl KeyEvent {
+    /// The character the key types in the current layout, or None if it doesn't type one
+    ///
+    /// Releases give the same character as presses,
+    /// so text entry should only look at presses
+    pub fn to_char(&self) -> Option<char> {
+        layout().to_char(self.keycode, self.key_modifiers)
+    }
+}
+
+/// The keys of the keypad and the space bar, which are the same in every layout
+fn keypad_levels(keycode: KeyCode) -> Option<Levels> {
+    let c = match keycode {
+        KeyCode::Space => ' ',
+        KeyCode::KeypadZero => '0',
+        KeyCode::KeypadOne => '1',
+        KeyCode::KeypadTwo => '2',
+        KeyCode::KeypadThree => '3',
+        KeyCode::KeypadFour => '4',
+        KeyCode::KeypadFive => '5',
+        KeyCode::KeypadSix => '6',
+        KeyCode::KeypadSeven => '7',
+        KeyCode::KeypadEight => '8',
+        KeyCode::KeypadNine => '9',
+        KeyCode::KeypadDot => '.',
+        KeyCode::KeypadStar => '*',
+        KeyCode::KeypadDash => '-',
+        KeyCode::KeypadPlus => '+',
+        KeyCode::KeypadForwardSlash => '/',
+        _ => return None
+    };
+    Some(key(c, c))
+}
+
+fn qwerty_levels(keycode: KeyCode) -> Option<Levels> {
+    let levels = match keycode {
+        KeyCode::Backtick => key('`', '~'),
+        KeyCode::One => key('1', '!'),
+        KeyCode::Two => key('2', '@'),
+        KeyCode::Three => key('3', '#'),
+        KeyCode::Four => key('4', '$'),
+        KeyCode::Five => key('5', '%'),
+        KeyCode::Six => key('6', '^'),
+        KeyCode::Seven => key('7', '&'),
+        KeyCode::Eight => key('8', '*'),
+        KeyCode::Nine => key('9', '('),
+        KeyCode::Zero => key('0', ')'),
+        KeyCode::Dash => key('-', '_'),
+        KeyCode::Equals => key('=', '+'),
+        KeyCode::Q => letter('q'),
+        KeyCode::W => letter('w'),
+        KeyCode::E => letter('e'),
+        KeyCode::R => letter('r'),
+        KeyCode::T => letter('t'),
+        KeyCode::Y => letter('y'),
+        KeyCode::U => letter('u'),
+        KeyCode::I => letter('i'),
+        KeyCode::O => letter('o'),
+        KeyCode::P => letter('p'),
+        KeyCode::OpenBracket => key('[', '{'),
+        KeyCode::CloseBracket => key(']', '}'),
+        KeyCode::BackSlash => key('\\', '|'),
+        KeyCode::A => letter('a'),
+        KeyCode::S => letter('s'),
+        KeyCode::D => letter('d'),
+        KeyCode::F => letter('f'),
+        KeyCode::G => letter('g'),
+        KeyCode::H => letter('h'),
+        KeyCode::J => letter('j'),
+        KeyCode::K => letter('k'),
+        KeyCode::L => letter('l'),
+        KeyCode::SemiColon => key(';', ':'),
+        KeyCode::SingleQuote => key('\'', '"'),
+        // US keyboards don't have this key, but some that are sold there do
+        KeyCode::NonUsBackSlash => key('\\', '|'),
+        KeyCode::Z => letter('z'),
+        KeyCode::X => letter('x'),
+        KeyCode::C => letter('c'),
+        KeyCode::V => letter('v'),
+        KeyCode::B => letter('b'),
+        KeyCode::N => letter('n'),
+        KeyCode::M => letter('m'),
+        KeyCode::Comma => key(',', '<'),
+        KeyCode::Dot => key('.', '>'),
+        KeyCode::ForwardSlash => key('/', '?'),
+        _ => return None
+    };
+    Some(levels)
+}
+
+/// The keys that differ from QWERTY in AZERTY
+fn azerty_levels(keycode: KeyCode) -> Option<Levels> {
+    let levels = match keycode {
+        KeyCode::Backtick => Levels { normal: '²', shifted: None, alt_gr: None },
+        KeyCode::One => key('&', '1'),
+        KeyCode::Two => key_with_alt_gr('é', '2', '~'),
+        KeyCode::Three => key_with_alt_gr('"', '3', '#'),
+        KeyCode::Four => key_with_alt_gr('\'', '4', '{'),
+        KeyCode::Five => key_with_alt_gr('(', '5', '['),
+        KeyCode::Six => key_with_alt_gr('-', '6', '|'),
+        KeyCode::Seven => key_with_alt_gr('è', '7', '`'),
+        KeyCode::Eight => key_with_alt_gr('_', '8', '\\'),
+        KeyCode::Nine => key_with_alt_gr('ç', '9', '^'),
+        KeyCode::Zero => key_with_alt_gr('à', '0', '@'),
+        KeyCode::Dash => key_with_alt_gr(')', '°', ']'),
+        KeyCode::Equals => key_with_alt_gr('=', '+', '}'),
+        KeyCode::Q => letter('a'),
+        KeyCode::W => letter('z'),
+        KeyCode::E => key_with_alt_gr('e', 'E', '€'),
+        KeyCode::OpenBracket => key('^', '¨'),
+        KeyCode::CloseBracket => key_with_alt_gr('$', '£', '¤'),
+        KeyCode::BackSlash => key('*', 'µ'),
+        KeyCode::A => letter('q'),
+        KeyCode::SemiColon => letter('m'),
+        KeyCode::SingleQuote => key('ù', '%'),
+        KeyCode::NonUsBackSlash => key('<', '>'),
+        KeyCode::Z => letter('w'),
+        KeyCode::M => key(',', '?'),
+        KeyCode::Comma => key(';', '.'),
+        KeyCode::Dot => key(':', '/'),
+        KeyCode::ForwardSlash => key('!', '§'),
+        _ => return None
+    };
+    Some(levels)
+}
+
+/// The keys that differ from QWERTY in QWERTZ
+fn qwertz_levels(keycode: KeyCode) -> Option<Levels> {
+    let levels = match keycode {
+        KeyCode::Backtick => key('^', '°'),
+        KeyCode::Two => key_with_alt_gr('2', '"', '²'),
+        KeyCode::Three => key_with_alt_gr('3', '§', '³'),
+        KeyCode::Six => key('6', '&'),
+        KeyCode::Seven => key_with_alt_gr('7', '/', '{'),
+        KeyCode::Eight => key_with_alt_gr('8', '(', '['),
+        KeyCode::Nine => key_with_alt_gr('9', ')', ']'),
+        KeyCode::Zero => key_with_alt_gr('0', '=', '}'),
+        KeyCode::Dash => key_with_alt_gr('ß', '?', '\\'),
+        KeyCode::Equals => key('´', '`'),
+        KeyCode::Q => key_with_alt_gr('q', 'Q', '@'),
+        KeyCode::E => key_with_alt_gr('e', 'E', '€'),
+        KeyCode::Y => letter('z'),
+        KeyCode::OpenBracket => key('ü', 'Ü'),
+        KeyCode::CloseBracket => key_with_alt_gr('+', '*', '~'),
+        KeyCode::BackSlash => key('#', '\''),
+        KeyCode::SemiColon => key('ö', 'Ö'),
+        KeyCode::SingleQuote => key('ä', 'Ä'),
+        KeyCode::NonUsBackSlash => key_with_alt_gr('<', '>', '|'),
+        KeyCode::Z => letter('y'),
+        KeyCode::M => key_with_alt_gr('m', 'M', 'µ'),
+        KeyCode::Comma => key(',', ';'),
+        KeyCode::Dot => key('.', ':'),
+        KeyCode::ForwardSlash => key('-', '_'),
+        _ => return None
+    };
+    Some(levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modifiers(shift: bool, alt_gr: bool, caps_lock: bool) -> KeyModifiers {
+        let mut modifiers = KeyModifiers::new();
+        modifiers.lshift = shift;
+        modifiers.alt_gr = alt_gr;
+        modifiers.caps_lock = caps_lock;
+        modifiers
+    }
+
+    #[test]
+    fn test_letters_move_between_layouts() {
+        let none = KeyModifiers::new();
+        let typed = |layout: Layout| [KeyCode::Q, KeyCode::W, KeyCode::Y, KeyCode::Z, KeyCode::A, KeyCode::SemiColon]
+            .map(|keycode| layout.to_char(keycode, none).unwrap());
+        assert_eq!(typed(Layout::Qwerty), ['q', 'w', 'y', 'z', 'a', ';']);
+        assert_eq!(typed(Layout::Azerty), ['a', 'z', 'y', 'w', 'q', 'm']);
+        assert_eq!(typed(Layout::Qwertz), ['q', 'w', 'z', 'y', 'a', 'ö']);
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let shift = modifiers(true, false, false);
+        let alt_gr = modifiers(false, true, false);
+        let caps_lock = modifiers(false, false, true);
+        assert_eq!(Layout::Qwerty.to_char(KeyCode::Two, shift), Some('@'));
+        assert_eq!(Layout::Azerty.to_char(KeyCode::Two, KeyModifiers::new()), Some('é'));
+        assert_eq!(Layout::Azerty.to_char(KeyCode::Two, shift), Some('2'));
+        assert_eq!(Layout::Azerty.to_char(KeyCode::Zero, alt_gr), Some('@'));
+        assert_eq!(Layout::Qwertz.to_char(KeyCode::Q, alt_gr), Some('@'));
+        // Keys without an AltGr character type nothing with it
+        assert_eq!(Layout::Qwerty.to_char(KeyCode::Q, alt_gr), None);
+        // Caps lock only capitalizes letters
+        assert_eq!(Layout::Qwertz.to_char(KeyCode::A, caps_lock), Some('A'));
+        assert_eq!(Layout::Qwertz.to_char(KeyCode::One, caps_lock), Some('1'));
+        assert_eq!(Layout::Azerty.to_char(KeyCode::SingleQuote, caps_lock), Some('ù'));
+
+        let mut ctrl = KeyModifiers::new();
+        ctrl.lctrl = true;
+        assert_eq!(Layout::Qwerty.to_char(KeyCode::A, ctrl), None);
+        // The keypad and non printable keys are the same everywhere
+        assert_eq!(Layout::Azerty.to_char(KeyCode::KeypadOne, KeyModifiers::new()), Some('1'));
+        assert_eq!(Layout::Azerty.to_char(KeyCode::Enter, KeyModifiers::new()), None);
+    }
+
+    #[test]
+    fn test_selecting_the_layout() {
+        for layout in Layout::ALL {
+            assert_eq!(Layout::from_u8(layout as u8), Some(layout));
+        }
+        assert_eq!(Layout::from_u8(3), None);
+        assert_eq!(layout(), Layout::Qwerty);
+    }
+}
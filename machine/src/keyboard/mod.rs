@@ -1,9 +1,50 @@
 //! PS/2 Keyboard driver for US 104 layout
+//!
+//! Keys are decoded in scancode set 1. Keyboards send set 2 and the PS/2
+//! controller usually translates it to set 1, but when it doesn't, set 2 is
+//! translated here the same way the controller would, byte by byte
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/PS/2_Keyboard>
+//! * Linux's atkbd_unxlate_table in drivers/input/keyboard/atkbd.c
 
 pub mod uefi;
+pub mod ps2;
+pub mod layout;
 
 /// The beginning byte for an extended key code
 const EXTENDED_KEY_CODE: u8 = 0xe0;
+/// The beginning byte of the pause key's code, the only key that uses it
+const PAUSE_KEY_CODE: u8 = 0xe1;
+/// The bytes of the pause key's code that come after `PAUSE_KEY_CODE`,
+/// which are a left ctrl and num lock press, then their releases
+const PAUSE_SEQUENCE: [u8; 5] = [0x1d, 0x45, 0xe1, 0x9d, 0xc5];
+/// The number of bytes in `PAUSE_SEQUENCE` that make up the press
+const PAUSE_PRESS_LEN: usize = 2;
+/// Set in a set 1 scancode when the key is released
+const BREAK_BIT: u8 = 0x80;
+/// Comes before a set 2 scancode when the key is released
+const SET_2_BREAK_CODE: u8 = 0xf0;
+/// The extended left shift, sent around extended keys as a fake shift
+const FAKE_LEFT_SHIFT: u8 = 0x2a;
+/// The extended right shift, sent around extended keys as a fake shift
+const FAKE_RIGHT_SHIFT: u8 = 0x36;
+
+/// The set 1 scancode of each set 2 scancode, which is what the PS/2
+/// controller replaces them with when it translates. Extended keys have
+/// the same bytes after the 0xe0
+const SET_2_TO_SET_1: [u8; 0x84] = [
+    0xff, 0x43, 0x41, 0x3f, 0x3d, 0x3b, 0x3c, 0x58, 0x64, 0x44, 0x42, 0x40, 0x3e, 0x0f, 0x29, 0x59,
+    0x65, 0x38, 0x2a, 0x70, 0x1d, 0x10, 0x02, 0x5a, 0x66, 0x71, 0x2c, 0x1f, 0x1e, 0x11, 0x03, 0x5b,
+    0x67, 0x2e, 0x2d, 0x20, 0x12, 0x05, 0x04, 0x5c, 0x68, 0x39, 0x2f, 0x21, 0x14, 0x13, 0x06, 0x5d,
+    0x69, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x5e, 0x6a, 0x72, 0x32, 0x24, 0x16, 0x08, 0x09, 0x5f,
+    0x6b, 0x33, 0x25, 0x17, 0x18, 0x0b, 0x0a, 0x60, 0x6c, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0c, 0x61,
+    0x6d, 0x73, 0x28, 0x74, 0x1a, 0x0d, 0x62, 0x6e, 0x3a, 0x36, 0x1c, 0x1b, 0x75, 0x2b, 0x63, 0x76,
+    0x55, 0x56, 0x77, 0x78, 0x79, 0x7a, 0x0e, 0x7b, 0x7c, 0x4f, 0x7d, 0x4b, 0x47, 0x7e, 0x7f, 0x6f,
+    0x52, 0x53, 0x50, 0x4c, 0x4d, 0x48, 0x01, 0x45, 0x57, 0x4e, 0x51, 0x4a, 0x37, 0x49, 0x46, 0x54,
+    0x80, 0x81, 0x82, 0x41
+];
 
 /// A representation of the state of the keyboard
 pub struct Keyboard {
@@ -11,7 +52,18 @@ pub struct Keyboard {
     /// on a regular or extended key
     state: KeyboardState,
     /// Tells whether or not shift, ctrl, alt,... is down
-    modifiers: KeyModifiers
+    modifiers: KeyModifiers,
+    /// The scancode set the bytes come in
+    scancode_set: ScancodeSet,
+    /// Tells whether the last byte was a set 2 break code
+    set_2_break: bool
+}
+
+/// The scancode sets keys can be decoded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    One,
+    Two
 }
 
 /// For toggling modifier states
@@ -29,7 +81,9 @@ impl Keyboard {
     pub fn new() -> Self {
         Keyboard {
             state: KeyboardState::Start,
-            modifiers: KeyModifiers::new()
+            modifiers: KeyModifiers::new(),
+            scancode_set: ScancodeSet::One,
+            set_2_break: false
         }
     }
 
@@ -63,83 +117,104 @@ impl Keyboard {
     /// })));
     /// ```
     pub fn process_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, KeyError> {
+        match self.scancode_set {
+            ScancodeSet::One => self.process_set_1_byte(byte),
+            ScancodeSet::Two => match byte {
+                SET_2_BREAK_CODE => {
+                    self.set_2_break = true;
+                    Ok(None)
+                }
+                EXTENDED_KEY_CODE | PAUSE_KEY_CODE => self.process_set_1_byte(byte),
+                byte => {
+                    let is_break = core::mem::replace(&mut self.set_2_break, false);
+                    let byte = *SET_2_TO_SET_1.get(byte as usize).ok_or(KeyError::UnknownScancode)?;
+                    self.process_set_1_byte(if is_break { byte | BREAK_BIT } else { byte })
+                }
+            }
+        }
+    }
+
+    /// Takes the scancodes of `set` from now on
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.scancode_set = set;
+        self.forget_partial_key();
+    }
+
+    fn process_set_1_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, KeyError> {
+        let direction = if byte & BREAK_BIT == 0 { KeyDirection::Down } else { KeyDirection::Up };
         match self.state {
-            KeyboardState::Start => {
-                match byte {
-                    // The beginning of an extended key press
-                    EXTENDED_KEY_CODE => {
-                        self.state = KeyboardState::Extended;
-                        Ok(None)
-                    }
-                    // The range of scan codes for regular key presses
-                    0x01..=0x58 => {
-                        let keycode = self.map_scancode(byte)?;
-                        if keycode.is_modifier() {
-                            self.transition_modifier(keycode, KeyDirection::Down);
-                            Ok(None)
-                        } else {
-                            Ok(Some(KeyEvent {
-                                keycode,
-                                key_modifiers: self.modifiers,
-                                direction: KeyDirection::Down
-                            }))
-                        }
-                    }
-                    // For key releases
-                    0x81..=0xd8 => {
-                        let keycode = self.map_scancode(byte - 0x80)?;
-                        if keycode.is_modifier() {
-                            self.transition_modifier(keycode, KeyDirection::Up);
-                            Ok(None)
-                        } else {
-                            Ok(Some(KeyEvent {
-                                keycode,
-                                key_modifiers: self.modifiers,
-                                direction: KeyDirection::Up
-                            }))
-                        }
-                    }
-                    _ => Err(KeyError::UnknownScancode)
+            KeyboardState::Start => match byte {
+                // The beginning of an extended key press or release
+                EXTENDED_KEY_CODE => {
+                    self.state = KeyboardState::Extended;
+                    Ok(None)
+                }
+                PAUSE_KEY_CODE => {
+                    self.state = KeyboardState::Pause(0);
+                    Ok(None)
+                }
+                _ => {
+                    let keycode = self.map_scancode(byte & !BREAK_BIT)?;
+                    Ok(self.key_event(keycode, direction))
                 }
             }
             KeyboardState::Extended => {
-                // Reset keyboard state
                 self.state = KeyboardState::Start;
-                match byte {
-                    // Range of scancodes for extended key presses
-                    0x10..=0x90 => {
-                        let keycode = self.map_extended_scancode(byte)?;
-                        if keycode.is_modifier() {
-                            self.transition_modifier(keycode, KeyDirection::Down);
-                            Ok(None)
-                        } else {
-                            Ok(Some(KeyEvent {
-                                keycode,
-                                key_modifiers: self.modifiers,
-                                direction: KeyDirection::Down
-                            }))
-                        }
+                match byte & !BREAK_BIT {
+                    // Sent around extended keys as if shift was pressed or
+                    // released, for keyboards that didn't have them on their own.
+                    // The key is told apart by its extended code anyway
+                    FAKE_LEFT_SHIFT | FAKE_RIGHT_SHIFT => Ok(None),
+                    code => {
+                        let keycode = self.map_extended_scancode(code)?;
+                        Ok(self.key_event(keycode, direction))
                     }
-                    // Range for extended key releases
-                    0x99..=0xed => {
-                        let keycode = self.map_extended_scancode(byte - 0x80)?;
-                        if keycode.is_modifier() {
-                            self.transition_modifier(keycode, KeyDirection::Up);
-                            Ok(None)
-                        } else {
-                            Ok(Some(KeyEvent {
-                                keycode,
-                                key_modifiers: self.modifiers,
-                                direction: KeyDirection::Up
-                            }))
-                        }
-                    }
-                    _ => Err(KeyError::UnknownScancode)
+                }
+            }
+            KeyboardState::Pause(received) => {
+                if PAUSE_SEQUENCE[received] != byte {
+                    self.state = KeyboardState::Start;
+                    return Err(KeyError::UnknownScancode);
+                }
+                // The key has no release of its own, so the release is sent right after the press
+                let received = received + 1;
+                self.state = if received == PAUSE_SEQUENCE.len() { KeyboardState::Start } else { KeyboardState::Pause(received) };
+                match received {
+                    PAUSE_PRESS_LEN => Ok(self.key_event(KeyCode::Pause, KeyDirection::Down)),
+                    len if len == PAUSE_SEQUENCE.len() => Ok(self.key_event(KeyCode::Pause, KeyDirection::Up)),
+                    _ => Ok(None)
                 }
             }
         }
     }
 
+    /// The event for `keycode` going in `direction`, or None for modifiers,
+    /// which are kept track of instead
+    fn key_event(&mut self, keycode: KeyCode, direction: KeyDirection) -> Option<KeyEvent> {
+        if keycode.is_modifier() {
+            self.transition_modifier(keycode, direction);
+            None
+        } else {
+            Some(KeyEvent {
+                keycode,
+                key_modifiers: self.modifiers,
+                direction
+            })
+        }
+    }
+
+    /// Tells whether or not the last byte processed was the beginning of a key code
+    pub fn is_mid_key(&self) -> bool {
+        self.state != KeyboardState::Start || self.set_2_break
+    }
+
+    /// Drops the beginning of a key code that has been processed,
+    /// so the next byte is taken as the start of a new one
+    pub fn forget_partial_key(&mut self) {
+        self.state = KeyboardState::Start;
+        self.set_2_break = false;
+    }
+
     fn transition_modifier(&mut self, keycode: KeyCode, direction: KeyDirection) {
         match keycode {
             KeyCode::LeftCtrl => toggle_modifier!(self.lctrl, direction),
@@ -240,6 +315,7 @@ impl Keyboard {
             0x51 => Ok(KeyCode::KeypadThree),
             0x52 => Ok(KeyCode::KeypadZero),
             0x53 => Ok(KeyCode::KeypadDot),
+            0x56 => Ok(KeyCode::NonUsBackSlash),
             _ => Err(KeyError::UnknownScancode)
         }
     }
@@ -258,6 +334,7 @@ impl Keyboard {
             0x30 => Ok(KeyCode::VolumeUp),
             0x32 => Ok(KeyCode::WWWHome),
             0x35 => Ok(KeyCode::KeypadForwardSlash),
+            0x37 => Ok(KeyCode::PrintScreen),
             0x38 => Ok(KeyCode::AltGr),
             0x47 => Ok(KeyCode::Home),
             0x48 => Ok(KeyCode::ArrowUp),
@@ -294,7 +371,9 @@ enum KeyboardState {
     /// The keyboard is not in the middle of any extended key presses
     Start,
     /// An extended key, eg arrow keys, has been pressed, but the press event is not yet over
-    Extended
+    Extended,
+    /// The pause key has been pressed and this many bytes of `PAUSE_SEQUENCE` have come
+    Pause(usize)
 }
 
 /// Holds the state of the currently pressed modifier keys
@@ -326,6 +405,14 @@ impl KeyModifiers {
     pub fn shift(&self) -> bool {
         self.lshift || self.rshift
     }
+
+    pub fn ctrl(&self) -> bool {
+        self.lctrl || self.rctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.alt || self.alt_gr
+    }
 }
 
 /// A key press or release, together with modifiers
@@ -336,6 +423,16 @@ pub struct KeyEvent {
     pub direction: KeyDirection
 }
 
+impl KeyEvent {
+    /// Checks if the event is the Ctrl+Alt+Del chord, which asks for a system reset
+    pub fn is_ctrl_alt_del(&self) -> bool {
+        self.keycode == KeyCode::Delete
+            && self.direction == KeyDirection::Down
+            && self.key_modifiers.ctrl()
+            && self.key_modifiers.alt()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyDirection {
     /// The key is being pressed down
@@ -471,7 +568,12 @@ pub enum KeyCode {
     WWWBack,
     MyComputer,
     Email,
-    MediaSelect
+    MediaSelect,
+    PrintScreen,
+    Pause,
+    /// The key between the left shift and Z, which only keyboards
+    /// outside the US usually have
+    NonUsBackSlash
 }
 
 impl KeyCode {
@@ -500,6 +602,8 @@ mod tests {
     const SCANCODE_X_PRESS: u8 = 0x2d;
     const SCANCODE_ARROW_UP_PRESS: u8 = 0x48;
     const SCANCODE_SEMICOLON_RELEASE: u8 = 0xa7;
+    const SCANCODE_LALT_PRESS: u8 = 0x38;
+    const SCANCODE_DELETE_PRESS: u8 = 0x53;
     const SCANCODE_BAD: u8 = 0xff;
 
     #[test]
@@ -571,10 +675,105 @@ mod tests {
         })));
     }
 
+    #[test]
+    fn test_ctrl_alt_del() {
+        let mut kbd = Keyboard::new();
+        assert_eq!(kbd.process_byte(SCANCODE_LCTRL_PRESS), Ok(None));
+        assert_eq!(kbd.process_byte(SCANCODE_LALT_PRESS), Ok(None));
+        assert_eq!(kbd.process_byte(EXTENDED_KEY_CODE), Ok(None));
+        let event = kbd.process_byte(SCANCODE_DELETE_PRESS).unwrap().unwrap();
+        assert!(event.is_ctrl_alt_del());
+
+        let mut kbd = Keyboard::new();
+        kbd.process_byte(EXTENDED_KEY_CODE).unwrap();
+        let event = kbd.process_byte(SCANCODE_DELETE_PRESS).unwrap().unwrap();
+        assert!(!event.is_ctrl_alt_del());
+    }
+
     #[test]
     fn test_bad_keycode() {
         let mut kbd = Keyboard::new();
         let event = kbd.process_byte(SCANCODE_BAD);
         assert_eq!(event, Err(KeyError::UnknownScancode));
     }
+
+    /// The keycodes and directions of the events `bytes` make
+    fn decode(kbd: &mut Keyboard, bytes: &[u8]) -> Vec<(KeyCode, KeyDirection)> {
+        bytes.iter()
+            .filter_map(|byte| kbd.process_byte(*byte).unwrap())
+            .map(|event| (event.keycode, event.direction))
+            .collect()
+    }
+
+    #[test]
+    fn test_extended_releases() {
+        let mut kbd = Keyboard::new();
+        assert_eq!(decode(&mut kbd, &[0xe0, 0x10, 0xe0, 0x90]), [
+            (KeyCode::PrevTrack, KeyDirection::Down),
+            (KeyCode::PrevTrack, KeyDirection::Up)
+        ]);
+        assert_eq!(decode(&mut kbd, &[0xe0, 0x1d, 0xe0, 0x38]), []);
+        assert!(kbd.modifiers.rctrl && kbd.modifiers.alt_gr);
+        assert_eq!(decode(&mut kbd, &[0xe0, 0x9d, 0xe0, 0xb8]), []);
+        assert!(!kbd.modifiers.ctrl() && !kbd.modifiers.alt());
+    }
+
+    #[test]
+    fn test_fake_shifts_and_print_screen() {
+        let mut kbd = Keyboard::new();
+        // Print screen, then the arrow keys with num lock on
+        assert_eq!(decode(&mut kbd, &[0xe0, 0x2a, 0xe0, 0x37, 0xe0, 0xb7, 0xe0, 0xaa]), [
+            (KeyCode::PrintScreen, KeyDirection::Down),
+            (KeyCode::PrintScreen, KeyDirection::Up)
+        ]);
+        assert_eq!(decode(&mut kbd, &[0xe0, 0x2a, 0xe0, 0x4b, 0xe0, 0xcb, 0xe0, 0xaa]), [
+            (KeyCode::ArrowLeft, KeyDirection::Down),
+            (KeyCode::ArrowLeft, KeyDirection::Up)
+        ]);
+        assert!(!kbd.modifiers.shift());
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut kbd = Keyboard::new();
+        assert_eq!(decode(&mut kbd, &[0xe1, 0x1d, 0x45, 0xe1, 0x9d, 0xc5]), [
+            (KeyCode::Pause, KeyDirection::Down),
+            (KeyCode::Pause, KeyDirection::Up)
+        ]);
+        // Left ctrl and num lock aren't taken as pressed
+        assert!(!kbd.modifiers.ctrl());
+        assert!(!kbd.is_mid_key());
+
+        let mut kbd = Keyboard::new();
+        kbd.process_byte(0xe1).unwrap();
+        assert_eq!(kbd.process_byte(0x45), Err(KeyError::UnknownScancode));
+        assert!(!kbd.is_mid_key());
+    }
+
+    #[test]
+    fn test_scancode_set_2() {
+        let mut kbd = Keyboard::new();
+        kbd.set_scancode_set(ScancodeSet::Two);
+        assert_eq!(decode(&mut kbd, &[0x1c, 0xf0, 0x1c, 0x76, 0x7c]), [
+            (KeyCode::A, KeyDirection::Down),
+            (KeyCode::A, KeyDirection::Up),
+            (KeyCode::Escape, KeyDirection::Down),
+            (KeyCode::KeypadStar, KeyDirection::Down)
+        ]);
+        assert_eq!(decode(&mut kbd, &[0xe0, 0x74, 0xe0, 0xf0, 0x74, 0xe0, 0x5a, 0x83]), [
+            (KeyCode::ArrowRight, KeyDirection::Down),
+            (KeyCode::ArrowRight, KeyDirection::Up),
+            (KeyCode::KeypadEnter, KeyDirection::Down),
+            (KeyCode::F7, KeyDirection::Down)
+        ]);
+        assert_eq!(decode(&mut kbd, &[0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77]), [
+            (KeyCode::Pause, KeyDirection::Down),
+            (KeyCode::Pause, KeyDirection::Up)
+        ]);
+        assert_eq!(decode(&mut kbd, &[0x14, 0x11]), []);
+        assert!(kbd.modifiers.lctrl && kbd.modifiers.alt);
+        assert_eq!(decode(&mut kbd, &[0xf0, 0x14]), []);
+        assert!(!kbd.modifiers.lctrl);
+        assert_eq!(kbd.process_byte(0x84), Err(KeyError::UnknownScancode));
+    }
 }
@@ -0,0 +1,200 @@
+//! The PS/2 keyboard's side of the conversation, besides scancodes
+//!
+//! A keyboard that's plugged in runs its self test and sends 0xaa when it passes.
+//! It comes back the way it was at power on, so it has to be told to start
+//! scanning again before any key is pressed. USB keyboards emulated as PS/2
+//! ones by the firmware do the same when they're replugged.
+//!
+//! There is no byte for a keyboard being unplugged. It's only noticed when
+//! a keyboard is plugged back in
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/PS/2_Keyboard>
+//! * The OSDev wiki <https://wiki.osdev.org/%228042%22_PS/2_Controller>
+
+use crate::keyboard::{Keyboard, KeyEvent, KeyError, ScancodeSet};
+use crate::ps2_controller;
+use crate::instructions::interrupts::without_interrupts;
+use crate::serial_println;
+
+/// The keyboard acknowledged the last command
+const ACK: u8 = 0xfa;
+/// The keyboard didn't get the last command and wants it sent again
+const RESEND: u8 = 0xfe;
+/// The keyboard passed its self test, after being plugged in or reset
+const SELF_TEST_PASSED: u8 = 0xaa;
+/// The keyboard failed its self test
+const SELF_TEST_FAILED: [u8; 2] = [0xfc, 0xfd];
+/// A key detection error or internal buffer overrun
+const KEY_ERROR: [u8; 2] = [0x00, 0xff];
+/// Tells the keyboard to send scancodes when keys are pressed
+const ENABLE_SCANNING: u8 = 0xf4;
+/// Tells the keyboard to reset and run its self test again
+const RESET: u8 = 0xff;
+/// The number of times a command is resent before giving up on it
+const MAX_RESENDS: usize = 3;
+/// Set in the controller's configuration while it translates set 2 scancodes to set 1
+const CONFIG_TRANSLATION_BIT: u8 = 1 << 6;
+
+/// The scancode set the keyboard's bytes arrive in
+///
+/// Keyboards start out in set 2, so that's what arrives when the controller
+/// isn't translating it. Falls back to set 1 if the controller doesn't answer
+pub fn scancode_set() -> ScancodeSet {
+    let config = without_interrupts(|| {
+        // A byte already waiting would be taken for the configuration
+        ps2_controller::flush_output();
+        ps2_controller::read_config()
+    });
+    match config {
+        Ok(config) if config & CONFIG_TRANSLATION_BIT == 0 => ScancodeSet::Two,
+        _ => ScancodeSet::One
+    }
+}
+
+/// What a byte from the keyboard turned out to be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ps2Event {
+    Key(KeyEvent),
+    /// A keyboard was plugged in and has been set up again
+    Reattached
+}
+
+/// A PS/2 keyboard that's set up again when it's replugged
+pub struct Ps2Keyboard {
+    keyboard: Keyboard,
+    /// The command sent to the keyboard that hasn't been acknowledged yet
+    pending_command: Option<u8>,
+    /// The number of times the pending command has been resent
+    resends: usize
+}
+
+impl Ps2Keyboard {
+    pub fn new() -> Self {
+        Self {
+            keyboard: Keyboard::new(),
+            pending_command: None,
+            resends: 0
+        }
+    }
+
+    /// Decodes the keyboard's bytes as scancodes of `set` from now on
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.keyboard.set_scancode_set(set);
+    }
+
+    /// Handles a byte read from the data port, sending the keyboard
+    /// whatever commands it needs
+    pub fn process_byte(&mut self, byte: u8) -> Result<Option<Ps2Event>, KeyError> {
+        // Bytes written without a command to the controller go to the keyboard
+        self.process_byte_with(byte, ps2_controller::write_data)
+    }
+
+    /// Does the same as `process_byte`, but sends commands to the keyboard with `send`
+    fn process_byte_with(&mut self, byte: u8, mut send: impl FnMut(u8)) -> Result<Option<Ps2Event>, KeyError> {
+        match byte {
+            ACK => {
+                self.pending_command = None;
+                Ok(None)
+            }
+            RESEND => {
+                if let Some(command) = self.pending_command {
+                    if self.resends < MAX_RESENDS {
+                        self.resends += 1;
+                        send(command);
+                    } else {
+                        serial_println!("The keyboard didn't take command {:#x}", command);
+                        self.pending_command = None;
+                    }
+                }
+                Ok(None)
+            }
+            // Also the left shift's break code in set 1, which a replugged keyboard can't be holding
+            SELF_TEST_PASSED if !self.keyboard.is_mid_key() && !self.is_left_shift_release() => {
+                // Whatever was held down when the keyboard was unplugged isn't anymore
+                let set = self.keyboard.scancode_set;
+                self.keyboard = Keyboard::new();
+                self.keyboard.set_scancode_set(set);
+                self.send_command(ENABLE_SCANNING, &mut send);
+                Ok(Some(Ps2Event::Reattached))
+            }
+            byte if SELF_TEST_FAILED.contains(&byte) => {
+                serial_println!("The keyboard failed its self test, resetting it");
+                self.send_command(RESET, &mut send);
+                Ok(None)
+            }
+            byte if KEY_ERROR.contains(&byte) => {
+                // The rest of a key that was being sent may have been lost
+                self.keyboard.forget_partial_key();
+                Ok(None)
+            }
+            byte => self.keyboard.process_byte(byte).map(|event| event.map(Ps2Event::Key))
+        }
+    }
+
+    /// Tells whether a `SELF_TEST_PASSED` byte could be the left shift being let go of
+    fn is_left_shift_release(&self) -> bool {
+        self.keyboard.scancode_set == ScancodeSet::One && self.keyboard.modifiers.lshift
+    }
+
+    fn send_command(&mut self, command: u8, send: &mut impl FnMut(u8)) {
+        self.pending_command = Some(command);
+        self.resends = 0;
+        send(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec as StdVec;
+
+    const SCANCODE_LSHIFT_PRESS: u8 = 0x2a;
+
+    #[test]
+    fn test_self_test_byte_reinitializes_the_keyboard() {
+        let mut kbd = Ps2Keyboard::new();
+        let mut sent = StdVec::new();
+        kbd.keyboard.modifiers.caps_lock = true;
+        assert_eq!(kbd.process_byte_with(SELF_TEST_PASSED, |b| sent.push(b)), Ok(Some(Ps2Event::Reattached)));
+        assert_eq!(sent, [ENABLE_SCANNING]);
+        assert!(!kbd.keyboard.modifiers.caps_lock);
+        assert_eq!(kbd.process_byte_with(ACK, |b| sent.push(b)), Ok(None));
+        assert_eq!(kbd.pending_command, None);
+    }
+
+    #[test]
+    fn test_left_shift_release_is_not_a_reattach() {
+        let mut kbd = Ps2Keyboard::new();
+        let mut sent = StdVec::new();
+        assert_eq!(kbd.process_byte_with(SCANCODE_LSHIFT_PRESS, |b| sent.push(b)), Ok(None));
+        assert_eq!(kbd.process_byte_with(SELF_TEST_PASSED, |b| sent.push(b)), Ok(None));
+        assert!(!kbd.keyboard.modifiers.lshift);
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_reattach_keeps_the_scancode_set() {
+        let mut kbd = Ps2Keyboard::new();
+        kbd.set_scancode_set(ScancodeSet::Two);
+        // The set 2 left shift press, which doesn't make 0xaa a release
+        assert_eq!(kbd.process_byte_with(0x12, |_| ()), Ok(None));
+        assert_eq!(kbd.process_byte_with(SELF_TEST_PASSED, |_| ()), Ok(Some(Ps2Event::Reattached)));
+        assert_eq!(kbd.keyboard.scancode_set, ScancodeSet::Two);
+    }
+
+    #[test]
+    fn test_resend() {
+        let mut kbd = Ps2Keyboard::new();
+        let mut sent = StdVec::new();
+        kbd.process_byte_with(SELF_TEST_PASSED, |b| sent.push(b)).unwrap();
+        for _ in 0..MAX_RESENDS + 2 {
+            kbd.process_byte_with(RESEND, |b| sent.push(b)).unwrap();
+        }
+        // The first send and the resends, and nothing once it's given up on
+        assert_eq!(sent.len(), 1 + MAX_RESENDS);
+        assert!(sent.iter().all(|b| *b == ENABLE_SCANNING));
+        assert_eq!(kbd.pending_command, None);
+    }
+}
@@ -5,6 +5,7 @@
 #![allow(dead_code)]
 
 pub mod interrupts;
+pub mod apic;
 pub mod memory;
 pub mod tss;
 pub mod gdt;
@@ -16,16 +17,34 @@ pub mod power;
 pub mod cmos;
 pub mod uefi;
 pub mod keyboard;
+pub mod mouse;
+pub mod gamepad;
+mod ps2_controller;
 pub mod acpi;
+pub mod mce;
+pub mod entropy;
+pub mod mmio;
+pub mod stats;
+pub mod crashlog;
+pub mod settings;
+pub mod trace;
+pub mod driver;
+pub mod time;
+pub mod pci;
+pub mod serial;
+pub mod speaker;
+pub mod framebuffer;
+pub mod vsync;
 mod printer;
 mod font;
 
 use memory::Addr;
+use framebuffer::Framebuffer;
 
 use sync::once::Once;
 
-/// The address of the display screen memory map
-pub static FRAMEBUFFER: Once<Addr> = Once::new();
+/// The display screen's memory map
+pub static FRAMEBUFFER: Once<Framebuffer> = Once::new();
 
 /// A structure that is used to load a new Descriptor Table
 #[repr(C, packed(2))]
@@ -0,0 +1,350 @@
+//! Abstractions for reading the Machine Check Architecture error banks
+//!
+//! When the processor detects a hardware error, like an uncorrectable
+//! memory or cache error, it logs the error in one of its MCA banks and
+//! raises a machine check exception. Decoding the banks makes it possible
+//! to tell real hardware faults apart from bugs in the drivers.
+//!
+//! # References
+//!
+//! * Intel 64 and IA-32 Architectures Software Developer's Manual, Volume 3, Chapter 15
+//! * <https://wiki.osdev.org/Machine_Check_Exception>
+
+use core::arch::x86_64::__cpuid;
+use core::fmt;
+use num::{Integer, BitState};
+use crate::registers::Msr;
+
+/// Reports the machine check capabilities of the processor
+const IA32_MCG_CAP: Msr = Msr::new(0x179);
+/// Describes the current state of the processor after a machine check
+const IA32_MCG_STATUS: Msr = Msr::new(0x17a);
+/// The number of the first bank's control register.
+/// Every bank has 4 registers: control, status, address and misc
+const IA32_MC0_CTL: u32 = 0x400;
+
+/// Checks if the processor supports the machine check exception and architecture
+///
+/// Reading the MCA registers on a processor without them causes a general
+/// protection fault, so this must be checked before anything else in this module
+/// is used
+pub fn is_supported() -> bool {
+    // CPUID.01H:EDX bit 7 is the MCE flag and bit 14 is the MCA flag
+    let edx = unsafe { __cpuid(1).edx };
+    edx.get_bit(7) == BitState::Set && edx.get_bit(14) == BitState::Set
+}
+
+/// Returns a report of all the errors currently logged in the MCA banks,
+/// or None if the processor doesn't support the machine check architecture
+pub fn report() -> Option<MachineCheckReport> {
+    if !is_supported() {
+        return None;
+    }
+    let cap = unsafe { IA32_MCG_CAP.read() };
+    let global_status = MachineCheckGlobalStatus(unsafe { IA32_MCG_STATUS.read() });
+    Some(MachineCheckReport {
+        global_status,
+        no_of_banks: cap.get_bits(0..8).as_u8()
+    })
+}
+
+/// The errors logged in the MCA banks at the time the report was taken
+pub struct MachineCheckReport {
+    global_status: MachineCheckGlobalStatus,
+    no_of_banks: u8
+}
+
+impl MachineCheckReport {
+    /// The processor state at the time of the machine check
+    pub fn global_status(&self) -> MachineCheckGlobalStatus {
+        self.global_status
+    }
+
+    /// An iterator over the banks that hold a valid error
+    pub fn banks(&self) -> MachineCheckBankIter {
+        MachineCheckBankIter { curr_bank: 0, no_of_banks: self.no_of_banks }
+    }
+
+    /// Checks if any of the banks hold a valid error
+    pub fn has_errors(&self) -> bool {
+        self.banks().next().is_some()
+    }
+
+    /// Clears the machine check in progress flag
+    ///
+    /// If a machine check occurs while the flag is set, the processor shuts
+    /// down, so it should be cleared once the exception has been handled
+    pub fn clear_in_progress(&self) {
+        let mut status = unsafe { IA32_MCG_STATUS.read() };
+        status.unset_bit(2);
+        unsafe { IA32_MCG_STATUS.write(status); }
+    }
+
+    /// Clears the status registers of all the banks, so errors that have
+    /// already been reported aren't reported again
+    pub fn clear_banks(&self) {
+        for bank_no in 0..self.no_of_banks {
+            unsafe { MachineCheckBank::status_msr(bank_no).write(0); }
+        }
+    }
+}
+
+impl fmt::Display for MachineCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:?}", self.global_status)?;
+        if !self.has_errors() {
+            return write!(f, "No errors logged in the {} MCA banks", self.no_of_banks);
+        }
+        for bank in self.banks() {
+            writeln!(f, "{}", bank)?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the MCA banks with a valid error logged
+pub struct MachineCheckBankIter {
+    curr_bank: u8,
+    no_of_banks: u8
+}
+
+impl Iterator for MachineCheckBankIter {
+    type Item = MachineCheckBank;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.curr_bank < self.no_of_banks {
+            let bank_no = self.curr_bank;
+            self.curr_bank += 1;
+            let bank = MachineCheckBank::read(bank_no);
+            if bank.status.is_valid() {
+                return Some(bank);
+            }
+        }
+        None
+    }
+}
+
+/// An error logged in an MCA bank
+#[derive(Debug, Clone, Copy)]
+pub struct MachineCheckBank {
+    pub bank_no: u8,
+    pub status: MachineCheckBankStatus,
+    /// The address of the code or data that caused the error,
+    /// if the processor logged one
+    pub addr: Option<u64>,
+    /// Additional model specific information about the error,
+    /// if the processor logged any
+    pub misc: Option<u64>
+}
+
+impl MachineCheckBank {
+    /// Reads the registers of the bank with number `bank_no`
+    fn read(bank_no: u8) -> MachineCheckBank {
+        let status = MachineCheckBankStatus(unsafe { Self::status_msr(bank_no).read() });
+        let addr = if status.addr_is_valid() {
+            Some(unsafe { Self::bank_msr(bank_no, 2).read() })
+        } else {
+            None
+        };
+        let misc = if status.misc_is_valid() {
+            Some(unsafe { Self::bank_msr(bank_no, 3).read() })
+        } else {
+            None
+        };
+        MachineCheckBank { bank_no, status, addr, misc }
+    }
+
+    fn status_msr(bank_no: u8) -> Msr {
+        Self::bank_msr(bank_no, 1)
+    }
+
+    /// The `offset`th register of bank `bank_no`
+    fn bank_msr(bank_no: u8, offset: u32) -> Msr {
+        Msr::new(IA32_MC0_CTL + 4 * bank_no.as_u32() + offset)
+    }
+}
+
+impl fmt::Display for MachineCheckBank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bank {}: {} ({:#x})", self.bank_no, self.status.error_kind(), self.status.mca_error_code())?;
+        if self.status.is_uncorrected() {
+            write!(f, " uncorrected")?;
+        }
+        if self.status.processor_context_corrupt() {
+            write!(f, " pcc")?;
+        }
+        if self.status.overflowed() {
+            write!(f, " overflow")?;
+        }
+        if let Some(addr) = self.addr {
+            write!(f, " addr={:#x}", addr)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, " misc={:#x}", misc)?;
+        }
+        Ok(())
+    }
+}
+
+/// The IA32_MCG_STATUS register
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct MachineCheckGlobalStatus(u64);
+
+impl MachineCheckGlobalStatus {
+    /// Restart IP valid.
+    /// If set, execution can be restarted reliably at the instruction
+    /// pointer pushed on the stack
+    pub fn restart_ip_is_valid(&self) -> bool {
+        self.0.get_bit(0) == BitState::Set
+    }
+
+    /// Error IP valid.
+    /// If set, the instruction pointer pushed on the stack is directly
+    /// associated with the error
+    pub fn error_ip_is_valid(&self) -> bool {
+        self.0.get_bit(1) == BitState::Set
+    }
+
+    /// Machine check in progress.
+    /// If a machine check occurs while this is set, the processor shuts down
+    pub fn machine_check_in_progress(&self) -> bool {
+        self.0.get_bit(2) == BitState::Set
+    }
+}
+
+impl fmt::Debug for MachineCheckGlobalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MachineCheckGlobalStatus")
+            .field("ripv", &self.restart_ip_is_valid())
+            .field("eipv", &self.error_ip_is_valid())
+            .field("mcip", &self.machine_check_in_progress())
+            .finish()
+    }
+}
+
+/// The IA32_MCi_STATUS register of an MCA bank
+#[derive(Debug, Clone, Copy)]
+#[repr(transparent)]
+pub struct MachineCheckBankStatus(u64);
+
+impl MachineCheckBankStatus {
+    /// The bank holds a valid error
+    pub fn is_valid(&self) -> bool {
+        self.0.get_bit(63) == BitState::Set
+    }
+
+    /// An error was logged while a previous error was still in the bank
+    pub fn overflowed(&self) -> bool {
+        self.0.get_bit(62) == BitState::Set
+    }
+
+    /// The processor couldn't correct the error
+    pub fn is_uncorrected(&self) -> bool {
+        self.0.get_bit(61) == BitState::Set
+    }
+
+    /// Reporting of the error was enabled in the bank's control register
+    pub fn is_enabled(&self) -> bool {
+        self.0.get_bit(60) == BitState::Set
+    }
+
+    /// The bank's misc register holds information about the error
+    pub fn misc_is_valid(&self) -> bool {
+        self.0.get_bit(59) == BitState::Set
+    }
+
+    /// The bank's address register holds the address of the error
+    pub fn addr_is_valid(&self) -> bool {
+        self.0.get_bit(58) == BitState::Set
+    }
+
+    /// Processor context corrupt.
+    /// If set, the state of the processor may have been corrupted by the error
+    /// and execution can't continue reliably
+    pub fn processor_context_corrupt(&self) -> bool {
+        self.0.get_bit(57) == BitState::Set
+    }
+
+    /// The architecturally defined error code
+    pub fn mca_error_code(&self) -> u16 {
+        self.0.get_bits(0..16).as_u16()
+    }
+
+    /// The error code defined by the processor model
+    pub fn model_specific_error_code(&self) -> u16 {
+        self.0.get_bits(16..32).as_u16()
+    }
+
+    /// A rough classification of the MCA error code
+    pub fn error_kind(&self) -> MachineCheckErrorKind {
+        MachineCheckErrorKind::from(self.mca_error_code())
+    }
+}
+
+/// A classification of the MCA error codes
+///
+/// # References
+///
+/// * Intel 64 and IA-32 Architectures Software Developer's Manual, Volume 3, Section 15.9
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MachineCheckErrorKind {
+    NoError,
+    Unclassified,
+    MicrocodeRomParity,
+    ExternalError,
+    Frc,
+    InternalParity,
+    InternalTimer,
+    InternalUnclassified,
+    Generic,
+    Tlb,
+    MemoryController,
+    Cache,
+    BusInterconnect,
+    Unknown
+}
+
+impl From<u16> for MachineCheckErrorKind {
+    fn from(code: u16) -> Self {
+        match code {
+            0x0000 => Self::NoError,
+            0x0001 => Self::Unclassified,
+            0x0002 => Self::MicrocodeRomParity,
+            0x0003 => Self::ExternalError,
+            0x0004 => Self::Frc,
+            0x0005 => Self::InternalParity,
+            0x0400 => Self::InternalTimer,
+            // Compound error codes, identified by their most significant set bit,
+            // ignoring the filtering bit 12
+            c if c & 0xeffc == 0x000c => Self::Generic,
+            c if c & 0xeff0 == 0x0010 => Self::Tlb,
+            c if c & 0xef80 == 0x0080 => Self::MemoryController,
+            c if c & 0xef00 == 0x0100 => Self::Cache,
+            c if c & 0xe800 == 0x0800 => Self::BusInterconnect,
+            c if c & 0xfc00 == 0x0400 => Self::InternalUnclassified,
+            _ => Self::Unknown
+        }
+    }
+}
+
+impl fmt::Display for MachineCheckErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Self::NoError => "No error",
+            Self::Unclassified => "Unclassified error",
+            Self::MicrocodeRomParity => "Microcode ROM parity error",
+            Self::ExternalError => "External error",
+            Self::Frc => "FRC error",
+            Self::InternalParity => "Internal parity error",
+            Self::InternalTimer => "Internal timer error",
+            Self::InternalUnclassified => "Internal unclassified error",
+            Self::Generic => "Generic cache hierarchy error",
+            Self::Tlb => "TLB error",
+            Self::MemoryController => "Memory controller error",
+            Self::Cache => "Cache hierarchy error",
+            Self::BusInterconnect => "Bus / interconnect error",
+            Self::Unknown => "Unknown error"
+        };
+        write!(f, "{}", description)
+    }
+}
@@ -4,6 +4,8 @@ use core::ops::{Add, Sub, BitAnd, Index, AddAssign};
 use core::{slice, fmt};
 use num::Integer;
 
+/// The most regions a memory map holds. Stage 2 stops asking the BIOS
+/// for E820 regions at this many, so it has to be kept in sync with it
 const MAX_MEM_MAP_SIZE: usize = 100;
 
 /// A wrapper around a u64 to ensure it always remains a valid
@@ -189,6 +191,64 @@ impl MemMap {
         }
     }
 
+    /// Rebuilds the map so that no two regions overlap and no two adjacent
+    /// regions have the same type
+    ///
+    /// Firmware memory maps can contain overlapping regions and regions that
+    /// are split for no reason. Where regions overlap, the overlapped part is
+    /// given the most restrictive of the regions' types, so memory that any of
+    /// the regions says is unusable is never handed out
+    ///
+    /// # References
+    ///
+    /// * <https://wiki.osdev.org/Detecting_Memory_(x86)#BIOS_Function:_INT_0x15.2C_EAX_.3D_0xE820>
+    pub fn normalize(&mut self) {
+        // Every start and end address of a region is a point where the type
+        // of the memory can change
+        let mut boundaries = [0u64; MAX_MEM_MAP_SIZE * 2];
+        let mut no_of_boundaries = 0;
+        for region in self.entries.iter().filter(|r| !r.range.is_empty()) {
+            for addr in [region.range.start_addr.as_u64(), region.range.end_addr.as_u64()] {
+                if !boundaries[..no_of_boundaries].contains(&addr) {
+                    boundaries[no_of_boundaries] = addr;
+                    no_of_boundaries += 1;
+                }
+            }
+        }
+        let boundaries = &mut boundaries[..no_of_boundaries];
+        boundaries.sort_unstable();
+
+        let mut normalized = MemMap::new();
+        for [start, end] in boundaries.array_windows::<2>() {
+            let region_type = self.entries.iter()
+                .filter(|r| !r.range.is_empty())
+                .filter(|r| r.range.start_addr.as_u64() <= *start && r.range.end_addr.as_u64() >= *end)
+                .map(|r| r.region_type)
+                .max_by_key(|t| t.restrictiveness());
+            let region_type = match region_type {
+                Some(region_type) => region_type,
+                // A hole in the map
+                None => continue
+            };
+            let last_idx = normalized.next_entry_index as usize;
+            if last_idx > 0 {
+                let last = &mut normalized.entries[last_idx - 1];
+                if last.region_type == region_type && last.range.end_addr.as_u64() == *start {
+                    last.range.end_addr = Addr::new(*end);
+                    continue;
+                }
+            }
+            let region = MemRegion {
+                range: AddrRange { start_addr: Addr::new(*start), end_addr: Addr::new(*end) },
+                region_type
+            };
+            if let Err(_) = normalized.add_region(region) {
+                break;
+            }
+        }
+        *self = normalized;
+    }
+
     fn remove_usable_region_overlaps(&mut self) {
         let mut mmap_iter = self.entries.iter_mut().peekable();
         while let Some(region) = mmap_iter.next(){
@@ -274,13 +334,12 @@ pub struct AddrRange {
 }
 
 impl AddrRange {
-    /// Creates a new AddrRange that spans `start_addr`..=`end_addr`-1
+    /// Creates a new AddrRange that spans `start_addr`..`end_addr`
     #[inline]
     pub fn new(start_addr: u64, end_addr: u64) -> AddrRange {
-        let end_addr = end_addr.checked_sub(1).or(Some(0));
         AddrRange {
             start_addr: Addr::new(start_addr),
-            end_addr: Addr::new(end_addr.unwrap())
+            end_addr: Addr::new(end_addr)
         }
     }
 
@@ -349,6 +408,19 @@ impl MemRegionType {
         *self == MemRegionType::Usable
     }
 
+    /// How strongly the type forbids using the memory.
+    /// When regions overlap, the type with the highest restrictiveness wins
+    #[inline]
+    fn restrictiveness(&self) -> u8 {
+        match *self {
+            MemRegionType::Empty => 0,
+            MemRegionType::Usable => 1,
+            MemRegionType::AcpiReclaimable => 2,
+            MemRegionType::BadMem => 4,
+            _ => 3
+        }
+    }
+
     #[inline]
     fn as_str(&self) -> &str {
         match *self {
@@ -382,7 +454,8 @@ impl From<E820MemRegion> for MemRegion {
             3 => MemRegionType::AcpiReclaimable,
             4 => MemRegionType::AcpiNvs,
             5 => MemRegionType::BadMem,
-            t => panic!("Where the hell did this region type come from?! {}", t)
+            // The ACPI spec says that undefined types must be treated as reserved
+            _ => MemRegionType::Reserved
         };
         MemRegion {
             range: AddrRange::new(region.start_addr, region.start_addr + region.len),
@@ -572,6 +645,15 @@ impl<'b> MemAllocator<'b> {
         }
     }
     
+    /// Checks if every address in `range` is in a usable region
+    pub fn range_is_usable(&self, range: AddrRange) -> bool {
+        self.mmap.entries.iter().any(|r| {
+            r.region_type.is_usable()
+                && r.range.start_addr <= range.start_addr
+                && range.end_addr <= r.range.end_addr
+        })
+    }
+
     pub fn mark_alloc_region(&mut self, region: MemRegion){
         for r in self.mmap.entries.iter_mut(){
             if region.range.start_addr < r.range.end_addr {
@@ -682,12 +764,16 @@ impl From<E820MemMapDescriptor> for MemMap {
         let e820_mmap = unsafe { slice::from_raw_parts(mmap_start_ptr, mmap_entry_count as usize) };
         let mut mmap = MemMap::new();
         for region in e820_mmap {
+            // Some BIOSes report empty regions and regions beyond
+            // the addressable range, which can't be represented
+            if region.len == 0 || region.start_addr.checked_add(region.len).map_or(true, |end| end >= 1 << 47) {
+                continue;
+            }
             if let Err(_) = mmap.add_region(MemRegion::from(*region)) {
                 break;
             }
         }
-        mmap.sort();
-        mmap.remove_usable_region_overlaps();
+        mmap.normalize();
         mmap
     }
 }
@@ -758,3 +844,70 @@ impl<N: Integer + fmt::Display> fmt::Debug for Hex<N> {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(start: u64, end: u64, region_type: MemRegionType) -> MemRegion {
+        MemRegion {
+            range: AddrRange { start_addr: Addr::new(start), end_addr: Addr::new(end) },
+            region_type
+        }
+    }
+
+    #[test]
+    fn test_normalize_merges_adjacent_regions() {
+        let mut mmap = MemMap::new();
+        mmap.add_region(region(0x1000, 0x2000, MemRegionType::Usable)).unwrap();
+        mmap.add_region(region(0x2000, 0x3000, MemRegionType::Usable)).unwrap();
+        mmap.normalize();
+        assert_eq!(mmap.next_entry_index, 1);
+        assert_eq!(mmap[0].range.start_addr, 0x1000);
+        assert_eq!(mmap[0].range.end_addr, 0x3000);
+    }
+
+    #[test]
+    fn test_normalize_gives_overlaps_the_most_restrictive_type() {
+        let mut mmap = MemMap::new();
+        mmap.add_region(region(0x0, 0x10000, MemRegionType::Usable)).unwrap();
+        mmap.add_region(region(0x4000, 0x8000, MemRegionType::Reserved)).unwrap();
+        mmap.normalize();
+        assert_eq!(mmap.next_entry_index, 3);
+        assert_eq!(mmap[0].region_type, MemRegionType::Usable);
+        assert_eq!(mmap[0].range.end_addr, 0x4000);
+        assert_eq!(mmap[1].region_type, MemRegionType::Reserved);
+        assert_eq!(mmap[1].range.end_addr, 0x8000);
+        assert_eq!(mmap[2].region_type, MemRegionType::Usable);
+        assert_eq!(mmap[2].range.start_addr, 0x8000);
+        assert_eq!(mmap[2].range.end_addr, 0x10000);
+    }
+
+    #[test]
+    fn test_normalize_keeps_holes() {
+        let mut mmap = MemMap::new();
+        mmap.add_region(region(0x0, 0x1000, MemRegionType::Usable)).unwrap();
+        mmap.add_region(region(0x2000, 0x3000, MemRegionType::Usable)).unwrap();
+        mmap.normalize();
+        assert_eq!(mmap.next_entry_index, 2);
+        assert_eq!(mmap[1].range.start_addr, 0x2000);
+    }
+
+    #[test]
+    fn test_both_boot_paths_build_exclusive_ranges() {
+        let e820 = MemRegion::from(E820MemRegion { start_addr: 0x1000, len: 0x2000, region_type: 1, acpi_extended_attrs: 1 });
+        let efi = MemRegion::from(EFIMemRegion {
+            type_: EFIMemRegionType::Conventional,
+            physical_start: Addr::new(0x1000),
+            virtual_start: Addr::new(0),
+            no_of_pages: 2,
+            attribute: 0
+        });
+        for region in [e820, efi] {
+            assert_eq!(region.region_type, MemRegionType::Usable);
+            assert_eq!(region.range.start_addr, 0x1000);
+            assert_eq!(region.range.end_addr, 0x3000);
+            assert_eq!(region.range.size(), 0x2000);
+        }
+    }
+}
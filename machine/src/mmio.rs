@@ -0,0 +1,212 @@
+//! Abstractions for accessing memory mapped device registers
+//!
+//! Casting a pointer to the registers into a `#[repr(packed)]` struct and
+//! reading its fields is easy to get wrong: the compiler is free to leave out
+//! or merge the reads and writes, since it doesn't know the device can see and
+//! change them, and a field left out of the struct silently shifts every
+//! register after it. The `register_block!` macro generates accessors that
+//! are always volatile and registers whose offsets are checked at compile time.
+
+/// A memory mapped register holding a value of type `T`
+///
+/// Every access goes straight to the device with a volatile read or write
+pub struct Register<T> {
+    ptr: *mut T
+}
+
+impl<T> Register<T> {
+    /// Creates a handle to the register at `ptr`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a mapped register of type `T` that stays
+    /// mapped for as long as the handle is used, and it must be aligned
+    pub unsafe fn new(ptr: *mut T) -> Self {
+        Self { ptr }
+    }
+
+    /// Reads the register's current value
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { self.ptr.read_volatile() }
+    }
+
+    /// Writes `val` into the register
+    #[inline]
+    pub fn write(&self, val: T) {
+        unsafe { self.ptr.write_volatile(val) }
+    }
+
+    /// Reads the register, changes the value with `f` and writes it back
+    #[inline]
+    pub fn modify<F: FnOnce(&mut T)>(&self, f: F) {
+        let mut val = self.read();
+        f(&mut val);
+        self.write(val);
+    }
+
+    /// The address of the register
+    pub fn ptr(&self) -> *mut T {
+        self.ptr
+    }
+}
+
+/// Checks the (offset, size, alignment) of each register in a register block,
+/// failing the build if a register is misaligned, or overlaps or comes before
+/// the one declared before it
+///
+/// Used by `register_block!`, which evaluates it in a constant
+pub const fn check_layout(regs: &[(usize, usize, usize)]) {
+    let mut i = 0;
+    while i < regs.len() {
+        let (offset, _, align) = regs[i];
+        if offset % align != 0 {
+            panic!("A register's offset is not aligned for its type");
+        }
+        if i > 0 {
+            let (prev_offset, prev_size, _) = regs[i - 1];
+            if offset < prev_offset + prev_size {
+                panic!("A register overlaps or comes before the register declared before it");
+            }
+        }
+        i += 1;
+    }
+}
+
+/// The number of bytes from the start of a register block to the end of
+/// its last register, given the (offset, size, alignment) of each register
+pub const fn block_size(regs: &[(usize, usize, usize)]) -> usize {
+    match regs.last() {
+        Some((offset, size, _)) => *offset + *size,
+        None => 0
+    }
+}
+
+/// Generates a handle to a block of memory mapped registers, with an
+/// accessor returning a `Register` for each of them
+///
+/// Each register is declared with its offset from the start of the block.
+/// Gaps between the offsets are reserved space, so they don't need to be declared.
+/// The registers must be declared in order of their offsets
+///
+/// ```ignore
+/// register_block! {
+///     /// The registers of a made up timer
+///     pub struct TimerRegs {
+///         0x00 => count: u32,
+///         0x08 => control: TimerControlReg
+///     }
+/// }
+///
+/// let regs = unsafe { TimerRegs::new(base_ptr) };
+/// regs.control().modify(|control| control.set_enabled(true));
+/// while regs.count().read() < 100 {}
+/// ```
+#[macro_export]
+macro_rules! register_block {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$reg_attr:meta])*
+                $offset:literal => $reg:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy)]
+        $vis struct $name {
+            base: *mut u8
+        }
+
+        const _: () = $crate::mmio::check_layout(&[
+            $(($offset, core::mem::size_of::<$ty>(), core::mem::align_of::<$ty>())),*
+        ]);
+
+        impl $name {
+            /// The number of bytes from the start of the block to the end of its last register
+            $vis const SIZE: usize = $crate::mmio::block_size(&[
+                $(($offset, core::mem::size_of::<$ty>(), core::mem::align_of::<$ty>())),*
+            ]);
+
+            /// Creates a handle to the block of registers starting at `base`
+            ///
+            /// # Safety
+            ///
+            /// `base` must point to the mapped registers and stay mapped
+            /// for as long as the handle is used
+            $vis unsafe fn new(base: *mut u8) -> Self {
+                Self { base }
+            }
+
+            /// The address of the start of the block
+            $vis fn base_ptr(&self) -> *mut u8 {
+                self.base
+            }
+
+            $(
+                $(#[$reg_attr])*
+                $vis fn $reg(&self) -> $crate::mmio::Register<$ty> {
+                    unsafe { $crate::mmio::Register::new(self.base.add($offset).cast::<$ty>()) }
+                }
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(transparent)]
+    struct ControlReg(u32);
+
+    impl ControlReg {
+        fn set_enabled(&mut self) {
+            self.0 |= 1;
+        }
+    }
+
+    crate::register_block! {
+        struct TestRegs {
+            0x00 => id: u16,
+            0x02 => version: u8,
+            0x04 => control: ControlReg,
+            0x0c => count: u32
+        }
+    }
+
+    #[test]
+    fn test_register_block_offsets() {
+        let mut memory = [0u32; 4];
+        let regs = unsafe { TestRegs::new(memory.as_mut_ptr().cast::<u8>()) };
+        regs.id().write(0x1234);
+        regs.version().write(0x56);
+        regs.control().write(ControlReg(0x10));
+        regs.count().write(0xdeadbeef);
+        assert_eq!(memory, [0x0056_1234, 0x10, 0, 0xdeadbeef]);
+        assert_eq!(regs.count().read(), 0xdeadbeef);
+        assert_eq!(TestRegs::SIZE, 16);
+    }
+
+    #[test]
+    fn test_register_modify() {
+        let mut memory = [0u32; 4];
+        let regs = unsafe { TestRegs::new(memory.as_mut_ptr().cast::<u8>()) };
+        regs.control().write(ControlReg(0x10));
+        regs.control().modify(|control| control.set_enabled());
+        assert_eq!(regs.control().read().0, 0x11);
+        assert_eq!(memory[1], 0x11);
+    }
+
+    #[test]
+    fn test_check_layout() {
+        let result = std::panic::catch_unwind(|| check_layout(&[(0, 4, 4), (2, 2, 2)]));
+        assert!(result.is_err());
+        let result = std::panic::catch_unwind(|| check_layout(&[(0, 1, 1), (1, 2, 2)]));
+        assert!(result.is_err());
+        check_layout(&[(0, 2, 2), (2, 1, 1), (8, 4, 4)]);
+        assert_eq!(block_size(&[(0, 2, 2), (8, 4, 4)]), 12);
+        assert_eq!(block_size(&[]), 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! The PS/2 mouse, plugged into the PS/2 controller's second port
+//!
+//! Once it's set up, the mouse sends a 3 byte packet whenever it moves or a
+//! button is pressed or let go of, with an interrupt on IRQ 12 for each byte.
+//! The first byte has the buttons, the signs of the movements and a bit
+//! that's always set, which is used to find the start of a packet again if
+//! a byte is lost. USB mice emulated as PS/2 ones by the firmware work the same
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/PS/2_Mouse>
+//! * The OSDev wiki <https://wiki.osdev.org/%228042%22_PS/2_Controller>
+
+use crate::ps2_controller::{self, Source};
+use crate::instructions::interrupts::without_interrupts;
+use num::{Integer, BitState};
+
+/// Tells the controller to enable its second port
+const ENABLE_SECOND_PORT: u8 = 0xa8;
+/// Tells the controller to send the next byte written to the data port to the second port
+const WRITE_SECOND_PORT: u8 = 0xd4;
+/// Set in the configuration to have the controller interrupt for the second port's bytes
+const CONFIG_SECOND_PORT_INTERRUPT_BIT: u8 = 1 << 1;
+/// Set in the configuration while the second port's clock is disabled
+const CONFIG_SECOND_PORT_CLOCK_DISABLED_BIT: u8 = 1 << 5;
+/// The mouse acknowledged the last command
+const ACK: u8 = 0xfa;
+/// The mouse didn't get the last command and wants it sent again
+const RESEND: u8 = 0xfe;
+/// Tells the mouse to go back to its default rate and resolution
+const SET_DEFAULTS: u8 = 0xf6;
+/// Tells the mouse to start sending packets
+const ENABLE_DATA_REPORTING: u8 = 0xf4;
+/// The number of times a command is resent before giving up on it
+const MAX_RESENDS: usize = 3;
+
+/// Always set in the first byte of a packet
+const ALWAYS_SET_BIT: usize = 3;
+const LEFT_BUTTON_BIT: usize = 0;
+const RIGHT_BUTTON_BIT: usize = 1;
+const MIDDLE_BUTTON_BIT: usize = 2;
+const X_SIGN_BIT: usize = 4;
+const Y_SIGN_BIT: usize = 5;
+const X_OVERFLOW_BIT: usize = 6;
+const Y_OVERFLOW_BIT: usize = 7;
+
+/// Enables the controller's second port and tells the mouse to start sending packets
+///
+/// IRQ 12 has to be unmasked afterwards for the packets to be received
+pub fn init() -> Result<(), &'static str> {
+    without_interrupts(|| {
+        ps2_controller::flush_output();
+        ps2_controller::command(ENABLE_SECOND_PORT);
+        let config = ps2_controller::read_config()?;
+        if config & CONFIG_SECOND_PORT_CLOCK_DISABLED_BIT != 0 {
+            return Err("The PS/2 controller has no second port");
+        }
+        ps2_controller::write_config(config | CONFIG_SECOND_PORT_INTERRUPT_BIT);
+        send_to_mouse(SET_DEFAULTS)?;
+        send_to_mouse(ENABLE_DATA_REPORTING)
+    })
+}
+
+/// The buttons held down when a packet was sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool
+}
+
+/// A movement of the mouse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MousePacket {
+    pub dx: i16,
+    /// Positive downwards, like the screen's y, unlike the mouse's own
+    pub dy: i16,
+    pub buttons: MouseButtons
+}
+
+/// How the mouse's movements are scaled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    /// Movements are left as they are
+    Linear,
+    /// Fast movements are doubled, but slow ones are left about the same,
+    /// following the table of the mouse's own 2:1 scaling
+    Accelerated
+}
+
+impl Scaling {
+    fn apply(&self, d: i16) -> i16 {
+        match self {
+            Scaling::Linear => d,
+            Scaling::Accelerated => {
+                let scaled = match d.abs() {
+                    0 => 0,
+                    1 | 2 => 1,
+                    3 => 3,
+                    4 => 6,
+                    5 => 9,
+                    d => d * 2
+                };
+                if d < 0 { -scaled } else { scaled }
+            }
+        }
+    }
+}
+
+/// Puts the bytes from the mouse together into packets
+pub struct Mouse {
+    packet: [u8; 3],
+    /// The number of bytes of the packet received so far
+    len: usize,
+    scaling: Scaling
+}
+
+impl Mouse {
+    pub const fn new() -> Self {
+        Self { packet: [0; 3], len: 0, scaling: Scaling::Linear }
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    /// Handles a byte read from the data port, returning the packet
+    /// once all of it has been received
+    pub fn process_byte(&mut self, byte: u8) -> Option<MousePacket> {
+        // Dropped until a byte that can start a packet comes,
+        // so a lost byte doesn't throw every packet after it off
+        if self.len == 0 && byte.get_bit(ALWAYS_SET_BIT) == BitState::Unset {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet.len() {
+            return None;
+        }
+        self.len = 0;
+        let [flags, x, y] = self.packet;
+        // The movement is too big to be told, so it's better left out
+        if flags.get_bit(X_OVERFLOW_BIT) == BitState::Set || flags.get_bit(Y_OVERFLOW_BIT) == BitState::Set {
+            return None;
+        }
+        let dx = to_movement(x, flags.get_bit(X_SIGN_BIT));
+        let dy = to_movement(y, flags.get_bit(Y_SIGN_BIT));
+        Some(MousePacket {
+            dx: self.scaling.apply(dx),
+            dy: -self.scaling.apply(dy),
+            buttons: MouseButtons {
+                left: flags.get_bit(LEFT_BUTTON_BIT) == BitState::Set,
+                right: flags.get_bit(RIGHT_BUTTON_BIT) == BitState::Set,
+                middle: flags.get_bit(MIDDLE_BUTTON_BIT) == BitState::Set
+            }
+        })
+    }
+}
+
+/// A movement from its low 8 bits and its sign bit, which together make a 9 bit number
+fn to_movement(low: u8, sign: BitState) -> i16 {
+    match sign {
+        BitState::Set => low as i16 - 0x100,
+        BitState::Unset => low as i16
+    }
+}
+
+/// Writes `byte` to the mouse and waits for it to be acknowledged
+fn send_to_mouse(byte: u8) -> Result<(), &'static str> {
+    for _ in 0..=MAX_RESENDS {
+        ps2_controller::command(WRITE_SECOND_PORT);
+        ps2_controller::write_data(byte);
+        match ps2_controller::read_data(Source::SecondPort) {
+            Some(ACK) => return Ok(()),
+            Some(RESEND) => continue,
+            Some(_) => return Err("The mouse didn't acknowledge a command"),
+            None => return Err("The mouse didn't respond")
+        }
+    }
+    Err("The mouse kept asking for a command to be resent")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(mouse: &mut Mouse, bytes: &[u8]) -> Option<MousePacket> {
+        bytes.iter().fold(None, |_, byte| mouse.process_byte(*byte))
+    }
+
+    #[test]
+    fn test_packet_decoding() {
+        let mut mouse = Mouse::new();
+        // Left button, moved right by 5 and up by 3
+        assert_eq!(feed(&mut mouse, &[0b0000_1001, 5, 3]), Some(MousePacket {
+            dx: 5,
+            dy: -3,
+            buttons: MouseButtons { left: true, right: false, middle: false }
+        }));
+        // Both signs set, moved left by 2 and down by 256
+        assert_eq!(feed(&mut mouse, &[0b0011_1110, 0xfe, 0]), Some(MousePacket {
+            dx: -2,
+            dy: 256,
+            buttons: MouseButtons { left: false, right: true, middle: true }
+        }));
+    }
+
+    #[test]
+    fn test_resync_and_overflow() {
+        let mut mouse = Mouse::new();
+        // A byte without the always set bit can't start a packet
+        assert_eq!(mouse.process_byte(0x05), None);
+        assert_eq!(mouse.len, 0);
+        assert_eq!(feed(&mut mouse, &[0b0000_1000, 1, 0]).map(|packet| packet.dx), Some(1));
+        // Overflowed packets are dropped, but the next one is still read
+        assert_eq!(feed(&mut mouse, &[0b0100_1000, 0xff, 0]), None);
+        assert_eq!(feed(&mut mouse, &[0b0000_1000, 0, 1]).map(|packet| packet.dy), Some(-1));
+    }
+
+    #[test]
+    fn test_scaling() {
+        let mut mouse = Mouse::new();
+        mouse.set_scaling(Scaling::Accelerated);
+        let scaled: [i16; 7] = core::array::from_fn(|d| Scaling::Accelerated.apply(d as i16));
+        assert_eq!(scaled, [0, 1, 1, 3, 6, 9, 12]);
+        assert_eq!(Scaling::Accelerated.apply(-4), -6);
+        assert_eq!(Scaling::Linear.apply(-4), -4);
+        assert_eq!(feed(&mut mouse, &[0b0001_1000, 0xfb, 0]).map(|packet| packet.dx), Some(-9));
+    }
+}
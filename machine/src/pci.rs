@@ -0,0 +1,679 @@
+//! Access to the devices on the PCI bus
+//!
+//! The buses are scanned once, the first time a device is looked for,
+//! and the devices found are kept for every later search.
+//!
+//! Configuration spaces are accessed through memory when the ACPI MCFG table
+//! says where they're mapped, which is the only way to reach the devices in
+//! segment groups other than 0 and the extended configuration space of
+//! PCI Express devices. Otherwise, they're accessed through the CF8/CFC ports.
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/PCI>
+//! * The OSDev wiki <https://wiki.osdev.org/PCI_Express>
+
+use crate::port::{Port, PortReadWrite};
+use crate::interrupts::IRQ;
+use crate::instructions::interrupts::without_interrupts;
+use crate::acpi::{detect_rsdp, SDTTable, RSDP};
+use crate::mmio::Register;
+use crate::{apic, serial_println};
+use num::{Integer, BitState};
+
+/// The most devices that are kept after a scan. Any more are left out,
+/// with a warning on the serial port
+pub const MAX_DEVICES: usize = 64;
+/// The most memory mapped configuration space regions that are used.
+/// Any more in the MCFG are left out
+const MAX_ECAM_REGIONS: usize = 8;
+/// The size of a function's configuration space, when it's memory mapped
+const ECAM_CONFIG_SPACE_SIZE: u32 = 0x1000;
+/// The size of a function's configuration space through the ports
+const LEGACY_CONFIG_SPACE_SIZE: u32 = 0x100;
+
+/// The devices found on the first scan
+static mut DEVICES: Option<PCIDeviceList> = None;
+
+/// The memory mapped configuration space regions, read from the MCFG
+/// the first time a configuration space is accessed
+static mut ECAM_REGIONS: Option<EcamRegions> = None;
+
+/// Returns the devices on the PCI bus
+///
+/// The buses are only scanned on the first call
+pub fn devices() -> &'static [PCIDevice] {
+    let devices = unsafe {
+        DEVICES.get_or_insert_with(|| {
+            let mut devices = PCIDeviceList::new();
+            let mut left_out = 0;
+            enumerate(
+                ecam_regions().buses(),
+                |device| device.is_valid(),
+                |device| device.has_multiple_funcs(),
+                |device| if devices.push(device).is_err() {
+                    left_out += 1;
+                }
+            );
+            if left_out > 0 {
+                serial_println!("Left out {} PCI devices, since only {} are kept", left_out, MAX_DEVICES);
+            }
+            devices
+        })
+    };
+    devices.as_slice()
+}
+
+/// Returns the first device with the class code `classcode` and subclass `subclass`
+pub fn find_device(classcode: u8, subclass: u8) -> Option<PCIDevice> {
+    devices().iter()
+        .find(|device| device.classcode() == classcode && device.subclass() == subclass)
+        .copied()
+}
+
+/// Calls `found` with every device on `buses`, which are (segment, bus) pairs
+///
+/// Functions 1 to 7 of a device are only checked if function 0 exists
+/// and says the device has multiple functions.
+/// The checks are passed in so the scan can be tested without a PCI bus
+fn enumerate(
+    buses: impl Iterator<Item = (u16, u32)>,
+    is_valid: impl Fn(PCIDevice) -> bool,
+    has_multiple_funcs: impl Fn(PCIDevice) -> bool,
+    mut found: impl FnMut(PCIDevice)
+) {
+    for (segment, bus) in buses {
+        for device in 0..32 {
+            let func0 = PCIDevice { segment, bus, device, func: 0 };
+            if !is_valid(func0) {
+                continue;
+            }
+            found(func0);
+            if !has_multiple_funcs(func0) {
+                continue;
+            }
+            for func in 1..8 {
+                let pci_device = PCIDevice { segment, bus, device, func };
+                if is_valid(pci_device) {
+                    found(pci_device);
+                }
+            }
+        }
+    }
+}
+
+/// The devices found in a scan
+struct PCIDeviceList {
+    devices: [PCIDevice; MAX_DEVICES],
+    len: usize
+}
+
+impl PCIDeviceList {
+    fn new() -> Self {
+        Self {
+            devices: [PCIDevice { segment: 0, bus: 0, device: 0, func: 0 }; MAX_DEVICES],
+            len: 0
+        }
+    }
+
+    /// Adds `device` to the list, or returns an error if the list is full
+    fn push(&mut self, device: PCIDevice) -> Result<(), &'static str> {
+        if self.len == MAX_DEVICES {
+            return Err("The PCI device list is full");
+        }
+        self.devices[self.len] = device;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn as_slice(&self) -> &[PCIDevice] {
+        &self.devices[..self.len]
+    }
+}
+
+/// A device on the PCI bus
+///
+/// It is assumed that the device has a PCI configuration header of type 0x0
+///
+/// # References
+///
+/// * The OSDev wiki <https://wiki.osdev.org/PCI>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PCIDevice {
+    /// The PCI segment group the device's bus is in.
+    /// Only segment 0 can be reached without the MCFG
+    pub segment: u16,
+    pub bus: u32,
+    pub device: u32,
+    pub func: u32
+}
+
+impl PCIDevice {
+    // Register offsets for values in the PCI configuration header
+    const DEVICE_AND_VENDOR_ID_OFFSET: u32 = 0x0;
+    const STATUS_AND_COMMAND_OFFSET: u32 = 0x04;
+    const CLASSCODE_AND_SUBCLASS_OFFSET: u32 = 0x8;
+    const HEADER_TYPE_OFFSET: u32 = 0xc;
+    const BAR0_OFFSET: u32 = 0x10;
+    const BAR1_OFFSET: u32 = 0x14;
+    const CAPABILITIES_POINTER_OFFSET: u32 = 0x34;
+    const INTERRUPT_PIN_LINE_OFFSET: u32 = 0x3c;
+    /// The bit in the status register that's set if the device has a list of capabilities
+    const STATUS_CAPABILITIES_LIST_BIT: usize = 4;
+    /// The bit in the command register that stops the device from asserting its interrupt pin
+    const COMMAND_INTERRUPT_DISABLE_BIT: usize = 10;
+    const MSI_CAPABILITY_ID: u8 = 0x05;
+    /// The most capabilities followed in the list, in case it loops
+    const MAX_CAPABILITIES: usize = 48;
+
+    /// This port is written to specify which configuration header of a PCI device
+    /// should be read from the `DATA_PORT`
+    const ADDR_PORT: u16 = 0xcf8;
+    /// The data this port outputs is the data from the configuration header previously
+    /// specified by writing to the `ADDR_PORT`
+    const DATA_PORT: u16 = 0xcfc;
+
+    /// Checks if the device specified by the bus, device and func numbers
+    /// is a valid device on the PCI
+    ///
+    /// According to the OSDev wiki <https://wiki.osdev.org/PCI>, no valid device
+    /// can have a vendor id of 0xffff. It's what's read when nothing answers
+    pub fn is_valid(&self) -> bool {
+        self.vendor_id() != 0xffff
+    }
+
+    /// Returns the device's vendor id from the PCI configuration header
+    pub fn vendor_id(&self) -> u16 {
+        self.read_config(Self::DEVICE_AND_VENDOR_ID_OFFSET) as u16
+    }
+
+    fn read_classcode_subclass_reg(&self) -> (u8, u8) {
+        let val = self.read_config(Self::CLASSCODE_AND_SUBCLASS_OFFSET);
+        let classcode = (val >> 24) as u8;
+        let subclass = ((val >> 16) & 0xff) as u8;
+        (classcode, subclass)
+    }
+
+    pub fn header_type(&self) -> PCIHeaderType {
+        self.header_type_reg().header_type().unwrap()
+    }
+
+    /// Checks if the device implements functions other than function 0
+    ///
+    /// Only meaningful for function 0
+    pub fn has_multiple_funcs(&self) -> bool {
+        self.header_type_reg().has_multiple_funcs()
+    }
+
+    fn header_type_reg(&self) -> PCIHeaderTypeReg {
+        let val = self.read_config(Self::HEADER_TYPE_OFFSET);
+        PCIHeaderTypeReg(((val >> 16) & 0xff) as u8)
+    }
+
+    pub fn bar0(&self) -> PCIBaseAddrReg {
+        assert_eq!(self.header_type(), PCIHeaderType::Standard);
+        let val1 = self.read_config(Self::BAR0_OFFSET);
+        let val2 = self.read_config(Self::BAR1_OFFSET);
+        let bar = PCIBaseAddrReg::try_from((val1, val2)).unwrap();
+        assert_eq!(bar.kind(), PCIBaseAddrKind::Memory);
+        bar
+    }
+
+    /// Returns the base address register at `idx`, in the range 0..6,
+    /// which can map either memory or I/O ports
+    pub fn bar(&self, idx: u32) -> PCIBaseAddrReg {
+        assert_eq!(self.header_type(), PCIHeaderType::Standard);
+        assert!(idx < 6, "A standard header only has 6 BARs");
+        let val1 = self.read_config(Self::BAR0_OFFSET + idx * 4);
+        // The upper half of a 64 bit memory BAR is in the BAR after it
+        let val2 = match idx {
+            5 => 0,
+            idx => self.read_config(Self::BAR0_OFFSET + (idx + 1) * 4)
+        };
+        PCIBaseAddrReg::try_from((val1, val2)).unwrap()
+    }
+
+    /*fn size_of_addr_space_needed(&self) -> u32 {
+        assert_eq!(self.header_type(), PCIHeaderType::Standard);
+        let mut addr_port: Port<u32> = Port::new(Self::ADDR_PORT);
+        let mut data_port: Port<u32> = Port::new(Self::DATA_PORT);
+        let addr: u32 = self.reg_addr(Self::BAR0_OFFSET);
+        addr_port.write(addr);
+        let baddr_reg_val = data_port.read();
+        data_port.write(u32::MAX);
+        let new_val = data_port.read();
+        let amount_of_mem_needed = (!new_val) + 1;
+        data_port.write(baddr_reg_val);
+        amount_of_mem_needed
+    }*/
+
+    pub fn interrupt_pin(&self) -> u8 {
+        assert_eq!(self.header_type(), PCIHeaderType::Standard);
+        (self.read_config(Self::INTERRUPT_PIN_LINE_OFFSET) >> 8) as u8
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        assert_eq!(self.header_type(), PCIHeaderType::Standard);
+        self.read_config(Self::INTERRUPT_PIN_LINE_OFFSET) as u8
+    }
+
+    pub fn set_interrupt_line(&mut self, line: IRQ) {
+        assert_eq!(self.header_type(), PCIHeaderType::Standard);
+        let mut val = self.read_config(Self::INTERRUPT_PIN_LINE_OFFSET);
+        // The interrupt line is the lowest byte. The byte above it is the
+        // read only interrupt pin
+        val.set_bits(0..8, line.as_u8().as_u32());
+        self.write_config(Self::INTERRUPT_PIN_LINE_OFFSET, val);
+    }
+
+    /// Delivers the device's interrupts on `irq`'s vector
+    ///
+    /// With the APICs in use, they route the interrupts. Otherwise, the
+    /// interrupt line is set to `irq`
+    pub fn route_interrupt(&mut self, irq: IRQ) {
+        if apic::is_enabled() {
+            match apic::route_pci_interrupt(self, irq) {
+                Ok(()) => return,
+                Err(msg) => serial_println!("The device's interrupts can't be routed: {}", msg)
+            }
+        }
+        self.set_interrupt_line(irq);
+    }
+
+    /// Makes the device signal its interrupts by writing `data` to `addr`,
+    /// instead of with its interrupt pin
+    ///
+    /// Returns an error if the device doesn't support MSI, or can't write to `addr`
+    pub fn enable_msi(&mut self, addr: u64, data: u16) -> Result<(), &'static str> {
+        let cap = self.find_capability(Self::MSI_CAPABILITY_ID).ok_or("The device doesn't support MSI")?;
+        let header = self.read_config(cap);
+        let mut control = (header >> 16) as u16;
+        let addr_64bit = control.get_bit(7) == BitState::Set;
+        if addr >> 32 != 0 && !addr_64bit {
+            return Err("The device can only send MSIs to 32 bit addresses");
+        }
+        self.write_config(cap + 4, addr as u32);
+        let data_offset = if addr_64bit {
+            self.write_config(cap + 8, (addr >> 32) as u32);
+            cap + 12
+        } else {
+            cap + 8
+        };
+        // The data is the lower half of its dword
+        let data_reg = self.read_config(data_offset);
+        self.write_config(data_offset, (data_reg & 0xffff_0000) | data as u32);
+        // A single message, with MSI enabled
+        control.set_bits(4..7, 0);
+        control.set_bit(0);
+        self.write_config(cap, (header & 0xffff) | (control as u32) << 16);
+        let mut command = self.command();
+        command.set_bit(Self::COMMAND_INTERRUPT_DISABLE_BIT);
+        self.set_command(command);
+        Ok(())
+    }
+
+    /// The offset in the configuration header of the capability with `id`
+    fn find_capability(&self, id: u8) -> Option<u32> {
+        if self.status().get_bit(Self::STATUS_CAPABILITIES_LIST_BIT) == BitState::Unset {
+            return None;
+        }
+        let mut offset = self.read_config(Self::CAPABILITIES_POINTER_OFFSET) & 0xfc;
+        for _ in 0..Self::MAX_CAPABILITIES {
+            if offset == 0 {
+                return None;
+            }
+            // The capability's ID is in the first byte and the offset of the next one in the second
+            let header = self.read_config(offset);
+            if header as u8 == id {
+                return Some(offset);
+            }
+            offset = (header >> 8) & 0xfc;
+        }
+        None
+    }
+
+    pub fn status(&self) -> u16 {
+        (self.read_config(Self::STATUS_AND_COMMAND_OFFSET) >> 16) as u16
+    }
+
+    pub fn command(&self) -> u16 {
+        self.read_config(Self::STATUS_AND_COMMAND_OFFSET) as u16
+    }
+
+    pub fn set_command(&mut self, val: u16) {
+        // The status bits in the upper half are cleared by writing 1s to them,
+        // so writing 0s there leaves them as they are
+        self.write_config(Self::STATUS_AND_COMMAND_OFFSET, val.into());
+    }
+
+    pub fn enable_memory_space_accesses(&mut self) {
+        let mut val = self.command();
+
+        // Added for experimenting
+        val.set_bit(0);
+        val.set_bit(2);
+        val.set_bit(3);
+        val.set_bit(4);
+        val.set_bit(8);
+        //
+
+        val.set_bit(1);
+        self.set_command(val);
+    }
+
+    /// Returns the device's class code read from the PCI configuration header
+    pub fn classcode(&self) -> u8 {
+        self.read_classcode_subclass_reg().0
+    }
+
+    pub fn subclass(&self) -> u8 {
+        self.read_classcode_subclass_reg().1
+    }
+
+    /// Reads the dword at `reg_offset` in the device's configuration space,
+    /// which can be past the 256 bytes of the header
+    ///
+    /// Returns None if the offset is out of the configuration space, or if it's
+    /// in the extended configuration space and that isn't memory mapped
+    pub fn read_extended_config(&self, reg_offset: u32) -> Option<u32> {
+        if reg_offset >= self.config_space_size() {
+            return None;
+        }
+        Some(self.read_config(reg_offset))
+    }
+
+    /// The number of bytes of the configuration space that can be accessed
+    pub fn config_space_size(&self) -> u32 {
+        match ecam_regions().find(self.segment, self.bus) {
+            Some(_) => ECAM_CONFIG_SPACE_SIZE,
+            None => LEGACY_CONFIG_SPACE_SIZE
+        }
+    }
+
+    /// Returns the address to be written into the `ADDR_PORT` to access
+    /// the data in the configuration header at offset `reg_offset`
+    fn reg_addr(&self, reg_offset: u32) -> u32 {
+        self.bus << 16 
+            | self.device << 11 | self.func << 8
+            | (reg_offset & 0xfc) | 0x80000000u32
+    }
+
+    /// Reads the dword at `reg_offset` in the configuration header
+    ///
+    /// The address and data ports are shared by every device, so an interrupt
+    /// handler that accesses the configuration space between the write to
+    /// the address port and the access to the data port would redirect the access
+    /// to another register. Interrupts are disabled for the whole access to prevent that.
+    /// A memory mapped access is a single read, so it needs no such protection
+    ///
+    /// Reads all 1s, like a missing device, if the register can't be reached
+    fn read_config(&self, reg_offset: u32) -> u32 {
+        if let Some(register) = self.ecam_register(reg_offset) {
+            return register.read();
+        }
+        if self.segment != 0 || reg_offset >= LEGACY_CONFIG_SPACE_SIZE {
+            return u32::MAX;
+        }
+        without_interrupts(|| {
+            let (mut addr_port, data_port) = self.ports();
+            addr_port.write(self.reg_addr(reg_offset));
+            data_port.read()
+        })
+    }
+
+    /// Writes `val` into the dword at `reg_offset` in the configuration header
+    ///
+    /// Interrupts are disabled for the whole access, for the same reason as in `read_config`.
+    /// Nothing is written if the register can't be reached
+    fn write_config(&mut self, reg_offset: u32, val: u32) {
+        if let Some(register) = self.ecam_register(reg_offset) {
+            register.write(val);
+            return;
+        }
+        if self.segment != 0 || reg_offset >= LEGACY_CONFIG_SPACE_SIZE {
+            return;
+        }
+        without_interrupts(|| {
+            let (mut addr_port, mut data_port) = self.ports();
+            addr_port.write(self.reg_addr(reg_offset));
+            data_port.write(val);
+        })
+    }
+
+    /// The memory mapped register at `reg_offset`, if the device's bus is memory mapped
+    fn ecam_register(&self, reg_offset: u32) -> Option<Register<u32>> {
+        let addr = ecam_regions().find(self.segment, self.bus)?
+            .config_addr(self.bus, self.device, self.func, reg_offset)?;
+        // Identity mapped, like every other device's memory
+        Some(unsafe { Register::new(addr as *mut u32) })
+    }
+
+    fn ports(&self) -> (Port<u32>, Port<u32>) {
+        let addr_port: Port<u32> = Port::new(Self::ADDR_PORT);
+        let data_port: Port<u32> = Port::new(Self::DATA_PORT);
+        (addr_port, data_port)
+    }
+}
+
+#[repr(transparent)]
+struct PCIHeaderTypeReg(u8);
+
+impl PCIHeaderTypeReg {
+    fn has_multiple_funcs(&self) -> bool {
+        self.0 >> 7 == 1
+    }
+    fn header_type(&self) -> Result<PCIHeaderType, &'static str> {
+        match self.0 & 0b01111111 {
+            0x0 => Ok(PCIHeaderType::Standard),
+            0x1 => Ok(PCIHeaderType::PCIToPCIBridge),
+            0x2 => Ok(PCIHeaderType::CardBusBridge),
+            _ => Err("This header type register value has an unexpected header type number")
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
+pub enum PCIHeaderType {
+    Standard = 0x0,
+    PCIToPCIBridge = 0x1,
+    CardBusBridge = 0x2
+}
+
+/// The memory/port address used by a PCI device for mapping
+pub enum PCIBaseAddrReg {
+    Memory(MemBAR),
+    IO(IOBAR)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PCIBaseAddrKind {
+    Memory,
+    IO
+}
+
+impl PCIBaseAddrReg {
+    pub fn addr(&self) -> u64 {
+        match self {
+            Self::Memory(mbar) => mbar.addr(),
+            Self::IO(iobar) => iobar.addr()
+        }
+    }
+
+    pub fn kind(&self) -> PCIBaseAddrKind {
+        match self {
+            Self::Memory(_) => PCIBaseAddrKind::Memory,
+            Self::IO(_) => PCIBaseAddrKind::IO
+        }
+    }
+}
+
+impl TryFrom<(u32, u32)> for PCIBaseAddrReg {
+    type Error = &'static str;
+    fn try_from(val: (u32, u32)) -> Result<PCIBaseAddrReg, Self::Error> {
+        match val.0 & 0x1 {
+            0 => Ok(Self::Memory(MemBAR(val.0, val.1))),
+            1 => Ok(Self::IO(IOBAR(val.0))),
+            _ => Err("Expected either a 0 or 1 in bit 0")
+        }
+    }
+}
+
+pub struct MemBAR(u32, u32);
+
+impl MemBAR {
+    /// Returns the 16 byte aligned base address
+    pub fn addr(&self) -> u64 {
+        match self.0.get_bits(1..3) {
+            // 32 bit address
+            0x0 => self.0.align_down(16).as_u64(),
+            // 64 bit address
+            0x2 => self.0.align_down(16).as_u64() + ((self.1 & 0xffffffff).as_u64() << 32),
+            _ => panic!("Unexpected memory space BAR type")
+        }
+    }
+}
+
+pub struct IOBAR(u32);
+
+impl IOBAR {
+    /// Returns the 4 byte aligned base address
+    pub fn addr(&self) -> u64 {
+        self.0.align_down(4).as_u64()
+    }
+}
+
+/// The memory mapped configuration space of the buses `start_bus..=end_bus`
+/// in segment group `segment`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EcamRegion {
+    /// The address bus 0's configuration space would be at
+    base_addr: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8
+}
+
+impl EcamRegion {
+    fn contains(&self, segment: u16, bus: u32) -> bool {
+        segment == self.segment && bus >= self.start_bus.into() && bus <= self.end_bus.into()
+    }
+
+    /// The address of the dword at `reg_offset` in a function's configuration space,
+    /// or None if the function or offset is out of range
+    fn config_addr(&self, bus: u32, device: u32, func: u32, reg_offset: u32) -> Option<u64> {
+        if bus < self.start_bus.into() || bus > self.end_bus.into() || device >= 32 || func >= 8
+            || reg_offset >= ECAM_CONFIG_SPACE_SIZE {
+            return None;
+        }
+        let offset = (bus as u64) << 20 | (device as u64) << 15 | (func as u64) << 12 | (reg_offset & !0b11) as u64;
+        Some(self.base_addr + offset)
+    }
+}
+
+/// The memory mapped configuration space regions
+struct EcamRegions {
+    regions: [Option<EcamRegion>; MAX_ECAM_REGIONS]
+}
+
+impl EcamRegions {
+    const fn empty() -> Self {
+        Self { regions: [None; MAX_ECAM_REGIONS] }
+    }
+
+    /// Reads the regions from the MCFG, or returns no regions if there is no valid MCFG
+    unsafe fn from_acpi() -> Self {
+        let mut ecam_regions = Self::empty();
+        let rsdp = match detect_rsdp() {
+            Some(rsdp) if rsdp != RSDP::None && rsdp.is_valid() => rsdp,
+            _ => return ecam_regions
+        };
+        let rsdt = &*rsdp.rsdt_ptr();
+        if !rsdt.is_valid() {
+            return ecam_regions;
+        }
+        let mcfg = match rsdt.find_mcfg() {
+            Some(mcfg) if mcfg.is_valid() => mcfg,
+            _ => return ecam_regions
+        };
+        for (slot, entry) in ecam_regions.regions.iter_mut().zip(mcfg.entries()) {
+            *slot = Some(EcamRegion {
+                base_addr: entry.base_addr(),
+                segment: entry.segment(),
+                start_bus: entry.start_bus(),
+                end_bus: entry.end_bus()
+            });
+        }
+        ecam_regions
+    }
+
+    fn find(&self, segment: u16, bus: u32) -> Option<EcamRegion> {
+        self.regions.iter().flatten().find(|region| region.contains(segment, bus)).copied()
+    }
+
+    /// The (segment, bus) pairs of the buses to scan: those in the regions,
+    /// or every bus in segment 0 if there aren't any regions
+    fn buses(&self) -> impl Iterator<Item = (u16, u32)> + '_ {
+        let no_regions = self.regions.iter().all(|region| region.is_none());
+        let legacy_buses = (0..=255u32).filter(move |_| no_regions).map(|bus| (0u16, bus));
+        let ecam_buses = self.regions.iter().flatten()
+            .flat_map(|region| (region.start_bus..=region.end_bus).map(move |bus| (region.segment, u32::from(bus))));
+        legacy_buses.chain(ecam_buses)
+    }
+}
+
+/// The memory mapped configuration space regions, which are
+/// read from the MCFG on the first call
+fn ecam_regions() -> &'static EcamRegions {
+    unsafe { ECAM_REGIONS.get_or_insert_with(|| EcamRegions::from_acpi()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_list_is_limited() {
+        let mut devices = PCIDeviceList::new();
+        for device in 0..MAX_DEVICES {
+            assert_eq!(devices.push(PCIDevice { segment: 0, bus: 0, device: device as u32, func: 0 }), Ok(()));
+        }
+        assert!(devices.push(PCIDevice { segment: 0, bus: 1, device: 0, func: 0 }).is_err());
+        assert_eq!(devices.as_slice().len(), MAX_DEVICES);
+    }
+
+    #[test]
+    fn test_enumerate_honors_the_multiple_funcs_bit() {
+        // Device 0:1 has functions 0 and 2, device 0:2 only has function 0
+        // but answers on every function, like some single function devices do
+        let exists = |d: PCIDevice| d.bus == 0 && (d.device == 1 && (d.func == 0 || d.func == 2) || d.device == 2);
+        let multi = |d: PCIDevice| d.device == 1;
+        let mut found = std::vec::Vec::new();
+        enumerate(EcamRegions::empty().buses(), exists, multi, |d| found.push((d.bus, d.device, d.func)));
+        assert_eq!(found, [(0, 1, 0), (0, 1, 2), (0, 2, 0)]);
+    }
+
+    #[test]
+    fn test_ecam_config_addr() {
+        let region = EcamRegion { base_addr: 0xb000_0000, segment: 1, start_bus: 2, end_bus: 3 };
+        assert!(region.contains(1, 3));
+        assert!(!region.contains(0, 3));
+        assert!(!region.contains(1, 4));
+        assert_eq!(region.config_addr(2, 1, 2, 0x104), Some(0xb000_0000 + (2 << 20) + (1 << 15) + (2 << 12) + 0x104));
+        // Unaligned offsets are rounded down to the dword, like with the ports
+        assert_eq!(region.config_addr(2, 0, 0, 0x13), Some(0xb000_0000 + (2 << 20) + 0x10));
+        assert_eq!(region.config_addr(1, 0, 0, 0), None);
+        assert_eq!(region.config_addr(2, 32, 0, 0), None);
+        assert_eq!(region.config_addr(2, 0, 0, 0x1000), None);
+    }
+
+    #[test]
+    fn test_buses_come_from_the_regions() {
+        let mut regions = EcamRegions::empty();
+        regions.regions[0] = Some(EcamRegion { base_addr: 0, segment: 0, start_bus: 0, end_bus: 1 });
+        regions.regions[1] = Some(EcamRegion { base_addr: 0, segment: 1, start_bus: 4, end_bus: 4 });
+        let buses: std::vec::Vec<_> = regions.buses().collect();
+        assert_eq!(buses, [(0, 0), (0, 1), (1, 4)]);
+        assert_eq!(EcamRegions::empty().buses().count(), 256);
+    }
+}
@@ -1,7 +1,14 @@
 //! Abstractions for dealing with I/O ports
+//!
+//! With the `port_audit` feature, every read and write is recorded in the trace,
+//! along with where it was made from
 
 use core::arch::asm;
 use core::marker::PhantomData;
+#[cfg(feature = "port_audit")]
+use core::panic::Location;
+#[cfg(feature = "port_audit")]
+use crate::trace::{self, TraceEvent};
 
 /// An I/O port
 #[derive(Clone, Copy)]
@@ -18,23 +25,31 @@ impl<T> Port<T> {
 pub trait PortReadWrite {
     type T;
     /// Reads the value from the I/O port
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn read(&self) -> Self::T;
 
     /// Writes a value to a port
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn write(&mut self, value: Self::T);
 }
 
 impl PortReadWrite for Port<u8> {
     type T = u8;
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn read(&self) -> u8 {
         let value: u8;
         unsafe {
             asm!("in al, dx", out("al") value, in("dx") self.0, options(nomem, nostack, preserves_flags));
         }
+        #[cfg(feature = "port_audit")]
+        trace::record(TraceEvent::PortRead { port: self.0, value: value as u32 }, Location::caller());
         value
     }
 
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn write(&mut self, value: u8) {
+        #[cfg(feature = "port_audit")]
+        trace::record(TraceEvent::PortWrite { port: self.0, value: value as u32 }, Location::caller());
         unsafe {
             asm!("out dx, al", in("dx") self.0, in("al") value, options(nomem, nostack, preserves_flags));
         }
@@ -43,15 +58,21 @@ impl PortReadWrite for Port<u8> {
 
 impl PortReadWrite for Port<u16> {
     type T = u16;
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn read(&self) -> u16 {
         let value: u16;
         unsafe {
             asm!("in ax, dx", out("ax") value, in("dx") self.0, options(nomem, nostack, preserves_flags));
         }
+        #[cfg(feature = "port_audit")]
+        trace::record(TraceEvent::PortRead { port: self.0, value: value as u32 }, Location::caller());
         value
     }
 
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn write(&mut self, value: u16) {
+        #[cfg(feature = "port_audit")]
+        trace::record(TraceEvent::PortWrite { port: self.0, value: value as u32 }, Location::caller());
         unsafe {
             asm!("out dx, ax", in("dx") self.0, in("ax") value, options(nomem, nostack, preserves_flags));
         }
@@ -60,15 +81,21 @@ impl PortReadWrite for Port<u16> {
 
 impl PortReadWrite for Port<u32> {
     type T = u32;
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn read(&self) -> u32 {
         let value: u32;
         unsafe {
             asm!("in eax, dx", out("eax") value, in("dx") self.0, options(nomem, nostack, preserves_flags));
         }
+        #[cfg(feature = "port_audit")]
+        trace::record(TraceEvent::PortRead { port: self.0, value: value as u32 }, Location::caller());
         value
     }
 
+    #[cfg_attr(feature = "port_audit", track_caller)]
     fn write(&mut self, value: u32) {
+        #[cfg(feature = "port_audit")]
+        trace::record(TraceEvent::PortWrite { port: self.0, value: value as u32 }, Location::caller());
         unsafe {
             asm!("out dx, eax", in("dx") self.0, in("eax") value, options(nomem, nostack, preserves_flags));
         }
@@ -1,9 +1,57 @@
 use crate::port::{Port, PortReadWrite};
-use crate::acpi::{detect_rsdp, SDTTable, RSDP};
+use crate::acpi::{detect_rsdp, SDTTable, RSDP, FADT};
+use crate::uefi::{get_systable, runtime::ResetType};
+use crate::interrupts::IRQ;
+use crate::{DescriptorTablePointer, Addr};
+use core::arch::asm;
+use num::{Integer, BitState};
+use sync::mutex::Mutex;
+
+/// The keyboard controller's command port
+const KBC_COMMAND_PORT: u16 = 0x64;
+/// The keyboard controller command that pulses the CPU reset line
+const KBC_RESET_CMD: u8 = 0xfe;
+/// The bit in the PM1 control register that is set when the hardware is in ACPI mode
+const SCI_EN_BIT: usize = 0;
+/// The power button bit in the PM1 status and enable registers
+const PWRBTN_BIT: usize = 8;
+
+/// The number of shutdown hooks that can be registered
+pub const MAX_SHUTDOWN_HOOKS: usize = 8;
+
+/// The PM1 event register blocks, set once the power button event has been enabled
+static mut PM1_EVENT_BLOCKS: Option<PM1EventBlocks> = None;
+static SHUTDOWN_HOOKS: Mutex<ShutdownHooks<MAX_SHUTDOWN_HOOKS>> = Mutex::new(ShutdownHooks::new());
+
+/// Brings a subsystem to a stop before the computer is shut down or restarted
+pub type ShutdownHook = fn();
+
+/// Registers `hook` to be run before the computer is shut down or restarted
+///
+/// The hooks are run in the reverse of the order they were registered in,
+/// so a subsystem set up on top of another is stopped before it. They're
+/// run once, with interrupts disabled, and mustn't lock anything that the
+/// code asking for the shutdown could be holding
+pub fn on_shutdown(hook: ShutdownHook) -> Result<(), &'static str> {
+    SHUTDOWN_HOOKS.lock().register(hook)
+}
+
+/// Runs the shutdown hooks that haven't been run yet
+fn run_shutdown_hooks() {
+    crate::instructions::interrupts::without_interrupts(|| {
+        // Taken out of the lock, so a hook that registers another can't deadlock
+        let hooks = SHUTDOWN_HOOKS.lock().take();
+        for hook in hooks.iter().rev().flatten() {
+            hook();
+        }
+    });
+}
 
 
 /// Shuts down the computer
 ///
+/// The shutdown hooks are run once the sleep type has been found, right
+/// before the computer is put to sleep.
 /// If it's successful, the Ok(()) will never be returned
 /// An error is returned whenever anything expected isn't found, or anything
 /// goes wrong.
@@ -72,6 +120,8 @@ pub unsafe fn shutdown() -> Result<(), ()> {
         return Err(());
     }
     let (slp_typa, slp_typb) = slp_typ_opt.unwrap();
+    run_shutdown_hooks();
+    crate::cmos::record_clean_shutdown();
     let mut port: Port<u16> = Port::new(fadt.pm1a_ctrl_block() as u16);
     port.write(slp_typa as u16 | slp_en);
     if fadt.pm1b_ctrl_block() != 0 {
@@ -80,3 +130,257 @@ pub unsafe fn shutdown() -> Result<(), ()> {
     }
     Err(())
 }
+
+/// Restarts the computer
+///
+/// The shutdown hooks are run first.
+/// The firmware's ResetSystem runtime service is used when booted with UEFI.
+/// Otherwise, the keyboard controller is asked to pulse the CPU reset line.
+/// If the computer is still running after that, a triple fault is caused,
+/// which resets the processor on every machine.
+///
+/// # References
+///
+/// * <https://wiki.osdev.org/Reboot>
+/// * The UEFI spec, version 2.7, section 8.5.1
+pub fn reboot() -> ! {
+    run_shutdown_hooks();
+    crate::cmos::record_clean_shutdown();
+    if let Some(systable) = get_systable() {
+        systable.runtime_services().reset_system(ResetType::Cold);
+    }
+    let status_port: Port<u8> = Port::new(KBC_COMMAND_PORT);
+    let mut command_port: Port<u8> = Port::new(KBC_COMMAND_PORT);
+    // Waiting for the controller's input buffer to be empty before sending the command.
+    // Giving up after a while in case there is no keyboard controller
+    for _ in 0..0x10000 {
+        if status_port.read().get_bit(1) == BitState::Unset {
+            break;
+        }
+    }
+    command_port.write(KBC_RESET_CMD);
+    for _ in 0..0x100000 {
+        core::hint::spin_loop();
+    }
+    triple_fault()
+}
+
+/// Causes a triple fault by loading an empty IDT and raising an exception
+fn triple_fault() -> ! {
+    let empty_idt = DescriptorTablePointer { limit: 0, base: Addr::new(0) };
+    unsafe {
+        asm!("lidt [{}]", "int3", in(reg) &empty_idt, options(nostack));
+    }
+    loop {}
+}
+
+/// Enables the ACPI power button fixed event
+///
+/// Once enabled, presses of the power button raise the System Control Interrupt,
+/// on `IRQ::Acpi`, instead of being handled by the firmware, and `handle_sci`
+/// tells when the button has been pressed.
+/// The hardware is switched to ACPI mode if the firmware hasn't done it already.
+///
+/// # Note
+/// The general purpose events are all disabled, because they can only be handled
+/// by executing AML, which this project can't do. One of them being left enabled
+/// would keep the level triggered SCI asserted forever.
+/// For the same reason, lid switches, which are always general purpose events,
+/// and control method power buttons are not supported.
+///
+/// # References
+///
+/// * The ACPI spec, version 6.2, sections 4.8.3.1 and 4.8.4.1
+/// * <https://wiki.osdev.org/ACPI>
+pub fn enable_power_button() -> Result<(), &'static str> {
+    let fadt = unsafe { find_fadt() }.ok_or("Couldn't find a valid FADT")?;
+    if !fadt.has_fixed_power_button() {
+        return Err("The power button is not a fixed feature button");
+    }
+    if fadt.sci_int() != IRQ::Acpi.as_u8().as_u16() {
+        return Err("The SCI is not wired to the expected interrupt line");
+    }
+    // The status and enable registers must be at least 2 bytes each
+    if fadt.pm1_event_length() < 4 {
+        return Err("The PM1 event register blocks are too small");
+    }
+    enable_acpi_mode(fadt)?;
+    disable_gpes(fadt);
+    let pm1b = match fadt.pm1b_event_block() {
+        0 => None,
+        port => Some(port.as_u16())
+    };
+    let blocks = PM1EventBlocks {
+        pm1a: fadt.pm1a_event_block().as_u16(),
+        pm1b,
+        len: fadt.pm1_event_length().as_u16()
+    };
+    for (mut status_port, mut enable_port) in blocks.ports() {
+        // The status bits are cleared by writing 1s to them
+        status_port.write(u16::MAX);
+        let mut enable = 0u16;
+        enable.set_bit(PWRBTN_BIT);
+        enable_port.write(enable);
+    }
+    unsafe { PM1_EVENT_BLOCKS = Some(blocks) };
+    Ok(())
+}
+
+/// Acknowledges the fixed events that raised the System Control Interrupt
+///
+/// Returns true if the power button was pressed.
+/// Must be called by the SCI handler, because the SCI stays asserted
+/// until the events have been acknowledged
+pub fn handle_sci() -> bool {
+    let blocks = match unsafe { PM1_EVENT_BLOCKS } {
+        Some(blocks) => blocks,
+        None => return false
+    };
+    let mut power_button_pressed = false;
+    for (mut status_port, _) in blocks.ports() {
+        let status = status_port.read();
+        if status.get_bit(PWRBTN_BIT) == BitState::Set {
+            power_button_pressed = true;
+        }
+        status_port.write(status);
+    }
+    power_button_pressed
+}
+
+/// Switches the hardware from legacy mode to ACPI mode, in which
+/// the fixed events raise the SCI
+fn enable_acpi_mode(fadt: &FADT) -> Result<(), &'static str> {
+    let pm1a_ctrl_port: Port<u16> = Port::new(fadt.pm1a_ctrl_block().as_u16());
+    if pm1a_ctrl_port.read().get_bit(SCI_EN_BIT) == BitState::Set {
+        return Ok(());
+    }
+    if fadt.smi_cmd() == 0 || fadt.acpi_enable() == 0 {
+        return Err("The hardware is not in ACPI mode and can't be switched to it");
+    }
+    let mut smi_cmd_port: Port<u8> = Port::new(fadt.smi_cmd().as_u16());
+    smi_cmd_port.write(fadt.acpi_enable());
+    // The switch can take a while, because it's done by the firmware's SMI handler
+    for _ in 0..0x100000 {
+        if pm1a_ctrl_port.read().get_bit(SCI_EN_BIT) == BitState::Set {
+            return Ok(());
+        }
+    }
+    Err("Timed out waiting for the hardware to switch to ACPI mode")
+}
+
+/// Disables and acknowledges all the general purpose events
+fn disable_gpes(fadt: &FADT) {
+    let gpe_blocks = [
+        (fadt.gpe0_block(), fadt.gpe0_block_length()),
+        (fadt.gpe1_block(), fadt.gpe1_block_length())
+    ];
+    for (block, len) in gpe_blocks {
+        if block == 0 {
+            continue;
+        }
+        // The status registers are the first half of the block
+        // and the enable registers are the second half
+        let half_len = (len / 2).as_u16();
+        for i in 0..half_len {
+            let mut enable_port: Port<u8> = Port::new(block.as_u16() + half_len + i);
+            enable_port.write(0);
+            let mut status_port: Port<u8> = Port::new(block.as_u16() + i);
+            status_port.write(u8::MAX);
+        }
+    }
+}
+
+/// Finds the FADT and checks that it and the tables leading to it are valid
+unsafe fn find_fadt() -> Option<&'static FADT> {
+    let rsdp = detect_rsdp()?;
+    if rsdp == RSDP::None || !rsdp.is_valid() {
+        return None;
+    }
+    let rsdt = &*rsdp.rsdt_ptr();
+    if !rsdt.is_valid() {
+        return None;
+    }
+    let fadt = rsdt.find_fadt()?;
+    if !fadt.is_valid() {
+        return None;
+    }
+    Some(fadt)
+}
+
+/// The ports of the PM1a and, if it exists, PM1b event register blocks
+#[derive(Clone, Copy)]
+struct PM1EventBlocks {
+    pm1a: u16,
+    pm1b: Option<u16>,
+    /// The length in bytes of each block
+    len: u16
+}
+
+impl PM1EventBlocks {
+    /// An iterator over the (status, enable) register ports of each block
+    fn ports(&self) -> impl Iterator<Item = (Port<u16>, Port<u16>)> {
+        let len = self.len;
+        Some(self.pm1a).into_iter()
+            .chain(self.pm1b)
+            .map(move |block| (Port::new(block), Port::new(block + len / 2)))
+    }
+}
+
+/// The hooks run before shutting down, in the order they were registered
+struct ShutdownHooks<const N: usize> {
+    hooks: [Option<ShutdownHook>; N],
+    len: usize
+}
+
+impl<const N: usize> ShutdownHooks<N> {
+    const fn new() -> Self {
+        Self { hooks: [None; N], len: 0 }
+    }
+
+    fn register(&mut self, hook: ShutdownHook) -> Result<(), &'static str> {
+        if self.len == N {
+            return Err("Too many shutdown hooks have been registered");
+        }
+        self.hooks[self.len] = Some(hook);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes all the hooks and returns them
+    fn take(&mut self) -> [Option<ShutdownHook>; N] {
+        self.len = 0;
+        core::mem::replace(&mut self.hooks, [None; N])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn first_hook() {
+        CALLS.store(CALLS.load(Ordering::SeqCst) * 10 + 1, Ordering::SeqCst);
+    }
+
+    fn second_hook() {
+        CALLS.store(CALLS.load(Ordering::SeqCst) * 10 + 2, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_shutdown_hooks() {
+        let mut hooks: ShutdownHooks<2> = ShutdownHooks::new();
+        assert!(hooks.register(first_hook).is_ok());
+        assert!(hooks.register(second_hook).is_ok());
+        assert!(hooks.register(first_hook).is_err());
+        // Run the way `run_shutdown_hooks` runs them, last registered first
+        for hook in hooks.take().iter().rev().flatten() {
+            hook();
+        }
+        assert_eq!(CALLS.load(Ordering::SeqCst), 21);
+        // Taking the hooks leaves none to run again, but room for more
+        assert!(hooks.take().iter().all(Option::is_none));
+        assert!(hooks.register(first_hook).is_ok());
+    }
+}
@@ -7,17 +7,6 @@ const X_SCALE: usize = 2;
 const Y_SCALE: usize = 2;
 const SCREEN_HEIGHT: usize = 480;
 const SCREEN_WIDTH: usize = 640;
-#[repr(C)]
-struct Color {
-    blue: u8,
-    green: u8,
-    red: u8,
-    _r: u8
-}
-
-//#[repr(transparent)]
-//struct Color(u8);
-
 static X_POS: AtomicUsize = AtomicUsize::new(0);
 static Y_POS: AtomicUsize = AtomicUsize::new(0);
 use crate::font;
@@ -36,9 +25,7 @@ impl fmt::Write for Printer {
 // Quick and dirty printing
 impl Printer {
     pub fn print_char(&mut self, c: u8) {
-        let framebuffer = FRAMEBUFFER.get().unwrap().as_mut_ptr();
-        //let framebuffer = 0xa0000;
-        let vga = framebuffer as *mut Color;
+        let framebuffer = FRAMEBUFFER.get().unwrap();
         let curr_x = X_POS.load(Ordering::Relaxed);
         let curr_y = Y_POS.load(Ordering::Relaxed);
         if c == b'\n' {
@@ -52,13 +39,17 @@ impl Printer {
                     for x in 0..FONT_WIDTH {
                         let j = x + 1;
                         for xp in x * X_SCALE..j * X_SCALE {
+                            let pixel = match framebuffer.pixel_addr(curr_x + xp, curr_y + yp) {
+                                Some(addr) => addr.as_mut_ptr() as *mut u32,
+                                // Off the screen
+                                None => continue
+                            };
                             unsafe {
                                 if byte & (1 << (FONT_WIDTH - x - 1)) == 0 {
-                                    //*vga.offset(((curr_y + yp)*SCREEN_WIDTH+xp+curr_x) as isize) = Color(0);
-                                    *vga.offset(((curr_y + yp)*SCREEN_WIDTH+xp+curr_x) as isize) = Color { blue: 0, green: 255, red: 255, _r: 0};
+                                    // Yellow
+                                    *pixel = framebuffer.pixel_format().encode(255, 255, 0);
                                 } else {
-                                    *vga.offset(((curr_y + yp)*SCREEN_WIDTH+xp+curr_x) as isize) = Color { blue: 0, green: 0, red: 0, _r: 0};
-                                    //*vga.offset(((curr_y + yp)*SCREEN_WIDTH+xp+curr_x) as isize) = Color(0xf);
+                                    *pixel = 0;
                                 }
                             }
                         }
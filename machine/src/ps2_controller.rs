@@ -0,0 +1,103 @@
+//! Talking to the PS/2 controller itself, which the keyboard and the mouse
+//! are plugged into
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/%228042%22_PS/2_Controller>
+
+use crate::port::{Port, PortReadWrite};
+use num::{Integer, BitState};
+
+/// The port bytes are read from and written to the devices through
+const DATA_PORT: u16 = 0x60;
+/// The controller's status port, which commands to the controller are written to
+const STATUS_PORT: u16 = 0x64;
+/// Set in the status when there's a byte to read from the data port
+const OUTPUT_FULL_BIT: usize = 0;
+/// Set in the status while the controller hasn't taken the last byte written
+const INPUT_FULL_BIT: usize = 1;
+/// Set in the status when the byte to read came from the second port
+const SECOND_PORT_DATA_BIT: usize = 5;
+/// Tells the controller to read its configuration byte out through the data port
+const READ_CONFIG: u8 = 0x20;
+/// Tells the controller to take the next byte written to the data port as its configuration
+const WRITE_CONFIG: u8 = 0x60;
+/// The number of times the status is read before giving up on waiting
+const TIMEOUT: usize = 0x10000;
+
+/// Where a byte read from the data port is expected from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// The controller's responses to its own commands
+    Controller,
+    /// The device in the second port, which is the mouse
+    SecondPort
+}
+
+pub(crate) fn command(command: u8) {
+    let mut status_port: Port<u8> = Port::new(STATUS_PORT);
+    wait_for_input_empty();
+    status_port.write(command);
+}
+
+pub(crate) fn write_data(byte: u8) {
+    let mut data_port: Port<u8> = Port::new(DATA_PORT);
+    wait_for_input_empty();
+    data_port.write(byte);
+}
+
+/// Reads a byte from the data port that came from `source`. While waiting
+/// for the second port, the keyboard's bytes are thrown away.
+/// Returns None if no byte came
+///
+/// Interrupts should be disabled, so the interrupt handlers don't read the byte first
+pub(crate) fn read_data(source: Source) -> Option<u8> {
+    let status_port: Port<u8> = Port::new(STATUS_PORT);
+    let data_port: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..TIMEOUT {
+        let status = status_port.read();
+        if status.get_bit(OUTPUT_FULL_BIT) == BitState::Unset {
+            continue;
+        }
+        let byte = data_port.read();
+        // The controller's own bytes don't come with the second port's bit
+        if source == Source::Controller || status.get_bit(SECOND_PORT_DATA_BIT) == BitState::Set {
+            return Some(byte);
+        }
+    }
+    None
+}
+
+/// Reads the controller's configuration byte
+pub(crate) fn read_config() -> Result<u8, &'static str> {
+    command(READ_CONFIG);
+    read_data(Source::Controller).ok_or("The PS/2 controller didn't send its configuration")
+}
+
+pub(crate) fn write_config(config: u8) {
+    command(WRITE_CONFIG);
+    write_data(config);
+}
+
+/// Throws away any bytes waiting to be read, so they aren't taken for responses
+pub(crate) fn flush_output() {
+    let status_port: Port<u8> = Port::new(STATUS_PORT);
+    let data_port: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..TIMEOUT {
+        if status_port.read().get_bit(OUTPUT_FULL_BIT) == BitState::Unset {
+            break;
+        }
+        data_port.read();
+    }
+}
+
+/// Waits for the controller to take the last byte written, giving up
+/// after a while in case it's stuck
+fn wait_for_input_empty() {
+    let status_port: Port<u8> = Port::new(STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if status_port.read().get_bit(INPUT_FULL_BIT) == BitState::Unset {
+            break;
+        }
+    }
+}
@@ -22,4 +22,43 @@ impl RFlags {
     pub fn contains(&self, flag: u64) -> bool {
         self.0 & flag != 0
     }
-}
\ No newline at end of file
+}
+
+/// A Model Specific Register
+///
+/// # References
+///
+/// * <https://wiki.osdev.org/Model_Specific_Registers>
+#[derive(Debug, Clone, Copy)]
+pub struct Msr(u32);
+
+impl Msr {
+    /// Creates a new Msr for the register with number `reg_no`
+    pub const fn new(reg_no: u32) -> Msr {
+        Msr(reg_no)
+    }
+
+    /// Reads the value of the MSR with the rdmsr instruction
+    ///
+    /// # Safety
+    ///
+    /// Reading an MSR that the processor doesn't implement causes a
+    /// general protection fault
+    pub unsafe fn read(&self) -> u64 {
+        let (high, low): (u32, u32);
+        asm!("rdmsr", in("ecx") self.0, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+        ((high as u64) << 32) | (low as u64)
+    }
+
+    /// Writes `value` into the MSR with the wrmsr instruction
+    ///
+    /// # Safety
+    ///
+    /// Writing to an MSR that the processor doesn't implement or writing
+    /// a value with reserved bits set causes a general protection fault
+    pub unsafe fn write(&self, value: u64) {
+        let low = value as u32;
+        let high = (value >> 32) as u32;
+        asm!("wrmsr", in("ecx") self.0, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+    }
+}
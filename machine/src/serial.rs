@@ -0,0 +1,93 @@
+//! A driver for the 16550 UART serial port, used for debug logging
+//!
+//! # References
+//!
+//! * <https://wiki.osdev.org/Serial_Ports>
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::port::{Port, PortReadWrite};
+use num::{Integer, BitState};
+
+/// The I/O port base of the first serial port
+pub const COM1: u16 = 0x3f8;
+
+/// Tells if debug logging over the serial port has been enabled
+static LOGGING_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Initializes COM1 and enables logging with the `serial_println` macro
+pub fn enable_logging() {
+    SerialPort::new(COM1).init();
+    LOGGING_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Checks if debug logging over the serial port has been enabled
+pub fn logging_enabled() -> bool {
+    LOGGING_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    if logging_enabled() {
+        SerialPort::new(COM1).write_fmt(args).unwrap();
+    }
+}
+
+/// Writes to COM1 if debug logging has been enabled. Does nothing otherwise
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_println!(""));
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+/// A 16550 UART
+#[derive(Clone, Copy)]
+pub struct SerialPort(u16);
+
+impl SerialPort {
+    /// Creates a new SerialPort with its registers starting at I/O port `base`
+    pub const fn new(base: u16) -> SerialPort {
+        SerialPort(base)
+    }
+
+    /// Sets the port up for 38400 baud, 8 data bits, no parity and 1 stop bit
+    pub fn init(&self) {
+        // Disabling all interrupts
+        self.reg(1).write(0x00);
+        // Enabling the DLAB to set the baud rate divisor
+        self.reg(3).write(0x80);
+        // Divisor of 3 (low byte, then high byte): 115200 / 3 = 38400 baud
+        self.reg(0).write(0x03);
+        self.reg(1).write(0x00);
+        // 8 bits, no parity, one stop bit, DLAB cleared
+        self.reg(3).write(0x03);
+        // Enabling and clearing the FIFOs with a 14 byte threshold
+        self.reg(2).write(0xc7);
+        // Data terminal ready and request to send
+        self.reg(4).write(0x03);
+    }
+
+    /// Sends a byte, waiting until the transmitter can take it
+    pub fn send(&self, byte: u8) {
+        // Bit 5 of the line status register is set when the
+        // transmit holding register is empty
+        while self.reg(5).read().get_bit(5) == BitState::Unset {}
+        self.reg(0).write(byte);
+    }
+
+    fn reg(&self, offset: u16) -> Port<u8> {
+        Port::new(self.0 + offset)
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
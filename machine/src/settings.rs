@@ -0,0 +1,39 @@
+//! Storage for the game's settings that survives reboots
+//!
+//! The settings are a single byte whose meaning is up to the game.
+//! With the BIOS, the byte is kept in the CMOS. With UEFI, it's kept in
+//! a non-volatile variable, since the CMOS can't be relied on there
+
+/// The name of the UEFI variable the settings are kept in
+#[cfg(not(feature = "bios"))]
+const SETTINGS_VARIABLE_NAME: &str = "Settings";
+
+/// Returns the saved settings, or None if they have never been saved
+#[cfg(feature = "bios")]
+pub fn load() -> Option<u8> {
+    crate::cmos::read_settings()
+}
+
+/// Returns the saved settings, or None if they have never been saved
+/// or can't be read
+#[cfg(not(feature = "bios"))]
+pub fn load() -> Option<u8> {
+    let mut buffer = [0u8; 1];
+    match crate::uefi::runtime::get_variable(SETTINGS_VARIABLE_NAME, &mut buffer) {
+        Ok(Some(1)) => Some(buffer[0]),
+        _ => None
+    }
+}
+
+/// Saves the settings, so the next boot starts with them
+#[cfg(feature = "bios")]
+pub fn save(settings: u8) -> Result<(), &'static str> {
+    crate::cmos::write_settings(settings);
+    Ok(())
+}
+
+/// Saves the settings, so the next boot starts with them
+#[cfg(not(feature = "bios"))]
+pub fn save(settings: u8) -> Result<(), &'static str> {
+    crate::uefi::runtime::set_variable(SETTINGS_VARIABLE_NAME, &[settings])
+}
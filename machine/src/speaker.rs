@@ -0,0 +1,46 @@
+//! The PC speaker, which beeps at the frequency channel 2 of the PIT runs at
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/PC_Speaker>
+//! * The OSDev wiki <https://wiki.osdev.org/Programmable_Interval_Timer>
+
+use crate::port::{Port, PortReadWrite};
+use crate::instructions::interrupts::without_interrupts;
+use crate::time::PIT_FREQUENCY;
+
+const PIT_CHANNEL_2_PORT: u16 = 0x42;
+const PIT_COMMAND_PORT: u16 = 0x43;
+/// Selects channel 2, with the low byte of the divisor written before the high
+/// byte, as a square wave generator
+const PIT_CHANNEL_2_SQUARE_WAVE: u8 = 0b1011_0110;
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+/// Bit 0 lets channel 2 run and bit 1 connects its output to the speaker
+const SPEAKER_ENABLE_BITS: u8 = 0b11;
+
+/// Starts the speaker beeping at `freq_hz`, until `stop_beep` is called
+///
+/// Frequencies below about 19Hz, which the PIT can't divide down to, beep at 19Hz
+pub fn start_beep(freq_hz: u32) {
+    let divisor = (PIT_FREQUENCY / freq_hz.max(1)).clamp(1, u16::MAX as u32) as u16;
+    // The two bytes of the divisor must be written one after the other
+    without_interrupts(|| {
+        let mut command_port: Port<u8> = Port::new(PIT_COMMAND_PORT);
+        let mut channel_2_port: Port<u8> = Port::new(PIT_CHANNEL_2_PORT);
+        command_port.write(PIT_CHANNEL_2_SQUARE_WAVE);
+        channel_2_port.write(divisor as u8);
+        channel_2_port.write((divisor >> 8) as u8);
+        let mut control_port: Port<u8> = Port::new(SYSTEM_CONTROL_PORT_B);
+        let control = control_port.read();
+        if control & SPEAKER_ENABLE_BITS != SPEAKER_ENABLE_BITS {
+            control_port.write(control | SPEAKER_ENABLE_BITS);
+        }
+    });
+}
+
+/// Silences the speaker
+pub fn stop_beep() {
+    let mut control_port: Port<u8> = Port::new(SYSTEM_CONTROL_PORT_B);
+    let control = control_port.read();
+    control_port.write(control & !SPEAKER_ENABLE_BITS);
+}
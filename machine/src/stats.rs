@@ -0,0 +1,98 @@
+//! Statistics about how busy the processor is
+//!
+//! The processor is idle when it's halted in `idle`, waiting for an interrupt.
+//! Everything else, including the time spent in interrupt handlers, is work.
+//! The cycles spent idle are counted with the time stamp counter and compared
+//! with all the cycles that passed once every second
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use crate::instructions::rdtsc;
+use crate::instructions::interrupts;
+use crate::time::TIMER_TICKS_PER_SEC;
+
+/// Whether the processor is halted in `idle`
+static IS_IDLE: AtomicBool = AtomicBool::new(false);
+/// The value of the time stamp counter when the processor was last halted
+static IDLE_START: AtomicU64 = AtomicU64::new(0);
+/// The number of cycles spent idle since the start of the current second
+static IDLE_CYCLES: AtomicU64 = AtomicU64::new(0);
+/// The value of the time stamp counter at the start of the current second
+static SECOND_START: AtomicU64 = AtomicU64::new(0);
+/// The number of timer interrupts since the start of the current second
+static TICKS_IN_SECOND: AtomicUsize = AtomicUsize::new(0);
+/// The percentage of the last second the processor spent working
+static CPU_LOAD: AtomicU8 = AtomicU8::new(0);
+
+/// Halts the processor until the next interrupt, counting the time as idle
+///
+/// Interrupts are enabled, since nothing else can wake the processor up
+pub fn idle() {
+    interrupts::disable();
+    IDLE_START.store(rdtsc(), Ordering::SeqCst);
+    IS_IDLE.store(true, Ordering::SeqCst);
+    interrupts::enable_and_hlt();
+    // Handlers that don't call `interrupt_entered` leave the idle time to be ended here
+    end_idle();
+}
+
+/// Ends the idle time started by `idle`
+///
+/// Interrupt handlers call this before anything else, so the time spent
+/// handling the interrupt that woke the processor up isn't counted as idle
+pub fn interrupt_entered() {
+    end_idle();
+}
+
+/// Ends the idle time and works out the CPU load at the end of every second
+///
+/// Called by the timer interrupt handler instead of `interrupt_entered`
+pub fn timer_tick() {
+    end_idle();
+    if TICKS_IN_SECOND.fetch_add(1, Ordering::SeqCst) + 1 < TIMER_TICKS_PER_SEC {
+        return;
+    }
+    TICKS_IN_SECOND.store(0, Ordering::SeqCst);
+    let now = rdtsc();
+    let second_start = SECOND_START.swap(now, Ordering::SeqCst);
+    let idle_cycles = IDLE_CYCLES.swap(0, Ordering::SeqCst);
+    // Nothing was measured before the first second started
+    if second_start != 0 {
+        CPU_LOAD.store(load_percentage(idle_cycles, now - second_start), Ordering::SeqCst);
+    }
+}
+
+/// The percentage of the last second that the processor spent working instead of idling
+pub fn cpu_load() -> u8 {
+    CPU_LOAD.load(Ordering::SeqCst)
+}
+
+fn end_idle() {
+    if IS_IDLE.swap(false, Ordering::SeqCst) {
+        let idle_cycles = rdtsc() - IDLE_START.load(Ordering::SeqCst);
+        IDLE_CYCLES.fetch_add(idle_cycles, Ordering::SeqCst);
+    }
+}
+
+/// The percentage of `total_cycles` that weren't `idle_cycles`
+fn load_percentage(idle_cycles: u64, total_cycles: u64) -> u8 {
+    if total_cycles == 0 {
+        return 0;
+    }
+    let idle_cycles = idle_cycles.min(total_cycles);
+    (100 - idle_cycles * 100 / total_cycles) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_percentage() {
+        assert_eq!(load_percentage(0, 1000), 100);
+        assert_eq!(load_percentage(1000, 1000), 0);
+        assert_eq!(load_percentage(750, 1000), 25);
+        // Idle time that leaked in from before the second started
+        assert_eq!(load_percentage(1200, 1000), 0);
+        assert_eq!(load_percentage(0, 0), 0);
+    }
+}
@@ -0,0 +1,204 @@
+//! The time since boot
+//!
+//! Channel 0 of the PIT is left at the firmware's rate of about 18.2Hz, and
+//! each of its interrupts is a tick. Ticks are too coarse for waiting on
+//! hardware, so `init` measures the frequency of the time stamp counter against
+//! channel 2 of the PIT, and the uptime is read from the time stamp counter
+//! after that. Until then, or if the measurement fails, the uptime is worked
+//! out from the ticks
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/Programmable_Interval_Timer>
+//! * Linux's `pit_calibrate_tsc` in arch/x86/kernel/tsc.c
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::port::{Port, PortReadWrite};
+use crate::instructions::rdtsc;
+use crate::instructions::interrupts::{self, without_interrupts};
+
+/// The frequency of the PIT's oscillator, which the channels divide
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+/// The divisor channel 0 runs at, which is 0 for 65536
+const PIT_CHANNEL_0_DIVISOR: u64 = 65536;
+/// The number of whole timer interrupts in a second, for counting seconds in ticks
+pub const TIMER_TICKS_PER_SEC: usize = (PIT_FREQUENCY as u64 / PIT_CHANNEL_0_DIVISOR) as usize;
+const PIT_CHANNEL_2_PORT: u16 = 0x42;
+const PIT_COMMAND_PORT: u16 = 0x43;
+/// Selects channel 2, with the low byte of the count written before the high
+/// byte, to count down once and set its output when it reaches 0
+const PIT_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+/// Lets channel 2 count
+const CHANNEL_2_GATE_BIT: u8 = 1 << 0;
+/// Connects channel 2's output to the speaker
+const SPEAKER_DATA_BIT: u8 = 1 << 1;
+/// Reads as set once channel 2's count has reached 0
+const CHANNEL_2_OUTPUT_BIT: u8 = 1 << 5;
+/// How long the time stamp counter is measured for
+const CALIBRATION_MS: u32 = 10;
+/// The number of times channel 2's output is read before the measurement is
+/// given up on. Reading a port takes about a microsecond
+const CALIBRATION_TIMEOUT: usize = 1_000_000;
+
+/// The number of timer interrupts since they were enabled
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// The value of the time stamp counter when it was calibrated
+static TSC_AT_CALIBRATION: AtomicU64 = AtomicU64::new(0);
+/// The uptime in milliseconds when the time stamp counter was calibrated
+static MS_AT_CALIBRATION: AtomicU64 = AtomicU64::new(0);
+/// The number of time stamp counter cycles in a millisecond, or 0 if unknown
+static TSC_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Measures the frequency of the time stamp counter
+///
+/// Must be called before the PC speaker is used, since channel 2 is borrowed
+/// for the measurement. Returns an error if channel 2 never finished
+/// counting, in which case time is kept in ticks
+pub fn init() -> Result<(), &'static str> {
+    let tsc_per_ms = without_interrupts(|| count_per_ms(rdtsc)).ok_or("The PIT didn't count down")?;
+    if tsc_per_ms == 0 {
+        return Err("The time stamp counter isn't running");
+    }
+    MS_AT_CALIBRATION.store(ticks_to_ms(ticks()), Ordering::SeqCst);
+    TSC_AT_CALIBRATION.store(rdtsc(), Ordering::SeqCst);
+    TSC_PER_MS.store(tsc_per_ms, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Counts a timer interrupt
+///
+/// Called by the timer interrupt handler
+pub fn timer_tick() {
+    TICKS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// The number of timer interrupts so far, about 18.2 a second
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// The number of milliseconds since the timer started
+pub fn uptime_ms() -> u64 {
+    match TSC_PER_MS.load(Ordering::SeqCst) {
+        0 => ticks_to_ms(ticks()),
+        tsc_per_ms => {
+            let cycles = rdtsc().wrapping_sub(TSC_AT_CALIBRATION.load(Ordering::SeqCst));
+            MS_AT_CALIBRATION.load(Ordering::SeqCst) + cycles / tsc_per_ms
+        }
+    }
+}
+
+/// The number of microseconds since the timer started
+///
+/// Only as precise as the ticks without a calibrated time stamp counter
+pub fn uptime_us() -> u64 {
+    match TSC_PER_MS.load(Ordering::SeqCst) {
+        0 => ticks_to_ms(ticks()) * 1000,
+        tsc_per_ms => {
+            let cycles = rdtsc().wrapping_sub(TSC_AT_CALIBRATION.load(Ordering::SeqCst));
+            // Split up, so multiplying the cycles doesn't overflow
+            let us = cycles / tsc_per_ms * 1000 + cycles % tsc_per_ms * 1000 / tsc_per_ms;
+            MS_AT_CALIBRATION.load(Ordering::SeqCst) * 1000 + us
+        }
+    }
+}
+
+/// Waits for at least `ms` milliseconds
+pub fn sleep_ms(ms: u64) {
+    sleep_us(ms.saturating_mul(1000));
+}
+
+/// Waits for at least `us` microseconds
+///
+/// Without a calibrated time stamp counter, the wait is counted in ticks,
+/// rounded up, which only happen with interrupts enabled. With them
+/// disabled, nothing is waited for
+pub fn sleep_us(us: u64) {
+    let tsc_per_ms = TSC_PER_MS.load(Ordering::SeqCst);
+    if tsc_per_ms != 0 {
+        let start = rdtsc();
+        let cycles = us.saturating_mul(tsc_per_ms) / 1000;
+        while rdtsc().wrapping_sub(start) < cycles {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    if !interrupts::is_enabled() {
+        return;
+    }
+    let end = ticks() + us_to_ticks(us);
+    while ticks() < end {
+        core::hint::spin_loop();
+    }
+}
+
+/// Measures how much the counter read with `read_counter` goes up in a millisecond
+///
+/// Channel 2 is let count down from the number of PIT cycles in
+/// `CALIBRATION_MS` milliseconds. Returns None if it never got to 0.
+/// Interrupts should be disabled, so none of them lengthen the measurement
+pub(crate) fn count_per_ms<F: Fn() -> u64>(read_counter: F) -> Option<u64> {
+    let count = (PIT_FREQUENCY * CALIBRATION_MS / 1000) as u16;
+    let mut control_port: Port<u8> = Port::new(SYSTEM_CONTROL_PORT_B);
+    let mut command_port: Port<u8> = Port::new(PIT_COMMAND_PORT);
+    let mut channel_2_port: Port<u8> = Port::new(PIT_CHANNEL_2_PORT);
+    let control = control_port.read();
+    // Counting with the speaker disconnected, so nothing clicks
+    control_port.write((control & !SPEAKER_DATA_BIT) | CHANNEL_2_GATE_BIT);
+    command_port.write(PIT_CHANNEL_2_ONE_SHOT);
+    channel_2_port.write(count as u8);
+    channel_2_port.write((count >> 8) as u8);
+    let start = read_counter();
+    let counted_down = (0..CALIBRATION_TIMEOUT).any(|_| control_port.read() & CHANNEL_2_OUTPUT_BIT != 0);
+    let end = read_counter();
+    control_port.write(control & !(SPEAKER_DATA_BIT | CHANNEL_2_GATE_BIT));
+    if counted_down { Some(end.wrapping_sub(start) / CALIBRATION_MS as u64) } else { None }
+}
+
+/// How much a counter that goes up by `count_per_ms` in a millisecond
+/// goes up in a tick, for timers that stand in for channel 0
+pub(crate) fn count_per_tick(count_per_ms: u64) -> u64 {
+    count_per_ms * PIT_CHANNEL_0_DIVISOR * 1000 / PIT_FREQUENCY as u64
+}
+
+/// The number of whole timer interrupts that take at least `ms` milliseconds
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    us_to_ticks(ms.saturating_mul(1000))
+}
+
+/// The number of milliseconds `ticks` timer interrupts take
+fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * PIT_CHANNEL_0_DIVISOR * 1000 / PIT_FREQUENCY as u64
+}
+
+/// The number of whole timer interrupts that take at least `us` microseconds
+fn us_to_ticks(us: u64) -> u64 {
+    let tick_us = PIT_CHANNEL_0_DIVISOR * 1_000_000;
+    us.saturating_mul(PIT_FREQUENCY as u64).saturating_add(tick_us - 1) / tick_us
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_conversions() {
+        assert_eq!(ticks_to_ms(0), 0);
+        // A tick is about 54.9ms
+        assert_eq!(ticks_to_ms(1), 54);
+        assert_eq!(ticks_to_ms(18), 988);
+        assert_eq!(TIMER_TICKS_PER_SEC, 18);
+        assert_eq!(ticks_to_ms(1_000_000), 54_925_401);
+
+        assert_eq!(us_to_ticks(0), 0);
+        assert_eq!(us_to_ticks(1), 1);
+        assert_eq!(us_to_ticks(54_925), 1);
+        assert_eq!(us_to_ticks(54_926), 2);
+        assert_eq!(us_to_ticks(1_000_000), 19);
+        assert_eq!(ms_to_ticks(2000), 37);
+
+        assert_eq!(count_per_tick(0), 0);
+        assert_eq!(count_per_tick(1000), 54_925);
+    }
+}
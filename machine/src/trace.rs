@@ -0,0 +1,138 @@
+//! A ring buffer of the last things the machine did, for debugging
+//!
+//! Only the newest `TRACE_LEN` entries are kept. The trace can be written
+//! to the serial debug log with `dump`, for example after spotting that a
+//! device was misconfigured.
+//!
+//! With the `port_audit` feature, every I/O port read and write is recorded,
+//! along with the place in the source it was made from
+
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+use sync::mutex::Mutex;
+use crate::serial_println;
+
+/// The number of entries kept in the trace
+pub const TRACE_LEN: usize = 256;
+
+static TRACE: Mutex<TraceRing<TRACE_LEN>> = Mutex::new(TraceRing::new());
+
+/// Set while the trace is being dumped, so the port writes to the
+/// serial port don't push the entries being dumped out of the trace
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Something the machine did
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceEvent {
+    /// `value` was read from I/O port `port`
+    PortRead { port: u16, value: u32 },
+    /// `value` was written to I/O port `port`
+    PortWrite { port: u16, value: u32 }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub event: TraceEvent,
+    /// Where in the source the event came from
+    pub caller: &'static Location<'static>
+}
+
+/// Adds an entry to the trace, replacing the oldest one if the trace is full
+///
+/// The entry is dropped if the trace is in use by the code that was interrupted
+pub fn record(event: TraceEvent, caller: &'static Location<'static>) {
+    if PAUSED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Some(mut trace) = TRACE.try_lock() {
+        trace.push(TraceEntry { event, caller });
+    }
+}
+
+/// Writes the entries in the trace, from the oldest to the newest,
+/// to the serial debug log
+pub fn dump() {
+    PAUSED.store(true, Ordering::SeqCst);
+    // Copying the entries out, so interrupts can still record while they're written
+    let trace = match TRACE.try_lock() {
+        Some(trace) => *trace,
+        None => {
+            PAUSED.store(false, Ordering::SeqCst);
+            return;
+        }
+    };
+    serial_println!("Trace, oldest first:");
+    for entry in trace.iter() {
+        match entry.event {
+            TraceEvent::PortRead { port, value } => {
+                serial_println!("in  {:#06x} -> {:#x} at {}", port, value, entry.caller)
+            }
+            TraceEvent::PortWrite { port, value } => {
+                serial_println!("out {:#06x} <- {:#x} at {}", port, value, entry.caller)
+            }
+        }
+    }
+    PAUSED.store(false, Ordering::SeqCst);
+}
+
+/// Removes every entry from the trace
+pub fn clear() {
+    TRACE.lock().clear();
+}
+
+/// The last `N` entries added
+#[derive(Clone, Copy)]
+struct TraceRing<const N: usize> {
+    entries: [Option<TraceEntry>; N],
+    /// The index the next entry is written at
+    next: usize
+}
+
+impl<const N: usize> TraceRing<N> {
+    const fn new() -> Self {
+        Self { entries: [None; N], next: 0 }
+    }
+
+    fn push(&mut self, entry: TraceEntry) {
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % N;
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// The entries from the oldest to the newest
+    fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let (newest, oldest) = self.entries.split_at(self.next);
+        oldest.iter().chain(newest.iter()).filter_map(|entry| entry.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port_write(value: u32) -> TraceEntry {
+        TraceEntry { event: TraceEvent::PortWrite { port: 0xcf8, value }, caller: Location::caller() }
+    }
+
+    fn values<const N: usize>(ring: &TraceRing<N>) -> std::vec::Vec<u32> {
+        ring.iter().map(|entry| match entry.event {
+            TraceEvent::PortRead { value, .. } | TraceEvent::PortWrite { value, .. } => value
+        }).collect()
+    }
+
+    #[test]
+    fn test_trace_ring_keeps_the_newest_entries_in_order() {
+        let mut ring: TraceRing<3> = TraceRing::new();
+        ring.push(port_write(1));
+        ring.push(port_write(2));
+        assert_eq!(values(&ring), [1, 2]);
+        ring.push(port_write(3));
+        ring.push(port_write(4));
+        assert_eq!(values(&ring), [2, 3, 4]);
+        ring.clear();
+        assert!(values(&ring).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+//! Picking the graphics mode the screen is set to
+//!
+//! Graphics cards offer different modes under UEFI, and not every card has
+//! the same ones. Some only offer the monitor's native resolution, so the
+//! game can't count on an exact size and takes the best mode it fits in.
+//! The screen is scaled up to fill as much of a bigger mode as it can.
+//! A resolution can be asked for by setting the `Resolution` variable, the
+//! width and then the height as little endian 16 bit numbers, in the UEFI shell.
+
+use super::{EFIGraphicsOutputProtocol, runtime};
+use crate::framebuffer::PixelFormat;
+
+/// The name of the UEFI variable with the resolution the player wants
+const RESOLUTION_VARIABLE_NAME: &str = "Resolution";
+
+/// A graphics mode with a framebuffer that can be drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GraphicsMode {
+    /// The number it's set with
+    pub number: u32,
+    pub width: usize,
+    pub height: usize,
+    /// The number of pixels from the start of a row to the start of the next
+    pub stride: usize,
+    pub pixel_format: PixelFormat
+}
+
+impl GraphicsMode {
+    /// Tells whether or not a `width` by `height` screen can be drawn in the mode
+    pub fn fits(&self, width: usize, height: usize) -> bool {
+        width <= self.width && height <= self.height
+    }
+}
+
+impl EFIGraphicsOutputProtocol {
+    /// The modes that can be set, leaving out the ones that can't be
+    /// described or don't have a framebuffer
+    pub fn modes(&self) -> impl Iterator<Item = GraphicsMode> + Clone + '_ {
+        (0..self.mode().max_mode()).filter_map(move |mode_no| {
+            let info = self.query_mode(mode_no).ok()?;
+            let pixel_format = info.pixel_format()?;
+            Some(GraphicsMode {
+                number: mode_no,
+                width: info.horizontal_resolution() as usize,
+                height: info.vertical_resolution() as usize,
+                stride: info.pixels_per_scan_line() as usize,
+                pixel_format
+            })
+        })
+    }
+}
+
+/// The resolution the player asked for with the `Resolution` variable,
+/// or None if they didn't
+pub fn preferred_resolution() -> Option<(usize, usize)> {
+    let mut buffer = [0u8; 4];
+    match runtime::get_variable(RESOLUTION_VARIABLE_NAME, &mut buffer) {
+        Ok(Some(4)) => Some((
+            u16::from_le_bytes([buffer[0], buffer[1]]) as usize,
+            u16::from_le_bytes([buffer[2], buffer[3]]) as usize
+        )),
+        _ => None
+    }
+}
+
+/// Picks the best of `modes` for a `width` by `height` screen
+///
+/// The mode with the `preferred` resolution comes first, then the `current`
+/// mode, which the firmware usually sets to the monitor's native resolution.
+/// After that, it's one that's exactly the size of the screen, and then
+/// the smallest mode the screen fits in
+pub fn pick_mode(
+    modes: impl Iterator<Item = GraphicsMode> + Clone,
+    current: u32,
+    preferred: Option<(usize, usize)>,
+    width: usize,
+    height: usize
+) -> Option<GraphicsMode> {
+    let fitting = modes.filter(move |mode| mode.fits(width, height));
+    let with_resolution = |resolution: (usize, usize)| {
+        fitting.clone().find(|mode| (mode.width, mode.height) == resolution)
+    };
+    preferred.and_then(with_resolution)
+        .or_else(|| fitting.clone().find(|mode| mode.number == current))
+        .or_else(|| with_resolution((width, height)))
+        .or_else(|| fitting.clone().min_by_key(|mode| mode.width * mode.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn mode(number: u32, width: usize, height: usize) -> GraphicsMode {
+        GraphicsMode { number, width, height, stride: width, pixel_format: PixelFormat::Bgr }
+    }
+
+    const MODES: [GraphicsMode; 4] = [mode(0, 800, 600), mode(1, 1920, 1080), mode(2, 640, 480), mode(3, 320, 200)];
+
+    #[test]
+    fn test_pick_mode() {
+        let modes = MODES.iter().copied();
+        assert_eq!(pick_mode(modes.clone(), 1, Some((800, 600)), 640, 480), Some(MODES[0]));
+        assert_eq!(pick_mode(modes.clone(), 1, None, 640, 480), Some(MODES[1]));
+        // A preferred resolution that's too small is passed over
+        assert_eq!(pick_mode(modes.clone(), 1, Some((320, 200)), 640, 480), Some(MODES[1]));
+        // So is a current mode that's too small
+        assert_eq!(pick_mode(modes.clone(), 3, None, 640, 480), Some(MODES[2]));
+        let without_exact = [MODES[0], MODES[1], MODES[3]];
+        assert_eq!(pick_mode(without_exact.iter().copied(), 3, None, 640, 480), Some(MODES[0]));
+        assert_eq!(pick_mode(modes.clone(), 0, None, 2560, 1440), None);
+    }
+}
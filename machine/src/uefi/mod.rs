@@ -8,8 +8,13 @@ use core::fmt;
 use sync::once::Once;
 use crate::memory::{EFIMemMapDescriptor, MemMap};
 use crate::memory::{MemChunk, Addr, EFIMemRegionType, EFIMemRegion};
+use crate::framebuffer::PixelFormat;
 use crate::keyboard::uefi::{EFIInputKey, EFIKeyData, EFIKeyToggle};
 
+pub mod runtime;
+use runtime::EFIRuntimeServices;
+pub mod gop;
+
 static SYS_TABLE: Once<EFISystemTable> = Once::new();
 
 unsafe impl Sync for EFISystemTable {}
@@ -58,6 +63,7 @@ impl StatusCode {
     const STATUS_INVALID_PARAMETER: Status = 2;
     const STATUS_DEVICE_ERROR: Status = 7;
     const STATUS_NOT_READY: Status = 6;
+    const STATUS_NOT_FOUND: Status = 14;
 
     /// This bit is set in all error status codes
     const ERROR_BIT: usize = 1 << (core::mem::size_of::<usize>() * 8 - 1);
@@ -104,7 +110,7 @@ pub struct EFISystemTable {
     /// interface that is associated with `std_error_handle`
     std_err: *mut EFISimpleTextOutputProtocol,
     /// A pointer to the EFIRuntimeServicesTable
-    runtime_services: *mut [u8; 136],
+    runtime_services: *mut EFIRuntimeServices,
     /// A pointer to the EFIBootServicesTable
     boot_services: *mut EFIBootServices,
     /// Number of system configuration tables in the
@@ -119,6 +125,11 @@ impl EFISystemTable {
         unsafe { &*self.boot_services }
     }
 
+    /// The runtime services remain usable after exiting boot services
+    pub fn runtime_services(&self) -> &'static EFIRuntimeServices {
+        unsafe { &*self.runtime_services }
+    }
+
     pub fn stdin(&self) -> &'static EFISimpleTextInputProtocol {
         unsafe { &*self.stdin }
     }
@@ -300,13 +311,41 @@ pub struct EFIConfigurationTableEntry {
     pub vendor_table: *const core::ffi::c_void
 }
 
+/// How the address of pages allocated with the boot services is chosen
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EFIAllocType {
+    /// Any pages that are free
+    AnyPages = 0,
+    /// Any pages that are free, below an address
+    MaxAddress = 1,
+    /// The pages at an address
+    Address = 2
+}
+
 /// The boot services in the EFISystemTable
 #[repr(C)]
 pub struct EFIBootServices {
     /// The table header
     header: EFITableHeader,
     /// These fields are not needed in this project
-    unneeded0: [usize; 4],
+    unneeded0: [usize; 2],
+    /// Allocates whole pages of memory from the UEFI firmware
+    ///
+    /// # Arguments
+    ///
+    /// * alloc_type: How the address of the pages is chosen
+    /// * mem_type: The type of memory the pages will be used as
+    /// * pages: The number of 4KiB pages to allocate
+    /// * mem: On input, the address the pages must be at when `alloc_type` is
+    ///     `EFIAllocType::Address`. On output, the address of the allocated pages
+    alloc_pages: unsafe extern "efiapi" fn(
+        alloc_type: EFIAllocType,
+        mem_type: EFIMemRegionType,
+        pages: usize,
+        mem: &mut u64
+    ) -> Status,
+    unneeded0_1: [usize; 1],
     /// Returns the current memory map
     ///
     /// # Arguments
@@ -461,6 +500,46 @@ impl EFIBootServices {
         }
     }
 
+    /// Allocates the `pages` 4KiB pages starting at `addr`
+    ///
+    /// Fails if any of the pages are already in use or aren't usable memory
+    pub fn alloc_pages_at(&self, addr: u64, pages: usize, region_type: EFIMemRegionType) -> Result<MemChunk, &'static str> {
+        let mut mem = addr;
+        let status = unsafe { (self.alloc_pages)(
+            EFIAllocType::Address,
+            region_type,
+            pages,
+            &mut mem
+        ) };
+        if StatusCode::is_error(status) {
+            Err("Failed to allocate pages")
+        } else {
+            Ok(MemChunk {
+                start_addr: Addr::new(mem),
+                size: (pages * 4096) as u64
+            })
+        }
+    }
+
+    /// Allocates `pages` 4KiB pages that all lie at or below `max_addr`
+    pub fn alloc_pages_below(&self, max_addr: u64, pages: usize, region_type: EFIMemRegionType) -> Result<MemChunk, &'static str> {
+        let mut mem = max_addr;
+        let status = unsafe { (self.alloc_pages)(
+            EFIAllocType::MaxAddress,
+            region_type,
+            pages,
+            &mut mem
+        ) };
+        if StatusCode::is_error(status) {
+            Err("Failed to allocate pages")
+        } else {
+            Ok(MemChunk {
+                start_addr: Addr::new(mem),
+                size: (pages * 4096) as u64
+            })
+        }
+    }
+
     pub fn alloc_mem(&self, region_type: EFIMemRegionType, size: usize) -> Result<MemChunk, &'static str> {
         let mut mem: *mut u8 = ptr::null_mut();
         let status = unsafe { (self.alloc_mem)(
@@ -478,7 +557,25 @@ impl EFIBootServices {
         }
     }
 
+    /// Exits boot services and returns the final memory map
+    ///
+    /// The memory map can change between retrieving it and calling ExitBootServices,
+    /// for example, when a firmware timer event allocates memory. In that case,
+    /// the firmware rejects the map key with an INVALID_PARAMETER status and
+    /// the map has to be retrieved again.
+    ///
+    /// After the first call to ExitBootServices, the firmware's memory allocation
+    /// services can no longer be used, even if the call failed, so the buffer
+    /// for the map is allocated once, with room for the map to grow
+    ///
+    /// # References
+    ///
+    /// * The UEFI spec, version 2.7, chapter 7, section 4, ExitBootServices
     pub fn exit_boot_services(&self, image_handle: EFIHandle) -> Result<MemMap, &'static str> {
+        /// The number of times ExitBootServices is attempted before giving up
+        const MAX_EXIT_ATTEMPTS: usize = 8;
+        /// The number of extra descriptors the map buffer has room for
+        const EXTRA_DESCRIPTORS: usize = 16;
         unsafe {
         // The map_key is required to exit boot services
         let mut map_key = 0usize;
@@ -496,33 +593,42 @@ impl EFIBootServices {
             &mut descriptor_version
         );
         if status != StatusCode::STATUS_BUFFER_TOO_SMALL | StatusCode::ERROR_BIT {
-            return Err("Not too small for some reason")
+            return Err("Failed to get the size of the memory map");
         }
         // mem_map_size now contains the size of the buffer needed to store the mem_map
         // The EFI_MEMORY_TYPE as specified by the UEFI spcification
         let pool_type = EFIMemRegionType::BootServicesData;
-        // According to the UEFI spec extra space should be allocated
-        let mut map_size = mem_map_size + 500;
+        // According to the UEFI spec extra space should be allocated,
+        // because the allocation itself can add descriptors to the map
+        let buffer_size = mem_map_size + EXTRA_DESCRIPTORS * descriptor_size;
         let mut mem_map_buffer: *mut u8 = ptr::null_mut();
         // To get the memory map, space needs to be allocated to retrieve it
         let alloc_status = (self.alloc_mem)(
             pool_type,
-            map_size,
+            buffer_size,
             &mut mem_map_buffer
         );
         if alloc_status != StatusCode::STATUS_SUCCESS {
             return Err("Unable to allocate memory for the memory map");
         }
         let mem_map_buffer = mem_map_buffer.cast::<EFIMemRegion>();
-        loop {
+        for _ in 0..MAX_EXIT_ATTEMPTS {
+            // The map size is overwritten with the size of the retrieved map
+            // on every call, so it has to be reset to the size of the buffer
+            let mut map_size = buffer_size;
             // Get the memory map
-            (self.get_mem_map)(
+            let status = (self.get_mem_map)(
                 &mut map_size,
                 mem_map_buffer,
                 &mut map_key,
                 &mut descriptor_size,
                 &mut descriptor_version
             );
+            if status == StatusCode::ERROR_BIT | StatusCode::STATUS_BUFFER_TOO_SMALL {
+                return Err("Memory map outgrew the buffer allocated for it");
+            } else if status != StatusCode::STATUS_SUCCESS {
+                return Err("Failed to get the memory map");
+            }
             let boot_exit_status = (self.exit_boot_services)(
                 image_handle,
                 map_key
@@ -535,11 +641,13 @@ impl EFIBootServices {
                 };
                 return Ok(MemMap::from(mmap_descr));
             } else if boot_exit_status == StatusCode::ERROR_BIT | StatusCode::STATUS_INVALID_PARAMETER {
+                // The map key is stale because the memory map changed
                 continue;
             } else {
                 return Err("Unexpected boot exit status");
             }
         }
+        Err("Memory map kept changing while exiting boot services")
         }
     }
 }
@@ -764,9 +872,18 @@ impl EFIGraphicsOutputProtocolMode {
         self.max_mode
     }
 
+    /// The number of the mode the device is in
+    pub fn current_mode(&self) -> u32 {
+        self.mode
+    }
+
     pub fn frame_buffer_base(&self) -> u64 {
         self.frame_buffer_base
     }
+
+    pub fn frame_buffer_size(&self) -> usize {
+        self.frame_buffer_size
+    }
 }
 
 #[derive(Debug)]
@@ -796,6 +913,25 @@ impl EFIGraphicsOutputModeInfo {
     pub fn horizontal_resolution(&self) -> u32 {
         self.horizontal_resolution
     }
+
+    pub fn pixels_per_scan_line(&self) -> u32 {
+        self.pixels_per_scan_line
+    }
+
+    /// How the colors are laid out in the mode's pixels, or None if the mode
+    /// can only be drawn in with blt, instead of through a framebuffer
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        match self.pixel_format {
+            EFIGraphicsPixelFormat::PixelRGBReserved8BPC => Some(PixelFormat::Rgb),
+            EFIGraphicsPixelFormat::PixelBGRReserved8BPC => Some(PixelFormat::Bgr),
+            EFIGraphicsPixelFormat::PixelBitmask => Some(PixelFormat::Bitmask {
+                red: self.pixel_info.red_mask,
+                green: self.pixel_info.green_mask,
+                blue: self.pixel_info.blue_mask
+            }),
+            EFIGraphicsPixelFormat::PixelBltOnly | EFIGraphicsPixelFormat::PixelFormatMax => None
+        }
+    }
 }
 
 /// An enumeration that defines the pixel format of the pixel in a graphics mode
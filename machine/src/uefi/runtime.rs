@@ -0,0 +1,215 @@
+//! Abstractions for dealing with UEFI runtime services
+//!
+//! The runtime services stay available after exiting boot services.
+//! They are used here to keep settings and high scores in the firmware's
+//! non-volatile variable storage, on machines where the CMOS can't be
+//! relied on.
+//!
+//! # Addressing mode
+//!
+//! The firmware expects runtime services to be called in physical mode until
+//! SetVirtualAddressMap is called. This project keeps the firmware's identity
+//! mapped page tables after exiting boot services and never calls
+//! SetVirtualAddressMap, so the services can be called through the pointers
+//! in the system table as they are.
+//! If paging is ever changed, SetVirtualAddressMap must be called with the new
+//! mappings of the runtime regions before any of these functions are used.
+//!
+//! # References
+//!
+//! * The UEFI spec, version 2.7, chapter 8
+
+use core::ffi::c_void;
+use core::ptr;
+use super::{Status, StatusCode, EFITableHeader, Guid, get_systable};
+
+/// The vendor GUID under which the game's variables are stored
+pub const BLASTERBALL_VARIABLE_GUID: Guid = Guid {
+    first: 0x5b1a57e2,
+    second: 0x8ba1,
+    third: 0x4c6e,
+    fourth: [0x9d, 0x31, 0x0b, 0x1a, 0x57, 0xe2, 0xba, 0x11]
+};
+
+/// The maximum number of characters in a variable name, excluding
+/// the null terminator
+pub const MAX_VARIABLE_NAME_LEN: usize = 63;
+
+/// The runtime services in the EFISystemTable
+#[repr(C)]
+pub struct EFIRuntimeServices {
+    /// The table header
+    header: EFITableHeader,
+    /// These fields are not needed in this project.
+    /// They are the time services, SetVirtualAddressMap and ConvertPointer
+    unneeded0: [usize; 6],
+    /// Returns the value of a variable
+    ///
+    /// # Arguments
+    ///
+    /// * variable_name: A null terminated UCS-2 string that is the name of the variable
+    /// * vendor_guid: A unique identifier for the vendor
+    /// * attributes: If not null, on return, holds the attributes of the variable
+    /// * data_size: On input, the size in bytes of the return data buffer.
+    ///     On output, the size of the data returned in data, or the size of the buffer
+    ///     needed if the buffer was too small
+    /// * data: The buffer to return the contents of the variable in
+    get_variable: unsafe extern "efiapi" fn(
+        variable_name: *const u16,
+        vendor_guid: &Guid,
+        attributes: *mut u32,
+        data_size: &mut usize,
+        data: *mut c_void
+    ) -> Status,
+    unneeded1: [usize; 1],
+    /// Sets the value of a variable
+    ///
+    /// # Arguments
+    ///
+    /// * variable_name: A null terminated UCS-2 string that is the name of the variable
+    /// * vendor_guid: A unique identifier for the vendor
+    /// * attributes: The attributes to set for the variable
+    /// * data_size: The size in bytes of the data buffer.
+    ///     A size of 0 causes the variable to be deleted
+    /// * data: The contents of the variable
+    set_variable: unsafe extern "efiapi" fn(
+        variable_name: *const u16,
+        vendor_guid: &Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *const c_void
+    ) -> Status,
+    /// Not needed in this project
+    unneeded2: [usize; 1],
+    /// Resets the whole platform
+    ///
+    /// # Arguments
+    ///
+    /// * reset_type: The kind of reset to perform
+    /// * reset_status: The status code for the reset
+    /// * data_size: The size in bytes of reset_data
+    /// * reset_data: Optional data describing the reason for the reset
+    reset_system: unsafe extern "efiapi" fn(
+        reset_type: ResetType,
+        reset_status: Status,
+        data_size: usize,
+        reset_data: *const c_void
+    ) -> !,
+    /// These fields are not needed in this project
+    unneeded3: [usize; 3]
+}
+
+impl EFIRuntimeServices {
+    /// Reads the variable `name` of vendor `vendor_guid` into `buffer`
+    ///
+    /// Returns the number of bytes read, or None if the variable doesn't exist
+    pub fn get_variable(&self, name: &str, vendor_guid: &Guid, buffer: &mut [u8]) -> Result<Option<usize>, &'static str> {
+        let name = VariableName::new(name)?;
+        let mut data_size = buffer.len();
+        let status = unsafe { (self.get_variable)(
+            name.as_ptr(),
+            vendor_guid,
+            ptr::null_mut(),
+            &mut data_size,
+            buffer.as_mut_ptr().cast::<c_void>()
+        ) };
+        if status == StatusCode::STATUS_SUCCESS {
+            Ok(Some(data_size))
+        } else if status == StatusCode::ERROR_BIT | StatusCode::STATUS_NOT_FOUND {
+            Ok(None)
+        } else if status == StatusCode::ERROR_BIT | StatusCode::STATUS_BUFFER_TOO_SMALL {
+            Err("Buffer is too small for the variable")
+        } else {
+            Err("Failed to get variable")
+        }
+    }
+
+    /// Writes `data` into the variable `name` of vendor `vendor_guid`
+    ///
+    /// The variable is non-volatile, so it persists across reboots.
+    /// An empty `data` deletes the variable
+    pub fn set_variable(&self, name: &str, vendor_guid: &Guid, data: &[u8]) -> Result<(), &'static str> {
+        let name = VariableName::new(name)?;
+        let attributes = VariableAttributes::NON_VOLATILE
+            | VariableAttributes::BOOTSERVICE_ACCESS
+            | VariableAttributes::RUNTIME_ACCESS;
+        let status = unsafe { (self.set_variable)(
+            name.as_ptr(),
+            vendor_guid,
+            attributes,
+            data.len(),
+            data.as_ptr().cast::<c_void>()
+        ) };
+        if StatusCode::is_error(status) {
+            Err("Failed to set variable")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Resets the whole platform. Never returns
+    pub fn reset_system(&self, reset_type: ResetType) -> ! {
+        unsafe { (self.reset_system)(reset_type, StatusCode::STATUS_SUCCESS, 0, ptr::null()) }
+    }
+}
+
+/// Reads the game's variable `name` into `buffer`
+///
+/// Returns the number of bytes read, or None if the variable doesn't exist
+pub fn get_variable(name: &str, buffer: &mut [u8]) -> Result<Option<usize>, &'static str> {
+    let systable = get_systable().ok_or("System table is not initialized")?;
+    systable.runtime_services().get_variable(name, &BLASTERBALL_VARIABLE_GUID, buffer)
+}
+
+/// Writes `data` into the game's variable `name`
+pub fn set_variable(name: &str, data: &[u8]) -> Result<(), &'static str> {
+    let systable = get_systable().ok_or("System table is not initialized")?;
+    systable.runtime_services().set_variable(name, &BLASTERBALL_VARIABLE_GUID, data)
+}
+
+/// The kinds of resets that can be performed with `EFIRuntimeServices::reset_system`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u32)]
+pub enum ResetType {
+    /// Every circuit in the system is set to its initial state
+    Cold = 0,
+    /// The processors are set to their initial state, without necessarily
+    /// resetting the rest of the system
+    Warm = 1,
+    /// The system is powered off
+    Shutdown = 2
+}
+
+struct VariableAttributes;
+
+impl VariableAttributes {
+    const NON_VOLATILE: u32 = 0x1;
+    const BOOTSERVICE_ACCESS: u32 = 0x2;
+    const RUNTIME_ACCESS: u32 = 0x4;
+}
+
+/// A null terminated UCS-2 variable name
+struct VariableName([u16; MAX_VARIABLE_NAME_LEN + 1]);
+
+impl VariableName {
+    /// Converts `name` into UCS-2
+    ///
+    /// Only ascii names are supported
+    fn new(name: &str) -> Result<Self, &'static str> {
+        if name.is_empty() || name.len() > MAX_VARIABLE_NAME_LEN {
+            return Err("Variable name must have 1 to 63 characters");
+        }
+        let mut buffer = [0u16; MAX_VARIABLE_NAME_LEN + 1];
+        for (i, c) in name.bytes().enumerate() {
+            if !c.is_ascii() {
+                return Err("Variable name must be ascii");
+            }
+            buffer[i] = c as u16;
+        }
+        Ok(Self(buffer))
+    }
+
+    fn as_ptr(&self) -> *const u16 {
+        self.0.as_ptr()
+    }
+}
@@ -0,0 +1,177 @@
+//! Waiting for the screen's vertical retrace
+//!
+//! The monitor shows the framebuffer a row at a time, top to bottom. If the
+//! framebuffer changes halfway through, the top of the screen shows the old
+//! frame and the bottom the new one, which is tearing. Between the last row
+//! and the first there's a short blanking interval, the vertical retrace,
+//! and everything changed in it shows up together.
+//!
+//! VGA compatible cards tell whether they're in the retrace in input status
+//! register 1, which is what's used in BIOS mode. UEFI's Graphics Output
+//! Protocol has no way of telling, and the card behind it may not be VGA
+//! compatible, so if the register never changes, the retrace is taken to come
+//! at the usual 60Hz by the time stamp counter. That still keeps the screen from
+//! changing more than once a refresh, though not always during blanking
+//!
+//! # References
+//!
+//! * FreeVGA's external registers <http://www.osdever.net/FreeVGA/vga/extreg.htm>
+
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use crate::port::{Port, PortReadWrite};
+use crate::time;
+
+/// Input status register 1, when the card is in color mode
+const INPUT_STATUS_1_PORT: u16 = 0x3da;
+/// Set while the card is in the vertical retrace
+const VERTICAL_RETRACE_BIT: u8 = 1 << 3;
+/// The time between retraces at 60Hz
+const DEFAULT_REFRESH_US: u64 = 16_667;
+/// The refresh periods, from 85Hz to 24Hz, that a measurement is believed for
+const REFRESH_US_RANGE: core::ops::RangeInclusive<u64> = 11_764..=41_667;
+/// How long the register is watched for a change before it's given up on,
+/// which is a few refreshes even at 24Hz
+const DETECTION_TIMEOUT_US: u64 = 150_000;
+/// The rows of a 640x480 mode that are drawn, out of the rows in a refresh,
+/// the rest of which are blanking
+const VISIBLE_ROWS: u64 = 480;
+const TOTAL_ROWS: u64 = 525;
+
+/// How the vertical retrace is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RetraceSource {
+    /// Neither `init` nor `init_timed` has set it up, so there's no waiting
+    None = 0,
+    /// Input status register 1 is read
+    Port = 1,
+    /// The retrace is taken to come every refresh period by the time stamp counter
+    Timed = 2
+}
+
+static SOURCE: AtomicU8 = AtomicU8::new(RetraceSource::None as u8);
+/// The number of microseconds between the starts of 2 retraces
+static REFRESH_US: AtomicU64 = AtomicU64::new(DEFAULT_REFRESH_US);
+/// The uptime when a timed refresh started, which every other one is counted from
+static TIMED_EPOCH_US: AtomicU64 = AtomicU64::new(0);
+
+/// Checks if the card shows the vertical retrace in input status register 1,
+/// and measures the refresh rate if it does
+///
+/// Returns an error if the register never changed, in which case
+/// `init_timed` can stand in for it
+pub fn init() -> Result<(), &'static str> {
+    if !wait_for_retrace_bit(false, DETECTION_TIMEOUT_US) || !wait_for_retrace_bit(true, DETECTION_TIMEOUT_US) {
+        return Err("The display doesn't show its vertical retrace");
+    }
+    let start = time::uptime_us();
+    // From the start of one retrace to the start of the next
+    let measured = wait_for_retrace_bit(false, DETECTION_TIMEOUT_US)
+        && wait_for_retrace_bit(true, DETECTION_TIMEOUT_US);
+    let refresh_us = time::uptime_us().saturating_sub(start);
+    if measured && REFRESH_US_RANGE.contains(&refresh_us) {
+        REFRESH_US.store(refresh_us, Ordering::SeqCst);
+    }
+    SOURCE.store(RetraceSource::Port as u8, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Takes the retrace to come every 60th of a second, for displays
+/// that don't show it
+pub fn init_timed() {
+    REFRESH_US.store(DEFAULT_REFRESH_US, Ordering::SeqCst);
+    TIMED_EPOCH_US.store(time::uptime_us(), Ordering::SeqCst);
+    SOURCE.store(RetraceSource::Timed as u8, Ordering::SeqCst);
+}
+
+pub fn source() -> RetraceSource {
+    match SOURCE.load(Ordering::SeqCst) {
+        1 => RetraceSource::Port,
+        2 => RetraceSource::Timed,
+        _ => RetraceSource::None
+    }
+}
+
+/// The number of microseconds between the starts of 2 retraces
+pub fn refresh_period_us() -> u64 {
+    REFRESH_US.load(Ordering::SeqCst)
+}
+
+/// Tells whether or not the screen is in the vertical retrace, so it can be
+/// changed without tearing
+///
+/// Without a way of telling, it's always taken to be
+pub fn in_vertical_retrace() -> bool {
+    match source() {
+        RetraceSource::Port => retrace_bit_is_set(),
+        RetraceSource::Timed => {
+            let elapsed = time::uptime_us().saturating_sub(TIMED_EPOCH_US.load(Ordering::SeqCst));
+            in_timed_blanking(elapsed, refresh_period_us())
+        }
+        RetraceSource::None => true
+    }
+}
+
+/// Waits for the next vertical retrace to start
+///
+/// Doesn't wait at all without a way of telling when it does, and waits
+/// no longer than a couple of refreshes if the card stops showing it
+pub fn wait_for_vertical_retrace() {
+    match source() {
+        RetraceSource::Port => {
+            let timeout = 2 * refresh_period_us();
+            // Waiting for the one going on to end first, so a whole retrace is left
+            if wait_for_retrace_bit(false, timeout) {
+                wait_for_retrace_bit(true, timeout);
+            }
+        }
+        RetraceSource::Timed => {
+            let elapsed = time::uptime_us().saturating_sub(TIMED_EPOCH_US.load(Ordering::SeqCst));
+            time::sleep_us(us_until_next_refresh(elapsed, refresh_period_us()));
+        }
+        RetraceSource::None => ()
+    }
+}
+
+fn retrace_bit_is_set() -> bool {
+    let port: Port<u8> = Port::new(INPUT_STATUS_1_PORT);
+    port.read() & VERTICAL_RETRACE_BIT != 0
+}
+
+/// Waits for the retrace bit to be `set`, for about `timeout_us` at most
+///
+/// The wait is counted in reads of the register, which take about a
+/// microsecond, so it ends even if interrupts are disabled and time is
+/// kept in ticks. Returns false if it timed out
+fn wait_for_retrace_bit(set: bool, timeout_us: u64) -> bool {
+    (0..timeout_us).any(|_| retrace_bit_is_set() == set)
+}
+
+/// Tells whether `elapsed_us` after the start of a timed refresh is within the
+/// blanking at the start of a refresh of `refresh_us`
+fn in_timed_blanking(elapsed_us: u64, refresh_us: u64) -> bool {
+    elapsed_us % refresh_us < refresh_us * (TOTAL_ROWS - VISIBLE_ROWS) / TOTAL_ROWS
+}
+
+/// The number of microseconds from `elapsed_us` after the start of a timed
+/// refresh until the start of the next refresh of `refresh_us`
+fn us_until_next_refresh(elapsed_us: u64, refresh_us: u64) -> u64 {
+    refresh_us - elapsed_us % refresh_us
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timed_retrace() {
+        // About 1.4ms of blanking at 60Hz
+        assert!(in_timed_blanking(0, DEFAULT_REFRESH_US));
+        assert!(in_timed_blanking(1_400, DEFAULT_REFRESH_US));
+        assert!(!in_timed_blanking(1_500, DEFAULT_REFRESH_US));
+        assert!(in_timed_blanking(DEFAULT_REFRESH_US * 3 + 100, DEFAULT_REFRESH_US));
+        assert_eq!(us_until_next_refresh(0, DEFAULT_REFRESH_US), DEFAULT_REFRESH_US);
+        assert_eq!(us_until_next_refresh(16_000, DEFAULT_REFRESH_US), 667);
+        assert_eq!(us_until_next_refresh(DEFAULT_REFRESH_US * 2 + 1, DEFAULT_REFRESH_US), DEFAULT_REFRESH_US - 1);
+    }
+}
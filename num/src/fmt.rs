@@ -0,0 +1,149 @@
+//! Formatting of numbers into buffers supplied by the caller
+//!
+//! Meant for places that format numbers often, like the HUD and register dumps,
+//! where going through `core::fmt` is too slow. Every function writes the
+//! number at the end of the buffer and returns the part of the buffer that
+//! holds it
+//!
+//! ## Panics
+//!
+//! Every function will panic if the buffer is too small for the number
+
+/// The most bytes a `u32` takes in decimal
+pub const U32_MAX_LEN: usize = 10;
+/// The most bytes a `u64` takes in decimal
+pub const U64_MAX_LEN: usize = 20;
+/// The most bytes an `i64` takes in decimal, with the sign
+pub const I64_MAX_LEN: usize = 20;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Writes `n` in decimal
+///
+/// ```rust
+/// use num::fmt;
+///
+/// let mut buf = [0; fmt::U32_MAX_LEN];
+/// assert_eq!(fmt::write_u32(&mut buf, 4096), "4096");
+/// ```
+pub fn write_u32(buf: &mut [u8], n: u32) -> &str {
+    write_u64(buf, n as u64)
+}
+
+/// Writes `n` in decimal
+pub fn write_u64(buf: &mut [u8], mut n: u64) -> &str {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    as_str(&buf[start..])
+}
+
+/// Writes `n` in decimal, with a '-' before it if it's negative
+///
+/// ```rust
+/// use num::fmt;
+///
+/// let mut buf = [0; fmt::I64_MAX_LEN];
+/// assert_eq!(fmt::write_i64(&mut buf, -273), "-273");
+/// ```
+pub fn write_i64(buf: &mut [u8], n: i64) -> &str {
+    let len = write_u64(buf, n.unsigned_abs()).len();
+    with_sign(buf, len, n < 0)
+}
+
+/// Writes `n` in hexadecimal, padded with 0s to 2 digits
+pub fn write_hex_u8(buf: &mut [u8], n: u8) -> &str {
+    write_hex(buf, n as u64, 2)
+}
+
+/// Writes `n` in hexadecimal, padded with 0s to 4 digits
+pub fn write_hex_u16(buf: &mut [u8], n: u16) -> &str {
+    write_hex(buf, n as u64, 4)
+}
+
+/// Writes `n` in hexadecimal, padded with 0s to 8 digits
+///
+/// ```rust
+/// use num::fmt;
+///
+/// let mut buf = [0; 8];
+/// assert_eq!(fmt::write_hex_u32(&mut buf, 0x10de), "000010de");
+/// ```
+pub fn write_hex_u32(buf: &mut [u8], n: u32) -> &str {
+    write_hex(buf, n as u64, 8)
+}
+
+/// Writes `n` in hexadecimal, padded with 0s to 16 digits
+pub fn write_hex_u64(buf: &mut [u8], n: u64) -> &str {
+    write_hex(buf, n, 16)
+}
+
+/// Writes `n` in lowercase hexadecimal, padded with 0s to at least `min_digits` digits
+pub fn write_hex(buf: &mut [u8], mut n: u64, min_digits: usize) -> &str {
+    let mut start = buf.len();
+    let mut digits = 0;
+    while n != 0 || digits < min_digits.max(1) {
+        start -= 1;
+        buf[start] = HEX_DIGITS[(n & 0xf) as usize];
+        n >>= 4;
+        digits += 1;
+    }
+    as_str(&buf[start..])
+}
+
+/// Writes the fixed point number `n`, whose last `decimals` decimal digits
+/// are the fractional part
+///
+/// There can be more decimals than digits in any `i64`, in which case
+/// the integer part is 0 and the fractional part is padded with 0s
+///
+/// ```rust
+/// use num::fmt;
+///
+/// let mut buf = [0; 16];
+/// assert_eq!(fmt::write_fixed(&mut buf, 1234, 2), "12.34");
+/// assert_eq!(fmt::write_fixed(&mut buf, -5, 3), "-0.005");
+/// ```
+pub fn write_fixed(buf: &mut [u8], n: i64, decimals: u32) -> &str {
+    if decimals == 0 {
+        return write_i64(buf, n);
+    }
+    let abs = n.unsigned_abs();
+    // 10^20 doesn't fit in a u64, but it's more than any u64 anyway
+    let (int, mut frac) = match 10u64.checked_pow(decimals) {
+        Some(scale) => (abs / scale, abs % scale),
+        None => (0, abs)
+    };
+    let mut start = buf.len();
+    for _ in 0..decimals {
+        start -= 1;
+        buf[start] = b'0' + (frac % 10) as u8;
+        frac /= 10;
+    }
+    start -= 1;
+    buf[start] = b'.';
+    let int_len = write_u64(&mut buf[..start], int).len();
+    let len = buf.len() - start + int_len;
+    with_sign(buf, len, n < 0)
+}
+
+/// Puts a '-' before the `len` bytes at the end of `buf` if `negative` is true
+fn with_sign(buf: &mut [u8], len: usize, negative: bool) -> &str {
+    let mut start = buf.len() - len;
+    if negative {
+        start -= 1;
+        buf[start] = b'-';
+    }
+    as_str(&buf[start..])
+}
+
+fn as_str(bytes: &[u8]) -> &str {
+    // Only ASCII digits, '.' and '-' are ever written
+    unsafe { core::str::from_utf8_unchecked(bytes) }
+}
@@ -6,6 +6,11 @@
 #[cfg(test)]
 mod tests;
 
+mod rng;
+pub use rng::Rng;
+
+pub mod fmt;
+
 use core::mem;
 use core::ops::{Add, Sub, Rem, Div, Mul, Range, RangeBounds, Bound};
 
@@ -97,6 +102,97 @@ pub trait Integer: NumOps + PartialEq + PartialOrd + Sized {
     /// Will panic if the range is out of range of the bit length
     fn get_bits<R: RangeBounds<usize>>(&self, range: R) -> Self;
 
+    /// Returns the number of zeros before the most significant set bit
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(Integer::leading_zeros(0b0001_0000u8), 3);
+    /// assert_eq!(Integer::leading_zeros(0u16), 16);
+    /// ```
+    fn leading_zeros(self) -> u32;
+
+    /// Returns the number of zero bits below the least significant set bit,
+    /// or the number of bits if there is none
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(Integer::trailing_zeros(0b0001_0000u8), 4);
+    /// assert_eq!(Integer::trailing_zeros(0u32), 32);
+    /// ```
+    fn trailing_zeros(self) -> u32;
+
+    /// Returns the number of set bits
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(Integer::count_ones(0b1011_0001u8), 4);
+    /// ```
+    fn count_ones(self) -> u32;
+
+    /// Shifts the bits to the left by n, moving the bits shifted out
+    /// of the most significant end to the least significant end
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(Integer::rotate_left(0b1000_0001u8, 1), 0b0000_0011);
+    /// ```
+    fn rotate_left(self, n: u32) -> Self;
+
+    /// Shifts the bits to the right by n, moving the bits shifted out
+    /// of the least significant end to the most significant end
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(Integer::rotate_right(0b1000_0001u8, 1), 0b1100_0000);
+    /// ```
+    fn rotate_right(self, n: u32) -> Self;
+
+    /// Rounds the number up to the nearest multiple of align
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(130u32.align_up(128), 256);
+    /// assert_eq!(4096u64.align_up(4096), 4096);
+    /// ```
+    ///
+    /// ## Panics
+    ///
+    /// Will panic if align is not a power of 2
+    fn align_up(self, align: Self) -> Self;
+
+    /// Rounds the number down to the nearest multiple of align
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert_eq!(0x1fffu64.align_down(0x1000), 0x1000);
+    /// ```
+    ///
+    /// ## Panics
+    ///
+    /// Will panic if align is not a power of 2
+    fn align_down(self, align: Self) -> Self;
+
+    /// Checks if the number is a multiple of align
+    ///
+    /// ```rust
+    /// use num::Integer;
+    ///
+    /// assert!(256usize.is_aligned(128));
+    /// assert!(!100usize.is_aligned(128));
+    /// ```
+    ///
+    /// ## Panics
+    ///
+    /// Will panic if align is not a power of 2
+    fn is_aligned(self, align: Self) -> bool;
+
     fn as_u8(self) -> u8;
 
     fn as_u16(self) -> u16;
@@ -204,6 +300,40 @@ macro_rules! impl_int {
                     .overflowing_shr(right_shift).0
             }
 
+            fn leading_zeros(self) -> u32 {
+                <$T>::leading_zeros(self)
+            }
+
+            fn trailing_zeros(self) -> u32 {
+                <$T>::trailing_zeros(self)
+            }
+
+            fn count_ones(self) -> u32 {
+                <$T>::count_ones(self)
+            }
+
+            fn rotate_left(self, n: u32) -> Self {
+                <$T>::rotate_left(self, n)
+            }
+
+            fn rotate_right(self, n: u32) -> Self {
+                <$T>::rotate_right(self, n)
+            }
+
+            fn align_up(self, align: Self) -> Self {
+                assert!(align > 0 && align & (align - 1) == 0, "Alignment must be a power of 2");
+                (self + (align - 1)) & !(align - 1)
+            }
+
+            fn align_down(self, align: Self) -> Self {
+                assert!(align > 0 && align & (align - 1) == 0, "Alignment must be a power of 2");
+                self & !(align - 1)
+            }
+
+            fn is_aligned(self, align: Self) -> bool {
+                self.align_down(align) == self
+            }
+
             fn sinf32(self) -> f32 {
                 self.as_f32().sinf32()
             }
@@ -0,0 +1,62 @@
+//! A small pseudo random number generator
+
+use core::ops::Range;
+
+/// A xorshift64* pseudo random number generator
+///
+/// Fast and good enough for games, but the numbers are predictable
+/// from a few outputs, so it's not suitable for cryptography.
+/// The same seed always gives the same sequence of numbers
+///
+/// # References
+///
+/// * Sebastiano Vigna, An experimental exploration of Marsaglia's xorshift generators, scrambled
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    /// Creates a generator whose numbers are determined by `seed`
+    ///
+    /// ```rust
+    /// use num::Rng;
+    ///
+    /// let mut a = Rng::new(42);
+    /// let mut b = Rng::new(42);
+    /// assert_eq!(a.next_u64(), b.next_u64());
+    /// ```
+    pub fn new(seed: u64) -> Self {
+        // A state of 0 would only ever produce 0s
+        let state = if seed == 0 { 0x9e3779b97f4a7c15 } else { seed };
+        Self { state }
+    }
+
+    /// Returns the next number in the sequence
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a number in `range`
+    ///
+    /// ```rust
+    /// use num::Rng;
+    ///
+    /// let mut rng = Rng::new(7);
+    /// let n = rng.gen_range(180..360);
+    /// assert!(n >= 180 && n < 360);
+    /// ```
+    ///
+    /// ## Panics
+    ///
+    /// Will panic if the range is empty
+    pub fn gen_range(&mut self, range: Range<usize>) -> usize {
+        assert!(range.start < range.end, "Can't generate a number in an empty range");
+        let len = (range.end - range.start) as u64;
+        // The upper bits are the most random ones
+        range.start + ((self.next_u64() >> 32) % len) as usize
+    }
+}
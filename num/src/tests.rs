@@ -1,4 +1,4 @@
-use crate::{Integer, Float, BitState};
+use crate::{Integer, Float, BitState, Rng, fmt};
 
 #[test]
 fn test_bit_lengths(){
@@ -49,4 +49,125 @@ fn test_cast() {
     assert_eq!(12usize.as_i16(), 12i16);
     assert_eq!(285usize.sinf32().as_i16(), -3i16);
     assert_eq!((285 + 360usize).sinf32().as_i16(), -3i16);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_bit_counting() {
+    fn highest_set_bit<T: Integer>(n: T) -> u32 {
+        T::BIT_LENGTH as u32 - n.leading_zeros() - 1
+    }
+    assert_eq!(highest_set_bit(0x1000u64), 12);
+    assert_eq!(highest_set_bit(1u8), 0);
+    assert_eq!(Integer::trailing_zeros(0x1000u64), 12);
+    assert_eq!(Integer::count_ones(-1i32), 32);
+    assert_eq!(Integer::count_ones(0usize), 0);
+}
+
+#[test]
+fn test_rotate() {
+    fn rotate_twice<T: Integer>(n: T) -> T {
+        n.rotate_left(3).rotate_right(3)
+    }
+    assert_eq!(rotate_twice(0xdeadbeefu32), 0xdeadbeef);
+    assert_eq!(Integer::rotate_left(0x8000_0000_0000_0001u64, 4), 0x18);
+    assert_eq!(Integer::rotate_right(0x18u64, 4), 0x8000_0000_0000_0001);
+}
+
+#[test]
+fn test_alignment() {
+    assert_eq!(0u64.align_up(4096), 0);
+    assert_eq!(1u64.align_up(4096), 4096);
+    assert_eq!(4097u64.align_down(4096), 4096);
+    assert_eq!(127usize.align_down(128), 0);
+    assert_eq!(0xfffffff7u32.align_down(16), 0xfffffff0);
+    assert!(0x2000u64.is_aligned(0x1000));
+    assert!(!0x2001u64.is_aligned(2));
+    assert_eq!(5i32.align_up(4), 8);
+}
+
+#[test]
+#[should_panic]
+fn test_non_power_of_2_alignment() {
+    10u32.align_up(3);
+}
+
+#[test]
+fn test_rng() {
+    let mut a = Rng::new(0xdeadbeef);
+    let mut b = Rng::new(0xdeadbeef);
+    for _ in 0..100 {
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+    let mut c = Rng::new(0xdeadbef0);
+    assert_ne!(a.next_u64(), c.next_u64());
+
+    // A seed of 0 must not get stuck
+    let mut zero = Rng::new(0);
+    assert_ne!(zero.next_u64(), 0);
+    assert_ne!(zero.next_u64(), zero.next_u64());
+}
+
+#[test]
+fn test_rng_gen_range() {
+    let mut rng = Rng::new(1);
+    let mut seen = [false; 10];
+    for _ in 0..1000 {
+        let n = rng.gen_range(20..30);
+        assert!(n >= 20 && n < 30);
+        seen[n - 20] = true;
+    }
+    assert!(seen.iter().all(|seen| *seen));
+    assert_eq!(rng.gen_range(5..6), 5);
+}
+
+#[test]
+#[should_panic]
+fn test_rng_gen_empty_range() {
+    Rng::new(1).gen_range(3..3);
+}
+
+#[test]
+fn test_fmt_decimal(){
+    let mut buf = [0; fmt::U64_MAX_LEN];
+    assert_eq!(fmt::write_u32(&mut buf, 0), "0");
+    assert_eq!(fmt::write_u32(&mut buf, u32::MAX), "4294967295");
+    assert_eq!(fmt::write_u64(&mut buf, u64::MAX), "18446744073709551615");
+    assert_eq!(fmt::write_i64(&mut buf, 42), "42");
+    assert_eq!(fmt::write_i64(&mut buf, i64::MIN), "-9223372036854775808");
+}
+
+#[test]
+fn test_fmt_hex(){
+    let mut buf = [0; 16];
+    assert_eq!(fmt::write_hex_u8(&mut buf, 0xa), "0a");
+    assert_eq!(fmt::write_hex_u16(&mut buf, 0xbeef), "beef");
+    assert_eq!(fmt::write_hex_u64(&mut buf, u64::MAX), "ffffffffffffffff");
+    assert_eq!(fmt::write_hex(&mut buf, 0, 0), "0");
+    assert_eq!(fmt::write_hex(&mut buf, 0x12345, 2), "12345");
+}
+
+#[test]
+fn test_fmt_fixed(){
+    let mut buf = [0; 24];
+    assert_eq!(fmt::write_fixed(&mut buf, 1234, 2), "12.34");
+    assert_eq!(fmt::write_fixed(&mut buf, 7, 0), "7");
+    assert_eq!(fmt::write_fixed(&mut buf, 5, 2), "0.05");
+    assert_eq!(fmt::write_fixed(&mut buf, -1050, 3), "-1.050");
+}
+
+#[test]
+fn test_fmt_fixed_with_many_decimals(){
+    let mut buf = [0; 48];
+    assert_eq!(fmt::write_fixed(&mut buf, i64::MAX, 19), "0.9223372036854775807");
+    assert_eq!(fmt::write_fixed(&mut buf, i64::MIN, 19), "-0.9223372036854775808");
+    assert_eq!(fmt::write_fixed(&mut buf, 42, 20), "0.00000000000000000042");
+    assert_eq!(fmt::write_fixed(&mut buf, i64::MIN, 25), "-0.0000009223372036854775808");
+    assert_eq!(fmt::write_fixed(&mut buf, 0, 30), "0.000000000000000000000000000000");
+}
+
+#[test]
+#[should_panic]
+fn test_fmt_buffer_too_small(){
+    let mut buf = [0; 3];
+    fmt::write_u32(&mut buf, 1000);
+}
@@ -98,12 +98,48 @@ impl SubAssign for Point {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Rectangle {
     pub top_left: Point,
     pub width: usize,
     pub height: usize
 }
 
+impl Rectangle {
+    /// Creates the smallest rectangle that contains a `width` by `height`
+    /// rectangle as it moves from `from` to `to`
+    ///
+    /// Checking the swept rectangle instead of only the final position
+    /// catches fast objects that would otherwise pass through thin ones
+    /// in a single update
+    pub fn swept(from: Point, to: Point, width: usize, height: usize) -> Self {
+        let top_left = Point(from.x().min(to.x()), from.y().min(to.y()));
+        Self {
+            top_left,
+            width: (from.x() - to.x()).abs() as usize + width,
+            height: (from.y() - to.y()).abs() as usize + height
+        }
+    }
+
+    /// The x coordinate just past the rectangle's right edge
+    pub fn right(&self) -> i16 {
+        self.top_left.x() + self.width as i16
+    }
+
+    /// The y coordinate just past the rectangle's bottom edge
+    pub fn bottom(&self) -> i16 {
+        self.top_left.y() + self.height as i16
+    }
+
+    /// Checks if this rectangle and `other` overlap
+    pub fn intersects(&self, other: &Rectangle) -> bool {
+        self.top_left.x() < other.top_left.x() + other.width as i16
+            && other.top_left.x() < self.top_left.x() + self.width as i16
+            && self.top_left.y() < other.top_left.y() + other.height as i16
+            && other.top_left.y() < self.top_left.y() + self.height as i16
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -183,4 +219,39 @@ mod tests {
         x -= y;
         assert_eq!(x, Point(0, 1));
     }
+
+    #[test]
+    fn test_swept_rectangle() {
+        let rect = Rectangle::swept(Point(10, 20), Point(4, 30), 3, 2);
+        assert_eq!(rect, Rectangle { top_left: Point(4, 20), width: 9, height: 12 });
+
+        let rect = Rectangle::swept(Point(1, 1), Point(1, 1), 3, 2);
+        assert_eq!(rect, Rectangle { top_left: Point(1, 1), width: 3, height: 2 });
+    }
+
+    #[test]
+    fn test_rectangle_intersection() {
+        let a = Rectangle { top_left: Point(0, 0), width: 10, height: 10 };
+        let b = Rectangle { top_left: Point(9, 9), width: 5, height: 5 };
+        assert!(a.intersects(&b));
+        assert!(b.intersects(&a));
+
+        // Touching edges don't overlap
+        let c = Rectangle { top_left: Point(10, 0), width: 5, height: 5 };
+        assert!(!a.intersects(&c));
+
+        // A ball moving through a thin paddle in one step
+        let paddle = Rectangle { top_left: Point(50, 100), width: 30, height: 2 };
+        let ball_after_step = Rectangle { top_left: Point(60, 110), width: 4, height: 4 };
+        assert!(!ball_after_step.intersects(&paddle));
+        let ball_sweep = Rectangle::swept(Point(60, 90), Point(60, 110), 4, 4);
+        assert!(ball_sweep.intersects(&paddle));
+    }
+
+    #[test]
+    fn test_rectangle_edges() {
+        let rect = Rectangle { top_left: Point(-2, 3), width: 10, height: 4 };
+        assert_eq!(rect.right(), 8);
+        assert_eq!(rect.bottom(), 7);
+    }
 }
\ No newline at end of file
@@ -2,6 +2,7 @@ import argparse
 import subprocess
 import os
 import pathlib
+import shutil
 
 
 root_dir = pathlib.Path(__file__).parent
@@ -10,12 +11,20 @@ parser.add_argument('--bios', action='store_true', help='Build this project with
 parser.add_argument('--debug', action='store_true', help='Run in qemu debug mode?')
 parser.add_argument('--build-only', action='store_true', help='Build project without running it')
 parser.add_argument('--release', action='store_true', help='Build the project for release')
+parser.add_argument('--all', action='store_true', help='Build both the BIOS disk image and the UEFI application into target/images')
+parser.add_argument('--port-audit', action='store_true', help='Record every I/O port access and dump them to the serial log on a panic')
+parser.add_argument('--no-hda', action='store_true', help='Run without the HDA device, for testing with the software mixer')
+parser.add_argument('--ac97', action='store_true', help="Run with an AC'97 controller instead of the HDA device")
+
+BIOS_TARGET = f'{root_dir}/x86_64-bios-target.json'
+UEFI_TARGET = 'x86_64-unknown-uefi'
 
 
 def build_with_bios(base_cargo_args, release=False) -> int:
     sub_dir = 'release' if release else 'debug'
     BUILD_DIR = f'{root_dir}/target/x86_64-bios-target/{sub_dir}'
-    cargo = [*base_cargo_args, '--features', 'bios']
+    # The bootloader's dependencies get their bios features from the target
+    cargo = [*base_cargo_args, '--target', BIOS_TARGET]
     cargo_env = dict(os.environ, RUSTFLAGS=f'-C link-args={root_dir}/linker.ld')
     objcopy_strip_debug = ['objcopy', '--only-keep-debug', f'{BUILD_DIR}/bootloader', f'{BUILD_DIR}/bmb_sym']
     objcopy_output_binary = ['objcopy', '-O', 'binary', f'{BUILD_DIR}/bootloader', f'{BUILD_DIR}/bmb_bin']
@@ -36,7 +45,24 @@ def run_with_bios(base_qemu_args, release=False) -> None:
 
 
 def build_with_uefi(base_cargo_args) -> int:
-    return subprocess.run(base_cargo_args).returncode
+    return subprocess.run([*base_cargo_args, '--target', UEFI_TARGET]).returncode
+
+
+def build_all(base_cargo_args, release=False) -> int:
+    """Builds the BIOS disk image and the UEFI application and copies them into target/images"""
+    exit_code = build_with_bios(base_cargo_args, release)
+    if exit_code != 0:
+        return exit_code
+    exit_code = build_with_uefi(base_cargo_args)
+    if exit_code != 0:
+        return exit_code
+    sub_dir = 'release' if release else 'debug'
+    images_dir = root_dir / 'target' / 'images'
+    images_dir.mkdir(parents=True, exist_ok=True)
+    shutil.copy(f'{root_dir}/target/x86_64-bios-target/{sub_dir}/bmb_bin', images_dir / 'blasterball-bios.img')
+    shutil.copy(f'{root_dir}/target/{UEFI_TARGET}/{sub_dir}/bootloader.efi', images_dir / 'bootloader.efi')
+    print(f'Images written to {images_dir}')
+    return 0
 
 def run_with_uefi(base_qemu_args, release=False) -> None:
     sub_dir = 'release' if release else 'debug'
@@ -55,18 +81,29 @@ def run_with_uefi(base_qemu_args, release=False) -> None:
 
 if __name__ == '__main__':
     args = parser.parse_args()
-    target = f'{root_dir}/x86_64-bios-target.json' if args.bios else 'x86_64-unknown-uefi'
     base_cargo_args = [
-        'cargo', '+nightly-2022-08-26', 'b', '-p', 'bootloader', '--target', target,
+        'cargo', '+nightly-2022-08-26', 'b', '-p', 'bootloader',
         '-Zbuild-std=core,compiler_builtins', '-Zbuild-std-features=compiler-builtins-mem',
     ]
-    base_qemu_args = ['qemu-system-x86_64', 
-        '-device', 'ich9-intel-hda,debug=4', '-device', 'hda-micro', '-device', 'hda-micro']
+    base_qemu_args = ['qemu-system-x86_64']
+    if args.ac97:
+        base_qemu_args += ['-device', 'AC97']
+    elif not args.no_hda:
+        base_qemu_args += ['-device', 'ich9-intel-hda,debug=4', '-device', 'hda-micro', '-device', 'hda-micro']
     if args.release:
         base_cargo_args += ['--release']
+    if args.port_audit:
+        base_cargo_args += ['--features', 'port_audit']
     if args.debug:
         base_qemu_args += ['-S', '-s']
-    if args.bios:
+    if args.all:
+        if build_all(base_cargo_args, args.release) == 0:
+            if not args.build_only:
+                if args.bios:
+                    run_with_bios(base_qemu_args, args.release)
+                else:
+                    run_with_uefi(base_qemu_args, args.release)
+    elif args.bios:
         if build_with_bios(base_cargo_args, args.release) == 0:
             if not args.build_only:
                 run_with_bios(base_qemu_args, args.release)
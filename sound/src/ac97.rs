@@ -0,0 +1,430 @@
+//! A driver for AC'97 controllers, which some older machines and VMs have instead of HDA
+//!
+//! The controller is programmed through two sets of I/O ports. The native audio
+//! mixer (NAM) ports, behind BAR0, are the codec's registers, which hold its
+//! volumes and sample rate. The native audio bus master (NABM) ports, behind BAR1,
+//! run the DMA engines. Only the PCM out engine is used. It fetches samples from
+//! the buffers in a ring of 32 buffer descriptors, which point at the chunks of
+//! the mix buffer over and over, so sounds are mixed and played like they are
+//! through the HDA output stream
+//!
+//! # References
+//!
+//! * The OSDev wiki <https://wiki.osdev.org/AC97>
+//! * Intel's I/O Controller Hub AC'97 Programmer's Reference Manual
+
+use core::mem;
+use machine::port::{Port, PortReadWrite};
+use machine::interrupts::IRQ;
+use machine::pci::{self, PCIDevice, PCIBaseAddrReg};
+use machine::serial_println;
+use event_hook::{EventKind, HandlerId, BoxedFn, box_fn};
+use collections::allocator::{self, Allocator};
+use num::{Integer, BitState};
+use crate::{Sound, Sample, SoundHandle, ActionOnEnd, schedule_ended_actions, peak_levels, check_dma_range, alloc_dma, wait_until};
+use crate::{Backend, PlaybackPosition, queued_frames};
+use crate::{MIX_CHUNKS, LEVEL_WINDOW_FRAMES};
+use crate::mixer::{Mixer, EndedActions, PlayPolicy, MIX_RATE, MAX_GAIN, MAX_VOICES, DEFAULT_PRIORITY};
+
+// NAM register offsets
+const NAM_RESET: u16 = 0x00;
+const NAM_MASTER_VOLUME: u16 = 0x02;
+const NAM_PCM_OUT_VOLUME: u16 = 0x18;
+const NAM_EXTENDED_AUDIO_ID: u16 = 0x28;
+const NAM_EXTENDED_AUDIO_CONTROL: u16 = 0x2a;
+const NAM_PCM_FRONT_DAC_RATE: u16 = 0x2c;
+
+// NABM register offsets
+const NABM_PCM_OUT_BOX: u16 = 0x10;
+const NABM_GLOBAL_CONTROL: u16 = 0x2c;
+const NABM_GLOBAL_STATUS: u16 = 0x30;
+
+// Register offsets in a DMA engine's box of NABM registers
+const BOX_BDL_BASE_ADDR: u16 = 0x00;
+const BOX_CURRENT_INDEX: u16 = 0x04;
+const BOX_LAST_VALID_INDEX: u16 = 0x05;
+const BOX_STATUS: u16 = 0x06;
+const BOX_POSITION_IN_BUFFER: u16 = 0x08;
+const BOX_CONTROL: u16 = 0x0b;
+
+/// Takes the link out of cold reset when set in the global control register
+const GLOBAL_CONTROL_COLD_RESET: usize = 1;
+/// Set in the global status register once the primary codec is ready
+const GLOBAL_STATUS_PRIMARY_CODEC_READY: usize = 8;
+
+// Bits of a box's control register
+const CONTROL_RUN: usize = 0;
+const CONTROL_RESET: usize = 1;
+const CONTROL_INTERRUPT_ON_COMPLETION: usize = 4;
+
+// Bits of a box's status register
+const STATUS_DMA_HALTED: usize = 0;
+/// Set when a buffer whose descriptor asked for an interrupt has been played
+const STATUS_BUFFER_COMPLETION: usize = 3;
+/// The status bits that are cleared by writing 1s to them
+const STATUS_CLEAR_BITS: u16 = 0b1_1100;
+
+/// Set in the extended audio ID register if the codec can play at rates other than 48kHz
+const EXTENDED_AUDIO_VARIABLE_RATE: usize = 0;
+
+/// The number of descriptors in the buffer descriptor list, which is fixed
+const BDL_ENTRIES: usize = 32;
+/// The amount of sound, in milliseconds, in each chunk of the mix buffer
+const LATENCY_MS: usize = 20;
+/// The number of samples in a chunk of the mix buffer, 2 for every frame
+const CHUNK_LEN: usize = MIX_RATE as usize * LATENCY_MS / 1000 * 2;
+
+/// An entry in the buffer descriptor list
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct BufferDescriptor {
+    /// The address of the buffer's first sample
+    addr: u32,
+    /// The number of samples in the buffer
+    len: u16,
+    /// Bit 15 asks for an interrupt when the buffer has been played
+    control: u16
+}
+
+impl BufferDescriptor {
+    const INTERRUPT_ON_COMPLETION: u16 = 1 << 15;
+
+    const fn null() -> Self {
+        Self { addr: 0, len: 0, control: 0 }
+    }
+}
+
+/// The buffer descriptor list, which must be 8 byte aligned
+#[repr(C, align(8))]
+struct BufferDescriptorList([BufferDescriptor; BDL_ENTRIES]);
+
+pub(crate) struct Ac97 {
+    pci_config: PCIDevice,
+    /// The base of the NAM ports
+    nam: u16,
+    /// The base of the NABM ports
+    nabm: u16,
+    bdl: BufferDescriptorList,
+    mixer: Mixer,
+    /// The samples the PCM out engine plays over and over, in `MIX_CHUNKS` chunks
+    mix_buffer: &'static mut [Sample],
+    /// The index of the chunk of the mix buffer that's filled next,
+    /// once the controller has played it
+    next_chunk_to_fill: usize,
+    /// The number of chunks in a row that have been filled with silence
+    silent_chunks: usize,
+    /// The handler that fills the mix buffer when the controller has played a chunk.
+    /// Set while the PCM out engine is running
+    mix_hook: Option<HandlerId>
+}
+
+static mut AC97: Option<&'static mut Ac97> = None;
+
+/// Looks for an AC'97 controller among the devices on the PCI bus and sets it up
+///
+/// AC'97 controllers have the multimedia class code (0x4) and audio subclass (0x1)
+pub(crate) fn init() -> Result<(), &'static str> {
+    if unsafe { AC97.is_some() } {
+        return Ok(());
+    }
+    let pci_config = pci::find_device(0x4, 0x1).ok_or("Couldn't find the sound device")?;
+    let (nam, nabm) = match (pci_config.bar(0), pci_config.bar(1)) {
+        (PCIBaseAddrReg::IO(nam), PCIBaseAddrReg::IO(nabm)) => (nam.addr() as u16, nabm.addr() as u16),
+        _ => return Err("The AC'97 controller's registers aren't in I/O space")
+    };
+    let mix_buffer = alloc_mix_buffer()?;
+    // The controller must be moved to where it stays before it's given
+    // the address of the buffer descriptor list inside it
+    let ac97 = alloc_dma(Ac97 {
+        pci_config,
+        nam,
+        nabm,
+        bdl: BufferDescriptorList([BufferDescriptor::null(); BDL_ENTRIES]),
+        mixer: Mixer::new(),
+        mix_buffer,
+        next_chunk_to_fill: 0,
+        silent_chunks: 0,
+        mix_hook: None
+    })?;
+    unsafe { AC97 = Some(ac97) };
+    let ac97 = get().unwrap();
+    if let Err(msg) = ac97.start() {
+        unsafe { AC97 = None };
+        return Err(msg);
+    }
+    Ok(())
+}
+
+/// The AC'97 controller, if it's the one that was found
+pub(crate) fn get() -> Option<&'static mut Ac97> {
+    unsafe { AC97.as_deref_mut() }
+}
+
+/// Allocates the mix buffer where the controller can fetch it from,
+/// which is below 4GiB
+fn alloc_mix_buffer() -> Result<&'static mut [Sample], &'static str> {
+    let len = CHUNK_LEN * MIX_CHUNKS;
+    let buffer_ptr = unsafe { allocator::get_allocator().alloc(mem::size_of::<Sample>(), len) }
+        .map_err(|_| "No enough space on the heap for the mix buffer")?;
+    check_dma_range(buffer_ptr as u64, len * mem::size_of::<Sample>(), false)?;
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_ptr.cast::<Sample>(), len) };
+    buffer.fill(Sample(0));
+    Ok(buffer)
+}
+
+impl Ac97 {
+    /// Resets the codec, turns its volumes up and sets up the buffer descriptor list
+    fn start(&mut self) -> Result<(), &'static str> {
+        self.pci_config.enable_memory_space_accesses();
+        self.pci_config.route_interrupt(IRQ::Sound);
+
+        let mut global_control: Port<u32> = Port::new(self.nabm + NABM_GLOBAL_CONTROL);
+        let mut control = 0u32;
+        control.set_bit(GLOBAL_CONTROL_COLD_RESET);
+        global_control.write(control);
+        let global_status: Port<u32> = Port::new(self.nabm + NABM_GLOBAL_STATUS);
+        if !wait_until(|| global_status.read().get_bit(GLOBAL_STATUS_PRIMARY_CODEC_READY) == BitState::Set) {
+            return Err("The AC'97 codec didn't come up after the cold reset");
+        }
+
+        // Any value written to the reset register resets the codec's registers
+        self.write_nam(NAM_RESET, 0);
+        // 0 is no attenuation, with mute unset
+        self.write_nam(NAM_MASTER_VOLUME, 0);
+        // 0x08 in each channel's gain is 0dB
+        self.write_nam(NAM_PCM_OUT_VOLUME, 0x0808);
+        // Codecs without variable rates always play at 48kHz, which is already the mix rate
+        if self.read_nam(NAM_EXTENDED_AUDIO_ID).get_bit(EXTENDED_AUDIO_VARIABLE_RATE) == BitState::Set {
+            let mut extended_control = self.read_nam(NAM_EXTENDED_AUDIO_CONTROL);
+            extended_control.set_bit(EXTENDED_AUDIO_VARIABLE_RATE);
+            self.write_nam(NAM_EXTENDED_AUDIO_CONTROL, extended_control);
+            self.write_nam(NAM_PCM_FRONT_DAC_RATE, MIX_RATE as u16);
+        }
+
+        let bdl_addr = self.bdl.0.as_ptr() as u64;
+        check_dma_range(bdl_addr, mem::size_of::<BufferDescriptorList>(), false)?;
+        // Every descriptor points at a chunk, and since the number of descriptors
+        // is a multiple of the number of chunks, the ring plays them in order
+        for (i, descriptor) in self.bdl.0.iter_mut().enumerate() {
+            let chunk_start = (i % MIX_CHUNKS) * CHUNK_LEN;
+            *descriptor = BufferDescriptor {
+                addr: self.mix_buffer[chunk_start..].as_ptr() as u32,
+                len: CHUNK_LEN as u16,
+                control: BufferDescriptor::INTERRUPT_ON_COMPLETION
+            };
+        }
+        self.reset_engine()?;
+        Ok(())
+    }
+
+    /// Stops every sound and the PCM out engine for good, so the controller
+    /// isn't fetching samples while the computer shuts down
+    pub(crate) fn quiesce(&mut self) {
+        self.mixer.stop_all();
+        // Nothing can be played while paused, so the engine isn't started again
+        self.mixer.set_paused(true);
+        self.stop_mixing();
+    }
+
+    /// Fills the whole mix buffer and starts the PCM out engine,
+    /// unless it's already running
+    fn start_mixing(&mut self) -> Result<(), &'static str> {
+        if self.mix_hook.is_some() {
+            return Ok(());
+        }
+        let ended = self.mixer.mix(self.mix_buffer);
+        if let Err(msg) = self.reset_engine() {
+            serial_println!("Can't play sound: {}", msg);
+            return Err(msg);
+        }
+        let mut bdl_base_addr: Port<u32> = Port::new(self.box_port(BOX_BDL_BASE_ADDR));
+        bdl_base_addr.write(self.bdl.0.as_ptr() as u32);
+        self.write_last_valid_index(BDL_ENTRIES as u8 - 1);
+        self.next_chunk_to_fill = 0;
+        self.silent_chunks = 0;
+        self.mix_hook = Some(event_hook::hook_event(EventKind::Sound, box_fn!(|_| {
+            if let Some(ac97) = get() {
+                schedule_ended_actions(ac97.mix_played_chunks());
+            }
+        })));
+        self.modify_box_control(|control| {
+            control.set_bit(CONTROL_INTERRUPT_ON_COMPLETION);
+            control.set_bit(CONTROL_RUN);
+        });
+        schedule_ended_actions(ended);
+        Ok(())
+    }
+
+    /// Fills the chunks of the mix buffer that the controller has played
+    /// since the last time with the next frames of the playing sounds
+    ///
+    /// The engine is stopped once the whole mix buffer is silent.
+    /// Returns the actions of the sounds that ended
+    fn mix_played_chunks(&mut self) -> EndedActions {
+        const NO_ACTION: Option<BoxedFn<'static>> = None;
+        let mut ended = [NO_ACTION; MAX_VOICES];
+        let mut status: Port<u16> = Port::new(self.box_port(BOX_STATUS));
+        if status.read().get_bit(STATUS_BUFFER_COMPLETION) == BitState::Unset {
+            // The interrupt came from something else
+            return ended;
+        }
+        status.write(STATUS_CLEAR_BITS);
+        let current_index = self.current_index();
+        let playing_chunk = current_index as usize % MIX_CHUNKS;
+        while self.next_chunk_to_fill != playing_chunk {
+            if self.mixer.is_idle() {
+                self.silent_chunks += 1;
+            } else {
+                self.silent_chunks = 0;
+            }
+            let start = self.next_chunk_to_fill * CHUNK_LEN;
+            let chunk_ended = self.mixer.mix(&mut self.mix_buffer[start..start + CHUNK_LEN]);
+            for (slot, action) in chunk_ended.into_iter().enumerate() {
+                if action.is_some() {
+                    ended[slot] = action;
+                }
+            }
+            self.next_chunk_to_fill = (self.next_chunk_to_fill + 1) % MIX_CHUNKS;
+        }
+        // Keeping the last valid descriptor just behind the current one,
+        // so the engine never runs out of descriptors
+        self.write_last_valid_index(((current_index as usize + BDL_ENTRIES - 1) % BDL_ENTRIES) as u8);
+        if self.silent_chunks >= MIX_CHUNKS {
+            self.stop_mixing();
+        }
+        ended
+    }
+
+    /// Stops the PCM out engine and the handler that fills the mix buffer
+    fn stop_mixing(&mut self) {
+        if let Some(id) = self.mix_hook.take() {
+            event_hook::unhook_event(id, EventKind::Sound);
+            if let Err(msg) = self.reset_engine() {
+                serial_println!("{}", msg);
+            }
+        }
+    }
+
+    /// Stops the PCM out engine and resets its registers
+    fn reset_engine(&mut self) -> Result<(), &'static str> {
+        self.modify_box_control(|control| { control.unset_bit(CONTROL_RUN); });
+        let status: Port<u16> = Port::new(self.box_port(BOX_STATUS));
+        if !wait_until(|| status.read().get_bit(STATUS_DMA_HALTED) == BitState::Set) {
+            return Err("The AC'97 PCM out engine didn't halt");
+        }
+        let mut control: Port<u8> = Port::new(self.box_port(BOX_CONTROL));
+        let mut reset = 0u8;
+        reset.set_bit(CONTROL_RESET);
+        control.write(reset);
+        if !wait_until(|| control.read().get_bit(CONTROL_RESET) == BitState::Unset) {
+            return Err("The AC'97 PCM out engine didn't leave the reset state");
+        }
+        Ok(())
+    }
+
+    /// The index of the sample in the mix buffer the controller is playing
+    fn position(&self) -> usize {
+        let chunk = self.current_index() as usize % MIX_CHUNKS;
+        let position_in_buffer: Port<u16> = Port::new(self.box_port(BOX_POSITION_IN_BUFFER));
+        // The register counts the samples left in the current buffer
+        let left = (position_in_buffer.read() as usize).min(CHUNK_LEN);
+        chunk * CHUNK_LEN + CHUNK_LEN - left
+    }
+
+    fn current_index(&self) -> u8 {
+        let current_index: Port<u8> = Port::new(self.box_port(BOX_CURRENT_INDEX));
+        current_index.read() % BDL_ENTRIES as u8
+    }
+
+    fn write_last_valid_index(&mut self, idx: u8) {
+        let mut last_valid_index: Port<u8> = Port::new(self.box_port(BOX_LAST_VALID_INDEX));
+        last_valid_index.write(idx);
+    }
+
+    fn modify_box_control(&mut self, f: impl FnOnce(&mut u8)) {
+        let mut control: Port<u8> = Port::new(self.box_port(BOX_CONTROL));
+        let mut val = control.read();
+        f(&mut val);
+        control.write(val);
+    }
+
+    /// The port of the register at `offset` in the PCM out box
+    fn box_port(&self, offset: u16) -> u16 {
+        self.nabm + NABM_PCM_OUT_BOX + offset
+    }
+
+    fn read_nam(&self, offset: u16) -> u16 {
+        let port: Port<u16> = Port::new(self.nam + offset);
+        port.read()
+    }
+
+    fn write_nam(&mut self, offset: u16, val: u16) {
+        let mut port: Port<u16> = Port::new(self.nam + offset);
+        port.write(val);
+    }
+}
+
+impl Backend for Ac97 {
+    fn play_sound(&mut self, sound: Sound, action_on_end: ActionOnEnd, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str> {
+        let handle = self.mixer.play(sound, action_on_end, MAX_GAIN, priority, policy)?;
+        if let Err(msg) = self.start_mixing() {
+            self.mixer.stop(handle).unwrap();
+            return Err(msg);
+        }
+        Ok(handle)
+    }
+
+    fn crossfade(&mut self, from: SoundHandle, to: Sound, ms: usize) -> Result<SoundHandle, &'static str> {
+        let frames = (ms * MIX_RATE as usize / 1000).as_u32();
+        let handle = self.mixer.play(to, ActionOnEnd::Replay, 0, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy)?;
+        self.mixer.fade(handle, MAX_GAIN, frames, false).unwrap();
+        let _ = self.mixer.fade(from, 0, frames, true);
+        if let Err(msg) = self.start_mixing() {
+            self.mixer.stop(handle).unwrap();
+            return Err(msg);
+        }
+        Ok(handle)
+    }
+
+    fn mixer(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    /// Holds the mixer and the PCM out engine where they are
+    fn pause(&mut self) {
+        self.mixer.set_paused(true);
+        if self.mix_hook.is_some() {
+            self.modify_box_control(|control| { control.unset_bit(CONTROL_RUN); });
+        }
+    }
+
+    /// Carries on mixing and playing from where `pause` stopped
+    fn resume(&mut self) {
+        if !self.mixer.is_paused() {
+            return;
+        }
+        self.mixer.set_paused(false);
+        if self.mix_hook.is_some() {
+            self.modify_box_control(|control| { control.set_bit(CONTROL_RUN); });
+        }
+    }
+
+    fn playback_position(&self, handle: SoundHandle) -> Option<PlaybackPosition> {
+        let queued = match self.mix_hook {
+            Some(_) => queued_frames(self.position(), self.next_chunk_to_fill, CHUNK_LEN),
+            None => 0
+        };
+        self.mixer.position(handle, queued)
+    }
+
+    /// The peak levels of the samples the controller played most recently
+    fn levels(&self) -> (u16, u16) {
+        if self.mix_hook.is_none() {
+            return (0, 0);
+        }
+        // Keeping to whole stereo frames
+        let end = self.position() & !1;
+        let start = end.saturating_sub(LEVEL_WINDOW_FRAMES * 2);
+        peak_levels(&self.mix_buffer[start..end])
+    }
+}
@@ -0,0 +1,106 @@
+//! Beeps for when sounds can't be played through the output stream
+//!
+//! A codec's beep generator sends a square wave straight to the codec's
+//! outputs, so it works even when no stream could be set up. When there's
+//! no beep generator, or no sound device at all, the PC speaker beeps instead
+
+use event_hook::{TimeoutId, box_fn};
+use machine::speaker;
+use crate::{get_sound_device, HDANodeCommand, NodeAddr, PowerState};
+
+/// The beep generator's tone is this frequency divided by 4 times the divider
+const BEEP_GEN_CLOCK: u32 = 48000;
+
+/// What a beep is coming out of
+#[derive(Clone, Copy)]
+enum BeepSource {
+    BeepGen(NodeAddr),
+    Speaker
+}
+
+/// The beep that's sounding
+struct Beep {
+    source: BeepSource,
+    /// The timeout that ends the beep
+    timeout: TimeoutId
+}
+
+static mut BEEP: Option<Beep> = None;
+
+/// Starts a beep at `freq_hz` that ends after `duration_ms` milliseconds,
+/// cutting short the beep that's sounding, if any
+pub(crate) fn beep(freq_hz: u32, duration_ms: usize) -> Result<(), &'static str> {
+    if freq_hz == 0 {
+        return Err("A beep's frequency can't be 0");
+    }
+    if unsafe { BEEP.is_some() } && !stop() {
+        return Err("The beep that's sounding couldn't be stopped");
+    }
+    let source = start(freq_hz);
+    // Timeouts are rounded up to whole ticks, so even the shortest beep is heard
+    let timeout = event_hook::set_timeout(duration_ms as u64, box_fn!(|_| end()));
+    unsafe { BEEP = Some(Beep { source, timeout }) };
+    Ok(())
+}
+
+/// Stops the beep once its time is up
+fn end() {
+    // A beep that can't be stopped yet is tried again on the next tick
+    if !stop() {
+        if let Some(beep) = unsafe { BEEP.as_mut() } {
+            beep.timeout = event_hook::set_timeout(0, box_fn!(|_| end()));
+        }
+    }
+}
+
+/// Starts the beep on the codec's beep generator if it can make
+/// `freq_hz`, or on the PC speaker if it can't
+fn start(freq_hz: u32) -> BeepSource {
+    if let (Some(divider), Some(sd)) = (beep_gen_divider(freq_hz), get_sound_device()) {
+        // The beep generator is only known once the codecs have been talked
+        // to, so it's still there when sound was disabled because a later step failed
+        if let Some(node) = sd.beep_gen.filter(|_| !sd.commander.busy) {
+            sd.commander.command(HDANodeCommand::set_power_state(node.codec_addr(), node.node_id(), PowerState::D0));
+            sd.commander.command(HDANodeCommand::set_beep_gen(node.codec_addr(), node.node_id(), divider));
+            return BeepSource::BeepGen(node);
+        }
+    }
+    speaker::start_beep(freq_hz);
+    BeepSource::Speaker
+}
+
+/// Silences the beep that's sounding and clears its timeout
+///
+/// Returns false if the beep generator is in the middle of a command,
+/// so it can't be silenced now
+fn stop() -> bool {
+    let beep = match unsafe { BEEP.as_ref() } {
+        Some(beep) => beep,
+        None => return true
+    };
+    match beep.source {
+        BeepSource::BeepGen(node) => match get_sound_device() {
+            Some(sd) if sd.commander.busy => return false,
+            // A divider of 0 turns the beep generator off
+            Some(sd) => { sd.commander.command(HDANodeCommand::set_beep_gen(node.codec_addr(), node.node_id(), 0)); }
+            None => ()
+        },
+        BeepSource::Speaker => speaker::stop_beep()
+    }
+    event_hook::clear_timeout(beep.timeout);
+    unsafe { BEEP = None };
+    true
+}
+
+/// The divider that makes the beep generator beep closest to `freq_hz`,
+/// or None if it's out of the generator's range of about 47Hz to 12kHz
+fn beep_gen_divider(freq_hz: u32) -> Option<u8> {
+    if freq_hz > BEEP_GEN_CLOCK / 4 {
+        return None;
+    }
+    let divider = (BEEP_GEN_CLOCK / 4 + freq_hz / 2) / freq_hz;
+    match divider {
+        1..=255 => Some(divider as u8),
+        _ => None
+    }
+}
@@ -0,0 +1,288 @@
+//! Conversion of the samples in wav files into the format the mixer mixes
+//!
+//! The mixer takes 16 bit stereo samples. Mono samples are played on both
+//! channels, 8 and 24 bit samples are scaled to 16 bits and IMA ADPCM samples
+//! are decoded. The samples keep the file's sample rate, which the mixer
+//! converts to the mix rate as it mixes.
+//! Streamed sounds aren't converted up front, but a frame at a time as they're mixed.
+//! Files whose samples are already 16 bit stereo samples aren't converted at all
+
+use core::mem;
+use collections::copy;
+use crate::{Sample, WavFile, WavEncoding};
+
+/// How much the ADPCM step size index changes by after each sample
+const ADPCM_INDEX_CHANGES: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+/// The ADPCM step sizes, indexed by the step size index
+const ADPCM_STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45,
+    50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230,
+    253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963,
+    1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327,
+    3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487,
+    12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767
+];
+
+/// Checks that the frames in `file` can be read one at a time, as they're mixed
+pub(crate) fn check_streamable(file: &WavFile) -> Result<(), &'static str> {
+    match file.encoding() {
+        WavEncoding::Pcm => Ok(()),
+        WavEncoding::ImaAdpcm => Err("ADPCM sounds can't be streamed")
+    }
+}
+
+/// The number of samples `file` has after being converted
+pub(crate) fn output_len(file: &WavFile) -> usize {
+    file_frames(file) * 2
+}
+
+/// Writes the samples in `file`, converted to 16 bit stereo samples, into `out`
+pub(crate) fn convert(file: &WavFile, out: &mut [Sample]) -> Result<(), &'static str> {
+    let len = output_len(file);
+    if out.len() < len {
+        return Err("The sample buffer is too small for the sound");
+    }
+    if file.encoding() == WavEncoding::ImaAdpcm {
+        let channels = file.num_of_channels() as usize;
+        let mut frames = 0;
+        for block in file.sample_data().chunks(file.block_align() as usize) {
+            frames += decode_adpcm_block(block, channels, &mut out[frames * 2..len]);
+        }
+        return Ok(());
+    }
+    if file.num_of_channels() == 2 && file.bits_per_sample() == 16 {
+        // The file's samples are already in the mixer's format, so they're copied all at once
+        unsafe { copy::copy_bytes(file.sample_data().as_ptr(), out.as_mut_ptr().cast(), len * mem::size_of::<Sample>()) };
+        return Ok(());
+    }
+    for (i, out_frame) in out[..len].chunks_exact_mut(2).enumerate() {
+        let (left, right) = read_frame(file, i);
+        out_frame[0] = Sample(left as u16);
+        out_frame[1] = Sample(right as u16);
+    }
+    Ok(())
+}
+
+/// The samples in `file` read in place, if they're already 16 bit
+/// stereo samples and the data chunk is aligned for them
+pub(crate) fn as_samples(file: &WavFile) -> Option<&'static [Sample]> {
+    let data = file.sample_data();
+    let in_mixer_format = file.encoding() == WavEncoding::Pcm
+        && file.num_of_channels() == 2
+        && file.bits_per_sample() == 16;
+    if !in_mixer_format || data.as_ptr().align_offset(mem::align_of::<Sample>()) != 0 {
+        return None;
+    }
+    // The samples are little endian, like the processor's integers
+    Some(unsafe { core::slice::from_raw_parts(data.as_ptr().cast::<Sample>(), output_len(file)) })
+}
+
+/// The number of frames, samples for all the channels at one point
+/// in time, in `file`
+pub(crate) fn file_frames(file: &WavFile) -> usize {
+    let data_len = file.sample_data().len();
+    match file.encoding() {
+        WavEncoding::Pcm => data_len / frame_size(file),
+        WavEncoding::ImaAdpcm => {
+            let channels = file.num_of_channels() as usize;
+            let block_align = file.block_align() as usize;
+            // The last block can be cut short
+            data_len / block_align * adpcm_block_frames(block_align, channels)
+                + adpcm_block_frames(data_len % block_align, channels)
+        }
+    }
+}
+
+fn frame_size(file: &WavFile) -> usize {
+    file.num_of_channels() as usize * file.bits_per_sample() as usize / 8
+}
+
+/// Reads the left and right samples of the PCM frame at `idx` as 16 bit samples
+///
+/// A mono frame's sample is both the left and the right sample
+pub(crate) fn read_frame(file: &WavFile, idx: usize) -> (i16, i16) {
+    let data = file.sample_data();
+    let bytes_per_sample = file.bits_per_sample() as usize / 8;
+    let start = idx * frame_size(file);
+    let left = read_sample(&data[start..start + bytes_per_sample]);
+    if file.num_of_channels() == 1 {
+        return (left, left);
+    }
+    let right = read_sample(&data[start + bytes_per_sample..start + 2 * bytes_per_sample]);
+    (left, right)
+}
+
+/// Reads a little endian sample as a 16 bit sample
+fn read_sample(bytes: &[u8]) -> i16 {
+    match bytes.len() {
+        // 8 bit samples are unsigned, with silence at 128
+        1 => ((bytes[0] as i16) - 128) << 8,
+        2 => i16::from_le_bytes([bytes[0], bytes[1]]),
+        // Dropping the least significant byte of 24 bit samples
+        _ => i16::from_le_bytes([bytes[1], bytes[2]])
+    }
+}
+
+/// The number of frames in an ADPCM block of `block_len` bytes
+///
+/// The first frame is in the channels' headers, and every 4 bytes
+/// of each channel's samples after them make 8 more frames
+fn adpcm_block_frames(block_len: usize, channels: usize) -> usize {
+    let header_len = 4 * channels;
+    if block_len < header_len {
+        return 0;
+    }
+    1 + (block_len - header_len) / header_len * 8
+}
+
+/// Decodes the ADPCM block `block` into 16 bit stereo samples in `out`
+///
+/// Returns the number of frames written
+fn decode_adpcm_block(block: &[u8], channels: usize, out: &mut [Sample]) -> usize {
+    let frames = adpcm_block_frames(block.len(), channels).min(out.len() / 2);
+    if frames == 0 {
+        return 0;
+    }
+    let header_len = 4 * channels;
+    let mut decoders = [AdpcmDecoder::from_header(&block[..4]), AdpcmDecoder::from_header(&block[header_len - 4..header_len])];
+    let mut write = |frame: usize, channel: usize, sample: i16| {
+        if frame < frames {
+            out[frame * 2 + channel] = Sample(sample as u16);
+            if channels == 1 {
+                out[frame * 2 + 1] = Sample(sample as u16);
+            }
+        }
+    };
+    for channel in 0..channels {
+        write(0, channel, decoders[channel].predictor as i16);
+    }
+    for (i, group) in block[header_len..].chunks_exact(header_len).enumerate() {
+        for (channel, channel_bytes) in group.chunks_exact(4).enumerate() {
+            // The low nibble of each byte is the earlier sample
+            for (j, &byte) in channel_bytes.iter().enumerate() {
+                let first_frame = 1 + i * 8 + j * 2;
+                write(first_frame, channel, decoders[channel].decode(byte & 0xf));
+                write(first_frame + 1, channel, decoders[channel].decode(byte >> 4));
+            }
+        }
+    }
+    frames
+}
+
+/// The state of the IMA ADPCM decoder of one channel
+struct AdpcmDecoder {
+    /// The last sample
+    predictor: i32,
+    step_index: usize
+}
+
+impl AdpcmDecoder {
+    /// A decoder starting from the 4 byte header of a channel in a block
+    fn from_header(header: &[u8]) -> Self {
+        Self {
+            predictor: i16::from_le_bytes([header[0], header[1]]) as i32,
+            step_index: (header[2] as usize).min(ADPCM_STEPS.len() - 1)
+        }
+    }
+
+    /// Decodes the 4 bit sample `nibble` into the next 16 bit sample
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = ADPCM_STEPS[self.step_index];
+        let mut diff = step >> 3;
+        if nibble & 0b100 != 0 {
+            diff += step;
+        }
+        if nibble & 0b10 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 0b1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 0b1000 != 0 {
+            diff = -diff;
+        }
+        self.predictor = (self.predictor + diff).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.step_index = (self.step_index as i32 + ADPCM_INDEX_CHANGES[nibble as usize])
+            .clamp(0, ADPCM_STEPS.len() as i32 - 1) as usize;
+        self.predictor as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+    use crate::wav::tests::{chunk, wav, fmt};
+
+    /// The header of a channel in an ADPCM block
+    fn header(predictor: i16, step_index: u8) -> [u8; 4] {
+        let [low, high] = predictor.to_le_bytes();
+        [low, high, step_index, 0]
+    }
+
+    /// An ADPCM block with the channels' `headers` followed by `samples`,
+    /// the groups of 4 bytes of each channel's samples in turn
+    fn block(headers: &[[u8; 4]], samples: &[[u8; 4]]) -> Vec<u8> {
+        headers.iter().chain(samples).flatten().copied().collect()
+    }
+
+    fn frames(samples: &[Sample]) -> Vec<(i16, i16)> {
+        samples.chunks_exact(2).map(|frame| (frame[0].0 as i16, frame[1].0 as i16)).collect()
+    }
+
+    fn mono(samples: &[i16]) -> Vec<(i16, i16)> {
+        samples.iter().map(|&sample| (sample, sample)).collect()
+    }
+
+    /// The 9 samples decoded from a block with `header(0, 0)` and `[0x44; 4]`
+    const RISING: [i16; 9] = [0, 7, 17, 29, 43, 61, 82, 107, 138];
+
+    #[test]
+    fn test_decode_adpcm_block() {
+        let mut truncated = block(&[header(1000, 10)], &[]);
+        truncated.extend_from_slice(&[0x21, 0x43]);
+        let cases = [
+            ("mono", block(&[header(0, 0)], &[[0x44; 4]]), 1, 18, mono(&RISING)),
+            (
+                "stereo",
+                block(&[header(0, 0), header(100, 0)], &[[0x44; 4], [0x0c, 0x77, 0x08, 0x00]]),
+                2,
+                18,
+                RISING.iter().copied().zip([100, 93, 94, 109, 140, 136, 140, 143, 146]).collect()
+            ),
+            (
+                "clamped to the smallest sample and the largest step",
+                block(&[header(-32760, 200)], &[[0x0f, 0, 0, 0]]),
+                1,
+                18,
+                mono(&[-32760, -32768, -28673, -24949, -21564, -18487, -15689, -13146, -10834])
+            ),
+            ("no room for the whole block", block(&[header(0, 0)], &[[0x44; 4]]), 1, 6, mono(&RISING[..3])),
+            ("cut short in a group of samples", truncated, 1, 18, mono(&[1000])),
+            ("only the header", block(&[header(-5, 0)], &[]), 1, 18, mono(&[-5])),
+            ("shorter than the header", std::vec![0, 0, 0], 1, 18, Vec::new())
+        ];
+        for (name, block, channels, out_len, expected) in cases {
+            let mut out = [Sample(0); 18];
+            let written = decode_adpcm_block(&block, channels, &mut out[..out_len]);
+            assert_eq!(written, expected.len(), "{}", name);
+            assert_eq!(frames(&out[..written * 2]), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_convert_adpcm_file_with_truncated_last_block() {
+        let mut data = block(&[header(0, 0)], &[[0x44; 4]]);
+        data.extend(block(&[header(1000, 10)], &[]));
+        data.extend_from_slice(&[0x21, 0x43]);
+        // 0x11 is the format code of IMA ADPCM
+        let file = WavFile::from(wav(&[chunk(b"fmt ", &fmt(0x11, 1, 22050, 8, 4)), chunk(b"data", &data)])).unwrap();
+        assert_eq!(file_frames(&file), 10);
+        let mut out = [Sample(0); 20];
+        convert(&file, &mut out).unwrap();
+        let mut expected = mono(&RISING);
+        expected.push((1000, 1000));
+        assert_eq!(frames(&out), expected);
+    }
+}
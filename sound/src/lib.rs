@@ -1,174 +1,855 @@
 #![no_std]
-#![feature(array_windows)]
 #![allow(unaligned_references, dead_code)]
 
 use core::ops::{Index, DerefMut};
-use machine::port::{Port, PortReadWrite};
+use core::mem;
 use machine::interrupts::IRQ;
+use machine::instructions::barrier;
+use machine::time;
+use machine::power;
+use machine::{serial, serial_println, register_block};
+use machine::pci::{self, PCIDevice};
 use num::{Integer, BitState};
 use collections::vec;
 use collections::vec::Vec;
-use event_hook::{EventKind, box_fn, HandlerId, BoxedFn};
+use collections::allocator::{self, Allocator};
+use event_hook::{Event, EventKind, JackState, SoundErrorKind, box_fn, HandlerId, BoxedFn, HookOptions};
 
 mod wav;
+mod format;
+mod mixer;
+mod beep;
+mod software;
+mod ac97;
+pub mod synth;
+use synth::{Note, Waveform};
+pub use mixer::{SoundHandle, ResampleQuality, PlaybackPosition, PlayPolicy, MAX_VOICES, MAX_QUEUED, DEFAULT_PRIORITY};
+use mixer::{MIX_RATE, MAX_GAIN, EndedActions};
 pub mod macros;
-pub use wav::WavFile;
+pub use wav::{WavFile, WavEncoding, WavError};
 mod printer;
+mod recording;
+use recording::Recording;
 mod font;
 
-static mut SOUND_DEVICE: Option<SoundDevice> = None;
+/// The HDA controller, kept on the heap with its CORB, RIRB and buffer
+/// descriptor lists, so the controller can fetch them
+static mut SOUND_DEVICE: Option<&'static mut SoundDevice> = None;
+
+/// Finds the raw bytes of the asset at a path
+pub type AssetLookup = fn(&str) -> Option<&'static [u8]>;
+
+/// Where `load` reads WAV files from
+static mut ASSET_LOOKUP: Option<AssetLookup> = None;
+
+/// The sounds that have been loaded, with the paths they were loaded from
+static mut SOUND_CACHE: Option<Vec<'static, (&'static str, Sound)>> = None;
 
 unsafe impl Sync for SoundDevice {}
 
+/// Sets up the HDA controller, or the AC'97 controller on machines that
+/// have one instead
 pub fn init() -> Result<(), &'static str> {
-    if unsafe { SOUND_DEVICE.is_none() } {
-        let sound_device = find_sound_device().ok_or("Couldn't find the sound device")?;
-        // The SOUND_DEVICE static must be initialized before starting
+    if unsafe { SOUND_DEVICE.is_none() } && ac97::get().is_none() {
+        let sound_device = match find_sound_device() {
+            Some(sound_device) => sound_device,
+            None => {
+                ac97::init()?;
+                register_quiesce();
+                return Ok(());
+            }
+        };
+        // The sound device must be moved to where it stays before starting
         // to prevent registers in the sound controller from getting
         // temporary stack addresses written to them
+        let sound_device = alloc_dma(sound_device)?;
         unsafe { SOUND_DEVICE = Some(sound_device) };
-        let sound_device = unsafe { SOUND_DEVICE.as_mut().unwrap() };
-        sound_device.start()?;
+        let sound_device = get_sound_device().unwrap();
+        if let Err(msg) = sound_device.start() {
+            // Left around for `beep`, which may still work without a stream
+            sound_device.disable();
+            return Err(msg);
+        }
+        register_quiesce();
     }
     Ok(())
 }
 
-pub fn play_sound(sound: &Sound, action_on_end: ActionOnEnd) {
-    let sd = get_sound_device().unwrap();
-    sd.play_sound(*sound, action_on_end);
+/// Makes the sound device stop before the computer shuts down or restarts
+fn register_quiesce() {
+    if let Err(msg) = power::on_shutdown(quiesce) {
+        serial_println!("Sound won't be stopped before shutting down: {}", msg);
+    }
+}
+
+/// Stops every sound and the sound device's DMA, so the controller isn't
+/// reading or writing memory while the computer shuts down or restarts
+fn quiesce() {
+    if let Some(ac97) = ac97::get() {
+        ac97.quiesce();
+    }
+    if let Some(sd) = get_sound_device() {
+        sd.quiesce();
+    }
+}
+
+/// Mixes sound into a buffer in memory from now on, instead of playing it
+/// through the sound device
+///
+/// For running the game and the mixer without the sound device, like under
+/// QEMU with the HDA device left out. Meant to be called instead of `init`
+pub fn init_software() -> Result<(), &'static str> {
+    software::init()
+}
+
+/// Starts playing `sound` alongside the sounds that are already playing
+///
+/// The returned handle is used to stop the sound. Up to `MAX_VOICES` sounds
+/// can play at once. The sound has `DEFAULT_PRIORITY` and isn't played
+/// if every voice is busy
+pub fn play_sound(sound: &Sound, action_on_end: ActionOnEnd) -> Result<SoundHandle, &'static str> {
+    play_sound_with(sound, action_on_end, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy)
+}
+
+/// Starts playing `sound` with `priority`, or as `policy` says if every voice is busy
+///
+/// A sound with `PlayPolicy::Interrupt` cuts off a sound with the same or a lower
+/// priority, and one with `PlayPolicy::Queue` waits for a voice, for up to
+/// `MAX_QUEUED` sounds. The handle of a queued sound can be stopped before the
+/// sound has started
+pub fn play_sound_with(sound: &Sound, action_on_end: ActionOnEnd, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str> {
+    let backend = get_backend().ok_or("The sound device hasn't been initialized")?;
+    backend.play_sound(*sound, action_on_end, priority, policy)
+}
+
+/// Fades the sound with the handle `from` out while fading `to` in over `ms` milliseconds
+///
+/// `from` is stopped once it has faded out. If it has already ended, `to` just
+/// fades in. Like music, `to` is replayed when it ends
+pub fn crossfade(from: SoundHandle, to: &Sound, ms: usize) -> Result<SoundHandle, &'static str> {
+    let backend = get_backend().ok_or("The sound device hasn't been initialized")?;
+    backend.crossfade(from, *to, ms)
+}
+
+/// Sets where `load` reads WAV files from
+pub fn set_asset_lookup(lookup: AssetLookup) {
+    unsafe { ASSET_LOOKUP = Some(lookup) };
+}
+
+/// Reads the WAV file at `path` and copies its samples into a sample buffer on the heap,
+/// unless they're 16 bit stereo samples that can be played from the file
+///
+/// Sounds are cached by their paths, so loading a path that has already been
+/// loaded returns the same sound without reading the file again.
+/// The sample buffers are never freed
+pub fn load(path: &'static str) -> Result<Sound, &'static str> {
+    let cache = unsafe { SOUND_CACHE.get_or_insert_with(|| vec!(item_type => (&'static str, Sound), capacity => 4)) };
+    if let Some((_, sound)) = cache.iter().find(|(cached_path, _)| *cached_path == path) {
+        return Ok(*sound);
+    }
+    let lookup = unsafe { ASSET_LOOKUP.ok_or("No asset lookup has been set to load sounds from")? };
+    let raw_file = lookup(path).ok_or("Couldn't find the sound's file")?;
+    let sound = Sound::new_on_heap(WavFile::from(raw_file)?)?;
+    cache.push((path, sound));
+    Ok(sound)
+}
+
+/// Finds the WAV file at `path` and returns a sound that plays it
+/// straight from the file, without copying its samples onto the heap
+///
+/// Meant for music and other long sounds. Nothing is cached, since
+/// there's nothing to load
+pub fn load_streamed(path: &'static str) -> Result<Sound, &'static str> {
+    let lookup = unsafe { ASSET_LOOKUP.ok_or("No asset lookup has been set to load sounds from")? };
+    let raw_file = lookup(path).ok_or("Couldn't find the sound's file")?;
+    Sound::streamed(WavFile::from(raw_file)?)
+}
+
+/// Synthesizes `notes` played with `waveform`, at a peak of `amplitude`,
+/// into a sample buffer on the heap
+///
+/// Chiptune music made this way takes a few bytes per note in the binary
+/// instead of a WAV file. The sample buffers are never freed
+pub fn synthesize(notes: &[Note], waveform: Waveform, amplitude: i16) -> Result<Sound, &'static str> {
+    Sound::synthesized_on_heap(notes, waveform, amplitude)
+}
+
+/// Stops the sound with `handle` without running its action on end,
+/// or takes it off the queue if it hasn't started yet
+///
+/// Returns an error if the sound isn't playing or queued
+pub fn stop_sound(handle: SoundHandle) -> Result<(), ()> {
+    let backend = get_backend().ok_or(())?;
+    backend.mixer().stop(handle)
+}
+
+/// Stops every sound that's playing without running their actions on end
+pub fn stop_all_sounds() {
+    if let Some(backend) = get_backend() {
+        backend.mixer().stop_all();
+    }
+}
+
+/// Pauses every sound that's playing, so they can be resumed
+/// from where they were with `resume_sound`
+///
+/// The output stream's DMA is stopped without resetting the stream, so the
+/// controller carries on from the same position in the mix buffer on resume.
+/// While sound is paused, no new sound can be played
+pub fn pause_sound() {
+    if let Some(backend) = get_backend() {
+        backend.pause();
+    }
+}
+
+/// Resumes the sounds paused with `pause_sound`
+pub fn resume_sound() {
+    if let Some(backend) = get_backend() {
+        backend.resume();
+    }
+}
+
+/// Tells whether or not sound has been paused with `pause_sound`
+pub fn is_paused() -> bool {
+    match get_backend() {
+        Some(backend) => backend.mixer().is_paused(),
+        None => false
+    }
+}
+
+/// Sets how sounds recorded at rates other than the 48kHz they're mixed at
+/// are resampled, for the sounds that are playing and the ones played after them
+///
+/// `ResampleQuality::Linear`, the default, sounds smoother, while
+/// `ResampleQuality::Nearest` takes less time to mix
+pub fn set_resample_quality(quality: ResampleQuality) {
+    if let Some(backend) = get_backend() {
+        backend.mixer().set_resample_quality(quality);
+    }
+}
+
+/// Tells whether or not headphones are plugged into the headphone jack
+///
+/// While they are, sound is played through them instead of the speakers
+pub fn headphones_plugged() -> bool {
+    match get_sound_device() {
+        Some(sd) => sd.headphones_plugged,
+        None => false
+    }
+}
+
+/// Beeps at `freq_hz` for `duration_ms` milliseconds
+///
+/// The beep comes from the codec's beep generator, which doesn't need the
+/// output stream, or from the PC speaker if the codec has no beep generator
+/// or the sound device couldn't be set up. Returns as soon as the beep has
+/// started, cutting short the beep that was sounding, if any
+pub fn beep(freq_hz: u32, duration_ms: usize) -> Result<(), &'static str> {
+    beep::beep(freq_hz, duration_ms)
+}
+
+/// Tells whether or not the sound with `handle` is still playing
+pub fn is_playing(handle: SoundHandle) -> bool {
+    match get_backend() {
+        Some(backend) => backend.mixer().is_playing(handle),
+        None => false
+    }
+}
+
+/// Tells whether or not the sound with `handle` is waiting for a voice
+/// to be freed, after being played with `PlayPolicy::Queue`
+pub fn is_queued(handle: SoundHandle) -> bool {
+    match get_backend() {
+        Some(backend) => backend.mixer().is_queued(handle),
+        None => false
+    }
+}
+
+/// How far the sound with `handle` has been heard, for keeping what's
+/// on the screen in time with the music
+///
+/// The mixer runs ahead of the controller by up to the whole mix buffer,
+/// so the frames that have been mixed but not played yet aren't counted.
+/// Returns None if the sound isn't playing
+pub fn playback_position(handle: SoundHandle) -> Option<PlaybackPosition> {
+    get_backend()?.playback_position(handle)
+}
+
+/// Sets the amount of sound, in milliseconds, that is mixed at a time
+///
+/// Each chunk of the mix buffer is described by an entry in the buffer descriptor
+/// list. Smaller chunks make new sounds start sooner, but they give the controller
+/// less room to fetch samples before running dry, which can result in crackles.
+/// Takes effect the next time the stream starts, after every sound has ended
+pub fn set_latency(ms: usize) -> Result<(), &'static str> {
+    let sd = get_sound_device().ok_or("The sound device hasn't been initialized")?;
+    sd.output_stream.set_latency(ms)
+}
+
+/// Writes the values of the controller, interrupt, CORB, RIRB and
+/// output stream descriptor registers to the serial debug log
+///
+/// Does nothing if serial debug logging hasn't been enabled
+pub fn dump_regs() {
+    if !serial::logging_enabled() {
+        return;
+    }
+    match get_sound_device() {
+        Some(sd) => sd.dump_regs(),
+        None => serial_println!("Sound device has not been initialized")
+    }
+}
+
+/// Returns the peak levels of the left and right channels in the
+/// samples that were played most recently
+///
+/// The levels are read from the mix buffer at the position the controller
+/// has reached, so they show that samples are really being fetched, even if
+/// the speakers are muted. With the software backend, they're the levels of
+/// the frames mixed most recently. Both are 0 when nothing is playing
+pub fn levels() -> (u16, u16) {
+    match get_backend() {
+        Some(backend) => backend.levels(),
+        None => (0, 0)
+    }
+}
+
+/// Starts recording what the microphone picks up into `buffer`, as 16 bit
+/// stereo samples at 48kHz, for `duration_ms` milliseconds or until `buffer`
+/// is full
+///
+/// Returns as soon as recording has started. `buffer` must start on a
+/// 128 byte boundary, like every buffer the controller fetches samples from.
+/// `take_recording` gives `buffer` back once recording stops, even if it
+/// couldn't start. Returns an error without keeping `buffer` if the last
+/// recording hasn't been taken back yet
+pub fn record(buffer: &'static mut [Sample], duration_ms: usize) -> Result<(), &'static str> {
+    let sd = get_sound_device().ok_or("The sound device hasn't been initialized")?;
+    sd.record(buffer, duration_ms)
+}
+
+/// Gives back the buffer given to `record` once recording has stopped, with
+/// the number of samples at its start that were recorded
+///
+/// Returns None while still recording, or if there's no buffer to give back
+pub fn take_recording() -> Option<(&'static mut [Sample], usize)> {
+    get_sound_device().and_then(|sd| sd.take_recording())
+}
+
+/// Tells whether or not `record` is still filling its buffer
+pub fn is_recording() -> bool {
+    match get_sound_device() {
+        Some(sd) => sd.record_hook.is_some(),
+        None => false
+    }
+}
+
+/// Stops recording before the buffer given to `record` is full
+pub fn stop_recording() {
+    if let Some(sd) = get_sound_device() {
+        sd.stop_recording();
+    }
 }
 
-pub fn stop_sound() -> Result<(), ()> {
-    let sd = get_sound_device().unwrap();
-    sd.stop_sound()
+/// Returns the peak levels of the left and right channels in the
+/// samples that were recorded most recently
+///
+/// Both are 0 when nothing is being recorded
+pub fn input_levels() -> (u16, u16) {
+    match get_sound_device() {
+        Some(sd) => sd.input_stream.as_ref().and_then(|stream| stream.levels()).unwrap_or((0, 0)),
+        None => (0, 0)
+    }
 }
 
 fn get_sound_device() -> Option<&'static mut SoundDevice> {
-    unsafe { SOUND_DEVICE.as_mut() }
+    unsafe { SOUND_DEVICE.as_deref_mut() }
+}
+
+/// What sounds are played through
+trait Backend {
+    fn play_sound(&mut self, sound: Sound, action_on_end: ActionOnEnd, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str>;
+    fn crossfade(&mut self, from: SoundHandle, to: Sound, ms: usize) -> Result<SoundHandle, &'static str>;
+    fn mixer(&mut self) -> &mut mixer::Mixer;
+    fn pause(&mut self);
+    fn resume(&mut self);
+    fn playback_position(&self, handle: SoundHandle) -> Option<PlaybackPosition>;
+    fn levels(&self) -> (u16, u16);
 }
 
-/// Searches all buses on the PCI until it finds the HDA
+/// The software backend if it was selected, or else the AC'97
+/// or HDA controller, whichever one was set up
+fn get_backend() -> Option<&'static mut dyn Backend> {
+    if let Some(software_sound) = software::get() {
+        return Some(software_sound);
+    }
+    if let Some(ac97) = ac97::get() {
+        return Some(ac97);
+    }
+    get_sound_device().map(|sd| sd as &mut dyn Backend)
+}
+
+impl Backend for SoundDevice {
+    fn play_sound(&mut self, sound: Sound, action_on_end: ActionOnEnd, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str> {
+        SoundDevice::play_sound(self, sound, action_on_end, priority, policy)
+    }
+
+    fn crossfade(&mut self, from: SoundHandle, to: Sound, ms: usize) -> Result<SoundHandle, &'static str> {
+        SoundDevice::crossfade(self, from, to, ms)
+    }
+
+    fn mixer(&mut self) -> &mut mixer::Mixer {
+        &mut self.mixer
+    }
+
+    fn pause(&mut self) {
+        SoundDevice::pause(self)
+    }
+
+    fn resume(&mut self) {
+        SoundDevice::resume(self)
+    }
+
+    fn playback_position(&self, handle: SoundHandle) -> Option<PlaybackPosition> {
+        SoundDevice::playback_position(self, handle)
+    }
+
+    fn levels(&self) -> (u16, u16) {
+        SoundDevice::levels(self)
+    }
+}
+
+/// Looks for the HDA among the devices on the PCI bus
+///
+/// According to the OSDev wiki, the best way to identify HDA is to look for
+/// the class code (0x4) and subclass (0x3)
 ///
 /// # References
 ///
 /// * https://wiki.osdev.org/PCI
 /// * https://wiki.osdev.org/Intel_High_Definition_Audio#Identifying_HDA_on_a_machine
 fn find_sound_device() -> Option<SoundDevice> {
-    for bus in 0..=255 {
-        for device in 0..32 {
-            for func in 0..8 {
-                let pci_device = PCIDevice { bus, device, func };
-                if pci_device.is_valid() {
-                    // No vendor id is ever equal to 0xffff.
-                    // According to the OSDev wiki, the best way to identify HDA is to look for
-                    // the class code (0x4) and subclass (0x3)
-                    if pci_device.classcode() == 0x4 && pci_device.subclass() == 0x3 {
-                        return Some(SoundDevice::from(pci_device));
-                    }
-                }
-            }
-        }
-    }
-    None
+    pci::find_device(0x4, 0x3).map(SoundDevice::from)
 }
 
 type SampleDerefMut = &'static mut dyn DerefMut<Target=[Sample]>;
 
 #[derive(Clone, Copy)]
 pub struct Sound {
-    file: WavFile,
-    sample_buffer: &'static [Sample]
+    /// The sample rate of the sound's samples, which the mixer converts to the mix rate
+    rate: u32,
+    samples: SoundSamples
+}
+
+/// Where the mixer reads a sound's frames from
+#[derive(Clone, Copy)]
+enum SoundSamples {
+    /// 16 bit stereo samples, converted from a file, synthesized,
+    /// or a file's own samples when they're already in this format
+    Converted(&'static [Sample]),
+    /// The file's own samples, which are converted as they're mixed
+    Streamed(WavFile)
 }
 
 impl Sound {
-    fn sample_len(&self) -> usize {
-        self.sample_buffer.len()
+    /// The number of frames in the sound
+    fn frames(&self) -> usize {
+        match self.samples {
+            SoundSamples::Converted(samples) => samples.len() / 2,
+            SoundSamples::Streamed(file) => format::file_frames(&file)
+        }
+    }
+
+    /// The left and right samples of the frame at `idx`
+    fn frame(&self, idx: usize) -> (i16, i16) {
+        match self.samples {
+            SoundSamples::Converted(samples) => (samples[idx * 2].0 as i16, samples[idx * 2 + 1].0 as i16),
+            SoundSamples::Streamed(file) => format::read_frame(&file, idx)
+        }
     }
 
-    fn sample_buffer_ptr(&self) -> *const Sample {
-        self.sample_buffer.as_ptr()
+    fn rate(&self) -> u32 {
+        self.rate
     }
 }
 
 impl Sound {
-    pub fn new(file: WavFile, sample_buffer: SampleDerefMut) -> Self {
-        let sample_bytes = file.data_bytes();
-        let sample_bytes_len = sample_bytes.len();
-        let sample_ptr = sample_bytes.as_ptr();
-        for i in 0..sample_bytes_len {
-            unsafe { sample_buffer[i] = Sample(sample_ptr.offset(i.as_isize()).read())
-            };
+    /// Converts the samples in `file` to 16 bit stereo samples in `sample_buffer`
+    ///
+    /// Fails if the file's format can't be played or the buffer is too small
+    /// for the converted samples
+    pub fn new(file: WavFile, sample_buffer: SampleDerefMut) -> Result<Self, &'static str> {
+        let len = format::output_len(&file);
+        let sample_buffer: &'static mut [Sample] = sample_buffer;
+        format::convert(&file, sample_buffer)?;
+        Ok(Self {
+            rate: file.sample_rate(),
+            samples: SoundSamples::Converted(&sample_buffer[..len])
+        })
+    }
+
+    /// Plays the samples in `file` straight from the file, converting
+    /// them as they're mixed instead of copying them into a sample buffer
+    ///
+    /// Takes no memory besides the file's, so it suits long music. Only the
+    /// chunks of the mix buffer the controller has played are refilled from
+    /// the file, at every buffer completion interrupt.
+    /// ADPCM samples can't be streamed, since a frame can only be decoded
+    /// along with the frames before it in its block
+    pub fn streamed(file: WavFile) -> Result<Self, &'static str> {
+        format::check_streamable(&file)?;
+        Ok(Self {
+            rate: file.sample_rate(),
+            samples: SoundSamples::Streamed(file)
+        })
+    }
+
+    /// Plays the samples in `file` from where they are, without a sample buffer
+    ///
+    /// 16 bit stereo samples in a data chunk that's 2 byte aligned are played
+    /// as they are, and other PCM samples are streamed. Either way, the file's
+    /// bytes are the only memory the sound takes.
+    /// Fails for ADPCM samples, which have to be decoded into a sample buffer
+    pub fn in_place(file: WavFile) -> Result<Self, &'static str> {
+        match format::as_samples(&file) {
+            Some(samples) => Ok(Self {
+                rate: file.sample_rate(),
+                samples: SoundSamples::Converted(samples)
+            }),
+            None => Self::streamed(file)
         }
-        Self {
-            file,
-            sample_buffer: sample_buffer
+    }
+
+    /// Synthesizes `notes` played with `waveform` into `sample_buffer`
+    ///
+    /// Fails if the buffer is smaller than `synth::rendered_len` of the notes
+    pub fn synthesized(notes: &[Note], waveform: Waveform, amplitude: i16, sample_buffer: SampleDerefMut) -> Result<Self, &'static str> {
+        let len = synth::rendered_len(notes);
+        let sample_buffer: &'static mut [Sample] = sample_buffer;
+        synth::render(notes, waveform, amplitude, sample_buffer)?;
+        Ok(Self {
+            rate: synth::SYNTH_RATE,
+            samples: SoundSamples::Converted(&sample_buffer[..len])
+        })
+    }
+
+    /// The number of samples in the buffer `Sound::new` needs for `file`
+    pub fn sample_buffer_len(file: &WavFile) -> usize {
+        format::output_len(file)
+    }
+}
+
+impl Sound {
+    /// Copies the samples in `file` into a new sample buffer on the heap,
+    /// unless they can be played in place
+    fn new_on_heap(file: WavFile) -> Result<Self, &'static str> {
+        if let Some(samples) = format::as_samples(&file) {
+            return Ok(Self {
+                rate: file.sample_rate(),
+                samples: SoundSamples::Converted(samples)
+            });
         }
+        let sample_buffer = alloc_sample_buffer(format::output_len(&file))?;
+        format::convert(&file, sample_buffer)?;
+        Ok(Self {
+            rate: file.sample_rate(),
+            samples: SoundSamples::Converted(sample_buffer)
+        })
+    }
+
+    /// Synthesizes `notes` played with `waveform` into a new sample buffer on the heap
+    fn synthesized_on_heap(notes: &[Note], waveform: Waveform, amplitude: i16) -> Result<Self, &'static str> {
+        let sample_buffer = alloc_sample_buffer(synth::rendered_len(notes))?;
+        synth::render(notes, waveform, amplitude, sample_buffer)?;
+        Ok(Self {
+            rate: synth::SYNTH_RATE,
+            samples: SoundSamples::Converted(sample_buffer)
+        })
+    }
+}
+
+/// Allocates a sample buffer of `len` samples on the heap, which is never freed
+fn alloc_sample_buffer(len: usize) -> Result<&'static mut [Sample], &'static str> {
+    // Buffers in the buffer descriptor list must be 128 byte aligned,
+    // but the allocator doesn't align what it hands out
+    let buffer_size = len * mem::size_of::<Sample>() + SAMPLE_BUFFER_ALIGN - 1;
+    let buffer_ptr = unsafe { allocator::get_allocator().alloc(1, buffer_size) }
+        .map_err(|_| "No enough space on the heap for the sound")?;
+    let buffer_ptr = unsafe { buffer_ptr.add(buffer_ptr.align_offset(SAMPLE_BUFFER_ALIGN)).cast::<Sample>() };
+    Ok(unsafe { core::slice::from_raw_parts_mut(buffer_ptr, len) })
+}
+
+/// The alignment the controller requires of the sample buffers
+const SAMPLE_BUFFER_ALIGN: usize = 128;
+
+/// Moves `value`, which holds buffers the controller fetches, onto the heap,
+/// where it's never freed
+///
+/// The bootloaders put the heap below 4GiB, so controllers without
+/// 64 bit addressing can reach it
+fn alloc_dma<T>(value: T) -> Result<&'static mut T, &'static str> {
+    // The allocator doesn't align what it hands out
+    let buffer_size = mem::size_of::<T>() + mem::align_of::<T>() - 1;
+    let buffer_ptr = unsafe { allocator::get_allocator().alloc(1, buffer_size) }
+        .map_err(|_| "No enough space on the heap for the controller's buffers")?;
+    let ptr = unsafe { buffer_ptr.add(buffer_ptr.align_offset(mem::align_of::<T>())).cast::<T>() };
+    check_dma_range(ptr as u64, mem::size_of::<T>(), false)?;
+    unsafe {
+        ptr.write(value);
+        Ok(&mut *ptr)
     }
 }
 
 type StreamTag = usize;
 
+/// The number of times a register is read while waiting for
+/// the controller before it's considered unresponsive
+const CONTROLLER_TIMEOUT: usize = 1_000_000;
+
+/// Waits for `done` to return true, giving up after `CONTROLLER_TIMEOUT` tries
+///
+/// Returns true if it did
+fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+    (0..CONTROLLER_TIMEOUT).any(|_| done())
+}
+
+/// The number of chunks the mix buffer is split into
+const MIX_CHUNKS: usize = 4;
+
+/// The number of frames in a mix buffer of `chunk_len` sample chunks that have
+/// been mixed but not played yet, when the controller has reached the sample
+/// at `position` and `next_chunk_to_fill` is the chunk that's mixed next
+fn queued_frames(position: usize, next_chunk_to_fill: usize, chunk_len: usize) -> usize {
+    let len = chunk_len * MIX_CHUNKS;
+    let mixed_up_to = next_chunk_to_fill * chunk_len;
+    // Once the played chunks are filled again, everything up to the
+    // start of the chunk being played has been mixed
+    match (mixed_up_to + len - position % len) % len {
+        0 => len / 2,
+        queued => queued / 2
+    }
+}
+
+/// The tag the headphone pin's unsolicited responses are sent with
+const JACK_SENSE_TAG: u8 = 1;
+
+/// Hooks the actions of the sounds that ended while mixing to run once,
+/// on the next timer tick
+///
+/// The actions can use the sound device to play other sounds, so they aren't
+/// run while it's mixing. The timer is used because the sound interrupts
+/// stop once nothing is playing
+fn schedule_ended_actions(ended: EndedActions) {
+    let once = HookOptions { once: true, ..HookOptions::default() };
+    for action in ended.into_iter().flatten() {
+        event_hook::hook_event_with_options(EventKind::Timer, once, action);
+    }
+}
+
+/// The number of stereo sample pairs the levels are measured over, about 23ms of sound
+const LEVEL_WINDOW_FRAMES: usize = 1024;
+
+/// The peak levels of the left and right channels in `samples`,
+/// which are interleaved 16 bit stereo samples
+fn peak_levels(samples: &[Sample]) -> (u16, u16) {
+    let mut peaks = (0, 0);
+    for frame in samples.chunks_exact(2) {
+        // The samples are signed
+        let left = (frame[0].0 as i16).unsigned_abs();
+        let right = (frame[1].0 as i16).unsigned_abs();
+        peaks = (peaks.0.max(left), peaks.1.max(right));
+    }
+    peaks
+}
+
 /// An output stream that represents a connection
 /// between sound sample buffers and the HDA sound controller
 ///
-/// This stream assumes that the sound samples in the wav file
-/// have a sample rate of 44.1kHz, 16 bits per sample and 2 channels
+/// The stream plays the mix buffer, which holds 16 bit stereo samples at 48kHz
 struct OutputStream {
     regs: &'static mut StreamDescriptorRegs,
     bdl: BufferDescriptorList,
     /// A number in the range 1..=15 that is used to identify
     /// a stream by the controller
-    tag: StreamTag
+    tag: StreamTag,
+    /// The amount of sound, in milliseconds, in each chunk of the mix buffer
+    latency_ms: usize,
+    /// Tells whether or not the controller can fetch the BDL and
+    /// sample buffers from above 4GiB
+    addr_64bit_supported: bool,
+    /// The samples the stream has been set up to play, which the levels are read from
+    samples: Option<&'static [Sample]>,
+    /// The link position the stream was at when it was paused
+    paused_at: Option<usize>
 }
 
 impl OutputStream {
+    /// The size of each sample the stream plays
+    const BITS_PER_SAMPLE: BitsPerSample = BitsPerSample::Sixteen;
+    /// The number of channels in each frame the stream plays
+    const CHANNELS: NumOfChannels = NumOfChannels::Two;
+    /// The number of bytes in a frame of the stream's format
+    const BYTES_PER_FRAME: usize = Self::CHANNELS.count() * Self::BITS_PER_SAMPLE.container_bytes();
+    /// The HDA spec dictates that there must be at least 2 entries in the BDL
+    const MIN_BDL_ENTRIES: usize = 2;
+    /// The largest latency that can be requested with `set_latency`
+    const MAX_LATENCY_MS: usize = 1000;
+    /// The latency until `set_latency` is called
+    const DEFAULT_LATENCY_MS: usize = 20;
+    /// Every buffer in the buffer descriptor list must start on a
+    /// 128 byte boundary
+    const BDL_ENTRY_ALIGN: usize = 128;
+    /// The number of times the run bit is read while waiting for the
+    /// stream to stop before the stream is considered stuck
+    const STOP_TIMEOUT: usize = 1_000_000;
+
     fn new(regs: &'static mut StreamDescriptorRegs, tag: StreamTag) -> Self {
         assert!(tag < 16);
         Self {
             regs,
             tag,
-            bdl: BufferDescriptorList::new()
+            bdl: BufferDescriptorList::new(),
+            latency_ms: Self::DEFAULT_LATENCY_MS,
+            // Assuming the worst until the controller's capabilities have been read
+            addr_64bit_supported: false,
+            samples: None,
+            paused_at: None
+        }
+    }
+
+    /// Sets the length of the mix buffer's chunks to the
+    /// number of samples played in `ms` milliseconds
+    fn set_latency(&mut self, ms: usize) -> Result<(), &'static str> {
+        if ms == 0 || ms > Self::MAX_LATENCY_MS {
+            return Err("Latency must be in the range 1..=1000 ms");
         }
+        self.latency_ms = ms;
+        Ok(())
+    }
+
+    /// The number of samples in a chunk of the mix buffer
+    ///
+    /// The chunks are described by buffer descriptor list entries,
+    /// so their lengths in bytes are kept to the entries' alignment
+    fn chunk_len(&self) -> usize {
+        Self::chunk_len_for(self.latency_ms)
+    }
+
+    fn chunk_len_for(ms: usize) -> usize {
+        let len = ms * MIX_RATE as usize / 1000 * Self::BYTES_PER_FRAME;
+        len.align_up(Self::BDL_ENTRY_ALIGN) / mem::size_of::<Sample>()
     }
 
     // A seperate init function is needed because the controller
     // has to be setup before writing to registers
-    fn init(&mut self) {
-        self.regs.format.set_sample_base_rate(SampleBaseRate::KHz44P1);
+    fn init(&mut self) -> Result<(), &'static str> {
+        self.regs.format.set_sample_base_rate(SampleBaseRate::KHz48);
         self.regs.format.set_sample_base_rate_multiple(SampleBaseRateMultiple::KHz48OrLess);
         self.regs.format.set_sample_base_rate_divisor(SampleBaseRateDivisor::One);
-        self.regs.format.set_bits_per_sample(BitsPerSample::Sixteen);
-        self.regs.format.set_number_of_channels(NumOfChannels::Two);
+        self.regs.format.set_bits_per_sample(Self::BITS_PER_SAMPLE);
+        self.regs.format.set_number_of_channels(Self::CHANNELS);
         self.regs.last_valid_index.set_last_valid_index(1);
         self.regs.control.set_stream_number(self.tag.as_u8());
         self.regs.control.set_interrupt_on_completion_enable(true);
-        self.regs.set_bdl_base_addr(&self.bdl);
+        self.regs.control.enable_fifo_interrupt(true);
+        self.regs.control.enable_descriptor_error_interrupt(true);
+        self.regs.set_bdl_base_addr(&self.bdl, self.addr_64bit_supported)
     }
 
-    fn setup_sound_stream(&mut self, sound: Sound) {
-        let bdl_entry = BufferDescriptorListEntry {
-            addr: sound.sample_buffer_ptr(),
-            len: sound.sample_len().as_u32(),
-            interrupt_on_completion: InterruptOnCompletion::new()
-        };
+    /// Sets the stream up to play `samples` over and over, in `chunks` chunks
+    /// that each interrupt on completion
+    fn setup_cyclic_buffer(&mut self, samples: &'static [Sample], chunks: usize) -> Result<(), &'static str> {
         // BDL should be empty before starting a stream to make sure no
         // other stream is currently running
         assert!(self.bdl.next_index == 0);
-        // The HDA spec dictates that there must be at least 2 entries
-        // in the BDL
-        self.bdl.add_entry(bdl_entry).unwrap();
-        self.bdl.add_entry(bdl_entry).unwrap();
+        if chunks < Self::MIN_BDL_ENTRIES || samples.len() < chunks {
+            return Err("The cyclic buffer must be split into at least 2 non-empty chunks");
+        }
+        let len_bytes = samples.len() * mem::size_of::<Sample>();
+        check_dma_range(samples.as_ptr() as u64, len_bytes, self.addr_64bit_supported)?;
+        let chunk_len = samples.len() / chunks;
+        for chunk in samples.chunks_exact(chunk_len) {
+            let mut entry = BufferDescriptorListEntry::new(chunk.as_ptr(), chunk_len * mem::size_of::<Sample>());
+            entry.interrupt_on_completion.set(true);
+            self.bdl.add_entry(entry).unwrap();
+        }
+        // The BDL entries have to be in memory before the controller
+        // is told how many of them there are
+        barrier::mfence();
         self.regs.cyclic_buffer_len.set_cyclic_buffer_len(self.bdl.data_bytes_len());
+        self.regs.last_valid_index.set_last_valid_index((self.bdl.no_of_entries() - 1).as_u8());
+        self.samples = Some(samples);
+        Ok(())
     }
 
-    fn stop(&mut self) {
+    /// The index of the sample in the cyclic buffer the controller has reached
+    fn position(&self) -> usize {
+        self.regs.link_pos_in_buffer.link_pos_in_buffer().as_usize() / mem::size_of::<Sample>()
+    }
+
+    /// Checks if a buffer descriptor list entry has been completed since the
+    /// last check, which means the interrupt came from this stream
+    fn take_buffer_completion(&mut self) -> bool {
+        let completed = self.regs.status.buffer_completion_interrupt_status();
+        if completed {
+            self.regs.status.clear_buffer_completion_interrupt_status();
+        }
+        completed
+    }
+
+    /// Stops the stream, resetting it if the run bit doesn't clear in time
+    ///
+    /// Returns an error if the run bit is still set after the reset
+    fn stop(&mut self) -> Result<(), &'static str> {
         self.regs.control.set_stream_run(false);
         // The HDA spec doesn't say anything about waiting here
         // but is seems necessary on my computer
-        while self.regs.control.stream_run() == true {}
+        if self.wait_for_stop() {
+            return Ok(());
+        }
+        serial_println!("Output stream {} didn't stop, resetting it", self.tag);
+        self.reset();
+        if self.wait_for_stop() {
+            Ok(())
+        } else {
+            Err("The output stream's run bit is stuck")
+        }
+    }
+
+    /// Stops the stream's DMA where it is, keeping the BDL and the position
+    /// in the cyclic buffer, so `resume` carries on from the same sample
+    ///
+    /// Returns an error if the run bit doesn't clear in time
+    fn pause(&mut self) -> Result<(), &'static str> {
+        self.regs.control.set_stream_run(false);
+        if !self.wait_for_stop() {
+            return Err("The output stream's run bit is stuck");
+        }
+        self.paused_at = Some(self.position());
+        Ok(())
+    }
+
+    /// Starts the DMA again from the position the stream was paused at
+    fn resume(&mut self) {
+        if let Some(pos) = self.paused_at.take() {
+            if self.position() != pos {
+                serial_println!("Output stream {} moved from {} to {} while paused", self.tag, pos, self.position());
+            }
+            self.start();
+        }
+    }
+
+    /// Waits for the run bit to clear, giving up after `STOP_TIMEOUT` tries
+    ///
+    /// Returns true if it cleared
+    fn wait_for_stop(&self) -> bool {
+        (0..Self::STOP_TIMEOUT).any(|_| !self.regs.control.stream_run())
     }
 
     fn start(&mut self) {
+        // The BDL and the sample buffers have to be in memory
+        // before the DMA engine starts reading them
+        barrier::mfence();
         self.regs.control.set_stream_run(true);
     }
 
@@ -181,19 +862,174 @@ impl OutputStream {
         self.regs.control.exit_stream_reset();
         while time < 1000 && self.regs.control.stream_reset() == true { time += 1; }
         self.bdl.clear_entries();
+        self.samples = None;
+        self.paused_at = None;
+    }
+
+    /// The peak levels of the samples just before the position the
+    /// controller has reached, or None if the stream isn't running
+    fn levels(&self) -> Option<(u16, u16)> {
+        let samples = self.samples?;
+        if !self.regs.control.stream_run() || samples.is_empty() {
+            return None;
+        }
+        // Keeping to whole stereo frames
+        let end = (self.position() % samples.len()) & !1;
+        let start = end.saturating_sub(LEVEL_WINDOW_FRAMES * 2);
+        Some(peak_levels(&samples[start..end]))
     }
 
     fn has_initialized(&self) -> bool {
-        !self.regs.control.stream_reset() && self.bdl.no_of_entries() == 2
+        !self.regs.control.stream_reset() && self.bdl.no_of_entries() >= 2
+    }
+}
+
+/// An input stream that represents a connection
+/// between an ADC and a buffer that recorded samples are written into
+///
+/// Records 16 bit stereo samples at 48kHz, like the output stream plays
+struct InputStream {
+    regs: &'static mut StreamDescriptorRegs,
+    bdl: BufferDescriptorList,
+    /// A number in the range 1..=15 that is used to identify
+    /// a stream by the controller
+    tag: StreamTag,
+    /// Tells whether or not the controller can fetch the BDL and
+    /// write to buffers above 4GiB
+    addr_64bit_supported: bool,
+    /// The buffer the stream has been set up to record into
+    samples: Option<&'static [Sample]>
+}
+
+impl InputStream {
+    /// The number of times the run bit is read while waiting for the
+    /// stream to stop before the stream is considered stuck
+    const STOP_TIMEOUT: usize = 1_000_000;
+
+    fn new(regs: &'static mut StreamDescriptorRegs, tag: StreamTag) -> Self {
+        assert!(tag < 16);
+        Self {
+            regs,
+            tag,
+            bdl: BufferDescriptorList::new(),
+            // Assuming the worst until the controller's capabilities have been read
+            addr_64bit_supported: false,
+            samples: None
+        }
+    }
+
+    fn init(&mut self) -> Result<(), &'static str> {
+        self.regs.format.set_sample_base_rate(SampleBaseRate::KHz48);
+        self.regs.format.set_sample_base_rate_multiple(SampleBaseRateMultiple::KHz48OrLess);
+        self.regs.format.set_sample_base_rate_divisor(SampleBaseRateDivisor::One);
+        self.regs.format.set_bits_per_sample(OutputStream::BITS_PER_SAMPLE);
+        self.regs.format.set_number_of_channels(OutputStream::CHANNELS);
+        self.regs.last_valid_index.set_last_valid_index(1);
+        self.regs.control.set_stream_number(self.tag.as_u8());
+        self.regs.control.set_interrupt_on_completion_enable(true);
+        self.regs.control.enable_fifo_interrupt(true);
+        self.regs.control.enable_descriptor_error_interrupt(true);
+        self.regs.set_bdl_base_addr(&self.bdl, self.addr_64bit_supported)
+    }
+
+    /// Sets the stream up to record into `samples`, which is split into
+    /// the 2 entries the BDL needs at least
+    ///
+    /// Only the last entry interrupts on completion, which is when the
+    /// stream is stopped, since it would wrap around and overwrite
+    /// the start of `samples` otherwise
+    fn setup_buffer(&mut self, samples: &'static [Sample]) -> Result<(), &'static str> {
+        assert!(self.bdl.next_index == 0);
+        let len_bytes = samples.len() * mem::size_of::<Sample>();
+        if samples.as_ptr() as usize % SAMPLE_BUFFER_ALIGN != 0 {
+            return Err("The record buffer must start on a 128 byte boundary");
+        }
+        if len_bytes == 0 || len_bytes % (2 * SAMPLE_BUFFER_ALIGN) != 0 {
+            return Err("The record buffer's length must be a multiple of 256 bytes");
+        }
+        check_dma_range(samples.as_ptr() as u64, len_bytes, self.addr_64bit_supported)?;
+        let half_len = samples.len() / 2;
+        for (i, half) in samples.chunks_exact(half_len).enumerate() {
+            let mut entry = BufferDescriptorListEntry::new(half.as_ptr(), half_len * mem::size_of::<Sample>());
+            entry.interrupt_on_completion.set(i == 1);
+            self.bdl.add_entry(entry).unwrap();
+        }
+        barrier::mfence();
+        self.regs.cyclic_buffer_len.set_cyclic_buffer_len(self.bdl.data_bytes_len());
+        self.regs.last_valid_index.set_last_valid_index((self.bdl.no_of_entries() - 1).as_u8());
+        self.samples = Some(samples);
+        Ok(())
+    }
+
+    /// The index of the sample in the buffer the controller has reached
+    fn position(&self) -> usize {
+        self.regs.link_pos_in_buffer.link_pos_in_buffer().as_usize() / mem::size_of::<Sample>()
+    }
+
+    /// Checks if the buffer has been filled since the last check,
+    /// which means the interrupt came from this stream
+    fn take_buffer_completion(&mut self) -> bool {
+        let completed = self.regs.status.buffer_completion_interrupt_status();
+        if completed {
+            self.regs.status.clear_buffer_completion_interrupt_status();
+        }
+        completed
+    }
+
+    fn start(&mut self) {
+        barrier::mfence();
+        self.regs.control.set_stream_run(true);
+    }
+
+    /// Stops the stream and resets it, so it's ready to record into another buffer
+    ///
+    /// Returns an error if the run bit is still set after the reset
+    fn stop(&mut self) -> Result<(), &'static str> {
+        self.regs.control.set_stream_run(false);
+        let stopped = (0..Self::STOP_TIMEOUT).any(|_| !self.regs.control.stream_run());
+        self.reset();
+        if stopped || !self.regs.control.stream_run() {
+            Ok(())
+        } else {
+            Err("The input stream's run bit is stuck")
+        }
+    }
+
+    fn reset(&mut self) {
+        self.regs.control.enter_stream_reset();
+        wait_until(|| self.regs.control.stream_reset());
+        self.regs.control.exit_stream_reset();
+        wait_until(|| !self.regs.control.stream_reset());
+        self.bdl.clear_entries();
+        self.samples = None;
+    }
+
+    /// The peak levels of the samples just before the position the
+    /// controller has reached, or None if the stream isn't running
+    fn levels(&self) -> Option<(u16, u16)> {
+        let samples = self.samples?;
+        if !self.regs.control.stream_run() {
+            return None;
+        }
+        let end = self.position().min(samples.len()) & !1;
+        let start = end.saturating_sub(LEVEL_WINDOW_FRAMES * 2);
+        Some(peak_levels(&samples[start..end]))
     }
 }
 
-/// Indicates the action to be taken when a stream
+/// Indicates the action to be taken when a sound
 /// has ended
 #[derive(Debug)]
 pub enum ActionOnEnd {
     Stop,
     Replay,
+    /// Plays the sound up to `end_sample`, then goes back to `start_sample` and
+    /// plays the part in between over and over, for music with an intro that
+    /// isn't repeated
+    ///
+    /// Both are frames of the sound, at the sound's own sample rate, and
+    /// `end_sample` is the first frame after the loop
+    LoopRange { start_sample: usize, end_sample: usize },
     Action(BoxedFn<'static>)
 }
 
@@ -394,6 +1230,7 @@ impl FuncGroup {
     }
 }
 
+#[derive(Clone)]
 struct NodeIter {
     start_node: NodeAddr,
     num_of_nodes: u8,
@@ -464,6 +1301,44 @@ impl Pin {
         commander.command(pin_widget_ctrl_command);
     }
 
+    /// Turns the pin's input and output off
+    fn disable(&mut self, commander: &mut Commander) {
+        let pin_widget_ctrl_command = HDANodeCommand::set_pin_widget_control(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            PinControl::new()
+        );
+        commander.command(pin_widget_ctrl_command);
+    }
+
+    /// Tells whether or not something is plugged into the pin's jack
+    fn presence_detected(&self, commander: &mut Commander) -> bool {
+        let cmd = HDANodeCommand::get_pin_sense(self.addr);
+        commander.command(cmd)
+            .pin_sense_resp()
+            .unwrap()
+            .presence_detected()
+    }
+
+    /// Makes the pin send an unsolicited response with `tag`
+    /// whenever something is plugged into or out of its jack
+    fn enable_unsolicited(&mut self, tag: u8, commander: &mut Commander) {
+        let cmd = HDANodeCommand::set_unsolicited_enable(self.addr, tag, true);
+        commander.command(cmd);
+    }
+
+    /// Enables the pin as an input only, for microphones
+    fn enable_input(&mut self, commander: &mut Commander) {
+        let pin_ctrl = PinControl::new()
+            .input_enabled(true);
+        let pin_widget_ctrl_command = HDANodeCommand::set_pin_widget_control(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            pin_ctrl
+        );
+        commander.command(pin_widget_ctrl_command);
+    }
+
     fn eapd_enable(&self, commander: &mut Commander) -> EAPDEnable {
         let cmd = HDANodeCommand::eapd_enable(self.addr);
         commander.command(cmd)
@@ -592,6 +1467,25 @@ impl DAC {
         commander.command(set_amp_gain_command);
     }
 
+    /// Sets the gain of the DAC's output amplifier
+    ///
+    /// `gain` is a step in the range 0..=`output_amp_cap().num_of_steps()`
+    fn set_gain(&self, gain: u8, commander: &mut Commander) {
+        let amp_gain = AmpGain::new()
+            .mute(false)
+            .output_amp(true)
+            .left_amp(true)
+            .right_amp(true)
+            .index(0)
+            .gain(gain);
+        let set_amp_gain_command = HDANodeCommand::set_amp_gain(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            amp_gain
+        );
+        commander.command(set_amp_gain_command);
+    }
+
     fn power_up(&self, commander: &mut Commander) {
         let set_power_command = HDANodeCommand::set_power_state(
             self.addr.codec_addr(),
@@ -629,6 +1523,78 @@ impl PartialEq<NodeAddr> for DAC {
     }
 }
 
+/// An audio input converter, which turns what an input pin picks up
+/// into samples for an input stream
+#[derive(Clone, Debug)]
+struct ADC {
+    addr: NodeAddr,
+    conn_list: Vec<'static, (u8, NodeAddr)>
+}
+
+impl ADC {
+    fn new(codec_addr: u8, node_id: u8) -> Self {
+        Self {
+            addr: NodeAddr(codec_addr, node_id),
+            conn_list: vec!(item_type => (u8, NodeAddr), capacity => 5)
+        }
+    }
+
+    fn setup_stream_and_channel(&mut self, commander: &mut Commander, stream: u8, channel: u8) {
+        let converter_ctrl = ConverterControl::new()
+            .stream(stream)
+            .channel(channel);
+        let converter_control_command = HDANodeCommand::set_converter_control(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            converter_ctrl
+        );
+        commander.command(converter_control_command);
+    }
+
+    /// Unmutes the ADC's input amplifier for the input at `idx` in its connection list
+    fn unmute_input(&mut self, idx: u8, commander: &mut Commander) {
+        let amp_gain = AmpGain::new()
+            .mute(false)
+            .input_amp(true)
+            .left_amp(true)
+            .right_amp(true)
+            .index(idx)
+            .gain(0x7f);
+        let set_amp_gain_command = HDANodeCommand::set_amp_gain(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            amp_gain
+        );
+        commander.command(set_amp_gain_command);
+    }
+
+    fn power_up(&self, commander: &mut Commander) {
+        let set_power_command = HDANodeCommand::set_power_state(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            PowerState::D0
+        );
+        commander.command(set_power_command);
+    }
+
+    fn set_converter_format(&mut self, format: u16, commander: &mut Commander) {
+        let set_format = HDANodeCommand::set_converter_format(self.addr, format);
+        commander.command(set_format);
+    }
+}
+
+impl NodeWithConnList for ADC {
+    fn conn_list(&self) -> &Vec<'static, (u8, NodeAddr)> {
+        &self.conn_list
+    }
+}
+
+impl Widget for ADC {
+    fn addr(&self) -> NodeAddr {
+        self.addr
+    }
+}
+
 #[derive(Clone, Debug)]
 struct Mixer {
     addr: NodeAddr,
@@ -658,6 +1624,23 @@ impl Mixer {
             .unwrap();
         resp.power_ctrl_supported()
     }    
+
+    /// Unmutes the mixer's input amplifier for the input at `idx` in its connection list
+    fn unmute_input(&mut self, idx: u8, commander: &mut Commander) {
+        let amp_gain = AmpGain::new()
+            .mute(false)
+            .input_amp(true)
+            .left_amp(true)
+            .right_amp(true)
+            .index(idx)
+            .gain(0x7f);
+        let set_amp_gain_command = HDANodeCommand::set_amp_gain(
+            self.addr.codec_addr(),
+            self.addr.node_id(),
+            amp_gain
+        );
+        commander.command(set_amp_gain_command);
+    }
 }
 
 impl NodeWithConnList for Mixer {
@@ -699,190 +1682,6 @@ impl Widget for Selector {
     }
 }
 
-/// A device on the PCI bus
-///
-/// It is assumed that the device has a PCI configuration header of type 0x0
-///
-/// # References
-///
-/// * The OSDev wiki <https://wiki.osdev.org/PCI>
-#[derive(Clone, Copy)]
-struct PCIDevice {
-    bus: u32,
-    device: u32,
-    func: u32
-}
-
-impl PCIDevice {
-    // Register offsets for values in the PCI configuration header
-    const DEVICE_AND_VENDOR_ID_OFFSET: u32 = 0x0;
-    const STATUS_AND_COMMAND_OFFSET: u32 = 0x04;
-    const CLASSCODE_AND_SUBCLASS_OFFSET: u32 = 0x8;
-    const HEADER_TYPE_OFFSET: u32 = 0xc;
-    const BAR0_OFFSET: u32 = 0x10;
-    const BAR1_OFFSET: u32 = 0x14;
-    const INTERRUPT_PIN_LINE_OFFSET: u32 = 0x3c;
-
-    /// This port is written to specify which configuration header of a PCI device
-    /// should be read from the `DATA_PORT`
-    const ADDR_PORT: u16 = 0xcf8;
-    /// The data this port outputs is the data from the configuration header previously
-    /// specified by writing to the `ADDR_PORT`
-    const DATA_PORT: u16 = 0xcfc;
-
-    /// Checks if the device specified by the bus, device and func numbers
-    /// is a valid device on the PCI
-    ///
-    /// According to the OSDev wiki <https://wiki.osdev.org/PCI>, no valid device
-    /// can have a vendor id of 0xfff
-    fn is_valid(&self) -> bool {
-        self.vendor_id() != 0xfff
-    }
-
-    /// Returns the device's vendor id from the PCI configuration header
-    fn vendor_id(&self) -> u16 {
-        let device_vendor_id_addr: u32 = self.reg_addr(Self::DEVICE_AND_VENDOR_ID_OFFSET);
-        let mut address_port: Port<u32> = Port::new(Self::ADDR_PORT);
-        address_port.write(device_vendor_id_addr);
-        let data_port: Port<u32> = Port::new(Self::DATA_PORT);
-        let val = data_port.read();
-        val as u16
-    }
-
-    fn read_classcode_subclass_reg(&self) -> (u8, u8) {
-        let mut address_port: Port<u32> = Port::new(Self::ADDR_PORT);
-        let data_port: Port<u32> = Port::new(Self::DATA_PORT);
-        let address: u32 = self.reg_addr(Self::CLASSCODE_AND_SUBCLASS_OFFSET);
-        address_port.write(address);
-        let val = data_port.read();
-        let classcode = (val >> 24) as u8;
-        let subclass = ((val >> 16) & 0xff) as u8;
-        (classcode, subclass)
-    }
-
-    fn header_type(&self) -> PCIHeaderType {
-        let (mut address_port, data_port) = self.ports();
-        let address: u32 = self.reg_addr(Self::HEADER_TYPE_OFFSET);
-        address_port.write(address);
-        let val = data_port.read();
-        let val = PCIHeaderTypeReg(((val >> 16) & 0xff) as u8);
-        val.header_type().unwrap()
-    }
-
-    fn bar0(&self) -> PCIBaseAddrReg {
-        assert_eq!(self.header_type(), PCIHeaderType::Standard);
-        let (mut addr_port, data_port) = self.ports();
-        let addr: u32 = self.reg_addr(Self::BAR0_OFFSET);
-        addr_port.write(addr);
-        let val1 = data_port.read();
-        let addr = self.reg_addr(Self::BAR1_OFFSET);
-        addr_port.write(addr);
-        let val2 = data_port.read();
-        let bar = PCIBaseAddrReg::try_from((val1, val2)).unwrap();
-        assert_eq!(bar.kind(), PCIBaseAddrKind::Memory);
-        bar
-    }
-
-    /*fn size_of_addr_space_needed(&self) -> u32 {
-        assert_eq!(self.header_type(), PCIHeaderType::Standard);
-        let mut addr_port: Port<u32> = Port::new(Self::ADDR_PORT);
-        let mut data_port: Port<u32> = Port::new(Self::DATA_PORT);
-        let addr: u32 = self.reg_addr(Self::BAR0_OFFSET);
-        addr_port.write(addr);
-        let baddr_reg_val = data_port.read();
-        data_port.write(u32::MAX);
-        let new_val = data_port.read();
-        let amount_of_mem_needed = (!new_val) + 1;
-        data_port.write(baddr_reg_val);
-        amount_of_mem_needed
-    }*/
-
-    fn interrupt_pin(&self) -> u8 {
-        assert_eq!(self.header_type(), PCIHeaderType::Standard);
-        let (mut addr_port, data_port) = self.ports();
-        let reg_addr = self.reg_addr(Self::INTERRUPT_PIN_LINE_OFFSET);
-        addr_port.write(reg_addr);
-        (data_port.read() >> 8) as u8
-    }
-
-    fn interrupt_line(&self) -> u8 {
-        assert_eq!(self.header_type(), PCIHeaderType::Standard);
-        let (mut addr_port, data_port) = self.ports();
-        let reg_addr = self.reg_addr(Self::INTERRUPT_PIN_LINE_OFFSET);
-        addr_port.write(reg_addr);
-        data_port.read() as u8
-    }
-
-    fn set_interrupt_line(&mut self, line: IRQ) {
-        assert_eq!(self.header_type(), PCIHeaderType::Standard);
-        let (mut addr_port, mut data_port) = self.ports();
-        let reg_addr = self.reg_addr(Self::INTERRUPT_PIN_LINE_OFFSET);
-        addr_port.write(reg_addr);
-        let mut val = data_port.read();
-        val.set_bits(8..16, line.as_u8().as_u32());
-        data_port.write(val);
-    }
-
-    fn status(&self) -> u16 {
-        let (mut addr_port, data_port) = self.ports();
-        let reg_addr = self.reg_addr(Self::STATUS_AND_COMMAND_OFFSET);
-        addr_port.write(reg_addr);
-        (data_port.read() >> 16) as u16
-    }
-
-    fn command(&self) -> u16 {
-        let (mut addr_port, data_port) = self.ports();
-        let reg_addr = self.reg_addr(Self::STATUS_AND_COMMAND_OFFSET);
-        addr_port.write(reg_addr);
-        data_port.read() as u16
-    }
-
-    fn set_command(&mut self, val: u16) {
-        let (mut addr_port, mut data_port) = self.ports();
-        let reg_addr = self.reg_addr(Self::STATUS_AND_COMMAND_OFFSET);
-        addr_port.write(reg_addr);
-        data_port.write(val.into());
-    }
-
-    fn enable_memory_space_accesses(&mut self) {
-        let mut val = self.command();
-
-        // Added for experimenting
-        val.set_bit(0);
-        val.set_bit(2);
-        val.set_bit(3);
-        val.set_bit(4);
-        val.set_bit(8);
-        //
-
-        val.set_bit(1);
-        self.set_command(val);
-    }
-
-    /// Returns the device's class code read from the PCI configuration header
-    fn classcode(&self) -> u8 {
-        self.read_classcode_subclass_reg().0
-    }
-
-    fn subclass(&self) -> u8 {
-        self.read_classcode_subclass_reg().1
-    }
-
-    /// Returns the address to be written into the `ADDR_PORT` to access
-    /// the data in the configuration header at offset `reg_offset`
-    fn reg_addr(&self, reg_offset: u32) -> u32 {
-        self.bus << 16 
-            | self.device << 11 | self.func << 8
-            | (reg_offset & 0xfc) | 0x80000000u32
-    }
-
-    fn ports(&self) -> (Port<u32>, Port<u32>) {
-        let addr_port: Port<u32> = Port::new(Self::ADDR_PORT);
-        let data_port: Port<u32> = Port::new(Self::DATA_PORT);
-        (addr_port, data_port)
-    }
-}
-
 /// A HDA sound device on the PCI bus
 struct SoundDevice {
     /// The sound device's PCI interface
@@ -894,23 +1693,59 @@ struct SoundDevice {
     /// The DACs connected to output pins that can be used
     /// to set up a sound stream with the controller
     output_converters: Vec<'static, DAC>,
-    /// The mixers for playing more than 1 stream at a time
+    /// The mixer widgets found in the codecs
     mixers: Vec<'static, Mixer>,
+    /// The pins attached to microphones that can be used to record sound
+    input_pins: Vec<'static, Pin>,
+    /// The pin of the headphone jack, which sound is played through
+    /// instead of the speakers while headphones are plugged in
+    headphone_pin: Option<Pin>,
+    /// Whether or not the headphone pin last sensed headphones in its jack
+    headphones_plugged: bool,
+    /// The handler that checks for unsolicited responses from the headphone pin.
+    /// Stays hooked through controller resets
+    jack_hook: Option<HandlerId>,
+    /// The handler that reads the responses the codecs send to the commander.
+    /// Stays hooked through controller resets
+    response_hook: Option<HandlerId>,
+    /// The ADCs that can be used to set up a recording stream with the controller
+    input_converters: Vec<'static, ADC>,
     /// The addresses of valid codecs in the controller
     codec_addrs: Vec<'static, u8>,
     /// Communicates with the controller with the CORB and RIRB
     commander: Commander,
-    /// A connection with a DAC through which sound samples
-    /// are channeled
+    /// The connection with the DAC through which the mix buffer is played
     output_stream: OutputStream,
+    /// The DAC the output stream is connected to
+    dac: Option<DAC>,
+    /// The connection with the ADC through which microphone input is recorded.
+    /// None if the controller has no input streams
+    input_stream: Option<InputStream>,
+    /// The ADC the input stream is connected to, None if no microphone was found
+    adc: Option<ADC>,
+    /// The handler that stops the input stream when the record buffer is full.
+    /// Set while recording
+    record_hook: Option<HandlerId>,
+    /// The buffer given to `record`, until `take_recording` gives it back
+    recording: Option<Recording>,
     /// A node that can generate beeps with the HDA beep commands
     beep_gen: Option<NodeAddr>,
-    /// The sound id of the sound that is currently playing in the
-    /// output streams
-    ///
-    /// This corresponds to the handler id of the action_on_end event hook
-    /// that will be executed when the current sound stream ends
-    currently_playing_sound_id: Option<HandlerId>,
+    /// Adds the sounds that are playing together
+    mixer: mixer::Mixer,
+    /// The samples the output stream plays over and over, big enough for the
+    /// chunks of the longest latency. Allocated when the controller starts
+    mix_buffer: &'static mut [Sample],
+    /// The index of the chunk of the mix buffer that's filled next,
+    /// once the controller has played it
+    next_chunk_to_fill: usize,
+    /// The number of chunks in a row that have been filled with silence
+    silent_chunks: usize,
+    /// The handler that fills the mix buffer when the controller has played a chunk.
+    /// Set while the output stream is running
+    mix_hook: Option<HandlerId>,
+    /// Set when the controller couldn't be recovered from a stuck stream.
+    /// Nothing is played after that
+    disabled: bool,
     //active_dac_index: Option<usize>
 }
 
@@ -929,113 +1764,497 @@ impl SoundDevice {
             output_pins: vec!(item_type => Pin, capacity => 10),
             output_converters: vec!(item_type => DAC, capacity => 10),
             mixers: vec!(item_type => Mixer, capacity => 10),
+            input_pins: vec!(item_type => Pin, capacity => 5),
+            input_converters: vec!(item_type => ADC, capacity => 5),
+            headphone_pin: None,
+            headphones_plugged: false,
+            jack_hook: None,
+            response_hook: None,
             codec_addrs: vec!(item_type => u8, capacity => 15),
             commander: Commander::new(Self::corb_regs_mut_base(pci_config), Self::rirb_regs_mut_base(pci_config)),
             output_stream: OutputStream::new(Self::stream_descriptor_regs_mut_base(pci_config, 0).unwrap(), 1),
-            currently_playing_sound_id: None,
+            dac: None,
+            input_stream: Self::input_stream_descriptor_regs_mut_base(pci_config, 0)
+                .map(|regs| InputStream::new(regs, 2)),
+            adc: None,
+            record_hook: None,
+            recording: None,
+            mixer: mixer::Mixer::new(),
+            mix_buffer: &mut [],
+            next_chunk_to_fill: 0,
+            silent_chunks: 0,
+            mix_hook: None,
+            disabled: false,
             beep_gen: None
         }
     }
     
-    /// Plays a sound
+    /// Starts playing `sound` alongside the sounds that are already playing
+    fn play_sound(&mut self, sound: Sound, action_on_end: ActionOnEnd, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str> {
+        if self.disabled {
+            return Err("Sound has been disabled");
+        }
+        let handle = self.mixer.play(sound, action_on_end, MAX_GAIN, priority, policy)?;
+        if let Err(msg) = self.start_mixing() {
+            self.mixer.stop(handle).unwrap();
+            return Err(msg);
+        }
+        Ok(handle)
+    }
+
+    /// Fades the sound with the handle `from` out and `to` in over `ms` milliseconds
+    fn crossfade(&mut self, from: SoundHandle, to: Sound, ms: usize) -> Result<SoundHandle, &'static str> {
+        if self.disabled {
+            return Err("Sound has been disabled");
+        }
+        let frames = (ms * MIX_RATE as usize / 1000).as_u32();
+        let handle = self.mixer.play(to, ActionOnEnd::Replay, 0, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy)?;
+        self.mixer.fade(handle, MAX_GAIN, frames, false).unwrap();
+        // `from` may have ended already, in which case there's nothing to fade out
+        let _ = self.mixer.fade(from, 0, frames, true);
+        if let Err(msg) = self.start_mixing() {
+            self.mixer.stop(handle).unwrap();
+            return Err(msg);
+        }
+        Ok(handle)
+    }
+
+    /// Sets the output stream up to play the mix buffer and starts it,
+    /// unless it's already running
     ///
-    /// The returned SoundId is used to identify the sound to stop
-    fn play_sound(&mut self, sound: Sound, action_on_end: ActionOnEnd) {
-        if self.currently_playing_sound_id.is_some() {
-            self.stop_sound().unwrap();
+    /// Every chunk of the mix buffer is filled before the stream starts
+    fn start_mixing(&mut self) -> Result<(), &'static str> {
+        if self.mix_hook.is_some() {
+            return Ok(());
+        }
+        let len = self.output_stream.chunk_len() * MIX_CHUNKS;
+        if self.mix_buffer.len() < len {
+            return Err("The mix buffer hasn't been allocated");
         }
-        let output_stream = &mut self.output_stream;
+        let ended = self.mixer.mix(&mut self.mix_buffer[..len]);
+        // The controller reads the mix buffer while the chunks it isn't
+        // reading are filled
+        let samples = unsafe { core::slice::from_raw_parts(self.mix_buffer.as_ptr(), len) };
         // For some reason, this init function has to be called
         // again before playing a new stream
-        output_stream.init();
-        output_stream.setup_sound_stream(sound);
-        let action_on_end_hook_id = match action_on_end {
-            ActionOnEnd::Stop => event_hook::hook_event(EventKind::Sound, box_fn!(move |_| {
-                stop_sound().unwrap();
-            })),
-            ActionOnEnd::Replay => event_hook::hook_event(EventKind::Sound, box_fn!(move |_| {
-                let sd = get_sound_device().unwrap();
-                sd.output_stream.stop();
-                sd.output_stream.reset();
-                sd.output_stream.init();
-                sd.output_stream.setup_sound_stream(sound);
-                sd.output_stream.start();
-            })),
-            ActionOnEnd::Action(func) => event_hook::hook_event(EventKind::Sound, func)
+        let setup_result = self.output_stream.init()
+            .and_then(|_| self.output_stream.setup_cyclic_buffer(samples, MIX_CHUNKS));
+        if let Err(msg) = setup_result {
+            serial_println!("Can't play sound: {}", msg);
+            self.output_stream.reset();
+            return Err(msg);
+        }
+        self.set_dac_format();
+        self.next_chunk_to_fill = 0;
+        self.silent_chunks = 0;
+        self.mix_hook = Some(event_hook::hook_event(EventKind::Sound, box_fn!(|_| {
+            let sd = match get_sound_device() {
+                Some(sd) => sd,
+                None => return
+            };
+            // Clearing the buffer completion status clears the error bits too
+            sd.handle_stream_errors();
+            let ended = sd.mix_played_chunks();
+            schedule_ended_actions(ended);
+        })));
+        self.output_stream.start();
+        schedule_ended_actions(ended);
+        Ok(())
+    }
+
+    /// Fills the chunks of the mix buffer that the controller has played
+    /// since the last time with the next frames of the playing sounds
+    ///
+    /// The stream is stopped once the whole mix buffer is silent.
+    /// Returns the actions of the sounds that ended
+    fn mix_played_chunks(&mut self) -> EndedActions {
+        const NO_ACTION: Option<BoxedFn<'static>> = None;
+        let mut ended = [NO_ACTION; MAX_VOICES];
+        if !self.output_stream.take_buffer_completion() {
+            // The interrupt came from something else
+            return ended;
+        }
+        let chunk_len = self.output_stream.chunk_len();
+        let playing_chunk = self.output_stream.position() / chunk_len % MIX_CHUNKS;
+        while self.next_chunk_to_fill != playing_chunk {
+            if self.mixer.is_idle() {
+                self.silent_chunks += 1;
+            } else {
+                self.silent_chunks = 0;
+            }
+            let start = self.next_chunk_to_fill * chunk_len;
+            let chunk_ended = self.mixer.mix(&mut self.mix_buffer[start..start + chunk_len]);
+            for (slot, action) in chunk_ended.into_iter().enumerate() {
+                if action.is_some() {
+                    ended[slot] = action;
+                }
+            }
+            self.next_chunk_to_fill = (self.next_chunk_to_fill + 1) % MIX_CHUNKS;
+        }
+        if self.silent_chunks >= MIX_CHUNKS {
+            self.stop_mixing();
+        }
+        ended
+    }
+
+    /// Sends a `SoundError` event for every error the streams have
+    /// reported since the last check
+    ///
+    /// A descriptor error halts the stream's DMA, so mixing or recording is
+    /// stopped, and the next sound or recording starts the stream again
+    fn handle_stream_errors(&mut self) {
+        let (fifo_error, descriptor_error) = self.output_stream.regs.take_errors();
+        if fifo_error {
+            serial_println!("The output stream ran out of samples");
+            event_hook::send_event(Event::SoundError(SoundErrorKind::OutputUnderrun));
+        }
+        if descriptor_error {
+            serial_println!("The output stream couldn't fetch a buffer descriptor");
+            self.stop_mixing();
+            event_hook::send_event(Event::SoundError(SoundErrorKind::DescriptorError));
+        }
+        let (fifo_error, descriptor_error) = match self.input_stream.as_mut() {
+            Some(stream) => stream.regs.take_errors(),
+            None => return
         };
-        self.currently_playing_sound_id = Some(action_on_end_hook_id);
-        output_stream.start();
+        if fifo_error {
+            serial_println!("The input stream lost recorded samples");
+            event_hook::send_event(Event::SoundError(SoundErrorKind::InputOverrun));
+        }
+        if descriptor_error {
+            serial_println!("The input stream couldn't fetch a buffer descriptor");
+            self.stop_recording();
+            event_hook::send_event(Event::SoundError(SoundErrorKind::DescriptorError));
+        }
     }
 
-    fn stop_sound(&mut self) -> Result<(), ()> {
-        if let Some(id) = self.currently_playing_sound_id.take() {
-            self.output_stream.stop();
+    /// Stops the output stream and the handler that fills the mix buffer
+    fn stop_mixing(&mut self) {
+        if let Some(id) = self.mix_hook.take() {
+            event_hook::unhook_event(id, EventKind::Sound);
+            self.stop_stream();
             self.output_stream.reset();
+        }
+    }
+
+    /// Holds the mixer and the output stream where they are
+    fn pause(&mut self) {
+        if self.mixer.is_paused() {
+            return;
+        }
+        self.mixer.set_paused(true);
+        if self.mix_hook.is_none() {
+            return;
+        }
+        if let Err(msg) = self.output_stream.pause() {
+            // The position is lost, but a paused game shouldn't keep playing
+            serial_println!("Failed to pause the output stream: {}", msg);
+            self.stop_mixing();
+        }
+    }
+
+    /// Stops every sound, the streams and the controller's interrupts for good
+    fn quiesce(&mut self) {
+        self.mixer.stop_all();
+        self.stop_mixing();
+        self.stop_recording();
+        self.disable();
+    }
+
+    /// Carries on mixing and playing from where `pause` stopped
+    fn resume(&mut self) {
+        if !self.mixer.is_paused() {
+            return;
+        }
+        self.mixer.set_paused(false);
+        if self.mix_hook.is_some() {
+            self.output_stream.resume();
+        } else if !self.mixer.is_idle() && !self.disabled {
+            // The stream had to be stopped while pausing
+            if let Err(msg) = self.start_mixing() {
+                serial_println!("Failed to resume sound: {}", msg);
+            }
+        }
+    }
+
+    /// Starts recording into `buffer` for `duration_ms` milliseconds, or less if
+    /// `buffer` is too small
+    ///
+    /// `buffer` is kept until `take_recording` gives it back, unless the
+    /// last recording hasn't been taken back yet
+    fn record(&mut self, buffer: &'static mut [Sample], duration_ms: usize) -> Result<(), &'static str> {
+        if self.recording.is_some() {
+            return Err("The last recording hasn't been taken back");
+        }
+        self.recording = Some(Recording::new(buffer));
+        let result = self.start_recording(duration_ms);
+        if result.is_err() {
+            if let Some(recording) = self.recording.as_mut() {
+                recording.stop(0);
+            }
+        }
+        result
+    }
+
+    /// Starts recording into the buffer given to `record`
+    fn start_recording(&mut self, duration_ms: usize) -> Result<(), &'static str> {
+        if self.disabled {
+            return Err("Sound has been disabled");
+        }
+        if self.adc.is_none() {
+            return Err("No microphone was found");
+        }
+        let stream = self.input_stream.as_mut().ok_or("The controller has no input streams")?;
+        let recording = self.recording.as_mut().ok_or("There's no buffer to record into")?;
+        // Both BDL entries have to be multiples of 128 bytes
+        let samples = recording.start(duration_ms, MIX_RATE as usize, SAMPLE_BUFFER_ALIGN / mem::size_of::<Sample>() * 2);
+        let samples = unsafe { core::slice::from_raw_parts(samples.as_ptr(), samples.len()) };
+        let setup_result = stream.init().and_then(|_| stream.setup_buffer(samples));
+        if let Err(msg) = setup_result {
+            stream.reset();
+            return Err(msg);
+        }
+        if let Some(mut adc) = self.adc.take() {
+            adc.set_converter_format(stream.regs.format.reg_value(), &mut self.commander);
+            self.adc = Some(adc);
+        }
+        self.record_hook = Some(event_hook::hook_event(EventKind::Sound, box_fn!(|_| {
+            let sd = match get_sound_device() {
+                Some(sd) => sd,
+                None => return
+            };
+            sd.handle_stream_errors();
+            let completed = match sd.input_stream.as_mut() {
+                Some(stream) => stream.take_buffer_completion(),
+                None => false
+            };
+            if completed {
+                if let Some(recording) = sd.recording.as_mut() {
+                    recording.stop_full();
+                }
+                sd.stop_recording();
+            }
+        })));
+        stream.start();
+        Ok(())
+    }
+
+    /// Stops the input stream and the handler waiting for the record buffer to fill
+    fn stop_recording(&mut self) {
+        if let Some(id) = self.record_hook.take() {
             event_hook::unhook_event(id, EventKind::Sound);
-            Ok(())
-        } else {
-            Err(())
+            if let Some(stream) = self.input_stream.as_mut() {
+                if let Some(recording) = self.recording.as_mut() {
+                    recording.stop(stream.position());
+                }
+                if let Err(msg) = stream.stop() {
+                    serial_println!("{}", msg);
+                }
+            }
         }
     }
 
+    /// The buffer given to `record` and the number of samples recorded in it,
+    /// or None while still recording
+    fn take_recording(&mut self) -> Option<(&'static mut [Sample], usize)> {
+        if self.record_hook.is_some() {
+            return None;
+        }
+        self.recording.take().map(Recording::into_parts)
+    }
+
+    fn playback_position(&self, handle: SoundHandle) -> Option<PlaybackPosition> {
+        let queued = match self.mix_hook {
+            Some(_) => queued_frames(self.output_stream.position(), self.next_chunk_to_fill, self.output_stream.chunk_len()),
+            None => 0
+        };
+        self.mixer.position(handle, queued)
+    }
+
+    /// The peak levels of what the output stream is playing
+    fn levels(&self) -> (u16, u16) {
+        self.output_stream.levels().unwrap_or((0, 0))
+    }
+
+    /// Gives the DAC the output stream's format
+    fn set_dac_format(&mut self) {
+        if let Some(mut dac) = self.dac {
+            dac.set_converter_format(self.output_stream.regs.format.reg_value(), &mut self.commander);
+        }
+    }
+
+    /// Stops the output stream
+    ///
+    /// A stream that won't stop, even after being reset, gets the whole controller
+    /// reset and set up again. If that fails too, sound is disabled, so the stuck
+    /// stream can't keep the game waiting
+    fn stop_stream(&mut self) {
+        let msg = match self.output_stream.stop() {
+            Ok(()) => return,
+            Err(msg) => msg
+        };
+        serial_println!("{}, resetting the controller", msg);
+        self.dump_regs();
+        if let Err(msg) = self.reset_controller() {
+            serial_println!("Failed to reset the controller: {}. Disabling sound", msg);
+            self.disable();
+        }
+    }
+
+    /// Puts the controller through a reset and sets it up again from scratch
+    fn reset_controller(&mut self) -> Result<(), &'static str> {
+        let controller_regs = self.controller_regs();
+        controller_regs.control().modify(|control| control.set_controller_reset(false));
+        // A 0 must be read back to know the controller is in reset
+        if !wait_until(|| !controller_regs.control().read().controller_reset()) {
+            return Err("The controller didn't enter the reset state");
+        }
+        // Everything the controller knew is gone, so it's all found again
+        self.output_stream.bdl.clear_entries();
+        self.output_stream.samples = None;
+        self.output_stream.paused_at = None;
+        if let Some(id) = self.record_hook.take() {
+            event_hook::unhook_event(id, EventKind::Sound);
+        }
+        if let Some(stream) = self.input_stream.as_mut() {
+            stream.bdl.clear_entries();
+            stream.samples = None;
+        }
+        while self.codec_addrs.try_pop().is_some() {}
+        while self.output_pins.try_pop().is_some() {}
+        while self.output_converters.try_pop().is_some() {}
+        while self.mixers.try_pop().is_some() {}
+        while self.input_pins.try_pop().is_some() {}
+        while self.input_converters.try_pop().is_some() {}
+        self.dac = None;
+        self.adc = None;
+        self.headphone_pin = None;
+        self.headphones_plugged = false;
+        self.beep_gen = None;
+        self.start()
+    }
+
+    /// Stops the controller from interrupting and makes every sound
+    /// function do nothing from now on
+    fn disable(&mut self) {
+        self.interrupt_regs().control().modify(|control| control.set_global_interrupt_enable(false));
+        self.disabled = true;
+    }
+
     fn set_beep_gen(&mut self, beep_node: NodeAddr) {
         self.beep_gen = Some(beep_node);
     }
 
     fn start(&mut self) -> Result<(), &'static str> {
-        let controller_regs = self.controller_regs_mut();
+        let controller_regs = self.controller_regs();
         // Asserting the bit removes the controller from reset state
-        controller_regs.control.set_controller_reset(true);
-        while !controller_regs.control.controller_reset() {}
+        controller_regs.control().modify(|control| control.set_controller_reset(true));
+        if !wait_until(|| controller_regs.control().read().controller_reset()) {
+            return Err("The controller didn't leave the reset state");
+        }
         // After reset de-assertion, 521 us should be waited
-        let mut timeout = 0;
-        while timeout < 1_000_000 { timeout += 1; }
+        time::sleep_us(521);
         // Waiting for the codecs to initialize
-        while controller_regs.state_change_status.sdin_state_change_status() == 0 {}
+        if !wait_until(|| controller_regs.state_change_status().read().sdin_state_change_status() != 0) {
+            return Err("No codec came up after the controller reset");
+        }
 
         // After starting the device the addresses of the codecs
         // are the set bit positions in the state change status register
-        let sdin_state_change_stat = self
-            .controller_regs()
-            .state_change_status
+        let sdin_state_change_stat = controller_regs
+            .state_change_status()
+            .read()
             .sdin_state_change_status();
         (0..16u8)
             .for_each(|i| if sdin_state_change_stat.get_bit(i.into()) == BitState::Set {
                 self.codec_addrs.push(i);
             });
         
-        let interrupt_regs = self.interrupt_regs_mut();
+        let interrupt_regs = self.interrupt_regs();
 
         // Enable interrupts from the controller
-        interrupt_regs.control.set_global_interrupt_enable(true);
+        interrupt_regs.control().modify(|control| control.set_global_interrupt_enable(true));
 
         // Enable interrupts from output streams
-        let num_of_input_streams = controller_regs.capabilities.num_of_input_streams();
-        let num_of_output_streams = controller_regs.capabilities.num_of_output_streams();
-        if num_of_output_streams < 2 {
+        let capabilities = controller_regs.capabilities().read();
+        let num_of_input_streams = capabilities.num_of_input_streams();
+        let num_of_output_streams = capabilities.num_of_output_streams();
+        if num_of_output_streams < 1 {
             return Err("No enough output streams for sound operation");
         }
         for stream_idx in 0..num_of_output_streams {
             // The output streams bits in the interrupt control reg
             // start after the input streams
-            interrupt_regs.control.set_stream_interrupt_enable(num_of_input_streams + stream_idx);
+            interrupt_regs.control().modify(|control| control.set_stream_interrupt_enable(num_of_input_streams + stream_idx));
+        }
+        if num_of_input_streams > 0 {
+            // Only the first input stream is used, for recording
+            interrupt_regs.control().modify(|control| control.set_stream_interrupt_enable(0));
         }
 
         // Enable all possible streams to run in stream sync
-        interrupt_regs.stream_sync.unblock_all_streams();
+        interrupt_regs.stream_sync().modify(|stream_sync| stream_sync.unblock_all_streams());
 
-        self.pci_config.set_interrupt_line(IRQ::Sound);
+        self.pci_config.route_interrupt(IRQ::Sound);
+
+        // Controllers without 64 bit addressing ignore the upper
+        // halves of the addresses of the CORB, RIRB and BDL
+        let addr_64bit_supported = capabilities.addr_64bit_supported();
+        self.output_stream.addr_64bit_supported = addr_64bit_supported;
+        if let Some(stream) = self.input_stream.as_mut() {
+            stream.addr_64bit_supported = addr_64bit_supported;
+        }
+
+        // The mix buffer survives controller resets
+        if self.mix_buffer.is_empty() {
+            self.mix_buffer = Self::alloc_mix_buffer(addr_64bit_supported)?;
+        }
 
         // The commander must be initialized first
-        self.commander.init();
+        self.commander.init(addr_64bit_supported)?;
+        // Responses are read as they come in, not only when they're waited for
+        interrupt_regs.control().modify(|control| control.set_controller_interrupt_enable(true));
+        self.commander.enable_response_interrupts();
+        if self.response_hook.is_none() {
+            self.response_hook = Some(event_hook::hook_event(EventKind::Sound, box_fn!(|_| {
+                let commander = match get_sound_device() {
+                    Some(sd) => &mut sd.commander,
+                    None => return
+                };
+                if commander.take_response_interrupt() {
+                    commander.poll();
+                }
+            })));
+        }
         // Widgets must be discovered before preparing to play sound
         self.discover_widgets();
-        // Output stream must be initialized before preparing to play sound
-        self.output_stream.init();
+        // Output streams must be initialized before preparing to play sound
+        self.output_stream.init()?;
         self.prepare_to_play_sound()?;
+        // Plenty of machines have no microphone, which shouldn't stop sound from playing
+        if let Err(msg) = self.prepare_to_record() {
+            serial_println!("Recording isn't available: {}", msg);
+        }
+        if let Err(msg) = self.prepare_jack_detection() {
+            serial_println!("Headphone detection isn't available: {}", msg);
+        }
         Ok(())
     }
 
+    /// Allocates a mix buffer on the heap that fits `MIX_CHUNKS` chunks
+    /// of the longest latency
+    fn alloc_mix_buffer(addr_64bit_supported: bool) -> Result<&'static mut [Sample], &'static str> {
+        let len = OutputStream::chunk_len_for(OutputStream::MAX_LATENCY_MS) * MIX_CHUNKS;
+        // Buffers in the buffer descriptor list must be 128 byte aligned,
+        // but the allocator doesn't align what it hands out
+        let buffer_size = len * mem::size_of::<Sample>() + SAMPLE_BUFFER_ALIGN - 1;
+        let buffer_ptr = unsafe { allocator::get_allocator().alloc(1, buffer_size) }
+            .map_err(|_| "No enough space on the heap for the mix buffer")?;
+        let buffer_ptr = unsafe { buffer_ptr.add(buffer_ptr.align_offset(SAMPLE_BUFFER_ALIGN)).cast::<Sample>() };
+        check_dma_range(buffer_ptr as u64, len * mem::size_of::<Sample>(), addr_64bit_supported)?;
+        Ok(unsafe { core::slice::from_raw_parts_mut(buffer_ptr, len) })
+    }
+
     fn prepare_to_play_sound(&mut self) -> Result<(), &'static str> {
         if self.output_pins.len() < 1 {
             return Err("No enough output pins to play sound");
@@ -1066,7 +2285,132 @@ impl SoundDevice {
         if pin.power_ctrl_supported(&mut self.commander) {
             pin.power_up(&mut self.commander);
         }
+        self.dac = Some(dac);
+        Ok(())
+    }
+
+    /// Connects the first microphone pin to an ADC that has it in its
+    /// connection list and the ADC to the input stream
+    ///
+    /// Microphones reachable only through a mixer or selector aren't supported
+    fn prepare_to_record(&mut self) -> Result<(), &'static str> {
+        let stream = self.input_stream.as_mut().ok_or("The controller has no input streams")?;
+        stream.init()?;
+        if self.input_pins.len() < 1 {
+            return Err("No microphone pin was found");
+        }
+        let pin = &mut self.input_pins[0];
+        let (mut adc, input_idx) = self.input_converters.iter()
+            .find_map(|adc| adc.conn_list_idx(pin.addr).map(|idx| (adc.clone(), idx)))
+            .ok_or("No ADC has the microphone pin in its connection list")?;
+
+        adc.power_up(&mut self.commander);
+        adc.set_active_input(input_idx, &mut self.commander);
+        adc.set_converter_format(stream.regs.format.reg_value(), &mut self.commander);
+        adc.setup_stream_and_channel(&mut self.commander, stream.tag.as_u8(), 0);
+        adc.unmute_input(input_idx, &mut self.commander);
+
+        pin.enable_input(&mut self.commander);
+        if pin.power_ctrl_supported(&mut self.commander) {
+            pin.power_up(&mut self.commander);
+        }
+        self.adc = Some(adc);
+        Ok(())
+    }
+
+    /// Makes the headphone pin report headphones being plugged in and out
+    /// with unsolicited responses, and plays sound through the headphones
+    /// if they're already plugged in
+    fn prepare_jack_detection(&mut self) -> Result<(), &'static str> {
+        let pin = self.headphone_pin.as_mut().ok_or("No headphone pin was found")?;
+        if !pin.pin_cap(&mut self.commander).presence_detect_capable() {
+            return Err("The headphone pin can't sense headphones");
+        }
+        if !pin.widget_cap(&mut self.commander).unsolicited_capable() {
+            return Err("The headphone pin can't send unsolicited responses");
+        }
+        pin.enable_unsolicited(JACK_SENSE_TAG, &mut self.commander);
+        let headphones_plugged = pin.presence_detected(&mut self.commander);
+        self.controller_regs().control().modify(|control| control.set_unsolicited_response_accepted(true));
+        if self.jack_hook.is_none() {
+            self.jack_hook = Some(event_hook::hook_event(EventKind::Sound, box_fn!(|_| {
+                if let Some(sd) = get_sound_device() {
+                    sd.handle_unsolicited_responses();
+                }
+            })));
+        }
+        // The headphones may have been plugged in before booting
+        self.headphones_plugged = headphones_plugged;
+        if headphones_plugged {
+            self.route_output(true)?;
+        }
+        Ok(())
+    }
+
+    /// Reroutes sound and sends a `JackChange` event for every change
+    /// the headphone pin has reported
+    fn handle_unsolicited_responses(&mut self) {
+        while let Some(response) = self.commander.take_unsolicited() {
+            if response.unsolicited_tag() != Some(JACK_SENSE_TAG) {
+                continue;
+            }
+            // The response only says that something changed, so the pin is asked what
+            let plugged = match self.headphone_pin {
+                Some(ref pin) => pin.presence_detected(&mut self.commander),
+                None => continue
+            };
+            if plugged == self.headphones_plugged {
+                continue;
+            }
+            self.headphones_plugged = plugged;
+            if let Err(msg) = self.route_output(plugged) {
+                serial_println!("Failed to reroute sound: {}", msg);
+            }
+            let state = if plugged { JackState::Plugged } else { JackState::Unplugged };
+            event_hook::send_event(Event::JackChange(state));
+        }
+    }
 
+    /// Connects the output stream's DAC to the headphone pin if `headphones`
+    /// is true, or the speaker pin otherwise, and turns the other pin off
+    ///
+    /// The DAC is switched for one in the pin's connection list if the current
+    /// one isn't. Pins reachable only through a mixer aren't supported
+    fn route_output(&mut self, headphones: bool) -> Result<(), &'static str> {
+        let headphone_pin = self.headphone_pin.as_mut().ok_or("No headphone pin was found")?;
+        let speaker_pin = if self.output_pins.len() > 0 { Some(&mut self.output_pins[0]) } else { None };
+        let (pin, other_pin) = if headphones {
+            (headphone_pin, speaker_pin)
+        } else {
+            (speaker_pin.ok_or("No speaker pin was found")?, Some(headphone_pin))
+        };
+        let current_dac = self.dac.and_then(|dac| pin.conn_list_idx(dac.addr).map(|idx| (dac, idx)));
+        let (mut dac, idx) = match current_dac {
+            Some(current_dac) => current_dac,
+            None => self.output_converters.iter()
+                .find_map(|dac| pin.conn_list_idx(dac.addr).map(|idx| (*dac, idx)))
+                .ok_or("No DAC is in the pin's connection list")?
+        };
+        if self.dac.map(|current| current.addr) != Some(dac.addr) {
+            if let Some(mut old_dac) = self.dac {
+                // Stream 0 is never used, so the old DAC stops converting
+                old_dac.setup_stream_and_channel(&mut self.commander, 0, 0);
+            }
+            dac.power_up(&mut self.commander);
+            dac.set_converter_format(self.output_stream.regs.format.reg_value(), &mut self.commander);
+            dac.setup_stream_and_channel(&mut self.commander, self.output_stream.tag.as_u8(), 0);
+            dac.unmute(&mut self.commander);
+        }
+        pin.set_active_input(idx, &mut self.commander);
+        pin.enable(&mut self.commander);
+        pin.unmute(&mut self.commander);
+        if pin.power_ctrl_supported(&mut self.commander) {
+            pin.power_up(&mut self.commander);
+        }
+        if let Some(other_pin) = other_pin {
+            other_pin.disable(&mut self.commander);
+        }
+        self.dac = Some(dac);
         Ok(())
     }
 
@@ -1081,27 +2425,55 @@ impl SoundDevice {
                 if func_group.has_beep_gen(&mut self.commander) {
                     self.set_beep_gen(NodeAddr(codec_addr, func_group.addr.node_id()));
                 }
-                for node in func_group.nodes(&mut self.commander) {
-                    match node.widget_type(&mut self.commander) {
+                let nodes = func_group.nodes(&mut self.commander);
+                // Every widget is asked for its capabilities before any answer is waited for
+                let mut commands = [HDANodeCommand::null(); 256];
+                let mut responses = [HDANodeResponse::null(); 256];
+                let mut num_of_nodes = 0;
+                for (command, node) in commands.iter_mut().zip(nodes.clone()) {
+                    *command = HDANodeCommand::afg_widget_capabilities(node.codec_addr(), node.node_id());
+                    num_of_nodes += 1;
+                }
+                self.commander.command_batch(&commands[..num_of_nodes], &mut responses[..num_of_nodes]);
+                for (node, response) in nodes.zip(responses) {
+                    let widget_cap = response.afg_widget_capabilities_resp().unwrap();
+                    match widget_cap.widget_type() {
                         HDAAFGWidgetType::AudioOutput => {
                             self.output_converters.push(DAC::new(node));
                         }
+                        // Preferred over the function group, which may only
+                        // have a beep generator through this widget
+                        HDAAFGWidgetType::BeepGenerator => {
+                            self.set_beep_gen(NodeAddr(codec_addr, node.addr().node_id()));
+                        }
                         HDAAFGWidgetType::AudioMixer => {
                             let mut mixer = Mixer::new(codec_addr, node.addr().node_id());
                             build_conn_list(mixer.addr, &mut mixer.conn_list, &mut self.commander).unwrap();
                             self.mixers.push(mixer);
                         }
+                        HDAAFGWidgetType::AudioInput => {
+                            let mut adc = ADC::new(codec_addr, node.addr().node_id());
+                            build_conn_list(adc.addr, &mut adc.conn_list, &mut self.commander).unwrap();
+                            self.input_converters.push(adc);
+                        }
                         HDAAFGWidgetType::PinComplex => {
                             let mut pin = Pin::new(codec_addr, node.addr().node_id());
                             let pin_cap = pin.pin_cap(&mut self.commander);
-                            if !pin_cap.output_capable() { continue; }
                             let config_defaults = pin.config_defaults(&mut self.commander);
-                            if !(config_defaults.port_connectivity() != PortConnectivity::None
-                                && config_defaults.default_device() == DefaultDevice::Speaker) {
-                                    continue;
+                            if config_defaults.port_connectivity() == PortConnectivity::None {
+                                continue;
+                            }
+                            if pin_cap.output_capable() && config_defaults.default_device() == DefaultDevice::Speaker {
+                                build_conn_list(pin.addr, &mut pin.conn_list, &mut self.commander).unwrap();
+                                self.output_pins.push(pin);
+                            } else if pin_cap.output_capable() && config_defaults.default_device() == DefaultDevice::HPOut {
+                                if self.headphone_pin.is_none() {
+                                    build_conn_list(pin.addr, &mut pin.conn_list, &mut self.commander).unwrap();
+                                    self.headphone_pin = Some(pin);
                                 }
-                            build_conn_list(pin.addr, &mut pin.conn_list, &mut self.commander).unwrap();
-                            self.output_pins.push(pin);
+                            } else if pin_cap.input_capable() && config_defaults.default_device() == DefaultDevice::MicIn {
+                                self.input_pins.push(pin);
+                            }
                         },
                         _ => ()
                     };
@@ -1110,6 +2482,56 @@ impl SoundDevice {
         }
     }
     
+    fn dump_regs(&self) {
+        serial_println!(
+            "HDA controller at PCI {}:{}.{}, registers at {:#x}",
+            self.pci_config.bus, self.pci_config.device, self.pci_config.func, self.base_ptr() as u64
+        );
+        self.dump_reg_block("Controller", Self::CONTROLLER_REGS_OFFSET, ControllerRegs::SIZE);
+        self.dump_reg_block("Interrupt", Self::INTERRUPT_REGS_OFFSET, InterruptRegs::SIZE);
+        self.dump_reg_block("CORB", Self::CORB_REGS_OFFSET, mem::size_of::<CORBRegs>());
+        self.dump_reg_block("RIRB", Self::RIRB_REGS_OFFSET, mem::size_of::<RIRBRegs>());
+        if let Some(offset) = Self::input_stream_descriptor_offset_base(self.pci_config, 0) {
+            serial_println!("Input stream descriptor 0");
+            self.dump_reg_block("Stream descriptor", offset, mem::size_of::<StreamDescriptorRegs>());
+        }
+        for n in 0..self.controller_regs().capabilities().read().num_of_output_streams() {
+            if let Some(offset) = self.output_stream_descriptor_offset(n) {
+                serial_println!("Output stream descriptor {}", n);
+                self.dump_reg_block("Stream descriptor", offset, mem::size_of::<StreamDescriptorRegs>());
+            }
+        }
+    }
+
+    /// Writes `len` bytes of registers starting at `offset` to the
+    /// serial debug log, 4 double words per line
+    ///
+    /// The registers are read a double word at a time, which the
+    /// HDA spec allows for all the registers. Nothing past the end of the
+    /// block is read, so bytes left over at the end are read one at a time
+    fn dump_reg_block(&self, name: &str, offset: isize, len: usize) {
+        serial_println!("{} registers", name);
+        for line_offset in (0..len).step_by(16) {
+            let line_len = (len - line_offset).min(16);
+            let mut line = [0u32; 4];
+            let mut no_of_dwords = 0;
+            for (dword, dword_start) in line.iter_mut().zip((0..line_len).step_by(4)) {
+                let dword_offset = offset + (line_offset + dword_start).as_isize();
+                let dword_len = (line_len - dword_start).min(4);
+                no_of_dwords += 1;
+                *dword = if dword_len == 4 {
+                    unsafe { self.reg_ptr(dword_offset).cast::<u32>().read_volatile() }
+                } else {
+                    (0..dword_len).fold(0, |dword, byte_idx| {
+                        let byte = unsafe { self.reg_ptr(dword_offset + byte_idx.as_isize()).read_volatile() };
+                        dword | (byte as u32) << (byte_idx * 8)
+                    })
+                };
+            }
+            serial_println!("  {:#06x}:{}", offset + line_offset.as_isize(), Dwords(&line[..no_of_dwords]));
+        }
+    }
+
     /// Returns the pointer to the location of the device's
     /// memory mapped registers
     fn base_ptr(&self) -> *mut u8 {
@@ -1128,28 +2550,16 @@ impl SoundDevice {
         unsafe { base_ptr.offset(offset) }
     }
 
-    fn controller_regs(&self) -> &'static ControllerRegs {
+    fn controller_regs(&self) -> ControllerRegs {
         Self::controller_regs_base(self.pci_config)
     }
 
-    fn controller_regs_base(pci_config: PCIDevice) -> &'static ControllerRegs {
-        let ptr = Self::reg_ptr_base(pci_config, Self::CONTROLLER_REGS_OFFSET).cast::<ControllerRegs>();
-        unsafe { &*ptr }
-    }
-
-    fn controller_regs_mut(&self) -> &'static mut ControllerRegs {
-        let ptr = self.reg_ptr(Self::CONTROLLER_REGS_OFFSET).cast::<ControllerRegs>();
-        unsafe { &mut *ptr }
+    fn controller_regs_base(pci_config: PCIDevice) -> ControllerRegs {
+        unsafe { ControllerRegs::new(Self::reg_ptr_base(pci_config, Self::CONTROLLER_REGS_OFFSET)) }
     }
 
-    fn interrupt_regs(&self) -> &'static InterruptRegs {
-        let ptr = self.reg_ptr(Self::INTERRUPT_REGS_OFFSET).cast::<InterruptRegs>();
-        unsafe { &*ptr }
-    }
-
-    fn interrupt_regs_mut(&self) -> &'static mut InterruptRegs {
-        let ptr = self.reg_ptr(Self::INTERRUPT_REGS_OFFSET).cast::<InterruptRegs>();
-        unsafe { &mut *ptr }
+    fn interrupt_regs(&self) -> InterruptRegs {
+        unsafe { InterruptRegs::new(self.reg_ptr(Self::INTERRUPT_REGS_OFFSET)) }
     }
 
     fn corb_regs(&self) -> &'static CORBRegs {
@@ -1188,18 +2598,37 @@ impl SoundDevice {
     }
 
     fn output_stream_descriptor_offset_base(pci_config: PCIDevice, n: u8) -> Option<isize> {
-        let controller_regs = Self::controller_regs_base(pci_config);
+        let capabilities = Self::controller_regs_base(pci_config).capabilities().read();
         if n > 15 {
             None
-        } else if n > controller_regs.capabilities.num_of_output_streams() {
+        } else if n > capabilities.num_of_output_streams() {
             None
         } else {
             // Calculations as described in the HDA spec
-            let x = 0x80 + (controller_regs.capabilities.num_of_input_streams().as_isize() * 0x20);
+            let x = 0x80 + (capabilities.num_of_input_streams().as_isize() * 0x20);
             Some(x + n.as_isize() * 0x20)
         }
     }
     
+    /// The offset of the input stream descriptor register n
+    ///
+    /// Returns None when the input stream descriptor n does not exist
+    fn input_stream_descriptor_offset_base(pci_config: PCIDevice, n: u8) -> Option<isize> {
+        let capabilities = Self::controller_regs_base(pci_config).capabilities().read();
+        if n >= capabilities.num_of_input_streams() {
+            None
+        } else {
+            // The input stream descriptors come first
+            Some(0x80 + n.as_isize() * 0x20)
+        }
+    }
+
+    fn input_stream_descriptor_regs_mut_base(pci_config: PCIDevice, n: u8) -> Option<&'static mut StreamDescriptorRegs> {
+        let offset = Self::input_stream_descriptor_offset_base(pci_config, n)?;
+        let ptr = Self::reg_ptr_base(pci_config, offset).cast::<StreamDescriptorRegs>();
+        Some(unsafe { &mut *ptr })
+    }
+
     /// Returns the pointer to stream descriptor registers at offset n
     fn stream_descriptor_regs_ptr(&self, n: u8) -> Option<*mut StreamDescriptorRegs> {
         Self::stream_descriptor_regs_ptr_base(self.pci_config, n)
@@ -1233,6 +2662,36 @@ impl SoundDevice {
     }
 }
 
+/// Double words written in hex with a space before each, for register dumps
+struct Dwords<'a>(&'a [u32]);
+
+impl core::fmt::Display for Dwords<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for dword in self.0 {
+            write!(f, " {:#010x}", dword)?;
+        }
+        Ok(())
+    }
+}
+
+/// The highest address a controller without 64 bit addressing can reach
+const MAX_32BIT_DMA_ADDR: u64 = 0xffff_ffff;
+
+/// Checks that the controller can reach all `len` bytes starting at `start`
+/// with DMA
+///
+/// A controller without 64 bit addressing ignores the upper 32 bits of the
+/// addresses it's given, so a buffer above 4GiB would be fetched from the
+/// wrong place instead of causing an error
+fn check_dma_range(start: u64, len: usize, addr_64bit_supported: bool) -> Result<(), &'static str> {
+    let last_addr = start + len.saturating_sub(1).as_u64();
+    if addr_64bit_supported || last_addr <= MAX_32BIT_DMA_ADDR {
+        Ok(())
+    } else {
+        Err("The controller only supports 32 bit addresses, but a DMA buffer is above 4GiB")
+    }
+}
+
 fn build_conn_list(node: NodeAddr, conn_list: &mut Vec<(u8, NodeAddr)>, commander: &mut Commander) -> Result<(), ()> {
     let get_conn_list_command = HDANodeCommand::get_conn_list_len(node.codec_addr(), node.node_id());
     let conn_list_len_resp = commander.command(get_conn_list_command)
@@ -1246,13 +2705,17 @@ fn build_conn_list(node: NodeAddr, conn_list: &mut Vec<(u8, NodeAddr)>, commande
         conn_list_index_iter = (0..conn_list_len_resp.conn_list_len()).step_by(2);
         no_in_batch = 2;
     }
-    for conn_idx in conn_list_index_iter {
-        let get_conn_list_entry_command = HDANodeCommand::get_conn_list_entry(
-            node.codec_addr(),
-            node.node_id(),
-            conn_idx
-        );
-        let get_conn_list_entry_resp = commander.command(get_conn_list_entry_command)
+    // The entries are all asked for before any answer is waited for
+    let mut commands = [HDANodeCommand::null(); 128];
+    let mut responses = [HDANodeResponse::null(); 128];
+    let mut num_of_commands = 0;
+    for (command, conn_idx) in commands.iter_mut().zip(conn_list_index_iter.clone()) {
+        *command = HDANodeCommand::get_conn_list_entry(node.codec_addr(), node.node_id(), conn_idx);
+        num_of_commands += 1;
+    }
+    commander.command_batch(&commands[..num_of_commands], &mut responses[..num_of_commands]);
+    for (conn_idx, response) in conn_list_index_iter.zip(responses) {
+        let get_conn_list_entry_resp = response
             .get_conn_list_entry_resp(conn_list_len_resp.long_form())
             .unwrap();
         
@@ -1306,30 +2769,31 @@ impl From<PCIDevice> for SoundDevice {
     }
 }
 
-#[repr(packed)]
-struct ControllerRegs {
-    capabilities: HDAGlobalCapabilitiesReg,
-    minor_version: u8,
-    major_version: u8,
-    output_payload_capability: u16,
-    input_payload_capability: u16,
-    control: HDAGlobalControlReg,
-    wake_enable: HDAWakeEnableReg,
-    state_change_status: HDAStateChangeStatusReg,
-    status: u16,
-    reserved1: [u8; 6],
-    output_stream_payload_capability: u16,
-    input_stream_payload_capability: u16
+register_block! {
+    /// The global capabilities, control and status registers
+    struct ControllerRegs {
+        0x00 => capabilities: HDAGlobalCapabilitiesReg,
+        0x02 => minor_version: u8,
+        0x03 => major_version: u8,
+        0x04 => output_payload_capability: u16,
+        0x06 => input_payload_capability: u16,
+        0x08 => control: HDAGlobalControlReg,
+        0x0c => wake_enable: HDAWakeEnableReg,
+        0x0e => state_change_status: HDAStateChangeStatusReg,
+        0x10 => status: u16,
+        0x18 => output_stream_payload_capability: u16,
+        0x1a => input_stream_payload_capability: u16
+    }
 }
 
-#[repr(packed)]
-struct InterruptRegs {
-    control: HDAInterruptControlReg,
-    status: HDAInterruptStatusReg,
-    reserved1: [u8; 8],
-    wall_clock_counter: u32,
-    reserved2: [u8; 4],
-    stream_sync: StreamSyncReg
+register_block! {
+    /// The interrupt control, status and stream synchronization registers
+    struct InterruptRegs {
+        0x00 => control: HDAInterruptControlReg,
+        0x04 => status: HDAInterruptStatusReg,
+        0x10 => wall_clock_counter: u32,
+        0x18 => stream_sync: StreamSyncReg
+    }
 }
 
 #[repr(packed)]
@@ -1344,11 +2808,15 @@ struct CORBRegs {
 }
 
 impl CORBRegs {
-    fn set_corb_addr(&mut self, addr: u64) {
+    fn set_corb_addr(&mut self, addr: u64, addr_64bit_supported: bool) -> Result<(), &'static str> {
+        check_dma_range(addr, mem::size_of::<[HDANodeCommand; 256]>(), addr_64bit_supported)?;
         let lower = (addr & 0xffffffff) as u32;
         let upper = (addr >> 32) as u32;
         self.corb_lower_base_addr = lower;
-        self.corb_upper_base_addr = upper;
+        if addr_64bit_supported {
+            self.corb_upper_base_addr = upper;
+        }
+        Ok(())
     }
 }
 
@@ -1364,11 +2832,15 @@ struct RIRBRegs {
 }
 
 impl RIRBRegs {
-    fn set_rirb_addr(&mut self, addr: u64) {
+    fn set_rirb_addr(&mut self, addr: u64, addr_64bit_supported: bool) -> Result<(), &'static str> {
+        check_dma_range(addr, mem::size_of::<[HDANodeResponse; 256]>(), addr_64bit_supported)?;
         let lower = addr as u32;
         let upper = (addr >> 32) as u32;
         self.rirb_lower_base_addr = lower;
-        self.rirb_upper_base_addr = upper;
+        if addr_64bit_supported {
+            self.rirb_upper_base_addr = upper;
+        }
+        Ok(())
     }
 }
 
@@ -1388,12 +2860,28 @@ struct StreamDescriptorRegs {
 }
 
 impl StreamDescriptorRegs {
-    fn set_bdl_base_addr(&mut self, bdl: &BufferDescriptorList) {
+    /// Clears the FIFO error and descriptor error bits of the status
+    /// register and returns whether each of them was set
+    fn take_errors(&mut self) -> (bool, bool) {
+        let errors = (self.status.fifo_error(), self.status.descriptor_error());
+        if errors.0 || errors.1 {
+            // Only the error bits are written, so the buffer completion
+            // status is left for `take_buffer_completion`
+            self.status = HDAStreamDescriptorStatusReg::ERRORS;
+        }
+        errors
+    }
+
+    fn set_bdl_base_addr(&mut self, bdl: &BufferDescriptorList, addr_64bit_supported: bool) -> Result<(), &'static str> {
         let addr = &bdl.entries as *const _ as u64;
+        check_dma_range(addr, mem::size_of_val(&bdl.entries), addr_64bit_supported)?;
         let lower = addr.get_bits(0..32) as u32;
         let upper = addr.get_bits(32..64) as u32;
         self.bdl_ptr_lower_base_addr = lower;
-        self.bdl_ptr_upper_base_addr = upper;
+        if addr_64bit_supported {
+            self.bdl_ptr_upper_base_addr = upper;
+        }
+        Ok(())
     }
 }
 
@@ -2260,6 +3748,10 @@ impl HDAStreamDescriptorControlReg {
 struct HDAStreamDescriptorStatusReg(u8);
 
 impl HDAStreamDescriptorStatusReg {
+    /// The descriptor error and FIFO error bits, which are
+    /// cleared by writing 1s to them
+    const ERRORS: Self = Self(0b11000);
+
     /// Returns true when the output DMA FIFO contains
     /// enough data to maintain the stream on the link
     fn fifo_ready(&self) -> bool {
@@ -2289,6 +3781,11 @@ impl HDAStreamDescriptorStatusReg {
     fn buffer_completion_interrupt_status(&self) -> bool {
         self.0.get_bit(2) == BitState::Set
     }
+
+    fn clear_buffer_completion_interrupt_status(&mut self) {
+        // The bit is cleared by writing a 1 to the position
+        self.0.set_bit(2);
+    }
 }
 
 impl From<u8> for HDAStreamDescriptorStatusReg {
@@ -2508,7 +4005,7 @@ impl TryInto<SampleBaseRateDivisor> for u8 {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 #[repr(u8)]
 enum BitsPerSample {
     Eight = 0b000,
@@ -2518,6 +4015,19 @@ enum BitsPerSample {
     ThirtyTwo = 0b100
 }
 
+impl BitsPerSample {
+    /// The number of bytes each sample takes up in memory
+    ///
+    /// Samples of more than 16 bits are kept in 32 bit containers
+    const fn container_bytes(&self) -> usize {
+        match self {
+            BitsPerSample::Eight => 1,
+            BitsPerSample::Sixteen => 2,
+            BitsPerSample::Twenty | BitsPerSample::TwentyFour | BitsPerSample::ThirtyTwo => 4
+        }
+    }
+}
+
 impl TryInto<BitsPerSample> for u8 {
     type Error = ();
     fn try_into(self) -> Result<BitsPerSample, ()> {
@@ -2532,6 +4042,7 @@ impl TryInto<BitsPerSample> for u8 {
     }
 }
 
+#[derive(Clone, Copy)]
 #[repr(u8)]
 enum NumOfChannels {
     One = 0b0000,
@@ -2552,6 +4063,13 @@ enum NumOfChannels {
     Sixteen = 0b1111
 }
 
+impl NumOfChannels {
+    /// The number of channels, which is one more than the register value
+    const fn count(&self) -> usize {
+        *self as usize + 1
+    }
+}
+
 impl TryInto<NumOfChannels> for u8 {
     type Error = ();
     fn try_into(self) -> Result<NumOfChannels, ()> {
@@ -2577,94 +4095,6 @@ impl TryInto<NumOfChannels> for u8 {
     }
 }
 
-#[repr(transparent)]
-struct PCIHeaderTypeReg(u8);
-
-impl PCIHeaderTypeReg {
-    fn has_multiple_funcs(&self) -> bool {
-        self.0 >> 7 == 1
-    }
-    fn header_type(&self) -> Result<PCIHeaderType, &'static str> {
-        match self.0 & 0b01111111 {
-            0x0 => Ok(PCIHeaderType::Standard),
-            0x1 => Ok(PCIHeaderType::PCIToPCIBridge),
-            0x2 => Ok(PCIHeaderType::CardBusBridge),
-            _ => Err("This header type register value has an unexpected header type number")
-        }
-    }
-}
-
-#[derive(Debug, PartialEq)]
-#[repr(u8)]
-enum PCIHeaderType {
-    Standard = 0x0,
-    PCIToPCIBridge = 0x1,
-    CardBusBridge = 0x2
-}
-
-/// The memory/port address used by a PCI device for mapping
-enum PCIBaseAddrReg {
-    Memory(MemBAR),
-    IO(IOBAR)
-}
-
-#[derive(Debug, PartialEq)]
-enum PCIBaseAddrKind {
-    Memory,
-    IO
-}
-
-impl PCIBaseAddrReg {
-    fn addr(&self) -> u64 {
-        match self {
-            Self::Memory(mbar) => mbar.addr(),
-            Self::IO(iobar) => iobar.addr()
-        }
-    }
-
-    fn kind(&self) -> PCIBaseAddrKind {
-        match self {
-            Self::Memory(_) => PCIBaseAddrKind::Memory,
-            Self::IO(_) => PCIBaseAddrKind::IO
-        }
-    }
-}
-
-impl TryFrom<(u32, u32)> for PCIBaseAddrReg {
-    type Error = &'static str;
-    fn try_from(val: (u32, u32)) -> Result<PCIBaseAddrReg, Self::Error> {
-        match val.0 & 0x1 {
-            0 => Ok(Self::Memory(MemBAR(val.0, val.1))),
-            1 => Ok(Self::IO(IOBAR(val.0))),
-            _ => Err("Expected either a 0 or 1 in bit 0")
-        }
-    }
-}
-
-struct MemBAR(u32, u32);
-
-impl MemBAR {
-    /// Returns the 16 byte aligned base address
-    fn addr(&self) -> u64 {
-        match self.0.get_bits(1..3) {
-            // 32 bit address
-            0x0 => (self.0 & 0xfffffff0).as_u64(),
-            // 64 bit address
-            0x2 => (self.0 & 0xfffffff0).as_u64() + ((self.1 & 0xffffffff).as_u64() << 32),
-            _ => panic!("Unexpected memory space BAR type")
-        }
-    }
-}
-
-struct IOBAR(u32);
-
-impl IOBAR {
-    /// Returns the 4 byte aligned base address
-    fn addr(&self) -> u64 {
-        (self.0 & 0xfffffffc).as_u64()
-    }
-}
-
 #[derive(Clone, Copy, Debug)]
 #[repr(transparent)]
 struct InterruptOnCompletion(u32);
@@ -2810,6 +4240,8 @@ impl HDANodeCommandVerb {
     const GET_EAPD_ENABLE: u32 = 0xf0c;
     const GET_CONN_SEL_CTRL: u32 = 0xf01;
     const SET_CONN_SEL_CTRL: u32 = 0x701;
+    const GET_PIN_SENSE: u32 = 0xf09;
+    const SET_UNSOLICITED_ENABLE: u32 = 0x708;
     
     fn get_parameter(param_id: u8) -> Self {
         let mut val = 0u32;
@@ -2939,6 +4371,23 @@ impl HDANodeCommandVerb {
         val.set_bits(8..20, Self::SET_CONN_SEL_CTRL);
         Self(val)
     }
+
+    fn get_pin_sense() -> Self {
+        let mut val = 0u32;
+        val.set_bits(8..20, Self::GET_PIN_SENSE);
+        Self(val)
+    }
+
+    /// The tag is sent back in the unsolicited responses so they can be told apart
+    fn set_unsolicited_enable(tag: u8, enable: bool) -> Self {
+        let mut val = 0u32;
+        val.set_bits(0..6, tag.into());
+        if enable {
+            val.set_bit(7);
+        }
+        val.set_bits(8..20, Self::SET_UNSOLICITED_ENABLE);
+        Self(val)
+    }
 }
 
 impl Into<u32> for HDANodeCommandVerb {
@@ -2966,6 +4415,11 @@ impl HDANodeCommand {
         Self(0)
     }
 
+    /// The address of the codec the command is sent to
+    fn codec_addr(&self) -> u8 {
+        self.0.get_bits(28..32).as_u8()
+    }
+
     /// Returns a command to retrieve info about a specific
     /// codec root node, function group or widget with a node
     /// id of `node_id` in a codec at codec address `codec_addr`
@@ -3101,6 +4555,16 @@ impl HDANodeCommand {
         Self::command(node_addr.codec_addr(), node_addr.node_id(), verb)
     }
 
+    fn get_pin_sense(node_addr: NodeAddr) -> Self {
+        let verb = HDANodeCommandVerb::get_pin_sense();
+        Self::command(node_addr.codec_addr(), node_addr.node_id(), verb)
+    }
+
+    fn set_unsolicited_enable(node_addr: NodeAddr, tag: u8, enable: bool) -> Self {
+        let verb = HDANodeCommandVerb::set_unsolicited_enable(tag, enable);
+        Self::command(node_addr.codec_addr(), node_addr.node_id(), verb)
+    }
+
     fn command(codec_addr: u8, node_id: u8, verb: HDANodeCommandVerb) -> Self {
         let mut val = 0u32;
         val.set_bits(0..20, verb.into());
@@ -3227,6 +4691,16 @@ impl AmpGain {
         Self(val)
     }
 
+    fn input_amp(self, amp: bool) -> Self {
+        let mut val = self.0;
+        if amp {
+            val.set_bit(14);
+        } else {
+            val.unset_bit(14);
+        }
+        Self(val)
+    }
+
     fn left_amp(self, amp: bool) -> Self {
         let mut val = self.0;
         if amp {
@@ -3367,6 +4841,20 @@ impl HDANodeResponse {
     fn get_conn_sel_ctrl_resp(&self) -> Result<GetConnSelCtrlResp, ()> {
         Ok(GetConnSelCtrlResp(self.response))
     }
+
+    fn pin_sense_resp(&self) -> Result<PinSenseResp, ()> {
+        Ok(PinSenseResp(self.response))
+    }
+
+    /// The tag the node was given when its unsolicited responses were enabled,
+    /// if this is an unsolicited response
+    fn unsolicited_tag(&self) -> Option<u8> {
+        if self.response_info.solicited() {
+            None
+        } else {
+            Some(self.response.get_bits(26..32).as_u8())
+        }
+    }
 }
 
 impl From<u32> for HDANodeResponse {
@@ -3478,6 +4966,11 @@ impl HDANodeResponseAFGWidgetCap {
     fn power_ctrl_supported(&self) -> bool {
         self.0.get_bit(10) == BitState::Set
     }
+
+    /// Returns true when the widget can send unsolicited responses
+    fn unsolicited_capable(&self) -> bool {
+        self.0.get_bit(7) == BitState::Set
+    }
 }
 
 struct HDANodeResponseGetConnListEntry {
@@ -3614,6 +5107,11 @@ impl HDANodeResponsePinCapabilities {
     fn output_capable(&self) -> bool {
         self.0.get_bit(4) == BitState::Set
     }
+
+    /// Tells whether or not the pin can tell when something is plugged into its jack
+    fn presence_detect_capable(&self) -> bool {
+        self.0.get_bit(2) == BitState::Set
+    }
 }
 
 #[repr(transparent)]
@@ -3635,6 +5133,16 @@ impl AFGCapResp {
     }
 }
 
+#[repr(transparent)]
+struct PinSenseResp(u32);
+
+impl PinSenseResp {
+    /// Tells whether or not something is plugged into the pin's jack
+    fn presence_detected(&self) -> bool {
+        self.0.get_bit(31) == BitState::Set
+    }
+}
+
 #[derive(Debug)]
 #[repr(transparent)]
 struct GetConnSelCtrlResp(u32);
@@ -3870,11 +5378,19 @@ impl CORB {
         }
     }
 
+    /// Puts `command` after the commands the controller hasn't fetched yet
+    ///
+    /// Only waits if the ring buffer is full
     fn add_command(&mut self, command: HDANodeCommand) {
         assert!(self.regs.control.corb_dma_engine_enabled());
-        while self.regs.corbwp.write_pointer() != self.regs.corbrp.read_pointer() {}
-        self.write_pointer = (self.write_pointer + 1) % self.size.entries_as_u16().as_usize();
+        let next_write_pointer = (self.write_pointer + 1) % self.size.entries_as_u16().as_usize();
+        // The controller hasn't fetched the command in the slot yet
+        while next_write_pointer == self.regs.corbrp.read_pointer().as_usize() {}
+        self.write_pointer = next_write_pointer;
         self.commands[self.write_pointer] = command;
+        // The command has to be in memory before the controller is told about it,
+        // or the controller could read the stale command in the slot
+        barrier::mfence();
         self.regs.corbwp.set_write_pointer(self.write_pointer.as_u8());
     }
     
@@ -3884,13 +5400,13 @@ impl CORB {
 
     /// Sets up the CORB to a ready state for communication
     /// with the HDA controller
-    fn init(&mut self) {
+    fn init(&mut self, addr_64bit_supported: bool) -> Result<(), &'static str> {
         if self.regs.control.corb_dma_engine_enabled() {
             self.regs.control.enable_corb_dma_engine(false);
         }
         
         self.regs.size.set_corb_size(self.size());
-        self.regs.set_corb_addr(&self.commands as *const _ as u64);
+        self.regs.set_corb_addr(&self.commands as *const _ as u64, addr_64bit_supported)?;
 
         self.regs.corbwp.set_write_pointer(0);
 
@@ -3902,6 +5418,7 @@ impl CORB {
         self.regs.corbrp.set_read_pointer_reset(false);
         while self.regs.corbrp.read_pointer_reset() {}
         self.regs.control.enable_corb_dma_engine(true);
+        Ok(())
     }
 }
 
@@ -3923,10 +5440,18 @@ struct RIRB {
     /// The number of possible entries
     size: HDARingBufferSize,
     /// The memory mapped registers controlling the RIRB
-    regs: &'static mut RIRBRegs
+    regs: &'static mut RIRBRegs,
+    /// Unsolicited responses that have been read from the ring buffer,
+    /// but not handled yet
+    unsolicited: [Option<HDANodeResponse>; Self::MAX_UNSOLICITED],
+    /// The number of responses in `unsolicited`
+    unsolicited_len: usize
 }
 
 impl RIRB {
+    /// The number of unsolicited responses that are kept until they're handled
+    const MAX_UNSOLICITED: usize = 8;
+
     fn new(regs: &'static mut RIRBRegs) -> Self {
         let mut rirb_size = HDARingBufferSize::TwoFiftySix;
         let rirb_size_capability = regs.size.size_capability();
@@ -3941,14 +5466,41 @@ impl RIRB {
             responses: [HDANodeResponse::null(); 256],
             read_pointer: 0,
             size: rirb_size,
-            regs
+            regs,
+            unsolicited: [None; Self::MAX_UNSOLICITED],
+            unsolicited_len: 0
+        }
+    }
+
+    /// Takes the oldest unsolicited response that has been read
+    fn take_unsolicited(&mut self) -> Option<HDANodeResponse> {
+        if self.unsolicited_len == 0 {
+            return None;
+        }
+        let response = self.unsolicited[0].take();
+        self.unsolicited.rotate_left(1);
+        self.unsolicited_len -= 1;
+        response
+    }
+
+    fn keep_unsolicited(&mut self, response: HDANodeResponse) {
+        // The pin is asked for its current state when its response
+        // is handled, so a dropped response only loses a repeat
+        if self.unsolicited_len < Self::MAX_UNSOLICITED {
+            self.unsolicited[self.unsolicited_len] = Some(response);
+            self.unsolicited_len += 1;
         }
     }
 
-    fn read_next_response(&mut self) -> HDANodeResponse {
+    /// Reads the next entry in the ring buffer, or returns None
+    /// if the controller hasn't written one yet
+    fn try_read_next_entry(&mut self) -> Option<HDANodeResponse> {
         assert!(self.regs.control.rirb_dma_engine_enabled());
-        // Wait for the responses to be written
-        while self.regs.rirbwp.write_pointer() == self.read_pointer.as_u8() {}
+        if self.regs.rirbwp.write_pointer() == self.read_pointer.as_u8() {
+            return None;
+        }
+        // The response must not be read before the write pointer that says it's there
+        barrier::lfence();
         // The buffer is circular, so when the last entry is reached
         // the read pointer should wrap around
         self.read_pointer = (self.read_pointer + 1) % self.size.entries_as_u16().as_usize();
@@ -3956,27 +5508,29 @@ impl RIRB {
             self.regs.rirbwp.reset_write_pointer();
         }
 
-        self.responses[self.read_pointer]
+        Some(self.responses[self.read_pointer])
     }
 
     fn size(&self) -> HDARingBufferSize {
         self.size
     }
 
-    fn init(&mut self) {
+    fn init(&mut self, addr_64bit_supported: bool) -> Result<(), &'static str> {
         if self.regs.control.rirb_dma_engine_enabled() {
             self.regs.control.enable_rirb_dma_engine(false);
         }
+        self.unsolicited = [None; Self::MAX_UNSOLICITED];
+        self.unsolicited_len = 0;
 
         self.regs.size.set_rirb_size(self.size());
-        self.regs.set_rirb_addr(&self.responses as *const _ as u64);
+        self.regs.set_rirb_addr(&self.responses as *const _ as u64, addr_64bit_supported)?;
 
         self.regs.rirbwp.reset_write_pointer();
 
         self.regs.response_interrupt_count.set_response_interrupt_count(255);
 
         self.regs.control.enable_rirb_dma_engine(true);
-
+        Ok(())
     }
 }
 
@@ -3987,26 +5541,197 @@ impl Index<usize> for RIRB {
     }
 }
 
+/// Identifies a command sent with `Commander::command_async`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CommandTicket {
+    /// The command's index in the commander's pending commands
+    slot: usize,
+    seq: u32
+}
+
+/// A command that was sent, along with its response once it has come in
+#[derive(Clone, Copy)]
+struct PendingCommand {
+    /// Tells the commands sent in the same slot apart, and the order they were sent in
+    seq: u32,
+    codec: u8,
+    response: Option<HDANodeResponse>
+}
+
+/// Sends commands to the codecs through the CORB and matches
+/// the responses in the RIRB to them
+///
+/// Many commands can be in the CORB at once. A codec answers the commands
+/// sent to it in order, so a solicited response belongs to the oldest command
+/// sent to its codec that hasn't been answered yet. Responses are read from the
+/// RIRB when a response interrupt comes in and whenever a response is waited for
 struct Commander {
     corb: CORB,
-    rirb: RIRB
+    rirb: RIRB,
+    pending: [Option<PendingCommand>; Self::MAX_PENDING],
+    /// The seq the next command is sent with
+    next_seq: u32,
+    /// Set while the ring buffers are being used, so an interrupt
+    /// handler doesn't use them at the same time
+    busy: bool
 }
 
 impl Commander {
+    /// The number of commands that can wait for their responses at once
+    const MAX_PENDING: usize = 32;
+
     fn new(corb_regs: &'static mut CORBRegs, rirb_regs: &'static mut RIRBRegs) -> Self {
         Self {
             corb: CORB::new(corb_regs),
-            rirb: RIRB::new(rirb_regs)
+            rirb: RIRB::new(rirb_regs),
+            pending: [None; Self::MAX_PENDING],
+            next_seq: 0,
+            busy: false
         }
     }
-    fn init(&mut self) {
-        self.corb.init();
-        self.rirb.init();
+    fn init(&mut self, addr_64bit_supported: bool) -> Result<(), &'static str> {
+        // Responses to commands sent before a reset are never coming
+        self.pending = [None; Self::MAX_PENDING];
+        self.corb.init(addr_64bit_supported)?;
+        self.rirb.init(addr_64bit_supported)
     }
 
+    /// Sends `command` and waits for its response
     fn command(&mut self, command: HDANodeCommand) -> HDANodeResponse {
+        let ticket = self.command_async(command);
+        self.wait(ticket)
+    }
+
+    /// Sends every command in `commands` before waiting for any response, and
+    /// puts the response to each command at the same index in `responses`
+    ///
+    /// The codecs answer the queued commands one after the other, without a
+    /// round trip to the processor between them
+    fn command_batch(&mut self, commands: &[HDANodeCommand], responses: &mut [HDANodeResponse]) {
+        assert!(responses.len() >= commands.len());
+        for (commands, responses) in commands.chunks(Self::MAX_PENDING).zip(responses.chunks_mut(Self::MAX_PENDING)) {
+            let mut tickets = [None; Self::MAX_PENDING];
+            for (ticket, command) in tickets.iter_mut().zip(commands) {
+                *ticket = Some(self.command_async(*command));
+            }
+            for (response, ticket) in responses.iter_mut().zip(tickets.iter().flatten()) {
+                *response = self.wait(*ticket);
+            }
+        }
+    }
+
+    /// Sends `command` without waiting for its response, which is
+    /// taken with `try_response` or `wait`
+    ///
+    /// Only waits if `MAX_PENDING` commands are already waiting for their responses
+    ///
+    /// # Panics
+    ///
+    /// If there's no room for the command, because `MAX_PENDING` responses
+    /// have come in and haven't been taken
+    fn command_async(&mut self, command: HDANodeCommand) -> CommandTicket {
+        self.busy = true;
+        let slot = loop {
+            if let Some(slot) = self.pending.iter().position(|pending| pending.is_none()) {
+                break slot;
+            }
+            assert!(
+                self.pending.iter().flatten().any(|pending| pending.response.is_none()),
+                "Too many command responses haven't been taken"
+            );
+            self.read_responses();
+        };
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.pending[slot] = Some(PendingCommand { seq, codec: command.codec_addr(), response: None });
         self.corb.add_command(command);
-        self.rirb.read_next_response()
+        self.busy = false;
+        CommandTicket { slot, seq }
+    }
+
+    /// Takes the response to the command with `ticket`, or returns None
+    /// if it hasn't come in yet
+    fn try_response(&mut self, ticket: CommandTicket) -> Option<HDANodeResponse> {
+        if !self.busy {
+            self.busy = true;
+            self.read_responses();
+            self.busy = false;
+        }
+        let pending = self.pending[ticket.slot].filter(|pending| pending.seq == ticket.seq)?;
+        let response = pending.response?;
+        self.pending[ticket.slot] = None;
+        Some(response)
+    }
+
+    /// Waits for the response to the command with `ticket` and takes it
+    fn wait(&mut self, ticket: CommandTicket) -> HDANodeResponse {
+        loop {
+            if let Some(response) = self.try_response(ticket) {
+                return response;
+            }
+        }
+    }
+
+    /// Reads every response the controller has written, giving solicited
+    /// responses to their commands and keeping unsolicited ones for `take_unsolicited`
+    fn read_responses(&mut self) {
+        while let Some(response) = self.rirb.try_read_next_entry() {
+            if !response.response_info.solicited() {
+                self.rirb.keep_unsolicited(response);
+                continue;
+            }
+            let codec = response.response_info.codec();
+            let next_seq = self.next_seq;
+            let oldest = self.pending.iter_mut()
+                .flatten()
+                .filter(|pending| pending.codec == codec && pending.response.is_none())
+                .max_by_key(|pending| next_seq.wrapping_sub(pending.seq));
+            match oldest {
+                Some(pending) => pending.response = Some(response),
+                None => serial_println!("Dropped a response from codec {} that no command was waiting for", codec)
+            }
+        }
+    }
+
+    /// Reads the responses that have come in, unless the ring buffers are in use
+    ///
+    /// Meant for the response interrupt handler
+    fn poll(&mut self) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.read_responses();
+        self.busy = false;
+    }
+
+    /// Takes the oldest unsolicited response that has come in
+    ///
+    /// Returns None while the ring buffers are being used,
+    /// since the responses can't be read then
+    fn take_unsolicited(&mut self) -> Option<HDANodeResponse> {
+        if self.busy {
+            return None;
+        }
+        self.poll();
+        self.rirb.take_unsolicited()
+    }
+
+    /// Makes the controller interrupt whenever a response comes in,
+    /// so responses are read and unsolicited responses are handled as they come
+    fn enable_response_interrupts(&mut self) {
+        self.rirb.regs.response_interrupt_count.set_response_interrupt_count(1);
+        self.rirb.regs.control.enable_interrupt(true);
+    }
+
+    /// Checks if a response has come in since the last check,
+    /// which means the interrupt came from the RIRB
+    fn take_response_interrupt(&mut self) -> bool {
+        let flagged = self.rirb.regs.status.response_interrupt_flag();
+        if flagged {
+            self.rirb.regs.status.clear_response_interrupt_flag();
+        }
+        flagged
     }
 }
 
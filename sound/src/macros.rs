@@ -1,34 +1,27 @@
 pub use lazy_static::lazy_static;
 
+/// Declares a static `Sound` named `$name` that plays the WAV file at `$location`,
+/// which is included in the binary as `$raw_name`
+///
+/// The file is aligned for its samples, so 16 bit stereo files are played
+/// straight from it and other PCM files are streamed from it. Either way,
+/// no sample buffer the size of the file is needed
 #[macro_export]
 macro_rules! sound {
     ($name:ident, $raw_name:ident => $location:expr, size => $size:expr) => {
         #[link_section = ".sound"]
-        static $raw_name: [u8; $size] = *include_bytes!($location);
+        static $raw_name: $crate::macros::AlignedWav<$size> = $crate::macros::AlignedWav(*include_bytes!($location));
         $crate::macros::lazy_static! {
             #[link_section = ".sound"]
             static ref $name: Sound = {
-                #[repr(C, align(128))]
-                struct SB([Sample; $size / 2]);
-                impl core::ops::Deref for SB {
-                    type Target = [Sample];
-                    fn deref(&self) -> &Self::Target {
-                        self.0.as_slice()
-                    }
-                }
-                impl core::ops::DerefMut for SB {
-                    fn deref_mut(&mut self) -> &mut Self::Target {
-                        self.0.as_mut_slice()
-                    }
-                }
-                #[link_section = ".sound"]
-                static mut SAMPLE_BUFFER: SB = {
-                    SB([Sample(0); $size / 2])
-                };
-                let music = WavFile::from(&$raw_name).unwrap();
-                let sound = sound::Sound::new(music, unsafe { &mut SAMPLE_BUFFER });
-                sound
+                let music = WavFile::from(&$raw_name.0).unwrap();
+                sound::Sound::in_place(music).unwrap()
             };
         }
     }
-}
\ No newline at end of file
+}
+
+/// The bytes of a WAV file, aligned so the samples in its data chunk,
+/// which starts at an even offset, can be read in place
+#[repr(C, align(2))]
+pub struct AlignedWav<const N: usize>(pub [u8; N]);
@@ -0,0 +1,649 @@
+//! Software mixing of the sounds that play at the same time
+//!
+//! The output stream plays a short cyclic buffer, the mix buffer, which is
+//! split into chunks. Whenever the controller finishes playing a chunk, the
+//! chunk is filled again with the next samples of every playing sound added
+//! together, so music and sound effects can share the one stream
+//!
+//! Each sound keeps the sample rate it was recorded at, and is converted to
+//! the mix rate as it's mixed, by stepping through its frames at the ratio
+//! of the two rates
+
+use core::cmp::Reverse;
+use event_hook::BoxedFn;
+use crate::{Sound, Sample, ActionOnEnd};
+
+/// The number of sounds that can play at once
+pub const MAX_VOICES: usize = 8;
+/// The number of sounds that can wait for a voice with `PlayPolicy::Queue`
+pub const MAX_QUEUED: usize = 8;
+/// The priority sounds played with `play_sound` are given
+pub const DEFAULT_PRIORITY: u8 = 128;
+/// The rate the sounds are mixed and played at
+pub(crate) const MIX_RATE: u32 = 48000;
+/// The gain that leaves a sound's samples as they are
+pub(crate) const MAX_GAIN: i32 = 256;
+
+/// How the frames between a sound's own frames are made up when
+/// its sample rate isn't the mix rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Repeats or skips frames, taking whichever is closest.
+    /// Cheaper, but high frequencies come out harsher
+    Nearest,
+    /// Blends the two closest frames by how close each one is
+    Linear
+}
+
+/// What happens to a sound that's played while every voice is busy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayPolicy {
+    /// Takes the voice of the sound with the lowest priority, as long as it's
+    /// no higher than the new sound's. The oldest of the sounds with the
+    /// lowest priority is the one that's cut off
+    Interrupt,
+    /// Waits until a voice is free. When more than one sound is waiting,
+    /// the one with the highest priority starts first
+    Queue,
+    /// Isn't played at all
+    DropIfBusy
+}
+
+/// Identifies a sound that was started with `play_sound`
+///
+/// A handle stays tied to the sound it was returned for, so a handle
+/// of a sound that has ended doesn't refer to whatever plays after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SoundHandle {
+    /// Tells apart the sounds that have been played, and stays the same
+    /// when a queued sound gets a voice
+    generation: u32
+}
+
+/// How far a sound has been played, from its beginning or,
+/// for a sound that's replayed, from its latest beginning
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaybackPosition {
+    /// The number of the sound's own frames, at its own sample rate, that have been played
+    pub frames: usize,
+    pub ms: u32
+}
+
+/// The actions of the sounds that ended while mixing, by voice
+pub(crate) type EndedActions = [Option<BoxedFn<'static>>; MAX_VOICES];
+
+const NO_ACTION: Option<BoxedFn<'static>> = None;
+const NO_VOICE: Option<Voice> = None;
+const NO_QUEUED: Option<QueuedSound> = None;
+
+/// A sound being played by the mixer
+struct Voice {
+    handle: SoundHandle,
+    sound: Sound,
+    /// The position in the sound's frames, with 16 fractional bits
+    pos: u64,
+    /// The amount `pos` moves by for every frame that's mixed,
+    /// which isn't a whole frame when the sound's rate isn't `MIX_RATE`
+    step: u64,
+    /// Whether the sound has to be resampled at all
+    resampled: bool,
+    action_on_end: ActionOnEnd,
+    /// Whether the sound has gone back to the start of its loop at least once
+    looped: bool,
+    /// The sound's samples are multiplied by this and divided by `MAX_GAIN`
+    gain: i32,
+    fade: Option<Fade>,
+    /// Sounds with a higher priority can take the voice of this one
+    priority: u8
+}
+
+/// A sound waiting for a voice to be freed
+struct QueuedSound {
+    handle: SoundHandle,
+    sound: Sound,
+    action_on_end: ActionOnEnd,
+    gain: i32,
+    priority: u8
+}
+
+/// A gradual change of a voice's gain
+struct Fade {
+    from: i32,
+    to: i32,
+    /// The number of frames the fade lasts
+    frames: u32,
+    /// The number of frames that have been mixed since the fade started
+    frames_done: u32,
+    /// Whether the sound is stopped once the fade is over
+    stop_at_end: bool
+}
+
+impl Voice {
+    fn new(queued: QueuedSound) -> Self {
+        let QueuedSound { handle, sound, action_on_end, gain, priority } = queued;
+        Self {
+            handle,
+            sound,
+            pos: 0,
+            step: ((sound.rate() as u64) << 16) / MIX_RATE as u64,
+            resampled: sound.rate() != MIX_RATE,
+            action_on_end,
+            looped: false,
+            gain,
+            fade: None,
+            priority
+        }
+    }
+
+    /// The start and end of the part of the sound that's played over and over,
+    /// in the same units as `pos`, if it's looped
+    fn loop_range(&self) -> Option<(u64, u64)> {
+        match self.action_on_end {
+            ActionOnEnd::Replay => Some((0, (self.sound.frames() as u64) << 16)),
+            ActionOnEnd::LoopRange { start_sample, end_sample } => Some(((start_sample as u64) << 16, (end_sample as u64) << 16)),
+            _ => None
+        }
+    }
+
+    /// The next frame of the sound, resampled with `quality`, with the gain
+    /// applied, or None if the sound has ended
+    fn next_frame(&mut self, quality: ResampleQuality) -> Option<(i32, i32)> {
+        let frames = self.sound.frames();
+        if frames == 0 {
+            return None;
+        }
+        let loop_range = self.loop_range();
+        match loop_range {
+            Some((start, end)) => if self.pos >= end {
+                // Keeping the fraction the position went past the end by
+                self.pos = start + (self.pos - end) % (end - start);
+                self.looped = true;
+            },
+            None => if (self.pos >> 16) as usize >= frames {
+                return None;
+            }
+        }
+        if let Some(ref mut fade) = self.fade {
+            if fade.frames_done >= fade.frames {
+                self.gain = fade.to;
+                let stop_at_end = fade.stop_at_end;
+                self.fade = None;
+                if stop_at_end {
+                    return None;
+                }
+            } else {
+                self.gain = fade.from + (fade.to - fade.from) * fade.frames_done as i32 / fade.frames as i32;
+                fade.frames_done += 1;
+            }
+        }
+        let idx = (self.pos >> 16) as usize;
+        let frac = (self.pos & 0xffff) as i32;
+        let (left, right) = self.sound.frame(idx);
+        let (mut left, mut right) = (left as i32, right as i32);
+        if self.resampled {
+            // The next frame is the loop's first one at the end of the loop
+            let next_idx = match (idx + 1, loop_range) {
+                (next_idx, Some((start, end))) if next_idx as u64 == end >> 16 => (start >> 16) as usize,
+                (next_idx, _) if next_idx < frames => next_idx,
+                _ => idx
+            };
+            let (next_left, next_right) = self.sound.frame(next_idx);
+            let (next_left, next_right) = (next_left as i32, next_right as i32);
+            match quality {
+                ResampleQuality::Nearest => if frac >= 1 << 15 {
+                    (left, right) = (next_left, next_right);
+                },
+                ResampleQuality::Linear => {
+                    left += (next_left - left) * frac >> 16;
+                    right += (next_right - right) * frac >> 16;
+                }
+            }
+        }
+        self.pos += self.step;
+        Some((left * self.gain / MAX_GAIN, right * self.gain / MAX_GAIN))
+    }
+}
+
+/// Adds the sounds that are playing together
+pub(crate) struct Mixer {
+    voices: [Option<Voice>; MAX_VOICES],
+    /// The sounds played with `PlayPolicy::Queue` while every voice was busy
+    queue: [Option<QueuedSound>; MAX_QUEUED],
+    /// The generation the next handle is given
+    next_generation: u32,
+    /// While set, silence is mixed, the sounds stay where they are
+    /// and no new sound can be played
+    paused: bool,
+    /// How sounds that aren't at the mix rate are resampled
+    quality: ResampleQuality
+}
+
+impl Mixer {
+    pub(crate) const fn new() -> Self {
+        Self {
+            voices: [NO_VOICE; MAX_VOICES],
+            queue: [NO_QUEUED; MAX_QUEUED],
+            next_generation: 0,
+            paused: false,
+            quality: ResampleQuality::Linear
+        }
+    }
+
+    /// Starts playing `sound` from its beginning with a gain of `gain`,
+    /// or as `policy` says when every voice is busy
+    ///
+    /// The handle of a queued sound is returned straight away,
+    /// though the sound isn't playing until it gets a voice
+    pub(crate) fn play(
+        &mut self,
+        sound: Sound,
+        action_on_end: ActionOnEnd,
+        gain: i32,
+        priority: u8,
+        policy: PlayPolicy
+    ) -> Result<SoundHandle, &'static str> {
+        if self.paused {
+            return Err("Sound is paused");
+        }
+        if let ActionOnEnd::LoopRange { start_sample, end_sample } = action_on_end {
+            if start_sample >= end_sample || end_sample > sound.frames() {
+                return Err("The loop range is empty or goes past the end of the sound");
+            }
+        }
+        let free_slot = self.voices.iter().position(|voice| voice.is_none());
+        let slot = match (free_slot, policy) {
+            (Some(slot), _) => Some(slot),
+            (None, PlayPolicy::Interrupt) => Some(self.lowest_priority_voice(priority)
+                .ok_or("Every sound that's playing has a higher priority")?),
+            (None, PlayPolicy::Queue) => None,
+            (None, PlayPolicy::DropIfBusy) => return Err("Too many sounds are playing")
+        };
+        let queue_slot = match slot {
+            Some(_) => None,
+            None => Some(self.queue.iter().position(|queued| queued.is_none())
+                .ok_or("Too many sounds are waiting to play")?)
+        };
+        let handle = SoundHandle { generation: self.next_generation };
+        self.next_generation = self.next_generation.wrapping_add(1);
+        let queued = QueuedSound { handle, sound, action_on_end, gain, priority };
+        match (slot, queue_slot) {
+            (Some(slot), _) => self.voices[slot] = Some(Voice::new(queued)),
+            (None, Some(queue_slot)) => self.queue[queue_slot] = Some(queued),
+            (None, None) => unreachable!()
+        }
+        Ok(handle)
+    }
+
+    /// The slot of the voice that a sound with `priority` takes when every voice is busy:
+    /// the oldest of the sounds with the lowest priority, if that's no higher than `priority`
+    fn lowest_priority_voice(&self, priority: u8) -> Option<usize> {
+        let next_generation = self.next_generation;
+        self.voices.iter().enumerate()
+            .filter_map(|(slot, voice)| voice.as_ref().map(|voice| (slot, voice)))
+            .filter(|(_, voice)| voice.priority <= priority)
+            .min_by_key(|(_, voice)| (voice.priority, Reverse(next_generation.wrapping_sub(voice.handle.generation))))
+            .map(|(slot, _)| slot)
+    }
+
+    /// Gives the free voices to the queued sounds with the highest priorities
+    fn start_queued(&mut self) {
+        let next_generation = self.next_generation;
+        for slot in 0..MAX_VOICES {
+            if self.voices[slot].is_some() {
+                continue;
+            }
+            let next = self.queue.iter().enumerate()
+                .filter_map(|(i, queued)| queued.as_ref().map(|queued| (i, queued)))
+                .max_by_key(|(_, queued)| (queued.priority, next_generation.wrapping_sub(queued.handle.generation)))
+                .map(|(i, _)| i);
+            match next {
+                Some(i) => self.voices[slot] = self.queue[i].take().map(Voice::new),
+                None => return
+            }
+        }
+    }
+
+    /// Stops the sound with `handle`, without running its action on end
+    ///
+    /// Returns an error if the sound isn't playing or queued
+    pub(crate) fn stop(&mut self, handle: SoundHandle) -> Result<(), ()> {
+        let voice = self.voices.iter_mut()
+            .find(|voice| matches!(voice, Some(voice) if voice.handle == handle));
+        if let Some(voice) = voice {
+            *voice = None;
+            self.start_queued();
+            return Ok(());
+        }
+        let queued = self.queue.iter_mut()
+            .find(|queued| matches!(queued, Some(queued) if queued.handle == handle))
+            .ok_or(())?;
+        *queued = None;
+        Ok(())
+    }
+
+    /// Stops every sound and empties the queue, without running their actions on end
+    pub(crate) fn stop_all(&mut self) {
+        self.voices = [NO_VOICE; MAX_VOICES];
+        self.queue = [NO_QUEUED; MAX_QUEUED];
+    }
+
+    pub(crate) fn is_playing(&self, handle: SoundHandle) -> bool {
+        self.voice(handle).is_some()
+    }
+
+    /// Tells whether or not the sound with `handle` is waiting for a voice
+    pub(crate) fn is_queued(&self, handle: SoundHandle) -> bool {
+        self.queue.iter().flatten().any(|queued| queued.handle == handle)
+    }
+
+    /// Pauses or resumes every sound
+    pub(crate) fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Sets how the sounds that are playing, and the ones played
+    /// after them, are resampled
+    pub(crate) fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.quality = quality;
+    }
+
+    /// How far the sound with `handle` has been heard, given the number of
+    /// frames that have been mixed but are still waiting to be played
+    ///
+    /// Returns None if the sound isn't playing
+    pub(crate) fn position(&self, handle: SoundHandle, queued_frames: usize) -> Option<PlaybackPosition> {
+        let voice = self.voice(handle)?;
+        // The queued frames were taken from the sound at its own rate
+        let queued = queued_frames as u64 * voice.step;
+        let pos = match voice.loop_range() {
+            // Going back past the start of the loop lands at its end
+            Some((start, end)) if voice.looped && end > start => {
+                let loop_len = end - start;
+                start + (voice.pos - start + loop_len - queued % loop_len) % loop_len
+            }
+            _ => voice.pos.saturating_sub(queued)
+        };
+        let frames = (pos >> 16) as usize;
+        Some(PlaybackPosition {
+            frames,
+            ms: (frames as u64 * 1000 / voice.sound.rate() as u64) as u32
+        })
+    }
+
+    /// Tells whether or not no sound is playing or waiting to play
+    pub(crate) fn is_idle(&self) -> bool {
+        self.voices.iter().all(|voice| voice.is_none()) && self.queue.iter().all(|queued| queued.is_none())
+    }
+
+    /// Changes the gain of the sound with `handle` to `to` gradually, over `frames` frames,
+    /// and stops the sound when it's done if `stop_at_end` is true
+    ///
+    /// Returns an error if the sound isn't playing
+    pub(crate) fn fade(&mut self, handle: SoundHandle, to: i32, frames: u32, stop_at_end: bool) -> Result<(), ()> {
+        let voice = self.voice_mut(handle).ok_or(())?;
+        voice.fade = Some(Fade { from: voice.gain, to, frames, frames_done: 0, stop_at_end });
+        Ok(())
+    }
+
+    /// Fills `out` with the next frames of all the playing sounds added together
+    ///
+    /// Returns the actions of the sounds that ended with `ActionOnEnd::Action`,
+    /// which are left for the caller to run
+    pub(crate) fn mix(&mut self, out: &mut [Sample]) -> EndedActions {
+        let mut ended = [NO_ACTION; MAX_VOICES];
+        if self.paused {
+            out.fill(Sample(0));
+            return ended;
+        }
+        let quality = self.quality;
+        for frame in out.chunks_exact_mut(2) {
+            let (mut left, mut right) = (0, 0);
+            for (slot, voice) in self.voices.iter_mut().enumerate() {
+                let next_frame = match voice {
+                    Some(voice) => voice.next_frame(quality),
+                    None => continue
+                };
+                match next_frame {
+                    Some((voice_left, voice_right)) => {
+                        left += voice_left;
+                        right += voice_right;
+                    }
+                    None => if let Some(Voice { action_on_end: ActionOnEnd::Action(func), .. }) = voice.take() {
+                        ended[slot] = Some(func);
+                    }
+                }
+            }
+            // Loud sounds played together can go past what 16 bits can hold
+            frame[0] = Sample(left.clamp(i16::MIN as i32, i16::MAX as i32) as i16 as u16);
+            frame[1] = Sample(right.clamp(i16::MIN as i32, i16::MAX as i32) as i16 as u16);
+        }
+        // Queued sounds start with the next chunk, so the actions
+        // of the sounds that ended keep their voices' places
+        self.start_queued();
+        ended
+    }
+
+    fn voice(&self, handle: SoundHandle) -> Option<&Voice> {
+        self.voices.iter().flatten().find(|voice| voice.handle == handle)
+    }
+
+    fn voice_mut(&mut self, handle: SoundHandle) -> Option<&mut Voice> {
+        self.voices.iter_mut().flatten().find(|voice| voice.handle == handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+    use crate::SoundSamples;
+
+    /// A sound of `frames` at `rate`
+    fn sound_of(rate: u32, frames: &[(i16, i16)]) -> Sound {
+        let samples: Vec<Sample> = frames.iter()
+            .flat_map(|(left, right)| [Sample(*left as u16), Sample(*right as u16)])
+            .collect();
+        Sound { rate, samples: SoundSamples::Converted(samples.leak()) }
+    }
+
+    /// A sound of `frames` frames at `rate` whose frames are all `(left, right)`
+    fn sound(frames: usize, rate: u32, left: i16, right: i16) -> Sound {
+        sound_of(rate, &vec![(left, right); frames])
+    }
+
+    /// The next `frames` frames `mixer` mixes
+    fn mix(mixer: &mut Mixer, frames: usize) -> Vec<(i16, i16)> {
+        let mut out = vec![Sample(0); frames * 2];
+        mixer.mix(&mut out);
+        out.chunks_exact(2).map(|frame| (frame[0].0 as i16, frame[1].0 as i16)).collect()
+    }
+
+    fn play(mixer: &mut Mixer, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str> {
+        mixer.play(sound(100, MIX_RATE, 1, 1), ActionOnEnd::Stop, MAX_GAIN, priority, policy)
+    }
+
+    /// A mixer with every voice playing a sound with `DEFAULT_PRIORITY`,
+    /// and the sounds' handles, oldest first
+    fn busy_mixer() -> (Mixer, [SoundHandle; MAX_VOICES]) {
+        let mut mixer = Mixer::new();
+        let handles = [(); MAX_VOICES].map(|_| play(&mut mixer, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).unwrap());
+        (mixer, handles)
+    }
+
+    #[test]
+    fn test_play_policies() {
+        let (mut mixer, handles) = busy_mixer();
+        assert!(play(&mut mixer, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).is_err());
+        // Every sound that's playing has a higher priority
+        assert!(play(&mut mixer, DEFAULT_PRIORITY - 1, PlayPolicy::Interrupt).is_err());
+        assert!(handles.iter().all(|handle| mixer.is_playing(*handle)));
+
+        // The oldest of the sounds with the lowest priority is cut off
+        let interrupting = play(&mut mixer, DEFAULT_PRIORITY, PlayPolicy::Interrupt).unwrap();
+        assert!(mixer.is_playing(interrupting));
+        assert!(!mixer.is_playing(handles[0]));
+        assert!(handles[1..].iter().all(|handle| mixer.is_playing(*handle)));
+
+        let queued = play(&mut mixer, DEFAULT_PRIORITY, PlayPolicy::Queue).unwrap();
+        assert!(!mixer.is_playing(queued));
+        assert!(mixer.is_queued(queued));
+    }
+
+    #[test]
+    fn test_queue_is_limited() {
+        let (mut mixer, _) = busy_mixer();
+        for _ in 0..MAX_QUEUED {
+            assert!(play(&mut mixer, DEFAULT_PRIORITY, PlayPolicy::Queue).is_ok());
+        }
+        assert!(play(&mut mixer, DEFAULT_PRIORITY, PlayPolicy::Queue).is_err());
+    }
+
+    #[test]
+    fn test_stop_starts_queued_sounds() {
+        let (mut mixer, handles) = busy_mixer();
+        let low = play(&mut mixer, 1, PlayPolicy::Queue).unwrap();
+        let high = play(&mut mixer, 200, PlayPolicy::Queue).unwrap();
+        let newer_high = play(&mut mixer, 200, PlayPolicy::Queue).unwrap();
+
+        // The voice goes to the oldest of the sounds with the highest priority
+        assert_eq!(mixer.stop(handles[3]), Ok(()));
+        assert!(!mixer.is_playing(handles[3]));
+        assert!(mixer.is_playing(high));
+        assert!(!mixer.is_queued(high));
+        assert!(mixer.is_queued(newer_high));
+
+        // A queued sound is taken out of the queue
+        assert_eq!(mixer.stop(newer_high), Ok(()));
+        assert!(!mixer.is_queued(newer_high));
+        assert_eq!(mixer.stop(handles[0]), Ok(()));
+        assert!(mixer.is_playing(low));
+
+        // The handle of a stopped sound doesn't refer to anything anymore
+        assert_eq!(mixer.stop(handles[3]), Err(()));
+        assert_eq!(mixer.stop(newer_high), Err(()));
+
+        mixer.stop_all();
+        assert!(mixer.is_idle());
+        assert_eq!(mixer.stop(low), Err(()));
+    }
+
+    #[test]
+    fn test_resampling() {
+        let frames = [(0, 0), (1000, -1000), (2000, -2000), (3000, -3000)];
+        let cases = [
+            // name, rate, quality, left samples mixed
+            ("same rate", MIX_RATE, ResampleQuality::Linear, vec![0, 1000, 2000, 3000, 0, 0]),
+            ("upsampled nearest", MIX_RATE / 2, ResampleQuality::Nearest, vec![0, 1000, 1000, 2000, 2000, 3000, 3000, 3000, 0]),
+            ("upsampled linear", MIX_RATE / 2, ResampleQuality::Linear, vec![0, 500, 1000, 1500, 2000, 2500, 3000, 3000, 0]),
+            ("downsampled", MIX_RATE * 2, ResampleQuality::Linear, vec![0, 2000, 0])
+        ];
+        for (name, rate, quality, expected) in cases {
+            let mut mixer = Mixer::new();
+            mixer.set_resample_quality(quality);
+            mixer.play(sound_of(rate, &frames), ActionOnEnd::Stop, MAX_GAIN, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).unwrap();
+            let mixed = mix(&mut mixer, expected.len());
+            let left: Vec<i16> = mixed.iter().map(|frame| frame.0).collect();
+            assert_eq!(left, expected, "{}", name);
+            assert!(mixed.iter().all(|(left, right)| *right == -*left), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_gain_and_clipping() {
+        let cases = [
+            // name, the sounds' samples and gains, frame mixed
+            ("full gain", vec![(1000, MAX_GAIN)], (1000, -1000)),
+            ("half gain", vec![(1000, MAX_GAIN / 2)], (500, -500)),
+            ("silenced", vec![(1000, 0)], (0, 0)),
+            ("added together", vec![(1000, MAX_GAIN), (300, MAX_GAIN / 2)], (1150, -1150)),
+            ("clipped", vec![(20000, MAX_GAIN), (20000, MAX_GAIN)], (i16::MAX, i16::MIN))
+        ];
+        for (name, sounds, expected) in cases {
+            let mut mixer = Mixer::new();
+            for (sample, gain) in sounds {
+                mixer.play(sound(4, MIX_RATE, sample, -sample), ActionOnEnd::Stop, gain, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).unwrap();
+            }
+            assert_eq!(mix(&mut mixer, 1)[0], expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_position() {
+        let cases = [
+            // name, rate, frames mixed, frames queued, expected position
+            ("nothing queued", MIX_RATE, 4800, 0, PlaybackPosition { frames: 4800, ms: 100 }),
+            ("queued frames aren't heard yet", MIX_RATE, 4800, 480, PlaybackPosition { frames: 4320, ms: 90 }),
+            ("in the sound's own frames", MIX_RATE / 2, 4800, 480, PlaybackPosition { frames: 2160, ms: 90 }),
+            ("more queued than mixed", MIX_RATE, 100, 480, PlaybackPosition { frames: 0, ms: 0 })
+        ];
+        for (name, rate, mixed, queued, expected) in cases {
+            let mut mixer = Mixer::new();
+            let handle = mixer.play(sound(10000, rate, 1, 1), ActionOnEnd::Stop, MAX_GAIN, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).unwrap();
+            mix(&mut mixer, mixed);
+            assert_eq!(mixer.position(handle, queued), Some(expected), "{}", name);
+            mixer.stop(handle).unwrap();
+            assert_eq!(mixer.position(handle, queued), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_loops() {
+        let frames: Vec<(i16, i16)> = (0..6).map(|i| (i * 100, -i * 100)).collect();
+        let cases = [
+            // name, action on end, left samples mixed
+            ("stopped", ActionOnEnd::Stop, vec![0, 100, 200, 300, 400, 500, 0, 0]),
+            ("replayed", ActionOnEnd::Replay, vec![0, 100, 200, 300, 400, 500, 0, 100]),
+            ("looped range", ActionOnEnd::LoopRange { start_sample: 2, end_sample: 5 }, vec![0, 100, 200, 300, 400, 200, 300, 400, 200]),
+            ("looped to the end", ActionOnEnd::LoopRange { start_sample: 4, end_sample: 6 }, vec![0, 100, 200, 300, 400, 500, 400, 500])
+        ];
+        for (name, action_on_end, expected) in cases {
+            let mut mixer = Mixer::new();
+            mixer.play(sound_of(MIX_RATE, &frames), action_on_end, MAX_GAIN, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).unwrap();
+            let mixed = mix(&mut mixer, expected.len());
+            let left: Vec<i16> = mixed.iter().map(|frame| frame.0).collect();
+            assert_eq!(left, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_loop_ranges_are_checked() {
+        let cases = [
+            ("empty", 3, 3, false),
+            ("backwards", 4, 2, false),
+            ("past the end", 2, 7, false),
+            ("the whole sound", 0, 6, true)
+        ];
+        for (name, start_sample, end_sample, valid) in cases {
+            let mut mixer = Mixer::new();
+            let action_on_end = ActionOnEnd::LoopRange { start_sample, end_sample };
+            let result = mixer.play(sound(6, MIX_RATE, 1, 1), action_on_end, MAX_GAIN, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy);
+            assert_eq!(result.is_ok(), valid, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_position_in_loop() {
+        let cases = [
+            // frames queued, the frame that's heard next
+            (0, 4),
+            (1, 3),
+            (2, 2),
+            (3, 4),
+            (5, 2)
+        ];
+        for (queued, expected) in cases {
+            let mut mixer = Mixer::new();
+            let action_on_end = ActionOnEnd::LoopRange { start_sample: 2, end_sample: 5 };
+            let handle = mixer.play(sound(6, MIX_RATE, 1, 1), action_on_end, MAX_GAIN, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy).unwrap();
+            // Frames 0, 1, 2, 3, 4, 2 and 3
+            mix(&mut mixer, 7);
+            assert_eq!(mixer.position(handle, queued).map(|pos| pos.frames), Some(expected), "{} queued", queued);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! The buffer microphone input is recorded into
+//!
+//! The caller's buffer is kept while the controller fills it, and is given
+//! back with the number of samples that were recorded once recording stops
+
+use crate::Sample;
+
+/// A buffer given to `record`, kept until `take_recording` gives it back
+pub(crate) struct Recording {
+    buffer: &'static mut [Sample],
+    /// The number of samples at the start of the buffer the controller fills
+    len: usize,
+    /// The number of samples that were recorded, once recording has stopped
+    recorded: Option<usize>
+}
+
+impl Recording {
+    pub(crate) fn new(buffer: &'static mut [Sample]) -> Self {
+        Self { buffer, len: 0, recorded: None }
+    }
+
+    /// Sets how much of the buffer is filled in `duration_ms` milliseconds
+    /// of samples at `rate` frames a second, leaving out the samples after
+    /// the last multiple of `multiple`, and clears it
+    ///
+    /// Returns the part of the buffer that's filled
+    pub(crate) fn start(&mut self, duration_ms: usize, rate: usize, multiple: usize) -> &[Sample] {
+        let len = (duration_ms * rate / 1000 * 2).min(self.buffer.len());
+        self.len = len / multiple * multiple;
+        self.buffer[..self.len].fill(Sample(0));
+        &self.buffer[..self.len]
+    }
+
+    /// Notes that recording stopped after `recorded` samples were filled
+    ///
+    /// Only the first stop counts, since the stream's position is back at
+    /// the start once the buffer is full
+    pub(crate) fn stop(&mut self, recorded: usize) {
+        if self.recorded.is_none() {
+            self.recorded = Some(recorded.min(self.len));
+        }
+    }
+
+    /// Notes that the whole buffer was filled
+    pub(crate) fn stop_full(&mut self) {
+        self.stop(self.len);
+    }
+
+    /// The buffer and the number of samples recorded at its start
+    pub(crate) fn into_parts(self) -> (&'static mut [Sample], usize) {
+        (self.buffer, self.recorded.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec;
+
+    fn new_recording(len: usize) -> Recording {
+        Recording::new(vec![Sample(7); len].leak())
+    }
+
+    #[test]
+    fn test_start() {
+        let cases = [
+            // name, buffer length, duration, expected length
+            ("whole frames", 1000, 10, 960),
+            ("limited by the buffer", 500, 10, 448),
+            ("shorter than a multiple", 1000, 0, 0)
+        ];
+        for (name, buffer_len, duration_ms, expected) in cases {
+            let mut recording = new_recording(buffer_len);
+            assert_eq!(recording.start(duration_ms, 48000, 64).len(), expected, "{}", name);
+            assert!(recording.buffer[..expected].iter().all(|sample| sample.0 == 0), "{}", name);
+            assert!(recording.buffer[expected..].iter().all(|sample| sample.0 == 7), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_buffer_is_given_back() {
+        let mut recording = new_recording(1000);
+        recording.start(10, 48000, 64);
+        recording.stop(100);
+        // Stopping again doesn't change it
+        recording.stop(0);
+        let (buffer, recorded) = recording.into_parts();
+        assert_eq!(buffer.len(), 1000);
+        assert_eq!(recorded, 100);
+
+        let mut recording = new_recording(1000);
+        recording.start(10, 48000, 64);
+        recording.stop_full();
+        assert_eq!(recording.into_parts().1, 960);
+
+        let mut recording = new_recording(1000);
+        recording.start(10, 48000, 64);
+        recording.stop(5000);
+        assert_eq!(recording.into_parts().1, 960);
+
+        // Nothing is recorded if recording never started
+        assert_eq!(new_recording(1000).into_parts().1, 0);
+    }
+}
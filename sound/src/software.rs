@@ -0,0 +1,119 @@
+//! A backend that mixes into a buffer in memory instead of playing through the controller
+//!
+//! Selected by the software sound boot option, so the game, fades and the mixer
+//! can be exercised under QEMU with the HDA device left out. Sounds are mixed on
+//! timer ticks, a tick's worth of frames at a time, so they end, replay and fade
+//! when they would with the device
+
+use core::mem;
+use event_hook::{EventKind, HandlerId, HookOptions, Priority, box_fn};
+use collections::allocator::{self, Allocator};
+use crate::{Backend, Sound, Sample, SoundHandle, PlaybackPosition, ActionOnEnd, schedule_ended_actions, peak_levels};
+use crate::mixer::{Mixer, PlayPolicy, MIX_RATE, MAX_GAIN, DEFAULT_PRIORITY};
+use num::Integer;
+
+/// The number of frames mixed on every timer tick.
+/// The PIT is left at its default frequency of about 18.2Hz
+const FRAMES_PER_TICK: usize = MIX_RATE as usize * 10 / 182;
+
+pub(crate) struct SoftwareSound {
+    mixer: Mixer,
+    /// Holds the frames mixed on the last tick
+    buffer: &'static mut [Sample],
+    /// The timer handler that mixes, which is only hooked while sounds are playing
+    mix_hook: Option<HandlerId>
+}
+
+static mut SOFTWARE_SOUND: Option<SoftwareSound> = None;
+
+/// Sets up the software backend
+pub(crate) fn init() -> Result<(), &'static str> {
+    if unsafe { SOFTWARE_SOUND.is_some() } {
+        return Ok(());
+    }
+    let len = FRAMES_PER_TICK * 2;
+    let buffer_ptr = unsafe { allocator::get_allocator().alloc(mem::size_of::<Sample>(), len) }
+        .map_err(|_| "No enough space on the heap for the software mix buffer")?;
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_ptr.cast::<Sample>(), len) };
+    buffer.fill(Sample(0));
+    unsafe {
+        SOFTWARE_SOUND = Some(SoftwareSound {
+            mixer: Mixer::new(),
+            buffer,
+            mix_hook: None
+        });
+    }
+    Ok(())
+}
+
+/// The software backend, if it has been selected
+pub(crate) fn get() -> Option<&'static mut SoftwareSound> {
+    unsafe { SOFTWARE_SOUND.as_mut() }
+}
+
+impl SoftwareSound {
+    fn start_mixing(&mut self) {
+        if self.mix_hook.is_some() {
+            return;
+        }
+        // Mixed before the game's timer handler, which can take a while drawing a frame
+        let options = HookOptions { priority: Priority::High, ..HookOptions::default() };
+        self.mix_hook = Some(event_hook::hook_event_with_options(EventKind::Timer, options, box_fn!(|_| {
+            let software_sound = get().unwrap();
+            let ended = software_sound.mixer.mix(software_sound.buffer);
+            if software_sound.mixer.is_idle() {
+                software_sound.stop_mixing();
+            }
+            schedule_ended_actions(ended);
+        })));
+    }
+
+    fn stop_mixing(&mut self) {
+        if let Some(id) = self.mix_hook.take() {
+            event_hook::unhook_event(id, EventKind::Timer);
+            self.buffer.fill(Sample(0));
+        }
+    }
+}
+
+impl Backend for SoftwareSound {
+    fn play_sound(&mut self, sound: Sound, action_on_end: ActionOnEnd, priority: u8, policy: PlayPolicy) -> Result<SoundHandle, &'static str> {
+        let handle = self.mixer.play(sound, action_on_end, MAX_GAIN, priority, policy)?;
+        self.start_mixing();
+        Ok(handle)
+    }
+
+    fn crossfade(&mut self, from: SoundHandle, to: Sound, ms: usize) -> Result<SoundHandle, &'static str> {
+        let frames = (ms * MIX_RATE as usize / 1000).as_u32();
+        let handle = self.mixer.play(to, ActionOnEnd::Replay, 0, DEFAULT_PRIORITY, PlayPolicy::DropIfBusy)?;
+        self.mixer.fade(handle, MAX_GAIN, frames, false).unwrap();
+        let _ = self.mixer.fade(from, 0, frames, true);
+        self.start_mixing();
+        Ok(handle)
+    }
+
+    fn mixer(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    fn pause(&mut self) {
+        self.mixer.set_paused(true);
+    }
+
+    fn resume(&mut self) {
+        self.mixer.set_paused(false);
+    }
+
+    fn playback_position(&self, handle: SoundHandle) -> Option<PlaybackPosition> {
+        // Frames are mixed on the tick they would be played on
+        self.mixer.position(handle, 0)
+    }
+
+    /// The peak levels of the frames mixed on the last tick
+    fn levels(&self) -> (u16, u16) {
+        if self.mix_hook.is_none() {
+            return (0, 0);
+        }
+        peak_levels(self.buffer)
+    }
+}
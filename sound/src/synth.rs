@@ -0,0 +1,259 @@
+//! Synthesis of simple waveforms from a sequence of notes
+//!
+//! Chiptune style music can be described by a few bytes per note instead
+//! of a WAV file that takes megabytes. The notes are rendered into a sample
+//! buffer once, which is then played like any other sound
+//!
+//! # Example
+//!
+//! ```ignore
+//! use sound::synth::{Note, Waveform, midi_freq};
+//! const JINGLE: [Note; 3] = [
+//!     Note::tone(midi_freq(60), 150),
+//!     Note::rest(50),
+//!     Note::tone(midi_freq(67), 300)
+//! ];
+//! let jingle = sound::synthesize(&JINGLE, Waveform::Square, 8000)?;
+//! ```
+
+use crate::{Sample, MIX_RATE};
+
+/// The rate the notes are rendered at, which is the mix rate
+/// so the mixer doesn't have to resample them
+pub const SYNTH_RATE: u32 = MIX_RATE;
+
+/// The number of frames at the end of a note over which it fades out,
+/// so the jump to the next note doesn't click
+const RELEASE_FRAMES: usize = SYNTH_RATE as usize / 500;
+
+/// The frequencies of the notes of the octave starting at middle C,
+/// in hundredths of a hertz
+const OCTAVE_4_FREQS: [u32; 12] = [
+    26163, 27718, 29366, 31113, 32963, 34923, 36999, 39200, 41530, 44000, 46616, 49388
+];
+
+/// The MIDI note number of middle C
+const MIDDLE_C: u8 = 60;
+
+/// The shape of the wave a note is played with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    /// Switches between the peak and the trough halfway through every cycle
+    Square,
+    /// Rises to the peak and falls back to the trough linearly, a softer sound
+    Triangle,
+    /// Pseudo random samples, changed as many times a second as the note's
+    /// frequency, for drums and effects
+    Noise
+}
+
+/// A note, or a rest when the frequency is 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Note {
+    /// The pitch of the note in hertz
+    pub freq: u32,
+    /// How long the note lasts in milliseconds
+    pub ms: u32
+}
+
+impl Note {
+    pub const fn tone(freq: u32, ms: u32) -> Self {
+        Self { freq, ms }
+    }
+
+    /// Silence for `ms` milliseconds
+    pub const fn rest(ms: u32) -> Self {
+        Self { freq: 0, ms }
+    }
+
+    /// The number of frames the note lasts when rendered
+    fn frames(&self) -> usize {
+        (self.ms as u64 * SYNTH_RATE as u64 / 1000) as usize
+    }
+}
+
+/// The frequency in hertz of the note with the MIDI note number `note`,
+/// where 60 is middle C and each number is a semitone
+pub const fn midi_freq(note: u8) -> u32 {
+    let semitones_from_c4 = note as i32 - MIDDLE_C as i32;
+    let octave = semitones_from_c4.div_euclid(12);
+    let freq = OCTAVE_4_FREQS[semitones_from_c4.rem_euclid(12) as usize];
+    let freq = if octave >= 0 { freq << octave } else { freq >> -octave };
+    (freq + 50) / 100
+}
+
+/// The number of samples `notes` take when rendered
+pub fn rendered_len(notes: &[Note]) -> usize {
+    notes.iter().map(|note| note.frames() * 2).sum()
+}
+
+/// Renders `notes` played with `waveform` into `out` as 16 bit stereo samples,
+/// with the waves' peaks at `amplitude`
+///
+/// Fails if `out` is smaller than `rendered_len` of the notes
+pub fn render(notes: &[Note], waveform: Waveform, amplitude: i16, out: &mut [Sample]) -> Result<(), &'static str> {
+    if out.len() < rendered_len(notes) {
+        return Err("The sample buffer is too small for the notes");
+    }
+    let mut oscillator = Oscillator::new(waveform);
+    let mut frames = out.chunks_exact_mut(2);
+    for note in notes {
+        let note_frames = note.frames();
+        oscillator.set_freq(note.freq);
+        for (i, frame) in frames.by_ref().take(note_frames).enumerate() {
+            let mut sample = if note.freq == 0 {
+                0
+            } else {
+                oscillator.next_sample() * amplitude as i32 / i16::MAX as i32
+            };
+            let frames_left = note_frames - i;
+            if frames_left < RELEASE_FRAMES {
+                sample = sample * frames_left as i32 / RELEASE_FRAMES as i32;
+            }
+            let sample = Sample(sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16 as u16);
+            frame[0] = sample;
+            frame[1] = sample;
+        }
+    }
+    Ok(())
+}
+
+/// Generates a waveform one sample at a time
+struct Oscillator {
+    waveform: Waveform,
+    /// How far through the current cycle the wave is, where a whole cycle is 2^32
+    phase: u32,
+    /// The amount `phase` moves by every frame
+    step: u32,
+    /// The state of the noise generator, a 16 bit linear feedback shift register
+    lfsr: u16
+}
+
+impl Oscillator {
+    fn new(waveform: Waveform) -> Self {
+        Self { waveform, phase: 0, step: 0, lfsr: 0xace1 }
+    }
+
+    /// Changes the frequency of the wave, carrying on from where
+    /// the wave is so there's no jump in it
+    fn set_freq(&mut self, freq: u32) {
+        self.step = ((freq as u64) << 32).checked_div(SYNTH_RATE as u64)
+            .map_or(0, |step| step.min(u32::MAX as u64) as u32);
+    }
+
+    /// The next sample of the wave, between -i16::MAX and i16::MAX
+    fn next_sample(&mut self) -> i32 {
+        let (phase, wrapped) = self.phase.overflowing_add(self.step);
+        self.phase = phase;
+        match self.waveform {
+            Waveform::Square => if phase < 1 << 31 { i16::MAX as i32 } else { -(i16::MAX as i32) },
+            Waveform::Triangle => {
+                // Rising over the first half of the cycle and falling over the second
+                let pos = (phase >> 16) as i32;
+                let rising = if pos < 1 << 15 { pos } else { (1 << 16) - 1 - pos };
+                rising * 2 - i16::MAX as i32
+            }
+            Waveform::Noise => {
+                if wrapped {
+                    let bit = self.lfsr & 1;
+                    self.lfsr >>= 1;
+                    if bit == 1 {
+                        self.lfsr ^= 0xb400;
+                    }
+                }
+                if self.lfsr & 1 == 1 { i16::MAX as i32 } else { -(i16::MAX as i32) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// The left samples of `notes` rendered with `waveform` at a peak of `amplitude`,
+    /// checking that the right ones are the same
+    fn render_left(notes: &[Note], waveform: Waveform, amplitude: i16) -> Vec<i16> {
+        let mut out = vec![Sample(0x5555); rendered_len(notes)];
+        render(notes, waveform, amplitude, &mut out).unwrap();
+        out.chunks_exact(2).map(|frame| {
+            assert_eq!(frame[0].0, frame[1].0);
+            frame[0].0 as i16
+        }).collect()
+    }
+
+    #[test]
+    fn test_midi_freq() {
+        let cases = [
+            ("middle C", 60, 262),
+            ("A4", 69, 440),
+            ("an octave below", 57, 220),
+            ("an octave above", 81, 880),
+            ("C5", 72, 523),
+            ("2 octaves below", 36, 65)
+        ];
+        for (name, note, freq) in cases {
+            assert_eq!(midi_freq(note), freq, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_waveforms() {
+        // A quarter of a cycle every frame
+        let note = Note::tone(SYNTH_RATE / 4, 10);
+        let max = i16::MAX;
+        let cases = [
+            ("square", Waveform::Square, max, [max, -max, -max, max]),
+            ("square at a lower amplitude", Waveform::Square, 1000, [1000, -1000, -1000, 1000]),
+            ("triangle", Waveform::Triangle, max, [1, max, -1, -max]),
+            ("triangle at a lower amplitude", Waveform::Triangle, 1000, [0, 1000, 0, -1000])
+        ];
+        for (name, waveform, amplitude, cycle) in cases {
+            let samples = render_left(&[note], waveform, amplitude);
+            assert_eq!(samples.len(), note.frames(), "{}", name);
+            for (i, sample) in samples[..samples.len() - RELEASE_FRAMES].iter().enumerate() {
+                assert_eq!(*sample, cycle[i % 4], "{} at {}", name, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_changes_once_a_cycle() {
+        let samples = render_left(&[Note::tone(SYNTH_RATE / 4, 10)], Waveform::Noise, 1000);
+        let samples = &samples[..samples.len() - RELEASE_FRAMES];
+        assert!(samples.iter().all(|sample| sample.abs() == 1000));
+        // The phase first wraps around in the 4th frame
+        for cycle in samples[3..].chunks_exact(4) {
+            assert!(cycle.iter().all(|sample| *sample == cycle[0]));
+        }
+        assert!(samples.iter().any(|sample| *sample != samples[0]));
+    }
+
+    #[test]
+    fn test_release_envelope() {
+        let note = Note::tone(SYNTH_RATE / 4, 10);
+        let samples = render_left(&[note], Waveform::Square, 1000);
+        let release = &samples[samples.len() - RELEASE_FRAMES..];
+        // The release starts at the full amplitude and fades out to almost nothing
+        assert_eq!(release[0].abs(), 1000);
+        for (i, sample) in release.iter().enumerate() {
+            assert_eq!(sample.abs() as usize, 1000 * (RELEASE_FRAMES - i) / RELEASE_FRAMES, "{}", i);
+        }
+    }
+
+    #[test]
+    fn test_rests_and_lengths() {
+        let notes = [Note::tone(440, 5), Note::rest(5), Note::tone(880, 5)];
+        assert_eq!(rendered_len(&notes), 3 * 240 * 2);
+        let samples = render_left(&notes, Waveform::Square, 1000);
+        assert!(samples[..240].iter().any(|sample| *sample != 0));
+        assert!(samples[240..480].iter().all(|sample| *sample == 0));
+        assert!(samples[480..].iter().any(|sample| *sample != 0));
+
+        let mut out = vec![Sample(0); rendered_len(&notes) - 1];
+        assert!(render(&notes, Waveform::Square, 1000, &mut out).is_err());
+    }
+}
@@ -1,127 +1,323 @@
-use core::mem;
+//! Parsing of wav files
+//!
+//! A wav file is an RIFF file, a generic container format that stores data in
+//! tagged chunks. Each chunk starts with a 4 byte ascii id, followed by the
+//! size of the chunk's data as a 32 bit little endian integer. The data is
+//! padded to an even number of bytes.
+//! The "fmt " chunk describes the samples, which are in the "data" chunk.
+//! Other chunks, like lists of metadata, are skipped
+//!
+//! # References
+//!
+//! * <http://soundfile.sapp.org/doc/WaveFormat/>
+//! * <https://wiki.multimedia.cx/index.php/Microsoft_IMA_ADPCM>
+
+/// The format code of uncompressed PCM samples
+const FORMAT_PCM: u16 = 1;
+/// The format code of IMA ADPCM compressed samples
+const FORMAT_IMA_ADPCM: u16 = 0x11;
+/// The format code of files whose real format code is in the fmt chunk's extension
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// The size of an RIFF chunk's id and size
+const CHUNK_HEADER_SIZE: usize = 8;
 
 #[derive(Clone, Copy, Debug)]
 pub struct WavFile {
-    header: &'static WavHeader,
-    data: SampleDataChunk
+    format: WavFormat,
+    /// The contents of the data chunk
+    data: &'static [u8]
+}
+
+/// How the samples in a wav file are stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavEncoding {
+    /// Uncompressed samples of 8, 16 or 24 bits, with the channels interleaved
+    Pcm,
+    /// 4 bit samples compressed with IMA ADPCM, in blocks of `block_align` bytes
+    ImaAdpcm
+}
+
+/// Why a wav file can't be played
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavError {
+    /// The file doesn't start with the RIFF header
+    NotRiff,
+    /// The RIFF file isn't a wav file
+    NotWave,
+    /// A chunk's size goes past the end of the file
+    TruncatedChunk,
+    MissingFmtChunk,
+    MissingDataChunk,
+    /// The fmt chunk is too small for the fields its format needs
+    FmtChunkTooSmall,
+    /// The samples are compressed in a way that isn't supported, given by the format code
+    UnsupportedEncoding(u16),
+    /// Only mono and stereo sounds are supported
+    UnsupportedChannels(u16),
+    /// PCM samples have to be 8, 16 or 24 bits, and ADPCM samples 4 bits
+    UnsupportedBitsPerSample(u16),
+    ZeroSampleRate,
+    /// The ADPCM block size doesn't fit the channels' headers and a whole number of samples
+    InvalidBlockAlign(u16)
+}
+
+impl WavError {
+    pub fn msg(&self) -> &'static str {
+        match self {
+            WavError::NotRiff => "The file isn't an RIFF file",
+            WavError::NotWave => "The file isn't a wav file",
+            WavError::TruncatedChunk => "A chunk in the file goes past its end",
+            WavError::MissingFmtChunk => "Couldn't find the fmt chunk",
+            WavError::MissingDataChunk => "Couldn't find the data chunk",
+            WavError::FmtChunkTooSmall => "The fmt chunk is too small",
+            WavError::UnsupportedEncoding(_) => "Only PCM and IMA ADPCM samples are supported",
+            WavError::UnsupportedChannels(_) => "Only mono and stereo sounds can be played",
+            WavError::UnsupportedBitsPerSample(_) => "Only sounds with 8, 16 or 24 bits per sample, or 4 bit ADPCM samples, can be played",
+            WavError::ZeroSampleRate => "The sound's sample rate is 0",
+            WavError::InvalidBlockAlign(_) => "The ADPCM block size is invalid"
+        }
+    }
+}
+
+impl From<WavError> for &'static str {
+    fn from(err: WavError) -> Self {
+        err.msg()
+    }
 }
 
-/// The header of a wav file as described in <http://soundfile.sapp.org/doc/WaveFormat/>
-///
-/// The wav file format is an RIFF file type which is a generic file container format
-/// for storing data in tagged chunks.
-/// The file consists of chunks which have the format 4 bit ascii id, followed by
-/// a 32 bit integer which is the length of the chunk, 
-/// All ascii values are big-endian and all integer values are little endian
-#[derive(Debug)]
-#[repr(C)]
-struct WavHeader {
-    file_chunk_header: RIFFChunkHeader,
-    /// Always the ascii "WAVE"
-    format: [u8; 4],
-
-    /// Beginning of a RIFF chunk which gives some info about the data in the file
-    ///
-    /// The id is always the ascii "fmt ", and the size is 16 for PCM
-    fmt_chunk_header: RIFFChunkHeader,
-    /// Audio format
-    ///
-    /// A value of 1 indicates PCM. Values other than 1
-    /// indicate a form of compression
-    type_format: u16,
-    /// The number of channels
-    ///
+/// The fields of the fmt chunk that describe the samples
+#[derive(Clone, Copy, Debug)]
+struct WavFormat {
+    encoding: WavEncoding,
     /// 1 for mono and 2 for stereo
     num_of_channels: u16,
     /// The sample frequency
     sample_rate: u32,
-    /// `sample_rate` * `num_of_channels` * `bits_per_sample` / 8
-    byte_rate: u32,
-    /// Number of bytes for one sample including all channels,
-    /// that is, `num_of_channels` * `bits_per_sample` / 8
+    /// The size of a frame of PCM samples, or of a block of ADPCM samples
     block_align: u16,
     /// Number of bits in a single sample
     bits_per_sample: u16
 }
 
-/// The beginning of every chunk in an RIFF file type
-#[derive(Clone, Copy, Debug)]
-#[repr(C, packed)]
-struct RIFFChunkHeader {
-    /// A 4 byte ascii field that identifies the chunk
-    id: [u8; 4],
-    /// Size of the data in the chunk
-    size: u32
-}
-
-#[derive(Clone, Copy, Debug)]
-/// The RIFF chunk in a wav file that contains sample data
-#[repr(C)]
-struct SampleDataChunk {
-    header: RIFFChunkHeader,
-    data: &'static [u16]
-}
-
 impl WavFile {
-    pub fn from(file: &'static [u8]) -> Result<WavFile, &'static str> {
-        let header = unsafe { &*(file.as_ptr() as *const WavHeader) };
-        validate_header(header)?;
-        let data_ptr = find_data_chunk(file);
-        if data_ptr.is_none() {
-            return Err("Couldn't find the data chunk");
+    pub fn from(file: &'static [u8]) -> Result<WavFile, WavError> {
+        if file.len() < CHUNK_HEADER_SIZE + 4 || &file[..4] != b"RIFF" {
+            return Err(WavError::NotRiff);
         }
-        let data_ptr = data_ptr.unwrap();
-        const RIFF_HEADER_SIZE: isize = mem::size_of::<RIFFChunkHeader>() as isize;
-        let data_chunk_header = unsafe { data_ptr.cast::<RIFFChunkHeader>().read() };
-        let sample_data = unsafe { core::slice::from_raw_parts(data_ptr.cast::<u16>(), (data_chunk_header.size / 2) as usize) };
-        Ok(Self {
-            header,
-            data: SampleDataChunk {
-                header: data_chunk_header,
-                data: sample_data
+        if &file[8..12] != b"WAVE" {
+            return Err(WavError::NotWave);
+        }
+        let riff_end = file.len().min(CHUNK_HEADER_SIZE + read_u32(file, 4) as usize);
+        let mut format = None;
+        let mut data = None;
+        let mut offset = CHUNK_HEADER_SIZE + 4;
+        while offset + CHUNK_HEADER_SIZE <= riff_end {
+            let size = read_u32(file, offset + 4) as usize;
+            let start = offset + CHUNK_HEADER_SIZE;
+            let end = start.checked_add(size).filter(|&end| end <= riff_end)
+                .ok_or(WavError::TruncatedChunk)?;
+            match &file[offset..offset + 4] {
+                b"fmt " => format = Some(parse_format(&file[start..end])?),
+                b"data" => data = Some(&file[start..end]),
+                _ => ()
             }
+            offset = end + (size & 1);
+        }
+        Ok(Self {
+            format: format.ok_or(WavError::MissingFmtChunk)?,
+            data: data.ok_or(WavError::MissingDataChunk)?
         })
     }
 
+    pub fn encoding(&self) -> WavEncoding {
+        self.format.encoding
+    }
+
     pub fn sample_rate(&self) -> u32 {
-        self.header.sample_rate
+        self.format.sample_rate
     }
 
     pub fn num_of_channels(&self) -> u16 {
-        self.header.num_of_channels
+        self.format.num_of_channels
     }
 
     pub fn bits_per_sample(&self) -> u16 {
-        self.header.bits_per_sample
+        self.format.bits_per_sample
     }
 
-    pub fn data_bytes(&self) -> &'static [u16] {
-        self.data.data
+    /// The size of a frame of PCM samples, or of a block of ADPCM samples
+    pub fn block_align(&self) -> u16 {
+        self.format.block_align
+    }
+
+    /// The raw bytes of the samples, which are little endian and
+    /// interleaved when there's more than one channel
+    pub fn sample_data(&self) -> &'static [u8] {
+        self.data
     }
 }
 
-fn find_data_chunk(file: &[u8]) -> Option<*const u8> {
-    let header = unsafe { &*(file.as_ptr().cast::<RIFFChunkHeader>()) };
-    let bytes = unsafe { core::slice::from_raw_parts(file.as_ptr(), header.size as usize) };
-    for chunk in bytes.array_windows::<4>() {
-        // Chunk id of the data chunk is "data"
-        if chunk == b"data" {
-            return Some(chunk.as_ptr());
+/// Reads the fields of the fmt chunk `chunk` and checks that its samples can be played
+fn parse_format(chunk: &[u8]) -> Result<WavFormat, WavError> {
+    if chunk.len() < 16 {
+        return Err(WavError::FmtChunkTooSmall);
+    }
+    let mut format_code = read_u16(chunk, 0);
+    if format_code == FORMAT_EXTENSIBLE {
+        // The first 2 bytes of the sub format's GUID are the real format code
+        if chunk.len() < 26 {
+            return Err(WavError::FmtChunkTooSmall);
         }
+        format_code = read_u16(chunk, 24);
+    }
+    let format = WavFormat {
+        encoding: match format_code {
+            FORMAT_PCM => WavEncoding::Pcm,
+            FORMAT_IMA_ADPCM => WavEncoding::ImaAdpcm,
+            _ => return Err(WavError::UnsupportedEncoding(format_code))
+        },
+        num_of_channels: read_u16(chunk, 2),
+        sample_rate: read_u32(chunk, 4),
+        block_align: read_u16(chunk, 12),
+        bits_per_sample: read_u16(chunk, 14)
+    };
+    if !matches!(format.num_of_channels, 1 | 2) {
+        return Err(WavError::UnsupportedChannels(format.num_of_channels));
     }
-    None
+    if format.sample_rate == 0 {
+        return Err(WavError::ZeroSampleRate);
+    }
+    match (format.encoding, format.bits_per_sample) {
+        (WavEncoding::Pcm, 8 | 16 | 24) => (),
+        (WavEncoding::ImaAdpcm, 4) => {
+            // Every block starts with a 4 byte header for each channel,
+            // followed by the channels' samples in turns of 4 bytes
+            let channel_bytes = 4 * format.num_of_channels as usize;
+            let block_align = format.block_align as usize;
+            if block_align <= channel_bytes || block_align % channel_bytes != 0 {
+                return Err(WavError::InvalidBlockAlign(format.block_align));
+            }
+        }
+        (_, bits_per_sample) => return Err(WavError::UnsupportedBitsPerSample(bits_per_sample))
+    }
+    Ok(format)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
 }
 
-/// Checks if the WavHeader in a raw byte stream is valid
-fn validate_header(header: &WavHeader) -> Result<(), &'static str> {
-    if &header.file_chunk_header.id != b"RIFF" {
-        return Err("Unexpected file chunk id");
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    /// An RIFF chunk called `id` holding `data`, padded to an even number of bytes
+    pub(crate) fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// A wav file made of `chunks`, which is never freed
+    pub(crate) fn wav(chunks: &[Vec<u8>]) -> &'static [u8] {
+        let mut file = Vec::new();
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&(4 + chunks.iter().map(Vec::len).sum::<usize>() as u32).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        for chunk in chunks {
+            file.extend_from_slice(chunk);
+        }
+        file.leak()
     }
-    if &header.format != b"WAVE" {
-        return Err("Unexpected format");
+
+    /// The data of a 16 byte fmt chunk
+    pub(crate) fn fmt(format_code: u16, channels: u16, sample_rate: u32, block_align: u16, bits_per_sample: u16) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format_code.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+        fmt
+    }
+
+    /// The data of an extensible fmt chunk, with the real format code in the sub format
+    fn fmt_extensible(sub_format: u16, channels: u16, sample_rate: u32, block_align: u16, bits_per_sample: u16) -> Vec<u8> {
+        let mut fmt = fmt(FORMAT_EXTENSIBLE, channels, sample_rate, block_align, bits_per_sample);
+        // The size of the extension, the valid bits per sample and the channel mask
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+        fmt.extend_from_slice(&0u32.to_le_bytes());
+        fmt.extend_from_slice(&sub_format.to_le_bytes());
+        fmt.extend_from_slice(b"\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71");
+        fmt
     }
-    if &header.fmt_chunk_header.id != b"fmt " {
-        return Err("Unexpected fmt_chunk_header id");
+
+    #[test]
+    fn test_wav_file_errors() {
+        let pcm = chunk(b"fmt ", &fmt(FORMAT_PCM, 2, 44100, 4, 16));
+        let data = chunk(b"data", &[0; 4]);
+        let mut truncated_data = data.clone();
+        truncated_data[4] = 100;
+        let cases: [(&str, &'static [u8], WavError); 17] = [
+            ("empty", &[], WavError::NotRiff),
+            ("shorter than the RIFF header", b"RIFF\x04\x00\x00\x00WAV", WavError::NotRiff),
+            ("not RIFF", b"RIFX\x04\x00\x00\x00WAVE", WavError::NotRiff),
+            ("not wave", b"RIFF\x04\x00\x00\x00AVI ", WavError::NotWave),
+            ("chunk past the end", wav(&[pcm.clone(), truncated_data]), WavError::TruncatedChunk),
+            ("no fmt chunk", wav(&[chunk(b"data", &[0; 4])]), WavError::MissingFmtChunk),
+            ("no data chunk", wav(&[pcm]), WavError::MissingDataChunk),
+            ("small fmt chunk", wav(&[chunk(b"fmt ", &fmt(FORMAT_PCM, 2, 44100, 4, 16)[..14]), data.clone()]), WavError::FmtChunkTooSmall),
+            ("small extensible fmt chunk", wav(&[chunk(b"fmt ", &fmt_extensible(FORMAT_PCM, 2, 44100, 4, 16)[..24]), data.clone()]), WavError::FmtChunkTooSmall),
+            ("float samples", wav(&[chunk(b"fmt ", &fmt(3, 2, 44100, 8, 32)), data.clone()]), WavError::UnsupportedEncoding(3)),
+            ("extensible float samples", wav(&[chunk(b"fmt ", &fmt_extensible(3, 2, 44100, 8, 32)), data.clone()]), WavError::UnsupportedEncoding(3)),
+            ("3 channels", wav(&[chunk(b"fmt ", &fmt(FORMAT_PCM, 3, 44100, 6, 16)), data.clone()]), WavError::UnsupportedChannels(3)),
+            ("no sample rate", wav(&[chunk(b"fmt ", &fmt(FORMAT_PCM, 2, 0, 4, 16)), data.clone()]), WavError::ZeroSampleRate),
+            ("12 bit PCM", wav(&[chunk(b"fmt ", &fmt(FORMAT_PCM, 1, 44100, 2, 12)), data.clone()]), WavError::UnsupportedBitsPerSample(12)),
+            ("16 bit ADPCM", wav(&[chunk(b"fmt ", &fmt(FORMAT_IMA_ADPCM, 1, 22050, 8, 16)), data.clone()]), WavError::UnsupportedBitsPerSample(16)),
+            ("ADPCM blocks of only headers", wav(&[chunk(b"fmt ", &fmt(FORMAT_IMA_ADPCM, 2, 22050, 8, 4)), data.clone()]), WavError::InvalidBlockAlign(8)),
+            ("ADPCM blocks of part of a sample group", wav(&[chunk(b"fmt ", &fmt(FORMAT_IMA_ADPCM, 1, 22050, 10, 4)), data]), WavError::InvalidBlockAlign(10))
+        ];
+        for (name, file, err) in cases {
+            assert_eq!(WavFile::from(file).unwrap_err(), err, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_wav_file_with_odd_sized_chunks() {
+        let fmt = chunk(b"fmt ", &fmt(FORMAT_PCM, 1, 8000, 1, 8));
+        // The chunks after odd sized ones start after their padding
+        let file = wav(&[chunk(b"LIST", b"abc"), chunk(b"data", &[1, 2, 3]), fmt.clone()]);
+        let wav_file = WavFile::from(file).unwrap();
+        assert_eq!(wav_file.sample_data(), &[1, 2, 3]);
+        assert_eq!((wav_file.sample_rate(), wav_file.bits_per_sample()), (8000, 8));
+        // The last chunk's padding may be left out
+        let padded = wav(&[fmt, chunk(b"data", &[1, 2, 3])]);
+        let file = &padded[..padded.len() - 1];
+        assert_eq!(WavFile::from(file).unwrap().sample_data(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_extensible_format() {
+        let data = chunk(b"data", &[0; 8]);
+        let pcm = WavFile::from(wav(&[chunk(b"fmt ", &fmt_extensible(FORMAT_PCM, 2, 44100, 4, 16)), data.clone()])).unwrap();
+        assert_eq!(pcm.encoding(), WavEncoding::Pcm);
+        assert_eq!((pcm.num_of_channels(), pcm.sample_rate(), pcm.block_align(), pcm.bits_per_sample()), (2, 44100, 4, 16));
+        let adpcm = WavFile::from(wav(&[chunk(b"fmt ", &fmt_extensible(FORMAT_IMA_ADPCM, 1, 22050, 8, 4)), data])).unwrap();
+        assert_eq!(adpcm.encoding(), WavEncoding::ImaAdpcm);
+        assert_eq!((adpcm.num_of_channels(), adpcm.block_align()), (1, 8));
     }
-    Ok(())
 }